@@ -0,0 +1,287 @@
+//! Environment checks for the `doctor` commands of the client and server.
+//!
+//! Each check returns a [Check]. Failed checks describe what to fix.
+
+use std::{
+    fmt::Display,
+    io,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rfs_core::middleware::{ContextManager, RequestTimeout, Retries, TransmissionProtocol};
+use tokio::net::UdpSocket;
+
+use crate::interfaces::AdminOpsClient;
+
+/// Every host must be able to receive a UDP payload of this size
+const MIN_DATAGRAM_SIZE: usize = 508;
+
+/// Largest UDP payload over IPv4
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Largest clock difference between the client and server that is not reported
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(1);
+
+/// Result of a single check
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub name: &'static str,
+
+    /// What was found, or what to fix if the check failed
+    pub outcome: Result<String, String>,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Result<String, String>) -> Self {
+        Self { name, outcome }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Ok(found) => write!(f, "[ok]   {}: {}", self.name, found),
+            Err(fix) => write!(f, "[FAIL] {}: {}", self.name, fix),
+        }
+    }
+}
+
+/// Print the results of checks. Returns true if all checks passed.
+pub fn report(checks: &[Check]) -> bool {
+    for check in checks {
+        println!("{}", check);
+    }
+
+    let num_failed = checks.iter().filter(|c| !c.passed()).count();
+    match num_failed {
+        0 => println!("all {} checks passed", checks.len()),
+        n => println!("{} of {} checks failed", n, checks.len()),
+    }
+
+    num_failed == 0
+}
+
+/// Check that a UDP socket can be bound to an address
+pub async fn check_bind(addr: SocketAddrV4) -> Check {
+    let outcome = match UdpSocket::bind(addr).await {
+        Ok(sock) => Ok(format!(
+            "bound to {}",
+            sock.local_addr().unwrap_or(addr.into())
+        )),
+        Err(e) => Err(match e.kind() {
+            io::ErrorKind::AddrInUse => format!(
+                "{} is already in use. Stop the process using it or choose another port",
+                addr
+            ),
+            io::ErrorKind::AddrNotAvailable => format!(
+                "{} is not an address of this host. Choose one of its interface addresses",
+                addr.ip()
+            ),
+            io::ErrorKind::PermissionDenied => format!(
+                "not permitted to bind to {}. Ports below 1024 require elevated privileges",
+                addr
+            ),
+            _ => format!("failed to bind to {}: {}", addr, e),
+        }),
+    };
+
+    Check::new("port bindability", outcome)
+}
+
+/// Check that the server can be reached from an address, before any datagrams are sent
+pub fn check_route(source: Ipv4Addr, target: SocketAddrV4) -> Check {
+    let outcome = rfs_core::middleware::check_route(source, target)
+        .map(|_| format!("{} routes to {}", source, target))
+        .map_err(|e| e.to_string());
+
+    Check::new("outbound address", outcome)
+}
+
+/// Find the largest datagram that can be sent from an address, by sending datagrams to itself.
+pub async fn check_max_datagram(addr: Ipv4Addr) -> Check {
+    let addr = match addr.is_unspecified() {
+        true => Ipv4Addr::LOCALHOST,
+        false => addr,
+    };
+
+    let outcome = match largest_datagram(addr).await {
+        Ok(size) if size >= MIN_DATAGRAM_SIZE => Ok(format!("{} bytes", size)),
+        Ok(size) => Err(format!(
+            "only {} byte datagrams can be sent, below the {} bytes every host must support. \
+            Check the MTU of the interface",
+            size, MIN_DATAGRAM_SIZE
+        )),
+        Err(e) => Err(format!("failed to send datagrams from {}: {}", addr, e)),
+    };
+
+    Check::new("max datagram size", outcome)
+}
+
+/// Binary search for the largest datagram that is sent and received in full
+async fn largest_datagram(addr: Ipv4Addr) -> io::Result<usize> {
+    let sock = UdpSocket::bind(SocketAddrV4::new(addr, 0)).await?;
+    let local = sock.local_addr()?;
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE + 1];
+
+    let (mut lower, mut upper) = (0, MAX_DATAGRAM_SIZE);
+    while lower < upper {
+        let size = (lower + upper).div_ceil(2);
+
+        let received = match sock.send_to(&buf[..size], local).await {
+            Ok(_) => {
+                match tokio::time::timeout(Duration::from_millis(100), sock.recv(&mut buf)).await {
+                    Ok(res) => res? == size,
+                    Err(_) => false,
+                }
+            }
+            Err(_) => false,
+        };
+
+        match received {
+            true => lower = size,
+            false => upper = size - 1,
+        }
+    }
+
+    Ok(lower)
+}
+
+/// Check that the server responds, measuring the round trip time.
+///
+/// Returns the context manager if the server is reachable.
+pub async fn check_reachable(
+    source: Ipv4Addr,
+    target: SocketAddrV4,
+    timeout: RequestTimeout,
+    retries: Retries,
+    protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
+) -> (Check, Option<ContextManager>) {
+    let start = Instant::now();
+
+    match ContextManager::new(source, target, timeout, retries, protocol).await {
+        Ok(ctx) => (
+            Check::new(
+                "server reachability",
+                Ok(format!(
+                    "{} responded in {:.1}ms",
+                    target,
+                    start.elapsed().as_secs_f64() * 1000.0
+                )),
+            ),
+            Some(ctx),
+        ),
+        Err(e) => (
+            Check::new(
+                "server reachability",
+                Err(format!(
+                    "no response from {} ({}). Check that the server is running, \
+                    that firewalls allow UDP to this port, \
+                    and that both sides use the same invocation semantics",
+                    target, e
+                )),
+            ),
+            None,
+        ),
+    }
+}
+
+/// Compare the local clock against the server's
+pub async fn check_clock_skew(ctx: &mut ContextManager) -> Check {
+    let sent = unix_time();
+    let status = AdminOpsClient::server_status(ctx).await;
+    let received = unix_time();
+
+    let outcome = match status {
+        Ok(status) => {
+            // assume the server read its clock halfway through the call
+            let local = (sent + received) / 2;
+            let skew = Duration::from_millis(status.server_time_ms.abs_diff(local));
+
+            match skew <= MAX_CLOCK_SKEW {
+                true => Ok(format!("{}ms", skew.as_millis())),
+                false => Err(format!(
+                    "clocks differ by {:.1}s. Synchronize both hosts with NTP",
+                    skew.as_secs_f64()
+                )),
+            }
+        }
+        Err(e) => Err(format!("failed to get the server time: {}", e)),
+    };
+
+    Check::new("clock skew", outcome)
+}
+
+/// Check that the server can read and write its export directory
+pub fn check_export_dir(path: &Path) -> Check {
+    Check::new("export directory", export_dir_outcome(path))
+}
+
+fn export_dir_outcome(path: &Path) -> Result<String, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("{:?} does not exist. Create it first", path),
+        _ => format!("cannot access {:?}: {}", path, e),
+    })?;
+
+    if !metadata.is_dir() {
+        return Err(format!("{:?} is not a directory", path));
+    }
+
+    std::fs::read_dir(path).map_err(|e| {
+        format!(
+            "cannot list {:?}: {}. Grant the server user read permissions",
+            path, e
+        )
+    })?;
+
+    let probe = path.join(format!(".rfs_doctor_{}", std::process::id()));
+    std::fs::write(&probe, b"").map_err(|e| {
+        format!(
+            "cannot write to {:?}: {}. Grant the server user write permissions",
+            path, e
+        )
+    })?;
+    std::fs::remove_file(&probe).map_err(|e| format!("cannot remove {:?}: {}", probe, e))?;
+
+    Ok(format!("{:?} is readable and writable", path))
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_checks() {
+        let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let taken = rfs_core::middleware::sockaddr_to_v4(sock.local_addr().unwrap()).unwrap();
+
+        assert!(check_bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .passed());
+        assert!(!check_bind(taken).await.passed());
+
+        assert!(check_max_datagram(Ipv4Addr::UNSPECIFIED).await.passed());
+
+        let dir = std::env::temp_dir().join(format!("rfs_doctor_{}", std::process::id()));
+        assert!(!check_export_dir(&dir).passed());
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(check_export_dir(&dir).passed());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+//! Fibonacci computations on the remote, with progress reports.
+
+use std::io;
+
+use rfs_core::middleware::{sockaddr_to_v4, ContextManager, InvokeError, MiddlewareData};
+use tokio::sync::mpsc;
+
+use crate::interfaces::{FibError, FibProgress, SimpleOpsClient};
+
+/// Compute the Nth fibonacci number on the remote.
+///
+/// Progress reported by the remote is sent on `progress` until the computation finishes.
+pub async fn compute_fib(
+    ctx: &mut ContextManager,
+    fib_num: u8,
+    progress: mpsc::Sender<FibProgress>,
+) -> Result<Result<u64, FibError>, InvokeError> {
+    // this is the return socket the remote will report progress to
+    let ret_sock = ctx.generate_socket().await?;
+    let ret_addr = sockaddr_to_v4(ret_sock.local_addr()?)?;
+
+    let mut listen_ctx = ctx.clone();
+    let listener = tokio::spawn(async move {
+        loop {
+            let bytes = match listen_ctx.listen(&ret_sock).await {
+                Ok(b) => b,
+                // the remote may not report progress within the timeout
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    log::error!("failed to receive fib progress: {}", e);
+                    return;
+                }
+            };
+
+            let report = match decode_progress(&bytes) {
+                Some(r) => r,
+                None => {
+                    log::error!("invalid fib progress received");
+                    continue;
+                }
+            };
+
+            if progress.send(report).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let res = SimpleOpsClient::compute_fib(ctx, fib_num, Some(ret_addr)).await;
+    listener.abort();
+
+    res
+}
+
+/// Serialize a progress report for sending to the client
+pub fn encode_progress(progress: &FibProgress) -> Vec<u8> {
+    let payload = rfs_core::serialize(progress).expect("serialization must not fail");
+
+    rfs_core::serialize(&MiddlewareData::Callback(payload)).expect("serialization must not fail")
+}
+
+/// Deserialize a progress report
+pub fn decode_progress(bytes: &[u8]) -> Option<FibProgress> {
+    match rfs_core::deserialize(bytes).ok()? {
+        MiddlewareData::Callback(payload) => rfs_core::deserialize(&payload).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_round_trip() {
+        let progress = FibProgress {
+            fib_num: 40,
+            step: 3,
+            steps: 10,
+        };
+
+        assert_eq!(decode_progress(&encode_progress(&progress)), Some(progress));
+    }
+}
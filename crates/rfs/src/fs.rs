@@ -1,12 +1,54 @@
 //! Virtual file module
+//!
+//! Read-only helpers in this module retry invocations that time out with [RetryingClient].
 
+mod diff;
+mod merge;
+mod subscription;
 mod virt_objects;
+mod virt_path;
+mod watch_chan;
 
-use std::{io, path::Path};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    path::Path,
+    sync::OnceLock,
+};
 
+pub use diff::*;
+pub use merge::*;
+pub use subscription::*;
 pub use virt_objects::*;
+pub use virt_path::*;
+pub use watch_chan::*;
 
-use crate::interfaces::PrimitiveFsOpsClient;
+use futures::{stream::BoxStream, StreamExt};
+use rfs_core::middleware::{sockaddr_to_v4, CacheLookup, RetryPolicy, RetryingClient};
+use tokio::sync::mpsc;
+
+use crate::interfaces::{
+    CallbackOpsClient, DirEvent, ImmutableFileOpsClient, PrimitiveFsOpsClient, SystemOpsClient,
+};
+
+/// Free space left on the remote disk after a write that passed [check_free_space]
+pub const FREE_SPACE_MARGIN: u64 = 16 * 1024 * 1024;
+
+/// Largest chunk returned by [PrimitiveFsOpsClient::read_chunk]
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Largest page of entries returned by [ImmutableFileOpsClient::ls]
+pub const MAX_LS_PAGE: usize = 1000;
+
+/// Session ID of this process, sent to the remote as the author of writes.
+static SESSION_ID: OnceLock<u64> = OnceLock::new();
+
+/// Returns the session ID of this process.
+///
+/// The ID is random and stays the same until the process exits.
+pub fn session_id() -> u64 {
+    *SESSION_ID.get_or_init(rand::random)
+}
 
 /// Read the contents of a file to a string.
 ///
@@ -17,22 +59,17 @@ use crate::interfaces::PrimitiveFsOpsClient;
 /// This function uses the primitive method [PrimitiveFsOpsClient::read_bytes] and does not
 /// create a virtual file.
 pub async fn read_to_string<P>(
-    mut ctx: rfs_core::middleware::ContextManager,
+    ctx: rfs_core::middleware::ContextManager,
     path: P,
 ) -> io::Result<String>
 where
     P: AsRef<Path>,
     // T: TransmissionProtocol,
 {
-    let contents = PrimitiveFsOpsClient::read_all(
-        &mut ctx,
-        path.as_ref()
-            .to_str()
-            .and_then(|s| Some(s.to_owned()))
-            .unwrap_or_default(),
-    )
-    .await
-    .map_err(|e| io::Error::from(e))?;
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    let contents = PrimitiveFsOpsClient::read_all(&mut client, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(|e| io::Error::from(e))?;
 
     let x = std::str::from_utf8(&contents)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
@@ -40,22 +77,422 @@ where
     Ok(x.to_owned())
 }
 
+/// Read up to `len` bytes of a file, starting from `offset`.
+///
+/// If `len` is `None`, the rest of the file is read.
+///
+/// If the context manager has a read cache, fresh blocks are served from it. Stale blocks are
+/// checked against the modification time of the remote file, and read again if it was modified.
+pub async fn read_range<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    offset: usize,
+    len: Option<usize>,
+) -> io::Result<Vec<u8>> {
+    let path = VirtPath::from(path.as_ref());
+    let mut client = RetryingClient::new(ctx.clone(), RetryPolicy::default());
+
+    let cache = match ctx.read_cache() {
+        Some(c) => c,
+        None => {
+            return ImmutableFileOpsClient::read_file(&mut client, path, offset, len)
+                .await
+                .map_err(io::Error::from)?
+                .map_err(io::Error::from)
+        }
+    };
+
+    let lookup = cache
+        .lock()
+        .expect("read cache lock poisoned")
+        .lookup(path.as_str(), offset, len);
+
+    let modified = match lookup {
+        CacheLookup::Fresh(data) => return Ok(data),
+        CacheLookup::Stale { data, .. } => {
+            let current = last_modified(ctx.clone(), &path).await?;
+
+            let mut lock = cache.lock().expect("read cache lock poisoned");
+            if lock.revalidate(path.as_str(), current) {
+                log::debug!("{} was not modified since it was cached", path);
+                return Ok(data);
+            }
+
+            current
+        }
+        // checked before the read, so a write in between is caught by the next revalidation
+        CacheLookup::Missing => last_modified(ctx.clone(), &path).await?,
+    };
+
+    let data = ImmutableFileOpsClient::read_file(&mut client, path.clone(), offset, len)
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)?;
+
+    cache.lock().expect("read cache lock poisoned").insert(
+        path.as_str(),
+        offset,
+        len,
+        data.clone(),
+        modified,
+    );
+
+    Ok(data)
+}
+
+/// Remove the cached blocks of a file from the read cache of the context manager, if it has one.
+pub(crate) fn invalidate_cached(ctx: &rfs_core::middleware::ContextManager, path: &VirtPath) {
+    if let Some(cache) = ctx.read_cache() {
+        cache
+            .lock()
+            .expect("read cache lock poisoned")
+            .invalidate(path.as_str());
+    }
+}
+
+/// Read up to `len` bytes of a file from `offset`, along with the size of the file.
+pub async fn read_chunk<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    offset: usize,
+    len: usize,
+) -> io::Result<FileChunk> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    PrimitiveFsOpsClient::read_chunk(&mut client, VirtPath::from(path.as_ref()), offset, len)
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)
+}
+
+/// Returns a stream of the contents of a file, read `chunk_size` bytes at a time.
+///
+/// Each chunk is requested when the previous one is taken from the stream, so large files
+/// are never held in a single payload. The stream ends at the end of the file, or after the
+/// first error. The remote limits chunks to [MAX_CHUNK_SIZE].
+pub fn read_stream<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    chunk_size: usize,
+) -> BoxStream<'static, io::Result<Vec<u8>>> {
+    let path = VirtPath::from(path.as_ref());
+
+    futures::stream::try_unfold(Some(0), move |offset| {
+        let (ctx, path) = (ctx.clone(), path.clone());
+
+        async move {
+            let offset = match offset {
+                Some(o) => o,
+                None => return Ok(None),
+            };
+
+            let chunk = read_chunk(ctx, path.as_str(), offset, chunk_size).await?;
+            if chunk.data.is_empty() {
+                return Ok(None);
+            }
+
+            let next = offset + chunk.data.len();
+            let next = (next < chunk.size as usize).then_some(next);
+
+            Ok(Some((chunk.data, next)))
+        }
+    })
+    .boxed()
+}
+
+/// Copy a remote file to a local path, `chunk_size` bytes at a time.
+///
+/// Returns the number of bytes copied.
+pub async fn download<P: AsRef<Path>, Q: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    local_path: Q,
+    chunk_size: usize,
+) -> io::Result<usize> {
+    if chunk_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "chunk size must be non-zero",
+        ));
+    }
+
+    let mut local = std::fs::File::create(local_path)?;
+    let mut copied = 0;
+
+    let mut chunks = read_stream(ctx, path, chunk_size);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        local.write_all(&chunk)?;
+        copied += chunk.len();
+    }
+
+    Ok(copied)
+}
+
+/// Copy a remote file to a local path, reading up to `streams` chunks of `chunk_size` bytes
+/// at the same time.
+///
+/// The file is split into chunks by its size at the start of the transfer. Every read is sent
+/// from its own socket, and chunks are written to the local file in order as they arrive.
+///
+/// Returns the number of bytes copied.
+pub async fn download_parallel<P: AsRef<Path>, Q: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    local_path: Q,
+    chunk_size: usize,
+    streams: usize,
+) -> io::Result<usize> {
+    if streams == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "number of streams must be non-zero",
+        ));
+    }
+    if streams == 1 || chunk_size == 0 {
+        return download(ctx, path, local_path, chunk_size).await;
+    }
+
+    let path = path.as_ref();
+    let mut client = RetryingClient::new(ctx.clone(), RetryPolicy::default());
+    let size = PrimitiveFsOpsClient::file_size(&mut client, VirtPath::from(path))
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)?;
+
+    let mut local = std::fs::File::create(local_path)?;
+    let mut copied = 0;
+
+    let mut chunks = futures::stream::iter((0..size).step_by(chunk_size))
+        .map(|start| read_range(ctx.clone(), path, start, Some(chunk_size)))
+        .buffered(streams);
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        local.write_all(&chunk)?;
+        copied += chunk.len();
+    }
+
+    Ok(copied)
+}
+
 /// Returns an iterator over the entries of a directory.
 pub async fn read_dir<P: AsRef<Path>>(
-    mut ctx: rfs_core::middleware::ContextManager,
+    ctx: rfs_core::middleware::ContextManager,
     path: P,
 ) -> io::Result<VirtReadDir> {
-    let entries = PrimitiveFsOpsClient::read_dir(
-        &mut ctx,
-        path.as_ref()
-            .to_str()
-            .and_then(|s| Some(s.to_owned()))
-            .unwrap_or_default(),
-    )
-    .await
-    .map_err(|e| io::Error::from(e))?;
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    let entries = PrimitiveFsOpsClient::read_dir(&mut client, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(|e| io::Error::from(e))?;
+
+    Ok(entries)
+}
+
+/// List a page of up to `limit` entries of a directory.
+///
+/// Pass the cursor of the previous page to continue a listing, or `None` to start from
+/// the first entry.
+pub async fn ls<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    cursor: Option<String>,
+    limit: usize,
+) -> io::Result<DirListing> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    ImmutableFileOpsClient::ls(&mut client, VirtPath::from(path.as_ref()), cursor, limit)
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)
+}
+
+/// Returns the entries of a directory and all of its subdirectories, in breadth-first order.
+///
+/// Symbolic links to directories are listed, but not followed.
+pub async fn read_dir_tree<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<Vec<VirtDirEntry>> {
+    let mut entries = Vec::new();
+    let mut pending = VecDeque::from([VirtPath::from(path.as_ref())]);
+
+    while let Some(dir) = pending.pop_front() {
+        for entry in read_dir(ctx.clone(), dir.as_str()).await?.entries {
+            if !entry.is_file() && entry.link_target().is_none() {
+                pending.push_back(VirtPath::from(&entry.path));
+            }
+            entries.push(entry);
+        }
+    }
 
-    Ok(VirtReadDir::from(entries))
+    Ok(entries)
+}
+
+/// Returns the change counter of a directory.
+///
+/// Compare this with [VirtReadDir::change_counter] to check if a directory listing is stale.
+pub async fn dir_change_counter<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<u64> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    PrimitiveFsOpsClient::dir_change_counter(&mut client, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(io::Error::from)
+}
+
+/// Watch a directory for created, removed and renamed entries, including those
+/// of its subdirectories if `recursive` is set.
+///
+/// Events are received on the returned channel. The watch is removed from the
+/// remote once the receiver is dropped.
+pub async fn watch_dir<P: AsRef<Path>>(
+    mut ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    recursive: bool,
+) -> io::Result<mpsc::Receiver<DirEvent>> {
+    let path = VirtPath::from(path.as_ref());
+
+    // this is the return socket the remote will send events to
+    let ret_sock = ctx.generate_socket().await?;
+    let ret_addr = sockaddr_to_v4(ret_sock.local_addr()?)?;
+
+    let handle =
+        CallbackOpsClient::subscribe_dir_watch(&ctx, path.clone(), recursive, ret_addr).await?;
+
+    let (tx, rx) = mpsc::channel(8);
+
+    tokio::spawn(async move {
+        // the watch is unregistered when the handle is dropped
+        let _handle = handle;
+
+        loop {
+            let listen_res = tokio::select! {
+                res = ctx.listen(&ret_sock) => res,
+                _ = tx.closed() => break,
+            };
+
+            let bytes = match listen_res {
+                Ok(b) => b,
+                // nothing changed
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    log::error!("watch on directory {} failed: {}", path, e);
+                    return;
+                }
+            };
+
+            let event: DirEvent = match rfs_core::deserialize(&bytes) {
+                Ok(ev) => ev,
+                Err(_) => {
+                    log::error!("invalid event for directory {}", path);
+                    continue;
+                }
+            };
+
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+
+        log::debug!("no longer watching directory {}", path);
+    });
+
+    Ok(rx)
+}
+
+/// Returns the total and free space in bytes of the remote disk.
+pub async fn disk_usage(ctx: rfs_core::middleware::ContextManager) -> io::Result<(u64, u64)> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    SystemOpsClient::disk_usage(&mut client)
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)
+}
+
+/// Check that `len` bytes can be written to the remote disk, leaving [FREE_SPACE_MARGIN] free.
+///
+/// Fails with [io::ErrorKind::StorageFull] if they cannot.
+pub async fn check_free_space(
+    ctx: rfs_core::middleware::ContextManager,
+    len: u64,
+) -> io::Result<()> {
+    let (total, free) = disk_usage(ctx).await?;
+
+    match free.saturating_sub(FREE_SPACE_MARGIN) >= len {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "remote disk has {} of {} bytes free, {} bytes are needed",
+                free,
+                total,
+                len + FREE_SPACE_MARGIN
+            ),
+        )),
+    }
+}
+
+/// Returns basic metadata of a file or directory, or `None` if it does not exist.
+pub async fn stat<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<Option<VirtMetadataLite>> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    PrimitiveFsOpsClient::stat(&mut client, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(io::Error::from)
+}
+
+/// Returns the last modification time of a file, in nanoseconds since the unix epoch.
+pub async fn last_modified<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<u64> {
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    PrimitiveFsOpsClient::last_modified(&mut client, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)
+}
+
+/// Returns basic metadata of many files or directories in one invocation, in the same order as `paths`.
+pub async fn stat_many<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    paths: &[P],
+) -> io::Result<Vec<Option<VirtMetadataLite>>> {
+    let paths = paths
+        .iter()
+        .map(|p| VirtPath::from(p.as_ref()).to_string())
+        .collect();
+
+    let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+    PrimitiveFsOpsClient::stat_many(&mut client, paths)
+        .await
+        .map_err(io::Error::from)
+}
+
+/// Checks if a file or directory exists on the remote.
+pub async fn exists<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<bool> {
+    Ok(stat(ctx, path).await?.is_some())
+}
+
+/// Checks if a path exists on the remote and is a file.
+pub async fn is_file<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<bool> {
+    Ok(stat(ctx, path).await?.is_some_and(|m| m.file))
+}
+
+/// Checks if a path exists on the remote and is a directory.
+pub async fn is_dir<P: AsRef<Path>>(
+    ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<bool> {
+    Ok(stat(ctx, path).await?.is_some_and(|m| !m.file))
 }
 
 /// Create a new directory at the specified path.
@@ -63,33 +500,74 @@ pub async fn create_dir<P: AsRef<Path>>(
     mut ctx: rfs_core::middleware::ContextManager,
     path: P,
 ) -> io::Result<()> {
-    PrimitiveFsOpsClient::mkdir(
+    PrimitiveFsOpsClient::mkdir(&mut ctx, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(|e| io::Error::from(e))?
+        .map_err(|e| io::Error::from(e))
+}
+
+/// Create a symbolic link at `link_path`, pointing to `target`.
+///
+/// The target is relative to the directory containing the link, like a relative symlink on the remote.
+/// Targets outside the remote's base directory are rejected.
+pub async fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(
+    mut ctx: rfs_core::middleware::ContextManager,
+    target: P,
+    link_path: Q,
+) -> io::Result<()> {
+    PrimitiveFsOpsClient::symlink(
         &mut ctx,
-        path.as_ref()
-            .to_str()
-            .and_then(|s| Some(s.to_owned()))
-            .unwrap_or_default(),
+        VirtPath::from(target.as_ref()),
+        VirtPath::from(link_path.as_ref()),
     )
     .await
-    .map_err(|e| io::Error::from(e))?
-    .map_err(|e| io::Error::from(e))
+    .map_err(io::Error::from)?
+    .map_err(io::Error::from)
 }
 
-/// Delete a directory and all of its contents.
-pub async fn remove_dir<P: AsRef<Path>>(
+/// Create a hard link at `dst` to the file at `src`.
+pub async fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(
     mut ctx: rfs_core::middleware::ContextManager,
-    path: P,
+    src: P,
+    dst: Q,
 ) -> io::Result<()> {
-    PrimitiveFsOpsClient::rmdir(
+    PrimitiveFsOpsClient::hardlink(
         &mut ctx,
-        path.as_ref()
-            .to_str()
-            .and_then(|s| Some(s.to_owned()))
-            .unwrap_or_default(),
+        VirtPath::from(src.as_ref()),
+        VirtPath::from(dst.as_ref()),
     )
     .await
-    .map_err(|e| io::Error::from(e))?
-    .map_err(|e| io::Error::from(e))
+    .map_err(io::Error::from)?
+    .map_err(io::Error::from)
+}
+
+/// Append a line of text to a file on the remote, creating the file if it does not exist.
+///
+/// The remote handles the newlines, so the length of the file does not need to be known.
+/// Returns the number of bytes appended.
+pub async fn append_line<P: AsRef<Path>>(
+    mut ctx: rfs_core::middleware::ContextManager,
+    path: P,
+    text: &str,
+) -> io::Result<usize> {
+    let path = VirtPath::from(path.as_ref());
+    invalidate_cached(&ctx, &path);
+
+    PrimitiveFsOpsClient::append_line(&mut ctx, path, text.to_owned(), session_id())
+        .await
+        .map_err(io::Error::from)?
+        .map_err(io::Error::from)
+}
+
+/// Delete a directory and all of its contents.
+pub async fn remove_dir<P: AsRef<Path>>(
+    mut ctx: rfs_core::middleware::ContextManager,
+    path: P,
+) -> io::Result<()> {
+    PrimitiveFsOpsClient::rmdir(&mut ctx, VirtPath::from(path.as_ref()))
+        .await
+        .map_err(|e| io::Error::from(e))?
+        .map_err(|e| io::Error::from(e))
 }
 
 /// Delete a file
@@ -97,16 +575,13 @@ pub async fn remove_file<P: AsRef<Path>>(
     mut ctx: rfs_core::middleware::ContextManager,
     path: P,
 ) -> io::Result<()> {
-    PrimitiveFsOpsClient::remove(
-        &mut ctx,
-        path.as_ref()
-            .to_str()
-            .and_then(|s| Some(s.to_owned()))
-            .unwrap_or_default(),
-    )
-    .await
-    .map_err(|e| io::Error::from(e))?
-    .map_err(|e| io::Error::from(e))
+    let path = VirtPath::from(path.as_ref());
+    invalidate_cached(&ctx, &path);
+
+    PrimitiveFsOpsClient::remove(&mut ctx, path)
+        .await
+        .map_err(|e| io::Error::from(e))?
+        .map_err(|e| io::Error::from(e))
 }
 
 mod testing {}
@@ -0,0 +1,267 @@
+//! Line-based diffs of file contents, in the unified format.
+//!
+//! Diffs contain hunks only, without the `---`/`+++` file headers:
+//!
+//! ```text
+//! @@ -2,2 +2,2 @@
+//!  second
+//! -third
+//! +3rd
+//! ```
+
+use std::fmt::Write;
+
+/// Number of unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Marks a line without a trailing newline
+const NO_NEWLINE_MARKER: &str = "\\ No newline at end of file";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns the unified diff that turns `old` into `new`.
+///
+/// The diff is empty if the contents are the same.
+pub fn unified_diff(old: &str, new: &str) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    // group changes that are close enough to share context into hunks
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (idx, _) in ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Equal)
+    {
+        let start = idx.saturating_sub(CONTEXT_LINES);
+        let end = (idx + 1 + CONTEXT_LINES).min(ops.len());
+
+        match hunks.last_mut() {
+            Some((_, prev_end)) if start <= *prev_end => *prev_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = String::new();
+    for (start, end) in hunks {
+        // line numbers of the hunk start, counted over the ops before it
+        let old_start = ops[..start]
+            .iter()
+            .filter(|(op, _)| *op != Op::Insert)
+            .count();
+        let new_start = ops[..start]
+            .iter()
+            .filter(|(op, _)| *op != Op::Delete)
+            .count();
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|(op, _)| *op != Op::Insert)
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|(op, _)| *op != Op::Delete)
+            .count();
+
+        let _ = writeln!(
+            diff,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        );
+
+        for (op, line) in &ops[start..end] {
+            let prefix = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+
+            diff.push(prefix);
+            diff.push_str(line);
+            if !line.ends_with('\n') {
+                diff.push('\n');
+                diff.push_str(NO_NEWLINE_MARKER);
+                diff.push('\n');
+            }
+        }
+    }
+
+    diff
+}
+
+/// Apply a unified diff to the contents it was made from.
+///
+/// Returns `None` if the diff is malformed or does not match the contents.
+pub fn apply_diff(old: &str, diff: &str) -> Option<String> {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let mut patched = String::with_capacity(old.len());
+    let mut pos = 0;
+
+    let mut diff_lines = diff.split_inclusive('\n').peekable();
+    while let Some(header) = diff_lines.next() {
+        let (old_start, old_len) = parse_hunk_header(header)?;
+
+        // hunks without old lines are inserted after their start line
+        let hunk_pos = match old_len {
+            0 => old_start,
+            _ => old_start.checked_sub(1)?,
+        };
+        if hunk_pos < pos || hunk_pos > old_lines.len() {
+            return None;
+        }
+        old_lines[pos..hunk_pos]
+            .iter()
+            .for_each(|l| patched.push_str(l));
+        pos = hunk_pos;
+
+        while let Some(line) = diff_lines.next_if(|l| !l.starts_with("@@")) {
+            let mut content = line.get(1..)?.to_string();
+            if diff_lines.next_if(|l| l.starts_with('\\')).is_some() {
+                content.pop();
+            }
+
+            match line.chars().next()? {
+                ' ' | '-' if old_lines.get(pos) != Some(&content.as_str()) => return None,
+                ' ' => {
+                    patched.push_str(&content);
+                    pos += 1;
+                }
+                '-' => pos += 1,
+                '+' => patched.push_str(&content),
+                _ => return None,
+            }
+        }
+    }
+
+    old_lines[pos..].iter().for_each(|l| patched.push_str(l));
+
+    Some(patched)
+}
+
+/// Formats the line range of a hunk. Empty ranges start at the line before them.
+fn hunk_range(lines_before: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", lines_before),
+        1 => format!("{}", lines_before + 1),
+        _ => format!("{},{}", lines_before + 1, len),
+    }
+}
+
+/// Returns the start and length of the old range in a hunk header
+fn parse_hunk_header(header: &str) -> Option<(usize, usize)> {
+    let old_range = header.strip_prefix("@@ -")?.split_whitespace().next()?;
+
+    match old_range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((old_range.parse().ok()?, 1)),
+    }
+}
+
+/// Returns the operations that turn the old lines into the new ones.
+///
+/// Lines common to the start and end are skipped before finding the longest common subsequence.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the LCS of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0_usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = match old_mid[i] == new_mid[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ops = old[..prefix]
+        .iter()
+        .map(|l| (Op::Equal, *l))
+        .collect::<Vec<_>>();
+
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        match (old_mid.get(i), new_mid.get(j)) {
+            (Some(o), Some(n)) if o == n => {
+                ops.push((Op::Equal, *o));
+                i += 1;
+                j += 1;
+            }
+            (Some(o), Some(_)) if lcs[i + 1][j] >= lcs[i][j + 1] => {
+                ops.push((Op::Delete, *o));
+                i += 1;
+            }
+            (Some(o), None) => {
+                ops.push((Op::Delete, *o));
+                i += 1;
+            }
+            (_, Some(n)) => {
+                ops.push((Op::Insert, *n));
+                j += 1;
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    ops.extend(old[old.len() - suffix..].iter().map(|l| (Op::Equal, *l)));
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "first\nsecond\nthird\nfourth\n";
+        let new = "first\nsecond\n3rd\nfourth\n";
+
+        assert_eq!(
+            unified_diff(old, new),
+            "@@ -1,4 +1,4 @@\n first\n second\n-third\n+3rd\n fourth\n"
+        );
+        assert_eq!(unified_diff(old, old), "");
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let cases = [
+            ("", "new file\n"),
+            ("one\ntwo\n", ""),
+            ("no newline", "no newline\nnow"),
+            (
+                "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n",
+                "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nl\nm\n",
+            ),
+        ];
+
+        for (old, new) in cases {
+            let diff = unified_diff(old, new);
+            assert_eq!(apply_diff(old, &diff).as_deref(), Some(new), "{}", diff);
+        }
+
+        let diff = unified_diff("a\nb\n", "a\nc\n");
+        assert_eq!(apply_diff("x\ny\n", &diff), None);
+    }
+}
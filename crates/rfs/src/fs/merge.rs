@@ -0,0 +1,346 @@
+//! Three-way merging of concurrent edits to a file.
+
+use crate::interfaces::FileUpdate;
+
+/// Marks the start of the local side of a conflict
+pub const CONFLICT_MARKER_LOCAL: &str = "<<<<<<< local\n";
+/// Separates the local and remote sides of a conflict
+pub const CONFLICT_MARKER_SEP: &str = "=======\n";
+/// Marks the end of the remote side of a conflict
+pub const CONFLICT_MARKER_REMOTE: &str = ">>>>>>> remote\n";
+
+/// A contiguous edit to a file.
+///
+/// The edit replaces `len` bytes from `offset` in the base contents with `data`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub offset: usize,
+    pub len: usize,
+    pub data: Vec<u8>,
+}
+
+/// The result of a three-way merge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeResult {
+    /// Edits did not overlap and were both applied.
+    Clean(Vec<u8>),
+
+    /// Some edits overlap. Overlapping lines are surrounded by conflict markers.
+    Conflict(Vec<u8>),
+}
+
+impl TextEdit {
+    /// Returns the edit made by a [FileUpdate] to the `base` contents.
+    pub fn from_update(upd: &FileUpdate, base: &[u8]) -> Self {
+        let base_len = base.len();
+        match upd {
+            FileUpdate::Append(data) => Self {
+                offset: base_len,
+                len: 0,
+                data: data.clone(),
+            },
+            FileUpdate::Insert((offset, data)) => Self {
+                offset: (*offset).min(base_len),
+                len: 0,
+                data: data.clone(),
+            },
+            FileUpdate::Overwrite(data) => Self {
+                offset: 0,
+                len: base_len,
+                data: data.clone(),
+            },
+            FileUpdate::Restarted => Self {
+                offset: 0,
+                len: 0,
+                data: vec![],
+            },
+            FileUpdate::WriteAt(_) | FileUpdate::Diff(_) => {
+                let edited = upd.clone().update_file(base);
+                Self::diff(base, &edited).unwrap_or(Self {
+                    offset: 0,
+                    len: 0,
+                    data: vec![],
+                })
+            }
+        }
+    }
+
+    /// Returns the smallest edit that turns `base` into `edited`, if they differ.
+    pub fn diff(base: &[u8], edited: &[u8]) -> Option<Self> {
+        if base == edited {
+            return None;
+        }
+
+        let prefix = base
+            .iter()
+            .zip(edited.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = base[prefix..]
+            .iter()
+            .rev()
+            .zip(edited[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        Some(Self {
+            offset: prefix,
+            len: base.len() - suffix - prefix,
+            data: edited[prefix..edited.len() - suffix].to_vec(),
+        })
+    }
+
+    /// End of the replaced range in the base contents
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    /// Checks if two edits touch the same region of the base contents.
+    ///
+    /// Edits that share a boundary are considered overlapping, as their order is ambiguous.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.offset <= other.end() && other.offset <= self.end()
+    }
+
+    /// Change in length of the contents after applying this edit
+    pub fn delta(&self) -> isize {
+        self.data.len() as isize - self.len as isize
+    }
+}
+
+/// Merge a set of local edits and a remote update to the same base contents.
+///
+/// Local edits must not overlap with each other.
+/// Local edits that overlap with the remote update are written out in full lines,
+/// between conflict markers.
+pub fn merge(base: &[u8], local: &[TextEdit], remote: &FileUpdate) -> MergeResult {
+    let remote = TextEdit::from_update(remote, base);
+
+    let mut local = local.to_vec();
+    local.sort_by_key(|e| e.offset);
+
+    // region of the base contents in conflict, expanded to full lines
+    let mut conflict: Option<(usize, usize)> = None;
+    loop {
+        let (start, end) = conflict.unwrap_or((remote.offset, remote.end()));
+
+        let overlapping = local
+            .iter()
+            .filter(|e| match conflict {
+                // edits touching the line boundaries of the region are not in conflict
+                Some(_) => e.offset < end && e.end().max(e.offset + 1) > start,
+                None => remote.overlaps(e),
+            })
+            .fold(None, |acc: Option<(usize, usize)>, e| match acc {
+                Some((s, en)) => Some((s.min(e.offset), en.max(e.end()))),
+                None => Some((e.offset, e.end())),
+            });
+
+        let expanded = match overlapping {
+            Some((s, e)) => line_bounds(base, start.min(s), end.max(e)),
+            None => break,
+        };
+
+        match conflict == Some(expanded) {
+            true => break,
+            false => conflict = Some(expanded),
+        }
+    }
+
+    match conflict {
+        None => {
+            let mut edits = local;
+            edits.push(remote);
+            MergeResult::Clean(apply_edits(base, &edits))
+        }
+        Some((start, end)) => {
+            let (inside, outside): (Vec<_>, Vec<_>) = local
+                .into_iter()
+                .partition(|e| e.offset >= start && e.end() <= end);
+
+            let rebase = |e: &TextEdit| TextEdit {
+                offset: e.offset - start,
+                ..e.clone()
+            };
+            let region = &base[start..end];
+            let local_side = apply_edits(region, &inside.iter().map(rebase).collect::<Vec<_>>());
+            let remote_side = apply_edits(region, &[rebase(&remote)]);
+
+            let conflict_edit = TextEdit {
+                offset: start,
+                len: end - start,
+                data: [
+                    CONFLICT_MARKER_LOCAL.as_bytes(),
+                    &with_trailing_newline(local_side),
+                    CONFLICT_MARKER_SEP.as_bytes(),
+                    &with_trailing_newline(remote_side),
+                    CONFLICT_MARKER_REMOTE.as_bytes(),
+                ]
+                .concat(),
+            };
+
+            let mut edits = outside;
+            edits.push(conflict_edit);
+            MergeResult::Conflict(apply_edits(base, &edits))
+        }
+    }
+}
+
+/// Apply a set of non-overlapping edits to the base contents.
+fn apply_edits(base: &[u8], edits: &[TextEdit]) -> Vec<u8> {
+    let mut edits = edits.iter().collect::<Vec<_>>();
+    edits.sort_by_key(|e| e.offset);
+
+    let mut merged = Vec::with_capacity(base.len());
+    let mut pos = 0;
+    for edit in edits {
+        merged.extend_from_slice(&base[pos..edit.offset]);
+        merged.extend_from_slice(&edit.data);
+        pos = edit.end();
+    }
+    merged.extend_from_slice(&base[pos..]);
+
+    merged
+}
+
+/// Expand a byte range to the start and end of the lines it touches.
+///
+/// The end includes the trailing newline, if any.
+fn line_bounds(base: &[u8], start: usize, end: usize) -> (usize, usize) {
+    let line_start = base[..start]
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map(|p| p + 1)
+        .unwrap_or(0);
+
+    // range already ends on a line boundary
+    if end > start && base[end - 1] == b'\n' {
+        return (line_start, end);
+    }
+
+    let line_end = base[end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|p| end + p + 1)
+        .unwrap_or(base.len());
+
+    (line_start, line_end)
+}
+
+fn with_trailing_newline(mut bytes: Vec<u8>) -> Vec<u8> {
+    if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+        bytes.push(b'\n');
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let edit = TextEdit::diff(b"hello world", b"hello there world").unwrap();
+        assert_eq!(
+            edit,
+            TextEdit {
+                offset: 6,
+                len: 0,
+                data: b"there ".to_vec()
+            }
+        );
+
+        let edit = TextEdit::diff(b"hello world", b"hello").unwrap();
+        assert_eq!(edit.offset, 5);
+        assert_eq!(edit.len, 6);
+        assert!(edit.data.is_empty());
+
+        assert!(TextEdit::diff(b"hello", b"hello").is_none());
+    }
+
+    #[test]
+    fn test_merge_disjoint() {
+        let base = b"first line\nsecond line\nthird line\n";
+        let local = TextEdit::diff(base, b"first line\nsecond line\n3rd line\n").unwrap();
+
+        let res = merge(
+            base,
+            std::slice::from_ref(&local),
+            &FileUpdate::Insert((0, b"zeroth\n".to_vec())),
+        );
+        assert_eq!(
+            res,
+            MergeResult::Clean(b"zeroth\nfirst line\nsecond line\n3rd line\n".to_vec())
+        );
+
+        let res = merge(
+            base,
+            &[local],
+            &FileUpdate::Append(b"fourth line\n".to_vec()),
+        );
+        assert_eq!(
+            res,
+            MergeResult::Clean(b"first line\nsecond line\n3rd line\nfourth line\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_merge_multiple_local_edits() {
+        let base = b"aaaa\nbbbb\ncccc\n";
+        let local = [
+            TextEdit {
+                offset: 0,
+                len: 1,
+                data: b"A".to_vec(),
+            },
+            TextEdit {
+                offset: 14,
+                len: 0,
+                data: b"C".to_vec(),
+            },
+        ];
+
+        let res = merge(base, &local, &FileUpdate::Insert((7, b"B".to_vec())));
+        assert_eq!(res, MergeResult::Clean(b"Aaaa\nbbBbb\nccccC\n".to_vec()));
+    }
+
+    #[test]
+    fn test_merge_overlapping() {
+        let base = b"first line\nsecond line\nthird line\n";
+        let local = TextEdit::diff(base, b"first line\n2nd line\nthird line\n").unwrap();
+
+        let res = merge(base, &[local], &FileUpdate::Insert((11, b"the ".to_vec())));
+
+        let expected = [
+            "first line\n",
+            CONFLICT_MARKER_LOCAL,
+            "2nd line\n",
+            CONFLICT_MARKER_SEP,
+            "the second line\n",
+            CONFLICT_MARKER_REMOTE,
+            "third line\n",
+        ]
+        .concat();
+
+        assert_eq!(res, MergeResult::Conflict(expected.into_bytes()));
+    }
+
+    #[test]
+    fn test_merge_overwrite_conflicts() {
+        let base = b"one\ntwo";
+        let local = TextEdit::diff(base, b"one\ntwo!").unwrap();
+
+        let res = merge(base, &[local], &FileUpdate::Overwrite(b"three".to_vec()));
+
+        let expected = [
+            CONFLICT_MARKER_LOCAL,
+            "one\ntwo!\n",
+            CONFLICT_MARKER_SEP,
+            "three\n",
+            CONFLICT_MARKER_REMOTE,
+        ]
+        .concat();
+
+        assert_eq!(res, MergeResult::Conflict(expected.into_bytes()));
+    }
+}
@@ -0,0 +1,176 @@
+//! Handles to callback registrations on the remote.
+
+use std::{io, marker::PhantomData, net::SocketAddrV4};
+
+use rfs_core::middleware::ContextManager;
+
+use crate::interfaces::{
+    CallbackOpsClient, DirEvent, FileUpdateNotice, MissedUpdates, SubscriptionId, WatchMode,
+};
+
+use super::VirtPath;
+
+/// A callback registration on the remote, for callbacks of type `T`.
+///
+/// Dropping the handle unregisters the callback in the background.
+#[derive(Debug)]
+pub struct SubscriptionHandle<T> {
+    ctx: ContextManager,
+    id: SubscriptionId,
+    registered: bool,
+    /// Removed with [CallbackOpsClient::unsubscribe] instead of [CallbackOpsClient::unregister]
+    persistent: bool,
+    _callback: PhantomData<fn() -> T>,
+}
+
+impl<T> SubscriptionHandle<T> {
+    fn new(ctx: ContextManager, id: SubscriptionId) -> Self {
+        Self {
+            ctx,
+            id,
+            registered: true,
+            persistent: false,
+            _callback: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    /// Unregister the callback, returning `false` if the remote no longer had it.
+    pub async fn unregister(mut self) -> io::Result<bool> {
+        self.registered = false;
+
+        remove(&mut self.ctx, self.id, self.persistent).await
+    }
+
+    /// The remote removed the registration by itself, e.g. a watch that was triggered.
+    pub(crate) fn triggered(&mut self) {
+        self.registered = false;
+    }
+
+    /// The subscription was renewed under `id`, which changes if the remote had lost it.
+    pub(crate) fn renewed(&mut self, id: SubscriptionId) {
+        if id != self.id {
+            log::debug!("subscription {} renewed as {}", self.id, id);
+            self.id = id;
+        }
+    }
+}
+
+/// Remove a registration from the remote
+async fn remove(
+    ctx: &mut ContextManager,
+    id: SubscriptionId,
+    persistent: bool,
+) -> io::Result<bool> {
+    let res = match persistent {
+        true => CallbackOpsClient::unsubscribe(ctx, id).await,
+        false => CallbackOpsClient::unregister(ctx, id).await,
+    };
+
+    res.map_err(io::Error::from)
+}
+
+impl<T> Drop for SubscriptionHandle<T> {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+
+        // a handle can be dropped outside a runtime
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(h) => h,
+            Err(_) => {
+                log::error!("subscription {} could not be unregistered", self.id);
+                return;
+            }
+        };
+
+        let mut ctx = self.ctx.clone();
+        let (id, persistent) = (self.id, self.persistent);
+
+        handle.spawn(async move {
+            match remove(&mut ctx, id, persistent).await {
+                Ok(removed) => log::debug!("subscription {} unregistered: {}", id, removed),
+                Err(e) => log::error!("failed to unregister subscription {}: {:?}", id, e),
+            }
+        });
+    }
+}
+
+impl CallbackOpsClient {
+    /// Register a watch on a file, returning a handle that unregisters it when dropped.
+    ///
+    /// Updates are sent to `return_addr` in the given mode, see [crate::interfaces::CallbackOps::register_file_watch].
+    pub async fn subscribe_file_watch(
+        ctx: &ContextManager,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+    ) -> io::Result<SubscriptionHandle<FileUpdateNotice>> {
+        let mut ctx = ctx.clone();
+        let id = Self::register_file_watch(&mut ctx, path, return_addr, mode)
+            .await?
+            .map_err(io::Error::from)?;
+
+        Ok(SubscriptionHandle::new(ctx, id))
+    }
+
+    /// Register a watch on a file for a watcher that has seen it up to `version`, returning
+    /// a handle that unregisters it when dropped, and the updates the watcher missed.
+    ///
+    /// See [crate::interfaces::CallbackOps::resume_file_watch].
+    pub async fn resubscribe_file_watch(
+        ctx: &ContextManager,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+        version: u64,
+    ) -> io::Result<(SubscriptionHandle<FileUpdateNotice>, MissedUpdates)> {
+        let mut ctx = ctx.clone();
+        let (id, missed) = Self::resume_file_watch(&mut ctx, path, return_addr, mode, version)
+            .await?
+            .map_err(io::Error::from)?;
+
+        Ok((SubscriptionHandle::new(ctx, id), missed))
+    }
+
+    /// Subscribe to every update of a file, returning a handle that unsubscribes when dropped.
+    ///
+    /// Updates are sent to `return_addr` until the subscription is removed or expires,
+    /// see [crate::interfaces::CallbackOps::subscribe].
+    pub async fn subscribe_file(
+        ctx: &ContextManager,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+    ) -> io::Result<SubscriptionHandle<FileUpdateNotice>> {
+        let mut ctx = ctx.clone();
+        let id = Self::subscribe(&mut ctx, path, return_addr)
+            .await?
+            .map_err(io::Error::from)?;
+
+        let mut handle = SubscriptionHandle::new(ctx, id);
+        handle.persistent = true;
+
+        Ok(handle)
+    }
+
+    /// Register a watch on the entries of a directory, returning a handle that unregisters it when dropped.
+    ///
+    /// Events are sent to `return_addr`, see [crate::interfaces::CallbackOps::register_dir_update].
+    pub async fn subscribe_dir_watch(
+        ctx: &ContextManager,
+        path: VirtPath,
+        recursive: bool,
+        return_addr: SocketAddrV4,
+    ) -> io::Result<SubscriptionHandle<DirEvent>> {
+        let mut ctx = ctx.clone();
+        let id = Self::register_dir_update(&mut ctx, path, recursive, return_addr)
+            .await?
+            .map_err(io::Error::from)?;
+
+        Ok(SubscriptionHandle::new(ctx, id))
+    }
+}
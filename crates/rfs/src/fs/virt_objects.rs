@@ -9,14 +9,27 @@ use std::{
     net::{SocketAddr, SocketAddrV4},
     ops::Deref,
     path::{Path, PathBuf},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
+use futures::{stream::BoxStream, StreamExt};
 use rfs_core::{deserialize_packed, middleware::ContextManager};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
 
-use crate::interfaces::{CallbackOpsClient, FileUpdate, PrimitiveFsOpsClient};
+use crate::interfaces::{
+    CallbackOpsClient, FileUpdate, FileUpdateNotice, LockMode, LockOpsClient, MetadataNotice,
+    MissedUpdates, PrimitiveFsOpsClient, WatchMode, SUBSCRIPTION_TIMEOUT,
+};
+
+use super::{
+    watch_channel, OverflowPolicy, SubscriptionHandle, VirtPath, WatchReceiver,
+    DEFAULT_WATCH_BUFFER,
+};
+
+/// Subscriptions are renewed this often, well before they expire on the remote
+const SUBSCRIPTION_RENEWAL: Duration = Duration::from_secs(SUBSCRIPTION_TIMEOUT.as_secs() / 3);
 
 /// Errors for virtual IO
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -64,6 +77,25 @@ pub struct VirtFile {
 
     /// Information regarding reads
     read_info: FileReadMeta,
+
+    /// Version of the last update notice received for the file
+    version: u64,
+
+    /// The remote does not permit writes to the file
+    readonly: bool,
+
+    /// Updates held back until they are committed, see [VirtFile::begin_edit]
+    edit: Option<PendingEdit>,
+}
+
+/// Updates of a [VirtFile] that are not sent to the remote yet
+#[derive(Clone, Debug)]
+struct PendingEdit {
+    /// Local contents when the edit began
+    snapshot: Vec<u8>,
+
+    /// Updates to send, in order
+    updates: Vec<FileUpdate>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -93,7 +125,7 @@ pub struct VirtOpenOptions {
 /// An item inside a directory.
 ///
 /// This item can be a file, or a directory.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VirtDirEntry {
     /// Converted from the `path()` on the remote,
     /// because PathBuf does not implement serialize/deserialize.
@@ -103,18 +135,46 @@ pub struct VirtDirEntry {
 
     /// Marker for if the entry is for a file or directory
     pub file: bool,
+
+    /// Target of the entry if it is a symbolic link, as it was created
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+/// A page of the entries of a directory, listed with [ImmutableFileOps::ls](crate::interfaces::ImmutableFileOps::ls)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirListing {
+    /// Entries of the page, sorted by path
+    pub entries: Vec<VirtDirEntry>,
+
+    /// Continues the listing after this page, or `None` if this is the last page
+    pub cursor: Option<String>,
+
+    /// Number of entries in the directory, when the page was listed
+    pub total: usize,
+
+    /// Change counter of the directory, when the page was listed
+    pub change_counter: u64,
 }
 
 /// Iterator over [VirtDirEntry] items.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VirtReadDir {
     pub entries: Vec<VirtDirEntry>,
+
+    /// Change counter of the directory at the time it was read.
+    ///
+    /// If the remote counter differs from this, the entries may be stale.
+    pub change_counter: u64,
 }
 
 /// Virtual file metadata
 #[derive(Clone, Debug, Default)]
 #[allow(dead_code)]
 pub struct VirtMetadata {
+    /// Size in bytes
+    size: u64,
+
     /// Last file access time
     accessed: Option<SystemTime>,
 
@@ -124,6 +184,47 @@ pub struct VirtMetadata {
     permissions: VirtPermissions,
 }
 
+impl VirtMetadata {
+    /// Size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Checks if the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Last modification time of the file, if known
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+/// A portion of a file, read with [PrimitiveFsOps::read_chunk](crate::interfaces::PrimitiveFsOps::read_chunk)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub data: Vec<u8>,
+
+    /// Size of the file in bytes, when the chunk was read
+    pub size: u64,
+}
+
+/// Basic metadata of a file or directory on the remote
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VirtMetadataLite {
+    /// Marker for if the entry is for a file or directory
+    pub file: bool,
+
+    /// Size in bytes. This is 0 for directories.
+    pub size: u64,
+
+    /// Last modification time, in seconds since the unix epoch
+    pub modified: Option<u64>,
+
+    pub readonly: bool,
+}
+
 /// File permissions (rwx)
 #[derive(Clone, Debug, Default)]
 #[allow(dead_code)]
@@ -183,16 +284,21 @@ impl VirtDirEntry {
     ///
     /// If base is not a prefix of the entry's path, this method will return None.
     /// This is used instead of `From<DirEntry>` because of the additional base path requirement.
+    ///
+    /// The entry path is normalized, so it can be used as a key for watches.
     pub fn from_dir_entry<P: AsRef<Path>>(value: DirEntry, base: P) -> Option<Self> {
         let path = value.path();
         let rel = path.strip_prefix(base.as_ref()).ok()?;
 
+        let link = match value.file_type().ok()?.is_symlink() {
+            true => Some(fs::read_link(&path).ok()?.to_string_lossy().to_string()),
+            false => None,
+        };
+
         Some(Self {
-            path: rel
-                .to_str()
-                .and_then(|s| Some(s.to_owned()))
-                .unwrap_or_default(),
+            path: VirtPath::from(rel).to_string(),
             file: path.is_file(),
+            link,
         })
     }
 
@@ -206,6 +312,11 @@ impl VirtDirEntry {
         self.file
     }
 
+    /// Returns the target of the entry if it is a symbolic link
+    pub fn link_target(&self) -> Option<&str> {
+        self.link.as_deref()
+    }
+
     pub fn metadata(&self) -> VirtMetadata {
         todo!()
     }
@@ -224,6 +335,7 @@ impl<E: AsRef<[VirtDirEntry]>> From<E> for VirtReadDir {
     fn from(value: E) -> Self {
         Self {
             entries: value.as_ref().iter().map(|entry| entry.clone()).collect(),
+            change_counter: 0,
         }
     }
 }
@@ -233,44 +345,59 @@ impl VirtFile {
     ///
     /// Attempts to mirror [std::fs::File::create]
     pub async fn create<P: AsRef<Path>>(mut ctx: ContextManager, path: P) -> std::io::Result<Self> {
-        let _res = PrimitiveFsOpsClient::create(
-            &mut ctx,
-            path.as_ref()
-                .to_str()
-                .and_then(|s| Some(s.to_string()))
-                .unwrap_or_default(),
-        )
-        .await
-        .map_err(|_| io::Error::new(io::ErrorKind::Other, "invocation error"))?
-        .map_err(|e| io::Error::from(e))?;
+        let path = VirtPath::from(path.as_ref());
+        super::invalidate_cached(&ctx, &path);
+        let _res = PrimitiveFsOpsClient::create(&mut ctx, path.clone())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "invocation error"))?
+            .map_err(|e| io::Error::from(e))?;
 
         Ok(Self {
             ctx,
             metadata_local: Default::default(),
-            path: PathBuf::from(path.as_ref()),
+            path: PathBuf::from(path.as_str()),
             local_buf: Default::default(),
             read_info: Default::default(),
+            version: 0,
+            readonly: false,
+            edit: None,
         })
     }
 
     /// Open an existing file in read-only mode.
     ///
-    /// Attempts to mirror [std::fs::File::open]
+    /// Attempts to mirror [std::fs::File::open]. The contents are read through the read cache
+    /// of the context manager, if it has one.
     pub async fn open<P: AsRef<Path>>(mut ctx: ContextManager, path: P) -> std::io::Result<Self> {
         // let res = PrimitiveFsOpsClient
 
-        let contents =
-            PrimitiveFsOpsClient::read_all(&mut ctx, path.as_ref().to_str().unwrap().to_string())
+        let path = VirtPath::from(path.as_ref());
+        let contents = match ctx.read_cache() {
+            Some(_) => super::read_range(ctx.clone(), &path, 0, None).await?,
+            None => PrimitiveFsOpsClient::read_all(&mut ctx, path.clone())
                 .await
-                .map_err(|e| io::Error::from(e))?;
+                .map_err(|e| io::Error::from(e))?,
+        };
+
+        // files on remotes that cannot stat them are assumed to be writable
+        let meta = match PrimitiveFsOpsClient::stat(&mut ctx, path.clone()).await {
+            Ok(m) => m,
+            Err(e) => {
+                log::debug!("failed to stat {}: {:?}", path, e);
+                None
+            }
+        };
 
         // load contents into local buffer
         Ok(Self {
             ctx,
-            path: path.as_ref().to_path_buf(),
+            path: PathBuf::from(path.as_str()),
             metadata_local: VirtMetadata::default(),
             local_buf: contents,
             read_info: Default::default(), // this needs to contain file info
+            version: 0,
+            readonly: meta.map(|m| m.readonly).unwrap_or_default(),
+            edit: None,
         })
     }
 
@@ -287,6 +414,13 @@ impl VirtFile {
             .unwrap_or_default()
     }
 
+    /// Returns `true` if the remote does not permit writes to the file.
+    ///
+    /// This is read when the file is opened.
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
     /// Returns the locally cached file contents
     pub fn local_cache(&self) -> &[u8] {
         &self.local_buf
@@ -296,7 +430,7 @@ impl VirtFile {
     pub async fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
         let path = self.as_path();
 
-        let res = PrimitiveFsOpsClient::read_all(&mut self.ctx, path)
+        let res = PrimitiveFsOpsClient::read_all(&mut self.ctx, path.into())
             .await
             .map_err(|e| io::Error::from(e))?;
 
@@ -305,110 +439,741 @@ impl VirtFile {
         Ok(res)
     }
 
+    /// Read up to `len` bytes of the file, starting from `offset`.
+    ///
+    /// If `len` is `None`, the rest of the file is read. The local cache is not updated,
+    /// but the read cache of the context manager is used, see [read_range](super::read_range).
+    pub async fn read_range(&mut self, offset: usize, len: Option<usize>) -> io::Result<Vec<u8>> {
+        super::read_range(self.ctx.clone(), &self.path, offset, len).await
+    }
+
+    /// Returns a stream of the contents of the remote file, read `chunk_size` bytes at a time.
+    ///
+    /// See [read_stream](super::read_stream). The local cache is not updated.
+    pub fn read_stream(&self, chunk_size: usize) -> BoxStream<'static, io::Result<Vec<u8>>> {
+        super::read_stream(self.ctx.clone(), &self.path, chunk_size)
+    }
+
     /// Write to the file from a vector of bytes.
+    ///
+    /// If the invocation fails, the file is read again with [VirtFile::recover_write].
+    /// A write that turns out to have been applied returns successfully. Otherwise the error
+    /// is returned with the [WriteOutcome], and the local cache holds the remote contents.
+    ///
+    /// During an edit, the update is only applied to the local cache until it is committed.
     pub async fn write_bytes(&mut self, data: FileUpdate) -> io::Result<usize> {
+        if let Some(edit) = &mut self.edit {
+            // an overwrite replaces the updates before it
+            if matches!(data, FileUpdate::Overwrite(_)) {
+                edit.updates.clear();
+            }
+
+            self.local_buf = data.clone().update_file(&self.local_buf);
+            let size = data.len();
+            edit.updates.push(data);
+
+            return Ok(size);
+        }
+
         let path = self.as_path();
+        super::invalidate_cached(&self.ctx, &VirtPath::from(&self.path));
 
-        let _res = PrimitiveFsOpsClient::write_bytes(&mut self.ctx, path, data.clone())
-            .await
-            .map_err(|e| io::Error::from(e))?;
+        let res = PrimitiveFsOpsClient::write_bytes(
+            &mut self.ctx,
+            path.clone().into(),
+            data.clone(),
+            super::session_id(),
+        )
+        .await;
 
         let size = data.len();
+
+        if let Err(e) = res {
+            let e = io::Error::from(e);
+
+            return match self.recover_write(&data).await {
+                Ok(WriteOutcome::Applied) => {
+                    log::warn!("write to {} failed with {}, but was applied", path, e);
+                    Ok(size)
+                }
+                Ok(outcome) => Err(io::Error::new(e.kind(), format!("{}: {}", e, outcome))),
+                Err(read_err) => {
+                    log::error!("failed to read {} after a failed write: {}", path, read_err);
+                    Err(e)
+                }
+            };
+        }
+
         // update local buf only after write request completes
         self.local_buf = data.update_file(&self.local_buf);
 
         Ok(size)
     }
 
+    /// Begin an edit of the file.
+    ///
+    /// Writes are held back until [VirtFile::commit] sends them to the remote as one batch,
+    /// or [VirtFile::rollback] discards them. Does nothing if an edit is already in progress.
+    pub fn begin_edit(&mut self) {
+        if self.edit.is_none() {
+            self.edit = Some(PendingEdit {
+                snapshot: self.local_buf.clone(),
+                updates: vec![],
+            });
+        }
+    }
+
+    /// Returns `true` if an edit is in progress
+    pub fn in_edit(&self) -> bool {
+        self.edit.is_some()
+    }
+
+    /// Send the writes of the edit to the remote, and end the edit.
+    ///
+    /// The remote applies all of the writes or none of them. If the commit fails, the edit
+    /// stays in progress, so it can be committed again or rolled back.
+    /// Returns the number of bytes written, which is 0 if no edit is in progress.
+    pub async fn commit(&mut self) -> io::Result<usize> {
+        let updates = match &self.edit {
+            Some(edit) => edit.updates.clone(),
+            None => return Ok(0),
+        };
+
+        if updates.is_empty() {
+            self.edit = None;
+            return Ok(0);
+        }
+
+        let path = VirtPath::from(&self.path);
+        super::invalidate_cached(&self.ctx, &path);
+
+        let size =
+            PrimitiveFsOpsClient::write_batch(&mut self.ctx, path, updates, super::session_id())
+                .await
+                .map_err(io::Error::from)?
+                .map_err(io::Error::from)?;
+
+        self.edit = None;
+        Ok(size)
+    }
+
+    /// Discard the writes of the edit, and end the edit.
+    ///
+    /// The local cache is restored to the contents from before the edit, with any updates
+    /// from the remote since. Returns `false` if no edit is in progress.
+    pub fn rollback(&mut self) -> bool {
+        match self.edit.take() {
+            Some(edit) => {
+                self.local_buf = edit.snapshot;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Read the file again after a write of `data` failed, and check if the write was applied.
+    ///
+    /// The local cache is replaced with the remote contents.
+    pub async fn recover_write(&mut self, data: &FileUpdate) -> io::Result<WriteOutcome> {
+        let remote = PrimitiveFsOpsClient::read_all(&mut self.ctx, VirtPath::from(&self.path))
+            .await
+            .map_err(io::Error::from)?;
+
+        let expected = data.clone().update_file(&self.local_buf);
+
+        let outcome = if remote == expected {
+            WriteOutcome::Applied
+        } else if remote == self.local_buf {
+            WriteOutcome::NotApplied
+        } else if remote == data.clone().update_file(&expected) {
+            WriteOutcome::Duplicated
+        } else {
+            WriteOutcome::Diverged
+        };
+
+        log::debug!("{} after failed write: {}", self.as_path(), outcome);
+        self.local_buf = remote;
+
+        Ok(outcome)
+    }
+
     /// Blocks until the file is updated. The new file contents are returned,
     /// as well as the update information.
-    pub async fn watch(&mut self) -> io::Result<(Vec<u8>, FileUpdate)> {
+    ///
+    /// The watch fails with [io::ErrorKind::TimedOut] if no update arrives within the timeout,
+    /// and with [io::ErrorKind::Interrupted] once the token is cancelled.
+    /// A watch that ends without an update, including by dropping the returned future,
+    /// is removed from the remote.
+    pub async fn watch(
+        &mut self,
+        timeout: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> io::Result<(Vec<u8>, FileUpdateNotice)> {
+        self.watch_with(WatchMode::Raw, timeout, cancel).await
+    }
+
+    /// Same as [Self::watch], with updates sent in the given mode.
+    pub async fn watch_with(
+        &mut self,
+        mode: WatchMode,
+        timeout: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> io::Result<(Vec<u8>, FileUpdateNotice)> {
         // this is the return socket the remote will send callbacks to
         let ret_sock = self.ctx.generate_socket().await?;
+        let (mut guard, _) =
+            register_watch(&self.ctx, &self.as_path(), &ret_sock, mode, None).await?;
 
-        let _ = CallbackOpsClient::register_file_update(
-            &mut self.ctx,
-            self.path
-                .to_str()
-                .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?
-                .to_string(),
-            sockaddr_to_v4(ret_sock.local_addr()?)?,
-        )
-        .await?
-        .map_err(|e| io::Error::from(e))?;
-
-        let resp = self.ctx.listen(&ret_sock).await?;
+        let resp = listen_until(&mut self.ctx, &ret_sock, timeout, &cancel).await?;
+        guard.triggered();
         log::debug!("watch triggered");
 
-        let update: FileUpdate = deserialize_packed(&resp)
+        let notice: FileUpdateNotice = deserialize_packed(&resp)
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "deserialization failed"))?;
 
-        self.local_buf = update.clone().update_file(&self.local_buf);
+        self.update_bytes(&notice);
+
+        Ok((self.local_buf.clone(), notice))
+    }
+
+    /// Watch for changes to the size and modification time of the file, without receiving the updates.
+    ///
+    /// The stream yields the metadata of the file after every update, and ends once the token
+    /// is cancelled. The watch is removed from the remote when the stream is dropped.
+    pub async fn watch_metadata(
+        &self,
+        cancel: CancellationToken,
+    ) -> io::Result<BoxStream<'static, io::Result<VirtMetadata>>> {
+        // this is the return socket the remote will send callbacks to
+        let ret_sock = self.ctx.generate_socket().await?;
+        let (guard, _) = register_watch(
+            &self.ctx,
+            &self.as_path(),
+            &ret_sock,
+            WatchMode::Metadata,
+            None,
+        )
+        .await?;
+
+        let state = (self.ctx.clone(), self.as_path(), ret_sock, guard);
+
+        let stream = futures::stream::try_unfold(state, move |state| {
+            let cancel = cancel.clone();
+
+            async move {
+                let (mut ctx, path, ret_sock, mut guard) = state;
+
+                loop {
+                    let resp = match listen_until(&mut ctx, &ret_sock, None, &cancel).await {
+                        Ok(r) => r,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(None),
+                        Err(e) => return Err(e),
+                    };
+                    guard.triggered();
 
-        Ok((self.local_buf.clone(), update))
+                    let notice: MetadataNotice = rfs_core::deserialize(&resp).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "deserialization failed")
+                    })?;
+
+                    // watches are one-shot on the remote
+                    (guard, _) =
+                        register_watch(&ctx, &path, &ret_sock, WatchMode::Metadata, None).await?;
+
+                    // the remote restarted, or could not read the metadata
+                    if let Some(metadata) = notice.metadata {
+                        let state = (ctx, path, ret_sock, guard);
+                        return Ok(Some((VirtMetadata::from(metadata), state)));
+                    }
+                }
+            }
+        });
+
+        Ok(stream.boxed())
     }
 
-    /// Watch for file updates on the returned channel.
+    /// Watch for file updates on the returned channel, until the watch ends.
     ///
     /// The local file buffer will need to be manually updated.
     /// The updated file contents are: file path and update info.
-    pub async fn watch_chan(&self) -> io::Result<mpsc::Receiver<io::Result<(String, FileUpdate)>>> {
+    ///
+    /// The watch ends with an error if no update arrives within the timeout,
+    /// or once the token is cancelled, as in [Self::watch].
+    /// The watch is removed from the remote when the receiver is dropped.
+    pub async fn watch_chan(
+        &self,
+        timeout: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> io::Result<WatchReceiver<(String, FileUpdateNotice)>> {
+        self.watch_chan_with(WatchOptions::default().with_timeout(timeout), cancel)
+            .await
+    }
+
+    /// Same as [Self::watch_chan], with the mode and buffering of the watch set by `options`.
+    ///
+    /// In [WatchMode::Diff], overwrites of text files arrive as [FileUpdate::Diff],
+    /// which only applies to the contents before the update.
+    pub async fn watch_chan_with(
+        &self,
+        options: WatchOptions,
+        cancel: CancellationToken,
+    ) -> io::Result<WatchReceiver<(String, FileUpdateNotice)>> {
         // this is the return socket the remote will send callbacks to
         let ret_sock = self.ctx.generate_socket().await?;
-
-        let _ = CallbackOpsClient::register_file_update(
-            &mut self.ctx.clone(),
-            self.path
-                .to_str()
-                .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?
-                .to_string(),
-            sockaddr_to_v4(ret_sock.local_addr()?)?,
+        let (mut guard, mut missed) = register_watch(
+            &self.ctx,
+            &self.as_path(),
+            &ret_sock,
+            options.mode,
+            options.since,
         )
-        .await?
-        .map_err(|e| io::Error::from(e))?;
+        .await?;
 
-        let (tx, rx) = mpsc::channel(3);
+        let (tx, rx) = watch_channel(options.buffer, options.overflow);
 
         let mut ctx_clone = self.ctx.clone();
         let file_path = self.as_path();
 
         tokio::spawn(async move {
-            let resp = match ctx_clone.listen(&ret_sock).await {
-                Ok(r) => r,
-                Err(e) => {
-                    log::error!("watch callback failed: {:?}", e);
-                    tx.send(Err(io::Error::from(e)))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                        .unwrap();
-                    return;
+            loop {
+                // updates made while the watch was not registered come first
+                for update in missed.drain(..) {
+                    if tx.send(Ok((file_path.clone(), update))).await.is_err() {
+                        return;
+                    }
                 }
-            };
 
-            let update: FileUpdate = match deserialize_packed(&resp)
-                .map_err(|_e| io::Error::new(io::ErrorKind::InvalidData, "deserialization failed"))
-            {
-                Ok(upd) => upd,
-                Err(e) => {
-                    tx.send(Err(e))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                        .unwrap();
+                // stop listening once the receiver is dropped
+                let listen_res = tokio::select! {
+                    res = listen_until(&mut ctx_clone, &ret_sock, options.timeout, &cancel) => res,
+                    _ = tx.closed() => {
+                        log::debug!("watch receiver dropped for {:?}", file_path);
+                        return;
+                    }
+                };
+
+                let resp = match listen_res {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::debug!("watch on {:?} ended: {:?}", file_path, e);
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+                guard.triggered();
+
+                let update: FileUpdateNotice = match deserialize_packed(&resp).map_err(|_e| {
+                    io::Error::new(io::ErrorKind::InvalidData, "deserialization failed")
+                }) {
+                    Ok(upd) => upd,
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+
+                // watches are one-shot on the remote, register again before handing out the update.
+                // the remote returns the updates made in between
+                let next = register_watch(
+                    &ctx_clone,
+                    &file_path,
+                    &ret_sock,
+                    options.mode,
+                    Some(update.version),
+                )
+                .await;
+
+                if tx.send(Ok((file_path.clone(), update))).await.is_err() {
                     return;
                 }
-            };
 
-            tx.send(Ok((file_path, update))).await.unwrap();
+                (guard, missed) = match next {
+                    Ok(g) => g,
+                    Err(e) => {
+                        log::error!("failed to watch {:?} again: {}", file_path, e);
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+            }
         });
 
         Ok(rx)
     }
 
-    /// Update the local contents of the file.
+    /// Subscribe to every update of the file on the returned channel.
+    ///
+    /// Unlike [Self::watch_chan], which registers again after every update, a single
+    /// subscription is kept on the remote and renewed in the background. Each subscription
+    /// has its own socket, so a client can hold any number of them.
+    ///
+    /// The subscription ends once the token is cancelled, and is removed from the remote
+    /// when the receiver is dropped.
+    pub async fn subscribe(
+        &self,
+        cancel: CancellationToken,
+    ) -> io::Result<WatchReceiver<(String, FileUpdateNotice)>> {
+        // this is the return socket the remote will send updates to
+        let ret_sock = self.ctx.generate_socket().await?;
+        let return_addr = sockaddr_to_v4(ret_sock.local_addr()?)?;
+        let file_path = self.as_path();
+
+        let mut handle = CallbackOpsClient::subscribe_file(
+            &self.ctx,
+            VirtPath::from(file_path.as_str()),
+            return_addr,
+        )
+        .await?;
+
+        let (tx, rx) = watch_channel(DEFAULT_WATCH_BUFFER, OverflowPolicy::default());
+        let mut ctx = self.ctx.clone();
+
+        tokio::spawn(async move {
+            let mut renewal = tokio::time::interval(SUBSCRIPTION_RENEWAL);
+            renewal.reset();
+
+            loop {
+                let listen_res = tokio::select! {
+                    res = listen_until(&mut ctx, &ret_sock, None, &cancel) => res,
+                    _ = renewal.tick() => {
+                        let path = VirtPath::from(file_path.as_str());
+                        match CallbackOpsClient::subscribe(&mut ctx, path, return_addr).await {
+                            Ok(Ok(id)) => handle.renewed(id),
+                            res => {
+                                log::error!(
+                                    "failed to renew subscription to {:?}: {:?}",
+                                    file_path,
+                                    res
+                                );
+                                let err = io::Error::new(
+                                    io::ErrorKind::NotConnected,
+                                    "subscription lost",
+                                );
+                                let _ = tx.send(Err(err)).await;
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    _ = tx.closed() => {
+                        log::debug!("subscription receiver dropped for {:?}", file_path);
+                        return;
+                    }
+                };
+
+                let resp = match listen_res {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::debug!("subscription to {:?} ended: {:?}", file_path, e);
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+
+                let update: FileUpdateNotice = match deserialize_packed(&resp) {
+                    Ok(upd) => upd,
+                    Err(_) => {
+                        let err =
+                            io::Error::new(io::ErrorKind::InvalidData, "deserialization failed");
+                        let _ = tx.send(Err(err)).await;
+                        return;
+                    }
+                };
+
+                if tx.send(Ok((file_path.clone(), update))).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Take an advisory lock on `len` bytes of the file from `offset`.
+    ///
+    /// Fails with [io::ErrorKind::WouldBlock] if another client holds a conflicting lock.
+    /// The lock is released when the guard is dropped, or with [RangeLockGuard::unlock].
+    pub async fn lock_range(
+        &self,
+        offset: usize,
+        len: usize,
+        mode: LockMode,
+    ) -> io::Result<RangeLockGuard> {
+        let mut ctx = self.ctx.clone();
+        let path = VirtPath::from(&self.path);
+
+        LockOpsClient::lock_range(&mut ctx, path.clone(), offset, len, mode)
+            .await
+            .map_err(io::Error::from)?
+            .map_err(io::Error::from)?;
+
+        Ok(RangeLockGuard {
+            ctx,
+            path,
+            offset,
+            len,
+            mode,
+            held: true,
+        })
+    }
+
+    /// Update the local contents of the file with a notice from the remote.
+    ///
+    /// Notices that were already applied, and notices for writes made by this session,
+    /// are ignored. Returns `true` if the local contents were updated.
     ///
     /// If the remote file needs to be updated, use `write_bytes` instead.
-    pub fn update_bytes(&mut self, upd: FileUpdate) {
-        self.local_buf = upd.update_file(&self.local_buf);
+    pub fn update_bytes(&mut self, notice: &FileUpdateNotice) -> bool {
+        if notice.update == FileUpdate::Restarted {
+            log::info!("remote restarted, versions of {:?} start over", self.path);
+            self.version = notice.version;
+            return false;
+        }
+
+        if !self.is_new(notice) {
+            self.version = self.version.max(notice.version);
+            return false;
+        }
+
+        self.version = notice.version;
+        self.local_buf = notice.update.clone().update_file(&self.local_buf);
+        if let Some(edit) = &mut self.edit {
+            edit.snapshot = notice.update.clone().update_file(&edit.snapshot);
+        }
+        super::invalidate_cached(&self.ctx, &VirtPath::from(&self.path));
+        true
+    }
+
+    /// Checks if a notice has not been applied to the local contents of the file.
+    ///
+    /// A [FileUpdate::Restarted] notice changes no contents, and is never new.
+    pub fn is_new(&self, notice: &FileUpdateNotice) -> bool {
+        notice.update != FileUpdate::Restarted
+            && notice.version > self.version
+            && notice.author != Some(super::session_id())
+    }
+
+    /// Version of the last update notice received for the file
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Whether a failed write reached the remote, found by [VirtFile::recover_write]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteOutcome {
+    /// The remote has the contents the write would produce
+    Applied,
+
+    /// The write was applied twice, e.g. an append that was sent again
+    Duplicated,
+
+    /// The remote has the contents from before the write
+    NotApplied,
+
+    /// The remote has other contents, e.g. from a write by another client
+    Diverged,
+}
+
+impl Display for WriteOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            Self::Applied => "write was applied",
+            Self::Duplicated => "write was applied twice",
+            Self::NotApplied => "write was not applied",
+            Self::Diverged => "remote contents diverged",
+        };
+
+        write!(f, "{}", desc)
+    }
+}
+
+/// Options of a continuous watch
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    pub mode: WatchMode,
+
+    /// Time to wait for each update before the watch ends
+    pub timeout: Option<Duration>,
+
+    /// Number of updates buffered for the receiver
+    pub buffer: usize,
+
+    pub overflow: OverflowPolicy,
+
+    /// Version of the file the watcher has seen. Updates made since are received first.
+    pub since: Option<u64>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            mode: WatchMode::Raw,
+            timeout: None,
+            buffer: DEFAULT_WATCH_BUFFER,
+            overflow: OverflowPolicy::default(),
+            since: None,
+        }
+    }
+}
+
+impl WatchOptions {
+    /// Receive updates in this mode
+    pub fn with_mode(mut self, mode: WatchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// End the watch if no update arrives within the timeout
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Buffer up to `buffer` updates, and handle the overflow with a policy
+    pub fn with_buffer(mut self, buffer: usize, overflow: OverflowPolicy) -> Self {
+        self.buffer = buffer;
+        self.overflow = overflow;
+        self
+    }
+
+    /// Catch up from a version of the file, e.g. [VirtFile::version] of a file that was
+    /// watched before. If the remote no longer has the updates since, the contents of
+    /// the file are received as an overwrite.
+    pub fn with_since(mut self, version: u64) -> Self {
+        self.since = Some(version);
+        self
+    }
+}
+
+/// Register a watch that sends updates to a socket.
+///
+/// Watches are removed by the remote once triggered. Dropping the handle before then
+/// removes the watch, so cancelled watches do not leave callbacks behind.
+///
+/// If the watcher has seen the file up to version `since`, the updates it missed are returned
+/// in order. The file is read again if the remote no longer has them, and returned as an overwrite.
+async fn register_watch(
+    ctx: &ContextManager,
+    path: &str,
+    ret_sock: &UdpSocket,
+    mode: WatchMode,
+    since: Option<u64>,
+) -> io::Result<(SubscriptionHandle<FileUpdateNotice>, Vec<FileUpdateNotice>)> {
+    let return_addr = sockaddr_to_v4(ret_sock.local_addr()?)?;
+    let path = VirtPath::from(path);
+
+    let version = match since {
+        Some(v) => v,
+        None => {
+            let handle =
+                CallbackOpsClient::subscribe_file_watch(ctx, path, return_addr, mode).await?;
+            return Ok((handle, vec![]));
+        }
+    };
+
+    let (handle, missed) =
+        CallbackOpsClient::resubscribe_file_watch(ctx, path.clone(), return_addr, mode, version)
+            .await?;
+
+    match missed {
+        MissedUpdates::Replay(updates) => Ok((handle, updates)),
+        MissedUpdates::Refetch { version } => {
+            log::debug!(
+                "updates of {} since the watch are gone, reading it again",
+                path
+            );
+
+            let contents = PrimitiveFsOpsClient::read_all(&mut ctx.clone(), path)
+                .await
+                .map_err(io::Error::from)?;
+            let notice = FileUpdateNotice {
+                version,
+                author: None,
+                update: FileUpdate::Overwrite(contents),
+            };
+
+            Ok((handle, vec![notice]))
+        }
+    }
+}
+
+/// An advisory lock on a byte range of a [VirtFile].
+///
+/// Dropping the guard releases the lock in the background.
+#[derive(Debug)]
+pub struct RangeLockGuard {
+    ctx: ContextManager,
+    path: VirtPath,
+    offset: usize,
+    len: usize,
+    mode: LockMode,
+    held: bool,
+}
+
+impl RangeLockGuard {
+    /// Bytes covered by the lock
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset..self.offset + self.len
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Release the lock, returning `false` if the remote no longer had it.
+    pub async fn unlock(mut self) -> io::Result<bool> {
+        self.held = false;
+
+        LockOpsClient::unlock_range(&mut self.ctx, self.path.clone(), self.offset, self.len)
+            .await
+            .map_err(io::Error::from)
+    }
+}
+
+impl Drop for RangeLockGuard {
+    fn drop(&mut self) {
+        if !self.held {
+            return;
+        }
+
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(h) => h,
+            Err(_) => {
+                log::error!("lock on {} could not be released", self.path);
+                return;
+            }
+        };
+
+        let mut ctx = self.ctx.clone();
+        let (path, offset, len) = (self.path.clone(), self.offset, self.len);
+
+        handle.spawn(async move {
+            match LockOpsClient::unlock_range(&mut ctx, path.clone(), offset, len).await {
+                Ok(unlocked) => log::debug!("lock on {} released: {}", path, unlocked),
+                Err(e) => log::error!("failed to release lock on {}: {:?}", path, e),
+            }
+        });
+    }
+}
+
+/// Listen for a callback until the timeout elapses or the token is cancelled.
+async fn listen_until(
+    ctx: &mut ContextManager,
+    sock: &UdpSocket,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+) -> io::Result<Vec<u8>> {
+    let expired = async {
+        match timeout {
+            Some(t) => tokio::time::sleep(t).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        res = ctx.listen(sock) => res,
+        _ = expired => Err(io::Error::new(io::ErrorKind::TimedOut, "watch timed out")),
+        _ = cancel.cancelled() => Err(io::Error::new(io::ErrorKind::Interrupted, "watch cancelled")),
     }
 }
 
@@ -518,6 +1283,7 @@ fn sockaddr_to_v4(addr: SocketAddr) -> io::Result<SocketAddrV4> {
 impl From<fs::Metadata> for VirtMetadata {
     fn from(value: fs::Metadata) -> Self {
         Self {
+            size: value.len(),
             accessed: value.accessed().ok(),
             modified: value.modified().ok(),
             permissions: value.permissions().into(),
@@ -525,9 +1291,40 @@ impl From<fs::Metadata> for VirtMetadata {
     }
 }
 
-impl From<fs::Permissions> for VirtPermissions {
-    fn from(value: fs::Permissions) -> Self {
-        match value.readonly() {
+impl From<fs::Metadata> for VirtMetadataLite {
+    fn from(value: fs::Metadata) -> Self {
+        Self {
+            file: value.is_file(),
+            size: match value.is_file() {
+                true => value.len(),
+                false => 0,
+            },
+            modified: value
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            readonly: value.permissions().readonly(),
+        }
+    }
+}
+
+impl From<VirtMetadataLite> for VirtMetadata {
+    fn from(value: VirtMetadataLite) -> Self {
+        Self {
+            size: value.size,
+            accessed: None,
+            modified: value
+                .modified
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            permissions: VirtPermissions::from_readonly(value.readonly),
+        }
+    }
+}
+
+impl VirtPermissions {
+    fn from_readonly(readonly: bool) -> Self {
+        match readonly {
             true => Self {
                 read: (true, true, true),
                 write: Default::default(),
@@ -542,6 +1339,12 @@ impl From<fs::Permissions> for VirtPermissions {
     }
 }
 
+impl From<fs::Permissions> for VirtPermissions {
+    fn from(value: fs::Permissions) -> Self {
+        Self::from_readonly(value.readonly())
+    }
+}
+
 impl From<io::Error> for VirtIOErr {
     fn from(value: io::Error) -> Self {
         match value.kind() {
@@ -631,9 +1434,32 @@ mod testing {
 
     #[test]
     fn test_virt_dir_entry() {
-        let dir_entry = VirtDirEntry {
-            path: "top_dir/next_dir".to_string(),
-            file: todo!(),
-        };
+        let base = std::env::temp_dir().join(format!("rfs-dir-entry-{}", std::process::id()));
+        fs::create_dir_all(base.join("top_dir/next_dir")).unwrap();
+        fs::write(base.join("top_dir/file"), b"").unwrap();
+
+        // unnormalized bases still produce normalized entry paths
+        let mut entries = fs::read_dir(base.join("./top_dir//"))
+            .unwrap()
+            .filter_map(|e| VirtDirEntry::from_dir_entry(e.unwrap(), base.join(".")))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                VirtDirEntry {
+                    path: "top_dir/file".to_string(),
+                    file: true,
+                    link: None,
+                },
+                VirtDirEntry {
+                    path: "top_dir/next_dir".to_string(),
+                    file: false,
+                    link: None,
+                },
+            ]
+        );
     }
 }
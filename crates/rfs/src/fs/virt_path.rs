@@ -0,0 +1,259 @@
+//! Serializable paths on the remote
+
+use std::{
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Separator between path segments, regardless of platform
+const SEPARATOR: char = '/';
+
+/// The remote's base directory
+const BASE: &str = ".";
+
+/// A path on the remote, relative to the remote's base directory.
+///
+/// Paths are normalized on construction: `.` and empty segments are removed,
+/// and segments are always separated by `/`. The base directory is represented by `.`.
+///
+/// This is serialized as a plain string, so payloads containing string paths
+/// deserialize into a [VirtPath] and vice versa.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct VirtPath(String);
+
+impl VirtPath {
+    /// Returns the base directory of the remote
+    pub fn base() -> Self {
+        Self(BASE.to_string())
+    }
+
+    /// Normalize a `/`-separated path
+    fn normalize(path: &str) -> String {
+        let segments = path
+            .split(SEPARATOR)
+            .filter(|s| !s.is_empty() && *s != BASE)
+            .collect::<Vec<_>>();
+
+        match segments.is_empty() {
+            true => BASE.to_string(),
+            false => segments.join("/"),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Checks if this is the base directory of the remote
+    pub fn is_base(&self) -> bool {
+        self.0 == BASE
+    }
+
+    /// Returns the path segments
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0.split(SEPARATOR).filter(|s| *s != BASE)
+    }
+
+    /// Returns the last segment of the path, if it is not the base directory
+    pub fn file_name(&self) -> Option<&str> {
+        self.segments().last()
+    }
+
+    /// Returns the parent of the path. The base directory has no parent.
+    pub fn parent(&self) -> Option<Self> {
+        match self.is_base() {
+            true => None,
+            false => match self.0.rsplit_once(SEPARATOR) {
+                Some((parent, _)) => Some(Self::from(parent)),
+                None => Some(Self::base()),
+            },
+        }
+    }
+
+    /// Checks if this path is `dir` or is inside it
+    pub fn starts_with(&self, dir: &VirtPath) -> bool {
+        let mut segments = self.segments();
+        dir.segments().all(|s| segments.next() == Some(s))
+    }
+
+    /// Append a path to this one
+    pub fn join<P: Into<VirtPath>>(&self, path: P) -> Self {
+        Self::from(format!("{}/{}", self.0, path.into().0))
+    }
+
+    /// Resolve `..` segments without accessing the filesystem.
+    ///
+    /// Returns `None` if the path leaves the base directory.
+    pub fn resolve_parents(&self) -> Option<Self> {
+        let mut resolved: Vec<&str> = Vec::new();
+
+        for segment in self.segments() {
+            match segment {
+                ".." => {
+                    resolved.pop()?;
+                }
+                s => resolved.push(s),
+            }
+        }
+
+        Some(Self::from(resolved.join("/")))
+    }
+}
+
+impl Default for VirtPath {
+    fn default() -> Self {
+        Self::base()
+    }
+}
+
+impl Display for VirtPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AsRef<Path> for VirtPath {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl AsRef<str> for VirtPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for VirtPath {
+    fn from(value: &str) -> Self {
+        Self(Self::normalize(value))
+    }
+}
+
+impl From<String> for VirtPath {
+    fn from(value: String) -> Self {
+        Self::from(value.as_str())
+    }
+}
+
+impl From<&String> for VirtPath {
+    fn from(value: &String) -> Self {
+        Self::from(value.as_str())
+    }
+}
+
+impl From<&Path> for VirtPath {
+    fn from(value: &Path) -> Self {
+        // platform-specific separators are handled by `components`
+        let joined = value
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => Some(s.to_string_lossy()),
+                Component::ParentDir => Some("..".into()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Self::from(joined)
+    }
+}
+
+impl From<&PathBuf> for VirtPath {
+    fn from(value: &PathBuf) -> Self {
+        Self::from(value.as_path())
+    }
+}
+
+impl From<PathBuf> for VirtPath {
+    fn from(value: PathBuf) -> Self {
+        Self::from(value.as_path())
+    }
+}
+
+impl From<VirtPath> for String {
+    fn from(value: VirtPath) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rfs_core::ser_de;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            VirtPath::from("./some//nested/./file").as_str(),
+            "some/nested/file"
+        );
+        assert_eq!(VirtPath::from("dir/").as_str(), "dir");
+        assert_eq!(VirtPath::from("").as_str(), ".");
+        assert_eq!(VirtPath::from("./").as_str(), ".");
+        assert_eq!(
+            VirtPath::from(PathBuf::from(".").join("dir").join("file")),
+            VirtPath::from("dir/file")
+        );
+    }
+
+    #[test]
+    fn test_parent_and_join() {
+        let path = VirtPath::from("dir/nested/file");
+
+        assert_eq!(path.file_name(), Some("file"));
+        assert_eq!(path.parent(), Some(VirtPath::from("dir/nested")));
+        assert_eq!(VirtPath::from("file").parent(), Some(VirtPath::base()));
+        assert_eq!(VirtPath::base().parent(), None);
+        assert_eq!(VirtPath::base().file_name(), None);
+
+        assert_eq!(
+            VirtPath::base().join("dir").join("./file"),
+            VirtPath::from("dir/file")
+        );
+    }
+
+    #[test]
+    fn test_resolve_parents() {
+        assert_eq!(
+            VirtPath::from("dir/../other/./file").resolve_parents(),
+            Some(VirtPath::from("other/file"))
+        );
+        assert_eq!(
+            VirtPath::from("dir/..").resolve_parents(),
+            Some(VirtPath::base())
+        );
+        assert_eq!(VirtPath::from("dir/../..").resolve_parents(), None);
+        assert_eq!(VirtPath::from("../file").resolve_parents(), None);
+    }
+
+    #[test]
+    fn test_starts_with() {
+        let path = VirtPath::from("dir/nested/file");
+
+        assert!(path.starts_with(&VirtPath::base()));
+        assert!(path.starts_with(&VirtPath::from("dir")));
+        assert!(path.starts_with(&VirtPath::from("./dir/nested")));
+        assert!(path.starts_with(&path));
+
+        assert!(!path.starts_with(&VirtPath::from("di")));
+        assert!(!path.starts_with(&VirtPath::from("dir/nested/file/more")));
+        assert!(!VirtPath::base().starts_with(&path));
+    }
+
+    /// Paths sent as strings by older peers must deserialize into a [VirtPath].
+    #[test]
+    fn test_string_compat() {
+        let ser = ser_de::serialize(&"./dir//file".to_string()).unwrap();
+        let path: VirtPath = ser_de::deserialize(&ser).unwrap();
+        assert_eq!(path.as_str(), "dir/file");
+
+        let ser = ser_de::serialize(&path).unwrap();
+        let string: String = ser_de::deserialize(&ser).unwrap();
+        assert_eq!(string, "dir/file");
+    }
+}
@@ -0,0 +1,240 @@
+//! Bounded channel for the updates of a continuous watch.
+//!
+//! Unlike [tokio::sync::mpsc], a full channel does not have to hold up the sender.
+//! With [OverflowPolicy::DropOldest], the oldest buffered update is discarded instead,
+//! and the receiver is told how many updates it missed with [WatchEvent::Lagged].
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+/// Default number of updates buffered for a watch
+pub const DEFAULT_WATCH_BUFFER: usize = 16;
+
+/// What a watch does with a new update when its buffer is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest buffered update, and report it with [WatchEvent::Lagged]
+    #[default]
+    DropOldest,
+
+    /// Stop listening until the receiver makes room.
+    ///
+    /// Callbacks sent by the remote in the meantime may be lost.
+    Block,
+}
+
+/// Item received from a watch channel
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchEvent<T> {
+    Update(T),
+
+    /// Number of updates discarded since the last item received
+    Lagged(u64),
+}
+
+/// Receiving half of a watch channel.
+///
+/// Dropping the receiver stops the watch.
+#[derive(Debug)]
+pub struct WatchReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Sending half of a watch channel
+#[derive(Debug)]
+pub struct WatchSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+
+    /// Woken when an item is sent, or the sender is dropped
+    sent: Notify,
+
+    /// Woken when an item is received, or the receiver is dropped
+    received: Notify,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    queue: VecDeque<io::Result<T>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    lagged: u64,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+/// Create a watch channel that buffers up to `capacity` items.
+pub fn watch_channel<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (WatchSender<T>, WatchReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            policy,
+            lagged: 0,
+            sender_dropped: false,
+            receiver_dropped: false,
+        }),
+        sent: Notify::new(),
+        received: Notify::new(),
+    });
+
+    (
+        WatchSender {
+            shared: shared.clone(),
+        },
+        WatchReceiver { shared },
+    )
+}
+
+impl<T> WatchSender<T> {
+    /// Send an item, following the overflow policy if the buffer is full.
+    ///
+    /// Errors end the watch, so they are always buffered.
+    /// The item is returned if the receiver was dropped.
+    pub async fn send(&self, item: io::Result<T>) -> Result<(), io::Result<T>> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().expect("watch channel poisoned");
+                if state.receiver_dropped {
+                    return Err(item);
+                }
+
+                let full = state.queue.len() >= state.capacity && item.is_ok();
+                if !full || state.policy == OverflowPolicy::DropOldest {
+                    if full {
+                        state.queue.pop_front();
+                        state.lagged += 1;
+                    }
+                    state.queue.push_back(item);
+                    drop(state);
+
+                    self.shared.sent.notify_one();
+                    return Ok(());
+                }
+            }
+
+            self.shared.received.notified().await;
+        }
+    }
+
+    /// Completes once the receiver is dropped.
+    pub async fn closed(&self) {
+        while !self.is_closed() {
+            self.shared.received.notified().await;
+        }
+    }
+
+    /// Returns `true` if the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        self.shared
+            .state
+            .lock()
+            .expect("watch channel poisoned")
+            .receiver_dropped
+    }
+}
+
+impl<T> WatchReceiver<T> {
+    /// Receive the next item, or `None` once the watch has ended and the buffer is empty.
+    ///
+    /// If updates were discarded since the last call, [WatchEvent::Lagged] is received first.
+    pub async fn recv(&mut self) -> Option<io::Result<WatchEvent<T>>> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().expect("watch channel poisoned");
+                if state.lagged > 0 {
+                    let lagged = std::mem::take(&mut state.lagged);
+                    return Some(Ok(WatchEvent::Lagged(lagged)));
+                }
+
+                if let Some(item) = state.queue.pop_front() {
+                    drop(state);
+                    self.shared.received.notify_one();
+                    return Some(item.map(WatchEvent::Update));
+                }
+
+                if state.sender_dropped {
+                    return None;
+                }
+            }
+
+            self.shared.sent.notified().await;
+        }
+    }
+}
+
+impl<T> Drop for WatchSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.sender_dropped = true;
+        }
+        self.shared.sent.notify_one();
+    }
+}
+
+impl<T> Drop for WatchReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_dropped = true;
+        }
+        self.shared.received.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_channel_overflow() {
+        let (tx, mut rx) = watch_channel(2, OverflowPolicy::DropOldest);
+        for num in 0..5 {
+            tx.send(Ok(num)).await.unwrap();
+        }
+
+        // the oldest updates were discarded
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Lagged(3));
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Update(3));
+
+        // errors are kept even when full
+        tx.send(Ok(5)).await.unwrap();
+        tx.send(Err(io::ErrorKind::TimedOut.into())).await.unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Update(4));
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Update(5));
+        assert!(rx.recv().await.unwrap().is_err());
+        assert!(rx.recv().await.is_none());
+
+        // a blocking channel waits for room
+        let (tx, mut rx) = watch_channel(1, OverflowPolicy::Block);
+        tx.send(Ok(0)).await.unwrap();
+        let sender = tokio::spawn(async move {
+            tx.send(Ok(1)).await.unwrap();
+            tx.closed().await;
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Update(0));
+        assert_eq!(rx.recv().await.unwrap().unwrap(), WatchEvent::Update(1));
+
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(1), sender)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -1,33 +1,58 @@
 //! Virtual method definitions.
 //!
 //! All traits have [`remote_interface`] attribute and only contain async functions.
+//!
+//! This module is the only definition of the interfaces, shared by clients and servers.
+//! [INTERFACE_HASH] is exchanged when a client connects, so builds whose interfaces
+//! drifted apart are detected at runtime.
 
-use std::net::SocketAddrV4;
-use std::path::PathBuf;
+use std::{net::SocketAddrV4, time::Duration};
 
+use rfs_core::middleware::ClientId;
 use rfs_core::remote_interface;
 use rfs_core::RemoteMethodSignature;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_bytes::ByteBuf;
 
-use crate::fs::VirtDirEntry;
+use crate::fs::DirListing;
+use crate::fs::FileChunk;
 use crate::fs::VirtIOErr;
+use crate::fs::VirtMetadataLite;
+use crate::fs::VirtPath;
+use crate::fs::VirtReadDir;
 
 /// Immutable file operations are defined in this interface.
 #[remote_interface]
 pub trait ImmutableFileOps {
-    /// Read the contents of a file.
-    async fn read_file(path: PathBuf, offset: Option<usize>) -> Vec<u8>;
+    /// Read up to `len` bytes of a file, starting from `offset`.
+    ///
+    /// If `len` is `None`, the rest of the file is read.
+    /// Reading past the end of the file returns no bytes.
+    #[wire(read_only)]
+    async fn read_file(
+        path: VirtPath,
+        offset: usize,
+        len: Option<usize>,
+    ) -> Result<Vec<u8>, VirtIOErr>;
 
-    /// List all files in the current directory
-    async fn ls(path: PathBuf) -> Vec<String>;
+    /// List up to `limit` entries of a directory, after the entry at `cursor`.
+    ///
+    /// The cursor of a listing continues it in the next call. Pages are at least 1
+    /// and at most [MAX_LS_PAGE](crate::fs::MAX_LS_PAGE) entries long.
+    #[wire(read_only)]
+    async fn ls(
+        path: VirtPath,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<DirListing, VirtIOErr>;
 }
 
 /// Mutable file operations are defined in this interface.
 #[remote_interface]
 pub trait MutableFileOps {
     /// Create a new file at the new path
-    async fn create_file(path: PathBuf, truncate: bool) -> Result<(bool, i32), ()>;
+    async fn create_file(path: VirtPath, truncate: bool) -> Result<(bool, i32), ()>;
 }
 
 /// Remotely invoked primitives, platform agnostic.
@@ -36,51 +61,120 @@ pub trait MutableFileOps {
 #[remote_interface]
 pub trait PrimitiveFsOps {
     /// Read the entire file
-    async fn read_all(path: String) -> Vec<u8>;
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn read_all(path: VirtPath) -> Vec<u8>;
 
     /// Read a portion of the file
-    async fn read_bytes(path: String, offset: usize, len: usize) -> Vec<u8>;
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn read_bytes(path: VirtPath, offset: usize, len: usize) -> Vec<u8>;
+
+    /// Read up to `len` bytes of a file from `offset`, along with the size of the file.
+    ///
+    /// Chunks are at most [MAX_CHUNK_SIZE](crate::fs::MAX_CHUNK_SIZE) bytes long.
+    /// Reading past the end of the file returns no bytes.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn read_chunk(path: VirtPath, offset: usize, len: usize) -> Result<FileChunk, VirtIOErr>;
 
     /// Write a vector of bytes to a file. The file will be created if it does not exist.
     ///
     /// If the file exists, the contents of the file will be replaced by the payload.
     /// This is a convenience method and is equivalent to calling [PrimitiveFsOps::write_bytes]
     /// with [`FileWriteMode::Truncate`].
-    async fn write_all(path: String, contents: Vec<u8>) -> bool;
+    ///
+    /// The contents are sent as they are, so the remote can stream large uploads to the file.
+    #[wire(packed = false)]
+    async fn write_all(path: VirtPath, contents: ByteBuf) -> bool;
 
     /// Writes some bytes into a file path, returning the number of bytes written.
     ///
-    /// Use the `mode` parameter to specify the write mode.
-    async fn write_bytes(path: String, bytes: FileUpdate) -> Result<usize, VirtIOErr>;
+    /// `author` is the session ID of the writer, and is forwarded to watchers of the file.
+    /// Appends are not idempotent, so duplicates are always filtered.
+    #[wire(semantics = "at-most-once")]
+    async fn write_bytes(
+        path: VirtPath,
+        bytes: FileUpdate,
+        author: u64,
+    ) -> Result<usize, VirtIOErr>;
+
+    /// Apply a batch of updates to a file in order, returning the number of bytes written.
+    ///
+    /// Either all of the updates are applied or none of them are. The new contents are written
+    /// to a temporary file, which replaces the file. Watchers receive the new contents as one update.
+    #[wire(semantics = "at-most-once")]
+    async fn write_batch(
+        path: VirtPath,
+        updates: Vec<FileUpdate>,
+        author: u64,
+    ) -> Result<usize, VirtIOErr>;
+
+    /// Append a line of text to a file, creating the file if it does not exist.
+    /// Returns the number of bytes appended.
+    ///
+    /// The line is terminated with a newline, and starts on a new line
+    /// if the file does not end with one. Watchers receive the appended bytes.
+    #[wire(semantics = "at-most-once")]
+    async fn append_line(path: VirtPath, text: String, author: u64) -> Result<usize, VirtIOErr>;
 
     /// Writes some bytes into a file path, returning the number of bytes written.
     ///
     /// If the file exists, the contents will be overwritten.
-    // async fn write_truncate_bytes(path: String, bytes: Vec<u8>) -> usize;
+    // async fn write_truncate_bytes(path: VirtPath, bytes: Vec<u8>) -> usize;
 
     /// Create a file at a specified path.
     ///
     /// This will truncate any data if the file already exists.
     /// Returns the result of the operation.
-    async fn create(path: String) -> Result<(), VirtIOErr>;
+    async fn create(path: VirtPath) -> Result<(), VirtIOErr>;
 
     /// Remove a file at a specified path. Returns the result of the operation.
-    async fn remove(path: String) -> Result<(), VirtIOErr>;
+    async fn remove(path: VirtPath) -> Result<(), VirtIOErr>;
 
     /// Rename a file or directory at a specified path. Returns the result of the operation.
-    async fn rename(path: String, from: String, to: String) -> Result<(), VirtIOErr>;
+    async fn rename(path: VirtPath, from: VirtPath, to: VirtPath) -> Result<(), VirtIOErr>;
 
     /// Create a directory.
-    async fn mkdir(path: String) -> Result<(), VirtIOErr>;
+    async fn mkdir(path: VirtPath) -> Result<(), VirtIOErr>;
+
+    /// Create a symbolic link at `link_path` pointing to `target`.
+    ///
+    /// `target` is relative to the directory of the link, and must stay inside the remote's base directory.
+    #[wire(semantics = "at-most-once")]
+    async fn symlink(target: VirtPath, link_path: VirtPath) -> Result<(), VirtIOErr>;
+
+    /// Create a hard link at `dst` to the file at `src`.
+    #[wire(semantics = "at-most-once")]
+    async fn hardlink(src: VirtPath, dst: VirtPath) -> Result<(), VirtIOErr>;
 
     /// Remove a directory and all of its contents.
-    async fn rmdir(path: String) -> Result<(), VirtIOErr>;
+    async fn rmdir(path: VirtPath) -> Result<(), VirtIOErr>;
 
-    /// Read the contents of a directory
-    async fn read_dir(path: String) -> Vec<VirtDirEntry>;
+    /// Read the contents of a directory, along with its change counter.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn read_dir(path: VirtPath) -> VirtReadDir;
+
+    /// Returns the change counter of a directory.
+    ///
+    /// The counter is incremented by any mutation inside the directory.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn dir_change_counter(path: VirtPath) -> u64;
 
     /// Returns the size of the file in bytes.
-    async fn file_size(path: String) -> Result<usize, VirtIOErr>;
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn file_size(path: VirtPath) -> Result<usize, VirtIOErr>;
+
+    /// Returns the last modification time of a file, in nanoseconds since the unix epoch.
+    ///
+    /// Clients compare this with the time of their cached contents, to check if they are stale.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn last_modified(path: VirtPath) -> Result<u64, VirtIOErr>;
+
+    /// Returns basic metadata of a file or directory, or `None` if it does not exist.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn stat(path: VirtPath) -> Option<VirtMetadataLite>;
+
+    /// Returns basic metadata of many files or directories, in the same order as `paths`.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn stat_many(paths: Vec<String>) -> Vec<Option<VirtMetadataLite>>;
 }
 
 /// File write modes
@@ -97,7 +191,7 @@ pub enum FileWriteMode {
 }
 
 /// File update types
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum FileUpdate {
     /// New data that is appended to the file.
     Append(Vec<u8>),
@@ -107,6 +201,102 @@ pub enum FileUpdate {
 
     /// Data that completely replaces the file
     Overwrite(Vec<u8>),
+
+    /// Changes to the lines of a text file, as a unified diff.
+    ///
+    /// Sent in place of an [FileUpdate::Overwrite] to watchers in [WatchMode::Diff].
+    Diff(String),
+
+    /// The remote restarted and restored the watch from its saved state.
+    ///
+    /// The contents are unchanged. Versions start over from the version of the notice,
+    /// and watchers register again to keep watching.
+    Restarted,
+
+    /// Data that replaces the bytes at a specified offset, extending the file if needed.
+    ///
+    /// Offsets past the end of the file are filled with zeroes, like a sparse file.
+    WriteAt((usize, Vec<u8>)),
+}
+
+/// How updates to a watched file are sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum WatchMode {
+    /// Send updates as they were written
+    #[default]
+    Raw,
+
+    /// Send overwrites of text files as a diff against the previous contents.
+    /// Other updates are sent as they were written.
+    Diff,
+
+    /// Send the metadata of the file after each update as a [MetadataNotice],
+    /// instead of the update.
+    Metadata,
+}
+
+/// A file update, as sent to watchers of the file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileUpdateNotice {
+    /// Version of the file after the update.
+    /// The remote increments the version of a file on every write.
+    pub version: u64,
+
+    /// Session ID of the writer, if known
+    pub author: Option<u64>,
+
+    pub update: FileUpdate,
+}
+
+/// The metadata of a file after an update, as sent to watchers in [WatchMode::Metadata].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetadataNotice {
+    /// Version of the file after the update
+    pub version: u64,
+
+    /// Session ID of the writer, if known
+    pub author: Option<u64>,
+
+    /// `None` if the remote could not read the metadata, or restarted and did not update the file
+    pub metadata: Option<VirtMetadataLite>,
+}
+
+/// Updates to a file that a watcher missed, see [CallbackOps::resume_file_watch]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MissedUpdates {
+    /// The missed updates, in order. Empty if the watcher did not miss any.
+    Replay(Vec<FileUpdateNotice>),
+
+    /// The remote no longer has all the missed updates.
+    /// The file must be read again, and is at this version.
+    Refetch { version: u64 },
+}
+
+/// A change to the entries of a watched directory.
+///
+/// Paths are relative to the base directory of the remote.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DirEvent {
+    /// A file, directory or link was created
+    Created(VirtPath),
+
+    /// A file or directory was removed
+    Removed(VirtPath),
+
+    Renamed {
+        from: VirtPath,
+        to: VirtPath,
+    },
+}
+
+impl DirEvent {
+    /// Returns the paths changed by the event
+    pub fn paths(&self) -> Vec<&VirtPath> {
+        match self {
+            DirEvent::Created(path) | DirEvent::Removed(path) => vec![path],
+            DirEvent::Renamed { from, to } => vec![from, to],
+        }
+    }
 }
 
 /// Identifier for a file registered with the remote.
@@ -117,12 +307,40 @@ pub struct FileId(pub(crate) u64);
 #[remote_interface]
 pub trait SimpleOps {
     /// Pass something to the remote to log.
+    #[wire(read_only)]
     async fn say_hello(content: String) -> bool;
 
     /// Compute the Nth fibonacci number and return the result.
     ///
     /// This is supposed to simulate an expensive computation.
-    async fn compute_fib(fib_num: u8) -> u64;
+    /// [FibProgress] is sent to the progress address as the computation proceeds.
+    /// Requesting the same number again cancels the earlier computation.
+    #[wire(read_only)]
+    async fn compute_fib(fib_num: u8, progress_addr: Option<SocketAddrV4>)
+        -> Result<u64, FibError>;
+}
+
+/// Largest fibonacci number that fits in a `u64`
+pub const MAX_FIB_NUM: u8 = 93;
+
+/// Errors of [SimpleOps::compute_fib]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FibError {
+    /// The requested number is larger than [MAX_FIB_NUM]
+    OutOfRange(u8),
+
+    /// The computation was superseded by a later request
+    Cancelled,
+}
+
+/// Progress of a fibonacci computation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FibProgress {
+    pub fib_num: u8,
+
+    /// Steps completed, out of `steps`
+    pub step: u32,
+    pub steps: u32,
 }
 
 /// Methods that register a callback are defined here.
@@ -132,9 +350,210 @@ pub trait SimpleOps {
 pub trait CallbackOps {
     /// Registers a path to be watched for updates.
     ///
-    /// Upon a write update, a [FileUpdate] will be sent to the return address.
-    async fn register_file_update(path: String, return_addr: SocketAddrV4)
-        -> Result<(), VirtIOErr>;
+    /// Upon a write update, a [FileUpdateNotice] will be sent to the return address.
+    #[wire(read_only)]
+    async fn register_file_update(
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr>;
+
+    /// Registers a path to be watched for updates, sent in the given mode.
+    #[wire(read_only)]
+    async fn register_file_watch(
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+    ) -> Result<SubscriptionId, VirtIOErr>;
+
+    /// Registers a path to be watched for updates, for a watcher that has seen the file up to `version`.
+    ///
+    /// The updates made since that version are returned instead of being sent to the return address,
+    /// as they were written regardless of the mode.
+    #[wire(read_only)]
+    async fn resume_file_watch(
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+        version: u64,
+    ) -> Result<(SubscriptionId, MissedUpdates), VirtIOErr>;
+
+    /// Registers a directory to be watched for created, removed and renamed entries.
+    ///
+    /// A [DirEvent] is sent to the return address for every change, until the
+    /// registration is removed with [CallbackOps::unregister]. Changes in subdirectories
+    /// are sent as well if `recursive` is set.
+    #[wire(read_only)]
+    async fn register_dir_update(
+        path: VirtPath,
+        recursive: bool,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr>;
+
+    /// Removes a watch that has not been triggered.
+    ///
+    /// Returns false if no watch on the path sends updates to the return address.
+    #[wire(read_only)]
+    async fn unregister_file_watch(path: VirtPath, return_addr: SocketAddrV4) -> bool;
+
+    /// Removes a registration of this client.
+    ///
+    /// Returns false if it no longer exists, e.g. a watch that was triggered.
+    #[wire(read_only)]
+    async fn unregister(id: SubscriptionId) -> bool;
+
+    /// Subscribes to every update of a file.
+    ///
+    /// Unlike a watch, which is removed once triggered, a [FileUpdateNotice] is sent to the
+    /// return address for every update until [CallbackOps::unsubscribe] is called. A client can
+    /// hold any number of subscriptions, each with its own return address.
+    ///
+    /// Subscriptions expire after [SUBSCRIPTION_TIMEOUT]. Subscribing to the same file at
+    /// the same return address renews the subscription, and returns the same ID.
+    #[wire(read_only)]
+    async fn subscribe(
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr>;
+
+    /// Removes a subscription of this client.
+    ///
+    /// Returns false if it no longer exists, e.g. a subscription that expired.
+    #[wire(read_only)]
+    async fn unsubscribe(id: SubscriptionId) -> bool;
+}
+
+/// Subscriptions that are not renewed within this time are removed
+pub const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Identifies a callback registration on the remote
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SubscriptionId(pub u64);
+
+impl std::fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Subscriptions to server-initiated notifications.
+///
+/// Messages published to a topic are sent to each subscriber as a
+/// `MiddlewareData::Callback` containing a [TopicMessage].
+#[remote_interface]
+pub trait TopicOps {
+    /// Subscribe the return address to a topic.
+    ///
+    /// Returns false if the address is already subscribed.
+    #[wire(read_only)]
+    async fn subscribe(topic: String, return_addr: SocketAddrV4) -> bool;
+
+    /// Unsubscribe the return address from a topic.
+    ///
+    /// Returns false if the address was not subscribed.
+    #[wire(read_only)]
+    async fn unsubscribe(topic: String, return_addr: SocketAddrV4) -> bool;
+}
+
+/// A message published to a topic
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TopicMessage {
+    pub topic: String,
+
+    pub message: String,
+}
+
+/// Clients announce the files they have open, so others know who is viewing or editing them.
+///
+/// Registrations expire unless they are refreshed by registering again.
+#[remote_interface]
+pub trait PresenceOps {
+    /// Register the file as opened by this client, in the given mode.
+    #[wire(read_only)]
+    async fn register_open(path: VirtPath, mode: OpenMode) -> Result<(), VirtIOErr>;
+
+    /// Remove this client's registration of the file.
+    ///
+    /// Returns false if the file was not registered.
+    #[wire(read_only)]
+    async fn register_close(path: VirtPath) -> bool;
+
+    /// Returns the other clients that have the file open.
+    #[wire(read_only)]
+    async fn list_watchers(path: VirtPath) -> Vec<Watcher>;
+}
+
+/// What a client is doing with an open file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenMode {
+    Viewing,
+    Editing,
+}
+
+/// A client that has a file open
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Watcher {
+    pub client: ClientId,
+    pub mode: OpenMode,
+}
+
+/// Advisory locks on byte ranges of files, so clients can edit disjoint regions of the same file.
+///
+/// Locks do not prevent reads or writes. Clients are expected to lock a region before editing it.
+#[remote_interface]
+pub trait LockOps {
+    /// Lock `len` bytes of the file from `offset` for this client.
+    ///
+    /// Fails with [VirtIOErr::WouldBlock] if the range overlaps a conflicting lock of another client.
+    /// Locking a range this client already holds changes the mode of the lock.
+    async fn lock_range(
+        path: VirtPath,
+        offset: usize,
+        len: usize,
+        mode: LockMode,
+    ) -> Result<(), VirtIOErr>;
+
+    /// Release this client's lock on exactly this range.
+    ///
+    /// Returns false if the client does not hold the lock.
+    #[wire(read_only)]
+    async fn unlock_range(path: VirtPath, offset: usize, len: usize) -> bool;
+
+    /// Returns the locks held on the file, ordered by offset.
+    #[wire(read_only)]
+    async fn list_range_locks(path: VirtPath) -> Vec<RangeLock>;
+}
+
+/// How a byte range is locked
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockMode {
+    /// Other clients may also hold shared locks on the range
+    Shared,
+
+    /// No other client may lock the range
+    Exclusive,
+}
+
+/// A lock held by a client on a byte range of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeLock {
+    pub client: ClientId,
+    pub offset: usize,
+    pub len: usize,
+    pub mode: LockMode,
+}
+
+impl RangeLock {
+    /// Returns `true` if the lock covers any of the `len` bytes from `offset`.
+    pub fn overlaps(&self, offset: usize, len: usize) -> bool {
+        self.offset < offset.saturating_add(len) && offset < self.offset.saturating_add(self.len)
+    }
+
+    /// Returns `true` if the locks cannot be held at the same time.
+    pub fn conflicts_with(&self, other: &RangeLock) -> bool {
+        self.client != other.client
+            && self.overlaps(other.offset, other.len)
+            && (self.mode == LockMode::Exclusive || other.mode == LockMode::Exclusive)
+    }
 }
 
 /// These methods are used for testing invocation semantics (various transmission protocols).
@@ -143,6 +562,7 @@ pub trait CallbackOps {
 #[remote_interface]
 pub trait TestOps {
     /// Get the stringified name of the protocol used by the remote.
+    #[wire(read_only)]
     async fn get_remote_protocol() -> String;
 
     /// Simulate an idempotent operation.
@@ -160,6 +580,102 @@ pub trait TestOps {
     async fn reset_non_idempotent() -> ();
 }
 
+/// A counter shared by all clients.
+///
+/// Incrementing is not idempotent, so a duplicated request increments the counter again.
+/// Compare the counter against the number of increments made to see which invocation
+/// semantics execute a request more than once.
+#[remote_interface]
+pub trait CounterOps {
+    /// Add to the counter and return the new value.
+    async fn increment(by: u64) -> u64;
+
+    /// Returns the value of the counter.
+    #[wire(read_only)]
+    async fn get() -> u64;
+}
+
+/// Server administration and monitoring.
+///
+/// These methods are used by operators to inspect a running server.
+#[remote_interface]
+pub trait AdminOps {
+    /// Returns a snapshot of the server's status.
+    #[wire(read_only)]
+    async fn server_status() -> ServerStatus;
+
+    /// Publish a message to the subscribers of a topic.
+    ///
+    /// Returns the number of subscribers the message was sent to.
+    async fn publish(topic: String, message: String) -> usize;
+}
+
+/// Information about the host that the remote runs on.
+#[remote_interface]
+pub trait SystemOps {
+    /// Returns the total and free space in bytes of the disk that holds the served directory.
+    #[wire(semantics = "at-least-once", read_only)]
+    async fn disk_usage() -> Result<(u64, u64), VirtIOErr>;
+}
+
+/// A snapshot of a server's status, returned by [AdminOps::server_status].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ServerStatus {
+    /// Time since the server started, in seconds
+    pub uptime_secs: u64,
+
+    /// Server clock, in milliseconds since the unix epoch
+    pub server_time_ms: u64,
+
+    /// Total number of requests received
+    pub total_requests: u64,
+
+    /// Number of duplicate requests answered without reprocessing
+    pub duplicate_requests: u64,
+
+    /// Average number of requests per second, over a short window
+    pub request_rate: f64,
+
+    /// Number of times the p95 latency of a class of methods went above its target
+    pub slo_violations: u64,
+
+    /// Number of incoming transfers abandoned by clients and reaped
+    pub reaped_transfers: u64,
+
+    /// Number of responses sent compressed
+    pub compressed_responses: u64,
+
+    /// Size of compressed responses relative to their original size
+    pub compression_ratio: Option<f64>,
+
+    /// Clients that have made a request recently
+    pub sessions: Vec<SessionStatus>,
+
+    /// Watched paths and the number of callbacks registered for each
+    pub watches: Vec<(String, usize)>,
+
+    /// Number of files in the read cache
+    pub cache_entries: usize,
+
+    /// Total size of the read cache in bytes
+    pub cache_bytes: usize,
+}
+
+/// A client session, as seen by the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub client: ClientId,
+
+    /// Address of the last request from the client
+    pub addr: SocketAddrV4,
+
+    /// Number of requests made by the client
+    pub requests: u64,
+
+    /// Time since the last request, in milliseconds
+    pub idle_ms: u64,
+}
+
 /// Data streaming operations.
 ///
 /// These methods should not be invoked directly!
@@ -169,13 +685,43 @@ pub trait StreamingOps {
     /// Signal to the remote to open a blob transmitter and return the network address.
     ///
     /// The path to the file is expected to be valid.
-    async fn open_blob_file_tx(path: String) -> SocketAddrV4;
+    #[wire(read_only)]
+    async fn open_blob_file_tx(path: VirtPath) -> SocketAddrV4;
 
     /// Signal to the remote to open a blob receiver and return the network address.
     ///
     /// The path to the file may or may not be valid.
     /// File contents can be overridden or appended by setting `overwrite` to `true` or `false`.
-    async fn open_blob_file_rx(path: String, overwrite: bool) -> SocketAddrV4;
+    async fn open_blob_file_rx(path: VirtPath, overwrite: bool) -> SocketAddrV4;
+}
+
+/// Hash of the definitions of every interface in this module
+pub const INTERFACE_HASH: u64 = combine_hashes(&[
+    ImmutableFileOpsClient::INTERFACE_HASH,
+    MutableFileOpsClient::INTERFACE_HASH,
+    PrimitiveFsOpsClient::INTERFACE_HASH,
+    SimpleOpsClient::INTERFACE_HASH,
+    CallbackOpsClient::INTERFACE_HASH,
+    TopicOpsClient::INTERFACE_HASH,
+    PresenceOpsClient::INTERFACE_HASH,
+    LockOpsClient::INTERFACE_HASH,
+    TestOpsClient::INTERFACE_HASH,
+    CounterOpsClient::INTERFACE_HASH,
+    AdminOpsClient::INTERFACE_HASH,
+    SystemOpsClient::INTERFACE_HASH,
+    StreamingOpsClient::INTERFACE_HASH,
+]);
+
+/// Combine hashes in order, with the same FNV-1a steps the interface hashes are derived with
+const fn combine_hashes(hashes: &[u64]) -> u64 {
+    let mut combined: u64 = 0xcbf29ce484222325;
+    let mut idx = 0;
+    while idx < hashes.len() {
+        combined = (combined ^ hashes[idx]).wrapping_mul(0x100000001b3);
+        idx += 1;
+    }
+
+    combined
 }
 
 impl FileUpdate {
@@ -194,14 +740,58 @@ impl FileUpdate {
                 }
             },
             FileUpdate::Overwrite(data) => data.to_owned(),
+            FileUpdate::Diff(diff) => {
+                let patched = std::str::from_utf8(prev)
+                    .ok()
+                    .and_then(|prev| crate::fs::apply_diff(prev, &diff));
+
+                match patched {
+                    Some(contents) => contents.into_bytes(),
+                    None => {
+                        log::error!("diff does not apply to the previous contents");
+                        prev.to_vec()
+                    }
+                }
+            }
+            FileUpdate::Restarted => prev.to_vec(),
+            FileUpdate::WriteAt((offset, data)) => {
+                let mut contents = prev.to_vec();
+                let end = offset + data.len();
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[offset..end].copy_from_slice(&data);
+
+                contents
+            }
         }
     }
 
+    /// Append `text` as a line after the previous file contents.
+    ///
+    /// A newline is added before the line if the contents do not end with one,
+    /// and after it if the text does not.
+    pub fn append_line(prev: &[u8], text: &str) -> Self {
+        let mut data = Vec::with_capacity(text.len() + 2);
+        if !prev.is_empty() && !prev.ends_with(b"\n") {
+            data.push(b'\n');
+        }
+        data.extend_from_slice(text.as_bytes());
+        if !text.ends_with('\n') {
+            data.push(b'\n');
+        }
+
+        FileUpdate::Append(data)
+    }
+
     pub fn len(&self) -> usize {
         match self {
             FileUpdate::Append(data) => data.len(),
             FileUpdate::Insert((_, data)) => data.len(),
             FileUpdate::Overwrite(data) => data.len(),
+            FileUpdate::Diff(diff) => diff.len(),
+            FileUpdate::Restarted => 0,
+            FileUpdate::WriteAt((_, data)) => data.len(),
         }
     }
 }
@@ -212,33 +802,26 @@ mod tests {
 
     /// Check for signature collisions between every method defined
     /// in a particular trait.
-    ///
-    /// Takes advantage of the fact that a vector slices is lexicographically sorted
     macro_rules! check_signature_collision {
         ($($sig: ty),*,) => {
-            let mut vec = Vec::new();
-
-            $(
-                vec.push(<$sig>::remote_method_signature());
-            )*
-
-            vec.sort();
+            let vec = vec![$(<$sig>::remote_method_signature()),*];
 
-            // let words = vec.iter().map(|bytes| std::str::from_utf8(bytes).unwrap()).collect::<Vec<_>>();
-            // println!("{:#?}", words);
-
-            for i in 0..vec.len() - 1 {
-                if vec[i].starts_with(&vec[i + 1]) {
-                    panic!(
-                        "signature prefix collision: {} and {}",
-                        std::str::from_utf8(vec[i]).unwrap(),
-                        std::str::from_utf8(vec[i + 1]).unwrap()
-                    );
-                }
+            if let Err(e) = crate::middleware::check_signatures(&vec) {
+                panic!("{}", e);
             }
         };
     }
 
+    /// Signature test for [PresenceOps]
+    #[test]
+    fn test_method_signature_collision_presence_ops() {
+        check_signature_collision! {
+            PresenceOpsRegisterOpen,
+            PresenceOpsRegisterClose,
+            PresenceOpsListWatchers,
+        }
+    }
+
     /// Signature test for [PrimitiveFsOps]
     #[test]
     fn test_method_signature_collision_primitive_fs_ops() {
@@ -251,7 +834,11 @@ mod tests {
             PrimitiveFsOpsRename,
             PrimitiveFsOpsMkdir,
             PrimitiveFsOpsRmdir,
+            PrimitiveFsOpsSymlink,
+            PrimitiveFsOpsHardlink,
             PrimitiveFsOpsReadDir,
+            PrimitiveFsOpsStat,
+            PrimitiveFsOpsStatMany,
         }
     }
 
@@ -264,13 +851,317 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_method_signature_collision_counter_ops() {
+        check_signature_collision! {
+            CounterOpsIncrement,
+            CounterOpsGet,
+        }
+    }
+
     #[test]
     fn test_method_signature_collision_callback_ops() {
-        check_signature_collision! {CallbackOpsRegisterFileUpdate,}
+        check_signature_collision! {
+            CallbackOpsRegisterFileUpdate,
+            CallbackOpsRegisterFileWatch,
+            CallbackOpsSubscribe,
+            CallbackOpsUnsubscribe,
+            TopicOpsSubscribe,
+            TopicOpsUnsubscribe,
+        }
+    }
+
+    #[remote_interface]
+    #[allow(dead_code)]
+    pub trait WireOps {
+        async fn packed(data: Vec<u8>) -> usize;
+
+        #[wire(packed = false)]
+        async fn plain(data: Vec<u8>) -> usize;
+    }
+
+    /// The wire format is carried after the signature, and payloads decode in either format.
+    #[test]
+    fn test_wire_format() {
+        use rfs_core::{RemotelyInvocable, WireFormat};
+
+        let data = (0..=255).collect::<Vec<u8>>();
+
+        let packed = WireOpsPacked::Request { data: data.clone() }.invoke_bytes();
+        let sig_len = WireOpsPacked::remote_method_signature().len();
+        assert_eq!(packed[sig_len], WireFormat::Packed as u8);
+
+        let plain = WireOpsPlain::Request { data: data.clone() }.invoke_bytes();
+        let sig_len = WireOpsPlain::remote_method_signature().len();
+        assert_eq!(plain[sig_len], WireFormat::Plain as u8);
+        let body =
+            rfs_core::ser_de::serialize(&WireOpsPlain::Request { data: data.clone() }).unwrap();
+        assert_eq!(
+            plain[sig_len + 1..sig_len + 9],
+            (body.len() as u64).to_be_bytes()
+        );
+        assert_eq!(plain[sig_len + 9..], body);
+
+        match WireOpsPlain::process_invocation(&plain).unwrap() {
+            WireOpsPlain::Request { data: d } => assert_eq!(d, data),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+        match WireOpsPacked::process_invocation(&packed).unwrap() {
+            WireOpsPacked::Request { data: d } => assert_eq!(d, data),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    /// Payloads that are cut short or carry extra bytes are rejected.
+    #[test]
+    fn test_envelope_framing() {
+        use rfs_core::{middleware::InvokeError, RemotelyInvocable};
+
+        let request = WireOpsPlain::Request {
+            data: vec![1, 2, 3],
+        };
+        let bytes = request.invoke_bytes();
+        let sig_len = WireOpsPlain::remote_method_signature().len();
+        let rejected = |bytes: &[u8]| {
+            matches!(
+                WireOpsPlain::process_invocation(bytes),
+                Err(InvokeError::DeserializationFailed)
+            )
+        };
+
+        assert!(WireOpsPlain::process_invocation(&bytes).is_ok());
+        assert!(rejected(&bytes[..bytes.len() - 1]));
+        assert!(rejected(&bytes[..sig_len + 7]));
+        assert!(rejected(&[bytes.as_slice(), &[0]].concat()));
+
+        // a length that covers extra bytes does not hide them from the body
+        let mut padded = [bytes.as_slice(), &[0]].concat();
+        let len = (padded.len() - sig_len - 9) as u64;
+        padded[sig_len + 1..sig_len + 9].copy_from_slice(&len.to_be_bytes());
+        assert!(rejected(&padded));
+
+        let packed = WireOpsPacked::Request { data: vec![0; 64] }.invoke_bytes();
+        assert!(WireOpsPacked::process_invocation(&packed).is_ok());
+        assert!(WireOpsPacked::process_invocation(&packed[..packed.len() - 1]).is_err());
+        assert!(WireOpsPacked::process_invocation(&[packed.as_slice(), &[7]].concat()).is_err());
+    }
+
+    /// Only methods marked as read-only can be invoked with read-only tokens.
+    #[test]
+    fn test_method_access() {
+        use rfs_core::middleware::MethodAccess;
+
+        assert_eq!(PrimitiveFsOpsReadAll::access(), MethodAccess::Read);
+        assert_eq!(ImmutableFileOpsLs::access(), MethodAccess::Read);
+        assert_eq!(PrimitiveFsOpsWriteAll::access(), MethodAccess::Write);
+        assert_eq!(AdminOpsPublish::access(), MethodAccess::Write);
+    }
+
+    #[test]
+    fn test_application_error_round_trip() {
+        use rfs_core::{middleware::InvokeError, RemoteResponse};
+
+        let err = PrimitiveFsOpsCreate::Response(Err(VirtIOErr::AlreadyExists))
+            .application_error()
+            .unwrap();
+        assert!(PrimitiveFsOpsCreate::Response(Ok(()))
+            .application_error()
+            .is_none());
+
+        match PrimitiveFsOpsCreate::from_application_error(err.clone()).unwrap() {
+            PrimitiveFsOpsCreate::Response(Err(VirtIOErr::AlreadyExists)) => (),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        // errors of other methods are passed through
+        assert!(matches!(
+            PrimitiveFsOpsRemove::from_application_error(err.clone()),
+            Err(InvokeError::RemoteApplication(bytes)) if bytes == err
+        ));
+        assert!(PrimitiveFsOpsReadAll::from_application_error(err).is_err());
+    }
+
+    #[test]
+    fn test_method_signature_collision_admin_ops() {
+        check_signature_collision! {AdminOpsServerStatus, AdminOpsPublish,}
+    }
+
+    #[test]
+    fn test_method_signature_collision_topic_ops() {
+        check_signature_collision! {TopicOpsSubscribe, TopicOpsUnsubscribe,}
     }
 
     #[test]
     fn test_method_signature_collision_streaming_ops() {
         check_signature_collision! {StreamingOpsOpenBlobFileRx, StreamingOpsOpenBlobFileTx,}
     }
+
+    use rfs_core::{middleware::InvokeError, RemoteCall, RemotelyInvocable};
+
+    #[remote_interface(client_name = "DoublerClient", message_prefix = "Doubler")]
+    trait RenamedOps {
+        async fn double(value: u64) -> u64;
+    }
+
+    struct Doubler;
+
+    #[async_trait::async_trait]
+    impl RenamedOps for Doubler {
+        async fn double(&mut self, value: u64) -> u64 {
+            value * 2
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl rfs_core::middleware::Invoker for Doubler {
+        async fn invoke_raw(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+            let req = DoublerDouble::process_invocation(&payload)?;
+
+            Ok(req.call(self).await.invoke_bytes())
+        }
+
+        async fn reconnect(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_interface_hash() {
+        // interfaces with different definitions hash differently
+        assert_ne!(DoublerClient::INTERFACE_HASH, 0);
+        assert_ne!(
+            DoublerClient::INTERFACE_HASH,
+            CounterOpsClient::INTERFACE_HASH
+        );
+        assert_ne!(
+            evolving_v1::EvolvingOpsClient::INTERFACE_HASH,
+            evolving_v2::EvolvingOpsClient::INTERFACE_HASH
+        );
+
+        assert_ne!(INTERFACE_HASH, combine_hashes(&[]));
+    }
+
+    /// Renamed clients and messages keep the signature of the trait method.
+    #[tokio::test]
+    async fn test_renamed_interface() {
+        assert_eq!(
+            DoublerDouble::remote_method_signature(),
+            b"RenamedOps::double"
+        );
+        assert_eq!(DoublerClient::double(&mut Doubler, 21).await.unwrap(), 42);
+    }
+
+    /// Generated items placed in their own module, with only the client re-exported
+    mod scoped {
+        use super::*;
+
+        #[remote_interface(module = "scoped_generated", vis = "pub(super)", reexport = "client")]
+        pub trait ScopedOps {
+            async fn stat(path: VirtPath) -> Option<usize>;
+        }
+    }
+
+    /// Generated items in a module see the types of the trait's module, and only the client
+    /// is re-exported next to the trait.
+    #[test]
+    fn test_generated_module() {
+        use scoped::{scoped_generated::ScopedOpsStat, ScopedOpsClient};
+
+        assert_eq!(ScopedOpsStat::remote_method_signature(), b"ScopedOps::stat");
+        assert_ne!(ScopedOpsClient::INTERFACE_HASH, 0);
+
+        let req = ScopedOpsStat::Request {
+            path: VirtPath::from("file.txt"),
+        };
+        match ScopedOpsStat::process_invocation(&req.invoke_bytes()).unwrap() {
+            ScopedOpsStat::Request { path } => assert_eq!(path.as_str(), "file.txt"),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    /// Request payloads call the method on an implementor and return the response payload.
+    #[tokio::test]
+    async fn test_remote_call() {
+        let req = DoublerDouble::Request { value: 4 };
+        match req.call(&mut Doubler).await {
+            DoublerDouble::Response(res) => assert_eq!(res, 8),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        // implementors behind a trait object
+        let server: &mut (dyn RenamedOps + Send) = &mut Doubler;
+        let req = DoublerDouble::Request { value: 5 };
+        match req.call(server).await {
+            DoublerDouble::Response(res) => assert_eq!(res, 10),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    /// The interface before a parameter was added
+    mod evolving_v1 {
+        use super::*;
+
+        #[remote_interface]
+        #[allow(dead_code)]
+        pub trait EvolvingOps {
+            async fn list(path: String) -> Vec<String>;
+        }
+    }
+
+    /// The interface after a parameter was added
+    mod evolving_v2 {
+        use super::*;
+
+        #[remote_interface]
+        #[allow(dead_code)]
+        pub trait EvolvingOps {
+            async fn list(
+                path: String,
+                #[wire(default)] recursive: bool,
+                #[wire(default)] limit: Option<u32>,
+            ) -> Vec<String>;
+        }
+    }
+
+    /// Old and new payloads of a method with trailing default parameters decode on either version.
+    #[test]
+    fn test_optional_trailing_params() {
+        use evolving_v1::EvolvingOpsList as V1;
+        use evolving_v2::EvolvingOpsList as V2;
+
+        let old = V1::Request {
+            path: "dir".to_string(),
+        }
+        .invoke_bytes();
+        match V2::process_invocation(&old).unwrap() {
+            V2::Request {
+                path,
+                recursive,
+                limit,
+            } => assert_eq!((path.as_str(), recursive, limit), ("dir", false, None)),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        let new = V2::Request {
+            path: "dir".to_string(),
+            recursive: true,
+            limit: Some(10),
+        }
+        .invoke_bytes();
+        match V1::process_invocation(&new).unwrap() {
+            V1::Request { path } => assert_eq!(path, "dir"),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_write_at() {
+        let write = |offset, data: &[u8], prev: &[u8]| {
+            FileUpdate::WriteAt((offset, data.to_vec())).update_file(prev)
+        };
+
+        assert_eq!(write(1, b"EL", b"hello"), b"hELlo");
+        assert_eq!(write(3, b"p me", b"hello"), b"help me");
+        assert_eq!(write(4, b"!", b"hi"), b"hi\0\0!");
+    }
 }
@@ -1,11 +1,15 @@
 //! Remote methods, data structures between server and client are defined here.
 
+pub mod doctor;
+pub mod fib;
 pub mod fs;
 pub mod interfaces;
+pub mod topics;
 
 pub use rfs_core::{
-    fsm, middleware, payload_handler, ser_de, state_transitions, RemoteMethodSignature,
-    RemoteRequest, RemotelyInvocable,
+    envelope_header, fsm, matches_signature, middleware, path_policy, payload_handler, ser_de,
+    state_transitions, RemoteCall, RemoteMethodSignature, RemoteRequest, RemoteResponse,
+    RemotelyInvocable, WireFormat, ENVELOPE_LEN,
 };
 
 /// Default constants used between a client and the remote.
@@ -41,7 +45,8 @@ mod tests {
 
         let message = ImmutableFileOpsReadFile::Request {
             path: Default::default(),
-            offset: None,
+            offset: 0,
+            len: None,
         };
 
         let ser = message.invoke_bytes();
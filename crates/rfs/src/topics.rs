@@ -0,0 +1,104 @@
+//! Server-initiated notifications.
+//!
+//! Clients subscribe to named topics with [subscribe]. The server sends messages published
+//! to a topic to every subscriber, e.g. to announce that it is shutting down.
+
+use std::io;
+
+use rfs_core::middleware::{sockaddr_to_v4, ContextManager, MiddlewareData};
+use tokio::sync::mpsc;
+
+use crate::interfaces::{TopicMessage, TopicOpsClient};
+
+/// Topic the server publishes announcements to
+pub const SERVER_TOPIC: &str = "server";
+
+/// Subscribe to a topic.
+///
+/// Messages are received on the returned channel. The subscription is
+/// cancelled once the receiver is dropped.
+pub async fn subscribe(
+    mut ctx: ContextManager,
+    topic: &str,
+) -> io::Result<mpsc::Receiver<TopicMessage>> {
+    // this is the return socket the remote will publish messages to
+    let ret_sock = ctx.generate_socket().await?;
+    let ret_addr = sockaddr_to_v4(ret_sock.local_addr()?)?;
+
+    TopicOpsClient::subscribe(&mut ctx, topic.to_string(), ret_addr).await?;
+
+    let (tx, rx) = mpsc::channel(8);
+    let topic = topic.to_string();
+
+    tokio::spawn(async move {
+        loop {
+            let listen_res = tokio::select! {
+                res = ctx.listen(&ret_sock) => res,
+                _ = tx.closed() => break,
+            };
+
+            let bytes = match listen_res {
+                Ok(b) => b,
+                // nothing was published
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    log::error!("subscription to {} failed: {}", topic, e);
+                    return;
+                }
+            };
+
+            let message = match decode_message(&bytes) {
+                Some(m) => m,
+                None => {
+                    log::error!("invalid message published to {}", topic);
+                    continue;
+                }
+            };
+
+            if tx.send(message).await.is_err() {
+                break;
+            }
+        }
+
+        log::debug!("unsubscribing from {}", topic);
+        if let Err(e) = TopicOpsClient::unsubscribe(&mut ctx, topic, ret_addr).await {
+            log::error!("failed to unsubscribe: {}", e);
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Serialize a message for publishing
+pub fn encode_message(message: &TopicMessage) -> Vec<u8> {
+    let payload = rfs_core::serialize(message).expect("serialization must not fail");
+
+    rfs_core::serialize(&MiddlewareData::Callback(payload)).expect("serialization must not fail")
+}
+
+/// Deserialize a published message
+pub fn decode_message(bytes: &[u8]) -> Option<TopicMessage> {
+    match rfs_core::deserialize(bytes).ok()? {
+        MiddlewareData::Callback(payload) => rfs_core::deserialize(&payload).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = TopicMessage {
+            topic: SERVER_TOPIC.to_string(),
+            message: "shutting down in 30s".to_string(),
+        };
+
+        assert_eq!(decode_message(&encode_message(&message)), Some(message));
+        assert_eq!(
+            decode_message(&rfs_core::serialize(&MiddlewareData::Ping).unwrap()),
+            None
+        );
+    }
+}
@@ -1,15 +1,18 @@
 //! Command-line args for client
 
-use std::{fmt::Display, net::Ipv4Addr};
+use std::{fmt::Display, net::Ipv4Addr, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use rfs::middleware::{AccessToken, FailureRate, RequestTimeout, Retries};
+use serde::Deserialize;
 
 #[derive(Parser)]
 pub struct ClientArgs {
-    /// The IPv4 address of the client.
+    /// The IPv4 address of the client, which selects the interface requests are sent from.
+    ///
+    /// Defaults to the address of the interface that routes to the server.
     #[clap(short, long)]
-    #[clap(default_value_t = Ipv4Addr::LOCALHOST)]
-    pub listen_address: Ipv4Addr,
+    pub listen_address: Option<Ipv4Addr>,
 
     /// The IPv4 address of the server.
     #[clap(short, long)]
@@ -21,15 +24,28 @@ pub struct ClientArgs {
     #[clap(default_value_t = rfs::defaults::DEFAULT_PORT)]
     pub port: u16,
 
+    /// Access token issued by the server, for servers that require one.
+    #[clap(long)]
+    pub token: Option<AccessToken>,
+
+    /// Ask the server to compress large responses, for slow links.
+    #[clap(long)]
+    pub compressed: bool,
+
+    /// Derive each request timeout from the measured round-trip time to the server.
+    /// The request timeout is the upper bound.
+    #[clap(long)]
+    pub adaptive_timeout: bool,
+
     /// The timeout duration
     #[clap(short, long)]
     #[clap(default_value = rfs::defaults::DEFAULT_TIMEOUT)]
-    pub request_timeout: humantime::Duration,
+    pub request_timeout: RequestTimeout,
 
     /// The number of retries before returning an error
     #[clap(short, long)]
-    #[clap(default_value_t = rfs::defaults::DEFAULT_RETRIES)]
-    pub num_retries: u8,
+    #[clap(default_value_t = Retries(rfs::defaults::DEFAULT_RETRIES))]
+    pub num_retries: Retries,
 
     /// Invocation semantics (transmission protocol) to use
     #[clap(long)]
@@ -40,9 +56,23 @@ pub struct ClientArgs {
     ///
     /// The client will simulate a transmission failure every 1 in N attempts.
     #[clap(long, value_name = "N")]
-    pub simulate_ommisions: Option<u32>,
+    pub simulate_ommisions: Option<FailureRate>,
+
+    /// Simulate lost responses, independent of the invocation semantics.
+    ///
+    /// The response of every 1 in N requests is discarded after the server has executed it.
+    #[clap(long, value_name = "N")]
+    pub drop_responses: Option<FailureRate>,
+
+    /// Send every request N more times, to observe how the invocation semantics handle duplicates.
+    #[clap(long, value_name = "N")]
+    #[clap(default_value_t = 0)]
+    pub duplicate_requests: u8,
 
-    /// Client local cache lifetime
+    /// Time that file contents are read from the local cache for,
+    /// before they are checked against the server, e.g. `30s`.
+    ///
+    /// `0s` checks every read against the server.
     #[clap(long)]
     #[clap(default_value = "1m")]
     pub freshness_interval: humantime::Duration,
@@ -56,12 +86,95 @@ pub struct ClientArgs {
     #[clap(long)]
     pub test: bool,
 
+    /// TOML file with the test runs to make, in place of a single run configured by the other args.
+    #[clap(long, value_name = "PATH", requires = "test")]
+    pub scenario: Option<PathBuf>,
+
+    /// Write a summary report of the test results, in addition to the CSV file.
+    #[clap(long, value_name = "FORMAT", requires = "test")]
+    pub report: Option<ReportFormat>,
+
+    /// Include latency histograms in the report.
+    #[clap(long, requires = "report")]
+    pub report_histograms: bool,
+
     /// Send logs to a log file.
     #[clap(long)]
     pub log_to_file: bool,
+
+    /// File logs are sent to with `--log-to-file`.
+    /// Defaults to a new file for each session.
+    #[clap(long, value_name = "PATH", requires = "log_to_file")]
+    pub log_file: Option<PathBuf>,
+
+    /// Number of log lines kept in the logs window.
+    #[clap(long)]
+    #[clap(default_value_t = 100)]
+    pub max_log_lines: usize,
+
+    /// How to resolve remote updates that overlap with unsaved edits
+    #[clap(long)]
+    #[clap(default_value_t = ConflictResolution::Prompt)]
+    pub conflict_resolution: ConflictResolution,
+
+    /// File the session (open directories, file and cursor) is saved to on exit,
+    /// and restored from on start.
+    #[clap(long)]
+    #[clap(default_value = "rfs_client.session")]
+    pub session_file: PathBuf,
+
+    /// Do not save or restore the session.
+    #[clap(long)]
+    pub no_session: bool,
+
+    /// TOML file with styles and icons for filesystem entries.
+    #[clap(long, value_name = "PATH")]
+    pub theme: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: Option<ClientCommand>,
 }
 
-#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+#[derive(Clone, Debug, Subcommand)]
+pub enum ClientCommand {
+    /// Check the environment and the connection to the server, and exit.
+    Doctor,
+
+    /// Copy a remote file or directory to a local path, and exit.
+    Get {
+        /// Copy a directory and all of its subdirectories.
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// Number of files copied at the same time.
+        #[clap(short = 'j', long, default_value_t = 4)]
+        parallel: usize,
+
+        /// Number of times a file is copied again after it fails.
+        #[clap(long, default_value_t = 2)]
+        file_retries: u8,
+
+        /// Number of chunks of each file read at the same time, over separate sockets.
+        #[clap(long, default_value_t = 1)]
+        streams: usize,
+
+        /// Remote path to copy.
+        remote: String,
+
+        /// Local path to copy to.
+        local: PathBuf,
+    },
+
+    /// Mount the remote as a local directory, until it is unmounted.
+    #[cfg(feature = "fuse")]
+    Mount {
+        /// Empty local directory to mount at.
+        mountpoint: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum InvocationSemantics {
     /// A request is sent only once, and the receipt is not guaranteed.
     Maybe,
@@ -71,6 +184,47 @@ pub enum InvocationSemantics {
 
     /// Duplicate requests will be processed at most once.
     AtMostOnce,
+
+    /// Small requests are sent until they are acknowledged, and large ones with handshakes.
+    /// Duplicate requests will be processed at most once.
+    Adaptive,
+
+    /// Requests are sent over TCP connections, which deliver them reliably.
+    /// Duplicate requests will be processed at most once.
+    Tcp,
+}
+
+/// Resolution for a remote update that conflicts with unsaved edits.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ConflictResolution {
+    /// Ask the user every time a conflict occurs.
+    Prompt,
+
+    /// Keep the unsaved edits. The remote update is overwritten on the next save.
+    KeepMine,
+
+    /// Discard the unsaved edits.
+    TakeRemote,
+
+    /// Keep both the remote update and the unsaved edits.
+    Merge,
+}
+
+/// Format of the test report
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    /// File extension of reports in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Html => "html",
+        }
+    }
 }
 
 impl Display for InvocationSemantics {
@@ -79,6 +233,12 @@ impl Display for InvocationSemantics {
     }
 }
 
+impl Display for ConflictResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", camel_to_snake_case(&format!("{:?}", self)))
+    }
+}
+
 pub fn camel_to_snake_case(s: &str) -> String {
     let mut result = String::new();
     for (i, c) in s.chars().enumerate() {
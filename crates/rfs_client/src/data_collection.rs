@@ -1,25 +1,33 @@
 //! Data collection module. Tests a particular protocol and the success rate
 
+mod report;
+mod scenario;
+
 use std::{
     hash::{DefaultHasher, Hash, Hasher},
     io,
     net::{Ipv4Addr, SocketAddrV4},
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use rfs::{
-    interfaces::TestOpsClient,
+    interfaces::{CounterOpsClient, TestOpsClient},
     middleware::{
-        ContextManager, DefaultProto, FaultyDefaultProto, FaultyHandshakeProto,
-        FaultyRequestAckProto, HandshakeProto, RequestAckProto, TransmissionProtocol,
+        observe_retries, ContextManager, FailureRate, ProtocolOptions, ProtocolRegistry,
+        RetryEvent, RetryObserver, RetryReason, TransmissionProtocol,
     },
 };
 use serde::Serialize;
 
-use crate::args::InvocationSemantics;
+use crate::args::{InvocationSemantics, ReportFormat};
 
-/// Number of test iterations to perform
+pub use scenario::{PayloadKind, Run, Scenario};
+
+/// Number of test iterations to perform, unless a run sets its own
 const TEST_ITERATIONS: usize = 10;
 
 // /// Number of method calls to perform every test iteration
@@ -41,7 +49,7 @@ const TERMINATION_FAILURE_THRESHOLD: f64 = 0.001;
 const MAX_METHOD_CALLS: usize = 10_000;
 
 #[derive(Debug, Default, Serialize)]
-struct TestResult {
+pub struct TestResult {
     // protocol names
     client_protocol: String,
     remote_protocol: String,
@@ -62,37 +70,125 @@ struct TestResult {
 
     non_idempotent_calls: usize,
     non_idempotent_mismatches: usize,
+
+    // successful increments of the shared counter,
+    // and the difference between how much the counter changed and the increments
+    counter_increments: usize,
+    counter_excess: i64,
+
+    // retries made by the client protocol during method calls
+    retry_timeouts: usize,
+    retry_invalid_responses: usize,
+    retry_retransmits: usize,
+    retry_wait_ms: u64,
+
+    // latencies of successful method calls, summarized in the report
+    #[serde(skip)]
+    latencies: Vec<Duration>,
 }
 
-/// Run a test based on the consts defined above
-pub async fn test(
-    semantics: InvocationSemantics,
-    inv_prob: u32, // used only for faulty protos
+/// Aggregates retry events reported by the client protocol
+#[derive(Debug, Default)]
+struct RetryTally {
+    timeouts: AtomicUsize,
+    invalid_responses: AtomicUsize,
+    retransmits: AtomicUsize,
+    wait_ms: AtomicU64,
+}
+
+impl RetryObserver for RetryTally {
+    fn on_retry(&self, event: &RetryEvent) {
+        let counter = match event.reason {
+            RetryReason::Timeout => &self.timeouts,
+            RetryReason::InvalidResponse => &self.invalid_responses,
+            RetryReason::Retransmit => &self.retransmits,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.wait_ms
+            .fetch_add(event.wait.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl RetryTally {
+    /// Add the tallied retries to the test results
+    fn add_to(&self, results: &mut TestResult) {
+        results.retry_timeouts += self.timeouts.load(Ordering::Relaxed);
+        results.retry_invalid_responses += self.invalid_responses.load(Ordering::Relaxed);
+        results.retry_retransmits += self.retransmits.load(Ordering::Relaxed);
+        results.retry_wait_ms += self.wait_ms.load(Ordering::Relaxed);
+    }
+}
+
+impl Run {
+    /// A run of all methods, with the default number of iterations
+    pub fn new(semantics: InvocationSemantics, failure_rate: u32) -> Self {
+        Self {
+            name: None,
+            semantics,
+            failure_rate,
+            payload: PayloadKind::default(),
+            repetitions: TEST_ITERATIONS,
+        }
+    }
+}
+
+/// Make every run of a scenario, in order.
+///
+/// Each run writes its own results file and report, as in [test].
+pub async fn run_scenario(
+    scenario: &Scenario,
+    report: Option<(ReportFormat, bool)>,
     source: Ipv4Addr,
     target: Ipv4Addr,
     port: u16,
     timeout: Duration,
     retries: u8,
 ) -> io::Result<()> {
-    let absolute_timeout = timeout * retries as u32 * 10;
+    for (idx, run) in scenario.runs.iter().enumerate() {
+        log::info!(
+            "scenario run {}/{}: {:?}",
+            idx + 1,
+            scenario.runs.len(),
+            run
+        );
 
-    let (normal_proto, faulty_proto): (
-        Arc<dyn TransmissionProtocol + Send + Sync>,
-        Arc<dyn TransmissionProtocol + Send + Sync>,
-    ) = match semantics {
-        InvocationSemantics::Maybe => (
-            Arc::new(DefaultProto),
-            Arc::new(FaultyDefaultProto::from_frac(inv_prob)),
-        ),
-        InvocationSemantics::AtLeastOnce => (
-            Arc::new(RequestAckProto),
-            Arc::new(FaultyRequestAckProto::from_frac(inv_prob)),
-        ),
-        InvocationSemantics::AtMostOnce => (
-            Arc::new(HandshakeProto),
-            Arc::new(FaultyHandshakeProto::from_frac(inv_prob)),
-        ),
-    };
+        let results = test(run, source, target, port, timeout, retries).await?;
+        if let Some((format, histograms)) = report {
+            write_report_to_file(&results, run, format, histograms)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Make a test run, with the limits defined by the consts above.
+///
+/// The results are written to a CSV file, and returned for reporting.
+pub async fn test(
+    run: &Run,
+    source: Ipv4Addr,
+    target: Ipv4Addr,
+    port: u16,
+    timeout: Duration,
+    retries: u8,
+) -> io::Result<Vec<TestResult>> {
+    let absolute_timeout = timeout * retries as u32 * 10;
+    let inv_prob = run.failure_rate; // used only for faulty protos
+
+    let registry = ProtocolRegistry::default();
+    let protocol_name = run.semantics.to_string();
+    let normal_proto = registry.build(&protocol_name, &ProtocolOptions::default())?;
+    let faulty_proto = registry.build(
+        &protocol_name,
+        &ProtocolOptions {
+            failure_rate: Some(
+                FailureRate::try_from(inv_prob)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            ),
+            ..Default::default()
+        },
+    )?;
 
     log::info!("creating temp context manager");
     let mut temp_ctx = loop {
@@ -145,12 +241,12 @@ pub async fn test(
     };
 
     // test normal proto
-    for _ in 0..TEST_ITERATIONS {
+    for _ in 0..run.repetitions {
         single_test_iteration(
             normal_proto.clone(),
+            run.payload,
             source,
-            target,
-            port,
+            SocketAddrV4::new(target, port),
             timeout,
             retries,
             &mut res,
@@ -161,12 +257,12 @@ pub async fn test(
     }
 
     // test faulty proto
-    for _ in 0..TEST_ITERATIONS {
+    for _ in 0..run.repetitions {
         single_test_iteration(
             normal_proto.clone(),
+            run.payload,
             source,
-            target,
-            port,
+            SocketAddrV4::new(target, port),
             timeout,
             retries,
             &mut faulty_res,
@@ -176,17 +272,22 @@ pub async fn test(
         // tokio::time::sleep(absolute_timeout).await;
     }
 
-    write_results_to_file(&[res, faulty_res])?;
+    let results = vec![res, faulty_res];
+    write_results_to_file(&results, run)?;
 
-    Ok(())
+    Ok(results)
 }
 
-/// Write the results to a file.
+/// Name of the files the results are written to, without an extension.
 ///
-/// The file is named according to these fields of the first element:
+/// Unless the run is named, the file is named according to these fields of the first element:
 /// - remote protocol
 /// - failure probability
-fn write_results_to_file(results: &[TestResult]) -> io::Result<()> {
+fn results_file_stem(results: &[TestResult], run: &Run) -> String {
+    if let Some(name) = &run.name {
+        return format!("test_{}", name);
+    }
+
     let failure_prob = results
         .iter()
         .find_map(|r| match r.inverse_failure_probability {
@@ -195,7 +296,30 @@ fn write_results_to_file(results: &[TestResult]) -> io::Result<()> {
         })
         .expect("one element must have a failure probability defined");
 
-    let file_name = format!("test_{}_{}.csv", results[0].remote_protocol, failure_prob);
+    format!("test_{}_{}", results[0].remote_protocol, failure_prob)
+}
+
+/// Write a summary of the results, with a row for each configuration.
+pub fn write_report_to_file(
+    results: &[TestResult],
+    run: &Run,
+    format: ReportFormat,
+    histograms: bool,
+) -> io::Result<()> {
+    let summaries = results
+        .iter()
+        .map(report::Summary::from)
+        .collect::<Vec<_>>();
+
+    let file_name = format!("{}.{}", results_file_stem(results, run), format.extension());
+    log::info!("writing report to file: {}", file_name);
+
+    std::fs::write(file_name, report::render(&summaries, format, histograms))
+}
+
+/// Write the results to a CSV file.
+fn write_results_to_file(results: &[TestResult], run: &Run) -> io::Result<()> {
+    let file_name = format!("{}.csv", results_file_stem(results, run));
     log::info!("writing to file: {}", file_name);
 
     let mut csv_writer = csv::Writer::from_path(file_name)?;
@@ -237,11 +361,11 @@ async fn get_remote_protocol_name(ctx: &mut ContextManager) -> String {
 /// Run a single test iteration for a particular protocol
 async fn single_test_iteration(
     proto: Arc<dyn TransmissionProtocol + Send + Sync>,
+    payload: PayloadKind,
     // client_sim_fail: bool,
     // inv_probability: Option<usize>,
     source: Ipv4Addr,
-    target: Ipv4Addr,
-    port: u16,
+    target: SocketAddrV4,
     timeout: Duration,
     retries: u8,
     results: &mut TestResult,
@@ -261,7 +385,7 @@ async fn single_test_iteration(
 
             ctx_res = ContextManager::new(
                 source,
-                SocketAddrV4::new(target, port),
+                target,
                 timeout,
                 retries,
                 proto.clone(),
@@ -282,97 +406,146 @@ async fn single_test_iteration(
     let mut num_method_calls = 0;
     let mut method_failures = 0;
 
-    while num_method_calls < MAX_METHOD_CALLS {
-        log::info!(
-            "method call count: {}, failure count: {}",
-            num_method_calls,
-            method_failures
-        );
+    let counter_start = read_counter(&mut ctx, method_call_absolute_timeout).await;
+    let mut counter_increments = 0;
 
-        if method_failures >= NUM_FAILURE_THRESHOLD {
-            break;
-        }
+    let tally = Arc::new(RetryTally::default());
+    observe_retries(tally.clone(), async {
+        while num_method_calls < MAX_METHOD_CALLS {
+            log::info!(
+                "method call count: {}, failure count: {}",
+                num_method_calls,
+                method_failures
+            );
 
-        // early exit for very reliable protocols
-        if num_method_calls >= MIN_METHOD_CALLS_TO_PROB_CHECK {
-            let failure_rate = method_failures as f64 / num_method_calls as f64;
-            if failure_rate < TERMINATION_FAILURE_THRESHOLD {
+            if method_failures >= NUM_FAILURE_THRESHOLD {
                 break;
             }
-        }
 
-        let u_id = {
-            let now = std::time::SystemTime::now();
-            let mut hasher = DefaultHasher::new();
-            now.hash(&mut hasher);
+            // early exit for very reliable protocols
+            if num_method_calls >= MIN_METHOD_CALLS_TO_PROB_CHECK {
+                let failure_rate = method_failures as f64 / num_method_calls as f64;
+                if failure_rate < TERMINATION_FAILURE_THRESHOLD {
+                    break;
+                }
+            }
 
-            hasher.finish()
-        };
+            let u_id = {
+                let now = std::time::SystemTime::now();
+                let mut hasher = DefaultHasher::new();
+                now.hash(&mut hasher);
+
+                hasher.finish()
+            };
+
+            // idempotent
+            // need to implement timeout here cause of maybe semantics
+            if payload.idempotent() {
+                num_method_calls += 1;
+                let start = Instant::now();
+                tokio::select! {
+                    _ = tokio::time::sleep(method_call_absolute_timeout) => {
+                        method_failures += 1;
+                    },
 
-        // idempotent
-        // need to implement timeout here cause of maybe semantics
-        num_method_calls += 1;
-        tokio::select! {
-            _ = tokio::time::sleep(method_call_absolute_timeout) => {
-                method_failures += 1;
-            },
+                    method_call_res = TestOpsClient::test_idempotent(&mut ctx, u_id) => {
+                        match method_call_res {
+                            Ok(_) => results.latencies.push(start.elapsed()),
+                            Err(_) => {
+                                tokio::time::sleep(method_call_absolute_timeout).await;
+                                method_failures += 1;
+                            }
+                        }
 
-            method_call_res = TestOpsClient::test_idempotent(&mut ctx, u_id) => {
-                match method_call_res {
-                    Ok(_) => (),
-                    Err(_) => {
-                        tokio::time::sleep(method_call_absolute_timeout).await;
-                        method_failures += 1;
                     }
                 }
-
             }
-        }
 
-        // non-idempotent
-        num_method_calls += 1;
-        tokio::select! {
-            _ = tokio::time::sleep(method_call_absolute_timeout) => {
-                method_failures += 1;
-            },
-
-            method_call_res = TestOpsClient::test_non_idempotent(&mut ctx, u_id) => {
-                match method_call_res {
-                    Ok(val) => {
-                        results.non_idempotent_calls += 1;
+            // non-idempotent
+            if payload.non_idempotent() {
+                num_method_calls += 1;
+                let start = Instant::now();
+                tokio::select! {
+                    _ = tokio::time::sleep(method_call_absolute_timeout) => {
+                        method_failures += 1;
+                    },
 
-                        if val != 1 {
-                            results.non_idempotent_mismatches += 1;
+                    method_call_res = TestOpsClient::test_non_idempotent(&mut ctx, u_id) => {
+                        match method_call_res {
+                            Ok(val) => {
+                                results.latencies.push(start.elapsed());
+                                results.non_idempotent_calls += 1;
+
+                                if val != 1 {
+                                    results.non_idempotent_mismatches += 1;
+                                }
+                            },
+
+                            Err(_) => {
+                                tokio::time::sleep(method_call_absolute_timeout).await;
+                                method_failures += 1;
+                            }
                         }
-                    },
 
-                    Err(_) => {
-                        tokio::time::sleep(method_call_absolute_timeout).await;
-                        method_failures += 1;
                     }
                 }
-
             }
-        }
-
-        // reset non-idempotent
-        num_method_calls += 1;
-        tokio::select! {
-            _ = tokio::time::sleep(method_call_absolute_timeout) => {
-                method_failures += 1;
-            },
 
-            method_call_res = TestOpsClient::reset_non_idempotent(&mut ctx) => {
-                match method_call_res {
-                    Ok(_) => (),
-                    Err(_) => {
-                        tokio::time::sleep(method_call_absolute_timeout).await;
+            // shared counter, where every executed duplicate adds to the counter
+            if payload.counter() {
+                num_method_calls += 1;
+                let start = Instant::now();
+                tokio::select! {
+                    _ = tokio::time::sleep(method_call_absolute_timeout) => {
                         method_failures += 1;
+                    },
+
+                    method_call_res = CounterOpsClient::increment(&mut ctx, 1) => {
+                        match method_call_res {
+                            Ok(_) => {
+                                results.latencies.push(start.elapsed());
+                                counter_increments += 1;
+                            }
+                            Err(_) => {
+                                tokio::time::sleep(method_call_absolute_timeout).await;
+                                method_failures += 1;
+                            }
+                        }
                     }
                 }
+            }
+
+            // reset non-idempotent
+            if payload.non_idempotent() {
+                num_method_calls += 1;
+                let start = Instant::now();
+                tokio::select! {
+                    _ = tokio::time::sleep(method_call_absolute_timeout) => {
+                        method_failures += 1;
+                    },
+
+                    method_call_res = TestOpsClient::reset_non_idempotent(&mut ctx) => {
+                        match method_call_res {
+                            Ok(_) => results.latencies.push(start.elapsed()),
+                            Err(_) => {
+                                tokio::time::sleep(method_call_absolute_timeout).await;
+                                method_failures += 1;
+                            }
+                        }
 
+                    }
+                }
             }
         }
+    })
+    .await;
+
+    tally.add_to(results);
+
+    let counter_end = read_counter(&mut ctx, method_call_absolute_timeout).await;
+    if let (Some(start), Some(end)) = (counter_start, counter_end) {
+        results.counter_increments += counter_increments;
+        results.counter_excess += (end - start) as i64 - counter_increments as i64;
     }
 
     results.method_call_count += num_method_calls;
@@ -380,3 +553,23 @@ async fn single_test_iteration(
 
     Ok(())
 }
+
+/// Read the shared counter, giving up after a few failed attempts.
+async fn read_counter(ctx: &mut ContextManager, timeout: Duration) -> Option<u64> {
+    for _ in 0..3 {
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => {
+                log::error!("timeout reading counter, retrying...");
+            },
+
+            counter_res = CounterOpsClient::get(ctx) => {
+                match counter_res {
+                    Ok(val) => return Some(val),
+                    Err(_) => log::error!("failed to read counter, retrying..."),
+                }
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,318 @@
+//! Summary statistics of test results, written as a Markdown or HTML report.
+
+use std::{fmt::Write, time::Duration};
+
+use crate::args::ReportFormat;
+
+use super::TestResult;
+
+/// Number of buckets in a latency histogram
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Width of the longest histogram bar, in characters
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Latency distribution of successful method calls
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencyStats {
+    pub mean: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Returns `None` if there are no samples.
+    pub fn from_samples(samples: &[Duration]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+
+        Some(Self {
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            median: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty samples
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Statistics of a single configuration: client protocol, remote protocol and failure rate.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub configuration: String,
+    pub latency: Option<LatencyStats>,
+
+    /// Fraction of method calls that succeeded
+    pub success_ratio: f64,
+
+    /// Retries made per method call
+    pub retransmission_rate: f64,
+
+    /// Fraction of context managers created without retrying
+    pub init_success_ratio: f64,
+
+    /// Fraction of non-idempotent calls that were executed more than once
+    pub duplicate_execution_ratio: f64,
+
+    samples: Vec<Duration>,
+}
+
+impl From<&TestResult> for Summary {
+    fn from(res: &TestResult) -> Self {
+        let retries = res.retry_timeouts + res.retry_invalid_responses + res.retry_retransmits;
+
+        Self {
+            configuration: format!(
+                "{} -> {} (1 in {})",
+                res.client_protocol,
+                res.remote_protocol,
+                res.inverse_failure_probability
+                    .map(|p| p.to_string())
+                    .unwrap_or("-".to_string())
+            ),
+            latency: LatencyStats::from_samples(&res.latencies),
+            success_ratio: ratio(
+                res.method_call_count - res.method_call_failures,
+                res.method_call_count,
+            ),
+            retransmission_rate: ratio(retries, res.method_call_count),
+            init_success_ratio: ratio(res.init_count - res.init_failures, res.init_count),
+            duplicate_execution_ratio: ratio(
+                res.non_idempotent_mismatches,
+                res.non_idempotent_calls,
+            ),
+            samples: res.latencies.clone(),
+        }
+    }
+}
+
+/// Ratio of two counts, 0 if there is nothing to divide by.
+fn ratio(num: usize, den: usize) -> f64 {
+    match den {
+        0 => 0.0,
+        d => num as f64 / d as f64,
+    }
+}
+
+/// Latency histogram, with one line per bucket.
+///
+/// ```text
+///    1.200ms | ######## 12
+///    2.400ms | ## 3
+/// ```
+pub fn ascii_histogram(samples: &[Duration]) -> String {
+    let (min, max) = match (samples.iter().min(), samples.iter().max()) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => return String::new(),
+    };
+
+    let width = (max - min) / HISTOGRAM_BUCKETS as u32;
+    let mut counts = [0_usize; HISTOGRAM_BUCKETS];
+    for sample in samples {
+        let bucket = match width.is_zero() {
+            true => 0,
+            false => ((*sample - min).as_nanos() / width.as_nanos()) as usize,
+        };
+        counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    let peak = counts.iter().copied().max().unwrap_or(1);
+    let mut out = String::new();
+    for (idx, count) in counts.iter().enumerate() {
+        let start = min + width * idx as u32;
+        let bar = "#".repeat(count * HISTOGRAM_WIDTH / peak);
+
+        let _ = writeln!(out, "{:>10} | {} {}", format_ms(start), bar, count);
+    }
+
+    out
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
+
+fn format_pct(r: f64) -> String {
+    format!("{:.2}%", r * 100.0)
+}
+
+/// Table cells of a summary, in the order of [HEADERS]
+fn row(s: &Summary) -> Vec<String> {
+    let latency = |f: fn(&LatencyStats) -> Duration| match &s.latency {
+        Some(l) => format_ms(f(l)),
+        None => "-".to_string(),
+    };
+
+    vec![
+        s.configuration.clone(),
+        format_pct(s.success_ratio),
+        format!("{:.4}", s.retransmission_rate),
+        format_pct(s.init_success_ratio),
+        format_pct(s.duplicate_execution_ratio),
+        latency(|l| l.mean),
+        latency(|l| l.median),
+        latency(|l| l.p95),
+        latency(|l| l.p99),
+        latency(|l| l.max),
+    ]
+}
+
+const HEADERS: [&str; 10] = [
+    "configuration",
+    "success",
+    "retries/call",
+    "init success",
+    "duplicate executions",
+    "mean",
+    "median",
+    "p95",
+    "p99",
+    "max",
+];
+
+/// Render the summaries as a report, optionally with a latency histogram for each.
+pub fn render(summaries: &[Summary], format: ReportFormat, histograms: bool) -> String {
+    match format {
+        ReportFormat::Markdown => markdown(summaries, histograms),
+        ReportFormat::Html => html(summaries, histograms),
+    }
+}
+
+fn markdown(summaries: &[Summary], histograms: bool) -> String {
+    let mut out = String::from("# Test results\n\n");
+
+    let _ = writeln!(out, "| {} |", HEADERS.join(" | "));
+    let _ = writeln!(out, "|{}", "---|".repeat(HEADERS.len()));
+    for s in summaries {
+        let _ = writeln!(out, "| {} |", row(s).join(" | "));
+    }
+
+    if histograms {
+        for s in summaries.iter().filter(|s| !s.samples.is_empty()) {
+            let _ = write!(
+                out,
+                "\n## {}\n\n```text\n{}```\n",
+                s.configuration,
+                ascii_histogram(&s.samples)
+            );
+        }
+    }
+
+    out
+}
+
+fn html(summaries: &[Summary], histograms: bool) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Test results</title></head>\n<body>\n<h1>Test results</h1>\n<table>\n",
+    );
+
+    let _ = writeln!(
+        out,
+        "<tr>{}</tr>",
+        HEADERS
+            .iter()
+            .map(|h| format!("<th>{}</th>", h))
+            .collect::<String>()
+    );
+    for s in summaries {
+        let _ = writeln!(
+            out,
+            "<tr>{}</tr>",
+            row(s)
+                .iter()
+                .map(|c| format!("<td>{}</td>", escape_html(c)))
+                .collect::<String>()
+        );
+    }
+    out.push_str("</table>\n");
+
+    if histograms {
+        for s in summaries.iter().filter(|s| !s.samples.is_empty()) {
+            let _ = write!(
+                out,
+                "<h2>{}</h2>\n<pre>{}</pre>\n",
+                escape_html(&s.configuration),
+                ascii_histogram(&s.samples)
+            );
+        }
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let samples = (1..=100).rev().map(ms).collect::<Vec<_>>();
+        let stats = LatencyStats::from_samples(&samples).unwrap();
+
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+        assert_eq!(stats.median, ms(50));
+        assert_eq!(stats.p95, ms(95));
+        assert_eq!(stats.p99, ms(99));
+        assert_eq!(stats.max, ms(100));
+
+        assert_eq!(LatencyStats::from_samples(&[]), None);
+        assert_eq!(LatencyStats::from_samples(&[ms(7)]).unwrap().p99, ms(7));
+    }
+
+    #[test]
+    fn test_report() {
+        let res = TestResult {
+            client_protocol: "RequestAckProto".to_string(),
+            remote_protocol: "FaultyRequestAckProto".to_string(),
+            inverse_failure_probability: Some(10),
+            init_count: 4,
+            init_failures: 1,
+            method_call_count: 200,
+            method_call_failures: 10,
+            retry_timeouts: 20,
+            retry_retransmits: 5,
+            latencies: vec![ms(1), ms(1), ms(2), ms(11)],
+            ..Default::default()
+        };
+        let summary = Summary::from(&res);
+
+        assert_eq!(summary.success_ratio, 0.95);
+        assert_eq!(summary.retransmission_rate, 0.125);
+        assert_eq!(summary.init_success_ratio, 0.75);
+
+        let report = render(std::slice::from_ref(&summary), ReportFormat::Markdown, true);
+        assert!(report.contains(
+            "| RequestAckProto -> FaultyRequestAckProto (1 in 10) | 95.00% | 0.1250 | 75.00% |"
+        ));
+        // 2 samples in the first bucket, 1 in the second, 1 in the last
+        assert!(report.contains(&format!("1.000ms | {} 2", "#".repeat(HISTOGRAM_WIDTH))));
+        assert!(report.contains(&format!("10.000ms | {} 1", "#".repeat(HISTOGRAM_WIDTH / 2))));
+
+        let report = render(&[summary], ReportFormat::Html, false);
+        assert!(report.contains("<td>RequestAckProto -&gt; FaultyRequestAckProto (1 in 10)</td>"));
+        assert!(!report.contains("<pre>"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Test runs defined in a TOML file, so an experiment suite can be run with one command.
+//!
+//! ```toml
+//! [[run]]
+//! name = "at-most-once-lossy"
+//! semantics = "at-most-once"
+//! failure_rate = 100
+//! payload = "non-idempotent"
+//! repetitions = 5
+//! ```
+//!
+//! The server is not part of a scenario. Runs are made against the server that is running,
+//! with the semantics and simulated failures it was started with.
+
+use std::{io, path::Path};
+
+use serde::Deserialize;
+
+use crate::args::InvocationSemantics;
+
+use super::TEST_ITERATIONS;
+
+/// Test runs, made in order
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(rename = "run")]
+    pub runs: Vec<Run>,
+}
+
+/// A single test run
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Run {
+    /// Names the files the results are written to
+    #[serde(default)]
+    pub name: Option<String>,
+
+    pub semantics: InvocationSemantics,
+
+    /// Simulated failures of the faulty client protocol, as 1 in N transmissions
+    pub failure_rate: u32,
+
+    #[serde(default)]
+    pub payload: PayloadKind,
+
+    /// Number of test iterations for each client protocol
+    #[serde(default = "default_repetitions")]
+    pub repetitions: usize,
+}
+
+/// Methods called in each test iteration
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadKind {
+    /// All of the methods below
+    #[default]
+    Mixed,
+
+    /// A method that returns the same result when repeated
+    Idempotent,
+
+    /// A method whose result changes when repeated, and the method that resets it
+    NonIdempotent,
+
+    /// A shared counter, where every executed duplicate adds to the counter
+    Counter,
+}
+
+fn default_repetitions() -> usize {
+    TEST_ITERATIONS
+}
+
+impl Scenario {
+    /// Load a scenario from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+
+        Self::parse(&contents)
+    }
+
+    fn parse(contents: &str) -> io::Result<Self> {
+        let scenario: Self =
+            toml::from_str(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if let Some(run) = scenario.runs.iter().find(|r| r.failure_rate == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("run {:?} must have a non-zero failure rate", run.name),
+            ));
+        }
+
+        Ok(scenario)
+    }
+}
+
+impl PayloadKind {
+    pub fn idempotent(&self) -> bool {
+        matches!(self, Self::Mixed | Self::Idempotent)
+    }
+
+    pub fn non_idempotent(&self) -> bool {
+        matches!(self, Self::Mixed | Self::NonIdempotent)
+    }
+
+    pub fn counter(&self) -> bool {
+        matches!(self, Self::Mixed | Self::Counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario =
+            Scenario::parse(include_str!("../../../../scenarios/at_most_once.toml")).unwrap();
+        assert!(!scenario.runs.is_empty());
+
+        let scenario = Scenario::parse(
+            r#"
+            [[run]]
+            semantics = "at-least-once"
+            failure_rate = 10
+
+            [[run]]
+            name = "counter"
+            semantics = "at-most-once"
+            failure_rate = 100
+            payload = "counter"
+            repetitions = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.runs[0],
+            Run {
+                name: None,
+                semantics: InvocationSemantics::AtLeastOnce,
+                failure_rate: 10,
+                payload: PayloadKind::Mixed,
+                repetitions: TEST_ITERATIONS,
+            }
+        );
+        assert_eq!(scenario.runs[1].payload, PayloadKind::Counter);
+        assert_eq!(scenario.runs[1].repetitions, 2);
+
+        assert!(Scenario::parse("[[run]]\nsemantics = \"maybe\"\nfailure_rate = 0").is_err());
+        assert!(Scenario::parse("[[run]]\nsemantics = \"sometimes\"\nfailure_rate = 1").is_err());
+    }
+}
@@ -5,7 +5,8 @@ mod ui;
 
 use std::{
     io::{self, Write},
-    net::SocketAddrV4,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
@@ -28,9 +29,35 @@ async fn main() -> io::Result<()> {
 
     let args = ClientArgs::parse();
 
+    let source = match args.listen_address {
+        Some(addr) => addr,
+        None => {
+            let addr = route_source(SocketAddrV4::new(args.target, args.port))?;
+            log::info!("sending requests from {}", addr);
+            addr
+        }
+    };
+
     if args.test {
         drop(sh);
 
+        let report = args.report.map(|format| (format, args.report_histograms));
+
+        if let Some(path) = &args.scenario {
+            let scenario = data_collection::Scenario::load(path)?;
+
+            return data_collection::run_scenario(
+                &scenario,
+                report,
+                source,
+                args.target,
+                args.port,
+                args.request_timeout.into(),
+                args.num_retries.into(),
+            )
+            .await;
+        }
+
         let inv_prob = match args.simulate_ommisions {
             Some(frac) => frac,
             None => {
@@ -41,107 +68,219 @@ async fn main() -> io::Result<()> {
             }
         };
 
-        let _ = data_collection::test(
-            args.invocation_semantics.clone(),
-            inv_prob,
-            args.listen_address,
+        let run = data_collection::Run::new(args.invocation_semantics, inv_prob.into());
+        let results = data_collection::test(
+            &run,
+            source,
             args.target,
             args.port,
             args.request_timeout.into(),
-            args.num_retries,
+            args.num_retries.into(),
         )
         .await?;
 
+        if let Some((format, histograms)) = report {
+            data_collection::write_report_to_file(&results, &run, format, histograms)?;
+        }
+
         return Ok(());
     }
 
-    let manager = match (args.invocation_semantics, args.simulate_ommisions) {
-        (args::InvocationSemantics::Maybe, Some(frac)) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(FaultyDefaultProto::from_frac(frac)),
-            )
-            .await?
-        }
-        (args::InvocationSemantics::Maybe, None) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(DefaultProto),
-            )
-            .await?
-        }
-        (args::InvocationSemantics::AtLeastOnce, Some(frac)) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(FaultyRequestAckProto::from_frac(frac)),
-            )
-            .await?
-        }
-        (args::InvocationSemantics::AtLeastOnce, None) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(RequestAckProto),
-            )
-            .await?
-        }
-        (args::InvocationSemantics::AtMostOnce, Some(frac)) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(FaultyHandshakeProto::from_frac(frac)),
-            )
-            .await?
-        }
-        (args::InvocationSemantics::AtMostOnce, None) => {
-            ContextManager::new(
-                args.listen_address,
-                SocketAddrV4::new(args.target, args.port),
-                args.request_timeout.into(),
-                args.num_retries,
-                Arc::new(HandshakeProto),
-            )
-            .await?
-        }
+    let protocol = ProtocolRegistry::default().build(
+        &args.invocation_semantics.to_string(),
+        &ProtocolOptions {
+            failure_rate: args.simulate_ommisions,
+            ..Default::default()
+        },
+    )?;
+    let target = SocketAddrV4::new(args.target, args.port);
+
+    if let Some(args::ClientCommand::Doctor) = args.command {
+        return doctor(&args, source, target, protocol).await;
+    }
+
+    let mut manager = ContextManager::new(
+        source,
+        target,
+        args.request_timeout,
+        args.num_retries,
+        protocol,
+    )
+    .await?
+    .with_interface_hash(rfs::interfaces::INTERFACE_HASH)
+    .await?
+    .with_faults(InvocationFaults {
+        drop_response: args.drop_responses,
+        duplicates: args.duplicate_requests,
+    })
+    .with_read_cache(args.freshness_interval.into());
+    if let Some(token) = args.token {
+        manager = manager.with_token(token);
+    }
+    if args.compressed {
+        manager = manager.with_compressed_responses();
+    }
+    if args.adaptive_timeout {
+        manager = manager.with_adaptive_timeout();
+    }
+
+    if let Some(args::ClientCommand::Get {
+        recursive,
+        parallel,
+        file_retries,
+        streams,
+        remote,
+        local,
+    }) = &args.command
+    {
+        let options = rfs_client_core::DownloadOptions {
+            parallel: *parallel,
+            retries: *file_retries,
+            streams: *streams,
+        };
+        return get(manager, remote, local, *recursive, &options).await;
+    }
+
+    #[cfg(feature = "fuse")]
+    if let Some(args::ClientCommand::Mount { mountpoint }) = &args.command {
+        let remote = rfs_client_core::RemoteFs::new(manager);
+        let (mountpoint, runtime) = (mountpoint.clone(), tokio::runtime::Handle::current());
+        log::info!("mounting the remote at {:?}", mountpoint);
+
+        return tokio::task::spawn_blocking(move || {
+            rfs_client_core::fuse::mount(remote, mountpoint, runtime)
+        })
+        .await?;
+    }
+
+    let log_file = match args.log_to_file {
+        true => Some(args.log_file.unwrap_or_else(session_log_file)),
+        false => None,
     };
 
-    let stderr_pipe: Box<dyn io::Read + Send + 'static> = match args.log_to_file {
-        true => {
+    let stderr_pipe: Box<dyn io::Read + Send + 'static> = match &log_file {
+        Some(path) => {
             let io_pipe = IOPipe::new(
                 Box::new(shh::stderr()?),
                 Box::new(
                     std::fs::File::options()
                         .create(true)
                         .append(true)
-                        .open(format!("{}.log", env!("CARGO_BIN_NAME")))?,
+                        .open(path)?,
                 ),
             );
 
             Box::new(io_pipe)
         }
-        false => Box::new(shh::stderr()?),
+        None => Box::new(shh::stderr()?),
+    };
+
+    let theme = match &args.theme {
+        Some(path) => ui::FsTheme::load(path)?,
+        None => Default::default(),
     };
 
     let frame_rate = 50.0;
-    let mut app = ui::App::new(manager, frame_rate, frame_rate, stderr_pipe);
+    let mut app = ui::App::new(
+        manager,
+        frame_rate,
+        frame_rate,
+        stderr_pipe,
+        args.conflict_resolution,
+        match args.no_session {
+            true => None,
+            false => Some(args.session_file),
+        },
+        theme,
+    )
+    .with_logs(args.max_log_lines, log_file);
     app.run().await?;
 
     return Ok(());
 }
 
+/// Copy a remote file or directory tree to a local path, and print a summary
+async fn get(
+    manager: ContextManager,
+    remote: &str,
+    local: &Path,
+    recursive: bool,
+    options: &rfs_client_core::DownloadOptions,
+) -> io::Result<()> {
+    let remote_fs = rfs_client_core::RemoteFs::new(manager);
+
+    if !recursive {
+        let len = remote_fs
+            .download_parallel(remote, local, options.streams)
+            .await?;
+        println!("copied {} ({} bytes)", remote, len);
+        return Ok(());
+    }
+
+    let summary = remote_fs.download_tree(remote, local, options).await?;
+
+    println!(
+        "copied {} files ({} bytes)",
+        summary.copied.len(),
+        summary.bytes()
+    );
+    for (path, e) in &summary.failed {
+        println!("failed {}: {}", path, e);
+    }
+
+    match summary.is_success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "{} files could not be copied",
+            summary.failed.len()
+        ))),
+    }
+}
+
+/// Check the environment and the connection to the server
+async fn doctor(
+    args: &ClientArgs,
+    source: Ipv4Addr,
+    target: SocketAddrV4,
+    protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
+) -> io::Result<()> {
+    let mut checks = vec![
+        rfs::doctor::check_bind(SocketAddrV4::new(source, 0)).await,
+        rfs::doctor::check_max_datagram(source).await,
+        rfs::doctor::check_route(source, target),
+    ];
+
+    let (reachable, ctx) = rfs::doctor::check_reachable(
+        source,
+        target,
+        args.request_timeout,
+        args.num_retries,
+        protocol,
+    )
+    .await;
+    checks.push(reachable);
+
+    // the remaining checks require a connection
+    if let Some(mut ctx) = ctx {
+        checks.push(rfs::doctor::check_clock_skew(&mut ctx).await);
+    }
+
+    match rfs::doctor::report(&checks) {
+        true => Ok(()),
+        false => Err(io::Error::other("doctor found problems")),
+    }
+}
+
+/// Log file of this session, named after the time it started
+fn session_log_file() -> PathBuf {
+    let started = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    PathBuf::from(format!("{}-{}.log", env!("CARGO_BIN_NAME"), started))
+}
+
 ///
 struct IOPipe {
     // usually a file
@@ -4,6 +4,7 @@ use std::time::Duration;
 use rfs::fs::VirtFile;
 use rfs::interfaces::*;
 use rfs::middleware::ContextManager;
+use tokio_util::sync::CancellationToken;
 
 /// Test various stuff out
 #[allow(unused)]
@@ -35,7 +36,7 @@ pub async fn test_mode(mut ctx: ContextManager) -> io::Result<()> {
         log::debug!("watching file");
         let mut file = VirtFile::open(cloned_ctx, "remote_file.txt").await.unwrap();
 
-        match file.watch().await {
+        match file.watch(None, CancellationToken::new()).await {
             Ok(c) => {
                 log::info!("successfully received file update");
                 c
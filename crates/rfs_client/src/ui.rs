@@ -2,8 +2,16 @@
 //!
 #![allow(unused)]
 
+mod action;
 mod app;
+mod content_lines;
 mod contents;
+mod key_macro;
+mod messages;
+mod progress;
+mod session;
+mod tasks;
+mod theme;
 mod tui;
 mod widgets;
 
@@ -38,6 +46,8 @@ pub type Ui = Terminal<CrosstermBackend<Stdout>>;
 pub const TICK_PERIOD: Duration = Duration::from_millis(1000 / 60);
 
 pub use app::App;
+use tasks::{TaskPurpose, TaskRegistry};
+pub use theme::FsTheme;
 
 #[derive(Debug)]
 pub enum SelectedScreen {
@@ -65,22 +75,20 @@ pub async fn run() -> io::Result<()> {
     // let mut term = init()?;
     // let mut app = App::new(60.0, 60.0);
 
-    loop {
-        // if app.exit {
-        //     break;
-        // }
-    }
+    // the app runs its own loop, see [App::run]
+    std::future::pending::<()>().await;
 
     Ok(())
 }
 
 impl EventHandler {
-    pub fn new() -> Self {
+    /// Read terminal events in a task registered with `tasks`, until it shuts down.
+    pub fn new(tasks: &mut TaskRegistry) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
 
         // spawn the event handler
-        tokio::spawn(async move {
-            loop {
+        tasks.spawn(TaskPurpose::Input, |token| async move {
+            while !token.is_cancelled() {
                 if crossterm::event::poll(TICK_PERIOD).expect("poll failed") {
                     if let Ok(ev) = crossterm::event::read() {
                         tx.send(ev).expect("event send failed");
@@ -0,0 +1,642 @@
+//! Actions performed by the app.
+//!
+//! Key events are reduced to an [Action] depending on the current [AppState].
+//! The app then applies the action to its state.
+
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::args::ConflictResolution;
+
+use super::app::{AppEvents, AppState, ContentState, FsState};
+
+const FS_CREATE_FILE: char = 'f';
+const FS_CREATE_DIR: char = 'd';
+const FS_DELETE: char = 'x';
+const FS_REFRESH: char = 'u';
+const FS_WATCH: char = 'w';
+const FS_DETAILS: char = 'i';
+const FS_LOGS: char = 'l';
+const FS_GOTO: char = 'g';
+
+// feature not impl'd
+const FS_RENAME: char = 'r';
+
+const DEBUG_COUNTER: char = 'c';
+
+const MACRO_RECORD: char = 'q';
+const MACRO_PLAY: char = '@';
+
+const CONTENT_WATCH: char = 'w';
+const CONTENT_GOTO: char = ':';
+
+const CONFLICT_KEEP_MINE: char = 'k';
+const CONFLICT_TAKE_REMOTE: char = 't';
+const CONFLICT_MERGE: char = 'm';
+
+/// Function key that toggles the stats overlay
+const TOGGLE_STATS: u8 = 2;
+
+/// Something the user wants the app to do
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Move focus between and into widgets
+    Focus(AppEvents),
+
+    Quit,
+
+    /// Open the selected file or directory
+    OpenSelected,
+
+    /// Go up one directory
+    ParentDir,
+
+    /// Read the current directory again
+    RefreshDir,
+
+    /// Toggle reading the current directory again whenever it changes on the remote
+    ToggleDirWatch,
+
+    /// Toggle showing the size and modification time of entries
+    ToggleDetails,
+
+    SelectPrev,
+    SelectNext,
+
+    /// Open a dialogue to create a file or directory
+    BeginCreate(CreateKind),
+
+    /// Delete the selected file or directory
+    DeleteSelected,
+
+    /// Open a dialogue to go to a path, or to a position in the open file
+    BeginGoto,
+
+    /// Type into the open dialogue
+    DialogueInput(char),
+    DialogueBackspace,
+    DialogueCancel,
+    DialogueSubmit,
+
+    /// Save changes to the open file and leave the content widget
+    SaveAndLeave,
+
+    /// Delete the character under the cursor
+    DeleteChar,
+
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+
+    EnterInsert,
+
+    /// Watch the open file for remote updates
+    WatchFile,
+
+    /// Insert a character at the cursor
+    InsertChar(char),
+    InsertBackspace,
+
+    /// Write the inserted text to the file and leave insert mode
+    LeaveInsert,
+
+    /// Resolve the pending conflict with a remote update
+    ResolveConflict(ConflictResolution),
+
+    /// Increment the shared counter on the remote, to check for duplicated requests
+    DebugCounter,
+
+    /// Start or stop recording keys into the macro
+    ToggleMacroRecord,
+
+    /// Replay the recorded macro
+    PlayMacro,
+
+    /// Show the next pending notification or error
+    NextMessage,
+
+    /// Show or hide the latency and throughput of remote calls
+    ToggleStats,
+
+    /// Show the full log file in the content window
+    ShowLogFile,
+}
+
+/// What a create dialogue creates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CreateKind {
+    File,
+    Dir,
+}
+
+impl Action {
+    /// Reduce a key event to an action, given the current state.
+    ///
+    /// Keys that do nothing in the current state return `None`.
+    pub fn from_key(state: &AppState, key: KeyEvent) -> Option<Self> {
+        Command::ALL
+            .iter()
+            .filter(|cmd| cmd.applies_to(state))
+            .find_map(|cmd| cmd.action(key.code))
+    }
+}
+
+/// A key binding: the keys it is bound to, what it does and the states it applies in.
+///
+/// Key events are reduced with the same commands that the commands widget lists,
+/// so the help shown always matches the keys handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    NextMessage,
+    ToggleStats,
+
+    Quit,
+    BrowseFileSystem,
+    GoToContent,
+    EnterContent,
+    GoToFileSystem,
+
+    LeaveFileSystem,
+    OpenSelected,
+    ParentDir,
+    SelectEntry,
+    CreateFile,
+    CreateDir,
+    DeleteSelected,
+    RefreshDir,
+    ToggleDirWatch,
+    ToggleDetails,
+    ShowLogFile,
+    GotoPath,
+    DebugCounter,
+
+    CancelDialogue,
+    SubmitCreate,
+    SubmitGotoPath,
+    SubmitGotoPosition,
+    /// Typing into a dialogue, not listed
+    TypeDialogue,
+
+    SaveAndLeave,
+    EnterInsert,
+    DeleteChar,
+    MoveCursor,
+    WatchFile,
+    GotoPosition,
+
+    RecordMacro,
+    PlayMacro,
+
+    LeaveInsert,
+    /// Typing into the file, not listed
+    TypeInsert,
+
+    KeepMine,
+    TakeRemote,
+    Merge,
+}
+
+impl Command {
+    /// All commands, in the order keys are matched against them
+    pub const ALL: &'static [Command] = &[
+        Self::NextMessage,
+        Self::ToggleStats,
+        Self::Quit,
+        Self::BrowseFileSystem,
+        Self::GoToContent,
+        Self::EnterContent,
+        Self::GoToFileSystem,
+        Self::LeaveFileSystem,
+        Self::OpenSelected,
+        Self::ParentDir,
+        Self::SelectEntry,
+        Self::CreateFile,
+        Self::CreateDir,
+        Self::DeleteSelected,
+        Self::RefreshDir,
+        Self::ToggleDirWatch,
+        Self::ToggleDetails,
+        Self::ShowLogFile,
+        Self::GotoPath,
+        Self::DebugCounter,
+        Self::CancelDialogue,
+        Self::SubmitCreate,
+        Self::SubmitGotoPath,
+        Self::SubmitGotoPosition,
+        Self::TypeDialogue,
+        Self::SaveAndLeave,
+        Self::EnterInsert,
+        Self::DeleteChar,
+        Self::MoveCursor,
+        Self::WatchFile,
+        Self::GotoPosition,
+        Self::RecordMacro,
+        Self::PlayMacro,
+        Self::LeaveInsert,
+        Self::TypeInsert,
+        Self::KeepMine,
+        Self::TakeRemote,
+        Self::Merge,
+    ];
+
+    /// Commands listed in the commands widget for a state, as `(keys, description)`
+    pub fn available(state: &AppState) -> impl Iterator<Item = (String, &'static str)> + '_ {
+        Self::ALL
+            .iter()
+            .filter(move |cmd| cmd.applies_to(state))
+            .filter_map(|cmd| Some((cmd.key_label(), cmd.description()?)))
+    }
+
+    /// Keys bound to the command. Commands that take typed characters have none.
+    pub fn keys(&self) -> &'static [KeyCode] {
+        match self {
+            Self::NextMessage => &[KeyCode::Tab],
+            Self::ToggleStats => &[KeyCode::F(TOGGLE_STATS)],
+            Self::Quit => &[KeyCode::Esc],
+            Self::BrowseFileSystem => &[KeyCode::Enter],
+            Self::GoToContent => &[KeyCode::Right],
+            Self::EnterContent => &[KeyCode::Enter],
+            Self::GoToFileSystem => &[KeyCode::Left],
+            Self::LeaveFileSystem => &[KeyCode::Esc],
+            Self::OpenSelected => &[KeyCode::Enter],
+            Self::ParentDir => &[KeyCode::Backspace],
+            Self::SelectEntry => &[KeyCode::Up, KeyCode::Down],
+            Self::CreateFile => &[KeyCode::Char(FS_CREATE_FILE)],
+            Self::CreateDir => &[KeyCode::Char(FS_CREATE_DIR)],
+            Self::DeleteSelected => &[KeyCode::Char(FS_DELETE)],
+            Self::RefreshDir => &[KeyCode::Char(FS_REFRESH)],
+            Self::ToggleDirWatch => &[KeyCode::Char(FS_WATCH)],
+            Self::ToggleDetails => &[KeyCode::Char(FS_DETAILS)],
+            Self::ShowLogFile => &[KeyCode::Char(FS_LOGS)],
+            Self::GotoPath => &[KeyCode::Char(FS_GOTO)],
+            Self::DebugCounter => &[KeyCode::Char(DEBUG_COUNTER)],
+            Self::CancelDialogue => &[KeyCode::Esc],
+            Self::SubmitCreate | Self::SubmitGotoPath | Self::SubmitGotoPosition => {
+                &[KeyCode::Enter]
+            }
+            Self::TypeDialogue | Self::TypeInsert => &[],
+            Self::SaveAndLeave => &[KeyCode::Esc],
+            Self::EnterInsert => &[KeyCode::Enter],
+            Self::DeleteChar => &[KeyCode::Delete],
+            Self::MoveCursor => &[KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right],
+            Self::WatchFile => &[KeyCode::Char(CONTENT_WATCH)],
+            Self::GotoPosition => &[KeyCode::Char(CONTENT_GOTO)],
+            Self::RecordMacro => &[KeyCode::Char(MACRO_RECORD)],
+            Self::PlayMacro => &[KeyCode::Char(MACRO_PLAY)],
+            Self::LeaveInsert => &[KeyCode::Esc],
+            Self::KeepMine => &[KeyCode::Char(CONFLICT_KEEP_MINE)],
+            Self::TakeRemote => &[KeyCode::Char(CONFLICT_TAKE_REMOTE)],
+            Self::Merge => &[KeyCode::Char(CONFLICT_MERGE)],
+        }
+    }
+
+    /// Description shown in the commands widget, `None` if the command is not listed
+    pub fn description(&self) -> Option<&'static str> {
+        let desc = match self {
+            Self::NextMessage => "next message",
+            Self::ToggleStats => "toggle call stats",
+            Self::Quit => "exit",
+            Self::BrowseFileSystem => "enter filesystem browse",
+            Self::GoToContent => "go to content",
+            Self::EnterContent => "enter content",
+            Self::GoToFileSystem => "filesystem tree",
+            Self::LeaveFileSystem => "exit filesystem browse",
+            Self::OpenSelected => "enter file/dir",
+            Self::ParentDir => "go to parent dir",
+            Self::SelectEntry | Self::MoveCursor => "navigate",
+            Self::CreateFile => "create file",
+            Self::CreateDir => "create directory",
+            Self::DeleteSelected => "delete file/dir",
+            Self::RefreshDir => "refresh directory",
+            Self::ToggleDirWatch => "toggle directory watch",
+            Self::ToggleDetails => "toggle entry details",
+            Self::ShowLogFile => "view log file",
+            Self::GotoPath => "go to path",
+            Self::DebugCounter => "debug: increment counter",
+            Self::CancelDialogue => "cancel",
+            Self::SubmitCreate => "create file/dir",
+            Self::SubmitGotoPath => "go to path",
+            Self::SubmitGotoPosition => "go to position",
+            Self::TypeDialogue | Self::TypeInsert => return None,
+            Self::SaveAndLeave => "exit content",
+            Self::EnterInsert => "enter insert mode",
+            Self::DeleteChar => "delete a character",
+            Self::WatchFile => "watch file for changes",
+            Self::GotoPosition => "go to line[:col] or #offset",
+            Self::RecordMacro => "start/stop recording macro",
+            Self::PlayMacro => "play macro",
+            Self::LeaveInsert => "exit insert mode and save changes",
+            Self::KeepMine => "keep mine",
+            Self::TakeRemote => "take remote",
+            Self::Merge => "merge",
+        };
+
+        Some(desc)
+    }
+
+    /// Returns true if the command can be used in a state
+    pub fn applies_to(&self, state: &AppState) -> bool {
+        let in_create = matches!(
+            state,
+            AppState::InFileSystem(FsState::CreateFile(_) | FsState::CreateDir(_))
+        );
+        let in_goto_path = matches!(state, AppState::InFileSystem(FsState::GotoPath(_)));
+        let in_goto_position = matches!(state, AppState::InContent(ContentState::Goto(_)));
+
+        match self {
+            Self::NextMessage | Self::ToggleStats => true,
+            Self::Quit | Self::BrowseFileSystem | Self::GoToContent => {
+                matches!(state, AppState::OnFileSystem)
+            }
+            Self::EnterContent | Self::GoToFileSystem => matches!(state, AppState::OnContent),
+            Self::LeaveFileSystem
+            | Self::OpenSelected
+            | Self::ParentDir
+            | Self::SelectEntry
+            | Self::CreateFile
+            | Self::CreateDir
+            | Self::DeleteSelected
+            | Self::RefreshDir
+            | Self::ToggleDirWatch
+            | Self::ToggleDetails
+            | Self::ShowLogFile
+            | Self::GotoPath
+            | Self::DebugCounter => matches!(state, AppState::InFileSystem(FsState::Navigate)),
+            Self::CancelDialogue | Self::TypeDialogue => {
+                in_create || in_goto_path || in_goto_position
+            }
+            Self::SubmitCreate => in_create,
+            Self::SubmitGotoPath => in_goto_path,
+            Self::SubmitGotoPosition => in_goto_position,
+            Self::SaveAndLeave
+            | Self::EnterInsert
+            | Self::DeleteChar
+            | Self::MoveCursor
+            | Self::WatchFile
+            | Self::GotoPosition => matches!(state, AppState::InContent(ContentState::Navigate)),
+            Self::RecordMacro | Self::PlayMacro => matches!(
+                state,
+                AppState::InFileSystem(FsState::Navigate)
+                    | AppState::InContent(ContentState::Navigate)
+            ),
+            Self::LeaveInsert | Self::TypeInsert => {
+                matches!(state, AppState::InContent(ContentState::Insert))
+            }
+            Self::KeepMine | Self::TakeRemote | Self::Merge => {
+                matches!(state, AppState::InContent(ContentState::Conflict))
+            }
+        }
+    }
+
+    /// The action a key performs through this command, if the key is bound to it
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        match (self, key) {
+            (Self::TypeDialogue, KeyCode::Char(c)) => return Some(Action::DialogueInput(c)),
+            (Self::TypeDialogue, KeyCode::Backspace) => return Some(Action::DialogueBackspace),
+            (Self::TypeInsert, KeyCode::Char(c)) => return Some(Action::InsertChar(c)),
+            (Self::TypeInsert, KeyCode::Enter) => return Some(Action::InsertChar('\n')),
+            (Self::TypeInsert, KeyCode::Backspace) => return Some(Action::InsertBackspace),
+            _ => (),
+        }
+
+        if !self.keys().contains(&key) {
+            return None;
+        }
+
+        let action = match self {
+            Self::NextMessage => Action::NextMessage,
+            Self::ToggleStats => Action::ToggleStats,
+            Self::Quit => Action::Quit,
+            Self::BrowseFileSystem | Self::EnterContent => Action::Focus(AppEvents::EnterKey),
+            Self::GoToContent => Action::Focus(AppEvents::RightArrowKey),
+            Self::GoToFileSystem => Action::Focus(AppEvents::LeftArrowKey),
+            Self::LeaveFileSystem => Action::Focus(AppEvents::EscKey),
+            Self::OpenSelected => Action::OpenSelected,
+            Self::ParentDir => Action::ParentDir,
+            Self::SelectEntry => match key {
+                KeyCode::Up => Action::SelectPrev,
+                _ => Action::SelectNext,
+            },
+            Self::CreateFile => Action::BeginCreate(CreateKind::File),
+            Self::CreateDir => Action::BeginCreate(CreateKind::Dir),
+            Self::DeleteSelected => Action::DeleteSelected,
+            Self::RefreshDir => Action::RefreshDir,
+            Self::ToggleDirWatch => Action::ToggleDirWatch,
+            Self::ToggleDetails => Action::ToggleDetails,
+            Self::ShowLogFile => Action::ShowLogFile,
+            Self::GotoPath | Self::GotoPosition => Action::BeginGoto,
+            Self::DebugCounter => Action::DebugCounter,
+            Self::CancelDialogue => Action::DialogueCancel,
+            Self::SubmitCreate | Self::SubmitGotoPath | Self::SubmitGotoPosition => {
+                Action::DialogueSubmit
+            }
+            Self::SaveAndLeave => Action::SaveAndLeave,
+            Self::EnterInsert => Action::EnterInsert,
+            Self::DeleteChar => Action::DeleteChar,
+            Self::MoveCursor => match key {
+                KeyCode::Up => Action::CursorUp,
+                KeyCode::Down => Action::CursorDown,
+                KeyCode::Left => Action::CursorLeft,
+                _ => Action::CursorRight,
+            },
+            Self::WatchFile => Action::WatchFile,
+            Self::RecordMacro => Action::ToggleMacroRecord,
+            Self::PlayMacro => Action::PlayMacro,
+            Self::LeaveInsert => Action::LeaveInsert,
+            Self::KeepMine => Action::ResolveConflict(ConflictResolution::KeepMine),
+            Self::TakeRemote => Action::ResolveConflict(ConflictResolution::TakeRemote),
+            Self::Merge => Action::ResolveConflict(ConflictResolution::Merge),
+            // typed keys are handled above
+            Self::TypeDialogue | Self::TypeInsert => return None,
+        };
+
+        Some(action)
+    }
+
+    /// Keys of the command as shown in the commands widget, e.g. `UP/DOWN`
+    pub fn key_label(&self) -> String {
+        self.keys()
+            .iter()
+            .map(|key| match key {
+                KeyCode::Char(c) => c.to_string(),
+                KeyCode::F(n) => format!("F{}", n),
+                KeyCode::Delete => "DEL".to_string(),
+                KeyCode::Backspace => "BACKSPACE".to_string(),
+                other => format!("{:?}", other).to_uppercase(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyModifiers;
+
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_reduce_keys() {
+        let navigate = AppState::InFileSystem(FsState::Navigate);
+        let create = AppState::InFileSystem(FsState::CreateFile(String::new()));
+        let insert = AppState::InContent(ContentState::Insert);
+
+        assert_eq!(
+            Action::from_key(&AppState::OnFileSystem, key(KeyCode::Esc)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Esc)),
+            Some(Action::Focus(AppEvents::EscKey))
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(FS_CREATE_DIR))),
+            Some(Action::BeginCreate(CreateKind::Dir))
+        );
+
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(FS_WATCH))),
+            Some(Action::ToggleDirWatch)
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(FS_DETAILS))),
+            Some(Action::ToggleDetails)
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(FS_LOGS))),
+            Some(Action::ShowLogFile)
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(DEBUG_COUNTER))),
+            Some(Action::DebugCounter)
+        );
+        assert_eq!(
+            Action::from_key(&navigate, key(KeyCode::Char(FS_GOTO))),
+            Some(Action::BeginGoto)
+        );
+        assert_eq!(
+            Action::from_key(
+                &AppState::InFileSystem(FsState::GotoPath(String::new())),
+                key(KeyCode::Char(FS_GOTO))
+            ),
+            Some(Action::DialogueInput(FS_GOTO))
+        );
+
+        // key bindings do not apply while typing
+        assert_eq!(
+            Action::from_key(&create, key(KeyCode::Char(FS_CREATE_DIR))),
+            Some(Action::DialogueInput(FS_CREATE_DIR))
+        );
+        assert_eq!(
+            Action::from_key(&insert, key(KeyCode::Char(CONTENT_WATCH))),
+            Some(Action::InsertChar(CONTENT_WATCH))
+        );
+        assert_eq!(
+            Action::from_key(&insert, key(KeyCode::Char(MACRO_RECORD))),
+            Some(Action::InsertChar(MACRO_RECORD))
+        );
+        assert_eq!(
+            Action::from_key(
+                &AppState::InContent(ContentState::Navigate),
+                key(KeyCode::Char(MACRO_PLAY))
+            ),
+            Some(Action::PlayMacro)
+        );
+        assert_eq!(
+            Action::from_key(
+                &AppState::InContent(ContentState::Navigate),
+                key(KeyCode::Char(CONTENT_GOTO))
+            ),
+            Some(Action::BeginGoto)
+        );
+        assert_eq!(
+            Action::from_key(
+                &AppState::InContent(ContentState::Goto(String::new())),
+                key(KeyCode::Char(CONTENT_WATCH))
+            ),
+            Some(Action::DialogueInput(CONTENT_WATCH))
+        );
+        assert_eq!(
+            Action::from_key(&insert, key(KeyCode::Enter)),
+            Some(Action::InsertChar('\n'))
+        );
+
+        assert_eq!(
+            Action::from_key(&AppState::OnContent, key(KeyCode::Right)),
+            None
+        );
+        assert_eq!(
+            Action::from_key(&insert, key(KeyCode::Tab)),
+            Some(Action::NextMessage)
+        );
+        assert_eq!(
+            Action::from_key(&create, key(KeyCode::F(TOGGLE_STATS))),
+            Some(Action::ToggleStats)
+        );
+        assert_eq!(
+            Action::from_key(
+                &AppState::InContent(ContentState::Conflict),
+                key(KeyCode::Char(CONFLICT_MERGE))
+            ),
+            Some(Action::ResolveConflict(ConflictResolution::Merge))
+        );
+    }
+
+    #[test]
+    fn test_listed_commands_are_handled() {
+        let states = [
+            AppState::OnFileSystem,
+            AppState::OnContent,
+            AppState::InFileSystem(FsState::Navigate),
+            AppState::InFileSystem(FsState::CreateFile(String::new())),
+            AppState::InFileSystem(FsState::CreateDir(String::new())),
+            AppState::InFileSystem(FsState::GotoPath(String::new())),
+            AppState::InContent(ContentState::Navigate),
+            AppState::InContent(ContentState::Insert),
+            AppState::InContent(ContentState::Watch),
+            AppState::InContent(ContentState::Conflict),
+            AppState::InContent(ContentState::Goto(String::new())),
+        ];
+
+        for state in states.iter() {
+            let mut bound = vec![];
+            let listed = Command::ALL
+                .iter()
+                .filter(|cmd| cmd.applies_to(state) && cmd.description().is_some());
+
+            for cmd in listed {
+                for code in cmd.keys() {
+                    // a listed key reaches its own command, and no other listed command
+                    assert_eq!(
+                        Action::from_key(state, key(*code)),
+                        cmd.action(*code),
+                        "{:?} in {:?}",
+                        cmd,
+                        state
+                    );
+                    assert!(cmd.action(*code).is_some());
+                    assert!(
+                        !bound.contains(code),
+                        "{:?} bound twice in {:?}",
+                        code,
+                        state
+                    );
+                    bound.push(*code);
+                }
+            }
+        }
+
+        let navigate: Vec<_> =
+            Command::available(&AppState::InFileSystem(FsState::Navigate)).collect();
+        assert!(navigate.contains(&("UP/DOWN".to_string(), "navigate")));
+        assert!(navigate.contains(&("F2".to_string(), "toggle call stats")));
+        assert!(
+            Command::available(&AppState::InContent(ContentState::Insert))
+                .all(|(keys, _)| keys != "ENTER")
+        );
+    }
+}
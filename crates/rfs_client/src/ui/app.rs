@@ -2,42 +2,72 @@
 //!
 //! For simplicity, only single key events are handled here (no modifiers).
 
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
-use std::{collections::HashMap, default, io};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    default, io,
+};
 
 use async_trait::async_trait;
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
-use rfs::fs::VirtFile;
+use rfs::fs::{MergeResult, TextEdit, VirtFile, VirtPath, WatchEvent};
 use rfs::fsm::TransitableState;
-use rfs::interfaces::FileUpdate;
-use rfs::{fs::VirtReadDir, middleware::ContextManager, state_transitions};
-use tokio::sync::Mutex;
-
+use rfs::interfaces::{
+    CounterOpsClient, FileUpdate, FileUpdateNotice, OpenMode, PresenceOpsClient, Watcher,
+};
+use rfs::{
+    fs::VirtReadDir,
+    middleware::{ContextManager, InvokeError, InvokeProgress, VersionInfo, VersionMatch},
+    path_policy::{PathPolicy, PathViolation},
+    state_transitions,
+};
+use rfs_client_core::RemoteFs;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::args::ConflictResolution;
+
+use super::action::{Action, CreateKind};
 use super::contents;
+use super::key_macro::MacroKey;
+use super::messages::{Message, MessageQueue, Severity};
+use super::progress::with_progress;
+use super::session::Session;
+use super::tasks::{TaskPurpose, TaskRegistry};
+use super::theme::FsTheme;
 use super::tui::{AppEvent, FocusedWidget, Tui};
+use super::widgets::{StatsOverlay, StderrLogs};
 
-const FS_CREATE_FILE: char = 'f';
-const FS_CREATE_DIR: char = 'd';
-const FS_DELETE: char = 'x';
+/// Time given to background tasks to exit before they are aborted
+const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
 
-// feature not impl'd
-const FS_RENAME: char = 'r';
+/// Interval between checks for changes to the current directory
+const DIR_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
-/// Trait for handling application state.
-///
-/// ```ignore
-/// #[async_trait]
-/// impl HandleStateEvent for AppState {
-///     async fn handle_event(&mut self, event: KeyEvent, app_data: &mut AppData, tui: &mut Tui) {
-///         todo!()
-///     }
-/// }
-/// ```
-#[async_trait]
-trait HandleStateEvent {
-    async fn handle_event(&mut self, event: KeyEvent, app_data: &mut AppData, tui: &mut Tui);
-}
+/// Interval between checks for changes to the current directory while it is watched
+const DIR_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long entries added to a watched directory are highlighted
+const NEW_ENTRY_HIGHLIGHT_DURATION: Duration = Duration::from_secs(2);
+
+/// How long messages published to subscribed topics are shown
+const TOPIC_MESSAGE_DURATION: Duration = Duration::from_secs(5);
+
+/// Number of entries whose details are read in one remote call
+const DETAILS_PAGE_SIZE: usize = 64;
+
+/// Number of entries listed when entering a directory, and each time the selection passes them
+const LS_PAGE_SIZE: usize = 200;
+
+/// Shown in the title of the content window for read-only files
+const READONLY_INDICATOR: &str = "🔒 read-only";
+
+/// Title of the go to path dialogue
+const GOTO_TITLE: &str = "go to path";
+
+/// Title of the go to position prompt of the content window
+const CONTENT_GOTO_TITLE: &str = "go to line[:col] or #offset";
 
 /// Application state
 // #[derive(Debug)]
@@ -78,6 +108,15 @@ pub struct App {
 
     /// State history. Not sure if this is required.
     state_stack: FixedSizeStack<AppState>,
+
+    /// Where the session is saved on exit and restored from on start
+    session_path: Option<PathBuf>,
+
+    /// Styles of filesystem entries
+    theme: FsTheme,
+
+    /// Number of lines kept in the logs widget
+    max_log_lines: usize,
 }
 
 // q: how can I have a struct field be a reference to another field in the same struct?
@@ -93,8 +132,11 @@ pub struct AppData {
     // current selection idx in the filsystem
     filesystem_pos: usize,
 
-    /// Previously opened virtual files
-    v_file_history: HashMap<String, Arc<Mutex<VirtFile>>>,
+    /// Directories that are not listed in full, and the cursor continuing each listing
+    dir_cursors: HashMap<String, String>,
+
+    /// Remote filesystem, caching previously opened virtual files
+    remote: RemoteFs,
 
     /// Current open virtual file in the content window
     v_file: Option<Arc<Mutex<VirtFile>>>,
@@ -105,6 +147,9 @@ pub struct AppData {
     /// Cursor position in the contents widget
     cursor_pos: Option<usize>,
 
+    /// Last cursor position in each file that was opened, by normalized path
+    cursors: BTreeMap<String, usize>,
+
     /// A continuous unbroken string sequence that has not been written to the file.
     ///
     /// Offset is taken from unsaved_offset
@@ -114,6 +159,45 @@ pub struct AppData {
 
     /// Error message to overlay on the screen
     err_msg: Option<String>,
+
+    /// Background tasks, cancelled on exit
+    tasks: TaskRegistry,
+
+    /// How remote updates that overlap with unsaved edits are resolved
+    conflict_resolution: ConflictResolution,
+
+    /// Remote update waiting for the user to resolve a conflict
+    pending_update: Option<FileUpdateNotice>,
+
+    /// Retry progress of remote calls
+    progress: broadcast::Receiver<InvokeProgress>,
+
+    /// Existing file the user was warned about, and may overwrite by submitting again
+    overwrite_warned: Option<String>,
+
+    /// Read the current directory again as soon as it changes on the remote
+    watch_dir: bool,
+
+    /// Show the size and modification time of entries
+    show_details: bool,
+
+    /// Directory and change counter the shown details were read for
+    details_of: Option<(String, u64)>,
+
+    /// Keys recorded so far, while a macro is being recorded
+    recording: Option<Vec<MacroKey>>,
+
+    /// Last recorded macro, replayed on request
+    key_macro: Vec<MacroKey>,
+
+    /// Notifications and errors waiting to be shown
+    messages: MessageQueue,
+
+    /// The open file cannot be written to
+    readonly: bool,
+
+    /// Local file logs are sent to, if any
+    log_file: Option<PathBuf>,
 }
 
 /// An (optionally) fixed size stack of elements
@@ -156,6 +240,12 @@ pub enum ContentState {
 
     /// File watch
     Watch,
+
+    /// A remote update conflicts with unsaved edits
+    Conflict,
+
+    /// Typing a position to move the cursor to
+    Goto(String),
 }
 
 /// Filesystem inner state
@@ -167,10 +257,13 @@ pub enum FsState {
     CreateFile(String),
 
     CreateDir(String),
+
+    /// Typing a path to go to
+    GotoPath(String),
 }
 
 /// App events are a subset of [KeyEvent]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum AppEvents {
     EnterKey,
     EscKey,
@@ -179,57 +272,52 @@ pub enum AppEvents {
     Char(char),
 }
 
-// impl TransitableState for AppState {
-//     type Event = AppEvents;
-
-//     fn ingest(&mut self, event: Self::Event) {
-//         match (&self, event) {
-//             (Self::OnContent, AppEvents::EnterKey) => {
-//                 *self = Self::InContent(ContentState::default())
-//             }
-//             (Self::InContent(_), AppEvents::EscKey) => *self = Self::OnContent,
-
-//             (Self::OnContent, AppEvents::LeftArrowKey) => *self = Self::OnFileSystem,
-//             (Self::OnFileSystem, AppEvents::RightArrowKey) => *self = Self::OnContent,
-
-//             (Self::OnFileSystem, AppEvents::EnterKey) => {
-//                 *self = Self::InFileSystem(FsState::default())
-//             }
-//             (Self::InFileSystem(_), AppEvents::EscKey) => *self = Self::OnFileSystem,
-
-//             _ => (),
-//         }
-//     }
-// }
-
-// impl TransitableState for ContentState {
-//     type Event = AppEvents;
+/// Widget focus, without the inner state of the focused widget
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Focus {
+    #[default]
+    OnContent,
+    InContent,
+    OnFileSystem,
+    InFileSystem,
+}
 
-//     fn ingest(&mut self, event: Self::Event) {
-//         match (self, event) {
-//             (Self::Insert, AppEvents::EnterKey) => (),
-//             (Self::Insert, AppEvents::EscKey) => (),
-//             _ => (),
-//         }
-//     }
-// }
+state_transitions! {
+    type State = Focus;
+    type Event = AppEvents;
 
-// state_transitions! {
-//     type State = AppState;
-//     type Event = AppEvents;
+    OnContent + EnterKey => InContent;
+    InContent + EscKey => OnContent;
 
-//     OnContent + EnterKey => InContent;
-//     InContent + EscKey => OnContent;
+    OnContent + LeftArrowKey => OnFileSystem;
+    OnFileSystem + RightArrowKey => OnContent;
 
-//     OnContent + LeftArrowKey => OnFileSystem;
-//     OnFileSystem + RightArrowKey => OnContent;
+    OnFileSystem + EnterKey => InFileSystem;
+    InFileSystem + EscKey => OnFileSystem;
+}
 
-//     OnFileSystem + EnterKey => InFileSystem;
-//     InFileSystem + EscKey => OnFileSystem;
+impl AppState {
+    pub fn focus(&self) -> Focus {
+        match self {
+            AppState::OnContent => Focus::OnContent,
+            AppState::InContent(_) => Focus::InContent,
+            AppState::OnFileSystem => Focus::OnFileSystem,
+            AppState::InFileSystem(_) => Focus::InFileSystem,
+        }
+    }
+}
 
-//     // error ack
-//     // Error + EnterKey => OnFileSystem;
-// }
+// entering a widget starts from its default state
+impl From<Focus> for AppState {
+    fn from(value: Focus) -> Self {
+        match value {
+            Focus::OnContent => AppState::OnContent,
+            Focus::InContent => AppState::InContent(Default::default()),
+            Focus::OnFileSystem => AppState::OnFileSystem,
+            Focus::InFileSystem => AppState::InFileSystem(Default::default()),
+        }
+    }
+}
 
 // if key event can be translated into a state event, then handle the state event.
 //
@@ -254,10 +342,13 @@ impl App {
         tick_rate: f64,
         frame_rate: f64,
         shh: Box<dyn io::Read + Send + 'static>,
+        conflict_resolution: ConflictResolution,
+        session_path: Option<PathBuf>,
+        theme: FsTheme,
     ) -> Self {
         Self {
             exit: false,
-            data: AppData::new(ctx),
+            data: AppData::new(ctx, conflict_resolution),
             sh: Arc::new(std::sync::Mutex::new(shh)),
             state: Default::default(),
             state_stack: {
@@ -265,15 +356,27 @@ impl App {
                 stack.push(Default::default());
                 stack
             },
+            session_path,
+            theme,
+            max_log_lines: 100,
         }
     }
 
+    /// Keep a number of log lines in the logs widget, and view the file logs are sent to.
+    pub fn with_logs(mut self, max_lines: usize, log_file: Option<PathBuf>) -> Self {
+        self.max_log_lines = max_lines;
+        self.data.log_file = log_file;
+        self
+    }
+
     /// This is the main application loop.
     /// A [Tui] is instantiated here and used to render the UI.
     pub async fn run(&mut self) -> io::Result<()> {
         let mut tui = Tui::new(60.0, 4.0, self.sh.clone())?;
+        tui.logs_widget = StderrLogs::with_capacity(self.max_log_lines);
+        tui.fs_widget.set_theme(self.theme.clone());
         tui.enter()?;
-        tui.start();
+        tui.start(&mut self.data.tasks);
 
         // tui.draw(f);
         while let Some(event) = tui.next().await {
@@ -285,18 +388,27 @@ impl App {
                 }
                 AppEvent::Quit => {
                     log::debug!("quit event received");
+                    self.save_session().await;
                     tui.stop();
                     tui.exit()?;
                     break;
                 }
-                // TODO: refac to Option<String>
-                AppEvent::Error(e) => {
-                    tui.content_widget.set_error_message(e);
-
-                    // tui.event_tx.send(AppEvent::Render);
+                AppEvent::Message(message) => {
+                    self.data.messages.push(message);
+                    self.data.show_messages(&mut tui);
+                }
+                AppEvent::Closed => {
+                    self.save_session().await;
+                    break;
+                }
+                AppEvent::Tick => {
+                    if self.data.messages.expire(Instant::now()) {
+                        self.data.show_messages(&mut tui);
+                    }
+                    self.data.update_stats(&mut tui);
+                    tui.draw_to_screen().await?
                 }
-                AppEvent::Closed => break,
-                AppEvent::Tick | AppEvent::Render | AppEvent::Resize(_, _) => {
+                AppEvent::Render | AppEvent::Resize(_, _) => {
                     // tui.logs_widget.update_logs();
                     tui.draw_to_screen().await?
                 }
@@ -307,24 +419,63 @@ impl App {
                 }
                 AppEvent::Key(key_event) => {
                     self.data
-                        .handle_app_state(&mut self.state, key_event, &mut tui)
+                        .handle_key(&mut self.state, key_event, &mut tui)
                         .await;
 
                     // tui.event_tx.send(AppEvent::Render).unwrap();
                 }
                 AppEvent::Mouse(_) => (),
-                AppEvent::SetContentNotification(notif) => {
-                    tui.content_widget.set_notification(notif);
-                }
                 AppEvent::HighlightContent(content) => match content {
                     Some((offset, len)) => {
                         tui.content_widget.set_highlight(offset, len);
                     }
                     None => tui.content_widget.clear_highlight(),
                 },
+                AppEvent::DirChanged {
+                    path,
+                    change_counter,
+                } => {
+                    if let Some((dir, read_dir)) = self.data.fs_dirs.top() {
+                        if dir == &path && read_dir.change_counter != change_counter {
+                            log::debug!("directory {} changed on remote", path);
+                            match self.data.watch_dir {
+                                true => {
+                                    self.data.apply_dir_change(&mut tui).await;
+                                    self.data.refresh_details(&mut tui).await;
+                                }
+                                false => tui.fs_widget.set_stale(true),
+                            }
+                        }
+                    }
+                }
+                AppEvent::DirEntryChanged { path, event } => {
+                    if let Some((dir, _)) = self.data.fs_dirs.top() {
+                        if dir == &path {
+                            log::debug!("entry of {} changed on remote: {:?}", path, event);
+                            self.data.apply_dir_change(&mut tui).await;
+                            self.data.refresh_details(&mut tui).await;
+                        }
+                    }
+                }
+                AppEvent::HighlightEntries(paths) => tui.fs_widget.highlight_entries(paths),
+                AppEvent::TopicMessage(msg) => {
+                    log::info!("message from {}: {}", msg.topic, msg.message);
+                    Self::show_notification(
+                        format!("[{}] {}", msg.topic, msg.message),
+                        TOPIC_MESSAGE_DURATION,
+                        &tui,
+                    );
+                }
                 AppEvent::FileUpdate { path, upd } => {
                     log::debug!("file update event for: {:?}", path);
-                    //
+
+                    // the update also changes the directory containing the file
+                    if let Some((dir, _)) = self.data.fs_dirs.top() {
+                        if is_parent_dir(dir, &path) {
+                            tui.fs_widget.set_stale(true);
+                        }
+                    }
+
                     let v_file = match &self.data.v_file {
                         Some(vf) => vf,
                         // ignore
@@ -332,55 +483,31 @@ impl App {
                     };
 
                     log::debug!("acquiring lock on current vfile");
-                    let mut lock = v_file.lock().await;
-                    let upd_dur = Duration::from_secs(2);
-                    match &lock.as_path() == &path {
+                    let lock = v_file.lock().await;
+                    match lock.as_path() == path {
                         // curr file is being updated
                         true => {
-                            lock.update_bytes(upd.clone());
-                            let upd_contents = std::str::from_utf8(lock.local_cache()).unwrap();
-
-                            // clear notif
-                            tui.content_widget.set_notification(Option::<&str>::None);
-                            self.data.content = Some(upd_contents.to_string());
-                            self.data.unsaved_buf.clear();
-                            self.data.unsaved_offset = self.data.cursor_pos.unwrap_or_default();
-
-                            tui.content_widget.set_contents(Some(upd_contents));
-
-                            match &upd {
-                                FileUpdate::Append(data) => Self::show_highlight(
-                                    lock.local_cache().len(),
-                                    data.len(),
-                                    upd_dur,
-                                    &tui,
-                                ),
-                                FileUpdate::Insert((offset, data)) => {
-                                    log::info!(
-                                        "file insertion of len {} at offset {}",
-                                        data.len(),
-                                        offset
-                                    );
-                                    Self::show_highlight(*offset, data.len(), upd_dur, &tui)
-                                }
-                                FileUpdate::Overwrite(data) => {
-                                    Self::show_highlight(0, data.len(), upd_dur, &tui)
-                                }
-                            }
+                            drop(lock);
+                            self.data
+                                .apply_file_update(&mut self.state, upd, &mut tui)
+                                .await;
+                            self.data
+                                .update_presence(open_mode(&self.state), &mut tui)
+                                .await;
                         }
                         // search for other files in lookup and update it
-                        false => match self.data.v_file_history.get(&path) {
+                        false => match self.data.remote.cached(&path) {
                             Some(vf) => {
                                 log::debug!("updating file in history");
                                 let mut map_lock = vf.lock().await;
 
-                                Self::show_notification(
-                                    format!("{} updated", &path),
-                                    Duration::from_secs(2),
-                                    &tui,
-                                );
-
-                                map_lock.update_bytes(upd);
+                                if map_lock.update_bytes(&upd) {
+                                    Self::show_notification(
+                                        format!("{} updated", &path),
+                                        Duration::from_secs(2),
+                                        &tui,
+                                    );
+                                }
                             }
                             None => (),
                         },
@@ -392,6 +519,8 @@ impl App {
             }
         }
 
+        self.data.tasks.shutdown(TASK_SHUTDOWN_TIMEOUT).await;
+
         Ok(())
     }
 
@@ -407,735 +536,1677 @@ impl App {
 
         tui.fs_widget.push(start_dir_entry, ".");
         tui.title_widget.set_title(Some("rfs_client"));
+        tui.title_widget.set_warning(version_warning(
+            self.data.ctx.local_version(),
+            self.data.ctx.remote_version(),
+        ));
         tui.in_filesystem();
-    }
-
-    /// Show a notification message on the content window for a specified duration,
-    /// and then toggle it off.
-    fn show_notification<M: ToString>(msg: M, dur: Duration, tui: &Tui) {
-        let ev_chan = tui.event_tx.clone();
-        let message = msg.to_string();
-
-        tokio::spawn(async move {
-            ev_chan
-                .send(super::tui::AppEvent::SetContentNotification(Some(
-                    message.to_string(),
-                )))
-                .unwrap();
 
-            tokio::time::sleep(dur).await;
+        if let Some(path) = &self.session_path {
+            match Session::load(path) {
+                Ok(session) => self.data.restore(session, tui).await,
+                Err(e) => log::debug!("no session restored from {:?}: {}", path, e),
+            }
+        }
 
-            ev_chan
-                .send(super::tui::AppEvent::SetContentNotification(None))
-                .unwrap();
-        });
+        self.data.update_disk_usage(tui).await;
+        self.data.poll_dir_changes(tui);
+        self.data.subscribe(rfs::topics::SERVER_TOPIC, tui);
     }
 
-    /// Show a highlight on the content widget for a specified duration,
-    /// and then toggle it off.
-    fn show_highlight(offset: usize, len: usize, dur: Duration, tui: &Tui) {
-        let ev_chan = tui.event_tx.clone();
-
-        tokio::spawn(async move {
-            ev_chan
-                .send(AppEvent::HighlightContent(Some((offset, len))))
-                .unwrap();
-
-            tokio::time::sleep(dur).await;
+    /// Save the current session, if enabled
+    async fn save_session(&self) {
+        let path = match &self.session_path {
+            Some(p) => p,
+            None => return,
+        };
 
-            ev_chan.send(AppEvent::HighlightContent(None))
-        });
+        if let Err(e) = self.data.session().await.save(path) {
+            log::error!("failed to save session to {:?}: {}", path, e);
+        }
     }
 
-    fn show_error_message<M: ToString>(msg: M, dur: Duration, tui: &Tui) {
-        let ev_chan = tui.event_tx.clone();
-        let message = msg.to_string();
-
-        tokio::spawn(async move {
-            log::debug!("sending err msg to channel");
-            ev_chan.send(AppEvent::Error(Some(message))).unwrap();
+    /// Queue a notification message, shown on the content window for a specified duration.
+    fn show_notification<M: ToString>(msg: M, dur: Duration, tui: &Tui) {
+        let message = Message::new(Severity::Info, msg).with_duration(dur);
+        tui.event_tx.send(AppEvent::Message(message)).unwrap();
+    }
 
-            tokio::time::sleep(dur).await;
+    /// Queue a warning, shown in a pop-up
+    fn show_warning<M: ToString>(msg: M, tui: &Tui) {
+        let message = Message::new(Severity::Warning, msg);
+        tui.event_tx.send(AppEvent::Message(message)).unwrap();
+    }
 
-            log::debug!("clearing err msg");
-            ev_chan.send(AppEvent::Error(None)).unwrap();
-        });
+    /// Queue an error, shown in a pop-up
+    fn show_error_message<M: ToString>(msg: M, tui: &Tui) {
+        let message = Message::new(Severity::Error, msg);
+        tui.event_tx.send(AppEvent::Message(message)).unwrap();
     }
 }
 
 impl AppData {
-    pub fn new(ctx: ContextManager) -> Self {
+    pub fn new(ctx: ContextManager, conflict_resolution: ConflictResolution) -> Self {
         Self {
+            progress: ctx.progress(),
+            remote: RemoteFs::new(ctx.clone()),
             ctx,
             fs_dirs: FixedSizeStack::new(None),
             filesystem_pos: 0,
-            v_file_history: Default::default(),
+            dir_cursors: HashMap::new(),
             v_file: None,
             content: None,
             cursor_pos: None,
+            cursors: BTreeMap::new(),
             unsaved_buf: Default::default(),
             unsaved_offset: 0,
             err_msg: None,
+            tasks: TaskRegistry::new(),
+            conflict_resolution,
+            pending_update: None,
+            overwrite_warned: None,
+            watch_dir: false,
+            show_details: false,
+            details_of: None,
+            recording: None,
+            key_macro: Vec::new(),
+            messages: MessageQueue::default(),
+            readonly: false,
+            log_file: None,
         }
     }
 
-    /// Top-level state handelr
-    pub async fn handle_app_state(
-        &mut self,
-        app_state: &mut AppState,
-        app_ev: KeyEvent,
-        tui: &mut Tui,
-    ) {
-        match app_state {
-            AppState::OnContent => match app_ev.code {
-                KeyCode::Enter => {
-                    *app_state = AppState::InContent(ContentState::default());
-                    tui.in_content_navi();
-                }
-                KeyCode::Left => {
-                    *app_state = AppState::OnFileSystem;
-                    tui.on_filesystem();
-                }
-                _ => (),
-            },
-            AppState::InContent(_) => {
-                self.handle_content_state(app_state, app_ev, tui).await;
+    /// Reduce a key event to an action and apply it
+    pub async fn handle_key(&mut self, app_state: &mut AppState, key: KeyEvent, tui: &mut Tui) {
+        let action = match Action::from_key(app_state, key) {
+            Some(a) => a,
+            None => return,
+        };
+
+        match action {
+            Action::ToggleMacroRecord => self.toggle_macro_record(tui),
+            Action::NextMessage => {
+                self.messages.page();
+                self.show_messages(tui);
             }
-            AppState::OnFileSystem => match app_ev.code {
-                KeyCode::Esc => {
-                    tui.event_tx.send(AppEvent::Quit).unwrap();
-                }
-                KeyCode::Enter => {
-                    *app_state = AppState::InFileSystem(FsState::default());
-                    tui.in_filesystem();
-                }
-                KeyCode::Right => {
-                    *app_state = AppState::OnContent;
-                    tui.on_content();
+            Action::ToggleStats => {
+                tui.stats_widget = match tui.stats_widget.take() {
+                    Some(_) => None,
+                    None => Some(StatsOverlay::new()),
+                };
+                self.update_stats(tui);
+            }
+            Action::PlayMacro => self.play_macro(app_state, tui).await,
+            action => {
+                if let (Some(keys), Some(macro_key)) =
+                    (&mut self.recording, MacroKey::from_event(key))
+                {
+                    keys.push(macro_key);
                 }
-                _ => (),
-            },
-            AppState::InFileSystem(_) => {
-                self.handle_fs_state(app_state, app_ev, tui).await;
+
+                log::debug!("applying {:?}", action);
+                self.apply(app_state, action, tui).await;
             }
         }
+        self.refresh_details(tui).await;
     }
 
-    pub async fn handle_fs_state(
-        &mut self,
-        app_state: &mut AppState,
-        app_ev: KeyEvent,
-        tui: &mut Tui,
-    ) {
-        let fs_state = if let AppState::InFileSystem(s) = app_state {
-            s
-        } else {
-            return;
+    /// Refresh the stats overlay, if shown
+    fn update_stats(&self, tui: &mut Tui) {
+        if let Some(stats) = &mut tui.stats_widget {
+            stats.set_stats(
+                &self.ctx.stats(),
+                self.ctx.live_callback_sockets(),
+                Instant::now(),
+            );
+        }
+    }
+
+    /// Start recording a macro, or stop and keep the recorded one.
+    fn toggle_macro_record(&mut self, tui: &mut Tui) {
+        let msg = match self.recording.take() {
+            Some(keys) => {
+                let msg = format!("recorded macro of {} keys", keys.len());
+                self.key_macro = keys;
+                msg
+            }
+            None => {
+                self.recording = Some(Vec::new());
+                "recording macro".to_string()
+            }
         };
 
-        match fs_state {
-            FsState::Navigate => match app_ev.code {
-                KeyCode::Esc => {
-                    *app_state = AppState::OnFileSystem;
-                    tui.on_filesystem();
-                    return;
-                }
-                KeyCode::Enter => {
-                    let top_dir_entry = self.fs_dirs.top().cloned();
+        App::show_notification(msg, Duration::from_secs(2), tui);
+    }
 
-                    let dir_entry = match &top_dir_entry {
-                        Some((dir, read_dir)) => match read_dir.get(self.filesystem_pos) {
-                            Some(entry) => entry,
-                            None => return,
-                        },
-                        None => return,
-                    };
+    /// Replay the recorded macro, reducing each key in the state it leads to.
+    ///
+    /// Keys that do nothing in the state reached are skipped.
+    async fn play_macro(&mut self, app_state: &mut AppState, tui: &mut Tui) {
+        if self.recording.is_some() {
+            App::show_warning("cannot play a macro while recording", tui);
+            return;
+        }
 
-                    match dir_entry.is_file() {
-                        // open file
-                        true => {
-                            let path = dir_entry.path.clone();
-
-                            let v_file = match self.v_file_history.get(path.as_str()) {
-                                Some(v_file) => v_file.clone(),
-                                None => {
-                                    let v_file = match VirtFile::open(self.ctx.clone(), &path).await
-                                    {
-                                        Ok(vf) => Arc::new(Mutex::new(vf)),
-                                        Err(e) => {
-                                            log::error!("virtual file open error: {:?}", e);
-                                            App::show_error_message(e, Duration::from_secs(2), tui);
-                                            return;
-                                        }
-                                    };
-                                    self.v_file_history.insert(path, v_file.clone());
-
-                                    v_file
-                                }
-                            };
-
-                            self.v_file = Some(v_file.clone());
-                            self.content = Some(
-                                std::str::from_utf8(v_file.lock().await.local_cache())
-                                    .unwrap()
-                                    .to_string(),
-                            );
-                            tui.content_widget
-                                .set_contents(Some(self.content.clone().unwrap_or_default()));
-                            tui.content_widget.set_cursor_pos(Some((0, 0)));
-                            *app_state = AppState::InContent(Default::default());
-                            tui.in_content_navi();
-
-                            //
-                        }
-                        // read dir and recurse
-                        false => {
-                            let path = dir_entry.path.clone();
-                            match rfs::fs::read_dir(self.ctx.clone(), &path).await {
-                                Ok(read_dir) => {
-                                    let entry = (path, read_dir.clone());
-                                    self.fs_dirs.push(entry);
-                                    self.filesystem_pos = 0;
-                                    tui.fs_widget.push(
-                                        read_dir,
-                                        dir_entry.path().file_name().unwrap_or_default(),
-                                    );
-                                    tui.fs_widget.select(Some(self.filesystem_pos));
-                                }
-                                Err(e) => {
-                                    log::error!("Read dir error: {:?}", e);
-                                    App::show_error_message(e, Duration::from_secs(2), tui);
-                                    return;
-                                }
-                            };
-                        }
-                    }
+        for macro_key in self.key_macro.clone() {
+            match Action::from_key(app_state, macro_key.to_event()) {
+                Some(
+                    Action::ToggleMacroRecord
+                    | Action::PlayMacro
+                    | Action::NextMessage
+                    | Action::ToggleStats,
+                )
+                | None => continue,
+                Some(action) => {
+                    log::debug!("replaying {:?}", action);
+                    self.apply(app_state, action, tui).await;
                 }
-                /// Go up one dir (if possible)
-                KeyCode::Backspace => match self.fs_dirs.depth() > 1 {
-                    true => {
-                        self.fs_dirs.pop();
-                        tui.fs_widget.pop();
-
-                        let dir_path = self.fs_dirs.pop().unwrap();
-                        let read_dir = rfs::fs::read_dir(self.ctx.clone(), dir_path.0.clone())
-                            .await
-                            .unwrap();
+            }
+        }
+    }
 
-                        self.fs_dirs.push((dir_path.0.clone(), read_dir.clone()));
-                        tui.fs_widget.update(read_dir);
+    /// Apply an action to the app state
+    pub async fn apply(&mut self, app_state: &mut AppState, action: Action, tui: &mut Tui) {
+        let content_navigate = matches!(app_state, AppState::InContent(ContentState::Navigate));
 
-                        self.filesystem_pos = 0;
-                        tui.fs_widget.select(Some(self.filesystem_pos));
-                    }
-                    false => (),
-                },
-                KeyCode::Up => {
-                    self.filesystem_pos = self.filesystem_pos.saturating_sub(1);
-                    tui.fs_widget.select(Some(self.filesystem_pos));
-                }
-                KeyCode::Down => {
-                    self.filesystem_pos = match self.fs_dirs.top() {
-                        Some(dir) => match dir.1.get(self.filesystem_pos + 1) {
-                            Some(_) => self.filesystem_pos + 1,
-                            None => self.filesystem_pos,
-                        },
-                        None => 0,
-                    };
+        if let AppState::InContent(ContentState::Insert) = app_state {
+            self.cursor_pos.get_or_insert(0);
+        }
 
+        match action {
+            Action::Focus(event) => self.change_focus(app_state, event, tui),
+            Action::Quit => tui.event_tx.send(AppEvent::Quit).unwrap(),
+
+            Action::OpenSelected => self.open_selected(app_state, tui).await,
+            Action::ParentDir => self.parent_dir(tui).await,
+            Action::RefreshDir => {
+                if self.reload_dir(tui).await {
+                    self.filesystem_pos = 0;
                     tui.fs_widget.select(Some(self.filesystem_pos));
                 }
-                KeyCode::Char(FS_CREATE_FILE) => {
-                    *fs_state = FsState::CreateFile(String::new());
-                    tui.in_filesystem_create("create file");
+                self.update_disk_usage(tui).await;
+            }
+            Action::ToggleDirWatch => {
+                self.watch_dir = !self.watch_dir;
+                let msg = match self.watch_dir {
+                    true => "directory watch enabled",
+                    false => "directory watch disabled",
+                };
+                App::show_notification(msg, Duration::from_secs(2), tui);
+
+                // poll at the interval of the new mode
+                self.poll_dir_changes(tui);
+            }
+            Action::ToggleDetails => {
+                self.show_details = !self.show_details;
+                if !self.show_details {
+                    self.details_of = None;
+                    tui.fs_widget.set_details(None);
                 }
-                KeyCode::Char(FS_CREATE_DIR) => {
-                    *fs_state = FsState::CreateDir(String::new());
-                    tui.in_filesystem_create("create dir");
+            }
+            Action::SelectPrev => {
+                self.filesystem_pos = self.filesystem_pos.saturating_sub(1);
+                tui.fs_widget.select(Some(self.filesystem_pos));
+            }
+            Action::SelectNext => {
+                let loaded = self.fs_dirs.top().map(|(_, read_dir)| read_dir.len());
+                if loaded == Some(self.filesystem_pos + 1) {
+                    self.list_next_page(tui).await;
                 }
-                KeyCode::Char(FS_DELETE) => {
-                    let top_dir_entry = self.fs_dirs.top().cloned();
-
-                    let dir_entry = match &top_dir_entry {
-                        Some((dir, read_dir)) => match read_dir.get(self.filesystem_pos) {
-                            Some(entry) => entry,
-                            None => return,
-                        },
-                        None => return,
-                    };
-
-                    let path = dir_entry.path.clone();
-                    match dir_entry.is_file() {
-                        true => match rfs::fs::remove_file(self.ctx.clone(), &path).await {
-                            Ok(_) => {
-                                App::show_notification(
-                                    format!("deleted file: {}", path),
-                                    Duration::from_secs(2),
-                                    tui,
-                                );
-
-                                // clear the screen if the file is displayed there
-                                if let Some(vf) = &self.v_file {
-                                    if &vf.lock().await.as_path() == &path {
-                                        self.v_file = None;
-                                        self.content = None;
-                                        self.unsaved_buf.clear();
-                                        self.unsaved_offset = 0;
-                                        tui.content_widget.set_contents(Option::<&str>::None);
-                                        tui.content_widget.set_cursor_offset(0);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                log::error!("remove file error: {:?}", e);
-                                App::show_error_message(
-                                    format!("{:?}", e),
-                                    Duration::from_secs(2),
-                                    tui,
-                                );
-                                return;
-                            }
-                        },
-                        false => match rfs::fs::remove_dir(self.ctx.clone(), &path).await {
-                            Ok(_) => {
-                                App::show_notification(
-                                    format!("deleted dir: {}", path),
-                                    Duration::from_secs(2),
-                                    tui,
-                                );
-                            }
-                            Err(e) => {
-                                log::error!("remove dir error: {:?}", e);
-                                App::show_error_message(
-                                    format!("{:?}", e),
-                                    Duration::from_secs(2),
-                                    tui,
-                                );
-                            }
-                        },
-                    }
-
-                    let read_dir =
-                        match rfs::fs::read_dir(self.ctx.clone(), &self.fs_dirs.top().unwrap().0)
-                            .await
-                        {
-                            Ok(rd) => rd,
-                            Err(e) => {
-                                log::error!("Read dir error: {:?}", e);
-                                App::show_error_message(e, Duration::from_secs(2), tui);
-                                return;
-                            }
-                        };
-
-                    tui.fs_widget.update(read_dir.clone());
-                    let p = self.fs_dirs.pop().expect("fs dirs should not be empty").0;
-                    self.fs_dirs.push((p, read_dir));
 
-                    tui.fs_widget.select(Some(self.filesystem_pos));
-                }
+                self.filesystem_pos = match self.fs_dirs.top() {
+                    Some(dir) => match dir.1.get(self.filesystem_pos + 1) {
+                        Some(_) => self.filesystem_pos + 1,
+                        None => self.filesystem_pos,
+                    },
+                    None => 0,
+                };
 
-                _ => (),
-            },
-            FsState::CreateFile(buf) => {
-                match app_ev.code {
-                    KeyCode::Esc => {
-                        log::debug!("esc key pressed. cancelling file creation");
-
-                        // clear dialogue
-                        tui.fs_widget
-                            .dialogue_box(Option::<(&str, &str, bool)>::None);
-                        // self.enqueue_render(tui);
-                        *app_state = AppState::InFileSystem(Default::default());
-                        tui.in_filesystem();
-                        return;
+                tui.fs_widget.select(Some(self.filesystem_pos));
+            }
+            Action::BeginCreate(kind) => {
+                let (state, title) = match kind {
+                    CreateKind::File => (FsState::CreateFile(String::new()), "create file"),
+                    CreateKind::Dir => (FsState::CreateDir(String::new()), "create dir"),
+                };
+                *app_state = AppState::InFileSystem(state);
+                tui.in_filesystem_create(title);
+            }
+            Action::DeleteSelected => self.delete_selected(tui).await,
+            Action::BeginGoto if matches!(app_state, AppState::InContent(_)) => {
+                tui.in_content_goto(CONTENT_GOTO_TITLE, "");
+                *app_state = AppState::InContent(ContentState::Goto(String::new()));
+            }
+            Action::BeginGoto => {
+                let current = match self.fs_dirs.top() {
+                    Some((dir, _)) if !VirtPath::from(dir.as_str()).is_base() => {
+                        format!("{}/", VirtPath::from(dir.as_str()))
                     }
-                    // there is some bug here that exits the app
-                    KeyCode::Enter => {
-                        log::debug!("enter key pressed. creating file");
-
-                        if is_valid_fs_path_segment(&buf) {
-                            // construct actual path to file
-                            let path = match self.fs_dirs.top() {
-                                Some((dir, _)) => format!("{}/{}", dir, buf),
-                                None => buf.clone(),
-                            };
-
-                            match self.v_file_history.get(path.as_str()) {
-                                Some(v_file) => {
-                                    self.v_file = Some(v_file.clone());
-                                }
-                                None => {
-                                    // create a new file
-                                    let v_file = match VirtFile::create(self.ctx.clone(), &path)
-                                        .await
-                                    {
-                                        Ok(vf) => Arc::new(Mutex::new(vf)),
-                                        Err(e) => {
-                                            App::show_error_message(e, Duration::from_secs(2), tui);
-                                            *fs_state = FsState::Navigate;
-                                            tui.in_filesystem();
-                                            return;
-                                        }
-                                    };
-
-                                    self.v_file = Some(v_file.clone());
-                                    self.v_file_history.insert(path, v_file);
-                                }
-                            }
-                        } else {
-                            return;
-                        }
-
-                        log::debug!("re-reading directory");
-                        // read the dir again
-                        let read_dir = match rfs::fs::read_dir(
-                            self.ctx.clone(),
-                            &self.fs_dirs.top().expect("fs dirs should not be empty").0,
-                        )
-                        .await
-                        {
-                            Ok(rd) => rd,
-                            Err(e) => {
-                                log::error!("Read dir error: {:?}", e);
-                                App::show_error_message(e, Duration::from_secs(2), tui);
-                                *fs_state = FsState::Navigate;
-                                tui.in_filesystem();
-                                return;
-                            }
-                        };
-
-                        tui.fs_widget.update(read_dir.clone());
-                        let p = self.fs_dirs.pop().expect("fs dirs should not be empty").0;
-                        self.fs_dirs.push((p, read_dir));
-
-                        tui.fs_widget
-                            .dialogue_box(Option::<(&str, &str, bool)>::None);
+                    _ => String::new(),
+                };
+                tui.in_filesystem_goto(GOTO_TITLE, &current);
+                *app_state = AppState::InFileSystem(FsState::GotoPath(current));
+            }
+            Action::ShowLogFile => self.show_log_file(tui).await,
+            // handled before actions are applied
+            Action::ToggleMacroRecord
+            | Action::PlayMacro
+            | Action::NextMessage
+            | Action::ToggleStats => (),
+            Action::DebugCounter => self.debug_counter(tui).await,
+
+            Action::DialogueInput(_) | Action::DialogueBackspace
+                if matches!(app_state, AppState::InContent(ContentState::Goto(_))) =>
+            {
+                let buf = match app_state {
+                    AppState::InContent(ContentState::Goto(buf)) => buf,
+                    _ => return,
+                };
 
-                        *fs_state = FsState::Navigate;
-                        tui.in_filesystem();
-                        return;
-                    }
-                    KeyCode::Backspace => {
+                match action {
+                    Action::DialogueInput(c) => buf.push(c),
+                    _ => {
                         buf.pop();
                     }
-                    KeyCode::Char(c) => {
-                        buf.push(c);
-                    }
-                    _ => (),
                 }
 
-                // update the dialogue box
-                tui.fs_widget.dialogue_box(Some((
-                    "create file",
-                    &buf,
-                    // false
-                    !is_valid_fs_path_segment(&buf),
-                )));
+                let title = match goto_offset(self.content.as_deref().unwrap_or_default(), buf) {
+                    Err(e) if !buf.is_empty() => e,
+                    _ => CONTENT_GOTO_TITLE.to_string(),
+                };
+                tui.content_widget.set_prompt(Some((title, buf.as_str())));
             }
-            FsState::CreateDir(buf) => {
-                match app_ev.code {
-                    KeyCode::Esc => {
-                        // clear dialogue
-                        tui.fs_widget
-                            .dialogue_box(Option::<(&str, &str, bool)>::None);
+            Action::DialogueInput(_) | Action::DialogueBackspace => {
+                let (title, buf, goto) = match app_state {
+                    AppState::InFileSystem(FsState::CreateFile(buf)) => ("create file", buf, false),
+                    AppState::InFileSystem(FsState::CreateDir(buf)) => ("create dir", buf, false),
+                    AppState::InFileSystem(FsState::GotoPath(buf)) => (GOTO_TITLE, buf, true),
+                    _ => return,
+                };
 
-                        *app_state = AppState::InFileSystem(Default::default());
-                        tui.in_filesystem();
-                        return;
+                match action {
+                    Action::DialogueInput(c) => buf.push(c),
+                    _ => {
+                        buf.pop();
                     }
-                    KeyCode::Enter => {
-                        if is_valid_fs_path_segment(&buf) {
-                            // construct actual path to file
-                            let path = match self.fs_dirs.top() {
-                                Some((dir, _)) => format!("{}/{}", dir, buf),
-                                None => format!("./{}", buf),
-                            };
-
-                            match rfs::fs::create_dir(self.ctx.clone(), &path).await {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    log::error!("create dir error: {:?}", e);
-                                    App::show_error_message(
-                                        format!("{:?}", e),
-                                        Duration::from_secs(2),
-                                        tui,
-                                    );
-                                    tui.fs_widget
-                                        .dialogue_box(Option::<(&str, &str, bool)>::None);
-                                    *app_state = AppState::InFileSystem(Default::default());
-                                    tui.in_filesystem();
-                                    return;
-                                }
-                            }
-
-                            let read_dir = match rfs::fs::read_dir(self.ctx.clone(), &path).await {
-                                Ok(rd) => rd,
-                                Err(e) => {
-                                    log::error!("Read dir error: {:?}", e);
-                                    App::show_error_message(
-                                        format!("{:?}", e),
-                                        Duration::from_secs(2),
-                                        tui,
-                                    );
-                                    tui.fs_widget
-                                        .dialogue_box(Option::<(&str, &str, bool)>::None);
-                                    *app_state = AppState::InFileSystem(Default::default());
-                                    tui.in_filesystem();
-                                    return;
-                                }
-                            };
-                        } else {
-                            return;
-                        }
-
-                        // clear dialogue
-                        tui.fs_widget
-                            .dialogue_box(Option::<(&str, &str, bool)>::None);
-
-                        // read the dir again
-                        let read_dir = match rfs::fs::read_dir(
-                            self.ctx.clone(),
-                            &self.fs_dirs.top().unwrap().0,
-                        )
-                        .await
-                        {
-                            Ok(rd) => rd,
-                            Err(e) => {
-                                App::show_error_message(e, Duration::from_secs(2), tui);
-                                *app_state = AppState::InFileSystem(Default::default());
-                                tui.in_filesystem();
-                                return;
-                            }
-                        };
+                }
 
-                        tui.fs_widget.update(read_dir.clone());
-                        let p = self.fs_dirs.pop().unwrap().0;
-                        self.fs_dirs.push((p, read_dir));
+                let checked = match goto {
+                    true => goto_dirs(buf).map(|_| ()),
+                    false => self.new_entry_path(buf).map(|_| ()),
+                };
 
-                        *app_state = AppState::InFileSystem(Default::default());
-                        tui.in_filesystem();
+                // the remote would reject the name, so the error is shown as it is typed
+                let dialogue = match checked {
+                    Err(e) if !buf.is_empty() => (e.to_string(), buf.as_str(), true),
+                    _ => (title.to_string(), buf.as_str(), false),
+                };
+                tui.fs_widget.dialogue_box(Some(dialogue));
+            }
+            Action::DialogueCancel if matches!(app_state, AppState::InContent(_)) => {
+                self.close_content_goto(app_state, tui);
+            }
+            Action::DialogueSubmit if matches!(app_state, AppState::InContent(_)) => {
+                let input = match app_state {
+                    AppState::InContent(ContentState::Goto(buf)) => buf.clone(),
+                    _ => return,
+                };
 
-                        return;
+                // invalid positions keep the prompt open
+                if let Ok(offset) = goto_offset(self.content.as_deref().unwrap_or_default(), &input)
+                {
+                    self.move_cursor(offset, tui);
+                    self.close_content_goto(app_state, tui);
+                }
+            }
+            Action::DialogueCancel => {
+                log::debug!("cancelling create dialogue");
+                self.close_dialogue(app_state, tui);
+            }
+            Action::DialogueSubmit => {
+                let created = match app_state.clone() {
+                    AppState::InFileSystem(FsState::CreateFile(buf)) => {
+                        self.create_file(&buf, tui).await
                     }
-                    KeyCode::Backspace => {
-                        buf.pop();
+                    AppState::InFileSystem(FsState::CreateDir(buf)) => {
+                        self.create_dir(&buf, tui).await
                     }
-                    KeyCode::Char(c) => {
-                        buf.push(c);
+                    AppState::InFileSystem(FsState::GotoPath(buf)) => {
+                        self.goto_path(&buf, tui).await
                     }
+                    _ => return,
+                };
 
-                    _ => (),
+                match created {
+                    // invalid and existing names keep the dialogue open
+                    None => (),
+                    Some(_) => self.close_dialogue(app_state, tui),
                 }
+            }
 
-                // update the dialogue box
-                tui.fs_widget.dialogue_box(Some((
-                    "create dir",
-                    &buf,
-                    !is_valid_fs_path_segment(&buf),
-                )));
+            Action::SaveAndLeave => {
+                self.save_contents(tui).await;
+                self.change_focus(app_state, AppEvents::EscKey, tui);
+            }
+            Action::DeleteChar | Action::EnterInsert if self.readonly => {
+                App::show_warning("the file is read-only", tui);
+            }
+            Action::DeleteChar => {
+                if let (Some(content), Some(pos)) = (&mut self.content, self.cursor_pos) {
+                    if pos < content.len() {
+                        content.remove(pos);
+                        tui.content_widget.set_contents(Some(&content));
+                    }
+                }
             }
+            Action::CursorUp => tui.content_widget.cursor_up(),
+            Action::CursorDown => tui.content_widget.cursor_down(),
+            Action::CursorLeft => tui.content_widget.cursor_left(),
+            Action::CursorRight => tui.content_widget.cursor_right(),
+            Action::EnterInsert => {
+                *app_state = AppState::InContent(ContentState::Insert);
+                tui.in_content_insert();
+
+                // insertions are sent together when the file is saved
+                if let Some(vf) = &self.v_file {
+                    vf.lock().await.begin_edit();
+                }
+                self.unsaved_buf.clear();
+                self.update_presence(OpenMode::Editing, tui).await;
+            }
+            Action::WatchFile => self.watch_file(tui).await,
 
-            _ => todo!(),
-        }
-    }
+            Action::InsertChar(c) => {
+                self.unsaved_buf.push(c);
+                if let Some(p) = self.cursor_pos.as_mut() {
+                    *p += 1;
+                }
+                self.update_content_disp(tui);
+            }
+            Action::InsertBackspace => {
+                if let (Some(_), Some(p)) = (self.unsaved_buf.pop(), self.cursor_pos.as_mut()) {
+                    *p -= 1;
+                }
+                self.update_content_disp(tui);
+            }
+            Action::LeaveInsert => {
+                self.commit_insert(tui).await;
 
-    pub async fn handle_content_state(
-        &mut self,
-        app_state: &mut AppState,
-        app_ev: KeyEvent,
-        tui: &mut Tui,
-    ) {
-        let cont_state = if let AppState::InContent(inner) = app_state {
-            inner
-        } else {
+                *app_state = AppState::InContent(ContentState::Navigate);
+                tui.in_content_navi();
+                self.update_presence(OpenMode::Viewing, tui).await;
+            }
+
+            Action::ResolveConflict(resolution) => {
+                let upd = match self.pending_update.take() {
+                    Some(upd) => upd,
+                    None => return,
+                };
+
+                tui.content_widget.set_prompt(Option::<(&str, &str)>::None);
+                *app_state = AppState::InContent(ContentState::Navigate);
+                tui.in_content_navi();
+
+                self.resolve_file_update(app_state, upd, resolution, tui)
+                    .await;
+            }
+        }
+
+        // the cursor is tracked by the content widget during navigation
+        if content_navigate {
+            self.cursor_pos = tui.content_widget.cursor_offset();
+            self.unsaved_offset = self.cursor_pos.unwrap_or_default();
+        }
+    }
+
+    /// Move focus between widgets, resetting the state of the newly focused widget.
+    fn change_focus(&mut self, app_state: &mut AppState, event: AppEvents, tui: &mut Tui) {
+        let mut focus = app_state.focus();
+        focus.ingest(event);
+
+        if focus == app_state.focus() {
             return;
+        }
+
+        *app_state = AppState::from(focus);
+        match focus {
+            Focus::OnContent => tui.on_content(),
+            Focus::InContent => tui.in_content_navi(),
+            Focus::OnFileSystem => tui.on_filesystem(),
+            Focus::InFileSystem => tui.in_filesystem(),
+        }
+    }
+
+    /// Clear the create dialogue and go back to filesystem navigation
+    fn close_dialogue(&mut self, app_state: &mut AppState, tui: &mut Tui) {
+        self.overwrite_warned = None;
+        tui.fs_widget
+            .dialogue_box(Option::<(&str, &str, bool)>::None);
+        *app_state = AppState::InFileSystem(FsState::Navigate);
+        tui.in_filesystem();
+    }
+
+    /// Clear the go to position prompt and go back to content navigation
+    fn close_content_goto(&mut self, app_state: &mut AppState, tui: &mut Tui) {
+        tui.content_widget.set_prompt(Option::<(&str, &str)>::None);
+        *app_state = AppState::InContent(ContentState::Navigate);
+        tui.in_content_navi();
+    }
+
+    /// Move the cursor to an offset in the open file, scrolling it into view
+    fn move_cursor(&mut self, offset: usize, tui: &mut Tui) {
+        let offset = offset.min(self.content.as_ref().map(|c| c.len()).unwrap_or_default());
+
+        self.cursor_pos = Some(offset);
+        self.unsaved_offset = offset;
+        tui.content_widget.set_cursor_pos(Some((0, 0)));
+        tui.content_widget.set_cursor_offset(offset);
+    }
+
+    /// Open the selected entry of the current directory
+    async fn open_selected(&mut self, app_state: &mut AppState, tui: &mut Tui) {
+        let dir_entry = match self.fs_dirs.top() {
+            Some((_, read_dir)) => match read_dir.get(self.filesystem_pos) {
+                Some(entry) => entry.clone(),
+                None => return,
+            },
+            None => return,
         };
 
-        match cont_state {
-            ContentState::Navigate => {
-                match app_ev.code {
-                    // write any delete changes to file
-                    // unlike insert writes, this overwrites the entire file.
-                    KeyCode::Esc => {
-                        log::debug!("writing changes to file");
-                        let v_f = match &self.v_file {
-                            Some(vf) => vf.clone(),
-                            None => return,
-                        };
+        match dir_entry.is_file() {
+            true => {
+                if self.open_file(&dir_entry.path, tui).await {
+                    *app_state = AppState::InContent(Default::default());
+                    tui.in_content_navi();
+                }
+            }
+            false => {
+                let name = dir_entry
+                    .path()
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+
+                if self.enter_dir(dir_entry.path, name, tui).await {
+                    self.poll_dir_changes(tui);
+                }
+            }
+        }
+    }
 
-                        let mut lock = v_f.lock().await;
+    /// Open a file in the content widget. Returns `false` if the file could not be opened.
+    async fn open_file(&mut self, path: &str, tui: &mut Tui) -> bool {
+        // history is keyed by normalized paths, matching the remote's watch keys
+        let path = VirtPath::from(path).to_string();
+        let path = path.as_str();
+
+        let v_file = match with_progress(&mut self.progress, tui, self.remote.open(path)).await {
+            Ok(vf) => vf,
+            Err(e) => {
+                log::error!("virtual file open error: {:?}", e);
+                App::show_error_message(e, tui);
+                return false;
+            }
+        };
 
-                        let contents = match &self.content {
-                            Some(c) => c,
-                            None => return,
-                        };
+        self.close_file().await;
+        self.v_file = Some(v_file.clone());
+        self.readonly = v_file.lock().await.is_readonly();
+        self.update_presence(OpenMode::Viewing, tui).await;
 
-                        // if contents have changed, write
-                        match contents.as_bytes() == lock.local_cache() {
-                            true => (),
-                            false => {
-                                let update = FileUpdate::Overwrite(contents.as_bytes().to_vec());
-                                match lock.write_bytes(update).await {
-                                    Ok(_) => (),
-                                    Err(e) => {
-                                        log::error!("write error: {:?}", e);
-                                        App::show_error_message(e, Duration::from_secs(2), tui);
-                                    }
-                                }
-                            }
-                        }
+        self.content = Some(String::from_utf8_lossy(v_file.lock().await.local_cache()).to_string());
+        tui.content_widget
+            .set_contents(Some(self.content.clone().unwrap_or_default()));
+        self.move_cursor(self.cursors.get(path).copied().unwrap_or_default(), tui);
+
+        true
+    }
+
+    /// Close the open file, which is no longer watched.
+    async fn close_file(&mut self) {
+        if let Some(prev) = self.v_file.take() {
+            let prev = prev.lock().await.as_path();
+            if let Some(cursor) = self.cursor_pos {
+                self.cursors
+                    .insert(VirtPath::from(&prev).to_string(), cursor);
+            }
+            self.tasks.cancel(&TaskPurpose::Watch(prev.clone()));
+
+            let prev = VirtPath::from(prev);
+            if let Err(e) = PresenceOpsClient::register_close(&mut self.ctx.clone(), prev).await {
+                log::debug!("failed to close previous file: {:?}", e);
+            }
+        }
+    }
 
-                        *app_state = AppState::OnContent;
-                        tui.on_content();
+    /// Show the full log file in the content window, in place of the open file.
+    async fn show_log_file(&mut self, tui: &mut Tui) {
+        let path = match &self.log_file {
+            Some(p) => p.clone(),
+            None => {
+                App::show_warning("logs are only kept in a file with --log-to-file", tui);
+                return;
+            }
+        };
+
+        let logs = match std::fs::read(&path) {
+            Ok(l) => String::from_utf8_lossy(&l).to_string(),
+            Err(e) => {
+                App::show_error_message(format!("{}: {}", path.display(), e), tui);
+                return;
+            }
+        };
+
+        self.close_file().await;
+        self.readonly = true;
+        self.content = Some(logs);
+        self.cursor_pos = Some(0);
+        self.unsaved_offset = 0;
+        tui.content_widget
+            .set_contents(Some(self.content.clone().unwrap_or_default()));
+        tui.content_widget.set_cursor_pos(Some((0, 0)));
+        tui.content_widget
+            .set_title(content_title(true, Some(path.display().to_string())));
+    }
+
+    /// Read a directory and push it onto the directory stack.
+    /// Returns `false` if the directory could not be read.
+    async fn enter_dir(&mut self, path: String, name: String, tui: &mut Tui) -> bool {
+        match with_progress(
+            &mut self.progress,
+            tui,
+            rfs::fs::ls(self.ctx.clone(), &path, None, LS_PAGE_SIZE),
+        )
+        .await
+        {
+            Ok(listing) => {
+                match listing.cursor {
+                    Some(cursor) => self.dir_cursors.insert(path.clone(), cursor),
+                    None => self.dir_cursors.remove(&path),
+                };
+
+                let read_dir = VirtReadDir {
+                    entries: listing.entries,
+                    change_counter: listing.change_counter,
+                };
+                self.fs_dirs.push((path, read_dir.clone()));
+                self.filesystem_pos = 0;
+                tui.fs_widget.push(read_dir, name);
+                tui.fs_widget.select(Some(self.filesystem_pos));
+                true
+            }
+            Err(e) => {
+                log::error!("Read dir error: {:?}", e);
+                App::show_error_message(e, tui);
+                false
+            }
+        }
+    }
+
+    /// List the next page of the current directory, if it is not listed in full.
+    async fn list_next_page(&mut self, tui: &mut Tui) {
+        let (dir, last) = match self.fs_dirs.top() {
+            Some((dir, read_dir)) => (dir.clone(), read_dir.iter().last().map(|e| e.path.clone())),
+            None => return,
+        };
+        let cursor = match self.dir_cursors.remove(&dir) {
+            Some(c) => c,
+            None => return,
+        };
+
+        let listing = match with_progress(
+            &mut self.progress,
+            tui,
+            rfs::fs::ls(self.ctx.clone(), &dir, Some(cursor.clone()), LS_PAGE_SIZE),
+        )
+        .await
+        {
+            Ok(l) => l,
+            Err(e) => {
+                log::error!("Read dir error: {:?}", e);
+                App::show_error_message(e, tui);
+                self.dir_cursors.insert(dir, cursor);
+                return;
+            }
+        };
+
+        if let Some(next) = listing.cursor {
+            self.dir_cursors.insert(dir, next);
+        }
+
+        // the directory may have been read in full since the cursor was issued
+        let entries = listing
+            .entries
+            .into_iter()
+            .filter(|e| last.as_ref().map(|l| e.path > *l).unwrap_or(true))
+            .collect::<Vec<_>>();
+
+        let depth = self.fs_dirs.depth();
+        if let Some((_, read_dir)) = self.fs_dirs.get_mut(depth - 1) {
+            read_dir.entries.extend(entries.clone());
+        }
+        tui.fs_widget.extend(entries);
+    }
+
+    /// Go to a directory, or to the directory of a file and select the file.
+    ///
+    /// Directories on the way that are already in the directory stack are not read again.
+    /// Returns `None` if the path is invalid or cannot be read, and the dialogue should stay open.
+    async fn goto_path(&mut self, input: &str, tui: &mut Tui) -> Option<()> {
+        let dirs = goto_dirs(input).ok()?;
+
+        let common = self
+            .fs_dirs
+            .iter()
+            .zip(&dirs)
+            .take_while(|((dir, _), target)| VirtPath::from(dir.as_str()) == **target)
+            .count();
+        let mut stack = self
+            .fs_dirs
+            .iter()
+            .take(common)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut selected = None;
+
+        for dir in &dirs[common..] {
+            // files are selected in their directory
+            let file_pos = stack.last().and_then(|(_, read_dir)| {
+                read_dir
+                    .iter()
+                    .position(|e| e.is_file() && VirtPath::from(e.path.as_str()) == *dir)
+            });
+            if file_pos.is_some() {
+                selected = file_pos;
+                break;
+            }
+
+            match with_progress(
+                &mut self.progress,
+                tui,
+                rfs::fs::read_dir(self.ctx.clone(), dir.as_str()),
+            )
+            .await
+            {
+                Ok(read_dir) => stack.push((dir.to_string(), read_dir)),
+                Err(e) => {
+                    log::error!("Read dir error: {:?}", e);
+                    App::show_error_message(format!("{}: {}", dir, e), tui);
+                    return None;
+                }
+            }
+        }
+
+        while self.fs_dirs.depth() > common {
+            self.fs_dirs.pop();
+            tui.fs_widget.pop();
+        }
+        for (dir, read_dir) in stack.into_iter().skip(common) {
+            let name = VirtPath::from(dir.as_str())
+                .file_name()
+                .unwrap_or(".")
+                .to_string();
+
+            tui.fs_widget.push(read_dir.clone(), name);
+            self.fs_dirs.push((dir, read_dir));
+        }
+
+        self.filesystem_pos = selected.unwrap_or_default();
+        tui.fs_widget.select(Some(self.filesystem_pos));
+        self.poll_dir_changes(tui);
+
+        Some(())
+    }
+
+    /// Go up one dir (if possible)
+    async fn parent_dir(&mut self, tui: &mut Tui) {
+        if self.fs_dirs.depth() <= 1 {
+            return;
+        }
+
+        self.fs_dirs.pop();
+        tui.fs_widget.pop();
+
+        if self.reload_dir(tui).await {
+            self.filesystem_pos = 0;
+            tui.fs_widget.select(Some(self.filesystem_pos));
+            self.poll_dir_changes(tui);
+        }
+    }
+
+    /// Read the current directory again. Returns `false` if the directory could not be read.
+    async fn reload_dir(&mut self, tui: &mut Tui) -> bool {
+        let dir = match self.fs_dirs.top() {
+            Some((dir, _)) => dir.clone(),
+            None => return false,
+        };
+
+        let read_dir = match with_progress(
+            &mut self.progress,
+            tui,
+            rfs::fs::read_dir(self.ctx.clone(), &dir),
+        )
+        .await
+        {
+            Ok(rd) => rd,
+            Err(e) => {
+                log::error!("Read dir error: {:?}", e);
+                App::show_error_message(e, tui);
+                return false;
+            }
+        };
+
+        tui.fs_widget.update(read_dir.clone());
+        self.dir_cursors.remove(&dir);
+        self.fs_dirs.pop();
+        self.fs_dirs.push((dir, read_dir));
+
+        true
+    }
+
+    /// Read every directory on the stack that contains a changed path again,
+    /// so listings further up the stack are not stale when returning to them.
+    ///
+    /// Returns `false` if the current directory could not be read.
+    async fn refresh_listings(&mut self, changed: &str, tui: &mut Tui) -> bool {
+        let depth = self.fs_dirs.depth();
+        let ancestors = self
+            .fs_dirs
+            .iter()
+            .enumerate()
+            .filter(|(_, (dir, _))| is_ancestor_dir(dir, changed))
+            .map(|(idx, (dir, _))| (idx, dir.clone()))
+            .collect::<Vec<_>>();
+
+        let mut current_read = true;
+        for (idx, dir) in ancestors {
+            match with_progress(
+                &mut self.progress,
+                tui,
+                rfs::fs::read_dir(self.ctx.clone(), &dir),
+            )
+            .await
+            {
+                Ok(read_dir) => {
+                    tui.fs_widget.update_at(idx, read_dir.clone());
+                    if let Some(entry) = self.fs_dirs.get_mut(idx) {
+                        entry.1 = read_dir;
+                    }
+                }
+                Err(e) => {
+                    log::error!("failed to refresh {}: {:?}", dir, e);
+                    if idx + 1 == depth {
+                        App::show_error_message(e, tui);
+                        current_read = false;
                     }
+                }
+            }
+        }
 
-                    // del char
-                    KeyCode::Delete => {
-                        match (&mut self.content, self.cursor_pos) {
-                            (Some(content), Some(pos)) => {
-                                content.remove(pos);
-                                tui.content_widget.set_contents(Some(&content));
-                            }
-                            // do nothing
-                            _ => (),
+        current_read
+    }
+
+    /// Read the watched directory again after it changed on the remote.
+    ///
+    /// The selection stays on the same entry if it still exists, and new entries are highlighted.
+    async fn apply_dir_change(&mut self, tui: &mut Tui) {
+        let (before, selected) = match self.fs_dirs.top() {
+            Some((_, read_dir)) => (
+                read_dir.clone(),
+                read_dir.get(self.filesystem_pos).map(|e| e.path.clone()),
+            ),
+            None => return,
+        };
+
+        if !self.reload_dir(tui).await {
+            return;
+        }
+
+        let after = match self.fs_dirs.top() {
+            Some((_, read_dir)) => read_dir,
+            None => return,
+        };
+
+        self.filesystem_pos = selected
+            .and_then(|path| after.entries.iter().position(|e| e.path == path))
+            .unwrap_or(self.filesystem_pos.min(after.len().saturating_sub(1)));
+        tui.fs_widget.select(Some(self.filesystem_pos));
+
+        let added = added_entries(&before, after);
+        if !added.is_empty() {
+            self.show_entry_highlight(added, NEW_ENTRY_HIGHLIGHT_DURATION, tui);
+        }
+    }
+
+    /// Delete the selected entry of the current directory
+    /// Increment the shared counter on the remote once, and show how much it changed.
+    ///
+    /// The counter changes by more than one if a duplicated request is executed again.
+    async fn debug_counter(&mut self, tui: &mut Tui) {
+        let mut ctx = self.ctx.clone();
+        let call = async move {
+            let before = CounterOpsClient::get(&mut ctx).await?;
+            let after = CounterOpsClient::increment(&mut ctx, 1).await?;
+
+            Ok::<_, InvokeError>((before, after))
+        };
+
+        match with_progress(&mut self.progress, tui, call).await {
+            Ok((before, after)) => App::show_notification(
+                format!(
+                    "counter: {} -> {} ({} executions of 1 increment)",
+                    before,
+                    after,
+                    after.saturating_sub(before)
+                ),
+                Duration::from_secs(3),
+                tui,
+            ),
+            Err(e) => {
+                log::error!("counter error: {:?}", e);
+                App::show_error_message(format!("{:?}", e), tui);
+            }
+        }
+    }
+
+    async fn delete_selected(&mut self, tui: &mut Tui) {
+        let dir_entry = match self.fs_dirs.top() {
+            Some((_, read_dir)) => match read_dir.get(self.filesystem_pos) {
+                Some(entry) => entry.clone(),
+                None => return,
+            },
+            None => return,
+        };
+
+        let path = dir_entry.path.clone();
+        match dir_entry.is_file() {
+            true => match with_progress(
+                &mut self.progress,
+                tui,
+                rfs::fs::remove_file(self.ctx.clone(), &path),
+            )
+            .await
+            {
+                Ok(_) => {
+                    App::show_notification(
+                        format!("deleted file: {}", path),
+                        Duration::from_secs(2),
+                        tui,
+                    );
+
+                    // clear the screen if the file is displayed there
+                    if let Some(vf) = &self.v_file {
+                        if vf.lock().await.as_path() == path {
+                            self.v_file = None;
+                            self.content = None;
+                            self.unsaved_buf.clear();
+                            self.unsaved_offset = 0;
+                            tui.content_widget.set_contents(Option::<&str>::None);
+                            tui.content_widget.set_cursor_offset(0);
                         }
                     }
+                }
+                Err(e) => {
+                    log::error!("remove file error: {:?}", e);
+                    App::show_error_message(format!("{:?}", e), tui);
+                    return;
+                }
+            },
+            false => match with_progress(
+                &mut self.progress,
+                tui,
+                rfs::fs::remove_dir(self.ctx.clone(), &path),
+            )
+            .await
+            {
+                Ok(_) => {
+                    App::show_notification(
+                        format!("deleted dir: {}", path),
+                        Duration::from_secs(2),
+                        tui,
+                    );
+                }
+                Err(e) => {
+                    log::error!("remove dir error: {:?}", e);
+                    App::show_error_message(format!("{:?}", e), tui);
+                }
+            },
+        }
+
+        if self.refresh_listings(&path, tui).await {
+            tui.fs_widget.select(Some(self.filesystem_pos));
+        }
+    }
+
+    /// Path of a new entry named `name` in the current directory.
+    ///
+    /// Fails if the name or path is not allowed by the path policy.
+    fn new_entry_path(&self, name: &str) -> Result<String, PathViolation> {
+        let policy = PathPolicy::default();
+        policy.check_segment(name)?;
+
+        let path = match self.fs_dirs.top() {
+            Some((dir, _)) => VirtPath::from(dir.as_str()).join(name).to_string(),
+            None => VirtPath::from(name).to_string(),
+        };
+        policy.check_path(&path)?;
+
+        Ok(path)
+    }
+
+    /// Create and open a file in the current directory.
+    ///
+    /// Returns `None` if the name is invalid or already taken, and the dialogue should stay open.
+    async fn create_file(&mut self, name: &str, tui: &mut Tui) -> Option<()> {
+        let path = self.new_entry_path(name).ok()?;
+
+        let cached = self.remote.cached(&path);
+        if cached.is_none() && !self.confirm_create(&path, name, tui).await {
+            return None;
+        }
+
+        match cached {
+            Some(v_file) => {
+                self.v_file = Some(v_file);
+            }
+            None => match with_progress(&mut self.progress, tui, self.remote.create(&path)).await {
+                Ok(v_file) => {
+                    self.v_file = Some(v_file);
+                }
+                Err(e) => {
+                    App::show_error_message(e, tui);
+                    return Some(());
+                }
+            },
+        }
+
+        log::debug!("re-reading directory");
+        self.refresh_listings(&path, tui).await;
 
-                    // navi
-                    KeyCode::Up => {
-                        tui.content_widget.cursor_up();
+        Some(())
+    }
+
+    /// Read the details of the entries in the current directory, if they are shown
+    /// and the directory has changed since they were last read.
+    async fn refresh_details(&mut self, tui: &mut Tui) {
+        if !self.show_details {
+            return;
+        }
+
+        let (dir, read_dir) = match self.fs_dirs.top() {
+            Some((dir, read_dir)) => (dir.clone(), read_dir.clone()),
+            None => return,
+        };
+        let current = Some((dir, read_dir.change_counter));
+        if self.details_of == current {
+            return;
+        }
+
+        let mut details = HashMap::new();
+        for page in read_dir.entries.chunks(DETAILS_PAGE_SIZE) {
+            let paths = page.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+
+            match with_progress(
+                &mut self.progress,
+                tui,
+                rfs::fs::stat_many(self.ctx.clone(), &paths),
+            )
+            .await
+            {
+                Ok(stats) => details.extend(
+                    paths
+                        .iter()
+                        .zip(stats)
+                        .filter_map(|(path, meta)| Some((path.to_string(), meta?))),
+                ),
+                Err(e) => {
+                    log::error!("failed to read entry details: {}", e);
+                    return;
+                }
+            }
+        }
+
+        tui.fs_widget.set_details(Some(details));
+        self.details_of = current;
+    }
+
+    /// Checks if a file can be created at a path without overwriting an existing entry.
+    ///
+    /// Existing files can be overwritten by submitting the same name again after a warning.
+    async fn confirm_create(&mut self, path: &str, name: &str, tui: &mut Tui) -> bool {
+        if self.overwrite_warned.as_deref() == Some(path) {
+            return true;
+        }
+
+        let meta = match with_progress(
+            &mut self.progress,
+            tui,
+            rfs::fs::stat(self.ctx.clone(), path),
+        )
+        .await
+        {
+            Ok(Some(meta)) => meta,
+            Ok(None) => return true,
+            Err(e) => {
+                log::error!("failed to stat {}: {}", path, e);
+                return true;
+            }
+        };
+
+        let title = match meta.file {
+            true => {
+                self.overwrite_warned = Some(path.to_string());
+                "file exists, enter to overwrite"
+            }
+            false => "a directory with this name exists",
+        };
+        tui.fs_widget.dialogue_box(Some((title, name, true)));
+
+        false
+    }
+
+    /// Create a directory in the current directory.
+    ///
+    /// Returns `None` if the name is invalid, and the dialogue should stay open.
+    async fn create_dir(&mut self, name: &str, tui: &mut Tui) -> Option<()> {
+        let path = self.new_entry_path(name).ok()?;
+
+        match with_progress(
+            &mut self.progress,
+            tui,
+            rfs::fs::create_dir(self.ctx.clone(), &path),
+        )
+        .await
+        {
+            Ok(_) => {
+                self.refresh_listings(&path, tui).await;
+            }
+            Err(e) => {
+                log::error!("create dir error: {:?}", e);
+                App::show_error_message(format!("{:?}", e), tui);
+            }
+        }
+
+        Some(())
+    }
+
+    /// Write the edits to the open file, as one batch.
+    ///
+    /// Insertions are committed along with any deletions, which overwrite the entire file.
+    async fn save_contents(&mut self, tui: &mut Tui) {
+        log::debug!("writing changes to file");
+        let (v_f, contents) = match (&self.v_file, &self.content) {
+            (Some(vf), Some(c)) => (vf.clone(), c),
+            _ => return,
+        };
+
+        let mut lock = v_f.lock().await;
+
+        // if contents have changed, write
+        let changed = contents.as_bytes() != lock.local_cache();
+        if !changed && !lock.in_edit() {
+            return;
+        }
+
+        lock.begin_edit();
+        if changed {
+            let update = FileUpdate::Overwrite(contents.as_bytes().to_vec());
+            if let Err(e) = lock.write_bytes(update).await {
+                log::error!("write error: {:?}", e);
+            }
+        }
+
+        // a failed commit stays in progress, and is sent again on the next save
+        if let Err(e) = with_progress(&mut self.progress, tui, lock.commit()).await {
+            log::error!("write error: {:?}", e);
+            App::show_error_message(e, tui);
+        }
+
+        drop(lock);
+        self.update_disk_usage(tui).await;
+    }
+
+    /// Show the free space on the remote disk in the title bar
+    async fn update_disk_usage(&mut self, tui: &mut Tui) {
+        let usage = match rfs::fs::disk_usage(self.ctx.clone()).await {
+            Ok(usage) => Some(usage),
+            Err(e) => {
+                log::debug!("failed to read remote disk usage: {}", e);
+                None
+            }
+        };
+
+        tui.title_widget.set_disk_usage(usage);
+    }
+
+    /// Write the unsaved insertion to the open file.
+    ///
+    /// The insertion is held back by the edit of the file until it is saved.
+    async fn commit_insert(&mut self, tui: &mut Tui) {
+        let v_file = match &self.v_file {
+            Some(vf) => vf.clone(),
+            None => return,
+        };
+
+        if self.unsaved_buf.is_empty() {
+            return;
+        }
+
+        let mut lock = v_file.lock().await;
+        let update =
+            FileUpdate::Insert((self.unsaved_offset, self.unsaved_buf.as_bytes().to_vec()));
+
+        if let Err(e) = with_progress(&mut self.progress, tui, lock.write_bytes(update)).await {
+            log::error!("write error: {:?}", e);
+            App::show_error_message(e, tui);
+            return;
+        }
+
+        let new_contents = String::from_utf8_lossy(lock.local_cache()).to_string();
+        drop(lock);
+
+        self.unsaved_buf.clear();
+        self.unsaved_offset = tui.content_widget.cursor_offset().unwrap_or_default();
+        tui.content_widget.set_contents(Some(&new_contents));
+        self.content = Some(new_contents);
+        self.update_disk_usage(tui).await;
+    }
+
+    /// Register the open file with the remote, and show the other clients that have it open.
+    ///
+    /// Registrations expire, so this is called again whenever the file changes.
+    async fn update_presence(&mut self, mode: OpenMode, tui: &mut Tui) {
+        let path = match &self.v_file {
+            Some(vf) => VirtPath::from(vf.lock().await.as_path()),
+            None => return,
+        };
+        let mut ctx = self.ctx.clone();
+
+        // remotes without presence are shown without a title
+        let watchers = match PresenceOpsClient::register_open(&mut ctx, path.clone(), mode).await {
+            Ok(Ok(_)) => PresenceOpsClient::list_watchers(&mut ctx, path).await,
+            Ok(Err(e)) => Err(InvokeError::from(io::Error::from(e))),
+            Err(e) => Err(e),
+        };
+
+        let presence = match watchers {
+            Ok(watchers) => presence_title(&watchers),
+            Err(e) => {
+                log::debug!("presence unavailable: {:?}", e);
+                None
+            }
+        };
+        tui.content_widget
+            .set_title(content_title(self.readonly, presence));
+    }
+
+    /// Watch the open file for a remote update in the background
+    async fn watch_file(&mut self, tui: &mut Tui) {
+        let v_f = match &self.v_file {
+            Some(vf) => vf.clone(),
+            None => return,
+        };
+
+        let ev_tx = tui.event_tx.clone();
+        let purpose = TaskPurpose::Watch(v_f.lock().await.as_path());
+
+        // replace any existing watch on the same file
+        self.tasks.cancel(&purpose);
+        self.tasks.spawn(purpose, |token| async move {
+            // cancelling the token removes the watch from the remote
+            let mut update_channel = match v_f.lock().await.watch_chan(None, token).await {
+                Ok(ch) => ch,
+                Err(_) => return,
+            };
+
+            loop {
+                match update_channel.recv().await {
+                    Some(Ok(WatchEvent::Update((path, update_data)))) => {
+                        log::info!("file update received");
+                        // update the content widget
+                        ev_tx
+                            .send(AppEvent::FileUpdate {
+                                path,
+                                upd: update_data,
+                            })
+                            .unwrap();
                     }
-                    KeyCode::Down => {
-                        tui.content_widget.cursor_down();
+                    Some(Ok(WatchEvent::Lagged(missed))) => {
+                        log::warn!("file watch missed {} updates", missed);
+                        let message = Message::new(
+                            Severity::Warning,
+                            format!(
+                                "missed {} file updates, reopen the file to refresh it",
+                                missed
+                            ),
+                        );
+                        ev_tx.send(AppEvent::Message(message)).unwrap();
                     }
-                    KeyCode::Left => {
-                        tui.content_widget.cursor_left();
+                    Some(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {
+                        log::debug!("file watch cancelled");
+                        break;
                     }
-                    KeyCode::Right => {
-                        tui.content_widget.cursor_right();
+                    Some(Err(e)) => {
+                        log::error!("file watch failed: {:?}", e);
+                        break;
                     }
-                    KeyCode::Enter => {
-                        // toggle insert mode
-                        *cont_state = ContentState::Insert;
-                        tui.in_content_insert();
+                    None => break,
+                }
+            }
+        });
 
-                        self.unsaved_buf.clear();
-                    }
-                    KeyCode::Char('w') => {
-                        let v_f = match &self.v_file {
-                            Some(vf) => vf.clone(),
-                            None => return,
-                        };
+        App::show_notification("file watch enabled", Duration::from_secs(2), tui);
+    }
 
-                        let ev_tx = tui.event_tx.clone();
-
-                        tokio::spawn(async move {
-                            let mut update_channel = match v_f.lock().await.watch_chan().await {
-                                Ok(ch) => ch,
-                                Err(_) => return,
-                            };
-
-                            match update_channel.recv().await {
-                                Some(Ok((path, update_data))) => {
-                                    log::info!("file update received");
-                                    // update the content widget
-                                    ev_tx
-                                        .send(AppEvent::FileUpdate {
-                                            path,
-                                            upd: update_data,
-                                        })
-                                        .unwrap();
-                                }
-                                _ => return,
-                            };
-                        });
+    /// Show the current message of the queue on the content widget.
+    ///
+    /// Notifications are shown in the border, warnings and errors in a pop-up.
+    fn show_messages(&self, tui: &mut Tui) {
+        let (message, pos, total) = match self.messages.current() {
+            Some(m) => m,
+            None => {
+                tui.content_widget.set_notification(Option::<&str>::None);
+                tui.content_widget
+                    .set_error_message(Option::<(Severity, &str, &str)>::None);
+                return;
+            }
+        };
+
+        let counter = match total > 1 {
+            true => format!(" ({}/{}, TAB: next)", pos + 1, total),
+            false => String::new(),
+        };
+
+        match message.severity {
+            Severity::Info => {
+                tui.content_widget
+                    .set_notification(Some(format!("{}{}", message.text, counter)));
+                tui.content_widget
+                    .set_error_message(Option::<(Severity, &str, &str)>::None);
+            }
+            severity => {
+                tui.content_widget.set_notification(Option::<&str>::None);
+                tui.content_widget.set_error_message(Some((
+                    severity,
+                    format!("{}{}", severity, counter),
+                    &message.text,
+                )));
+            }
+        }
+    }
+
+    /// Snapshot of where the user is, to be restored on the next start
+    pub async fn session(&self) -> Session {
+        let open_file = match &self.v_file {
+            Some(vf) => Some(vf.lock().await.as_path()),
+            None => None,
+        };
+
+        let mut cursors = self.cursors.clone();
+        if let (Some(path), Some(cursor)) = (&open_file, self.cursor_pos) {
+            cursors.insert(VirtPath::from(path).to_string(), cursor);
+        }
+
+        Session {
+            dirs: self.fs_dirs.iter().map(|(dir, _)| dir.clone()).collect(),
+            filesystem_pos: self.filesystem_pos,
+            open_file,
+            cursor_pos: self.cursor_pos,
+            cursors,
+            key_macro: self.key_macro.clone(),
+        }
+    }
+
+    /// Restore a previous session on top of the base directory.
+    ///
+    /// Directories or files that no longer exist on the remote are skipped.
+    async fn restore(&mut self, session: Session, tui: &mut Tui) {
+        self.key_macro = session.key_macro;
+        self.cursors = session.cursors;
+
+        for dir in session.dirs.into_iter().skip(1) {
+            let name = VirtPath::from(&dir)
+                .file_name()
+                .unwrap_or_default()
+                .to_string();
+
+            if !self.enter_dir(dir, name, tui).await {
+                break;
+            }
+        }
+
+        if let Some((_, read_dir)) = self.fs_dirs.top() {
+            if read_dir.get(session.filesystem_pos).is_some() {
+                self.filesystem_pos = session.filesystem_pos;
+            }
+        }
+        tui.fs_widget.select(Some(self.filesystem_pos));
+
+        if let Some(path) = session.open_file {
+            if self.open_file(&path, tui).await {
+                let len = self.content.as_ref().map(|c| c.len()).unwrap_or_default();
+                let cursor = session.cursor_pos.unwrap_or_default().min(len);
+
+                self.cursor_pos = Some(cursor);
+                self.unsaved_offset = cursor;
+                tui.content_widget.set_cursor_offset(cursor);
+            }
+        }
+    }
+
+    /// Apply a remote update to the open file.
+    ///
+    /// If the update overlaps with unsaved edits, the conflict is resolved
+    /// according to [AppData::conflict_resolution].
+    async fn apply_file_update(
+        &mut self,
+        app_state: &mut AppState,
+        notice: FileUpdateNotice,
+        tui: &mut Tui,
+    ) {
+        let v_file = match &self.v_file {
+            Some(vf) => vf.clone(),
+            None => return,
+        };
 
-                        tui.content_widget
-                            .set_notification(Some("file watch enabled"));
+        let mut lock = v_file.lock().await;
+        // duplicate deliveries and echoes of our own writes are already applied
+        if !lock.is_new(&notice) {
+            log::debug!("ignoring applied update version {}", notice.version);
+            lock.update_bytes(&notice);
+            return;
+        }
+
+        let base = lock.local_cache().to_vec();
+        drop(lock);
+        let remote = TextEdit::from_update(&notice.update, &base);
+
+        let resolution = match TextEdit::diff(&base, &self.local_view()) {
+            Some(local) if remote.overlaps(&local) => self.conflict_resolution,
+            // disjoint edits can always be merged
+            Some(_) => ConflictResolution::Merge,
+            None => ConflictResolution::TakeRemote,
+        };
+
+        match resolution {
+            ConflictResolution::Prompt => {
+                log::info!("remote update conflicts with unsaved edits");
+                self.pending_update = Some(notice);
+                *app_state = AppState::InContent(ContentState::Conflict);
+                tui.in_content_conflict();
+                tui.content_widget.set_prompt(Some((
+                    "conflict",
+                    "the remote file was updated while you have unsaved edits",
+                )));
+            }
+            res => self.resolve_file_update(app_state, notice, res, tui).await,
+        }
+    }
+
+    /// Apply a remote update to the open file, resolving any unsaved edits with `resolution`.
+    async fn resolve_file_update(
+        &mut self,
+        app_state: &mut AppState,
+        notice: FileUpdateNotice,
+        resolution: ConflictResolution,
+        tui: &mut Tui,
+    ) {
+        let v_file = match &self.v_file {
+            Some(vf) => vf.clone(),
+            None => return,
+        };
+
+        let mut lock = v_file.lock().await;
+        let base = lock.local_cache().to_vec();
+        let view = self.local_view();
+        let upd = &notice.update;
+        let remote = TextEdit::from_update(upd, &base);
+        let local = TextEdit::diff(&base, &view);
+
+        lock.update_bytes(&notice);
+        let remote_contents = lock.local_cache().to_vec();
+        drop(lock);
+
+        // new contents and the offset of the remote update in them, if visible
+        let (new_view, highlight) = match (resolution, &local) {
+            (ConflictResolution::KeepMine, _) => (view, None),
+            (ConflictResolution::Merge, Some(local)) => {
+                match rfs::fs::merge(&base, std::slice::from_ref(local), upd) {
+                    MergeResult::Clean(merged) => {
+                        let offset = match local.offset < remote.offset {
+                            true => remote.offset.saturating_add_signed(local.delta()),
+                            false => remote.offset,
+                        };
+                        (merged, Some(offset))
                     }
+                    MergeResult::Conflict(merged) => {
+                        App::show_notification(
+                            "merge conflict: edit the marked lines and save",
+                            Duration::from_secs(5),
+                            tui,
+                        );
+                        (merged, None)
+                    }
+                }
+            }
+            _ => (remote_contents.clone(), Some(remote.offset)),
+        };
 
-                    _ => (),
+        let cursor = self.cursor_pos.unwrap_or_default();
+        let cursor = match highlight.is_some() && remote.offset < cursor {
+            true => cursor.saturating_add_signed(remote.delta()),
+            false => cursor,
+        }
+        .min(new_view.len());
+        self.cursor_pos = Some(cursor);
+
+        match (resolution, &local) {
+            // disjoint edits: the unsaved insertion is still intact and stays unsaved
+            (ConflictResolution::Merge, Some(local))
+                if !self.unsaved_buf.is_empty() && !remote.overlaps(local) =>
+            {
+                if remote.offset < self.unsaved_offset {
+                    self.unsaved_offset = self.unsaved_offset.saturating_add_signed(remote.delta());
                 }
 
-                self.cursor_pos = tui.content_widget.cursor_offset();
-                self.unsaved_offset = self.cursor_pos.unwrap_or_default();
+                let mut contents = new_view.clone();
+                let start = self.unsaved_offset.min(contents.len());
+                let end = (start + self.unsaved_buf.len()).min(contents.len());
+                contents.drain(start..end);
+                self.content = Some(String::from_utf8_lossy(&contents).to_string());
             }
-            ContentState::Insert => {
-                // init pos if not init'd
-                match self.cursor_pos {
-                    Some(_) => (),
-                    None => {
-                        self.cursor_pos = Some(0);
+            _ => {
+                self.content = Some(String::from_utf8_lossy(&new_view).to_string());
+                self.unsaved_buf.clear();
+                self.unsaved_offset = cursor;
+
+                // retained edits are saved when leaving navigation mode
+                if let AppState::InContent(ContentState::Insert) = app_state {
+                    if new_view != remote_contents {
+                        *app_state = AppState::InContent(ContentState::Navigate);
+                        tui.in_content_navi();
                     }
-                };
+                }
+            }
+        }
 
-                match app_ev.code {
-                    // every escape updates the file
-                    KeyCode::Esc => {
-                        let v_file = match &self.v_file {
-                            Some(vf) => vf.clone(),
-                            None => return,
-                        };
+        match (resolution, &local) {
+            // without unsaved edits, the displayed lines only need the remote update
+            (ConflictResolution::TakeRemote | ConflictResolution::Merge, None) => {
+                tui.content_widget.apply_update(upd);
+                tui.content_widget.set_cursor_offset(cursor);
+            }
+            _ => self.update_content_disp(tui),
+        }
 
-                        let mut lock = v_file.lock().await;
-
-                        match self.unsaved_buf.len() {
-                            // do not update
-                            0 => (),
-                            _ => {
-                                let update = FileUpdate::Insert((
-                                    self.unsaved_offset,
-                                    self.unsaved_buf.as_bytes().to_vec(),
-                                ));
-
-                                // TODO: handle err here
-                                lock.write_bytes(update).await.unwrap();
-                                let new_contents = std::str::from_utf8(lock.local_cache()).unwrap();
-
-                                self.content = Some(new_contents.to_string());
-                                self.unsaved_buf.clear();
-                                self.unsaved_offset =
-                                    tui.content_widget.cursor_offset().unwrap_or_default();
-                                tui.content_widget.set_contents(Some(new_contents));
-                            }
-                        }
+        if let Some(offset) = highlight {
+            self.show_highlight(offset, remote.data.len(), Duration::from_secs(2), tui);
+        }
+    }
 
-                        // toggle insert mode
-                        *cont_state = ContentState::Navigate;
-                        tui.in_content_navi();
-                        // self.enqueue_render(tui);
+    /// Poll the current directory for changes in the background.
+    ///
+    /// Entries that are created, removed or renamed are also watched for, so the listing
+    /// is refreshed as soon as they change. Replaces any existing poll for a previous directory.
+    fn poll_dir_changes(&mut self, tui: &Tui) {
+        let (path, mut change_counter) = match self.fs_dirs.top() {
+            Some((dir, read_dir)) => (dir.clone(), read_dir.change_counter),
+            None => return,
+        };
+        self.watch_dir_entries(&path, tui);
+
+        let ctx = self.ctx.clone();
+        let ev_tx = tui.event_tx.clone();
+        let interval = match self.watch_dir {
+            true => DIR_WATCH_INTERVAL,
+            false => DIR_POLL_INTERVAL,
+        };
+
+        self.tasks.cancel(&TaskPurpose::PollDir);
+        self.tasks.spawn(TaskPurpose::PollDir, |token| async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = tokio::time::sleep(interval) => (),
+                }
+
+                match rfs::fs::dir_change_counter(ctx.clone(), &path).await {
+                    Ok(counter) if counter != change_counter => {
+                        change_counter = counter;
+                        if ev_tx
+                            .send(AppEvent::DirChanged {
+                                path: path.clone(),
+                                change_counter,
+                            })
+                            .is_err()
+                        {
+                            return;
+                        }
                     }
+                    Ok(_) => (),
+                    Err(e) => log::error!("failed to poll dir {}: {}", path, e),
+                }
+            }
+        });
+    }
+
+    /// Watch a directory for created, removed and renamed entries in the background,
+    /// forwarding them to the app.
+    ///
+    /// Replaces any existing watch on a previous directory.
+    fn watch_dir_entries(&mut self, path: &str, tui: &Tui) {
+        let ctx = self.ctx.clone();
+        let ev_tx = tui.event_tx.clone();
+        let path = path.to_string();
+
+        self.tasks.cancel(&TaskPurpose::WatchDir);
+        self.tasks.spawn(TaskPurpose::WatchDir, |token| async move {
+            // the change counter is still polled if the directory cannot be watched
+            let mut events = match rfs::fs::watch_dir(ctx, &path, false).await {
+                Ok(rx) => rx,
+                Err(e) => {
+                    log::error!("failed to watch dir {}: {}", path, e);
+                    return;
+                }
+            };
+
+            loop {
+                let event = tokio::select! {
+                    _ = token.cancelled() => return,
+                    ev = events.recv() => match ev {
+                        Some(ev) => ev,
+                        None => return,
+                    },
+                };
+
+                let changed = AppEvent::DirEntryChanged {
+                    path: path.clone(),
+                    event,
+                };
+                if ev_tx.send(changed).is_err() {
+                    return;
+                }
+            }
+        });
+    }
 
-                    KeyCode::Char(c) => {
-                        // insert char
-                        self.unsaved_buf.push(c);
-                        self.cursor_pos.as_mut().and_then(|p| Some(*p += 1));
-                        self.update_content_disp(tui);
+    /// Show a highlight on the content widget for a specified duration,
+    /// and then toggle it off.
+    fn show_highlight(&mut self, offset: usize, len: usize, dur: Duration, tui: &Tui) {
+        let ev_chan = tui.event_tx.clone();
+
+        self.tasks
+            .spawn(TaskPurpose::Highlight, |token| async move {
+                let _ = ev_chan.send(AppEvent::HighlightContent(Some((offset, len))));
+
+                tokio::select! {
+                    _ = token.cancelled() => (),
+                    _ = tokio::time::sleep(dur) => {
+                        let _ = ev_chan.send(AppEvent::HighlightContent(None));
                     }
+                }
+            });
+    }
 
-                    KeyCode::Enter => {
-                        // insert newline
-                        self.unsaved_buf.push('\n');
-                        self.cursor_pos.as_mut().and_then(|p| Some(*p += 1));
-                        self.update_content_disp(tui);
+    /// Highlight entries of the current directory for a specified duration,
+    /// and then toggle it off.
+    fn show_entry_highlight(&mut self, paths: Vec<String>, dur: Duration, tui: &Tui) {
+        let ev_chan = tui.event_tx.clone();
+
+        self.tasks
+            .spawn(TaskPurpose::Highlight, |token| async move {
+                let _ = ev_chan.send(AppEvent::HighlightEntries(paths));
+
+                tokio::select! {
+                    _ = token.cancelled() => (),
+                    _ = tokio::time::sleep(dur) => {
+                        let _ = ev_chan.send(AppEvent::HighlightEntries(Vec::new()));
                     }
+                }
+            });
+    }
 
-                    KeyCode::Backspace => {
-                        self.unsaved_buf.pop();
-                        self.cursor_pos.as_mut().and_then(|p| Some(*p -= 1));
-                        self.update_content_disp(tui);
+    /// Subscribe to a topic in the background, forwarding its messages to the app.
+    ///
+    /// The subscription ends when the app shuts down its tasks, or the remote closes it.
+    fn subscribe(&mut self, topic: &str, tui: &Tui) {
+        let ctx = self.ctx.clone();
+        let ev_tx = tui.event_tx.clone();
+        let topic = topic.to_string();
+
+        self.tasks.spawn(
+            TaskPurpose::Subscription(topic.clone()),
+            |token| async move {
+                let mut messages = match rfs::topics::subscribe(ctx, &topic).await {
+                    Ok(rx) => rx,
+                    Err(e) => {
+                        log::error!("failed to subscribe to {}: {}", topic, e);
+                        return;
                     }
+                };
+
+                loop {
+                    let message = tokio::select! {
+                        _ = token.cancelled() => return,
+                        msg = messages.recv() => match msg {
+                            Some(m) => m,
+                            None => return,
+                        },
+                    };
 
-                    _ => (),
+                    if ev_tx.send(AppEvent::TopicMessage(message)).is_err() {
+                        return;
+                    }
                 }
-            }
-            // spawns a watch channel
-            ContentState::Watch => {
-                //a ad
-            }
+            },
+        );
+    }
 
-            _ => unimplemented!(),
-        }
+    /// Returns the contents as displayed, including unsaved insertions.
+    fn local_view(&self) -> Vec<u8> {
+        let upd = FileUpdate::Insert((self.unsaved_offset, self.unsaved_buf.as_bytes().to_vec()));
+        upd.update_file(self.content.as_deref().unwrap_or("").as_bytes())
     }
 
     /// Update content widget with the current content, offset and unsaved buf.
@@ -1156,48 +2227,6 @@ impl AppData {
     }
 }
 
-#[async_trait]
-impl HandleStateEvent for AppState {
-    async fn handle_event(&mut self, event: KeyEvent, app_data: &mut AppData, tui: &mut Tui) {
-        match self {
-            AppState::OnContent => todo!(),
-            AppState::InContent(inner) => inner.handle_event(event, app_data, tui).await,
-            AppState::OnFileSystem => todo!(),
-            AppState::InFileSystem(inner) => inner.handle_event(event, app_data, tui).await,
-        }
-    }
-}
-
-#[async_trait]
-impl HandleStateEvent for ContentState {
-    async fn handle_event(&mut self, event: KeyEvent, app_data: &mut AppData, tui: &mut Tui) {
-        match self {
-            _ => unimplemented!(),
-        }
-    }
-}
-
-#[async_trait]
-impl HandleStateEvent for FsState {
-    async fn handle_event(&mut self, event: KeyEvent, app_data: &mut AppData, tui: &mut Tui) {
-        match self {
-            _ => unimplemented!(),
-        }
-    }
-}
-
-// impl HandleStateEvent for AppData {
-
-//     async fn handle_event(&mut self, state: &mut Self::State, event: KeyEvent, tui: &mut Tui) {
-//         match state {
-//             AppState::OnContent => todo!(),
-//             AppState::InContent(inner) => todo!(),
-//             AppState::OnFileSystem => todo!(),
-//             AppState::InFileSystem(inner) => todo!(),
-//         }
-//     }
-// }
-
 impl<T> FixedSizeStack<T> {
     pub fn new(size: Option<usize>) -> Self {
         Self {
@@ -1232,6 +2261,16 @@ impl<T> FixedSizeStack<T> {
         self.stack.last()
     }
 
+    /// Iterate over the stack, from the bottom
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.stack.iter()
+    }
+
+    /// Get a mutable reference to an element, counted from the bottom
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.stack.get_mut(idx)
+    }
+
     /// Get the current depth of the stack
     /// (same as the number of elements in the stack)
     pub fn depth(&self) -> usize {
@@ -1239,14 +2278,127 @@ impl<T> FixedSizeStack<T> {
     }
 }
 
-/// Checks if a string is a valid path segment (filename or directory name)
-fn is_valid_fs_path_segment(s: &str) -> bool {
-    s.chars()
-        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
-    // && match s.chars().next() {
-    //     Some(c) => !c.is_ascii_digit(),
-    //     None => true,
-    // }
+/// How the open file is used in a state of the app
+fn open_mode(app_state: &AppState) -> OpenMode {
+    match app_state {
+        AppState::InContent(ContentState::Insert) => OpenMode::Editing,
+        _ => OpenMode::Viewing,
+    }
+}
+
+/// Summarizes the other clients that have the open file, e.g. `2 other clients: 1 viewing, 1 editing`
+fn presence_title(watchers: &[Watcher]) -> Option<String> {
+    let editing = watchers
+        .iter()
+        .filter(|w| w.mode == OpenMode::Editing)
+        .count();
+    let viewing = watchers.len() - editing;
+
+    let activity = [(viewing, "viewing"), (editing, "editing")]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, mode)| format!("{} {}", count, mode))
+        .collect::<Vec<_>>();
+
+    match watchers.len() {
+        0 => None,
+        1 => Some(format!("1 other client: {}", activity.join(", "))),
+        n => Some(format!("{} other clients: {}", n, activity.join(", "))),
+    }
+}
+
+/// Title of the content window, marking read-only files before the presence summary
+fn content_title(readonly: bool, presence: Option<String>) -> Option<String> {
+    match (readonly, presence) {
+        (true, Some(p)) => Some(format!("{} | {}", READONLY_INDICATOR, p)),
+        (true, None) => Some(READONLY_INDICATOR.to_string()),
+        (false, p) => p,
+    }
+}
+
+/// Directories from the base of the remote down to a typed path, checked against the path policy.
+fn goto_dirs(input: &str) -> Result<Vec<VirtPath>, PathViolation> {
+    PathPolicy::default().check_path(input)?;
+
+    let target = VirtPath::from(input);
+    let dirs = target
+        .segments()
+        .scan(VirtPath::base(), |dir, segment| {
+            *dir = dir.join(segment);
+            Some(dir.clone())
+        })
+        .collect::<Vec<_>>();
+
+    Ok([VirtPath::base()].into_iter().chain(dirs).collect())
+}
+
+/// Cursor offset of a typed position in the contents of a file.
+///
+/// Positions are `line[:col]`, counted from 1, or `#offset`. Positions past the end
+/// of a line or of the contents are moved back to its end.
+fn goto_offset(contents: &str, input: &str) -> Result<usize, String> {
+    let input = input.trim();
+
+    if let Some(offset) = input.strip_prefix('#') {
+        let offset = offset
+            .parse::<usize>()
+            .map_err(|_| format!("invalid offset: {}", offset))?;
+        return Ok(offset.min(contents.len()));
+    }
+
+    let number = |s: &str, what: &str| {
+        s.parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("invalid {}: {}", what, s))
+    };
+    let (line, col) = match input.split_once(':') {
+        Some((line, col)) => (number(line, "line")?, number(col, "column")?),
+        None => (number(input, "line")?, 1),
+    };
+
+    let lines = contents.split('\n').collect::<Vec<_>>();
+    let line = (line - 1).min(lines.len() - 1);
+    let line_start = lines[..line].iter().map(|l| l.len() + 1).sum::<usize>();
+
+    Ok(line_start + (col - 1).min(lines[line].len()))
+}
+
+/// Checks if a directory directly contains a path.
+///
+/// Both paths are relative to the remote base, and `.` segments are ignored.
+fn is_parent_dir(dir: &str, path: &str) -> bool {
+    VirtPath::from(path).parent() == Some(VirtPath::from(dir))
+}
+
+/// Checks if a directory contains a path, directly or in a subdirectory.
+fn is_ancestor_dir(dir: &str, path: &str) -> bool {
+    let path = VirtPath::from(path);
+    path != VirtPath::from(dir) && path.starts_with(&VirtPath::from(dir))
+}
+
+/// Paths of entries in a directory listing that are not in an earlier listing
+fn added_entries(before: &VirtReadDir, after: &VirtReadDir) -> Vec<String> {
+    after
+        .entries
+        .iter()
+        .filter(|e| !before.entries.iter().any(|b| b.path == e.path))
+        .map(|e| e.path.clone())
+        .collect()
+}
+
+/// Warning shown when the remote runs a different minor version or interfaces than the client
+fn version_warning(local: &VersionInfo, remote: &VersionInfo) -> Option<String> {
+    match local.compare(remote) {
+        VersionMatch::Compatible => None,
+        VersionMatch::InterfaceMismatch => {
+            Some("remote was built with different interfaces than the client".to_string())
+        }
+        _ => Some(format!(
+            "remote is version {}, client is version {}",
+            remote.crate_version, local.crate_version
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -1255,20 +2407,136 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_validate_filesystem_string() {
-        assert!(is_valid_fs_path_segment("valid_string.txt"));
-        assert!(is_valid_fs_path_segment("0valid_string1"));
-        assert!(is_valid_fs_path_segment("a"));
-        assert!(is_valid_fs_path_segment("asd"));
-
-        assert!(!is_valid_fs_path_segment("invalid_string%.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string>.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string<.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string|.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string*.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string?.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string:.asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string\".asd"));
-        assert!(!is_valid_fs_path_segment("invalid_string\\.asd"));
+    fn test_is_parent_dir() {
+        assert!(is_parent_dir(".", "file.txt"));
+        assert!(is_parent_dir(".", "./file.txt"));
+        assert!(is_parent_dir("nested", "nested/file.txt"));
+        assert!(is_parent_dir("./nested", "nested/file.txt"));
+
+        assert!(!is_parent_dir(".", "nested/file.txt"));
+        assert!(!is_parent_dir("nested", "file.txt"));
+    }
+
+    #[test]
+    fn test_added_entries() {
+        let listing = |names: &[&str]| VirtReadDir {
+            entries: names
+                .iter()
+                .map(|n| rfs::fs::VirtDirEntry {
+                    path: n.to_string(),
+                    file: true,
+                    link: None,
+                })
+                .collect(),
+            change_counter: 0,
+        };
+
+        assert_eq!(
+            added_entries(&listing(&["a", "b"]), &listing(&["b", "c", "a", "d"])),
+            vec!["c".to_string(), "d".to_string()]
+        );
+        assert!(added_entries(&listing(&["a", "b"]), &listing(&["a"])).is_empty());
+    }
+
+    #[test]
+    fn test_goto_dirs() {
+        let dirs =
+            |input| goto_dirs(input).map(|d| d.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(dirs("a/b/"), Ok(vec![".".into(), "a".into(), "a/b".into()]));
+        assert_eq!(
+            dirs("./a//b"),
+            Ok(vec![".".into(), "a".into(), "a/b".into()])
+        );
+        assert_eq!(dirs(""), Ok(vec![".".into()]));
+        assert_eq!(dirs("a/../b"), Err(PathViolation::Reserved("..".into())));
+        assert_eq!(dirs("a b"), Err(PathViolation::InvalidChar(' ')));
+    }
+
+    #[test]
+    fn test_goto_offset() {
+        let contents = "first\nsecond line\n\nlast";
+
+        assert_eq!(goto_offset(contents, "1"), Ok(0));
+        assert_eq!(goto_offset(contents, "2"), Ok(6));
+        assert_eq!(goto_offset(contents, "2:8"), Ok(13));
+        assert_eq!(goto_offset(contents, "3:5"), Ok(18));
+        assert_eq!(goto_offset(contents, "99"), Ok(19));
+        assert_eq!(goto_offset(contents, "4:99"), Ok(contents.len()));
+        assert_eq!(goto_offset(contents, "#7"), Ok(7));
+        assert_eq!(goto_offset(contents, "#1000"), Ok(contents.len()));
+
+        assert!(goto_offset(contents, "0").is_err());
+        assert!(goto_offset(contents, "2:").is_err());
+        assert!(goto_offset(contents, "#x").is_err());
+        assert!(goto_offset("", "1").is_ok());
+    }
+
+    #[test]
+    fn test_is_ancestor_dir() {
+        assert!(is_ancestor_dir(".", "file.txt"));
+        assert!(is_ancestor_dir(".", "nested/deeper/file.txt"));
+        assert!(is_ancestor_dir("./nested", "nested/deeper/file.txt"));
+
+        assert!(!is_ancestor_dir("nested", "nested"));
+        assert!(!is_ancestor_dir("nested", "nested_other/file.txt"));
+        assert!(!is_ancestor_dir("nested/deeper", "nested/file.txt"));
+    }
+
+    #[test]
+    fn test_presence_title() {
+        let watcher = |mode| Watcher {
+            client: rfs::middleware::ClientId::random(),
+            mode,
+        };
+
+        assert_eq!(presence_title(&[]), None);
+        assert_eq!(
+            presence_title(&[watcher(OpenMode::Editing)]).unwrap(),
+            "1 other client: 1 editing"
+        );
+        assert_eq!(
+            presence_title(&[
+                watcher(OpenMode::Viewing),
+                watcher(OpenMode::Editing),
+                watcher(OpenMode::Viewing)
+            ])
+            .unwrap(),
+            "3 other clients: 2 viewing, 1 editing"
+        );
+    }
+
+    #[test]
+    fn test_content_title() {
+        assert_eq!(content_title(false, None), None);
+        assert_eq!(
+            content_title(false, Some("1 other client: 1 editing".to_string())).unwrap(),
+            "1 other client: 1 editing"
+        );
+        assert_eq!(content_title(true, None).unwrap(), READONLY_INDICATOR);
+        assert_eq!(
+            content_title(true, Some("1 other client: 1 viewing".to_string())).unwrap(),
+            format!("{} | 1 other client: 1 viewing", READONLY_INDICATOR)
+        );
+    }
+
+    #[test]
+    fn test_focus_transitions() {
+        let mut focus = AppState::InContent(ContentState::Insert).focus();
+
+        focus.ingest(AppEvents::EscKey);
+        assert_eq!(focus, Focus::OnContent);
+        focus.ingest(AppEvents::LeftArrowKey);
+        assert_eq!(focus, Focus::OnFileSystem);
+
+        // unrelated keys do not move focus
+        focus.ingest(AppEvents::Char('x'));
+        assert_eq!(focus, Focus::OnFileSystem);
+
+        focus.ingest(AppEvents::EnterKey);
+        assert!(matches!(
+            AppState::from(focus),
+            AppState::InFileSystem(FsState::Navigate)
+        ));
     }
 }
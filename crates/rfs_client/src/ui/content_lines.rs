@@ -0,0 +1,176 @@
+//! File contents split into lines for display.
+
+use std::{ops::Range, sync::Arc};
+
+use ratatui::text::Line;
+use rfs::interfaces::FileUpdate;
+
+/// File contents, split into lines once when set.
+///
+/// Cloning is cheap, so the content widget can be cloned every frame.
+#[derive(Clone, Debug, Default)]
+pub struct ContentLines(Arc<Vec<Line<'static>>>);
+
+impl ContentLines {
+    pub fn new(contents: &str) -> Self {
+        Self(Arc::new(split_lines(contents)))
+    }
+
+    /// Number of lines
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the text of a line
+    pub fn get(&self, idx: usize) -> Option<&str> {
+        self.0.get(idx).map(line_text)
+    }
+
+    /// Returns the text of the last line
+    pub fn last(&self) -> Option<&str> {
+        self.0.last().map(line_text)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(line_text)
+    }
+
+    /// Returns the contents as a single string
+    pub fn text(&self) -> String {
+        self.iter().collect::<Vec<_>>().join("\n")
+    }
+
+    /// Apply an update to the contents.
+    ///
+    /// Only the lines touched by an insert or append are split again.
+    pub fn apply(&mut self, update: &FileUpdate) {
+        let (offset, data) = match update {
+            FileUpdate::Overwrite(data) => {
+                *self = Self::new(&String::from_utf8_lossy(data));
+                return;
+            }
+            FileUpdate::WriteAt(_) | FileUpdate::Diff(_) => {
+                let contents = update.clone().update_file(self.text().as_bytes());
+                *self = Self::new(&String::from_utf8_lossy(&contents));
+                return;
+            }
+            FileUpdate::Restarted => return,
+            FileUpdate::Append(data) => (usize::MAX, data),
+            FileUpdate::Insert((offset, data)) => (*offset, data),
+        };
+
+        let (line_idx, col) = self.position(offset);
+        let joined = match self.get(line_idx) {
+            Some(line) if line.is_char_boundary(col) => format!(
+                "{}{}{}",
+                &line[..col],
+                String::from_utf8_lossy(data),
+                &line[col..]
+            ),
+            // offsets inside a character cannot be applied to the split lines
+            Some(_) => {
+                let contents = update.clone().update_file(self.text().as_bytes());
+                *self = Self::new(&String::from_utf8_lossy(&contents));
+                return;
+            }
+            None => String::from_utf8_lossy(data).to_string(),
+        };
+
+        let lines = Arc::make_mut(&mut self.0);
+        match line_idx < lines.len() {
+            true => {
+                lines.splice(line_idx..=line_idx, split_lines(&joined));
+            }
+            false => lines.extend(split_lines(&joined)),
+        }
+    }
+
+    /// Returns the line and byte column of a byte offset in the contents.
+    ///
+    /// Offsets past the end are placed at the end of the last line.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let mut remaining = offset;
+
+        for (idx, line) in self.iter().enumerate() {
+            if remaining <= line.len() {
+                return (idx, remaining);
+            }
+
+            // account for the newline
+            remaining -= line.len() + 1;
+        }
+
+        (
+            self.len().saturating_sub(1),
+            self.last().map(|l| l.len()).unwrap_or_default(),
+        )
+    }
+}
+
+/// Returns the range of lines visible in a viewport, keeping the selected line
+/// and `bottom_padding` lines below it in view.
+pub fn visible_range(
+    num_lines: usize,
+    selected: usize,
+    viewport: usize,
+    bottom_padding: usize,
+) -> Range<usize> {
+    let start = match num_lines > viewport {
+        true => (selected + 1 + bottom_padding).saturating_sub(viewport),
+        false => 0,
+    };
+
+    start..(start + viewport).min(num_lines)
+}
+
+fn split_lines(contents: &str) -> Vec<Line<'static>> {
+    contents
+        .split('\n')
+        .map(|l| Line::raw(l.to_string()))
+        .collect()
+}
+
+fn line_text<'a>(line: &'a Line<'static>) -> &'a str {
+    line.spans
+        .first()
+        .map(|s| s.content.as_ref())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_updates() {
+        let base = "first\nsecond\nthird";
+        let updates = [
+            FileUpdate::Insert((8, b"--\nnew\n".to_vec())),
+            FileUpdate::Insert((0, b"0".to_vec())),
+            FileUpdate::Append(b"\nlast".to_vec()),
+            FileUpdate::Insert((500, b"!".to_vec())),
+            FileUpdate::Overwrite(b"replaced\n".to_vec()),
+        ];
+
+        let mut lines = ContentLines::new(base);
+        let mut expected = base.as_bytes().to_vec();
+        for upd in updates.iter() {
+            lines.apply(upd);
+            expected = upd.clone().update_file(&expected);
+
+            assert_eq!(lines.text().as_bytes(), expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_visible_range() {
+        assert_eq!(visible_range(5, 4, 10, 2), 0..5);
+        assert_eq!(visible_range(100, 3, 10, 2), 0..10);
+        assert_eq!(visible_range(100, 50, 10, 2), 43..53);
+        assert_eq!(visible_range(100, 99, 10, 2), 92..100);
+    }
+}
@@ -0,0 +1,74 @@
+//! Recorded key sequences, replayed to repeat edits.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+
+/// A key that can be recorded in a macro.
+///
+/// Only keys that are bound to an action are recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MacroKey {
+    Char(char),
+    Enter,
+    Esc,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl MacroKey {
+    /// Convert a key event to a recordable key, if it is one.
+    pub fn from_event(key: KeyEvent) -> Option<Self> {
+        let macro_key = match key.code {
+            KeyCode::Char(c) => Self::Char(c),
+            KeyCode::Enter => Self::Enter,
+            KeyCode::Esc => Self::Esc,
+            KeyCode::Backspace => Self::Backspace,
+            KeyCode::Delete => Self::Delete,
+            KeyCode::Up => Self::Up,
+            KeyCode::Down => Self::Down,
+            KeyCode::Left => Self::Left,
+            KeyCode::Right => Self::Right,
+            _ => return None,
+        };
+
+        Some(macro_key)
+    }
+
+    /// Key event to replay
+    pub fn to_event(self) -> KeyEvent {
+        let code = match self {
+            Self::Char(c) => KeyCode::Char(c),
+            Self::Enter => KeyCode::Enter,
+            Self::Esc => KeyCode::Esc,
+            Self::Backspace => KeyCode::Backspace,
+            Self::Delete => KeyCode::Delete,
+            Self::Up => KeyCode::Up,
+            Self::Down => KeyCode::Down,
+            Self::Left => KeyCode::Left,
+            Self::Right => KeyCode::Right,
+        };
+
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_key_events() {
+        for key in [MacroKey::Char('a'), MacroKey::Enter, MacroKey::Delete] {
+            assert_eq!(MacroKey::from_event(key.to_event()), Some(key));
+        }
+
+        assert_eq!(
+            MacroKey::from_event(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE)),
+            None
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Queue of notifications and errors shown to the user.
+//!
+//! Messages are shown one at a time, most severe first. A message is dismissed
+//! once it has been shown for its duration, so messages arriving in bursts are not lost.
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// How important a message is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Shown in the border of the content widget
+    Info,
+
+    /// Shown in a pop-up
+    Warning,
+
+    /// Shown in a pop-up
+    Error,
+}
+
+impl Severity {
+    /// How long messages of this severity are shown by default
+    pub fn duration(&self) -> Duration {
+        match self {
+            Self::Info => Duration::from_secs(2),
+            Self::Warning => Duration::from_secs(4),
+            Self::Error => Duration::from_secs(8),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A message waiting to be shown
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub severity: Severity,
+    pub text: String,
+
+    /// How long the message is shown before it is dismissed
+    pub duration: Duration,
+}
+
+impl Message {
+    /// Create a message shown for the duration of its severity
+    pub fn new<M: ToString>(severity: Severity, text: M) -> Self {
+        Self {
+            severity,
+            text: text.to_string(),
+            duration: severity.duration(),
+        }
+    }
+
+    /// Show the message for a different duration
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+}
+
+/// Pending messages, ordered by severity and then by arrival
+#[derive(Debug, Default)]
+pub struct MessageQueue {
+    pending: Vec<Message>,
+
+    /// Index of the shown message
+    shown: usize,
+
+    /// When the shown message was first displayed
+    shown_since: Option<Instant>,
+}
+
+impl MessageQueue {
+    /// Queue a message behind pending messages of the same or higher severity.
+    ///
+    /// The shown message is never replaced by a new one.
+    pub fn push(&mut self, message: Message) {
+        let start = match self.pending.is_empty() {
+            true => 0,
+            false => self.shown + 1,
+        };
+
+        let pos = self.pending[start..]
+            .iter()
+            .position(|m| m.severity < message.severity)
+            .map(|p| start + p)
+            .unwrap_or(self.pending.len());
+
+        self.pending.insert(pos, message);
+    }
+
+    /// Shown message, its position and the number of pending messages
+    pub fn current(&self) -> Option<(&Message, usize, usize)> {
+        self.pending
+            .get(self.shown)
+            .map(|m| (m, self.shown, self.pending.len()))
+    }
+
+    /// Show the next pending message. The shown message stays in the queue.
+    pub fn page(&mut self) {
+        if self.pending.len() > 1 {
+            self.shown = (self.shown + 1) % self.pending.len();
+            self.shown_since = None;
+        }
+    }
+
+    /// Dismiss the shown message if it has been shown for its duration.
+    ///
+    /// The timer of a message starts on the first call after it is shown.
+    /// Returns `true` if a message was dismissed.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let duration = match self.pending.get(self.shown) {
+            Some(m) => m.duration,
+            None => return false,
+        };
+
+        let since = match self.shown_since {
+            Some(s) => s,
+            None => {
+                self.shown_since = Some(now);
+                return false;
+            }
+        };
+
+        match now.duration_since(since) >= duration {
+            true => {
+                self.pending.remove(self.shown);
+                if self.shown >= self.pending.len() {
+                    self.shown = 0;
+                }
+                self.shown_since = None;
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(queue: &MessageQueue) -> Vec<&str> {
+        queue.pending.iter().map(|m| m.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_message_queue() {
+        let mut queue = MessageQueue::default();
+        queue.push(Message::new(Severity::Info, "first"));
+        queue.push(Message::new(Severity::Info, "second"));
+        queue.push(Message::new(Severity::Error, "failed"));
+        queue.push(Message::new(Severity::Warning, "careful"));
+
+        // the shown message stays in front
+        assert_eq!(texts(&queue), ["first", "failed", "careful", "second"]);
+
+        let start = Instant::now();
+        assert!(!queue.expire(start));
+        assert!(!queue.expire(start + Duration::from_secs(1)));
+        assert!(queue.expire(start + Duration::from_secs(2)));
+
+        // the timer of the next message starts once it is shown
+        let (message, pos, total) = queue.current().unwrap();
+        assert_eq!((message.text.as_str(), pos, total), ("failed", 0, 3));
+        assert!(!queue.expire(start + Duration::from_secs(3)));
+        assert!(!queue.expire(start + Duration::from_secs(10)));
+
+        queue.page();
+        assert_eq!(queue.current().unwrap().0.text, "careful");
+        queue.page();
+        queue.page();
+        assert_eq!(queue.current().unwrap().0.text, "failed");
+
+        let custom = Message::new(Severity::Info, "topic").with_duration(Duration::from_secs(5));
+        assert_eq!(custom.duration, Duration::from_secs(5));
+    }
+}
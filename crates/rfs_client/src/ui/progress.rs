@@ -0,0 +1,113 @@
+//! Retry progress of remote calls.
+//!
+//! Remote calls made while handling a key block the app loop.
+//! Awaiting them with [with_progress] keeps drawing a spinner while the call is retrying.
+
+use std::{future::Future, time::Duration};
+
+use rfs::middleware::InvokeProgress;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::tui::Tui;
+
+/// Spinner frames shown while a remote call is retrying
+const SPINNER: [char; 8] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧'];
+
+/// Time between spinner frames
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Await a remote call, showing its retry progress in the content window notification area.
+pub async fn with_progress<F: Future>(
+    progress: &mut broadcast::Receiver<InvokeProgress>,
+    tui: &mut Tui,
+    call: F,
+) -> F::Output {
+    // progress of earlier calls is no longer relevant
+    *progress = progress.resubscribe();
+
+    tokio::pin!(call);
+    let mut spinner = tokio::time::interval(SPINNER_INTERVAL);
+    let mut retrying: Option<InvokeProgress> = None;
+    let mut frame = 0;
+    let mut shown = false;
+
+    let res = loop {
+        tokio::select! {
+            res = &mut call => break res,
+            ev = progress.recv() => match ev {
+                Ok(InvokeProgress::Finished) => retrying = None,
+                Ok(p) => retrying = Some(p),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break call.await,
+            },
+            _ = spinner.tick(), if retrying.is_some() => frame += 1,
+        }
+
+        // other notifications are left alone unless the call retries
+        match (&retrying, shown) {
+            (None, false) => continue,
+            (p, _) => {
+                shown = p.is_some();
+                tui.content_widget
+                    .set_notification(p.as_ref().and_then(|p| progress_message(p, frame)));
+            }
+        }
+
+        if let Err(e) = tui.draw_to_screen().await {
+            log::error!("failed to draw retry progress: {}", e);
+        }
+    };
+
+    // the call may complete before its final progress event is received
+    if shown {
+        tui.content_widget.set_notification(Option::<&str>::None);
+    }
+
+    res
+}
+
+/// Returns the notification for a retrying call, at a spinner frame
+fn progress_message(progress: &InvokeProgress, frame: usize) -> Option<String> {
+    match progress {
+        InvokeProgress::Retrying {
+            attempt,
+            max_attempts,
+            remaining,
+            ..
+        } => Some(format!(
+            "{} retrying {}/{} ({:.1}s left)",
+            SPINNER[frame % SPINNER.len()],
+            attempt,
+            max_attempts,
+            remaining.as_secs_f32()
+        )),
+        InvokeProgress::Finished => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rfs::middleware::RetryReason;
+
+    use super::*;
+
+    #[test]
+    fn test_progress_message() {
+        let retrying = InvokeProgress::Retrying {
+            attempt: 2,
+            max_attempts: 3,
+            remaining: Duration::from_millis(1500),
+            reason: RetryReason::Timeout,
+        };
+
+        assert_eq!(
+            progress_message(&retrying, 0).as_deref(),
+            Some("⠋ retrying 2/3 (1.5s left)")
+        );
+        assert_eq!(
+            progress_message(&retrying, SPINNER.len() + 1).as_deref(),
+            Some("⠙ retrying 2/3 (1.5s left)")
+        );
+        assert_eq!(progress_message(&InvokeProgress::Finished, 0), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Persisted app sessions.
+//!
+//! The session is saved when the app exits, and restored on the next start.
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::key_macro::MacroKey;
+
+/// Where the user left off
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Stack of open directories, starting from the remote base
+    pub dirs: Vec<String>,
+
+    /// Selected entry in the top directory
+    pub filesystem_pos: usize,
+
+    /// File open in the content widget
+    pub open_file: Option<String>,
+
+    /// Cursor offset in the open file
+    pub cursor_pos: Option<usize>,
+
+    /// Last cursor offset in each file that was opened, restored when it is opened again
+    #[serde(default)]
+    pub cursors: BTreeMap<String, usize>,
+
+    /// Last recorded key macro
+    #[serde(default)]
+    pub key_macro: Vec<MacroKey>,
+}
+
+impl Session {
+    /// Load a session from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        rfs::ser_de::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Save the session to a file, replacing any previous session
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let bytes = rfs::ser_de::serialize(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        std::fs::write(path, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let path = std::env::temp_dir().join(format!("rfs_client-{}.session", std::process::id()));
+
+        let session = Session {
+            dirs: vec![".".to_string(), "nested".to_string()],
+            filesystem_pos: 3,
+            open_file: Some("nested/file.txt".to_string()),
+            cursor_pos: Some(12),
+            cursors: BTreeMap::from([
+                ("nested/file.txt".to_string(), 12),
+                ("other".to_string(), 3),
+            ]),
+            key_macro: vec![MacroKey::Char('x'), MacroKey::Down, MacroKey::Enter],
+        };
+
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, session);
+    }
+}
@@ -0,0 +1,175 @@
+//! Background task registry.
+//!
+//! Long-running tasks spawned by the client (file watches, etc.) are registered here
+//! so that they can be cancelled and awaited when the application exits.
+
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// The purpose of a background task.
+///
+/// Tasks sharing the same purpose share the same cancellation token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TaskPurpose {
+    /// Watches a remote file for updates
+    Watch(String),
+
+    /// Polls the current directory for changes
+    PollDir,
+
+    /// Receives created, removed and renamed entries of the current directory
+    WatchDir,
+
+    /// Receives messages published to a topic
+    Subscription(String),
+
+    /// Reads terminal events
+    Input,
+
+    /// Turns off highlights after they are shown
+    Highlight,
+}
+
+/// Tasks registered under a single purpose
+#[derive(Debug)]
+struct TaskGroup {
+    token: CancellationToken,
+    handles: Vec<JoinHandle<()>>,
+}
+
+/// Registry of background tasks spawned by the client.
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    groups: HashMap<TaskPurpose, TaskGroup>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a task under a purpose.
+    ///
+    /// The task is given a child of the purpose's cancellation token and
+    /// should exit once the token is cancelled. The returned handle aborts the task.
+    pub fn spawn<F, Fut>(&mut self, purpose: TaskPurpose, task: F) -> AbortHandle
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let group = self.groups.entry(purpose).or_insert_with(|| TaskGroup {
+            token: CancellationToken::new(),
+            handles: Vec::new(),
+        });
+
+        // drop handles of tasks that have already completed
+        group.handles.retain(|h| !h.is_finished());
+
+        let handle = tokio::spawn(task(group.token.child_token()));
+        let abort_handle = handle.abort_handle();
+        group.handles.push(handle);
+
+        abort_handle
+    }
+
+    /// Checks if there are running tasks for a purpose
+    pub fn is_running(&self, purpose: &TaskPurpose) -> bool {
+        match self.groups.get(purpose) {
+            Some(group) => group.handles.iter().any(|h| !h.is_finished()),
+            None => false,
+        }
+    }
+
+    /// Cancel all tasks registered under a purpose.
+    ///
+    /// Cancelled tasks are not awaited.
+    pub fn cancel(&mut self, purpose: &TaskPurpose) {
+        if let Some(group) = self.groups.remove(purpose) {
+            group.token.cancel();
+        }
+    }
+
+    /// Cancel all registered tasks and wait for them to complete.
+    ///
+    /// Tasks that do not complete within the timeout are aborted.
+    pub async fn shutdown(&mut self, timeout: Duration) {
+        let handles = self
+            .groups
+            .drain()
+            .flat_map(|(purpose, group)| {
+                log::debug!(
+                    "cancelling {} task(s) for {:?}",
+                    group.handles.len(),
+                    purpose
+                );
+                group.token.cancel();
+                group.handles
+            })
+            .collect::<Vec<_>>();
+
+        let abort_handles = handles.iter().map(|h| h.abort_handle()).collect::<Vec<_>>();
+
+        match tokio::time::timeout(timeout, futures::future::join_all(handles)).await {
+            Ok(_) => log::debug!("all background tasks completed"),
+            Err(_) => {
+                log::error!(
+                    "background tasks did not complete in {:?}, aborting",
+                    timeout
+                );
+                abort_handles.iter().for_each(|h| h.abort());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_tasks() {
+        let mut registry = TaskRegistry::new();
+        let purpose = TaskPurpose::Watch("some/file".to_string());
+
+        registry.spawn(purpose.clone(), |token| async move {
+            token.cancelled().await;
+        });
+        registry.spawn(
+            TaskPurpose::Watch("other/file".to_string()),
+            |token| async move {
+                token.cancelled().await;
+            },
+        );
+        assert!(registry.is_running(&purpose));
+
+        // tasks exit on cancellation, so this should not time out
+        tokio::time::timeout(
+            Duration::from_millis(500),
+            registry.shutdown(Duration::from_millis(100)),
+        )
+        .await
+        .expect("shutdown should not block");
+
+        assert!(!registry.is_running(&purpose));
+        assert!(!registry.is_running(&TaskPurpose::Watch("other/file".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_stuck_tasks() {
+        let mut registry = TaskRegistry::new();
+
+        // ignores the cancellation token
+        registry.spawn(
+            TaskPurpose::Watch("some/file".to_string()),
+            |_| async move {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            },
+        );
+
+        let start = std::time::Instant::now();
+        registry.shutdown(Duration::from_millis(50)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
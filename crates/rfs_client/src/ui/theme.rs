@@ -0,0 +1,215 @@
+//! Styling of filesystem entries.
+//!
+//! Themes are loaded from a TOML file:
+//!
+//! ```toml
+//! icons = true
+//!
+//! [dir]
+//! fg = "blue"
+//! bold = true
+//!
+//! [extensions.rs]
+//! fg = "#dea584"
+//! icon = "\ue7a8"
+//! ```
+
+use std::{collections::HashMap, io, path::Path, str::FromStr};
+
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::Span,
+};
+use rfs::fs::VirtDirEntry;
+use serde::Deserialize;
+
+/// Nerd font folder icon, used when icons are enabled and no icon is configured
+const DEFAULT_DIR_ICON: &str = "\u{f07b}";
+
+/// Nerd font file icon, used when icons are enabled and no icon is configured
+const DEFAULT_FILE_ICON: &str = "\u{f15b}";
+
+/// Styles and icons of entries in the filesystem tree
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FsTheme {
+    /// Prefix entries with icons. Requires a nerd font.
+    pub icons: bool,
+
+    pub dir: EntryStyle,
+
+    pub file: EntryStyle,
+
+    /// File styles by extension, without the leading dot.
+    /// Unset fields fall back to the `file` style.
+    pub extensions: HashMap<String, EntryStyle>,
+}
+
+/// Style of a kind of entry
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EntryStyle {
+    /// Foreground color, as a name (`green`) or a hex code (`#00ff00`)
+    pub fg: Option<String>,
+
+    pub bold: Option<bool>,
+
+    /// Shown before the entry name when icons are enabled
+    pub icon: Option<String>,
+}
+
+impl Default for FsTheme {
+    fn default() -> Self {
+        Self {
+            icons: false,
+            dir: EntryStyle {
+                fg: Some("green".to_string()),
+                bold: Some(true),
+                icon: None,
+            },
+            file: Default::default(),
+            extensions: Default::default(),
+        }
+    }
+}
+
+impl FsTheme {
+    /// Load a theme from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns the name of an entry, styled according to the theme.
+    ///
+    /// Symbolic links are followed by their target, and italicized.
+    pub fn span(&self, entry: &VirtDirEntry) -> Span<'static> {
+        let name = entry
+            .path()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let name = match entry.link_target() {
+            Some(target) => format!("{} -> {}", name, target),
+            None => name,
+        };
+
+        let (style, icon) = match entry.is_file() {
+            true => {
+                let ext = entry
+                    .path()
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_lowercase());
+
+                match ext.and_then(|e| self.extensions.get(&e)) {
+                    Some(ext_style) => (
+                        ext_style.style_over(&self.file),
+                        ext_style
+                            .icon
+                            .as_deref()
+                            .or(self.file.icon.as_deref())
+                            .unwrap_or(DEFAULT_FILE_ICON),
+                    ),
+                    None => (
+                        self.file.style(),
+                        self.file.icon.as_deref().unwrap_or(DEFAULT_FILE_ICON),
+                    ),
+                }
+            }
+            false => (
+                self.dir.style(),
+                self.dir.icon.as_deref().unwrap_or(DEFAULT_DIR_ICON),
+            ),
+        };
+
+        let style = match entry.link_target() {
+            Some(_) => style.italic(),
+            None => style,
+        };
+
+        match self.icons {
+            true => Span::styled(format!("{} {}", icon, name), style),
+            false => Span::styled(name, style),
+        }
+    }
+}
+
+impl EntryStyle {
+    pub fn style(&self) -> Style {
+        self.style_over(&EntryStyle::default())
+    }
+
+    /// Returns the style, using `base` for unset fields
+    fn style_over(&self, base: &EntryStyle) -> Style {
+        let mut style = Style::new();
+
+        if let Some(fg) = self.fg.as_ref().or(base.fg.as_ref()) {
+            match Color::from_str(fg) {
+                Ok(color) => style = style.fg(color),
+                Err(_) => log::error!("invalid theme color: {}", fg),
+            }
+        }
+
+        if self.bold.or(base.bold).unwrap_or_default() {
+            style = style.bold();
+        }
+
+        style
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Modifier;
+
+    use super::*;
+
+    fn entry(path: &str, file: bool) -> VirtDirEntry {
+        VirtDirEntry {
+            path: path.to_string(),
+            file,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn test_theme_styles() {
+        let theme: FsTheme = toml::from_str(
+            r##"
+            icons = true
+
+            [file]
+            bold = true
+
+            [extensions.rs]
+            fg = "#ff0000"
+            icon = "R"
+            "##,
+        )
+        .unwrap();
+
+        let dir = theme.span(&entry("nested", false));
+        assert_eq!(dir.content, format!("{} nested", DEFAULT_DIR_ICON));
+        assert_eq!(dir.style.fg, Some(Color::Green));
+
+        let source = theme.span(&entry("main.rs", true));
+        assert_eq!(source.content, "R main.rs");
+        assert_eq!(source.style.fg, Some(Color::Rgb(255, 0, 0)));
+        // unset fields fall back to the file style
+        assert!(source.style.add_modifier.contains(Modifier::BOLD));
+
+        let other = FsTheme::default().span(&entry("notes.txt", true));
+        assert_eq!(other.content, "notes.txt");
+        assert_eq!(other.style, Style::new());
+
+        let link = VirtDirEntry {
+            link: Some("nested/notes.txt".to_string()),
+            ..entry("notes", true)
+        };
+        let link = FsTheme::default().span(&link);
+        assert_eq!(link.content, "notes -> nested/notes.txt");
+        assert!(link.style.add_modifier.contains(Modifier::ITALIC));
+    }
+}
@@ -25,15 +25,21 @@ use ratatui::{
     widgets::{block::title, Block, Borders, Clear, Widget},
     Frame, Terminal,
 };
-use rfs::interfaces::FileUpdate;
+use rfs::interfaces::{DirEvent, FileUpdateNotice, TopicMessage};
 use tokio::{
     sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
+    task::AbortHandle,
 };
 use tokio_util::sync::CancellationToken;
 
 use super::widgets::{
-    AvailableCommands, ContentWindow, FsTree, StderrLogs, TitleBar, DEFAULT_BLOCK,
+    AvailableCommands, ContentWindow, FsTree, StatsOverlay, StderrLogs, TitleBar, DEFAULT_BLOCK,
+};
+use super::{
+    action::Command,
+    app::{AppState, ContentState, FsState},
+    messages::{Message, Severity},
+    tasks::{TaskPurpose, TaskRegistry},
 };
 /// This is instantiated and run inside app::run().
 
@@ -42,7 +48,8 @@ pub type Ui = Terminal<CrosstermBackend<std::io::Stdout>>;
 
 pub struct Tui {
     pub terminal: ratatui::Terminal<CrosstermBackend<std::io::Stdout>>,
-    pub task: JoinHandle<()>,
+    /// Task reading terminal events, once started
+    pub task: Option<AbortHandle>,
     pub cancellation_token: CancellationToken,
     pub event_rx: UnboundedReceiver<AppEvent>,
     pub event_tx: UnboundedSender<AppEvent>,
@@ -60,6 +67,9 @@ pub struct Tui {
     pub logs_widget: StderrLogs,
     pub commands_widget: AvailableCommands,
     pub content_widget: ContentWindow,
+
+    /// Shown over the content widget if set
+    pub stats_widget: Option<StatsOverlay>,
 }
 
 /// Various rectangles rendered on the screen
@@ -86,7 +96,6 @@ pub enum AppEvent {
     /// First event sent is init
     Init,
     Quit,
-    Error(Option<String>),
     Closed,
     Tick,
     Render,
@@ -97,19 +106,39 @@ pub enum AppEvent {
     Mouse(MouseEvent),
     Resize(u16, u16),
 
-    /// notif
-    SetContentNotification(Option<String>),
+    /// Queue a notification or error to be shown
+    Message(Message),
 
     /// Highlight stuff in the content window.
     ///
     /// tuple contains `(offset, len)`
     HighlightContent(Option<(usize, usize)>),
 
+    /// Highlight entries of the current directory in the filesystem widget, by path.
+    ///
+    /// An empty list clears the highlight.
+    HighlightEntries(Vec<String>),
+
     /// file update event
     FileUpdate {
         path: String,
-        upd: FileUpdate,
+        upd: FileUpdateNotice,
+    },
+
+    /// The change counter of a directory on the remote has changed
+    DirChanged {
+        path: String,
+        change_counter: u64,
+    },
+
+    /// An entry of a watched directory was created, removed or renamed on the remote
+    DirEntryChanged {
+        path: String,
+        event: DirEvent,
     },
+
+    /// A message was published to a subscribed topic
+    TopicMessage(TopicMessage),
 }
 
 /// If a widget can be in focus, it should implement this trait.
@@ -146,7 +175,7 @@ impl Tui {
         // terminal.clear()?;
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let cancellation_token = CancellationToken::new();
-        let task = tokio::spawn(async {}); // placeholder task
+        let task = None;
         let mouse = false;
         let paste = false;
 
@@ -168,6 +197,7 @@ impl Tui {
             logs_widget: StderrLogs::new(),
             commands_widget: AvailableCommands::new(),
             content_widget: ContentWindow::new(),
+            stats_widget: None,
         })
     }
 
@@ -191,8 +221,8 @@ impl Tui {
         self
     }
 
-    /// Start key events capture task
-    pub fn start(&mut self) {
+    /// Start key events capture task, registered with the app's tasks
+    pub fn start(&mut self, tasks: &mut TaskRegistry) {
         let tick_delay = std::time::Duration::from_secs_f64(1.0 / self.tick_rate);
         let render_delay = std::time::Duration::from_secs_f64(1.0 / self.frame_rate);
         self.cancel();
@@ -201,7 +231,7 @@ impl Tui {
         let _event_tx = self.event_tx.clone();
 
         // spawn the keyboard events task
-        self.task = tokio::spawn(async move {
+        let task = tasks.spawn(TaskPurpose::Input, |shutdown| async move {
             let mut reader = crossterm::event::EventStream::new();
             let mut tick_interval = tokio::time::interval(tick_delay);
             let mut render_interval = tokio::time::interval(render_delay);
@@ -214,6 +244,9 @@ impl Tui {
                     _ = _cancellation_token.cancelled() => {
                         break;
                     }
+                    _ = shutdown.cancelled() => {
+                        break;
+                    }
                     maybe_event = crossterm_event => {
                         match maybe_event {
                             Some(Ok(evt)) => {
@@ -241,7 +274,7 @@ impl Tui {
                                 }
                             }
                             Some(Err(e)) => {
-                                _event_tx.send(AppEvent::Error(Some(e.to_string()))).unwrap();
+                                _event_tx.send(AppEvent::Message(Message::new(Severity::Error, e))).unwrap();
                             }
                             None => {},
                         }
@@ -255,16 +288,22 @@ impl Tui {
                 }
             }
         });
+        self.task = Some(task);
     }
 
     pub fn stop(&self) -> io::Result<()> {
         self.cancel();
+        let task = match &self.task {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
         let mut counter = 0;
-        while !self.task.is_finished() {
+        while !task.is_finished() {
             std::thread::sleep(Duration::from_millis(1));
             counter += 1;
             if counter > 50 {
-                self.task.abort();
+                task.abort();
             }
             if counter > 100 {
                 log::error!("Failed to abort task in 100 milliseconds for unknown reason");
@@ -333,88 +372,88 @@ impl Tui {
             .read_to_string(&mut new_logs)?;
         self.logs_widget.push(new_logs);
 
-        let title_widget = self.title_widget.clone();
-        let fs_widget = self.fs_widget.clone();
-        let commands_widget = self.commands_widget.clone();
-        let logs_widget = self.logs_widget.clone();
-        let content_widget = self.content_widget.clone();
-
-        self.draw(|f| {
+        // widgets are rendered by reference, so drawing does not copy file contents or logs
+        self.terminal.draw(|f| {
             let windows = UIWindows::from(f.borrow());
 
-            f.render_widget(title_widget, windows.title);
-            f.render_widget(fs_widget, windows.filesystem);
-            f.render_widget(content_widget, windows.content);
-            f.render_widget(commands_widget, windows.commands);
-            f.render_widget(logs_widget, windows.logs);
+            f.render_widget(&self.title_widget, windows.title);
+            f.render_widget(&self.fs_widget, windows.filesystem);
+            f.render_widget(&self.content_widget, windows.content);
+            f.render_widget(&self.commands_widget, windows.commands);
+            f.render_widget(&self.logs_widget, windows.logs);
+
+            if let Some(stats) = &self.stats_widget {
+                f.render_widget(stats, windows.content);
+            }
         })?;
 
         Ok(())
     }
 
+    /// List the commands available in a state
+    fn show_commands(&mut self, state: &AppState) {
+        self.commands_widget.clear();
+        self.commands_widget.add(Command::available(state));
+    }
+
     pub fn on_filesystem(&mut self) {
         self.fs_widget.focus(true);
         self.content_widget.focus(false);
-        self.commands_widget.clear();
-        self.commands_widget.add([
-            ("ESC", "exit"),
-            ("ENTER", "enter filesystem browse"),
-            ("RIGHT", "go to content"),
-        ]);
+        self.show_commands(&AppState::OnFileSystem);
     }
 
     pub fn on_content(&mut self) {
         self.content_widget.focus(true);
         self.fs_widget.focus(false);
-        self.commands_widget.clear();
-        self.commands_widget
-            .add([("ENTER", "enter content"), ("LEFT", "filesystem tree")]);
+        self.show_commands(&AppState::OnContent);
     }
 
     pub fn in_filesystem(&mut self) {
         self.content_widget.focus(false);
         self.fs_widget.focus(true);
-        self.commands_widget.clear();
-        self.commands_widget.add([
-            ("ESC", "exit filesystem browse"),
-            ("ENTER", "enter file/dir"),
-            ("BACKSPACE", "go to parent dir"),
-            ("UP/DOWN", "navigate"),
-            ("f", "create file"),
-            ("d", "create directory"),
-            ("x", "delete file/dir"),
-        ]);
+        self.show_commands(&AppState::InFileSystem(FsState::Navigate));
     }
 
     pub fn in_content_navi(&mut self) {
         self.fs_widget.focus(false);
         self.content_widget.focus(true);
-        self.commands_widget.clear();
-        self.commands_widget.add([
-            ("ESC", "exit content"),
-            ("ENTER", "enter insert mode"),
-            ("DEL", "delete a character"),
-            ("arrow keys", "navigate"),
-            ("w", "watch file for changes"),
-        ]);
+        self.show_commands(&AppState::InContent(ContentState::Navigate));
     }
 
     pub fn in_content_insert(&mut self) {
         self.fs_widget.focus(false);
         self.content_widget.focus(true);
-        self.commands_widget.clear();
-        self.commands_widget
-            .add([("ESC", "exit insert mode and save changes")]);
+        self.show_commands(&AppState::InContent(ContentState::Insert));
+    }
+
+    pub fn in_content_conflict(&mut self) {
+        self.fs_widget.focus(false);
+        self.content_widget.focus(true);
+        self.show_commands(&AppState::InContent(ContentState::Conflict));
     }
 
     pub fn in_filesystem_create(&mut self, title: &str) {
         self.fs_widget.focus(true);
         self.content_widget.focus(false);
-        self.commands_widget.clear();
-        self.commands_widget
-            .add([("ESC", "cancel"), ("ENTER", "create file/dir")]);
+        self.show_commands(&AppState::InFileSystem(FsState::CreateFile(String::new())));
         self.fs_widget.dialogue_box(Some((title, "", false)));
     }
+
+    /// Show the go to position prompt over the content widget
+    pub fn in_content_goto(&mut self, title: &str, input: &str) {
+        self.fs_widget.focus(false);
+        self.content_widget.focus(true);
+        self.show_commands(&AppState::InContent(ContentState::Goto(String::new())));
+        self.content_widget.set_prompt(Some((title, input)));
+    }
+
+    /// Show the go to path dialogue, starting from a path
+    pub fn in_filesystem_goto(&mut self, title: &str, path: &str) {
+        self.fs_widget.focus(true);
+        self.content_widget.focus(false);
+        self.show_commands(&AppState::InFileSystem(FsState::GotoPath(String::new())));
+        self.fs_widget.dialogue_box(Some((title, path, false)));
+    }
 }
 
 impl Deref for Tui {
@@ -528,6 +567,7 @@ mod tests {
             let cur_dir = VirtDirEntry {
                 path: BASE_PATH.to_string(),
                 file: false,
+                link: None,
             };
 
             let entries = std::fs::read_dir(BASE_PATH)?;
@@ -557,7 +597,11 @@ mod tests {
         let mut content_widget = ContentWindow::new();
         content_widget.set_cursor_pos(Some((0, 0)));
         // content_widget.set_notification(Some("hello world from the notifications!"));
-        content_widget.set_error_message(Some("this is an error message AHHHHH"));
+        content_widget.set_error_message(Some((
+            Severity::Error,
+            "error",
+            "this is an error message AHHHHH",
+        )));
         let mut content_highlight_offset = 0;
 
         let mut focus_toggle = false;
@@ -632,7 +676,7 @@ mod tests {
                         _ => unimplemented!(),
                     }
 
-                    frame.render_widget(title.clone(), windows.title);
+                    frame.render_widget(&title, windows.title);
 
                     // frame.render_widget(
                     //     Paragraph::new(format!(
@@ -644,13 +688,13 @@ mod tests {
                     //     windows.content,
                     // );
 
-                    frame.render_widget(content_widget.clone(), windows.content);
+                    frame.render_widget(&content_widget, windows.content);
 
-                    frame.render_widget(fs_tree.clone(), windows.filesystem);
+                    frame.render_widget(&fs_tree, windows.filesystem);
 
-                    frame.render_widget(commands.clone(), windows.commands);
+                    frame.render_widget(&commands, windows.commands);
 
-                    frame.render_widget(logs.clone(), windows.logs)
+                    frame.render_widget(&logs, windows.logs)
                 });
             }
         }
@@ -6,6 +6,7 @@ use std::{
     io::Read,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 use crossterm::event::KeyCode;
@@ -14,15 +15,23 @@ use ratatui::{
     style::{Style, Stylize},
     symbols::line,
     text::{Line, Span},
-    widgets::{block::Title, Block, Borders, Clear, Paragraph, Widget, Wrap},
+    widgets::{block::Title, Block, Borders, Clear, Paragraph, Sparkline, Widget, Wrap},
 };
 use rfs::{
-    fs::{VirtDirEntry, VirtReadDir},
+    fs::{VirtDirEntry, VirtMetadataLite, VirtReadDir},
+    interfaces::FileUpdate,
+    middleware::InvokeStats,
     ser_de::de,
 };
 use tokio::sync::Mutex;
 
-use super::{tui::FocusedWidget, Ui};
+use super::{
+    content_lines::{visible_range, ContentLines},
+    messages::Severity,
+    theme::FsTheme,
+    tui::FocusedWidget,
+    Ui,
+};
 
 /// Default block used for UI elements
 pub const DEFAULT_BLOCK: Block = Block::new().borders(Borders::ALL);
@@ -32,10 +41,19 @@ pub const DEFAULT_BLOCK: Block = Block::new().borders(Borders::ALL);
 // for widgets without a border, this const needs to be set to 0.
 const FRAME_BORDER_LINES: usize = 2;
 
+/// Separates the directories in the breadcrumbs of the filesystem tree
+const BREADCRUMB_SEPARATOR: &str = " › ";
+
 /// Title bar
 #[derive(Clone, Debug)]
 pub struct TitleBar {
     title: Option<String>,
+
+    /// Warning banner shown next to the title, such as a version mismatch with the remote
+    warning: Option<String>,
+
+    /// Total and free space of the remote disk, in bytes
+    disk_usage: Option<(u64, u64)>,
 }
 
 /// Filesystem tree widgets
@@ -55,6 +73,18 @@ pub struct FsTree {
 
     /// Dialogue contents and error flag
     dialogue: Option<(String, String, bool)>,
+
+    /// The current directory has changed on the remote since it was read
+    stale: bool,
+
+    /// Paths of entries that are highlighted, such as newly added ones
+    highlighted: Vec<String>,
+
+    /// Metadata of entries by path, shown next to them if set
+    details: Option<HashMap<String, VirtMetadataLite>>,
+
+    /// Styles and icons of entries
+    theme: Arc<FsTheme>,
 }
 
 /// Error log widget.
@@ -64,6 +94,9 @@ pub struct FsTree {
 #[derive(Clone)]
 pub struct StderrLogs {
     pub logs: VecDeque<String>,
+
+    /// Maximum number of lines kept
+    capacity: usize,
     // sh: Arc<std::sync::Mutex<shh::ShhStderr>>,
 }
 
@@ -74,13 +107,36 @@ pub struct AvailableCommands {
     commands: HashMap<String, String>,
 }
 
+/// Overlay with sparklines of the latency and throughput of recent remote calls.
+#[derive(Clone, Debug, Default)]
+pub struct StatsOverlay {
+    /// Latencies of recent calls in milliseconds, oldest first
+    latencies: Vec<u64>,
+
+    /// Bytes transferred in each of the last seconds, oldest first
+    throughput: Vec<u64>,
+
+    /// Number of calls and failures
+    totals: (u64, u64),
+
+    /// Number of sockets waiting for callbacks
+    callback_sockets: usize,
+}
+
+/// Number of lines kept by [StderrLogs] by default
+const DEFAULT_LOG_LINES: usize = 100;
+
+/// Size of the stats overlay, including its border
+const STATS_OVERLAY_SIZE: (u16, u16) = (64, 10);
+
+/// Number of seconds of throughput shown in the stats overlay
+const STATS_THROUGHPUT_SECS: usize = STATS_OVERLAY_SIZE.0 as usize - FRAME_BORDER_LINES;
+
 /// This widget is used to display file contents, as well as any error messages.
 #[derive(Clone, Debug)]
 pub struct ContentWindow {
-    /// File contents to display.
-    ///
-    /// Multiple lines should be separated by '\n'.
-    contents: Option<String>,
+    /// File contents to display, split into lines.
+    contents: Option<ContentLines>,
 
     /// Cursor position in the file: (x_col, y_row)
     ///
@@ -99,32 +155,59 @@ pub struct ContentWindow {
     /// Errors take precedence over notifications.
     notification: Option<String>,
 
-    /// Error messages are displayed over the main contents like a pop-up.
-    error_message: Option<String>,
+    /// Warnings and errors are displayed over the main contents like a pop-up, with a title.
+    error_message: Option<(Severity, String, String)>,
+
+    /// Prompts are displayed over the main contents like a pop-up, with a title.
+    /// Errors take precedence over prompts.
+    prompt: Option<(String, String)>,
 
     /// Render the widget with a brighter border when focused
     focused: bool,
+
+    /// Shown on the top border, e.g. other clients that have the file open
+    title: Option<String>,
 }
 
-impl Widget for TitleBar {
+impl Widget for &TitleBar {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
     {
-        let block = match self.title {
+        let block = match &self.title {
             Some(t) => DEFAULT_BLOCK
                 .borders(Borders::TOP)
-                .title(t)
+                .title(t.as_str())
                 .title_alignment(ratatui::layout::Alignment::Center)
                 .style(Style::new().bold()),
             None => DEFAULT_BLOCK.borders(Borders::TOP),
         };
 
+        let block = match &self.warning {
+            Some(w) => block.title(
+                Title::from(Span::from(format!(" {} ", w)).black().on_yellow())
+                    .alignment(ratatui::layout::Alignment::Right),
+            ),
+            None => block,
+        };
+
+        let block = match self.disk_usage {
+            Some((total, free)) => block.title(
+                Title::from(format!(
+                    " disk: {} free of {} ",
+                    format_size(free),
+                    format_size(total)
+                ))
+                .alignment(ratatui::layout::Alignment::Left),
+            ),
+            None => block,
+        };
+
         block.render(area, buf)
     }
 }
 
-impl Widget for FsTree {
+impl Widget for &FsTree {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
@@ -134,31 +217,7 @@ impl Widget for FsTree {
             (None, Some(_)) => Vec::new(),
             (Some(dirs), None) => dirs
                 .iter()
-                .enumerate()
-                .map(|(idx, en)| {
-                    let mut contents = if en.is_file() {
-                        Span::raw(
-                            en.path()
-                                .file_name()
-                                .unwrap()
-                                .to_str()
-                                .expect("invalid path")
-                                .to_string(),
-                        )
-                    } else {
-                        Span::styled(
-                            en.path()
-                                .file_name()
-                                .unwrap()
-                                .to_str()
-                                .expect("invalid path")
-                                .to_string(),
-                            Style::new().green().bold(),
-                        )
-                    };
-
-                    Line::from(contents)
-                })
+                .map(|en| self.entry_line(en, false))
                 .collect::<Vec<_>>(),
 
             (Some(dirs), Some(mut selection)) => {
@@ -185,31 +244,7 @@ impl Widget for FsTree {
                     .map(|(idx, en)| {
                         // let x = en.path().file_name().unwrap().to_str();
 
-                        let mut contents = if en.is_file() {
-                            Span::raw(
-                                en.path()
-                                    .file_name()
-                                    .unwrap()
-                                    .to_str()
-                                    .expect("invalid path"),
-                            )
-                        } else {
-                            Span::styled(
-                                en.path()
-                                    .file_name()
-                                    .unwrap()
-                                    .to_str()
-                                    .expect("invalid path"),
-                                Style::new().green().bold(),
-                            )
-                        };
-
-                        // highlight selection
-                        if selection == idx {
-                            contents = contents.reversed()
-                        }
-
-                        Line::from(contents)
+                        self.entry_line(en, selection == idx)
                     })
                     .collect::<Vec<_>>()
 
@@ -217,18 +252,31 @@ impl Widget for FsTree {
             }
         };
 
+        // breadcrumbs, with the current directory highlighted
+        let crumbs = self
+            .parent_dir
+            .iter()
+            .map(|dir| dir.to_str().expect("invalid path").to_owned())
+            .collect::<Vec<_>>();
+        let mut title = Vec::with_capacity(crumbs.len() * 2 + 1);
+        for (idx, crumb) in crumbs.iter().enumerate() {
+            if idx > 0 {
+                title.push(BREADCRUMB_SEPARATOR.dark_gray());
+            }
+            title.push(match idx + 1 == crumbs.len() {
+                true => crumb.clone().bold().white(),
+                false => crumb.clone().gray(),
+            });
+        }
+        if self.stale {
+            title.push(" (stale)".yellow());
+        }
+
         let para = Paragraph::new(lines)
             .block(
                 DEFAULT_BLOCK
                     .title(
-                        Title::from(
-                            self.parent_dir
-                                .to_str()
-                                .expect("invalid path")
-                                .bold()
-                                .gray(),
-                        )
-                        .alignment(ratatui::layout::Alignment::Left),
+                        Title::from(Line::from(title)).alignment(ratatui::layout::Alignment::Left),
                     )
                     .border_style(match self.focused {
                         true => Style::new().white(),
@@ -239,14 +287,14 @@ impl Widget for FsTree {
 
         para.render(area, buf);
 
-        if let Some((title, entry, err)) = self.dialogue {
+        if let Some((title, entry, err)) = &self.dialogue {
             let popup_style = match err {
                 true => Style::new().red(),
                 false => Style::new().white(),
             };
 
             let popup_contents = [
-                Line::from(entry),
+                Line::from(entry.as_str()),
                 Line::from(""),
                 Line::from(""),
                 Line::from("enter").style(popup_style),
@@ -264,7 +312,7 @@ impl Widget for FsTree {
                     DEFAULT_BLOCK
                         .borders(Borders::ALL)
                         .border_style(popup_style)
-                        .title(title)
+                        .title(title.as_str())
                         .title_alignment(ratatui::layout::Alignment::Center),
                 )
                 .alignment(ratatui::layout::Alignment::Center);
@@ -274,7 +322,7 @@ impl Widget for FsTree {
     }
 }
 
-impl Widget for StderrLogs {
+impl Widget for &StderrLogs {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
@@ -299,7 +347,70 @@ impl Widget for StderrLogs {
     }
 }
 
-impl Widget for AvailableCommands {
+impl Widget for &StatsOverlay {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        // the overlay sits in the top right corner of the area
+        let width = STATS_OVERLAY_SIZE.0.min(area.width);
+        let height = STATS_OVERLAY_SIZE.1.min(area.height);
+        let rect = Rect::new(area.right() - width, area.y, width, height);
+
+        let (calls, failures) = self.totals;
+        let block = DEFAULT_BLOCK.title(
+            Title::from(
+                format!(
+                    "stats: {} calls, {} failed, {} callback sockets",
+                    calls, failures, self.callback_sockets
+                )
+                .bold(),
+            )
+            .alignment(ratatui::layout::Alignment::Center),
+        );
+        let inner = block.inner(rect);
+
+        Clear.render(rect, buf);
+        block.render(rect, buf);
+
+        let layout = Layout::vertical([
+            Constraint::Max(1),
+            Constraint::Fill(1),
+            Constraint::Max(1),
+            Constraint::Fill(1),
+        ])
+        .split(inner);
+
+        // only the most recent values that fit are drawn
+        let latencies =
+            &self.latencies[self.latencies.len().saturating_sub(inner.width as usize)..];
+        let throughput =
+            &self.throughput[self.throughput.len().saturating_sub(inner.width as usize)..];
+
+        Line::from(format!(
+            "latency: {} ms (max {} ms)",
+            latencies.last().copied().unwrap_or(0),
+            latencies.iter().max().copied().unwrap_or(0)
+        ))
+        .render(layout[0], buf);
+        Sparkline::default()
+            .data(latencies)
+            .style(Style::new().yellow())
+            .render(layout[1], buf);
+
+        Line::from(format!(
+            "throughput: {} B/s",
+            throughput.last().copied().unwrap_or(0)
+        ))
+        .render(layout[2], buf);
+        Sparkline::default()
+            .data(throughput)
+            .style(Style::new().cyan())
+            .render(layout[3], buf);
+    }
+}
+
+impl Widget for &AvailableCommands {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
@@ -370,11 +481,52 @@ fn centered_rect(x_percent: u16, y_percent: u16, rect: Rect) -> Rect {
 }
 
 /// Formats the line number with padding and an indicator.
+/// Size and modification time of an entry. Directories have no size.
+fn entry_details(meta: &VirtMetadataLite) -> String {
+    let modified = meta
+        .modified
+        .map(|secs| {
+            humantime::format_rfc3339_seconds(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .to_string()
+        })
+        .unwrap_or_default();
+
+    match meta.file {
+        true => format!("{:>6}  {}", format_size(meta.size), modified),
+        false => format!("{:>6}  {}", "-", modified),
+    }
+}
+
+/// Size in bytes with a binary unit, e.g. `1.5K`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{}{}", bytes, UNITS[0]),
+        _ => format!("{:.1}{}", size, UNITS[unit]),
+    }
+}
+
 fn line_number(num: usize, padding: usize, indicator: char) -> String {
     format!("{:<padding$} {} ", num, indicator, padding = padding)
 }
 
-impl Widget for ContentWindow {
+/// Number of digits needed to display line numbers
+fn line_number_digits(num_lines: usize) -> usize {
+    match num_lines {
+        0 => 1,
+        n => n.ilog10() as usize + 1,
+    }
+}
+
+impl Widget for &ContentWindow {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
@@ -386,28 +538,30 @@ impl Widget for ContentWindow {
             true => Style::new().white(),
             false => Style::new().gray().dim(),
         };
-        let border = DEFAULT_BLOCK.border_style(border_style);
+        let border = match &self.title {
+            Some(title) => DEFAULT_BLOCK.title(Title::from(title.as_str().gray())),
+            None => DEFAULT_BLOCK,
+        }
+        .border_style(border_style);
 
-        let main_para = match (self.contents, self.cursor_pos, self.highlight) {
+        let main_para = match (&self.contents, self.cursor_pos, self.highlight) {
             // render a blank screen
             (None, _, _) => Paragraph::default().block(border),
 
             // render contents w/ line numbers
             (Some(contents), None, None) => {
-                let num_line_digits = match contents.lines().count() {
-                    0 => 1,
-                    n => n.ilog10() + 1,
-                };
+                let num_line_digits = line_number_digits(contents.len());
+                let viewport = (area.height as usize).saturating_sub(FRAME_BORDER_LINES);
 
-                // asd
                 Paragraph::new(
                     contents
-                        .split('\n')
+                        .iter()
+                        .take(viewport)
                         .enumerate()
                         .map(|(line_num, line)| {
                             Line::from(vec![
                                 Span::styled(
-                                    line_number(line_num + 1, num_line_digits as usize, '|'),
+                                    line_number(line_num + 1, num_line_digits, '|'),
                                     Style::new().bold(),
                                 ),
                                 Span::raw(line.to_owned()),
@@ -419,11 +573,9 @@ impl Widget for ContentWindow {
             }
             // highlight some text
             (Some(contents), cursor_opt, Some((h_start, h_len))) => {
-                let lines = contents.split('\n').collect::<Vec<_>>();
-
                 // let res = highlight_text(contents, h_start, h_len);
                 let res = highlight_text(
-                    contents,
+                    contents.text(),
                     h_start,
                     h_len,
                     self.cursor_pos.and_then(|(_, line)| Some(line)),
@@ -441,20 +593,18 @@ impl Widget for ContentWindow {
 
             // render contents w/ scrolling
             (Some(contents), Some((cursor_x, cursor_y)), None) => {
-                let num_line_digits = match contents.lines().count() {
-                    0 => 1,
-                    n => n.ilog10() + 1,
-                };
+                let num_line_digits = line_number_digits(contents.len());
+                let viewport = (area.height as usize).saturating_sub(FRAME_BORDER_LINES);
 
-                let lines = contents
-                    .split('\n')
-                    .enumerate()
+                // only the visible lines are rendered
+                let lines = visible_range(contents.len(), cursor_y as usize, viewport, 2)
+                    .filter_map(|line_num| Some((line_num, contents.get(line_num)?)))
                     .map(|(line_num, contents)| {
                         // highlight current row + selected character
                         if cursor_y as usize == line_num {
                             Line::from({
                                 let mut spans = vec![Span::styled(
-                                    line_number(line_num + 1, num_line_digits as usize, '>'),
+                                    line_number(line_num + 1, num_line_digits, '>'),
                                     Style::new().bold().white(),
                                 )];
                                 spans.extend(
@@ -475,7 +625,7 @@ impl Widget for ContentWindow {
                         } else {
                             Line::from(vec![
                                 Span::styled(
-                                    line_number(line_num + 1, num_line_digits as usize, '|'),
+                                    line_number(line_num + 1, num_line_digits, '|'),
                                     Style::new().bold(),
                                 ),
                                 Span::raw(contents.to_owned()),
@@ -484,27 +634,7 @@ impl Widget for ContentWindow {
                     })
                     .collect::<Vec<_>>();
 
-                // filter to fit the area
-                // let rendered_lines: Box<dyn Iterator<Item = _>> = match lines.len()
-                //     > area.height as usize - FRAME_BORDER_LINES
-                //     && (cursor_y as usize + 1 + 2) > area.height as usize - FRAME_BORDER_LINES
-                // {
-                //     true => Box::new(
-                //         lines
-                //             .iter()
-                //             .skip(
-                //                 (cursor_y as usize + 1 + 2).saturating_sub(area.height as usize)
-                //                     + FRAME_BORDER_LINES,
-                //             )
-                //             .take(area.height as usize - FRAME_BORDER_LINES)
-                //             .cloned(),
-                //     ),
-                //     false => Box::new(lines.into_iter()),
-                // };
-
-                let rendered_lines = fit_lines_to_window(lines, cursor_y, area.height, true, 2);
-
-                Paragraph::new(rendered_lines.collect::<Vec<_>>())
+                Paragraph::new(lines)
                     .block(border)
                     .wrap(Wrap { trim: false })
             }
@@ -513,28 +643,52 @@ impl Widget for ContentWindow {
         main_para.render(area, buf);
 
         // notifications are written to the title border and override the border colour
-        if let Some(notif) = self.notification {
+        if let Some(notif) = &self.notification {
             let notif_block = DEFAULT_BLOCK
                 .borders(Borders::ALL)
                 .border_style(Style::new().light_cyan())
-                .title(Title::from(notif.white().bold()))
+                .title(Title::from(notif.as_str().white().bold()))
                 .title_alignment(ratatui::layout::Alignment::Left);
 
             notif_block.render(area, buf);
         }
 
-        if let Some(err_msg) = self.error_message {
+        if let Some((title, msg)) = &self.prompt {
+            let prompt_rect = centered_rect(50, 50, area);
+
+            Clear.render(prompt_rect, buf);
+
+            let popup = Paragraph::new(msg.as_str().bold())
+                .block(
+                    DEFAULT_BLOCK
+                        .borders(Borders::ALL)
+                        .border_style(Style::new().yellow())
+                        .title(title.as_str())
+                        .title_alignment(ratatui::layout::Alignment::Center),
+                )
+                .alignment(ratatui::layout::Alignment::Center)
+                .wrap(Wrap { trim: true });
+
+            popup.render(prompt_rect, buf)
+        }
+
+        if let Some((severity, title, err_msg)) = &self.error_message {
             // error message takes up half the screen in each dimension
             let err_rect = centered_rect(50, 50, area);
 
             Clear.render(err_rect, buf);
 
-            let popup = Paragraph::new(err_msg.bold())
+            let border_style = match severity {
+                Severity::Error => Style::new().red(),
+                Severity::Warning | Severity::Info => Style::new().yellow(),
+            };
+
+            let popup = Paragraph::new(err_msg.as_str().bold())
                 .block(
                     DEFAULT_BLOCK
                         .borders(Borders::ALL)
-                        .border_style(Style::new().red())
-                        .title("error")
+                        .border_style(border_style)
+                        .title(title.as_str())
                         .title_alignment(ratatui::layout::Alignment::Center),
                 )
                 .alignment(ratatui::layout::Alignment::Center);
@@ -558,13 +712,27 @@ impl FocusedWidget for ContentWindow {
 
 impl TitleBar {
     pub fn new() -> Self {
-        Self { title: None }
+        Self {
+            title: None,
+            warning: None,
+            disk_usage: None,
+        }
     }
 
     /// Set the title of the title bar
     pub fn set_title<T: ToString>(&mut self, title: Option<T>) {
         self.title = title.and_then(|t| Some(t.to_string()));
     }
+
+    /// Set the warning banner of the title bar
+    pub fn set_warning<T: ToString>(&mut self, warning: Option<T>) {
+        self.warning = warning.map(|w| w.to_string());
+    }
+
+    /// Set the total and free space of the remote disk, in bytes
+    pub fn set_disk_usage(&mut self, usage: Option<(u64, u64)>) {
+        self.disk_usage = usage;
+    }
 }
 
 impl FsTree {
@@ -575,9 +743,50 @@ impl FsTree {
             selection: None,
             focused: false,
             dialogue: None,
+            stale: false,
+            highlighted: Vec::new(),
+            details: None,
+            theme: Default::default(),
+        }
+    }
+
+    /// Themed span of an entry, with a background if it is highlighted
+    fn entry_span(&self, entry: &VirtDirEntry) -> Span<'static> {
+        let span = self.theme.span(entry);
+        match self.highlighted.contains(&entry.path) {
+            true => span.on_dark_gray(),
+            false => span,
         }
     }
 
+    /// Line of an entry, followed by its details if they are shown
+    fn entry_line(&self, entry: &VirtDirEntry, selected: bool) -> Line<'static> {
+        let span = match selected {
+            true => self.entry_span(entry).reversed(),
+            false => self.entry_span(entry),
+        };
+
+        match self.details.as_ref().and_then(|d| d.get(&entry.path)) {
+            Some(meta) => Line::from(vec![span, format!("  {}", entry_details(meta)).dark_gray()]),
+            None => Line::from(span),
+        }
+    }
+
+    /// Highlight entries of the current directory by path
+    pub fn highlight_entries(&mut self, paths: Vec<String>) {
+        self.highlighted = paths;
+    }
+
+    /// Set the metadata shown next to entries, keyed by path. `None` hides the details.
+    pub fn set_details(&mut self, details: Option<HashMap<String, VirtMetadataLite>>) {
+        self.details = details;
+    }
+
+    /// Set the theme used to style entries
+    pub fn set_theme(&mut self, theme: FsTheme) {
+        self.theme = Arc::new(theme);
+    }
+
     /// Push a virtual directory into the stack.
     ///
     /// The entries and directory name are func params.
@@ -586,6 +795,14 @@ impl FsTree {
     pub fn push<P: AsRef<Path>>(&mut self, entries: VirtReadDir, dir_name: P) {
         self.entries.push(entries);
         self.parent_dir.push(dir_name);
+        self.stale = false;
+    }
+
+    /// Add entries to the end of the current directory, such as the next page of a listing
+    pub fn extend(&mut self, entries: Vec<VirtDirEntry>) {
+        if let Some(current) = self.entries.last_mut() {
+            current.entries.extend(entries);
+        }
     }
 
     /// Pop the last virtual directory from the stack
@@ -594,6 +811,7 @@ impl FsTree {
     pub fn pop(&mut self) {
         self.entries.pop();
         self.parent_dir.pop();
+        self.stale = false;
     }
 
     /// Select an entry by its index
@@ -626,13 +844,38 @@ impl FsTree {
     pub fn update(&mut self, entries: VirtReadDir) {
         self.entries.pop();
         self.entries.push(entries);
+        self.stale = false;
+    }
+
+    /// Update the dir entries of a directory in the stack, counted from the bottom
+    pub fn update_at(&mut self, depth: usize, entries: VirtReadDir) {
+        if let Some(e) = self.entries.get_mut(depth) {
+            *e = entries;
+        }
+
+        if depth + 1 == self.entries.len() {
+            self.stale = false;
+        }
+    }
+
+    /// Mark the current directory as changed on the remote.
+    ///
+    /// This is cleared when the directory is updated.
+    pub fn set_stale(&mut self, stale: bool) {
+        self.stale = stale;
     }
 }
 
 impl StderrLogs {
     pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_LOG_LINES)
+    }
+
+    /// Keep at most `capacity` lines, discarding the oldest ones
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
             logs: VecDeque::new(),
+            capacity,
             // sh: Arc::new(std::sync::Mutex::new(shh::stderr().unwrap())),
         }
     }
@@ -649,11 +892,11 @@ impl StderrLogs {
             })
             .collect::<Vec<_>>();
 
-        if self.logs.len() + lines.len() > 100 {
-            self.logs.drain(0..(self.logs.len() - (100 - lines.len())));
-        }
-
         self.logs.extend(lines);
+
+        if self.logs.len() > self.capacity {
+            self.logs.drain(0..(self.logs.len() - self.capacity));
+        }
     }
 
     /// Run every tick
@@ -695,6 +938,20 @@ impl AvailableCommands {
     }
 }
 
+impl StatsOverlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the overlay with the statistics of remote calls at a point in time
+    pub fn set_stats(&mut self, stats: &InvokeStats, callback_sockets: usize, now: Instant) {
+        self.latencies = stats.latencies_ms();
+        self.throughput = stats.bytes_per_second(now, STATS_THROUGHPUT_SECS);
+        self.totals = (stats.invocations, stats.failures);
+        self.callback_sockets = callback_sockets;
+    }
+}
+
 impl ContentWindow {
     pub fn new() -> Self {
         Self {
@@ -703,21 +960,41 @@ impl ContentWindow {
             highlight: None,
             notification: None,
             error_message: None,
+            prompt: None,
             focused: false,
+            title: None,
         }
     }
 
+    /// Set the title shown on the top border
+    pub fn set_title<T: ToString>(&mut self, title: Option<T>) {
+        self.title = title.map(|t| t.to_string());
+    }
+
     /// Set the contents of the content window
     pub fn set_contents<T: ToString>(&mut self, contents: Option<T>) {
-        self.contents = contents.and_then(|c| Some(c.to_string()));
+        self.contents = contents.map(|c| ContentLines::new(&c.to_string()));
+    }
+
+    /// Apply a file update to the contents, without splitting the entire file again
+    pub fn apply_update(&mut self, update: &FileUpdate) {
+        self.contents
+            .get_or_insert_with(Default::default)
+            .apply(update);
     }
 
     pub fn set_notification<T: ToString>(&mut self, notif: Option<T>) {
         self.notification = notif.and_then(|n| Some(n.to_string()));
     }
 
-    pub fn set_error_message<T: ToString>(&mut self, err: Option<T>) {
-        self.error_message = err.and_then(|e| Some(e.to_string()));
+    /// Show a warning or error with a title and message
+    pub fn set_error_message<T: ToString, M: ToString>(&mut self, err: Option<(Severity, T, M)>) {
+        self.error_message = err.map(|(s, t, m)| (s, t.to_string(), m.to_string()));
+    }
+
+    /// Show a prompt with a title and message
+    pub fn set_prompt<T: ToString, M: ToString>(&mut self, prompt: Option<(T, M)>) {
+        self.prompt = prompt.map(|(t, m)| (t.to_string(), m.to_string()));
     }
 
     /// Sets the cursor position in the file, x and y offset.
@@ -727,8 +1004,10 @@ impl ContentWindow {
 
     /// Sets the cursor position in the file as an offset.
     pub fn set_cursor_offset(&mut self, offset: usize) {
-        let contents = self.contents.as_deref().unwrap_or("");
-        let lines = contents.split('\n').collect::<Vec<_>>();
+        let lines = match &self.contents {
+            Some(c) => c,
+            None => return,
+        };
 
         let mut line_count = 0;
         let mut char_count = 0;
@@ -790,10 +1069,8 @@ impl ContentWindow {
 
     /// Returns the current cursor position in the file relative to the entire block of text.
     pub fn cursor_offset(&self) -> Option<usize> {
-        let contents = self.contents.as_deref()?;
+        let lines = self.contents.as_ref()?;
         let (cursor_x, cursor_y) = self.cursor_pos?;
-
-        let lines = contents.split('\n').collect::<Vec<_>>();
         let num_full_lines = (cursor_y as usize).saturating_sub(1);
 
         // count all chars (incl whitespace) for all full lines
@@ -819,10 +1096,10 @@ impl ContentWindow {
     }
 
     /// Get the lines and cursor position
-    fn lines_and_cursor_position(&self) -> Option<((u16, u16), Vec<&str>)> {
+    fn lines_and_cursor_position(&self) -> Option<((u16, u16), &ContentLines)> {
         let (curr_x, curr_y) = self.cursor_pos?;
 
-        let lines = self.contents.as_ref()?.split('\n').collect::<Vec<_>>();
+        let lines = self.contents.as_ref()?;
 
         Some(((curr_x, curr_y), lines))
     }
@@ -838,7 +1115,7 @@ impl ContentWindow {
 
         // gets the selected line. If the line is out of bounds, set to the last line.
         let line = match lines.get(curr_y as usize) {
-            Some(line) => *line,
+            Some(line) => line,
             None => {
                 curr_y = lines.len().saturating_sub(1) as u16;
                 curr_x = match lines.last() {
@@ -874,7 +1151,7 @@ impl ContentWindow {
 
         // gets the selected line. If the line is out of bounds, set to the last line.
         let line = match lines.get(curr_y as usize) {
-            Some(line) => *line,
+            Some(line) => line,
             None => {
                 curr_y = lines.len().saturating_sub(1) as u16;
                 curr_x = match lines.last() {
@@ -910,7 +1187,7 @@ impl ContentWindow {
 
         // gets the selected line. If the line is out of bounds, set to the last line.
         let line = match lines.get(curr_y as usize) {
-            Some(line) => *line,
+            Some(line) => line,
             None => {
                 curr_y = lines.len().saturating_sub(1) as u16;
                 curr_x = match lines.last() {
@@ -960,7 +1237,7 @@ impl ContentWindow {
 
         // gets the selected line. If the line is out of bounds, set to the last line.
         let line = match lines.get(curr_y as usize) {
-            Some(line) => *line,
+            Some(line) => line,
             None => {
                 curr_y = lines.len().saturating_sub(1) as u16;
                 curr_x = match lines.last() {
@@ -1229,13 +1506,141 @@ fn fit_lines_to_window(
 #[cfg(test)]
 mod tests {
 
-    use std::{io, time::Duration};
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        io,
+        time::Duration,
+    };
 
     use crossterm::event;
-    use ratatui::{backend::CrosstermBackend, Terminal};
+    use ratatui::{
+        backend::{CrosstermBackend, TestBackend},
+        Terminal,
+    };
 
     use super::*;
 
+    /// Counts the bytes allocated by each thread, so tests running in parallel do not interfere
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|a| a.set(a.get() + layout.size()));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|a| a.set(a.get() + new_size));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    /// Bytes allocated by the current thread while running `f`
+    fn allocated_by<F: FnOnce()>(f: F) -> usize {
+        let before = ALLOCATED.with(|a| a.get());
+        f();
+        ALLOCATED.with(|a| a.get()) - before
+    }
+
+    /// Average bytes allocated per frame when drawing a second of frames at 60 fps,
+    /// with the cursor in the middle of a file with `num_lines` lines
+    fn frame_allocations(num_lines: usize) -> usize {
+        let contents = (0..num_lines)
+            .map(|i| format!("line {} of the file", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut content_widget = ContentWindow::new();
+        content_widget.set_contents(Some(&contents));
+        content_widget.set_cursor_pos(Some((0, num_lines as u16 / 2)));
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+
+        let allocated = allocated_by(|| {
+            for _ in 0..60 {
+                terminal
+                    .draw(|f| f.render_widget(&content_widget, f.size()))
+                    .unwrap();
+            }
+        });
+
+        allocated / 60
+    }
+
+    #[test]
+    fn test_render_allocations() {
+        let small = frame_allocations(100);
+        let large = frame_allocations(60_000);
+
+        // only the visible lines are rendered, regardless of file size
+        assert!(
+            large < small * 2,
+            "{} bytes per frame for a large file, {} for a small file",
+            large,
+            small
+        );
+    }
+
+    #[test]
+    fn test_render_stats_overlay() {
+        let mut overlay = StatsOverlay::new();
+        overlay.latencies = (0..200).collect();
+        overlay.throughput = vec![1024; STATS_THROUGHPUT_SECS];
+        overlay.totals = (200, 3);
+        overlay.callback_sockets = 2;
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal
+            .draw(|f| f.render_widget(&overlay, f.size()))
+            .unwrap();
+
+        let buf = terminal.backend().buffer();
+        let row = |y: u16| {
+            (0..buf.area.width)
+                .map(|x| buf.get(x, y).symbol())
+                .collect::<String>()
+        };
+
+        // drawn in the top right corner, showing the most recent latency
+        assert!(row(0).contains("stats: 200 calls, 3 failed, 2 callback sockets"));
+        assert!(row(0).starts_with(&" ".repeat(100 - STATS_OVERLAY_SIZE.0 as usize)));
+        assert!(row(1).contains("latency: 199 ms (max 199 ms)"));
+        assert!(row(STATS_OVERLAY_SIZE.1).trim().is_empty());
+    }
+
+    #[test]
+    fn test_log_capacity() {
+        let mut logs = StderrLogs::with_capacity(3);
+        logs.push("one\ntwo\n\n".to_string());
+        assert_eq!(logs.logs, ["one", "two"]);
+
+        // the oldest lines are discarded, even if more lines arrive than are kept
+        logs.push("three\nfour".to_string());
+        assert_eq!(logs.logs, ["two", "three", "four"]);
+        logs.push("a\nb\nc\nd".to_string());
+        assert_eq!(logs.logs, ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0M");
+    }
+
     #[test]
     fn test_highlight_line_section() {
         let line = "hello world";
@@ -0,0 +1,252 @@
+//! Mount the remote as a local directory with FUSE.
+//!
+//! [RemoteMount] forwards the requests of the kernel to an [InodeAdapter], blocking the
+//! session thread on a tokio runtime for each one. Only files and directories are supported,
+//! and attributes cannot be changed.
+//!
+//! ```ignore
+//! let remote = RemoteFs::new(ctx);
+//! let runtime = tokio::runtime::Handle::current();
+//! tokio::task::spawn_blocking(move || fuse::mount(remote, "/mnt/remote", runtime)).await??;
+//! ```
+
+use std::{
+    ffi::OsStr,
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fuser::{
+    Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, ReplyWrite, Request,
+};
+use tokio::runtime::Handle;
+
+use crate::{
+    inode_adapter::{FileAttr, FileKind, InodeAdapter, ROOT_INODE},
+    RemoteFs,
+};
+
+/// How long the kernel may cache entries and attributes
+const TTL: Duration = Duration::from_secs(1);
+
+/// Block size reported for files
+const BLOCK_SIZE: u32 = 512;
+
+/// A remote filesystem, served to the kernel.
+#[derive(Debug)]
+pub struct RemoteMount {
+    adapter: InodeAdapter,
+
+    /// Runtime the requests to the remote are made on
+    runtime: Handle,
+}
+
+impl RemoteMount {
+    pub fn new(remote: RemoteFs, runtime: Handle) -> Self {
+        Self {
+            adapter: InodeAdapter::new(remote),
+            runtime,
+        }
+    }
+}
+
+/// Mount the remote at `mountpoint`, blocking until it is unmounted.
+///
+/// This must not be called from inside the runtime, use [tokio::task::spawn_blocking].
+pub fn mount<P: AsRef<Path>>(remote: RemoteFs, mountpoint: P, runtime: Handle) -> io::Result<()> {
+    let options = [
+        MountOption::FSName("rfs".to_string()),
+        MountOption::DefaultPermissions,
+        MountOption::NoExec,
+    ];
+
+    fuser::mount2(RemoteMount::new(remote, runtime), mountpoint, &options)
+}
+
+impl Filesystem for RemoteMount {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = name.to_string_lossy();
+        match self.runtime.block_on(self.adapter.lookup(parent, &name)) {
+            Ok(attr) => reply.entry(&TTL, &to_fuse_attr(attr, req), 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.runtime.block_on(self.adapter.getattr(ino)) {
+            Ok(attr) => reply.attr(&TTL, &to_fuse_attr(attr, req)),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entries = match self.runtime.block_on(self.adapter.readdir(ino)) {
+            Ok(e) => e,
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        // the parent of a directory is not tracked, the kernel resolves ".." by itself
+        let dots = [(".", ino), ("..", ROOT_INODE)]
+            .into_iter()
+            .map(|(name, ino)| (name.to_string(), ino, fuser::FileType::Directory));
+        let entries = entries
+            .into_iter()
+            .map(|(name, attr)| (name, attr.ino, to_file_type(attr.kind)));
+
+        for (idx, (name, ino, kind)) in dots.chain(entries).enumerate().skip(offset as usize) {
+            // the offset of an entry is the offset to continue listing from
+            if reply.add(ino, idx as i64 + 1, kind, name) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self.runtime.block_on(self.adapter.open(ino)) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self
+            .runtime
+            .block_on(self.adapter.read(fh, offset as u64, size))
+        {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self
+            .runtime
+            .block_on(self.adapter.write(fh, offset as u64, data))
+        {
+            Ok(written) => reply.written(written as u32),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.adapter.release(fh) {
+            Ok(_) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+}
+
+/// Returns the attributes of an entry as the kernel expects them, owned by the requester.
+fn to_fuse_attr(attr: FileAttr, req: &Request<'_>) -> fuser::FileAttr {
+    let modified = UNIX_EPOCH + Duration::from_secs(attr.modified.unwrap_or_default());
+    let perm = match (attr.kind, attr.readonly) {
+        (FileKind::File, false) => 0o644,
+        (FileKind::File, true) => 0o444,
+        (FileKind::Directory, false) => 0o755,
+        (FileKind::Directory, true) => 0o555,
+    };
+
+    fuser::FileAttr {
+        ino: attr.ino,
+        size: attr.size,
+        blocks: attr.size.div_ceil(BLOCK_SIZE as u64),
+        atime: modified,
+        mtime: modified,
+        ctime: modified,
+        crtime: SystemTime::UNIX_EPOCH,
+        kind: to_file_type(attr.kind),
+        perm,
+        nlink: 1,
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        blksize: BLOCK_SIZE,
+        flags: 0,
+    }
+}
+
+fn to_file_type(kind: FileKind) -> fuser::FileType {
+    match kind {
+        FileKind::File => fuser::FileType::RegularFile,
+        FileKind::Directory => fuser::FileType::Directory,
+    }
+}
+
+/// Returns the error number to reply to the kernel with.
+fn errno(e: &io::Error) -> i32 {
+    if let Some(code) = e.raw_os_error() {
+        return code;
+    }
+
+    match e.kind() {
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::PermissionDenied => libc::EACCES,
+        io::ErrorKind::AlreadyExists => libc::EEXIST,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => libc::EINVAL,
+        io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+        io::ErrorKind::Unsupported => libc::ENOSYS,
+        _ => libc::EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno() {
+        assert_eq!(
+            errno(&io::Error::from(io::ErrorKind::NotFound)),
+            libc::ENOENT
+        );
+        assert_eq!(
+            errno(&io::Error::from_raw_os_error(libc::EBUSY)),
+            libc::EBUSY
+        );
+        assert_eq!(
+            errno(&io::Error::from(io::ErrorKind::BrokenPipe)),
+            libc::EIO
+        );
+    }
+}
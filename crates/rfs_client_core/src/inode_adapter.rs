@@ -0,0 +1,287 @@
+//! Adapter that addresses the remote by inode, as FUSE does.
+//!
+//! [InodeAdapter] assigns inodes to remote paths and translates
+//! `lookup`/`getattr`/`readdir`/`open`/`read`/`write`/`release` into calls on a [RemoteFs].
+//! No kernel session is created here. The `fuse` feature mounts the remote with
+//! `fuse::RemoteMount`, which forwards each request of the kernel to the
+//! method of the same name and replies with the result.
+//!
+//! The contents of a file are read from the remote when it is opened, and reads and writes
+//! through its handles are served from that copy until every handle is released.
+
+use std::{collections::HashMap, io};
+
+use rfs::{
+    fs::{VirtMetadataLite, VirtPath},
+    interfaces::FileUpdate,
+};
+
+use crate::{FileHandle, RemoteFs};
+
+/// Inode of the mounted directory, the base of the remote
+pub const ROOT_INODE: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+/// Attributes of a mounted file or directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileAttr {
+    pub ino: u64,
+    pub kind: FileKind,
+    /// Size in bytes. This is 0 for directories.
+    pub size: u64,
+    /// Last modification time, in seconds since the unix epoch
+    pub modified: Option<u64>,
+    pub readonly: bool,
+}
+
+/// A remote filesystem, addressed by inode.
+#[derive(Debug)]
+pub struct InodeAdapter {
+    remote: RemoteFs,
+
+    /// Remote paths of known inodes
+    paths: HashMap<u64, VirtPath>,
+
+    /// Inodes of known remote paths
+    inodes: HashMap<VirtPath, u64>,
+
+    next_inode: u64,
+
+    /// Opened files by handle, with the inode each was opened from
+    handles: HashMap<u64, (u64, FileHandle)>,
+
+    next_handle: u64,
+}
+
+impl InodeAdapter {
+    pub fn new(remote: RemoteFs) -> Self {
+        let mut fs = Self {
+            remote,
+            paths: Default::default(),
+            inodes: Default::default(),
+            next_inode: ROOT_INODE,
+            handles: Default::default(),
+            next_handle: 0,
+        };
+        fs.inode(VirtPath::base());
+
+        fs
+    }
+
+    /// Returns the remote path of an inode.
+    pub fn path(&self, ino: u64) -> io::Result<&VirtPath> {
+        self.paths
+            .get(&ino)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown inode"))
+    }
+
+    /// Returns the attributes of an entry in a directory.
+    pub async fn lookup(&mut self, parent: u64, name: &str) -> io::Result<FileAttr> {
+        let path = self.path(parent)?.join(name);
+        self.attr_of(path).await
+    }
+
+    /// Returns the attributes of an inode.
+    pub async fn getattr(&mut self, ino: u64) -> io::Result<FileAttr> {
+        let path = self.path(ino)?.clone();
+        self.attr_of(path).await
+    }
+
+    /// Returns the entries of a directory, with their names.
+    pub async fn readdir(&mut self, ino: u64) -> io::Result<Vec<(String, FileAttr)>> {
+        let read_dir = self.remote.list(self.path(ino)?.as_str()).await?;
+
+        let mut entries = Vec::with_capacity(read_dir.len());
+        for entry in read_dir.iter() {
+            let path = VirtPath::from(&entry.path);
+            let name = path.file_name().unwrap_or_default().to_string();
+
+            // sizes are not listed, and are filled in by `getattr` when needed
+            let attr = FileAttr {
+                ino: self.inode(path),
+                kind: match entry.is_file() {
+                    true => FileKind::File,
+                    false => FileKind::Directory,
+                },
+                size: 0,
+                modified: None,
+                readonly: false,
+            };
+            entries.push((name, attr));
+        }
+
+        Ok(entries)
+    }
+
+    /// Open a file, returning a handle to read and write it with.
+    ///
+    /// A file that is already open is not read from the remote again.
+    pub async fn open(&mut self, ino: u64) -> io::Result<u64> {
+        let path = self.path(ino)?.to_string();
+        let file = self.remote.open(path).await?;
+
+        let fh = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(fh, (ino, file));
+
+        Ok(fh)
+    }
+
+    /// Close a handle. Once every handle of a file is closed, its contents are dropped.
+    pub fn release(&mut self, fh: u64) -> io::Result<()> {
+        let (ino, _) = self.handles.remove(&fh).ok_or_else(unknown_handle)?;
+
+        if !self.handles.values().any(|(open, _)| *open == ino) {
+            let path = self.path(ino)?.to_string();
+            self.remote.close(path);
+        }
+
+        Ok(())
+    }
+
+    /// Read up to `size` bytes of an opened file, starting from `offset`.
+    pub async fn read(&self, fh: u64, offset: u64, size: u32) -> io::Result<Vec<u8>> {
+        let file = self.handle(fh)?.lock().await;
+        let contents = file.local_cache();
+
+        let start = (offset as usize).min(contents.len());
+        let end = start.saturating_add(size as usize).min(contents.len());
+
+        Ok(contents[start..end].to_vec())
+    }
+
+    /// Write bytes to an opened file at `offset`, replacing the bytes that were there.
+    ///
+    /// Returns the number of bytes written.
+    pub async fn write(&self, fh: u64, offset: u64, data: &[u8]) -> io::Result<usize> {
+        let mut file = self.handle(fh)?.lock().await;
+        let update = match offset as usize == file.local_cache().len() {
+            true => FileUpdate::Append(data.to_vec()),
+            false => FileUpdate::WriteAt((offset as usize, data.to_vec())),
+        };
+        file.write_bytes(update).await?;
+
+        Ok(data.len())
+    }
+
+    fn handle(&self, fh: u64) -> io::Result<&FileHandle> {
+        self.handles
+            .get(&fh)
+            .map(|(_, file)| file)
+            .ok_or_else(unknown_handle)
+    }
+
+    /// Returns the inode of a path, assigning a new one if it was not seen before.
+    fn inode(&mut self, path: VirtPath) -> u64 {
+        if let Some(ino) = self.inodes.get(&path) {
+            return *ino;
+        }
+
+        let ino = self.next_inode;
+        self.next_inode += 1;
+        self.paths.insert(ino, path.clone());
+        self.inodes.insert(path, ino);
+
+        ino
+    }
+
+    async fn attr_of(&mut self, path: VirtPath) -> io::Result<FileAttr> {
+        let meta = self
+            .remote
+            .stat(path.as_str())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string()))?;
+
+        Ok(to_attr(self.inode(path), meta))
+    }
+}
+
+fn unknown_handle() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "unknown file handle")
+}
+
+fn to_attr(ino: u64, meta: VirtMetadataLite) -> FileAttr {
+    FileAttr {
+        ino,
+        kind: match meta.file {
+            true => FileKind::File,
+            false => FileKind::Directory,
+        },
+        size: meta.size,
+        modified: meta.modified,
+        readonly: meta.readonly,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+
+    use rfs::middleware::{sockaddr_to_v4, ContextManager, Dispatcher, RequestAckProto};
+
+    use crate::tests::MemFs;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_from_handle() {
+        let timeout = Duration::from_millis(200);
+        let mut fs = MemFs::default();
+        fs.files.insert("notes".to_string(), b"hello".to_vec());
+        let reads = fs.reads.clone();
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            fs,
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await;
+        let addr = sockaddr_to_v4(dispatcher.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+        let mut adapter = InodeAdapter::new(RemoteFs::new(ctx));
+
+        let (name, attr) = adapter.readdir(ROOT_INODE).await.unwrap().remove(0);
+        assert_eq!(name, "notes");
+
+        // reads and writes through open handles do not read the file again
+        let fh = adapter.open(attr.ino).await.unwrap();
+        let other = adapter.open(attr.ino).await.unwrap();
+        assert_eq!(adapter.read(fh, 1, 3).await.unwrap(), b"ell");
+        assert_eq!(adapter.write(other, 5, b"!").await.unwrap(), 1);
+        assert_eq!(adapter.write(other, 1, b"EL").await.unwrap(), 2);
+        assert_eq!(adapter.read(fh, 0, 100).await.unwrap(), b"hELlo!");
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+
+        // the file is read again once every handle is released
+        adapter.release(fh).unwrap();
+        adapter.release(other).unwrap();
+        assert!(adapter.read(fh, 0, 100).await.is_err());
+        let fh = adapter.open(attr.ino).await.unwrap();
+        assert_eq!(adapter.read(fh, 0, 100).await.unwrap(), b"hELlo!");
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+
+        dispatch.abort();
+    }
+}
@@ -0,0 +1,613 @@
+//! Client library for the remote filesystem, without a user interface.
+//!
+//! [RemoteFs] keeps the client-side state of the `rfs_client` binary: opened files are cached
+//! locally, read-only calls are retried, and watched files are kept up to date.
+//!
+//! ```ignore
+//! let ctx = ContextManager::new(source, target, timeout, retries, protocol).await?;
+//! let mut remote = RemoteFs::new(ctx);
+//!
+//! let contents = remote.read("notes.txt").await?;
+//! let mut updates = remote.watch("notes.txt").await?;
+//! ```
+
+#[cfg(feature = "fuse")]
+pub mod fuse;
+#[cfg(feature = "inode-adapter")]
+pub mod inode_adapter;
+mod transfer;
+
+use std::{collections::HashMap, io, path::Path, sync::Arc};
+
+use rfs::{
+    fs::{VirtFile, VirtMetadataLite, VirtPath, VirtReadDir, WatchEvent},
+    interfaces::{FileUpdate, FileUpdateNotice},
+    middleware::ContextManager,
+};
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+pub use transfer::{DownloadOptions, TransferSummary};
+
+/// Shared handle to an opened file.
+pub type FileHandle = Arc<Mutex<VirtFile>>;
+
+/// Number of bytes read per request in [RemoteFs::download]
+const DOWNLOAD_CHUNK_SIZE: usize = 4096;
+
+/// Uploads of at least this many bytes check the free space on the remote first
+const UPLOAD_SPACE_CHECK: usize = 1024 * 1024;
+
+/// Number of updates buffered for a watcher
+const WATCH_BUFFER: usize = 8;
+
+/// A remote filesystem, accessed through a context manager.
+#[derive(Debug)]
+pub struct RemoteFs {
+    ctx: ContextManager,
+
+    /// Opened files, keyed by normalized path
+    files: HashMap<String, FileHandle>,
+}
+
+impl RemoteFs {
+    pub fn new(ctx: ContextManager) -> Self {
+        Self {
+            ctx,
+            files: Default::default(),
+        }
+    }
+
+    /// Returns the context manager used for remote calls.
+    pub fn context(&self) -> &ContextManager {
+        &self.ctx
+    }
+
+    /// Returns the cached handle of a file, if it was opened before.
+    pub fn cached<P: AsRef<Path>>(&self, path: P) -> Option<FileHandle> {
+        self.files.get(&normalize(path)).cloned()
+    }
+
+    /// Open a file, reading its contents from the remote.
+    ///
+    /// Files that were opened before are not read again.
+    pub async fn open<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileHandle> {
+        let path = normalize(path);
+
+        if let Some(file) = self.files.get(&path) {
+            return Ok(file.clone());
+        }
+
+        let file = Arc::new(Mutex::new(VirtFile::open(self.ctx.clone(), &path).await?));
+        self.files.insert(path, file.clone());
+
+        Ok(file)
+    }
+
+    /// Create a file on the remote, truncating any existing file.
+    pub async fn create<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileHandle> {
+        let path = normalize(path);
+
+        let file = Arc::new(Mutex::new(VirtFile::create(self.ctx.clone(), &path).await?));
+        self.files.insert(path, file.clone());
+
+        Ok(file)
+    }
+
+    /// Remove a file from the cache. Returns `false` if the file was not opened.
+    pub fn close<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        self.files.remove(&normalize(path)).is_some()
+    }
+
+    /// Returns the contents of a file, opening it if necessary.
+    pub async fn read<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<u8>> {
+        let file = self.open(path).await?;
+        let contents = file.lock().await.local_cache().to_vec();
+
+        Ok(contents)
+    }
+
+    /// Write an update to a file, opening it if necessary.
+    ///
+    /// Returns the number of bytes written.
+    pub async fn write<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        update: FileUpdate,
+    ) -> io::Result<usize> {
+        let file = self.open(path).await?;
+        let mut lock = file.lock().await;
+
+        lock.write_bytes(update).await
+    }
+
+    /// Watch a file for updates by other clients.
+    ///
+    /// The cached contents of the file are updated before each notice is sent on the channel.
+    /// If updates were missed, the contents are read again before [WatchEvent::Lagged] is sent.
+    /// The watch stops when the receiver is dropped, or after the first error.
+    /// Dropping the receiver also removes the pending watch from the remote.
+    pub async fn watch<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<mpsc::Receiver<io::Result<WatchEvent<FileUpdateNotice>>>> {
+        let file = self.open(path).await?;
+        let mut updates = file
+            .lock()
+            .await
+            .watch_chan(None, CancellationToken::new())
+            .await?;
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+
+        tokio::spawn(async move {
+            loop {
+                let update = tokio::select! {
+                    upd = updates.recv() => upd,
+                    _ = tx.closed() => return,
+                };
+
+                let event = match update {
+                    Some(Ok(WatchEvent::Update((_, notice)))) => {
+                        file.lock().await.update_bytes(&notice);
+                        WatchEvent::Update(notice)
+                    }
+                    // missed updates cannot be applied, the whole file is read instead
+                    Some(Ok(WatchEvent::Lagged(missed))) => {
+                        log::warn!("watch missed {} updates", missed);
+                        if let Err(e) = file.lock().await.read_bytes().await {
+                            let _ = tx.send(Err(e)).await;
+                            return;
+                        }
+                        WatchEvent::Lagged(missed)
+                    }
+                    Some(Err(e)) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                    None => return,
+                };
+
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Returns the entries of a directory.
+    pub async fn list<P: AsRef<Path>>(&self, path: P) -> io::Result<VirtReadDir> {
+        rfs::fs::read_dir(self.ctx.clone(), path).await
+    }
+
+    /// Returns the metadata of a file or directory, or `None` if it does not exist.
+    pub async fn stat<P: AsRef<Path>>(&self, path: P) -> io::Result<Option<VirtMetadataLite>> {
+        rfs::fs::stat(self.ctx.clone(), path).await
+    }
+
+    /// Remove a file on the remote.
+    pub async fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        rfs::fs::remove_file(self.ctx.clone(), path.as_ref()).await?;
+        self.close(path);
+
+        Ok(())
+    }
+
+    /// Create a directory on the remote.
+    pub async fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        rfs::fs::create_dir(self.ctx.clone(), path).await
+    }
+
+    /// Remove an empty directory on the remote.
+    pub async fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        rfs::fs::remove_dir(self.ctx.clone(), path).await
+    }
+
+    /// Copy a remote file to a local path. Returns the number of bytes copied.
+    pub async fn download<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        path: P,
+        local_path: Q,
+    ) -> io::Result<usize> {
+        rfs::fs::download(self.ctx.clone(), path, local_path, DOWNLOAD_CHUNK_SIZE).await
+    }
+
+    /// Copy a remote file to a local path, reading `streams` chunks at the same time.
+    /// Returns the number of bytes copied.
+    pub async fn download_parallel<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        path: P,
+        local_path: Q,
+        streams: usize,
+    ) -> io::Result<usize> {
+        rfs::fs::download_parallel(
+            self.ctx.clone(),
+            path,
+            local_path,
+            DOWNLOAD_CHUNK_SIZE,
+            streams,
+        )
+        .await
+    }
+
+    /// Copy a local file to a remote path, replacing the remote contents.
+    ///
+    /// Large files are not sent if the remote disk does not have room for them.
+    /// Returns the number of bytes copied.
+    pub async fn upload<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        local_path: P,
+        path: Q,
+    ) -> io::Result<usize> {
+        let contents = std::fs::read(local_path)?;
+
+        if contents.len() >= UPLOAD_SPACE_CHECK {
+            match rfs::fs::check_free_space(self.ctx.clone(), contents.len() as u64).await {
+                Err(e) if e.kind() == io::ErrorKind::StorageFull => return Err(e),
+                // remotes that do not report disk usage are written to regardless
+                Err(e) => log::warn!("failed to check free space on the remote: {}", e),
+                Ok(_) => (),
+            }
+        }
+
+        match self.stat(path.as_ref()).await? {
+            Some(_) => self.open(path.as_ref()).await?,
+            None => self.create(path.as_ref()).await?,
+        };
+
+        self.write(path, FileUpdate::Overwrite(contents)).await
+    }
+}
+
+/// Files are cached by the same path the remote uses for them.
+fn normalize<P: AsRef<Path>>(path: P) -> String {
+    VirtPath::from(path.as_ref()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use rfs::{
+        fs::{VirtDirEntry, VirtIOErr},
+        interfaces::{
+            CallbackOpsRegisterFileWatch, CallbackOpsUnregister, CallbackOpsUnregisterFileWatch,
+            ImmutableFileOpsReadFile, PrimitiveFsOpsFileSize, PrimitiveFsOpsReadAll,
+            PrimitiveFsOpsReadDir, PrimitiveFsOpsWriteBytes, SubscriptionId,
+        },
+        middleware::{
+            sockaddr_to_v4, Dispatcher, InvokeError, PayloadHandler, RequestAckProto, Semantics,
+        },
+        RemotelyInvocable,
+    };
+
+    use super::*;
+
+    /// Files kept in memory, with the calls needed to read and write them
+    #[derive(Debug, Default)]
+    pub(crate) struct MemFs {
+        pub(crate) files: HashMap<String, Vec<u8>>,
+
+        /// Files that are listed, but cannot be read
+        unreadable: Vec<String>,
+
+        /// IDs and return addresses of registered watches
+        watches: Arc<std::sync::Mutex<Vec<(SubscriptionId, SocketAddrV4)>>>,
+
+        /// Number of watches registered so far
+        registered: u64,
+
+        /// Number of reads of file contents served so far
+        pub(crate) reads: Arc<AtomicUsize>,
+    }
+
+    impl MemFs {
+        /// Entries directly inside a directory, derived from the paths of files
+        fn read_dir(&self, dir: &VirtPath) -> VirtReadDir {
+            let depth = dir.segments().count();
+            let mut entries: Vec<VirtDirEntry> = Vec::new();
+
+            for path in self.files.keys().chain(&self.unreadable) {
+                let path = VirtPath::from(path);
+                if !path.starts_with(dir) || path.segments().count() <= depth {
+                    continue;
+                }
+
+                let segments = path.segments().take(depth + 1).collect::<Vec<_>>();
+                let entry = VirtDirEntry {
+                    path: segments.join("/"),
+                    file: path.segments().count() == depth + 1,
+                    link: None,
+                };
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+
+            VirtReadDir::from(entries)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for MemFs {
+        async fn handle_payload(&mut self, payload_bytes: &[u8]) -> Result<Vec<u8>, InvokeError> {
+            if let Ok(PrimitiveFsOpsReadAll::Request { path }) =
+                PrimitiveFsOpsReadAll::process_invocation(payload_bytes)
+            {
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                let contents = self.files.get(path.as_str()).cloned().unwrap_or_default();
+                return Ok(PrimitiveFsOpsReadAll::Response(contents).invoke_bytes());
+            }
+
+            if let Ok(PrimitiveFsOpsReadDir::Request { path }) =
+                PrimitiveFsOpsReadDir::process_invocation(payload_bytes)
+            {
+                return Ok(PrimitiveFsOpsReadDir::Response(self.read_dir(&path)).invoke_bytes());
+            }
+
+            if let Ok(PrimitiveFsOpsFileSize::Request { path }) =
+                PrimitiveFsOpsFileSize::process_invocation(payload_bytes)
+            {
+                let res = match self.files.get(path.as_str()) {
+                    Some(contents) => Ok(contents.len()),
+                    None => Err(VirtIOErr::NotFound),
+                };
+                return Ok(PrimitiveFsOpsFileSize::Response(res).invoke_bytes());
+            }
+
+            if let Ok(ImmutableFileOpsReadFile::Request { path, offset, len }) =
+                ImmutableFileOpsReadFile::process_invocation(payload_bytes)
+            {
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                let res = match self.files.get(path.as_str()) {
+                    Some(contents) => {
+                        let start = offset.min(contents.len());
+                        let end = len
+                            .map(|l| (start + l).min(contents.len()))
+                            .unwrap_or(contents.len());
+                        Ok(contents[start..end].to_vec())
+                    }
+                    None => Err(VirtIOErr::NotFound),
+                };
+                return Ok(ImmutableFileOpsReadFile::Response(res).invoke_bytes());
+            }
+
+            if let Ok(CallbackOpsRegisterFileWatch::Request { return_addr, .. }) =
+                CallbackOpsRegisterFileWatch::process_invocation(payload_bytes)
+            {
+                self.registered += 1;
+                let id = SubscriptionId(self.registered);
+                self.watches.lock().unwrap().push((id, return_addr));
+                return Ok(CallbackOpsRegisterFileWatch::Response(Ok(id)).invoke_bytes());
+            }
+
+            if let Ok(CallbackOpsUnregisterFileWatch::Request { return_addr, .. }) =
+                CallbackOpsUnregisterFileWatch::process_invocation(payload_bytes)
+            {
+                let mut watches = self.watches.lock().unwrap();
+                let before = watches.len();
+                watches.retain(|(_, addr)| *addr != return_addr);
+                let removed = watches.len() < before;
+                return Ok(CallbackOpsUnregisterFileWatch::Response(removed).invoke_bytes());
+            }
+
+            if let Ok(CallbackOpsUnregister::Request { id }) =
+                CallbackOpsUnregister::process_invocation(payload_bytes)
+            {
+                let mut watches = self.watches.lock().unwrap();
+                let before = watches.len();
+                watches.retain(|(watch, _)| *watch != id);
+                let removed = watches.len() < before;
+                return Ok(CallbackOpsUnregister::Response(removed).invoke_bytes());
+            }
+
+            match PrimitiveFsOpsWriteBytes::process_invocation(payload_bytes)? {
+                PrimitiveFsOpsWriteBytes::Request { path, bytes, .. } => {
+                    let file = self.files.entry(path.to_string()).or_default();
+                    let len = bytes.len();
+                    *file = bytes.update_file(file);
+
+                    Ok(PrimitiveFsOpsWriteBytes::Response(Ok(len)).invoke_bytes())
+                }
+                _ => Err(InvokeError::SignatureNotMatched),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remote_fs_cache() {
+        let timeout = Duration::from_millis(200);
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            MemFs::default(),
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await
+        .with_semantics(Semantics::AtMostOnce);
+        let addr = sockaddr_to_v4(dispatcher.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+        let mut remote = RemoteFs::new(ctx);
+
+        let update = FileUpdate::Overwrite(b"hello".to_vec());
+        assert_eq!(remote.write("./notes", update).await.unwrap(), 5);
+        assert!(remote.cached("notes").is_some());
+        assert_eq!(remote.read("notes").await.unwrap(), b"hello");
+
+        // the cached file is read again after closing it
+        assert!(remote.close("notes"));
+        assert!(!remote.close("notes"));
+        assert_eq!(remote.read("/notes").await.unwrap(), b"hello");
+
+        dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_watch() {
+        let timeout = Duration::from_millis(200);
+        let fs = MemFs {
+            files: HashMap::from([("notes.txt".to_string(), b"notes".to_vec())]),
+            ..Default::default()
+        };
+        let watches = fs.watches.clone();
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            fs,
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await;
+        let addr = sockaddr_to_v4(dispatcher.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+        let mut remote = RemoteFs::new(ctx);
+        let file = remote.open("notes.txt").await.unwrap();
+
+        let ctx = remote.context().clone();
+
+        // watches are removed in the background once they end, and their sockets returned
+        let removed = || async {
+            for _ in 0..50 {
+                if watches.lock().unwrap().is_empty() && ctx.live_callback_sockets() == 0 {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            false
+        };
+
+        let token = CancellationToken::new();
+        let mut updates = file
+            .lock()
+            .await
+            .watch_chan(None, token.clone())
+            .await
+            .unwrap();
+        assert_eq!(watches.lock().unwrap().len(), 1);
+        assert_eq!(ctx.live_callback_sockets(), 1);
+
+        token.cancel();
+        let err = updates.recv().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(removed().await);
+
+        let err = file
+            .lock()
+            .await
+            .watch(Some(Duration::from_millis(50)), CancellationToken::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(removed().await);
+
+        // dropping the receiver also removes the watch
+        let updates = remote.watch("notes.txt").await.unwrap();
+        drop(updates);
+        assert!(removed().await);
+
+        dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn test_download_tree() {
+        let timeout = Duration::from_millis(200);
+        let files = [
+            ("tree/a.txt", "a".repeat(5000)),
+            ("tree/sub/b.txt", "b".to_string()),
+            ("tree/sub/deep/c.txt", String::new()),
+            ("other.txt", "other".to_string()),
+        ];
+        let fs = MemFs {
+            files: files
+                .iter()
+                .map(|(path, contents)| (path.to_string(), contents.as_bytes().to_vec()))
+                .collect(),
+            unreadable: vec!["tree/sub/locked".to_string()],
+            ..Default::default()
+        };
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            fs,
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await;
+        let addr = sockaddr_to_v4(dispatcher.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+        let remote = RemoteFs::new(ctx);
+
+        let local = std::env::temp_dir().join(format!("rfs_client_core-{}", std::process::id()));
+        let options = DownloadOptions {
+            parallel: 2,
+            retries: 1,
+            streams: 3,
+        };
+        let summary = remote
+            .download_tree("./tree", &local, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            summary.copied,
+            [
+                ("tree/a.txt".to_string(), 5000),
+                ("tree/sub/b.txt".to_string(), 1),
+                ("tree/sub/deep/c.txt".to_string(), 0),
+            ]
+        );
+        assert_eq!(summary.bytes(), 5001);
+        assert!(!summary.is_success());
+        assert_eq!(summary.failed[0].0, "tree/sub/locked");
+
+        for (path, contents) in &files[..3] {
+            let local_path = local.join(path.strip_prefix("tree/").unwrap());
+            assert_eq!(std::fs::read_to_string(local_path).unwrap(), *contents);
+        }
+        assert!(!local.join("other.txt").exists());
+
+        std::fs::remove_dir_all(&local).unwrap();
+        dispatch.abort();
+    }
+}
@@ -0,0 +1,150 @@
+//! Copies of remote directory trees, several files at a time.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rfs::{fs::VirtPath, middleware::ContextManager};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{RemoteFs, DOWNLOAD_CHUNK_SIZE};
+
+/// How a directory tree is downloaded
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    /// Number of files downloaded at the same time
+    pub parallel: usize,
+
+    /// Number of times a file is downloaded again after it fails
+    pub retries: u8,
+
+    /// Number of chunks of a file read at the same time, each from its own socket
+    pub streams: usize,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            parallel: 4,
+            retries: 2,
+            streams: 1,
+        }
+    }
+}
+
+/// Outcome of downloading a directory tree
+#[derive(Debug, Default)]
+pub struct TransferSummary {
+    /// Remote paths that were copied, and their sizes in bytes
+    pub copied: Vec<(String, usize)>,
+
+    /// Remote paths that could not be copied, and the last error
+    pub failed: Vec<(String, io::Error)>,
+}
+
+impl TransferSummary {
+    /// Total number of bytes copied
+    pub fn bytes(&self) -> usize {
+        self.copied.iter().map(|(_, len)| len).sum()
+    }
+
+    /// Returns `true` if every file was copied
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl RemoteFs {
+    /// Copy a remote directory and all of its subdirectories into a local directory.
+    ///
+    /// Files that still fail after their retries are reported in the summary,
+    /// and do not stop the rest of the transfer.
+    pub async fn download_tree<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        path: P,
+        local_dir: Q,
+        options: &DownloadOptions,
+    ) -> io::Result<TransferSummary> {
+        let dir = VirtPath::from(path.as_ref());
+        let entries = rfs::fs::read_dir_tree(self.context().clone(), dir.as_str()).await?;
+        let depth = dir.segments().count();
+
+        std::fs::create_dir_all(&local_dir)?;
+
+        let permits = Arc::new(Semaphore::new(options.parallel.max(1)));
+        let mut tasks = JoinSet::new();
+
+        for entry in entries {
+            let local = VirtPath::from(&entry.path)
+                .segments()
+                .skip(depth)
+                .fold(local_dir.as_ref().to_path_buf(), |local, s| local.join(s));
+
+            match entry.is_file() {
+                true => {
+                    if let Some(parent) = local.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                }
+                false => {
+                    std::fs::create_dir_all(&local)?;
+                    continue;
+                }
+            }
+
+            let ctx = self.context().clone();
+            let permits = permits.clone();
+            let retries = options.retries;
+            let streams = options.streams;
+
+            tasks.spawn(async move {
+                let _permit = permits
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed");
+                let res = download_with_retries(ctx, &entry.path, local, retries, streams).await;
+
+                (entry.path, res)
+            });
+        }
+
+        let mut summary = TransferSummary::default();
+        while let Some(res) = tasks.join_next().await {
+            match res.map_err(io::Error::other)? {
+                (path, Ok(len)) => summary.copied.push((path, len)),
+                (path, Err(e)) => summary.failed.push((path, e)),
+            }
+        }
+
+        summary.copied.sort();
+        summary.failed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(summary)
+    }
+}
+
+/// Download a file, starting over up to `retries` times if it fails.
+async fn download_with_retries(
+    ctx: ContextManager,
+    path: &str,
+    local: PathBuf,
+    retries: u8,
+    streams: usize,
+) -> io::Result<usize> {
+    let mut attempt = 0;
+
+    loop {
+        match rfs::fs::download_parallel(ctx.clone(), path, &local, DOWNLOAD_CHUNK_SIZE, streams)
+            .await
+        {
+            Ok(len) => return Ok(len),
+            Err(e) if attempt < retries => {
+                log::debug!("retrying download of {}: {}", path, e);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Benchmarks of the middleware: serialization, byte packing and transfers over loopback.
+//!
+//! Run with `cargo bench -p rfs_core`. Reports are written to `target/criterion`.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rfs_core::middleware::{
+    sockaddr_to_v4, ProtocolOptions, ProtocolRegistry, TransmissionProtocol,
+};
+use rfs_core::ser_de::{self, byte_packer};
+use serde_bytes::ByteBuf;
+use tokio::net::UdpSocket;
+
+/// Payload sizes, all of which fit in a single datagram
+const PAYLOAD_SIZES: [usize; 3] = [1_024, 16_384, 49_152];
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+const RETRIES: u8 = 3;
+
+fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|num| (num % 251) as u8).collect()
+}
+
+fn bench_ser_de(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ser_de");
+
+    for size in PAYLOAD_SIZES {
+        let value = ByteBuf::from(payload(size));
+        let bytes = ser_de::serialize(&value).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("serialize", size), &value, |b, v| {
+            b.iter(|| ser_de::serialize(v).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", size), &bytes, |b, v| {
+            b.iter(|| ser_de::deserialize::<ByteBuf>(v).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_byte_packer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("byte_packer");
+
+    for size in PAYLOAD_SIZES {
+        let bytes = payload(size);
+        let packed = byte_packer::pack_bytes(&bytes);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("pack", size), &bytes, |b, v| {
+            b.iter(|| byte_packer::pack_bytes(v))
+        });
+        group.bench_with_input(BenchmarkId::new("unpack", size), &packed, |b, v| {
+            b.iter(|| byte_packer::unpack_bytes(v))
+        });
+    }
+
+    group.finish();
+}
+
+/// Send a payload between two sockets on the loopback interface.
+async fn loopback_transfer(proto: Arc<dyn TransmissionProtocol + Send + Sync>, data: &[u8]) {
+    let tx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let rx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let target = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+
+    let rx_proto = proto.clone();
+    let rx_handle =
+        tokio::spawn(async move { rx_proto.recv_bytes(&rx_sock, TIMEOUT, RETRIES).await });
+
+    proto
+        .send_bytes(&tx_sock, target, data, TIMEOUT, RETRIES)
+        .await
+        .expect("transmission failed");
+
+    let (_, received) = rx_handle
+        .await
+        .expect("unable to join task")
+        .expect("receive failed");
+    assert_eq!(received.len(), data.len());
+}
+
+fn bench_protocols(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let registry = ProtocolRegistry::default();
+    let mut group = c.benchmark_group("loopback");
+
+    for name in registry.names() {
+        let proto = registry.build(name, &ProtocolOptions::default()).unwrap();
+
+        for size in PAYLOAD_SIZES {
+            let data = payload(size);
+            group.throughput(Throughput::Bytes(size as u64));
+
+            group.bench_with_input(BenchmarkId::new(name, size), &data, |b, data| {
+                b.to_async(&runtime)
+                    .iter(|| loopback_transfer(proto.clone(), data))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_ser_de, bench_byte_packer, bench_protocols);
+criterion_main!(benches);
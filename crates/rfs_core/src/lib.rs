@@ -3,7 +3,9 @@
 
 pub mod fsm;
 pub mod middleware;
+pub mod path_policy;
 pub mod ser_de;
+mod signature_registry;
 
 use async_trait::async_trait;
 use middleware::InvokeError;
@@ -12,6 +14,7 @@ pub use ser_de::{
     deserialize, deserialize_packed, deserialize_packed_with_header, deserialize_with_header,
     serialize, serialize_packed, serialize_packed_with_header, serialize_with_header,
 };
+pub use signature_registry::{decode_signature, register_signatures};
 
 /// A type that is remotely invocable.
 ///
@@ -23,9 +26,26 @@ pub trait RemotelyInvocable:
     /// Serializes the invocation.
     ///
     /// This method is automatically implemented and should not be overidden.
+    ///
+    /// The method signature is followed by a byte marking the [WireFormat] of the payload,
+    /// and the length of the body, see [split_envelope].
     fn invoke_bytes(&self) -> Vec<u8> {
-        crate::serialize_with_header(self, Self::remote_method_signature())
-            .expect("serialization should not fail")
+        let format = Self::wire_format();
+
+        let body = match format {
+            WireFormat::Packed => crate::serialize_packed(self),
+            WireFormat::Plain => crate::serialize(self),
+        }
+        .expect("serialization should not fail");
+        let body_len = body.len() as u64;
+
+        [
+            Self::remote_method_signature(),
+            &[format as u8],
+            &body_len.to_be_bytes(),
+            &body,
+        ]
+        .concat()
     }
 
     /// Attempt to process and deserialize a set of bytes to `Self`.
@@ -34,16 +54,106 @@ pub trait RemotelyInvocable:
     fn process_invocation(bytes: &[u8]) -> Result<Self, InvokeError> {
         let signature = Self::remote_method_signature();
 
-        log::debug!("invocation signature: {:?}", signature);
-        log::debug!("invocation compare  : {:?}", &bytes[..signature.len()]);
+        log::debug!(
+            "matching {} against {}",
+            String::from_utf8_lossy(signature),
+            decode_signature(bytes).unwrap_or("an unknown method")
+        );
 
-        match bytes.starts_with(signature) {
+        match matches_signature(bytes, signature) {
             true => (),
             false => return Err(InvokeError::SignatureNotMatched),
         }
 
-        crate::deserialize_with_header(bytes, Self::remote_method_signature())
-            .map_err(|_| InvokeError::DeserializationFailed)
+        // the sender's format is used, regardless of our own
+        let (format, body) = split_envelope(&bytes[signature.len()..])?;
+
+        match format {
+            WireFormat::Packed => ser_de::deserialize_packed_exact(body),
+            WireFormat::Plain => ser_de::deserialize_exact(body),
+        }
+        .map_err(|e| {
+            log::debug!("invocation body rejected: {}", e);
+            InvokeError::DeserializationFailed
+        })
+    }
+}
+
+/// Number of bytes holding the length of the body of an invocation
+const BODY_LEN_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Number of bytes between the signature of an invocation and its body
+pub const ENVELOPE_LEN: usize = 1 + BODY_LEN_BYTES;
+
+/// Split the bytes after the signature of an invocation into its [WireFormat] and body.
+///
+/// The format byte is followed by the length of the body as a big-endian `u64`.
+/// Bodies that are shorter or longer than their length are rejected.
+pub fn split_envelope(bytes: &[u8]) -> Result<(WireFormat, &[u8]), InvokeError> {
+    let (format, len) = envelope_header(bytes)?;
+    let body = &bytes[ENVELOPE_LEN..];
+
+    match len == body.len() {
+        true => Ok((format, body)),
+        false => {
+            log::debug!("invocation body is {} bytes, expected {}", body.len(), len);
+            Err(InvokeError::DeserializationFailed)
+        }
+    }
+}
+
+/// Read the [WireFormat] and the length of the body from the bytes after the signature of an invocation.
+///
+/// Unlike [split_envelope], the body does not have to follow.
+pub fn envelope_header(bytes: &[u8]) -> Result<(WireFormat, usize), InvokeError> {
+    let (format, rest) = bytes
+        .split_first()
+        .ok_or(InvokeError::DeserializationFailed)?;
+    let format = WireFormat::try_from(*format)?;
+
+    let len = rest
+        .first_chunk::<BODY_LEN_BYTES>()
+        .ok_or(InvokeError::DeserializationFailed)?;
+
+    let len = usize::try_from(u64::from_be_bytes(*len))
+        .map_err(|_| InvokeError::DeserializationFailed)?;
+
+    Ok((format, len))
+}
+
+/// Checks if the bytes are an invocation of the method with this signature.
+///
+/// The signature must be followed by the [WireFormat] byte, so a method does not match
+/// the invocations of other methods its signature is a prefix of.
+pub fn matches_signature(bytes: &[u8], signature: &[u8]) -> bool {
+    bytes
+        .strip_prefix(signature)
+        .and_then(|rest| rest.first())
+        .map(|format| WireFormat::try_from(*format).is_ok())
+        .unwrap_or(false)
+}
+
+/// How the payload of a remote method is serialized.
+///
+/// Set per method with the `#[wire(packed = ..)]` attribute in a [`remote_interface`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Serialized without byte packing
+    Plain = 0,
+
+    /// Serialized and then byte-packed
+    Packed = 1,
+}
+
+impl TryFrom<u8> for WireFormat {
+    type Error = InvokeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Plain),
+            1 => Ok(Self::Packed),
+            _ => Err(InvokeError::DeserializationFailed),
+        }
     }
 }
 
@@ -67,15 +177,17 @@ pub trait RemoteRequest {
 /// This trait is used for derived payloads that call their parent
 /// interfaces.
 ///
-/// The given function signature must match with the parent interface.
+/// A request payload calls its method on any implementor `S` of the interface,
+/// and returns the response payload.
 ///
 /// This trait is automatically derived from any interface that has the
-/// [`remote_interface`] proc-macro. (not yet)
+/// [`remote_interface`] proc-macro.
 #[async_trait]
-trait RemoteCall {
-    type Function;
-
-    async fn call(&self, func: Self::Function) -> Self;
+pub trait RemoteCall<S: ?Sized>: Sized {
+    /// Call the method with the arguments of this request.
+    ///
+    /// Panics if the payload is a response.
+    async fn call(self, server: &mut S) -> Self;
 }
 
 /// The signature of a method call, used for routing remote invocations
@@ -88,6 +200,56 @@ pub trait RemoteMethodSignature {
     ///
     /// Used for routing method calls on the server side.
     fn remote_method_signature() -> &'static [u8];
+
+    /// Returns the wire format of the payload. Payloads are packed by default.
+    fn wire_format() -> WireFormat {
+        WireFormat::Packed
+    }
+
+    /// Returns the invocation semantics of the method, if it overrides the dispatcher's.
+    fn semantics() -> Option<middleware::Semantics> {
+        None
+    }
+
+    /// Returns whether the method only reads. Methods are writes by default.
+    fn access() -> middleware::MethodAccess {
+        middleware::MethodAccess::Write
+    }
+}
+
+/// Separates application errors from the response of a remote method.
+///
+/// Errors returned by methods with a `Result` return type are sent back as
+/// [InvokeError::RemoteApplication], and decoded back into the method's error type by the client.
+///
+/// This trait is automatically derived from any interface that has the
+/// [`remote_interface`] proc-macro.
+pub trait RemoteResponse: Sized {
+    /// Returns the application error of a response, serialized with its signature.
+    fn application_error(&self) -> Option<Vec<u8>>;
+
+    /// Decode an application error into a response.
+    ///
+    /// Errors that do not belong to this method are returned unchanged.
+    fn from_application_error(bytes: Vec<u8>) -> Result<Self, InvokeError>;
+}
+
+/// Serialize an application error, prefixed with its signature.
+pub fn encode_application_error<E: serde::Serialize>(signature: &str, err: &E) -> Vec<u8> {
+    let body = crate::serialize(err).expect("serialization should not fail");
+
+    [signature.as_bytes(), &body].concat()
+}
+
+/// Deserialize an application error, if it matches the signature.
+pub fn decode_application_error<E: for<'a> serde::Deserialize<'a>>(
+    signature: &str,
+    bytes: Vec<u8>,
+) -> Result<E, InvokeError> {
+    match bytes.strip_prefix(signature.as_bytes()) {
+        Some(body) => crate::deserialize(body).map_err(|_| InvokeError::DeserializationFailed),
+        None => Err(InvokeError::RemoteApplication(bytes)),
+    }
 }
 
 /// Macro testing mod
@@ -3,30 +3,77 @@
 //! over the network.
 // #![allow(unused)]
 
+mod auth;
 mod blob_trx;
 mod callback;
+mod cancellation;
+mod client_id;
+mod clock;
+mod compression;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod conformance;
 mod context_manager;
 mod dispatch;
-mod handshake_proto;
-
-use futures::FutureExt;
-use std::collections::HashMap;
-use std::fmt::Display;
+mod error;
+mod invoke_stats;
+mod lifecycle;
+mod loopback;
+mod params;
+mod protocol;
+mod read_cache;
+mod received_payload;
+mod retry_events;
+mod retrying_client;
+mod rtt_estimator;
+mod semantics;
+mod slo;
+mod socket;
+mod version;
+
+use std::fmt::Debug;
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::net::{SocketAddr, SocketAddrV4};
-use std::sync::Arc;
-use std::time::Duration;
-use std::{fmt::Debug, io, net::Ipv4Addr};
+use std::io;
+use std::net::SocketAddrV4;
 use tokio::net::UdpSocket;
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+pub use auth::{AccessToken, AuthError, MethodAccess, TokenScope, TokenSecret};
+pub use cancellation::request_cancellation;
+pub use client_id::{current_client, ClientId};
+#[cfg(test)]
+pub use clock::PausedClock;
+pub use clock::{Clock, RealClock};
+pub use compression::DEFAULT_COMPRESSION_THRESHOLD;
 pub use context_manager::*;
 pub use dispatch::*;
-pub use handshake_proto::{FaultyHandshakeProto, HandshakeProto};
+pub use error::InvokeError;
+pub use invoke_stats::{InvokeSample, InvokeStats};
+#[cfg(any(test, feature = "lifecycle-hooks"))]
+pub use lifecycle::LifecycleLog;
+pub use lifecycle::{request_hash, LifecycleEvent, LifecycleHook};
+pub use params::{FailureRate, PortRange, RequestTimeout, Retries};
+pub use protocol::{
+    AdaptiveProto, DefaultProto, Endpoint, FaultyDefaultProto, FaultyHandshakeProto,
+    FaultyRequestAckProto, HandshakeProto, ProtocolOptions, ProtocolRegistry, RequestAckProto,
+    SharedProtocol, TcpProto, TransferLimits, TransmissionPacket, TransmissionProtocol,
+};
+pub use read_cache::{CacheLookup, ReadCache};
+pub use received_payload::{PayloadBuffer, ReceivedPayload, SpillFile, DEFAULT_MEMORY_CAP};
+pub use retry_events::*;
+pub use retrying_client::*;
+pub use rtt_estimator::{RttEstimator, MIN_ADAPTIVE_TIMEOUT};
+pub use semantics::Semantics;
+pub use slo::{SloTarget, SloTracker, SLO_MIN_SAMPLES, SLO_WINDOW};
+pub use socket::{
+    check_route, route_source, sockaddr_to_v4, BasicSockProvider, PooledSocket, SharedSocketPool,
+    SocketPool, SocketProvider,
+};
+pub use version::{VersionInfo, VersionMatch, WIRE_VERSION};
+
+use protocol::probability_frac;
 
-use crate::ser_de::byte_packer::{pack_bytes, unpack_bytes};
 // define the serde method here once for use by submodules
 use crate::ser_de::deserialize_packed as deserialize_primary;
 use crate::ser_de::serialize_packed as serialize_primary;
@@ -34,40 +81,6 @@ use crate::ser_de::serialize_packed as serialize_primary;
 /// Max payload size
 const BYTE_BUF_SIZE: usize = 65535;
 
-/// Method invocation errors
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum InvokeError {
-    /// The remote is unable to find a handler for the given payload.
-    ///
-    /// This should be the most common error returned from an invocation.
-    HandlerNotFound,
-
-    /// The method signature of the response does not match
-    /// the payload.
-    SignatureNotMatched,
-
-    /// The context manager is unable to get a response from the remote
-    RequestTimedOut,
-
-    /// Deserialization of the payload failed
-    DeserializationFailed,
-
-    /// Connection to the remote was unsuccessful
-    RemoteConnectionFailed,
-
-    /// Unable to send data to the remote
-    DataTransmissionFailed,
-
-    /// Remote received an error
-    RemoteReceiveError,
-
-    /// Invalid data
-    InvalidData,
-
-    /// The request is a duplicate
-    DuplicateRequest,
-}
-
 /// Middleware-specific data sent between the context manager and the dispatcher
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum MiddlewareData {
@@ -97,6 +110,30 @@ pub enum MiddlewareData {
 
     /// A no-op.
     NoOp,
+
+    /// Remote method invocation request, from an identified client.
+    ///
+    /// `seq` differs between invocations of a client, so a repeated invocation
+    /// is not mistaken for a duplicate request.
+    Request {
+        client: ClientId,
+        seq: u64,
+        #[serde(with = "serde_bytes")]
+        payload: Vec<u8>,
+
+        /// Checked by dispatchers that require tokens
+        token: Option<AccessToken>,
+
+        /// The client accepts a [MiddlewareData::CompressedPayload] in response
+        accepts_compressed: bool,
+    },
+
+    /// A ping carrying the version of the sender. The remote answers with its own version.
+    Hello(VersionInfo),
+
+    /// Response payload compressed with DEFLATE, sent to clients that accept it
+    #[serde(with = "serde_bytes")]
+    CompressedPayload(Vec<u8>),
 }
 
 /// Dispatcher context, injected into each remote implementation.
@@ -111,35 +148,122 @@ pub trait HandleMiddleware {
     fn handle_middleware(&self, data: MiddlewareData) -> Self;
 }
 
-impl std::error::Error for InvokeError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+/// Route and handle the bytes of a remote method invocation.
+///
+/// The method proceseses the bytes of a remote method invocation,
+/// routes the bytes to the appropriate method call, and returns the
+/// result.
+#[async_trait]
+pub trait PayloadHandler {
+    async fn handle_payload(&mut self, payload_bytes: &[u8]) -> Result<Vec<u8>, InvokeError>;
+
+    /// Handle the bytes of an invocation that were spilled to disk while being received.
+    ///
+    /// The default reads the payload into memory and calls [PayloadHandler::handle_payload].
+    /// Handlers that take large uploads should stream the payload instead.
+    async fn handle_spilled(&mut self, payload: ReceivedPayload) -> Result<Vec<u8>, InvokeError>
+    where
+        Self: Send,
+    {
+        let bytes = payload.into_bytes().map_err(|e| {
+            log::error!("failed to load spilled payload: {}", e);
+            InvokeError::RemoteReceiveError
+        })?;
+
+        self.handle_payload(&bytes).await
     }
 
-    fn description(&self) -> &str {
-        "description() is deprecated; use Display"
+    /// Signatures of the methods the handler routes to.
+    ///
+    /// Handlers that do not list their routes are not checked for collisions.
+    fn signatures() -> Vec<&'static [u8]>
+    where
+        Self: Sized,
+    {
+        Vec::new()
     }
 
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        self.source()
+    /// Semantics of the method a payload routes to, if the method overrides the dispatcher's.
+    fn semantics(_payload_bytes: &[u8]) -> Option<Semantics>
+    where
+        Self: Sized,
+    {
+        None
     }
-}
 
-// temp, display is debug
-impl std::fmt::Display for InvokeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+    /// Access of the method a payload routes to, checked against the scope of access tokens.
+    fn access(_payload_bytes: &[u8]) -> MethodAccess
+    where
+        Self: Sized,
+    {
+        MethodAccess::Write
     }
 }
 
-/// Route and handle the bytes of a remote method invocation.
+/// The routes to every method of a remote interface, on servers that implement it.
 ///
-/// The method proceseses the bytes of a remote method invocation,
-/// routes the bytes to the appropriate method call, and returns the
-/// result.
+/// Implemented on the client of each [`remote_interface`](crate::remote_interface), so that
+/// [`payload_handler!`] registers all methods of an interface at once.
 #[async_trait]
-pub trait PayloadHandler {
-    async fn handle_payload(&mut self, payload_bytes: &[u8]) -> Result<Vec<u8>, InvokeError>;
+pub trait InterfaceRoutes<S: ?Sized + Send> {
+    /// Signatures of the methods of the interface.
+    fn signatures() -> Vec<&'static [u8]>;
+
+    /// Call the method a payload routes to, if it belongs to the interface.
+    async fn route(server: &mut S, payload_bytes: &[u8]) -> Option<Result<Vec<u8>, InvokeError>>;
+
+    /// Semantics of the method a payload routes to, if it belongs to the interface.
+    fn semantics(payload_bytes: &[u8]) -> Option<Option<Semantics>>;
+
+    /// Access of the method a payload routes to, if it belongs to the interface.
+    fn access(payload_bytes: &[u8]) -> Option<MethodAccess>;
+}
+
+/// Call the method of a request payload on a server, and serialize the response.
+pub async fn call_route<P, S>(server: &mut S, payload_bytes: &[u8]) -> Result<Vec<u8>, InvokeError>
+where
+    P: crate::RemotelyInvocable + crate::RemoteCall<S> + crate::RemoteResponse + Send,
+    S: ?Sized + Send,
+{
+    log::info!(
+        "{}",
+        String::from_utf8_lossy(<P as crate::RemoteMethodSignature>::remote_method_signature())
+    );
+
+    let payload = P::process_invocation(payload_bytes)?;
+    let resp = payload.call(server).await;
+
+    // application errors are sent separately from the response
+    if let Some(err) = resp.application_error() {
+        return Err(InvokeError::RemoteApplication(err));
+    }
+
+    Ok(resp.invoke_bytes())
+}
+
+/// Checks that no signature appears more than once.
+///
+/// Signatures can be prefixes of each other, as they are routed exactly.
+/// Remotes that predate exact matching route by prefix, but they also predate
+/// the [VersionInfo] exchange, so clients never connect to them.
+pub fn check_signatures(signatures: &[&[u8]]) -> io::Result<()> {
+    let mut sorted = signatures.to_vec();
+    sorted.sort();
+
+    for pair in sorted.windows(2) {
+        if pair[1] == pair[0] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "signature collision: {} and {}",
+                    String::from_utf8_lossy(pair[0]),
+                    String::from_utf8_lossy(pair[1])
+                ),
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 /// Route and handle the bytes of a remote callback.
@@ -155,26 +279,6 @@ pub trait CallbackHandler {
     ) -> Result<Vec<u8>, InvokeError>;
 }
 
-/// This trait is implemented for types that provide socket addresses to bind to.
-///
-/// Socket reuse logic can be implemented for certain types.
-#[async_trait]
-pub trait SocketProvider: core::marker::Send + core::marker::Sync {
-    /// Construct an instance of `Self` from a given address
-    fn from_addr(a: Ipv4Addr) -> Self;
-
-    /// Creates a new socket address to bind to, or reuses an existing one.
-    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>>;
-
-    /// Free a socket address.
-    ///
-    /// In the default impl, this is a no-op
-    #[allow(unused_variables)]
-    async fn free_sock(&mut self, s: Arc<UdpSocket>) -> io::Result<()> {
-        Ok(())
-    }
-}
-
 /// Serve requests by binding to a port.
 ///
 /// The default implementation does not cache requests.
@@ -198,7 +302,7 @@ pub trait SocketProvider: core::marker::Send + core::marker::Sync {
 /// #[async_trait::async_trait]
 /// impl ImmutableFileOps for Server {
 ///     /// Read the contents of a file.
-///     async fn read_file(&mut self, path: PathBuf, offset: Option<usize>) -> Vec<u8> {
+///     async fn read_file(&mut self, path: PathBuf, offset: usize, len: Option<usize>) -> Vec<u8> {
 ///         // ... implementation
 ///         todo!()
 ///     }
@@ -207,449 +311,166 @@ pub trait SocketProvider: core::marker::Send + core::marker::Sync {
 ///
 /// payload_handler! {
 ///     Server,
-///     // we use the '`method_name`_payload' method.
-///      ImmutableFileOpsReadFile => ImmutableFileOps::read_file_payload
-///     // an arbitrary number of paths can be added
+///     // payloads call their method on the server with [`RemoteCall`](crate::RemoteCall)
+///     ImmutableFileOpsReadFile,
+///     // an arbitrary number of payloads can be added
+/// }
+/// ```
+///
+/// All methods of an interface are registered at once with the client of the interface,
+/// see [`InterfaceRoutes`]. Registrations can be gated with `cfg` attributes:
+///
+/// ```ignore
+/// payload_handler! {
+///     Server,
+///     interfaces {
+///         ImmutableFileOpsClient,
+///         #[cfg(feature = "locks")]
+///         LockOpsClient,
+///     }
+/// }
+/// ```
+///
+/// Invocations spilled to disk while being received are read into memory, unless a method
+/// of the server handles them, see [`PayloadHandler::handle_spilled`]:
+///
+/// ```ignore
+/// payload_handler! {
+///     Server,
+///     interfaces {
+///         ImmutableFileOpsClient,
+///     },
+///     spilled = Server::handle_spilled_upload,
 /// }
 /// ```
 #[macro_export]
 macro_rules! payload_handler {
     ($server_ty: ty,
-        $($payload_ty: ty => $trait: ident :: $method: ident),+,
+        interfaces {
+            $($(#[$meta: meta])* $iface_ty: ty),+ $(,)?
+        }
+        $(, spilled = $spilled: path)? $(,)?
     ) => {
         #[async_trait::async_trait]
         impl PayloadHandler for $server_ty {
             async fn handle_payload(&mut self, payload_bytes: &[u8]) -> Result<Vec<u8>, rfs::middleware::InvokeError> {
-
-                $(if payload_bytes.starts_with(
-                        <$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature(),
-                    ) {
-
-                        log::info!("{}", std::str::from_utf8(<$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature()).unwrap());
-
-                        let payload =
-                            <$payload_ty as rfs::RemotelyInvocable>::process_invocation(payload_bytes)?;
-                        let res = self.$method(payload).await;
-                        let resp = <$payload_ty>::Response(res);
-                        let export_payload = rfs::RemotelyInvocable::invoke_bytes(&resp);
-                        return Ok(export_payload);
-                    })+
+                $(
+                    $(#[$meta])*
+                    if let Some(res) =
+                        <$iface_ty as rfs::middleware::InterfaceRoutes<Self>>::route(self, payload_bytes).await
+                    {
+                        return res;
+                    }
+                )+
 
                 // no matches, error out
                 Err(rfs::middleware::InvokeError::HandlerNotFound)
             }
-        }
-    };
-}
-
-/// Recommended payload to be sent between implementors of [`TransmissionProtocol`].
-///
-/// There is no requirement to use this data structure, or all it's variants/fields.
-/// Each implementor is responsible for how data is transmitted.
-///
-/// Implementors can opt to send raw bytes as well.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum TransmissionPacket {
-    /// Data payload
-    Data {
-        /// sequence number
-        seq: u32,
-
-        /// Hash value of bytes
-        hash: u64,
-
-        #[serde(with = "serde_bytes")]
-        data: Vec<u8>,
-
-        /// Indicates if this is the last packet
-        last: bool,
-    },
-
-    /// For receipients of this packet, switch transmissions to this new target
-    SwitchToAddress(SocketAddrV4),
-
-    /// A request for a sequence number
-    Seq(u64),
-
-    /// An ack packet, along with a number.
-    /// The meaning of the number sent within depends on the implementor of the protocol.
-    Ack(u64),
-
-    /// Signals the completion of the transfer
-    Complete,
-}
-
-/// Types that implement this trait can be plugged into [`ContextManager`] and [`Dispatcher`].
-#[async_trait]
-pub trait TransmissionProtocol: Debug + Display {
-    /// Send bytes to the remote. Any fault-tolerant logic should be implemented here.
-    async fn send_bytes(
-        &self,
-        sock: &UdpSocket,
-        target: SocketAddrV4,
-        payload: &[u8],
-        timeout: Duration,
-        retries: u8,
-    ) -> io::Result<usize>;
-    // where
-    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync;
-
-    /// Wait for a UDP packet. Returns the packet source and data.
-    async fn recv_bytes(
-        &self,
-        sock: &UdpSocket,
-        timeout: Duration,
-        retries: u8,
-    ) -> io::Result<(SocketAddrV4, Vec<u8>)>;
-}
-
-/// Converts a socket address to a V4 one.
-/// V6 addresses will return an error.
-pub fn sockaddr_to_v4(addr: SocketAddr) -> io::Result<SocketAddrV4> {
-    match addr {
-        SocketAddr::V4(a) => Ok(a),
-        SocketAddr::V6(_) => Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "IPv6 addresses are not supported",
-        )),
-    }
-}
-
-/// A simple version of [HandshakeProto]. This protocol is compatible with [FaultyRequestAckProto].
-///
-/// Every sent item needs an ack back.
-#[derive(Clone, Debug, Default)]
-pub struct RequestAckProto;
-
-impl Display for RequestAckProto {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", &self)
-    }
-}
 
-#[async_trait]
-impl TransmissionProtocol for RequestAckProto {
-    async fn send_bytes(
-        &self,
-        sock: &UdpSocket,
-        target: SocketAddrV4,
-        payload: &[u8],
-        timeout: Duration,
-        mut retries: u8,
-    ) -> io::Result<usize>
-// where
-    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync,
-    {
-        let mut res: io::Result<usize> = Err(io::Error::new(
-            io::ErrorKind::TimedOut,
-            "connection timed out",
-        ));
-
-        while retries != 0 {
-            log::debug!("sending data to target");
-
-            // occasionally err
-            let send_size = sock.send_to(payload, &target).await?;
-
-            let mut buf = [0_u8; 100];
-
-            tokio::select! {
-                biased;
-
-                recv_res = async {
-                    sock.recv(&mut buf).await
-                }.fuse() => {
-                    log::debug!("response received from target");
+            $(
+                async fn handle_spilled(
+                    &mut self,
+                    payload: rfs::middleware::ReceivedPayload,
+                ) -> Result<Vec<u8>, rfs::middleware::InvokeError> {
+                    $spilled(self, payload).await
+                }
+            )?
 
-                    let recv_size = recv_res?;
-                    let slice = &buf[..recv_size];
+            fn signatures() -> Vec<&'static [u8]> {
+                let mut signatures = Vec::new();
+                $(
+                    $(#[$meta])*
+                    signatures.extend(<$iface_ty as rfs::middleware::InterfaceRoutes<Self>>::signatures());
+                )+
 
-                    let de: TransmissionPacket = deserialize_primary(slice).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "deserialization failed"))?;
-                    let hash = if let TransmissionPacket::Ack(h) = de {
-                        h
-                    } else {
-                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "expected Ack"));
-                        break;
-                    };
+                signatures
+            }
 
-                    if hash == hash_primary(&payload) {
-                        res = Ok(send_size);
-                    } else {
-                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "Ack does not match"));
+            fn semantics(payload_bytes: &[u8]) -> Option<rfs::middleware::Semantics> {
+                $(
+                    $(#[$meta])*
+                    if let Some(semantics) =
+                        <$iface_ty as rfs::middleware::InterfaceRoutes<Self>>::semantics(payload_bytes)
+                    {
+                        return semantics;
                     }
+                )+
 
-                    break;
-                },
-                _ = async {
-                    tokio::time::sleep(timeout).await;
-                }.fuse() => {
-                    retries -= 1;
-                    log::debug!("response timed out. retries remaining: {}", retries);
-
-                    continue;
-                }
+                None
             }
-        }
-
-        res
-    }
-
-    async fn recv_bytes(
-        &self,
-        sock: &UdpSocket,
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
-        let mut recv_buf = [0_u8; BYTE_BUF_SIZE];
-
-        let (size, addr) = sock.recv_from(&mut recv_buf).await?;
-
-        let hash = hash_primary(&&recv_buf[..size]);
-        let resp = TransmissionPacket::Ack(hash);
 
-        let ser_resp = serialize_primary(&resp).expect("serialization should not fail");
-        sock.send_to(&ser_resp, addr).await?;
-
-        Ok((sockaddr_to_v4(addr)?, recv_buf[..size].to_vec()))
-    }
-}
-
-/// A faulty version that is compatible with [RequestAckProto].
-///
-/// This protocol may drop packets on transmission.
-/// The packet drop probabilty is specified in the const generic.
-///
-/// The proto will fail to transmit every 1 in `FRAC` invocations on average.
-#[derive(Clone, Debug)]
-pub struct FaultyRequestAckProto {
-    frac: u32,
-}
-
-impl FaultyRequestAckProto {
-    pub fn from_frac(frac: u32) -> Self {
-        Self { frac }
-    }
-}
-
-impl Display for FaultyRequestAckProto {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "FaultyRequestAckProto")
-    }
-}
-
-#[async_trait]
-impl TransmissionProtocol for FaultyRequestAckProto {
-    async fn send_bytes(
-        &self,
-        sock: &UdpSocket,
-        target: SocketAddrV4,
-        payload: &[u8],
-        timeout: Duration,
-        mut retries: u8,
-    ) -> io::Result<usize>
-// where
-    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync,
-    {
-        let mut res: io::Result<usize> = Err(io::Error::new(
-            io::ErrorKind::TimedOut,
-            "connection timed out",
-        ));
-
-        while retries != 0 {
-            log::debug!("sending data to target");
-
-            // occasionally err
-            let send_size = match probability_frac(self.frac) {
-                true => {
-                    log::error!("simulated packet drop");
-                    payload.len()
-                }
-                false => sock.send_to(payload, &target).await?,
-            };
-
-            let mut buf = [0_u8; 100];
-
-            tokio::select! {
-                biased;
-
-                recv_res = async {
-                    sock.recv(&mut buf).await
-                }.fuse() => {
-                    log::debug!("response received from target");
-
-                    let recv_size = recv_res?;
-                    let slice = &buf[..recv_size];
-
-                    let de: TransmissionPacket = deserialize_primary(slice).unwrap();
-                    let hash = if let TransmissionPacket::Ack(h) = de {
-                        h
-                    } else {
-                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "expected Ack"));
-                        break;
-                    };
-
-                    if hash == hash_primary(&payload) {
-                        res = Ok(send_size);
-                    } else {
-                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "Ack does not match"));
+            fn access(payload_bytes: &[u8]) -> rfs::middleware::MethodAccess {
+                $(
+                    $(#[$meta])*
+                    if let Some(access) =
+                        <$iface_ty as rfs::middleware::InterfaceRoutes<Self>>::access(payload_bytes)
+                    {
+                        return access;
                     }
+                )+
 
-                    break;
-                },
-                _ = async {
-                    tokio::time::sleep(timeout).await;
-                }.fuse() => {
-                    retries -= 1;
-                    log::debug!("response timed out. retries remaining: {}", retries);
-
-                    continue;
-                }
+                rfs::middleware::MethodAccess::Write
             }
         }
+    };
+    ($server_ty: ty,
+        $($payload_ty: ty),+,
+    ) => {
+        #[async_trait::async_trait]
+        impl PayloadHandler for $server_ty {
+            async fn handle_payload(&mut self, payload_bytes: &[u8]) -> Result<Vec<u8>, rfs::middleware::InvokeError> {
 
-        res
-    }
+                $(if rfs::matches_signature(
+                        payload_bytes,
+                        <$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature(),
+                    ) {
 
-    async fn recv_bytes(
-        &self,
-        sock: &UdpSocket,
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
-        let mut recv_buf = [0_u8; BYTE_BUF_SIZE];
+                        log::info!("{}", std::str::from_utf8(<$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature()).unwrap());
 
-        let (size, addr) = sock.recv_from(&mut recv_buf).await?;
+                        let payload =
+                            <$payload_ty as rfs::RemotelyInvocable>::process_invocation(payload_bytes)?;
+                        let resp = <$payload_ty as rfs::RemoteCall<Self>>::call(payload, self).await;
 
-        let hash = hash_primary(&&recv_buf[..size]);
-        let resp = TransmissionPacket::Ack(hash);
+                        // application errors are sent separately from the response
+                        if let Some(err) = rfs::RemoteResponse::application_error(&resp) {
+                            return Err(rfs::middleware::InvokeError::RemoteApplication(err));
+                        }
 
-        let ser_resp = serialize_primary(&resp).expect("serialization should not fail");
+                        let export_payload = rfs::RemotelyInvocable::invoke_bytes(&resp);
+                        return Ok(export_payload);
+                    })+
 
-        match probability_frac(self.frac) {
-            true => {
-                log::error!("simulated packet drop");
-            }
-            false => {
-                sock.send_to(&ser_resp, addr).await?;
+                // no matches, error out
+                Err(rfs::middleware::InvokeError::HandlerNotFound)
             }
-        };
-
-        Ok((sockaddr_to_v4(addr)?, recv_buf[..size].to_vec()))
-    }
-}
-
-/// Returns the outcome of the probability of getting `1` in `frac`.
-fn probability_frac(frac: u32) -> bool {
-    let rand_num: u64 = rand::random();
-    let threshold = u64::MAX / frac as u64;
-
-    rand_num < threshold
-}
-
-/// Packets are sent to the destination without checking if they have been received.
-///
-/// This protocol is compatible only with itself.
-///
-/// As this sends all data in a single UDP packet, the max payload size is `65507` bytes.
-#[derive(Clone, Debug)]
-pub struct DefaultProto;
-
-impl Display for DefaultProto {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", &self)
-    }
-}
-
-#[async_trait]
-impl TransmissionProtocol for DefaultProto {
-    async fn send_bytes(
-        &self,
-        sock: &UdpSocket,
-        target: SocketAddrV4,
-        payload: &[u8],
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<usize> {
-        let packed = pack_bytes(payload);
-        sock.send_to(&packed, target).await?;
-
-        Ok(payload.len())
-    }
 
-    async fn recv_bytes(
-        &self,
-        sock: &UdpSocket,
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
-        let mut buf = [0_u8; 65535];
-
-        let (size, addr) = sock.recv_from(&mut buf).await?;
-
-        let addr = sockaddr_to_v4(addr)?;
-        let unpacked = unpack_bytes(&buf[..size]);
-
-        Ok((addr, unpacked))
-    }
-}
-
-/// A faulty version of [DefaultProto].
-#[derive(Debug)]
-pub struct FaultyDefaultProto {
-    frac: u32,
-}
-
-impl FaultyDefaultProto {
-    pub fn from_frac(frac: u32) -> Self {
-        Self { frac }
-    }
-}
+            fn signatures() -> Vec<&'static [u8]> {
+                vec![$(<$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature()),+]
+            }
 
-impl Display for FaultyDefaultProto {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "FaultyDefaultProto")
-    }
-}
+            fn semantics(payload_bytes: &[u8]) -> Option<rfs::middleware::Semantics> {
+                $(if rfs::matches_signature(
+                        payload_bytes,
+                        <$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature(),
+                    ) {
+                        return <$payload_ty as rfs::RemoteMethodSignature>::semantics();
+                    })+
 
-#[async_trait]
-impl TransmissionProtocol for FaultyDefaultProto {
-    async fn send_bytes(
-        &self,
-        sock: &UdpSocket,
-        target: SocketAddrV4,
-        payload: &[u8],
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<usize> {
-        match probability_frac(self.frac) {
-            true => {
-                log::error!("simulated packet drop");
-                Ok(payload.len())
+                None
             }
-            false => {
-                let packed = pack_bytes(payload);
-                sock.send_to(&packed, target).await?;
 
-                Ok(payload.len())
+            fn access(payload_bytes: &[u8]) -> rfs::middleware::MethodAccess {
+                $(if rfs::matches_signature(
+                        payload_bytes,
+                        <$payload_ty as rfs::RemoteMethodSignature>::remote_method_signature(),
+                    ) {
+                        return <$payload_ty as rfs::RemoteMethodSignature>::access();
+                    })+
+
+                rfs::middleware::MethodAccess::Write
             }
         }
-    }
-
-    async fn recv_bytes(
-        &self,
-        sock: &UdpSocket,
-        _timeout: Duration,
-        _retries: u8,
-    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
-        let mut buf = [0_u8; 65535];
-
-        let (size, addr) = sock.recv_from(&mut buf).await?;
-
-        let addr = sockaddr_to_v4(addr)?;
-        let unpacked = unpack_bytes(&buf[..size]);
-
-        Ok((addr, unpacked))
-    }
+    };
 }
 
 /// The primary hash method used for verifying the integrity of data
@@ -660,303 +481,22 @@ fn hash_primary<T: Hash>(item: &T) -> u64 {
     hasher.finish()
 }
 
-impl From<io::Error> for InvokeError {
-    fn from(value: io::Error) -> Self {
-        log::error!("error kind: {:?}", value.kind());
-
-        match value.kind() {
-            io::ErrorKind::NotFound => InvokeError::HandlerNotFound,
-            io::ErrorKind::PermissionDenied => InvokeError::InvalidData,
-            io::ErrorKind::ConnectionRefused
-            | io::ErrorKind::ConnectionReset
-            | io::ErrorKind::ConnectionAborted
-            | io::ErrorKind::NotConnected
-            | io::ErrorKind::AddrInUse
-            | io::ErrorKind::AddrNotAvailable
-            | io::ErrorKind::BrokenPipe => InvokeError::DataTransmissionFailed,
-            // io::ErrorKind::AlreadyExists => todo!(),
-            // io::ErrorKind::WouldBlock => todo!(),
-            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => InvokeError::InvalidData,
-            io::ErrorKind::TimedOut => InvokeError::RequestTimedOut,
-            // io::ErrorKind::WriteZero => todo!(),
-            // io::ErrorKind::Interrupted => todo!(),
-            // io::ErrorKind::Unsupported => todo!(),
-            // io::ErrorKind::UnexpectedEof => todo!(),
-            // io::ErrorKind::OutOfMemory => todo!(),
-            // io::ErrorKind::Other => todo!(),
-            _ => InvokeError::RequestTimedOut,
-        }
-    }
-}
-
-impl From<InvokeError> for io::Error {
-    fn from(value: InvokeError) -> Self {
-        match value {
-            InvokeError::HandlerNotFound => {
-                io::Error::new(io::ErrorKind::NotFound, "handler not found")
-            }
-            InvokeError::SignatureNotMatched => {
-                io::Error::new(io::ErrorKind::InvalidData, "signature not matched")
-            }
-            InvokeError::RequestTimedOut => {
-                io::Error::new(io::ErrorKind::TimedOut, "request timed out")
-            }
-            InvokeError::DeserializationFailed => {
-                io::Error::new(io::ErrorKind::InvalidData, "deserialization failed")
-            }
-            InvokeError::RemoteConnectionFailed => {
-                io::Error::new(io::ErrorKind::ConnectionRefused, "remote connection failed")
-            }
-            InvokeError::DataTransmissionFailed => {
-                io::Error::new(io::ErrorKind::BrokenPipe, "data transmission failed")
-            }
-            InvokeError::RemoteReceiveError => {
-                io::Error::new(io::ErrorKind::BrokenPipe, "remote receive error")
-            }
-            InvokeError::InvalidData => io::Error::new(io::ErrorKind::InvalidData, "invalid data"),
-            InvokeError::DuplicateRequest => {
-                io::Error::new(io::ErrorKind::Interrupted, "duplicate request")
-            }
-        }
-    }
-}
-
-/// Basic socket provider impl, no socket reuse
-#[derive(Debug)]
-pub struct BasicSockProvider {
-    addr: Ipv4Addr,
-}
-
-#[async_trait]
-impl SocketProvider for BasicSockProvider {
-    fn from_addr(a: Ipv4Addr) -> Self {
-        Self { addr: a }
-    }
-
-    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>> {
-        Ok(Arc::new(
-            UdpSocket::bind(SocketAddrV4::new(self.addr, 0)).await?,
-        ))
-    }
-}
-
-/// Maintains an internal pool of bound sockets
-#[derive(Debug)]
-pub struct SocketPool {
-    addr: Ipv4Addr,
-
-    /// The boolean field indicates if the current socket is in use
-    sockets: HashMap<SocketAddrV4, (bool, Arc<UdpSocket>)>,
-}
-
-impl SocketPool {
-    async fn create_new_sock(&mut self) -> io::Result<UdpSocket> {
-        let sock = UdpSocket::bind(SocketAddrV4::new(self.addr, 0)).await?;
-
-        Ok(sock)
-    }
-
-    /// Create a new socket and inserts it into the pool with use condition `cond`.
-    async fn create_insert_new_sock(&mut self, in_use: bool) -> io::Result<Arc<UdpSocket>> {
-        let sock = Arc::new(self.create_new_sock().await?);
-
-        let a = match sock.local_addr()? {
-            std::net::SocketAddr::V4(a) => a,
-            std::net::SocketAddr::V6(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::AddrNotAvailable,
-                    "IPv6 addresses are not supported",
-                ))
-            }
-        };
-
-        self.sockets.insert(a, (in_use, sock.clone()));
-
-        return Ok(sock);
-    }
-}
-
-#[async_trait]
-impl SocketProvider for SocketPool {
-    fn from_addr(a: Ipv4Addr) -> Self {
-        Self {
-            addr: a,
-            sockets: Default::default(),
-        }
-    }
-
-    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>> {
-        if self.sockets.len() == 0 {
-            return Ok(self.create_insert_new_sock(true).await?);
-        }
-
-        let unused_sock = self
-            .sockets
-            .iter()
-            .find_map(|(_, (in_use, sock))| match in_use {
-                true => None,
-                false => Some(sock.clone()),
-            });
-
-        match unused_sock {
-            Some(s) => Ok(s),
-            None => Ok(self.create_insert_new_sock(true).await?),
-        }
-    }
-
-    async fn free_sock(&mut self, s: Arc<UdpSocket>) -> io::Result<()> {
-        let addr = match s.local_addr()? {
-            std::net::SocketAddr::V4(a) => a,
-            std::net::SocketAddr::V6(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::AddrNotAvailable,
-                    "IPv6 addresses are not supported",
-                ))
-            }
-        };
-
-        let entry = self.sockets.get_mut(&addr);
-
-        match entry {
-            Some((in_use, _)) => {
-                *in_use = false;
-                Ok(())
-            }
-            // we are ok with an entry not existing
-            None => Ok(()),
-        }
-    }
-}
-
 #[cfg(test)]
 #[allow(unused)]
 mod tests {
-
-    use std::net::SocketAddrV4;
-
     use super::*;
 
     #[test]
-    fn test_prob() {
-        let frac = 10;
-
-        let probs = (0..64)
-            .into_iter()
-            .map(|_| match probability_frac(frac) {
-                true => 1,
-                false => 0,
-            })
-            .collect::<Vec<_>>();
-
-        let s: i32 = probs.iter().sum();
-
-        println!("1 in {} yields {}", frac, s);
-    }
-
-    /// Transmit and receive some stuff
-    async fn tx_rx(
-        proto: Arc<dyn TransmissionProtocol + Send + Sync>,
-        large: bool,
-        timeout: Duration,
-        retries: u8,
-    ) {
-        let data_size = match large {
-            true => 60_000 * 10,
-            false => 51_200,
-        };
-
-        let data_payload = (0..data_size)
-            .into_iter()
-            .map(|num| (num & 0b1) as u8)
-            .collect::<Vec<_>>();
-
-        let tx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
-            .await
-            .unwrap();
-
-        let rx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
-            .await
-            .unwrap();
-
-        log::debug!("tx_sock: {:?}", tx_sock);
-        log::debug!("rx_sock: {:?}", rx_sock);
-
-        let tx_target = rx_sock.local_addr().unwrap();
-        let rx_target = tx_sock.local_addr().unwrap();
-
-        log::debug!("tx_target: {:?}", tx_target);
-        log::debug!("rx_target: {:?}", rx_target);
-
-        let mut tx_proto = proto.clone();
-        let mut rx_proto = proto.clone();
-
-        let payload_clone = data_payload.clone();
-
-        let rx_handle =
-            tokio::spawn(async move { rx_proto.recv_bytes(&rx_sock, timeout, retries).await });
-
-        let tx_handle = tokio::spawn(async move {
-            tx_proto
-                .send_bytes(
-                    &tx_sock,
-                    sockaddr_to_v4(tx_target)?,
-                    &payload_clone,
-                    timeout,
-                    retries,
-                )
-                .await
-        });
-
-        let tx_result = tx_handle
-            .await
-            .expect("unable to join task")
-            .expect("transmission failed");
-
-        let rx_result = rx_handle
-            .await
-            .expect("unable to join task")
-            .expect("receive failed");
-
-        assert_eq!(rx_result.1, data_payload);
-    }
+    fn test_signatures() {
+        assert!(crate::matches_signature(b"Ops::read\x01body", b"Ops::read"));
+        assert!(!crate::matches_signature(
+            b"Ops::read_all\x01body",
+            b"Ops::read"
+        ));
+        assert!(!crate::matches_signature(b"Ops::read", b"Ops::read"));
 
-    #[tokio::test]
-    async fn test_transmission_protocols() {
-        std::env::set_var("RUST_LOG", "DEBUG");
-        pretty_env_logger::formatted_timed_builder()
-            .parse_filters("DEBUG")
-            .init();
-
-        let handshake_proto = HandshakeProto {};
-        let proto_arc = Arc::new(handshake_proto);
-
-        log::info!("testing HandshakeProto large");
-        tx_rx(proto_arc.clone(), true, Duration::from_millis(750), 5).await;
-
-        log::info!("testing HandshakeProto small");
-        tx_rx(proto_arc.clone(), false, Duration::from_millis(750), 5).await;
-
-        log::info!("testing DefaultProto small");
-        tx_rx(Arc::new(DefaultProto), false, Duration::from_millis(400), 2).await;
-
-        log::info!("testing RequestAckProto small");
-        tx_rx(
-            Arc::new(RequestAckProto),
-            false,
-            Duration::from_millis(400),
-            3,
-        )
-        .await;
-
-        log::info!("testing FaultyRequestAckProto small");
-        tx_rx(
-            Arc::new(FaultyRequestAckProto::from_frac(10)),
-            false,
-            Duration::from_millis(400),
-            3,
-        )
-        .await;
-
-        return;
+        assert!(check_signatures(&[b"Ops::read_all", b"Ops::write"]).is_ok());
+        assert!(check_signatures(&[b"Ops::read_all", b"Ops::write", b"Ops::read"]).is_ok());
+        assert!(check_signatures(&[b"Ops::read", b"Ops::read"]).is_err());
     }
 }
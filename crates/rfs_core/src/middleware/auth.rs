@@ -0,0 +1,337 @@
+//! Access tokens that limit what a client may invoke, and until when.
+//!
+//! Tokens are issued with a [TokenSecret] shared by the issuer and the dispatcher.
+//! A token carries its scope and expiry, and a tag computed from both with the secret
+//! (keyed BLAKE3), so it cannot be extended or widened without the secret.
+//!
+//! Tokens are written as `<scope>-<expiry>-<nonce>-<tag>`, e.g. `ro-6553f100-...`.
+
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// What a remote method does to the remote.
+///
+/// Methods of a remote interface are writes unless marked with `#[wire(read_only)]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MethodAccess {
+    /// The method only reads state, or only affects the calling client
+    Read,
+
+    #[default]
+    Write,
+}
+
+/// Methods a token may invoke
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenScope {
+    /// Only methods that read
+    ReadOnly,
+
+    /// All methods
+    ReadWrite,
+}
+
+/// Why a dispatcher rejected an invocation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthError {
+    /// The dispatcher requires a token, and the request has none
+    Missing,
+
+    /// The token was not issued with the secret of the dispatcher
+    Invalid,
+
+    /// The token has expired
+    Expired,
+
+    /// The scope of the token does not cover the method
+    Forbidden,
+}
+
+/// A token presented with every invocation of a [super::ContextManager]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessToken {
+    pub scope: TokenScope,
+
+    /// Seconds since the unix epoch after which the token is rejected
+    pub expires: u64,
+
+    /// Tells apart tokens with the same scope and expiry
+    nonce: u64,
+
+    tag: [u8; blake3::OUT_LEN],
+}
+
+/// Key that access tokens are issued and checked with
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TokenSecret {
+    key: [u8; blake3::KEY_LEN],
+}
+
+impl TokenScope {
+    /// Name of the scope, as used in tokens and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ReadOnly => "ro",
+            Self::ReadWrite => "rw",
+        }
+    }
+
+    /// Returns `true` if the scope covers a method with this access.
+    pub fn allows(&self, access: MethodAccess) -> bool {
+        match self {
+            Self::ReadOnly => access == MethodAccess::Read,
+            Self::ReadWrite => true,
+        }
+    }
+}
+
+impl Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ro" | "read-only" => Ok(Self::ReadOnly),
+            "rw" | "read-write" => Ok(Self::ReadWrite),
+            _ => Err(format!("unknown token scope {:?}", s)),
+        }
+    }
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            Self::Missing => "access token required",
+            Self::Invalid => "access token invalid",
+            Self::Expired => "access token expired",
+            Self::Forbidden => "access token does not allow this method",
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl Display for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{:x}-{:016x}-{}",
+            self.scope,
+            self.expires,
+            self.nonce,
+            blake3::Hash::from(self.tag).to_hex()
+        )
+    }
+}
+
+impl FromStr for AccessToken {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.trim().split('-').collect::<Vec<_>>();
+        let [scope, expires, nonce, tag] = parts.as_slice() else {
+            return Err(AuthError::Invalid);
+        };
+        let hex = |part: &str| u64::from_str_radix(part, 16).map_err(|_| AuthError::Invalid);
+
+        Ok(Self {
+            scope: scope.parse().map_err(|_| AuthError::Invalid)?,
+            expires: hex(expires)?,
+            nonce: hex(nonce)?,
+            tag: blake3::Hash::from_hex(tag)
+                .map_err(|_| AuthError::Invalid)?
+                .into(),
+        })
+    }
+}
+
+impl AccessToken {
+    /// Time the token expires
+    pub fn expiry(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.expires)
+    }
+}
+
+impl TokenSecret {
+    /// Generate a new random secret.
+    pub fn random() -> Self {
+        Self {
+            key: rand::random(),
+        }
+    }
+
+    /// Read a secret from a file, creating the file with a new secret if it does not exist.
+    ///
+    /// On unix, a new file is only readable and writable by its owner.
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let secret = Self::random();
+
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+                options
+                    .open(&path)?
+                    .write_all(secret.to_string().as_bytes())?;
+                log::info!("created token secret at {:?}", path.as_ref());
+                Ok(secret)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Issue a token that is valid until `expires`.
+    pub fn issue(&self, scope: TokenScope, expires: SystemTime) -> AccessToken {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let nonce = rand::random();
+
+        AccessToken {
+            scope,
+            expires,
+            nonce,
+            tag: self.tag(scope, expires, nonce).into(),
+        }
+    }
+
+    /// Check that a token was issued with this secret, has not expired,
+    /// and allows a method with this access.
+    pub fn verify(
+        &self,
+        token: &AccessToken,
+        access: MethodAccess,
+        now: SystemTime,
+    ) -> Result<(), AuthError> {
+        // comparisons of [blake3::Hash] take constant time
+        if self.tag(token.scope, token.expires, token.nonce) != token.tag {
+            return Err(AuthError::Invalid);
+        }
+        if now >= token.expiry() {
+            return Err(AuthError::Expired);
+        }
+
+        match token.scope.allows(access) {
+            true => Ok(()),
+            false => Err(AuthError::Forbidden),
+        }
+    }
+
+    /// Keyed hash (BLAKE3) of the contents of a token
+    fn tag(&self, scope: TokenScope, expires: u64, nonce: u64) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(scope.name().as_bytes());
+        hasher.update(&expires.to_le_bytes());
+        hasher.update(&nonce.to_le_bytes());
+
+        hasher.finalize()
+    }
+}
+
+impl Display for TokenSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", blake3::Hash::from(self.key).to_hex())
+    }
+}
+
+/// The secret is never printed in logs
+impl std::fmt::Debug for TokenSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TokenSecret(..)")
+    }
+}
+
+impl FromStr for TokenSecret {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the secret has the length of a hash, and is written the same way
+        blake3::Hash::from_hex(s.trim())
+            .map(|key| Self { key: key.into() })
+            .map_err(|_| format!("token secret must be {} hex digits", 2 * blake3::KEY_LEN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_tokens() {
+        let secret = TokenSecret::random();
+        let now = SystemTime::now();
+        let token = secret.issue(TokenScope::ReadOnly, now + Duration::from_secs(60));
+
+        assert_eq!(secret.verify(&token, MethodAccess::Read, now), Ok(()));
+        assert_eq!(
+            secret.verify(&token, MethodAccess::Write, now),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            secret.verify(&token, MethodAccess::Read, now + Duration::from_secs(60)),
+            Err(AuthError::Expired)
+        );
+
+        // tokens survive a round trip through their text form
+        let parsed: AccessToken = token.to_string().parse().unwrap();
+        assert_eq!(parsed, token);
+        assert_eq!("ro-1-2".parse::<AccessToken>(), Err(AuthError::Invalid));
+
+        // widening the scope or using another secret invalidates the tag
+        let widened = AccessToken {
+            scope: TokenScope::ReadWrite,
+            ..token
+        };
+        assert_eq!(
+            secret.verify(&widened, MethodAccess::Write, now),
+            Err(AuthError::Invalid)
+        );
+        assert_eq!(
+            TokenSecret::random().verify(&token, MethodAccess::Read, now),
+            Err(AuthError::Invalid)
+        );
+
+        let restored: TokenSecret = secret.to_string().parse().unwrap();
+        assert!(restored == secret);
+    }
+
+    #[test]
+    fn test_load_or_create() {
+        let path = std::env::temp_dir().join(format!("rfs_token_secret_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let secret = TokenSecret::load_or_create(&path).unwrap();
+        assert!(TokenSecret::load_or_create(&path).unwrap() == secret);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
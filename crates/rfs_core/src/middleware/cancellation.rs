@@ -0,0 +1,25 @@
+//! Cancellation of requests that are superseded while they are handled.
+//!
+//! A client that sends the same invocation again, e.g. after giving up on a slow response,
+//! has no use for the result of the earlier request. The dispatcher cancels the earlier
+//! request, and long-running remote methods stop early by checking [request_cancellation].
+
+use std::future::Future;
+
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    static CANCELLATION: CancellationToken;
+}
+
+/// Run a future as the handler of a request that can be cancelled.
+pub(crate) async fn with_cancellation<F: Future>(token: CancellationToken, future: F) -> F::Output {
+    CANCELLATION.scope(token, future).await
+}
+
+/// Returns the token that is cancelled once the request being handled is superseded.
+///
+/// Only set while a dispatcher runs a remote method for an identified client.
+pub fn request_cancellation() -> Option<CancellationToken> {
+    CANCELLATION.try_with(|token| token.clone()).ok()
+}
@@ -0,0 +1,90 @@
+//! Identification of client instances.
+//!
+//! Clients behind the same NAT share an address, and every invocation is sent from a new port.
+//! Each [super::ContextManager] generates a [ClientId] and includes it in its requests,
+//! so the remote can tell clients apart regardless of their address.
+
+use std::{fmt::Display, future::Future, net::SocketAddrV4};
+
+use serde::{Deserialize, Serialize};
+
+/// Instance ID of a client, a random UUID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ClientId {
+    high: u64,
+    low: u64,
+}
+
+impl ClientId {
+    /// Generate a new random (version 4) ID.
+    pub fn random() -> Self {
+        Self {
+            high: (rand::random::<u64>() & !0xf000) | 0x4000,
+            low: (rand::random::<u64>() & !(0b11 << 62)) | (0b10 << 62),
+        }
+    }
+}
+
+/// Clients that do not identify themselves are identified by their address.
+impl From<SocketAddrV4> for ClientId {
+    fn from(addr: SocketAddrV4) -> Self {
+        Self {
+            high: 0,
+            low: ((u32::from(*addr.ip()) as u64) << 16) | addr.port() as u64,
+        }
+    }
+}
+
+impl Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            self.high >> 32,
+            (self.high >> 16) & 0xffff,
+            self.high & 0xffff,
+            self.low >> 48,
+            self.low & 0xffff_ffff_ffff
+        )
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_CLIENT: ClientId;
+}
+
+/// Run a future as the handler of a request made by a client.
+pub(crate) async fn with_client<F: Future>(client: ClientId, future: F) -> F::Output {
+    CURRENT_CLIENT.scope(client, future).await
+}
+
+/// Returns the client that made the request being handled.
+///
+/// Only set while a dispatcher runs a remote method.
+pub fn current_client() -> Option<ClientId> {
+    CURRENT_CLIENT.try_with(|client| *client).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_id() {
+        let id = ClientId::random();
+        let formatted = id.to_string();
+
+        assert_ne!(id, ClientId::random());
+        assert_eq!(formatted.len(), 36);
+        assert_eq!(&formatted[14..15], "4");
+        assert_eq!(
+            ClientId::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8080)).to_string(),
+            "00000000-0000-0000-0000-7f0000011f90"
+        );
+
+        assert_eq!(current_client(), None);
+        assert_eq!(with_client(id, async { current_client() }).await, Some(id));
+    }
+}
@@ -0,0 +1,140 @@
+//! Time source for timeouts, retries and request statistics.
+//!
+//! Middleware reads the time and sleeps through a [Clock], so tests can swap in a
+//! [PausedClock] and run timeouts without waiting for them.
+
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+
+/// A source of time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// Wait for a duration to elapse.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Time elapsed since an earlier instant.
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The default clock of middleware, shared between clones.
+pub(crate) fn real_clock() -> Arc<dyn Clock> {
+    Arc::new(RealClock)
+}
+
+/// A clock that only moves when [PausedClock::advance] is called.
+///
+/// Sleeps on the clock complete once it is advanced past their deadline, however long
+/// that takes in real time. The runtime timer is left alone, so sockets and other timers
+/// keep running in real time. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct PausedClock {
+    now: Arc<tokio::sync::watch::Sender<Instant>>,
+}
+
+#[cfg(test)]
+impl PausedClock {
+    /// Start a clock at the current time.
+    pub fn start() -> Self {
+        Self {
+            now: Arc::new(tokio::sync::watch::channel(Instant::now()).0),
+        }
+    }
+
+    /// Move the clock forward, letting any sleeps that are due complete.
+    pub async fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+        tokio::task::yield_now().await;
+    }
+
+    /// Run a future, advancing the clock by `step` whenever it is waiting.
+    ///
+    /// The runtime polls sockets between steps, so futures that wait on loopback sockets
+    /// keep up with the clock as long as `step` is well below their timeouts.
+    pub async fn run<F: std::future::Future>(&self, step: Duration, fut: F) -> F::Output {
+        tokio::pin!(fut);
+        loop {
+            tokio::select! {
+                biased;
+                out = &mut fut => return out,
+                _ = tokio::task::yield_now() => self.advance(step).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for PausedClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let deadline = self.now() + duration;
+        let mut now = self.now.subscribe();
+
+        Box::pin(async move {
+            // the clock cannot be advanced once every handle to it is dropped
+            if now.wait_for(|now| *now >= deadline).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_paused_clock() {
+        let clock = PausedClock::start();
+        let start = clock.now();
+
+        // sleeps wait for the clock, not the runtime timer
+        let mut sleep = clock.sleep(Duration::from_secs(3600));
+        assert!(futures::poll!(&mut sleep).is_pending());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(futures::poll!(&mut sleep).is_pending());
+
+        clock.advance(Duration::from_secs(3600)).await;
+        assert!(futures::poll!(&mut sleep).is_ready());
+        assert_eq!(clock.elapsed(start).as_secs(), 3600);
+
+        // clones share the time
+        clock.clone().advance(Duration::from_secs(5)).await;
+        assert_eq!(clock.elapsed(start), Duration::from_secs(3605));
+
+        // running a sleep advances the clock until it completes
+        clock
+            .run(
+                Duration::from_millis(100),
+                clock.sleep(Duration::from_secs(2)),
+            )
+            .await;
+        assert_eq!(clock.elapsed(start), Duration::from_secs(3607));
+    }
+}
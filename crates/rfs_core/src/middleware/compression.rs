@@ -0,0 +1,54 @@
+//! Compression of responses, for clients on slow links.
+//!
+//! Clients advertise that they accept compressed responses in their requests.
+//! Dispatchers only compress responses larger than a threshold, and only if
+//! compressing makes them smaller.
+
+use super::InvokeError;
+
+/// Responses larger than this number of bytes are compressed by default
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// DEFLATE level, trading speed for size
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Compress a response payload.
+///
+/// Returns `None` if the compressed payload is not smaller.
+pub(crate) fn compress(payload: &[u8]) -> Option<Vec<u8>> {
+    let compressed = miniz_oxide::deflate::compress_to_vec(payload, COMPRESSION_LEVEL);
+
+    match compressed.len() < payload.len() {
+        true => Some(compressed),
+        false => None,
+    }
+}
+
+/// Decompress a compressed response payload.
+pub(crate) fn decompress(compressed: &[u8]) -> Result<Vec<u8>, InvokeError> {
+    miniz_oxide::inflate::decompress_to_vec(compressed).map_err(|e| {
+        log::error!("failed to decompress response: {:?}", e.status);
+        InvokeError::DeserializationFailed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let payload = b"remote file service ".repeat(100);
+
+        let compressed = compress(&payload).unwrap();
+        assert!(compressed.len() < payload.len());
+        assert_eq!(decompress(&compressed).unwrap(), payload);
+
+        // incompressible payloads are sent as they are
+        assert_eq!(compress(&[0x42]), None);
+        assert_eq!(
+            decompress(b"not deflate"),
+            Err(InvokeError::DeserializationFailed)
+        );
+    }
+}
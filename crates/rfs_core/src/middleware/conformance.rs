@@ -0,0 +1,449 @@
+//! Conformance checks for implementors of [TransmissionProtocol](super::TransmissionProtocol).
+//!
+//! A [ConformanceSuite] runs transfers between loopback sockets and checks that payloads are
+//! delivered byte-exact, including empty payloads, payloads of the largest supported size
+//! and several transfers at once. To simulate faults, datagrams between the sockets pass
+//! through a relay that drops them according to a [FaultPolicy]. The relay only forwards
+//! UDP datagrams, so protocols over other transports are checked without faults.
+//!
+//! ```ignore
+//! ConformanceSuite::default()
+//!     .with_faults(FaultPolicy::DropEvery(5))
+//!     .run(Arc::new(MyProto))
+//!     .await
+//!     .unwrap();
+//! ```
+//!
+//! Only datagrams sent to the address a transfer was started with are relayed.
+//! Protocols that move a transfer to another address, such as [super::HandshakeProto] with
+//! data ports, only have their datagrams up to the move dropped.
+
+use std::{
+    fmt::Display,
+    io,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+use super::{probability_frac, sockaddr_to_v4, SharedProtocol};
+
+/// Datagrams dropped by the relay between the sender and receiver of a transfer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FaultPolicy {
+    /// Every datagram is delivered
+    #[default]
+    None,
+
+    /// The first `n` datagrams are dropped, in either direction
+    DropFirst(u32),
+
+    /// Every `n`th datagram is dropped, in either direction
+    DropEvery(u32),
+
+    /// 1 in `n` datagrams is dropped at random, in either direction
+    DropRandom(u32),
+}
+
+impl FaultPolicy {
+    /// Returns true if the `n`th datagram through the relay, counted from 1, is dropped.
+    fn drops(&self, n: u32) -> bool {
+        match *self {
+            FaultPolicy::None => false,
+            FaultPolicy::DropFirst(first) => n <= first,
+            FaultPolicy::DropEvery(every) => n.is_multiple_of(every),
+            FaultPolicy::DropRandom(frac) => frac != 0 && probability_frac(frac),
+        }
+    }
+}
+
+/// A check of the suite that a protocol failed
+#[derive(Debug)]
+pub struct ConformanceFailure {
+    /// Name of the check
+    pub check: &'static str,
+
+    /// Protocol under test
+    pub protocol: String,
+
+    pub reason: String,
+}
+
+impl Display for ConformanceFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed {}: {}",
+            self.protocol, self.check, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ConformanceFailure {}
+
+/// Checks a [TransmissionProtocol](super::TransmissionProtocol) against loopback sockets.
+#[derive(Clone, Debug)]
+pub struct ConformanceSuite {
+    timeout: Duration,
+    retries: u8,
+
+    /// Largest payload the protocol is expected to deliver
+    max_payload: usize,
+
+    /// Number of transfers made at once
+    concurrency: usize,
+
+    faults: FaultPolicy,
+
+    /// Time a transfer may take before the protocol is considered to hang
+    deadline: Duration,
+
+    /// Lost payloads are not failures
+    best_effort: bool,
+}
+
+impl Default for ConformanceSuite {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(400),
+            retries: 5,
+            max_payload: 60_000,
+            concurrency: 4,
+            faults: FaultPolicy::None,
+            deadline: Duration::from_secs(30),
+            best_effort: false,
+        }
+    }
+}
+
+/// Outcome of a single transfer
+struct Transfer {
+    sent: io::Result<usize>,
+
+    /// `None` if the receiver did not return within the deadline
+    received: Option<io::Result<Vec<u8>>>,
+}
+
+impl ConformanceSuite {
+    /// Set the timeout and retries passed to the protocol
+    pub fn with_timeout(mut self, timeout: Duration, retries: u8) -> Self {
+        self.timeout = timeout;
+        self.retries = retries;
+        self
+    }
+
+    /// Set the largest payload the protocol is expected to deliver
+    pub fn with_max_payload(mut self, size: usize) -> Self {
+        self.max_payload = size;
+        self
+    }
+
+    /// Set the number of transfers made at once
+    pub fn with_concurrency(mut self, transfers: usize) -> Self {
+        self.concurrency = transfers;
+        self
+    }
+
+    /// Drop datagrams of the fault check according to a policy
+    pub fn with_faults(mut self, faults: FaultPolicy) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Set the time a transfer may take before the protocol is considered to hang
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Accept lost payloads, for protocols that do not retransmit such as [super::DefaultProto].
+    ///
+    /// Payloads that are received must still be byte-exact.
+    pub fn best_effort(mut self) -> Self {
+        self.best_effort = true;
+        self
+    }
+
+    /// Run every check, returning the first that fails.
+    pub async fn run(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        self.check_empty(proto.clone()).await?;
+        self.check_max_size(proto.clone()).await?;
+        self.check_concurrent(proto.clone()).await?;
+        self.check_faults(proto.clone()).await?;
+        self.check_unreachable(proto).await
+    }
+
+    /// An empty payload is delivered as an empty payload
+    pub async fn check_empty(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        let transfer = self
+            .transfer(proto.clone(), vec![], FaultPolicy::None)
+            .await;
+        self.expect_delivered("empty payload", &proto, transfer, &[])
+    }
+
+    /// A payload of the largest supported size is delivered
+    pub async fn check_max_size(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        let payload = test_payload(self.max_payload, 0);
+        let transfer = self
+            .transfer(proto.clone(), payload.clone(), FaultPolicy::None)
+            .await;
+        self.expect_delivered("max-size payload", &proto, transfer, &payload)
+    }
+
+    /// Transfers made at once are each delivered to their own receiver
+    pub async fn check_concurrent(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        let payloads = (0..self.concurrency)
+            .map(|i| test_payload(self.max_payload / self.concurrency.max(1), i as u64 + 1))
+            .collect::<Vec<_>>();
+
+        let transfers = futures::future::join_all(
+            payloads
+                .iter()
+                .map(|p| self.transfer(proto.clone(), p.clone(), FaultPolicy::None)),
+        )
+        .await;
+
+        for (transfer, payload) in transfers.into_iter().zip(&payloads) {
+            self.expect_delivered("concurrent transfers", &proto, transfer, payload)?;
+        }
+
+        Ok(())
+    }
+
+    /// A payload is delivered despite dropped datagrams, or the transfer fails with an error.
+    ///
+    /// The sender must not report success for a payload that never arrives,
+    /// unless the suite is [Self::best_effort].
+    pub async fn check_faults(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        const CHECK: &str = "dropped datagrams";
+
+        let payload = test_payload(self.max_payload, u64::MAX);
+        let transfer = self
+            .transfer(proto.clone(), payload.clone(), self.faults)
+            .await;
+
+        match transfer.sent {
+            // the failure was reported to the sender
+            Err(_) if !self.best_effort => Ok(()),
+            _ => self.expect_delivered(CHECK, &proto, transfer, &payload),
+        }
+    }
+
+    /// Sending to a peer that never answers returns within the deadline.
+    ///
+    /// The send must fail, unless the suite is [Self::best_effort].
+    pub async fn check_unreachable(&self, proto: SharedProtocol) -> Result<(), ConformanceFailure> {
+        const CHECK: &str = "unreachable peer";
+        let failure = |reason: String| ConformanceFailure {
+            check: CHECK,
+            protocol: proto.to_string(),
+            reason,
+        };
+
+        let (tx, silent) = (bind_loopback().await, bind_loopback().await);
+        let target = local_v4(&silent);
+        let payload = test_payload(self.max_payload.min(1024), 0);
+
+        let sent = tokio::time::timeout(
+            self.deadline,
+            proto.send_bytes(&tx, target, &payload, self.timeout, self.retries),
+        )
+        .await
+        .map_err(|_| failure(format!("send did not return within {:?}", self.deadline)))?;
+
+        match sent {
+            Ok(_) if !self.best_effort => Err(failure("send reported success".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    fn expect_delivered(
+        &self,
+        check: &'static str,
+        proto: &SharedProtocol,
+        transfer: Transfer,
+        payload: &[u8],
+    ) -> Result<(), ConformanceFailure> {
+        let failure = |reason: String| ConformanceFailure {
+            check,
+            protocol: proto.to_string(),
+            reason,
+        };
+
+        if let Err(e) = &transfer.sent {
+            if !self.best_effort {
+                return Err(failure(format!("send failed: {}", e)));
+            }
+        }
+
+        match transfer.received {
+            Some(Ok(received)) if received == payload => Ok(()),
+            Some(Ok(received)) => Err(failure(format!(
+                "received {} bytes that differ from the {} sent",
+                received.len(),
+                payload.len()
+            ))),
+            _ if self.best_effort => Ok(()),
+            Some(Err(e)) => Err(failure(format!("receive failed: {}", e))),
+            None => Err(failure(format!(
+                "receive did not return within {:?}",
+                self.deadline
+            ))),
+        }
+    }
+
+    /// Send a payload from one loopback socket to another, through a relay if there are faults.
+    async fn transfer(
+        &self,
+        proto: SharedProtocol,
+        payload: Vec<u8>,
+        faults: FaultPolicy,
+    ) -> Transfer {
+        let (tx, rx) = (bind_loopback().await, bind_loopback().await);
+        let relay = match faults {
+            FaultPolicy::None => None,
+            faults => Some(Relay::start(local_v4(&tx), local_v4(&rx), faults).await),
+        };
+        let target = relay.as_ref().map_or(local_v4(&rx), |r| r.addr);
+
+        let (timeout, retries, deadline) = (self.timeout, self.retries, self.deadline);
+        let rx_proto = proto.clone();
+        let receiver = tokio::spawn(async move {
+            tokio::time::timeout(deadline, rx_proto.recv_bytes(&rx, timeout, retries))
+                .await
+                .ok()
+                .map(|res| res.map(|(_, data)| data))
+        });
+
+        let sent = match tokio::time::timeout(
+            deadline,
+            proto.send_bytes(&tx, target, &payload, timeout, retries),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("send did not return within {:?}", deadline),
+            )),
+        };
+
+        let received = match sent.is_ok() || self.best_effort {
+            true => receiver.await.unwrap_or(None),
+            false => {
+                receiver.abort();
+                None
+            }
+        };
+
+        Transfer { sent, received }
+    }
+}
+
+/// Forwards datagrams between the sender and receiver of a transfer, dropping some.
+struct Relay {
+    addr: SocketAddrV4,
+    task: JoinHandle<()>,
+}
+
+impl Relay {
+    async fn start(sender: SocketAddrV4, receiver: SocketAddrV4, faults: FaultPolicy) -> Self {
+        let sock = bind_loopback().await;
+        let addr = local_v4(&sock);
+        let count = AtomicU32::new(0);
+
+        let task = tokio::spawn(async move {
+            let mut buf = vec![0_u8; 65535];
+
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                if faults.drops(count.fetch_add(1, Ordering::Relaxed) + 1) {
+                    log::debug!("relay dropped a datagram of {} bytes", size);
+                    continue;
+                }
+
+                let to = match from == sender.into() {
+                    true => receiver,
+                    false => sender,
+                };
+                let _ = sock.send_to(&buf[..size], to).await;
+            }
+        });
+
+        Self { addr, task }
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn bind_loopback() -> UdpSocket {
+    UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("loopback sockets can be bound")
+}
+
+fn local_v4(sock: &UdpSocket) -> SocketAddrV4 {
+    sock.local_addr()
+        .and_then(sockaddr_to_v4)
+        .expect("loopback sockets are IPv4")
+}
+
+/// Bytes of a payload that vary with its seed, including zeros.
+fn test_payload(size: usize, seed: u64) -> Vec<u8> {
+    (0..size as u64)
+        .map(|i| (i.wrapping_mul(31).wrapping_add(seed) % 251) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::middleware::{DefaultProto, HandshakeProto, PausedClock, RequestAckProto};
+
+    #[test]
+    fn test_fault_policy() {
+        let dropped = |policy: FaultPolicy| (1..=10).filter(|n| policy.drops(*n)).count();
+
+        assert_eq!(dropped(FaultPolicy::None), 0);
+        assert_eq!(dropped(FaultPolicy::DropFirst(2)), 2);
+        assert_eq!(dropped(FaultPolicy::DropEvery(3)), 3);
+        assert_eq!(dropped(FaultPolicy::DropEvery(0)), 0);
+        assert_eq!(dropped(FaultPolicy::DropRandom(1)), 10);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_protocols_conform() {
+        ConformanceSuite::default()
+            .with_faults(FaultPolicy::DropEvery(4))
+            .best_effort()
+            .run(Arc::new(DefaultProto))
+            .await
+            .unwrap();
+
+        ConformanceSuite::default()
+            .with_timeout(Duration::from_millis(100), 3)
+            .with_faults(FaultPolicy::DropFirst(2))
+            .run(Arc::new(RequestAckProto))
+            .await
+            .unwrap();
+
+        // timeouts of the handshake elapse on a paused clock, which moves while it waits
+        let clock = PausedClock::start();
+        let suite = ConformanceSuite::default()
+            .with_timeout(Duration::from_millis(200), 3)
+            .with_max_payload(100_000)
+            .with_faults(FaultPolicy::DropEvery(7));
+        let proto = HandshakeProto::default().with_clock(Arc::new(clock.clone()));
+        clock
+            .run(Duration::from_millis(5), suite.run(Arc::new(proto)))
+            .await
+            .unwrap();
+    }
+}
@@ -1,16 +1,57 @@
 //! The client-side middleware module
 
 use crate::{middleware::MiddlewareData, RemotelyInvocable};
+use async_trait::async_trait;
 use std::{
     fmt::Debug,
     io,
     net::{Ipv4Addr, SocketAddrV4},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
-use tokio::net::UdpSocket;
+use tokio::{net::UdpSocket, sync::broadcast};
 
-use super::{InvokeError, TransmissionProtocol};
+use super::{
+    clock::real_clock, current_observer, loopback::Loopback, observe_retries, probability_frac,
+    AccessToken, ClientId, Clock, FailureRate, InvokeError, InvokeProgress, InvokeSample,
+    InvokeStats, PayloadHandler, PooledSocket, ReadCache, RequestTimeout, Retries, RetryEvent,
+    RttEstimator, SharedSocketPool, TransmissionProtocol, VersionInfo, VersionMatch,
+};
+
+/// Number of progress events kept for subscribers that fall behind
+const PROGRESS_CAPACITY: usize = 16;
+
+/// Time an invocation is allowed to take, including all of its retries
+const DEFAULT_DEADLINE: Duration = Duration::from_secs(30);
+
+/// Sends remote method invocations to the remote.
+///
+/// Generated clients accept any invoker, so wrappers such as [super::RetryingClient]
+/// can be used in place of a [ContextManager].
+#[async_trait]
+pub trait Invoker: Send {
+    /// Send a serialized invocation and return the serialized response.
+    async fn invoke_raw(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError>;
+
+    /// Re-establish the connection with the remote.
+    async fn reconnect(&mut self) -> io::Result<()>;
+
+    /// Send an invocation over the network, and returns the result.
+    async fn invoke<P: RemotelyInvocable + Debug + Send>(
+        &mut self,
+        payload: P,
+    ) -> Result<P, InvokeError> {
+        log::info!("invoking: {:?}", payload);
+        crate::register_signatures([P::remote_method_signature()]);
+
+        let resp = self.invoke_raw(payload.invoke_bytes()).await?;
+
+        P::process_invocation(&resp)
+    }
+}
 
 /// The context manager for the client.
 ///
@@ -36,6 +77,64 @@ where
 
     #[allow(unused)]
     protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
+
+    /// Retry progress of invocations. Shared between clones.
+    progress: broadcast::Sender<InvokeProgress>,
+
+    /// Failures injected into invocations
+    faults: InvocationFaults,
+
+    /// Identifies this client to the remote. Shared between clones.
+    client_id: ClientId,
+
+    /// Sequence number of the next invocation. Shared between clones.
+    next_seq: Arc<AtomicU64>,
+
+    /// Time source for the remaining time of retried invocations
+    clock: Arc<dyn Clock>,
+
+    /// Invocations still running after this long are abandoned
+    deadline: Duration,
+
+    /// Version sent to the remote when connecting
+    local_version: VersionInfo,
+
+    /// Version of the remote, exchanged when connecting
+    remote_version: VersionInfo,
+
+    /// Latency and size of completed invocations. Shared between clones.
+    stats: Arc<Mutex<InvokeStats>>,
+
+    /// Sockets that callbacks are received on. Shared between clones.
+    callback_sockets: SharedSocketPool,
+
+    /// Presented with every invocation, to remotes that require tokens
+    token: Option<AccessToken>,
+
+    /// Ask the remote to compress large responses
+    accepts_compressed: bool,
+
+    /// Handler that invocations are passed to instead of being sent to the remote
+    loopback: Option<Loopback>,
+
+    /// Round-trip time to the remote, if request timeouts adapt to it. Shared between clones.
+    rtt: Option<Arc<Mutex<RttEstimator>>>,
+
+    /// Cached contents of remote files, if reads are cached. Shared between clones.
+    read_cache: Option<Arc<Mutex<ReadCache>>>,
+}
+
+/// Failures injected into invocations by a [ContextManager], independent of the protocol.
+///
+/// Used to demonstrate the difference between invocation semantics on methods that are not idempotent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InvocationFaults {
+    /// Discard the response of 1 in N invocations after the remote has executed them.
+    /// The invocation fails with [InvokeError::RequestTimedOut].
+    pub drop_response: Option<FailureRate>,
+
+    /// Number of times each request is sent again, from the same socket
+    pub duplicates: u8,
 }
 
 impl ContextManager
@@ -46,74 +145,320 @@ impl ContextManager
 
     /// Create a new context manager, along with a target IP and port.
     ///
+    /// Fails with [io::ErrorKind::AddrNotAvailable] if the target cannot be reached from `source`,
+    /// see [super::route_source] to find an address that can.
+    ///
     /// TODO: bind and wait for server to become online.
     pub async fn new(
         source: Ipv4Addr,
         target: SocketAddrV4,
-        timeout: Duration,
-        retries: u8,
+        timeout: impl Into<RequestTimeout>,
+        retries: impl Into<Retries>,
         protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
     ) -> std::io::Result<Self> {
+        let (timeout, retries) = (timeout.into().0, retries.into().0);
+        super::check_route(source, target)?;
+
         let s = Self {
             source_ip: source,
             target_ip: target,
             timeout,
             retries,
             protocol,
+            progress: broadcast::channel(PROGRESS_CAPACITY).0,
+            faults: Default::default(),
+            client_id: ClientId::random(),
+            next_seq: Default::default(),
+            clock: real_clock(),
+            deadline: DEFAULT_DEADLINE,
+            local_version: VersionInfo::local(),
+            remote_version: VersionInfo::local(),
+            stats: Default::default(),
+            // the remote may still be retrying a callback to a socket when it is returned
+            callback_sockets: SharedSocketPool::new(source, timeout * (retries as u32 + 1)),
+            token: None,
+            accepts_compressed: false,
+            loopback: None,
+            rtt: None,
+            read_cache: None,
         };
 
-        // Ok(s)
+        let remote_version = s.ping().await?;
 
-        let sock = s.generate_socket().await?;
+        Ok(Self {
+            remote_version,
+            ..s
+        })
+    }
+
+    /// Create a context manager that passes invocations to a handler in this process.
+    ///
+    /// Invocations skip the network entirely, so client logic can be tested
+    /// quickly and deterministically. Callbacks from the handler are not received.
+    pub fn loopback<H: PayloadHandler + Send + 'static>(handler: H) -> Self {
+        let timeout = Duration::from_secs(1);
+
+        Self {
+            source_ip: Ipv4Addr::LOCALHOST,
+            target_ip: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            timeout,
+            retries: 0,
+            protocol: Arc::new(super::DefaultProto),
+            progress: broadcast::channel(PROGRESS_CAPACITY).0,
+            faults: Default::default(),
+            client_id: ClientId::random(),
+            next_seq: Default::default(),
+            clock: real_clock(),
+            deadline: DEFAULT_DEADLINE,
+            local_version: VersionInfo::local(),
+            remote_version: VersionInfo::local(),
+            stats: Default::default(),
+            callback_sockets: SharedSocketPool::new(Ipv4Addr::LOCALHOST, timeout),
+            token: None,
+            accepts_compressed: false,
+            loopback: Some(Loopback::new(handler)),
+            rtt: None,
+            read_cache: None,
+        }
+    }
+
+    /// Version of the remote.
+    ///
+    /// Only minor versions can differ, connecting to a remote with a different major version fails.
+    pub fn remote_version(&self) -> &VersionInfo {
+        &self.remote_version
+    }
+
+    /// Version sent to the remote when connecting
+    pub fn local_version(&self) -> &VersionInfo {
+        &self.local_version
+    }
+
+    /// Exchange versions with the remote again, advertising the hash of the local interfaces.
+    ///
+    /// Interfaces that drifted apart are logged on both ends,
+    /// and reported by comparing the local and remote versions.
+    pub async fn with_interface_hash(mut self, hash: u64) -> io::Result<Self> {
+        self.local_version = self.local_version.with_interface_hash(hash);
+        self.remote_version = self.ping().await?;
+
+        Ok(self)
+    }
+
+    /// Ping the remote with the local version, and wait for the version of the remote.
+    async fn ping(&self) -> io::Result<VersionInfo> {
+        if self.loopback.is_some() {
+            return Ok(self.local_version.clone());
+        }
+
+        let sock = self.bind_socket().await?;
         println!("{:?}", sock);
 
         log::debug!("establishing initial conn with remote from {:?}", sock);
 
-        let payload = MiddlewareData::Ping;
+        let local = self.local_version.clone();
+        let payload = MiddlewareData::Hello(local.clone());
         let ser_payload = crate::serialize(&payload).expect("serialization must not fail");
 
-        let payload_size = s
+        let payload_size = self
             .protocol
-            .send_bytes(&sock, target, &ser_payload, timeout, retries)
+            .send_bytes(
+                &sock,
+                self.target_ip,
+                &ser_payload,
+                self.timeout,
+                self.retries,
+            )
             .await?;
 
         assert_eq!(payload_size, ser_payload.len());
 
-        let (_addr, data) = s.protocol.recv_bytes(&sock, timeout, retries).await?;
-
-        let resp: MiddlewareData = crate::deserialize(&data).unwrap();
+        let (_addr, data) = self
+            .protocol
+            .recv_bytes(&sock, self.timeout, self.retries)
+            .await?;
 
-        match resp == payload {
-            true => {
-                log::debug!("handshake established");
-                Ok(s)
-            }
-            false => {
+        let remote = match crate::deserialize(&data) {
+            Ok(MiddlewareData::Hello(remote)) => remote,
+            _ => {
                 log::debug!("invalid response");
-                Err(io::Error::new(
+                return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
                     "Expected ping response",
+                ));
+            }
+        };
+
+        match local.compare(&remote) {
+            VersionMatch::Compatible => log::debug!("handshake established"),
+            VersionMatch::MinorMismatch => log::warn!(
+                "remote version {} differs from local version {}",
+                remote,
+                local
+            ),
+            VersionMatch::InterfaceMismatch => log::warn!(
+                "remote interfaces differ from local interfaces, calls to changed methods may fail: {} vs {}",
+                remote,
+                local
+            ),
+            VersionMatch::MajorMismatch => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "remote version {} is incompatible with local version {}",
+                        remote, local
+                    ),
                 ))
             }
         }
+
+        Ok(remote)
     }
 
     /// Send an invocation over the network, and returns the result.
-    pub async fn invoke<P: RemotelyInvocable + Debug>(
+    pub async fn invoke<P: RemotelyInvocable + Debug + Send>(
         &mut self,
         payload: P,
     ) -> Result<P, InvokeError> {
-        log::info!("invoking: {:?}", payload);
+        Invoker::invoke(self, payload).await
+    }
 
-        // send to server and wait for a reply
-        let data = payload.invoke_bytes();
+    /// Take a socket to receive callbacks on.
+    ///
+    /// Sockets are pooled between clones, and returned to the pool when dropped.
+    pub async fn generate_socket(&self) -> io::Result<PooledSocket> {
+        self.callback_sockets.take().await
+    }
+
+    /// Number of callback sockets in use by this context manager and its clones.
+    pub fn live_callback_sockets(&self) -> usize {
+        self.callback_sockets.live()
+    }
+
+    /// Create and bind to a new socket, with an arbitary port
+    async fn bind_socket(&self) -> io::Result<UdpSocket> {
+        UdpSocket::bind(SocketAddrV4::new(self.source_ip, 0)).await
+    }
+
+    /// Listen on a port for a request.
+    pub async fn listen(&mut self, target: &UdpSocket) -> io::Result<Vec<u8>> {
+        let (_addr, data) = self
+            .protocol
+            .recv_bytes(target, self.timeout, self.retries)
+            .await?;
+
+        Ok(data)
+    }
+
+    /// Inject failures into invocations made by this context manager and its clones.
+    pub fn with_faults(mut self, faults: InvocationFaults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Use a different time source for the progress of invocations.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Abandon invocations that take longer than a deadline.
+    ///
+    /// A transfer can outlive its retries if the protocol loses track of its state,
+    /// e.g. when the last packet of a handshake is lost. Past the deadline, the transfer socket
+    /// is closed and the invocation fails with [InvokeError::RequestTimedOut].
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Present an access token with every invocation made by this context manager and its clones.
+    pub fn with_token(mut self, token: AccessToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Ask the remote to compress large responses, for slow links.
+    pub fn with_compressed_responses(mut self) -> Self {
+        self.accepts_compressed = true;
+        self
+    }
+
+    /// Derive the timeout of each request from the measured round-trip time to the remote.
+    ///
+    /// The timeout this context manager was created with is the upper bound, and is used
+    /// until the first invocation completes. See [RttEstimator].
+    pub fn with_adaptive_timeout(mut self) -> Self {
+        self.rtt = Some(Arc::new(Mutex::new(RttEstimator::new(self.timeout))));
+        self
+    }
+
+    /// Serve reads of file contents from a local cache for `freshness`, before revalidating them.
+    ///
+    /// The cache is timed by the clock of this context manager. See [ReadCache].
+    pub fn with_read_cache(mut self, freshness: Duration) -> Self {
+        self.read_cache = Some(Arc::new(Mutex::new(ReadCache::new(
+            freshness,
+            self.clock.clone(),
+        ))));
+        self
+    }
+
+    /// Returns the read cache of this context manager and its clones, if reads are cached.
+    pub fn read_cache(&self) -> Option<&Mutex<ReadCache>> {
+        self.read_cache.as_deref()
+    }
+
+    /// Returns the timeout of the next request.
+    pub fn request_timeout(&self) -> Duration {
+        match &self.rtt {
+            Some(rtt) => rtt.lock().expect("rtt lock poisoned").timeout(),
+            None => self.timeout,
+        }
+    }
+
+    /// Returns the smoothed round-trip time to the remote and its variation,
+    /// if timeouts are adaptive and any invocation was measured.
+    pub fn rtt_estimate(&self) -> Option<(Duration, Duration)> {
+        self.rtt
+            .as_ref()
+            .and_then(|rtt| rtt.lock().expect("rtt lock poisoned").estimate())
+    }
+
+    /// Instance ID this context manager and its clones identify themselves with.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Subscribe to the retry progress of invocations made by this context manager and its clones.
+    pub fn progress(&self) -> broadcast::Receiver<InvokeProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Statistics of the invocations made by this context manager and its clones.
+    pub fn stats(&self) -> InvokeStats {
+        self.stats.lock().expect("stats lock poisoned").clone()
+    }
+
+    /// Send a middleware payload to the remote and wait for the response
+    async fn transmit(&self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+        if let Some(loopback) = &self.loopback {
+            return loopback.handle(self.client_id, &payload).await;
+        }
 
         // for now, bind and connect on every invocation
-        let source = self.generate_socket().await?;
+        let source = self.bind_socket().await?;
+        let timeout = self.request_timeout();
 
         log::debug!("connected to {}", self.target_ip);
 
-        let middleware_payload = MiddlewareData::Payload(data);
+        let middleware_payload = MiddlewareData::Request {
+            client: self.client_id,
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            payload,
+            token: self.token,
+            accepts_compressed: self.accepts_compressed,
+        };
         let serialized_payload =
             crate::serialize(&middleware_payload).expect("serialization must not fail");
 
@@ -123,45 +468,61 @@ impl ContextManager
                 &source,
                 self.target_ip,
                 &serialized_payload,
-                self.timeout,
+                timeout,
                 self.retries,
             )
             .await
-            .map_err(|e| <InvokeError>::from(e))?;
+            .map_err(InvokeError::from)?;
 
         log::debug!("awaiting remote response on {:?}", source);
         let (_addr, resp) = self
             .protocol
-            .recv_bytes(&source, self.timeout, self.retries)
+            .recv_bytes(&source, timeout, self.retries)
             .await?;
 
+        for dup in 0..self.faults.duplicates {
+            log::debug!("sending duplicate request #{}", dup + 1);
+
+            let dup_res = async {
+                self.protocol
+                    .send_bytes(
+                        &source,
+                        self.target_ip,
+                        &serialized_payload,
+                        timeout,
+                        self.retries,
+                    )
+                    .await?;
+
+                self.protocol
+                    .recv_bytes(&source, timeout, self.retries)
+                    .await
+            }
+            .await;
+
+            if let Err(e) = dup_res {
+                log::error!("duplicate request #{} failed: {}", dup + 1, e);
+            }
+        }
+
+        if let Some(FailureRate(frac)) = self.faults.drop_response {
+            if probability_frac(frac) {
+                log::debug!("dropping response");
+                return Err(InvokeError::RequestTimedOut);
+            }
+        }
+
         let middleware_resp: MiddlewareData =
             crate::deserialize(&resp).map_err(|_| InvokeError::DeserializationFailed)?;
 
         match middleware_resp {
-            MiddlewareData::Payload(p) => P::process_invocation(&p),
+            MiddlewareData::Payload(p) => Ok(p),
+            MiddlewareData::CompressedPayload(p) => super::compression::decompress(&p),
             MiddlewareData::Error(e) => Err(e),
             _ => unimplemented!(),
         }
     }
 
-    /// Create and bind to a new socket, with an arbitary port
-    pub async fn generate_socket(&self) -> io::Result<UdpSocket> {
-        let sock = UdpSocket::bind(SocketAddrV4::new(self.source_ip, 0)).await?;
-
-        Ok(sock)
-    }
-
-    /// Listen on a port for a request.
-    pub async fn listen(&mut self, target: &UdpSocket) -> io::Result<Vec<u8>> {
-        let (_addr, data) = self
-            .protocol
-            .recv_bytes(target, self.timeout, self.retries)
-            .await?;
-
-        Ok(data)
-    }
-
     // /// Ping the remote and waits for a response
     //     async fn ping_remote(&self) -> Result<(), InvokeError> {
     //         let sock = self.connect_remote().await?;
@@ -175,3 +536,219 @@ impl ContextManager
     //         Ok(())
     //     }
 }
+
+#[async_trait]
+impl Invoker for ContextManager {
+    async fn invoke_raw(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+        let started = self.clock.now();
+        let budget = self.request_timeout() * (self.retries as u32 + 1);
+        let max_attempts = self.retries as u32;
+        let retried = Arc::new(AtomicBool::new(false));
+        let request_len = payload.len();
+        let method = crate::decode_signature(&payload).unwrap_or("unknown method");
+
+        // retries are still reported to any observer set by the caller
+        let outer = current_observer();
+        let progress = self.progress.clone();
+        let observer = {
+            let retried = retried.clone();
+            let clock = self.clock.clone();
+            move |event: &RetryEvent| {
+                if let Some(o) = &outer {
+                    o.on_retry(event);
+                }
+
+                retried.store(true, Ordering::Relaxed);
+                // having no subscribers is not an error
+                let _ = progress.send(InvokeProgress::Retrying {
+                    attempt: event.attempt,
+                    max_attempts,
+                    remaining: budget.saturating_sub(clock.elapsed(started)),
+                    reason: event.reason,
+                });
+            }
+        };
+
+        // dropping the transfer closes its socket and releases any protocol state
+        let res = tokio::select! {
+            res = observe_retries(Arc::new(observer), self.transmit(payload)) => res,
+            _ = self.clock.sleep(self.deadline) => {
+                log::error!(
+                    "invocation of {} did not complete within {:?}, abandoning it",
+                    method,
+                    self.deadline
+                );
+                Err(InvokeError::RequestTimedOut)
+            }
+        };
+
+        if retried.load(Ordering::Relaxed) {
+            let _ = self.progress.send(InvokeProgress::Finished);
+        }
+        if let Err(e) = &res {
+            log::debug!("invocation of {} failed: {:?}", method, e);
+        }
+        if let Some(rtt) = &self.rtt {
+            let mut rtt = rtt.lock().expect("rtt lock poisoned");
+            match (&res, retried.load(Ordering::Relaxed)) {
+                (Ok(_), false) => rtt.observe(self.clock.elapsed(started)),
+                (Ok(_), true) | (Err(InvokeError::RequestTimedOut), _) => rtt.on_timeout(),
+                _ => (),
+            }
+        }
+
+        self.stats
+            .lock()
+            .expect("stats lock poisoned")
+            .record(InvokeSample {
+                finished: self.clock.now(),
+                latency: self.clock.elapsed(started),
+                bytes: request_len + res.as_ref().map(|r| r.len()).unwrap_or(0),
+                success: res.is_ok(),
+            });
+
+        res
+    }
+
+    async fn reconnect(&mut self) -> io::Result<()> {
+        self.remote_version = self.ping().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Display;
+
+    use crate::middleware::{Endpoint, PausedClock};
+
+    use super::*;
+
+    /// Answers pings with a version, and never receives anything else.
+    #[derive(Debug, Default)]
+    struct StalledProto {
+        version: VersionInfo,
+        pinged: AtomicBool,
+    }
+
+    impl Display for StalledProto {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "StalledProto")
+        }
+    }
+
+    #[async_trait]
+    impl TransmissionProtocol for StalledProto {
+        async fn send_bytes(
+            &self,
+            _sock: &dyn Endpoint,
+            _target: SocketAddrV4,
+            payload: &[u8],
+            _timeout: Duration,
+            _retries: u8,
+        ) -> io::Result<usize> {
+            if let Ok(MiddlewareData::Hello(_)) = crate::deserialize(payload) {
+                self.pinged.store(true, Ordering::Relaxed);
+            }
+            Ok(payload.len())
+        }
+
+        async fn recv_bytes(
+            &self,
+            sock: &dyn Endpoint,
+            _timeout: Duration,
+            _retries: u8,
+        ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+            match self.pinged.swap(false, Ordering::Relaxed) {
+                true => Ok((
+                    crate::middleware::sockaddr_to_v4(sock.local_addr()?)?,
+                    crate::serialize(&MiddlewareData::Hello(self.version.clone())).unwrap(),
+                )),
+                false => std::future::pending().await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invocation_deadline() {
+        let clock = PausedClock::start();
+        let deadline = Duration::from_secs(10);
+
+        let mut ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1),
+            Duration::from_millis(100),
+            3,
+            Arc::new(StalledProto::default()),
+        )
+        .await
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()))
+        .with_deadline(deadline);
+
+        let start = clock.now();
+        let res = clock
+            .run(Duration::from_millis(10), ctx.invoke_raw(vec![1, 2, 3]))
+            .await;
+
+        assert_eq!(res, Err(InvokeError::RequestTimedOut));
+        assert_eq!(clock.elapsed(start).as_secs(), deadline.as_secs());
+
+        // clones share their statistics
+        let stats = ctx.clone().stats();
+        assert_eq!((stats.invocations, stats.failures, stats.bytes), (1, 1, 3));
+        let sample = stats.samples().next().unwrap();
+        assert_eq!(sample.latency.as_secs(), deadline.as_secs());
+    }
+
+    #[tokio::test]
+    async fn test_version_exchange() {
+        let connect = |crate_version: &str| {
+            let proto = StalledProto {
+                version: VersionInfo {
+                    crate_version: crate_version.to_string(),
+                    ..VersionInfo::local()
+                },
+                ..Default::default()
+            };
+
+            ContextManager::new(
+                Ipv4Addr::LOCALHOST,
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1),
+                Duration::from_millis(100),
+                3,
+                Arc::new(proto),
+            )
+        };
+
+        // only the minor version differs
+        let ctx = connect("0.99.0").await.unwrap();
+        assert_eq!(ctx.remote_version().crate_version, "0.99.0");
+
+        let err = connect("99.0.0").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("99.0.0"));
+
+        // drifted interfaces are reported, but do not fail the connection
+        let proto = StalledProto {
+            version: VersionInfo::local().with_interface_hash(1),
+            ..Default::default()
+        };
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1),
+            Duration::from_millis(100),
+            3,
+            Arc::new(proto),
+        )
+        .await
+        .unwrap()
+        .with_interface_hash(2)
+        .await
+        .unwrap();
+        assert_eq!(
+            ctx.local_version().compare(ctx.remote_version()),
+            VersionMatch::InterfaceMismatch
+        );
+    }
+}
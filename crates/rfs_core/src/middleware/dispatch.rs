@@ -6,17 +6,25 @@
 use crate::middleware::{hash_primary, MiddlewareData};
 use crate::ser_de::{self, ser};
 
-use super::{PayloadHandler, TransmissionProtocol, BYTE_BUF_SIZE};
+use super::{
+    cancellation::with_cancellation, client_id::with_client, compression, AuthError, ClientId,
+    InvokeError, PayloadHandler, PortRange, ReceivedPayload, RequestTimeout, Retries, Semantics,
+    SloTarget, SloTracker, SocketPool, SocketProvider, TokenSecret, TransmissionProtocol,
+    VersionInfo, VersionMatch, BYTE_BUF_SIZE, DEFAULT_COMPRESSION_THRESHOLD, DEFAULT_MEMORY_CAP,
+};
+use super::{clock::real_clock, Clock, LifecycleEvent, LifecycleHook};
 use futures::lock::Mutex;
 use std::borrow::{Borrow, BorrowMut};
-use std::collections::{btree_map, HashMap};
+use std::collections::{btree_map, HashMap, VecDeque};
 use std::fmt::Debug;
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::net::{SocketAddr, SocketAddrV4};
+use std::io::{self, Read};
+use std::marker;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
-use std::{io, marker};
 use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio_util::sync::CancellationToken;
 
 /// The dispatcher for remote invocations.
 ///
@@ -41,15 +49,152 @@ where
 
     /// The dispatcher keeps track of duplicates to prevent reprocessing
     dup_filter: Arc<Mutex<DuplicateFilter>>,
-    use_filter: bool,
+
+    /// Semantics of methods that do not override them
+    semantics: Semantics,
+
+    /// Requests being handled, cancelled when superseded
+    in_flight: Arc<Mutex<InFlight>>,
+
+    /// Request statistics, shared with anything monitoring the dispatcher
+    stats: Arc<Mutex<DispatchStats>>,
+
+    /// Requests larger than this are spilled to disk while being received
+    memory_cap: usize,
+
+    /// Sockets responses are sent from, bound to the data port range.
+    ///
+    /// Responses are sent from any free port if unset.
+    data_ports: Option<Arc<Mutex<SocketPool>>>,
+
+    /// Time source for timeouts, statistics and the duplicate filter
+    clock: Arc<dyn Clock>,
+
+    /// Observes the handling of every request
+    hook: Option<Arc<dyn LifecycleHook>>,
+
+    /// Version sent to clients when they connect
+    version: VersionInfo,
+
+    /// Secret that access tokens are checked with. Tokens are not required if unset.
+    token_secret: Option<TokenSecret>,
+
+    /// Responses larger than this are compressed for clients that accept it
+    compression_threshold: usize,
+}
+
+/// Request statistics collected by the dispatcher.
+#[derive(Debug)]
+pub struct DispatchStats {
+    /// Time the dispatcher was created
+    pub started: Instant,
+
+    /// Total number of requests received
+    pub total_requests: u64,
+
+    /// Number of duplicate requests answered from the duplicate filter
+    pub duplicate_requests: u64,
+
+    /// Number of responses sent compressed
+    pub compressed_responses: u64,
+
+    /// Size of compressed responses before and after compression
+    pub compressed_bytes: (u64, u64),
+
+    /// Number of times the p95 latency of a class of methods went above its target
+    pub slo_violations: u64,
+
+    /// Latencies of the methods with a latency target
+    pub slo: SloTracker,
+
+    /// Per-client statistics
+    pub sources: HashMap<ClientId, SourceStats>,
+
+    /// Arrival times of requests within the rate window
+    recent: VecDeque<Instant>,
+
+    clock: Arc<dyn Clock>,
+}
+
+/// Statistics of a single request source
+#[derive(Clone, Debug)]
+pub struct SourceStats {
+    /// Number of requests received from the source
+    pub requests: u64,
+
+    /// Address of the last request from the source
+    pub addr: SocketAddrV4,
+
+    /// Time of the last request from the source
+    pub last_seen: Instant,
+}
+
+/// Requests being handled, keyed by client and a hash of the invocation.
+///
+/// A client sending the same invocation again, with a new sequence number,
+/// supersedes the earlier request.
+#[derive(Debug, Default)]
+struct InFlight {
+    requests: HashMap<(ClientId, u64), (u64, CancellationToken)>,
+}
+
+/// State a dispatcher shares with the tasks handling its requests
+#[derive(Debug)]
+struct HandlerContext<H> {
+    handler: Arc<Mutex<H>>,
+    protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
+    timeout: Duration,
+    retries: u8,
+    filter: Arc<Mutex<DuplicateFilter>>,
+    semantics: Semantics,
+    in_flight: Arc<Mutex<InFlight>>,
+    stats: Arc<Mutex<DispatchStats>>,
+    hook: Option<Arc<dyn LifecycleHook>>,
+    version: VersionInfo,
+    token_secret: Option<TokenSecret>,
+    compression_threshold: usize,
+}
+
+/// Frees the statistics and cached responses a dispatcher keeps for idle clients.
+#[derive(Clone, Debug)]
+pub struct ClientReaper {
+    stats: Arc<Mutex<DispatchStats>>,
+    dup_filter: Arc<Mutex<DuplicateFilter>>,
+}
+
+impl ClientReaper {
+    /// Forget clients that have not made a request for longer than `idle`.
+    ///
+    /// Returns the clients that were forgotten, and the number of their cached responses that were freed.
+    pub async fn reap(&self, idle: Duration) -> (Vec<ClientId>, usize) {
+        let mut stats = self.stats.lock().await;
+        let clients = stats
+            .sources
+            .iter()
+            .filter(|(_, s)| stats.idle_time(s) > idle)
+            .map(|(client, _)| *client)
+            .collect::<Vec<_>>();
+        stats.sources.retain(|client, _| !clients.contains(client));
+        drop(stats);
+
+        let mut filter = self.dup_filter.lock().await;
+        let cached = filter.data.len();
+        filter
+            .data
+            .retain(|(client, _), _| !clients.contains(client));
+
+        let freed = cached - filter.data.len();
+        (clients, freed)
+    }
 }
 
 /// A filter that keeps track of duplicate data, given a specific lifetime.
 #[derive(Debug)]
 struct DuplicateFilter {
-    /// Request (source + data) is the key and response (data + time) is the value
-    data: HashMap<(SocketAddrV4, Vec<u8>), (Instant, Vec<u8>)>,
+    /// Request (client + data) is the key and response (data + time) is the value
+    data: HashMap<(ClientId, Vec<u8>), (Instant, Vec<u8>)>,
     lifetime: Duration,
+    clock: Arc<dyn Clock>,
 }
 
 impl<H> Dispatcher<H>
@@ -65,15 +210,17 @@ where
         handler: H,
         protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
         sequential: bool,
-        timeout: Duration,
-        retries: u8,
-        use_filter: bool,
+        timeout: impl Into<RequestTimeout>,
+        retries: impl Into<Retries>,
     ) -> Self {
+        let (timeout, retries) = (timeout.into().0, retries.into().0);
+
         let socket = UdpSocket::bind(addr)
             .await
             .expect("failed to bind to specified address");
 
         log::info!("dipatcher using {:?}", protocol);
+        crate::register_signatures(H::signatures());
 
         Self {
             socket: Arc::new(socket),
@@ -82,8 +229,105 @@ where
             protocol,
             timeout,
             retries,
-            dup_filter: Arc::new(Mutex::new(DuplicateFilter::new(timeout, retries))),
-            use_filter,
+            dup_filter: Arc::new(Mutex::new(DuplicateFilter::new(
+                timeout,
+                retries,
+                real_clock(),
+            ))),
+            semantics: Default::default(),
+            in_flight: Default::default(),
+            stats: Default::default(),
+            memory_cap: DEFAULT_MEMORY_CAP,
+            data_ports: None,
+            clock: real_clock(),
+            hook: None,
+            version: VersionInfo::local(),
+            token_secret: None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+
+    /// Use a different time source. Statistics and cached responses are reset.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.dup_filter = Arc::new(Mutex::new(DuplicateFilter::new(
+            self.timeout,
+            self.retries,
+            clock.clone(),
+        )));
+
+        // latency targets are configuration, not statistics
+        let mut stats = DispatchStats::with_clock(clock.clone());
+        if let Some(mut prev) = self.stats.try_lock() {
+            stats.slo = std::mem::take(&mut prev.slo);
+        }
+        self.stats = Arc::new(Mutex::new(stats));
+        self.clock = clock;
+        self
+    }
+
+    /// Set the invocation semantics of methods that do not override them.
+    ///
+    /// Duplicate requests are only filtered for [Semantics::AtMostOnce].
+    pub fn with_semantics(mut self, semantics: Semantics) -> Self {
+        self.semantics = semantics;
+        self
+    }
+
+    /// Set the number of bytes of a request kept in memory while it is being received.
+    pub fn with_memory_cap(mut self, memory_cap: usize) -> Self {
+        self.memory_cap = memory_cap;
+        self
+    }
+
+    /// Notify a hook of every request received, deduplicated, executed and replied to.
+    #[cfg(any(test, feature = "lifecycle-hooks"))]
+    pub fn with_lifecycle_hook(mut self, hook: Arc<dyn LifecycleHook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Reject handlers that route to the same signature more than once.
+    ///
+    /// See [super::check_signatures].
+    pub fn with_strict_signatures(self) -> io::Result<Self> {
+        super::check_signatures(&H::signatures())?;
+        Ok(self)
+    }
+
+    /// Send responses from ports in a range, instead of any free port.
+    ///
+    /// Protocols that switch sockets for transfers are configured separately,
+    /// e.g. with [super::HandshakeProto::with_data_ports].
+    pub fn with_data_ports(mut self, ports: Option<PortRange>) -> Self {
+        let ip = match self.socket.local_addr() {
+            Ok(SocketAddr::V4(addr)) => *addr.ip(),
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+
+        self.data_ports = ports.map(|p| Arc::new(Mutex::new(SocketPool::with_ports(ip, p))));
+        self
+    }
+
+    /// Address the dispatcher receives requests on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns a handle to the request statistics of the dispatcher.
+    pub fn stats(&self) -> Arc<Mutex<DispatchStats>> {
+        self.stats.clone()
+    }
+
+    /// Returns a handle to the handler that requests are routed to.
+    pub fn handler(&self) -> Arc<Mutex<H>> {
+        self.handler.clone()
+    }
+
+    /// Returns a handle that frees the state the dispatcher keeps for clients that went idle.
+    pub fn reaper(&self) -> ClientReaper {
+        ClientReaper {
+            stats: self.stats.clone(),
+            dup_filter: self.dup_filter.clone(),
         }
     }
 
@@ -98,40 +342,34 @@ where
 
             // create new response socket
             // so we don't intercepts requests to the main dispatch socket
-            let mut resp_addr = self
-                .socket
-                .local_addr()
-                .expect("failed to get local address");
-            resp_addr.set_port(0);
-
-            let resp_sock = UdpSocket::bind(resp_addr)
-                .await
-                .expect("failed to bind response socket");
+            let resp_sock = match self.response_socket().await {
+                Ok(s) => s,
+                Err(e) => {
+                    // data ports may be freed by the time the request is retried
+                    log::error!("failed to bind response socket: {}", e);
+                    self.clock.sleep(self.timeout).await;
+                    continue;
+                }
+            };
 
             match self
                 .protocol
-                .recv_bytes(&self.socket, self.timeout, self.retries)
+                .recv_payload(&self.socket, self.timeout, self.retries, self.memory_cap)
                 .await
             {
                 // spawn resp in separate thread
-                Ok((addr, bytes)) => {
+                Ok((addr, payload)) => {
                     log::info!("received request #{} from {}", request_num, addr);
                     log::debug!("response will be sent from {:?}", resp_sock);
 
-                    let handler = self.handler.clone();
-                    let proto = self.protocol.clone(); // proto cannot be shared
-                    let timeout = self.timeout.clone();
-                    let retries = self.retries.clone();
-                    let filter = self.dup_filter.clone();
-                    let use_filter = self.use_filter;
+                    let context = self.handler_context();
+                    let data_ports = self.data_ports.clone();
 
                     // tasks can run for an arbitrary amount of time
                     let handle = tokio::spawn(async move {
-                        Self::execute_handler(
-                            addr, &bytes, resp_sock, handler, filter, use_filter, proto, timeout,
-                            retries,
-                        )
-                        .await
+                        context.execute(addr, payload, resp_sock.clone()).await;
+
+                        Self::release_response_socket(data_ports, resp_sock, context.timeout).await;
                     });
 
                     // if we are processing sequentially, we wait on each task every loop iter
@@ -143,6 +381,8 @@ where
                 // log the error
                 Err(e) => {
                     log::error!("Receive error: {}", e);
+                    Self::release_response_socket(self.data_ports.clone(), resp_sock, self.timeout)
+                        .await;
                 }
             }
 
@@ -150,38 +390,205 @@ where
         }
     }
 
+    /// Advertise the hash of the served interfaces to connecting clients.
+    pub fn with_interface_hash(mut self, hash: u64) -> Self {
+        self.version = self.version.with_interface_hash(hash);
+        self
+    }
+
+    /// Require every invocation to carry an access token issued with the secret.
+    ///
+    /// Requests without a valid token, or with a token whose scope does not cover
+    /// the method, fail with [InvokeError::Unauthorized].
+    pub fn with_token_secret(mut self, secret: TokenSecret) -> Self {
+        self.token_secret = Some(secret);
+        self
+    }
+
+    /// Track the p95 latency of classes of methods, and count the times it exceeds their target.
+    ///
+    /// See [SloTarget] for the classes a method belongs to.
+    pub fn with_slo_targets(self, targets: Vec<SloTarget>) -> Self {
+        match self.stats.try_lock() {
+            Some(mut stats) => stats.slo = SloTracker::new(targets),
+            None => log::error!("latency targets cannot be set while statistics are in use"),
+        }
+        self
+    }
+
+    /// Compress responses larger than this number of bytes, for clients that accept it.
+    pub fn with_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Returns the state shared with the task handling a request.
+    fn handler_context(&self) -> HandlerContext<H> {
+        HandlerContext {
+            handler: self.handler.clone(),
+            protocol: self.protocol.clone(),
+            timeout: self.timeout,
+            retries: self.retries,
+            filter: self.dup_filter.clone(),
+            semantics: self.semantics,
+            in_flight: self.in_flight.clone(),
+            stats: self.stats.clone(),
+            hook: self.hook.clone(),
+            version: self.version.clone(),
+            token_secret: self.token_secret,
+            compression_threshold: self.compression_threshold,
+        }
+    }
+
+    /// Returns a socket to send a response from.
+    async fn response_socket(&self) -> io::Result<Arc<UdpSocket>> {
+        match &self.data_ports {
+            Some(pool) => pool.lock().await.new_bind_sock().await,
+            None => {
+                let mut resp_addr = self.socket.local_addr()?;
+                resp_addr.set_port(0);
+
+                Ok(Arc::new(UdpSocket::bind(resp_addr).await?))
+            }
+        }
+    }
+
+    /// Return a response socket to the data port pool, once late acks can no longer arrive.
+    async fn release_response_socket(
+        data_ports: Option<Arc<Mutex<SocketPool>>>,
+        socket: Arc<UdpSocket>,
+        timeout: Duration,
+    ) {
+        if let Some(pool) = data_ports {
+            if let Err(e) = pool.lock().await.free_sock_after(socket, timeout * 2).await {
+                log::error!("failed to free response socket: {}", e);
+            }
+        }
+    }
+}
+
+impl<H> HandlerContext<H>
+where
+    H: Debug + PayloadHandler + std::marker::Send + std::marker::Sync + 'static,
+{
     /// Routes and executes the handler
-    async fn execute_handler(
+    async fn execute(
+        &self,
         address: SocketAddrV4,
-        data: &[u8],
-        socket: UdpSocket,
-        handler: Arc<Mutex<H>>,
-        filter: Arc<Mutex<DuplicateFilter>>,
-        enable_filter: bool,
-        protocol: Arc<dyn TransmissionProtocol + Send + Sync>,
-        timeout: Duration,
-        retries: u8,
+        request: ReceivedPayload,
+        socket: Arc<UdpSocket>,
     ) {
-        log::debug!("received {} bytes from {}", data.len(), address);
+        let HandlerContext {
+            handler,
+            protocol,
+            timeout,
+            retries,
+            filter,
+            semantics,
+            in_flight,
+            stats,
+            hook,
+            version,
+            token_secret,
+            compression_threshold,
+        } = self;
+        let (timeout, retries) = (*timeout, *retries);
+
+        log::debug!("received {} bytes from {}", request.len(), address);
 
         // connection packets have zero length
-        if data.len() == 0 {
+        if request.is_empty() {
             return;
         }
 
         log::debug!("packet has stuff");
         // log::debug!("packet contents: {:?}", data);
 
+        let (middle_data, data, spilled) = match split_request(request) {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("deserialization failed: {:?}", e);
+
+                return;
+            }
+        };
+
+        // clients that do not identify themselves are told apart by address
+        let client = match &middle_data {
+            MiddlewareData::Request { client, .. } => *client,
+            _ => ClientId::from(address),
+        };
+        stats.lock().await.record(client, address);
+
+        let hash = match (&spilled, &middle_data) {
+            (Some(spilled), _) => spilled.hash,
+            (_, MiddlewareData::Payload(payload) | MiddlewareData::Request { payload, .. }) => {
+                hash_primary(payload)
+            }
+            _ => hash_primary(&data),
+        };
+        let notify = |event: &dyn Fn() -> LifecycleEvent| {
+            if let Some(h) = &hook {
+                h.on_event(&event());
+            }
+        };
+        notify(&|| LifecycleEvent::Received { client, hash });
+
+        let method = match &middle_data {
+            MiddlewareData::Payload(payload) | MiddlewareData::Request { payload, .. } => {
+                crate::decode_signature(payload).unwrap_or("unknown method")
+            }
+            _ => "middleware message",
+        };
+        log::debug!("{} from {} at {}", method, client, address);
+
+        // rejected before the duplicate filter, so expired tokens are not answered from it
+        if let Err(e) = authorize::<H>(token_secret.as_ref(), &middle_data) {
+            log::warn!("rejected {} from {}: {}", method, client, e);
+            let response = MiddlewareData::Error(InvokeError::Unauthorized(e));
+            let serialized_response = crate::serialize(&response).unwrap();
+            let sent_bytes = protocol
+                .send_bytes(&socket, address, &serialized_response, timeout, retries)
+                .await;
+            log::debug!("sent {:?} bytes to {}", sent_bytes, address);
+
+            return;
+        }
+
+        // methods can override the semantics of the dispatcher
+        let enable_filter = match &middle_data {
+            MiddlewareData::Payload(payload) | MiddlewareData::Request { payload, .. } => {
+                H::semantics(payload).unwrap_or(*semantics)
+            }
+            _ => *semantics,
+        }
+        .filters_duplicates();
+
         // check for duplicates
         let filter_read_lock = filter.lock().await;
-        match filter_read_lock.find(address, data) {
+        match filter_read_lock
+            .find(client, &data)
+            .filter(|_| enable_filter)
+        {
             Some(cached_resp) => {
-                log::info!("received duplicate request from {}", address,);
+                log::info!(
+                    "received duplicate {} from {} at {}",
+                    method,
+                    client,
+                    address
+                );
+                stats.lock().await.duplicate_requests += 1;
+                notify(&|| LifecycleEvent::Deduplicated { client, hash });
 
                 // send the result
                 let sent_bytes = protocol
                     .send_bytes(&socket, address, &cached_resp, timeout, retries)
                     .await;
+                notify(&|| LifecycleEvent::Replied {
+                    client,
+                    hash,
+                    response: cached_resp.to_vec(),
+                });
 
                 return;
             }
@@ -193,23 +600,53 @@ where
         // send an ack back
         // T::send_ack(&self.socket, addr, copy).await;
 
-        let middle_data: MiddlewareData = match crate::deserialize(&data) {
-            Ok(d) => d,
-            Err(e) => {
-                log::error!("deserialization failed: {:?}", e);
+        // only identified clients can supersede their requests
+        let invocation = match &middle_data {
+            MiddlewareData::Request { seq, .. } => Some((hash, *seq)),
+            _ => None,
+        };
+        let token = match invocation {
+            Some((hash, seq)) => in_flight.lock().await.start(client, hash, seq),
+            None => CancellationToken::new(),
+        };
 
-                return;
+        let accepts_compressed = matches!(
+            middle_data,
+            MiddlewareData::Request {
+                accepts_compressed: true,
+                ..
             }
-        };
+        );
 
         let mut handler_lock = handler.lock().await;
 
         let middlware_response = match middle_data {
             MiddlewareData::Ping => handle_ping().await,
-            MiddlewareData::Payload(payload) => match handler_lock.handle_payload(&payload).await {
-                Ok(res) => MiddlewareData::Payload(res),
-                Err(e) => MiddlewareData::Error(e),
-            },
+            MiddlewareData::Hello(remote) => handle_hello(address, &remote, version.clone()),
+            MiddlewareData::Payload(payload) | MiddlewareData::Request { payload, .. } => {
+                notify(&|| LifecycleEvent::Executed { client, hash });
+                let started = stats.lock().await.clock.now();
+                let handled = with_cancellation(token.clone(), async {
+                    match spilled {
+                        Some(spilled) => handler_lock.handle_spilled(spilled.payload).await,
+                        None => handler_lock.handle_payload(&payload).await,
+                    }
+                });
+                let response = match with_client(client, handled).await {
+                    Ok(res) => MiddlewareData::Payload(res),
+                    Err(e) => MiddlewareData::Error(e),
+                };
+
+                let mut stats_lock = stats.lock().await;
+                if !stats_lock.slo.is_empty() {
+                    if let Some(signature) = method_signature::<H>(&payload) {
+                        stats_lock.record_latency(signature, started);
+                    }
+                }
+                drop(stats_lock);
+
+                response
+            }
 
             // branch currently not used
             MiddlewareData::Callback(call) => handle_callback(&call).await,
@@ -232,6 +669,28 @@ where
 
         drop(handler_lock);
 
+        if let Some((hash, _)) = invocation {
+            in_flight.lock().await.finish(client, hash, &token);
+        }
+
+        let middlware_response = match middlware_response {
+            MiddlewareData::Payload(res)
+                if accepts_compressed && res.len() > *compression_threshold =>
+            {
+                match compression::compress(&res) {
+                    Some(compressed) => {
+                        stats
+                            .lock()
+                            .await
+                            .record_compression(res.len(), compressed.len());
+                        MiddlewareData::CompressedPayload(compressed)
+                    }
+                    None => MiddlewareData::Payload(res),
+                }
+            }
+            other => other,
+        };
+
         let serialized_response = crate::serialize(&middlware_response).unwrap();
 
         log::debug!("dispatch sending response to {}", address);
@@ -242,28 +701,269 @@ where
             .await;
 
         log::debug!("sent {:?} bytes to {}", sent_bytes, address);
+        notify(&|| LifecycleEvent::Replied {
+            client,
+            hash,
+            response: serialized_response.clone(),
+        });
 
         // add to cache
-        let mut filter_lock = filter.lock().await;
-        filter_lock.insert(address, data, serialized_response.clone());
+        if enable_filter {
+            let mut filter_lock = filter.lock().await;
+            filter_lock.insert(client, &data, serialized_response.clone());
+        }
+    }
+}
+
+/// Number of bytes of a spilled request that are read into memory to route it.
+///
+/// Covers the fields of the request before its payload, and the signature at the start of the payload.
+const SPILLED_HEAD_LEN: usize = 4096;
+
+/// The payload of a request that was spilled to disk while being received
+#[derive(Debug)]
+struct SpilledInvocation {
+    payload: ReceivedPayload,
+
+    /// Hash of the payload, the same as [hash_primary] of its bytes
+    hash: u64,
+}
+
+/// Deserialize a request.
+///
+/// The payload of a spilled request is cut out of the request and left on disk, see [SpilledInvocation].
+/// The request then holds the first bytes of its payload, which are enough to route it.
+///
+/// Returns the request, the bytes identifying it in the duplicate filter, and the spilled payload.
+fn split_request(
+    request: ReceivedPayload,
+) -> io::Result<(MiddlewareData, Vec<u8>, Option<SpilledInvocation>)> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e));
+
+    let cut = match request.is_spilled() {
+        true => ser_de::cut_bytes_field(&request.read_range(0, SPILLED_HEAD_LEN)?, "payload"),
+        false => None,
+    };
+
+    let cut = match cut {
+        Some(c) => c,
+        None => {
+            if request.is_spilled() {
+                log::warn!("loading spilled request of {} bytes", request.len());
+            }
+
+            let bytes = request.into_bytes()?;
+            let data = crate::deserialize(&bytes).map_err(invalid)?;
+            return Ok((data, bytes, None));
+        }
+    };
+
+    let tail = request.read_range(cut.offset + cut.len, SPILLED_HEAD_LEN)?;
+    let mut data: MiddlewareData =
+        crate::deserialize(&[cut.head, tail].concat()).map_err(invalid)?;
+
+    let payload = request.section(cut.offset, cut.len);
+    let hash = hash_payload(&payload)?;
+    log::debug!("payload of {} bytes left on disk", payload.len());
+
+    // spilled requests are told apart by the hash of their payload
+    set_payload(&mut data, hash.to_be_bytes().to_vec());
+    let key = crate::serialize(&data).map_err(invalid)?;
+    set_payload(&mut data, payload.read_range(0, SPILLED_HEAD_LEN)?);
+
+    Ok((data, key, Some(SpilledInvocation { payload, hash })))
+}
+
+/// Replace the payload of an invocation
+fn set_payload(data: &mut MiddlewareData, bytes: Vec<u8>) {
+    if let MiddlewareData::Payload(payload) | MiddlewareData::Request { payload, .. } = data {
+        *payload = bytes;
+    }
+}
+
+/// Hash a payload without reading all of it into memory
+fn hash_payload(payload: &ReceivedPayload) -> io::Result<u64> {
+    // hashed like a slice of bytes: the length, then the bytes
+    let mut hasher = DefaultHasher::new();
+    hasher.write_usize(payload.len());
+
+    let mut reader = payload.reader()?;
+    let mut buf = [0; BYTE_BUF_SIZE];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => hasher.write(&buf[..n]),
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Signature of the method a payload routes to, as `Interface::method`
+fn method_signature<H: PayloadHandler>(payload: &[u8]) -> Option<&'static str> {
+    H::signatures()
+        .into_iter()
+        .find(|signature| crate::matches_signature(payload, signature))
+        .and_then(|signature| std::str::from_utf8(signature).ok())
+}
+
+impl Default for DispatchStats {
+    fn default() -> Self {
+        Self::with_clock(real_clock())
+    }
+}
+
+impl DispatchStats {
+    /// Window over which the request rate is computed
+    pub const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+    fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            started: clock.now(),
+            total_requests: 0,
+            duplicate_requests: 0,
+            compressed_responses: 0,
+            compressed_bytes: (0, 0),
+            slo_violations: 0,
+            slo: Default::default(),
+            sources: Default::default(),
+            recent: Default::default(),
+            clock,
+        }
+    }
+
+    /// Size of compressed responses after compression, relative to their original size
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match self.compressed_bytes {
+            (0, _) => None,
+            (original, compressed) => Some(compressed as f64 / original as f64),
+        }
+    }
+
+    /// Record a response compressed from `original` to `compressed` bytes
+    fn record_compression(&mut self, original: usize, compressed: usize) {
+        self.compressed_responses += 1;
+        self.compressed_bytes.0 += original as u64;
+        self.compressed_bytes.1 += compressed as u64;
+    }
+
+    /// Record the time taken to handle an invocation of a method, started at `started`
+    fn record_latency(&mut self, signature: &str, started: Instant) {
+        let latency = self.clock.elapsed(started);
+        self.slo_violations += self.slo.record(signature, latency);
+    }
+
+    /// Time since the dispatcher was created
+    pub fn uptime(&self) -> Duration {
+        self.clock.elapsed(self.started)
+    }
+
+    /// Time since the last request of a source
+    pub fn idle_time(&self, source: &SourceStats) -> Duration {
+        self.clock.elapsed(source.last_seen)
+    }
+
+    /// Record a request from a client, sent from an address
+    fn record(&mut self, source: ClientId, addr: SocketAddrV4) {
+        let now = self.clock.now();
+
+        self.total_requests += 1;
+        self.recent.push_back(now);
+        self.prune(now);
+
+        self.sources
+            .entry(source)
+            .and_modify(|s| {
+                s.requests += 1;
+                s.addr = addr;
+                s.last_seen = now;
+            })
+            .or_insert(SourceStats {
+                requests: 1,
+                addr,
+                last_seen: now,
+            });
+    }
+
+    /// Remove request times outside the rate window
+    fn prune(&mut self, now: Instant) {
+        while let Some(front) = self.recent.front() {
+            match now.duration_since(*front) > Self::RATE_WINDOW {
+                true => self.recent.pop_front(),
+                false => break,
+            };
+        }
+    }
+
+    /// Average number of requests per second over the rate window
+    pub fn request_rate(&mut self) -> f64 {
+        self.prune(self.clock.now());
+
+        let window = Self::RATE_WINDOW.min(self.uptime()).as_secs_f64();
+        match window > 0.0 {
+            true => self.recent.len() as f64 / window,
+            false => 0.0,
+        }
+    }
+
+    /// Returns sources that have made a request within the idle duration.
+    pub fn active_sources(
+        &self,
+        idle: Duration,
+    ) -> impl Iterator<Item = (&ClientId, &SourceStats)> {
+        self.sources
+            .iter()
+            .filter(move |(_, s)| self.idle_time(s) <= idle)
+    }
+}
+
+impl InFlight {
+    /// Register a request and return its cancellation token.
+    ///
+    /// An earlier request with the same invocation is cancelled. Requests with the
+    /// same sequence number are duplicates of each other, and share a token.
+    fn start(&mut self, client: ClientId, invocation: u64, seq: u64) -> CancellationToken {
+        let key = (client, invocation);
+
+        match self.requests.get(&key) {
+            Some((prev_seq, prev)) if *prev_seq == seq => return prev.clone(),
+            Some((_, prev)) => {
+                log::info!("request from {} superseded by #{}", client, seq);
+                prev.cancel();
+            }
+            None => (),
+        }
+
+        let token = CancellationToken::new();
+        self.requests.insert(key, (seq, token.clone()));
+
+        token
+    }
+
+    /// Remove a handled request. Superseded requests were already replaced.
+    fn finish(&mut self, client: ClientId, invocation: u64, token: &CancellationToken) {
+        if !token.is_cancelled() {
+            self.requests.remove(&(client, invocation));
+        }
     }
 }
 
 impl DuplicateFilter {
-    fn new(timeout: Duration, retries: u8) -> Self {
+    fn new(timeout: Duration, retries: u8, clock: Arc<dyn Clock>) -> Self {
         Self {
             data: Default::default(),
             // very generous lifetime
             lifetime: timeout * (retries as u32) * 4,
+            clock,
         }
     }
 
     /// Given a request, find the response if it exists
     /// and is within the configured lifetime.
-    fn find(&self, source: SocketAddrV4, request: &[u8]) -> Option<&[u8]> {
+    fn find(&self, source: ClientId, request: &[u8]) -> Option<&[u8]> {
         match self.data.get(&(source, request.to_owned())) {
             Some((time, resp)) => {
-                if time.elapsed() > self.lifetime {
+                if self.clock.elapsed(*time) > self.lifetime {
                     None
                 } else {
                     Some(&resp)
@@ -274,17 +974,17 @@ impl DuplicateFilter {
     }
 
     /// Insert a new request and response into the filter
-    fn insert(&mut self, source: SocketAddrV4, request: &[u8], response: Vec<u8>) {
+    fn insert(&mut self, source: ClientId, request: &[u8], response: Vec<u8>) {
         self.prune();
 
         self.data
-            .insert((source, request.to_vec()), (Instant::now(), response));
+            .insert((source, request.to_vec()), (self.clock.now(), response));
     }
 
     /// Clean up the data
     fn prune(&mut self) {
         self.data
-            .retain(|_, (time, _)| time.elapsed() < self.lifetime);
+            .retain(|_, (time, _)| self.clock.elapsed(*time) < self.lifetime);
     }
 }
 
@@ -294,6 +994,22 @@ async fn handle_ping() -> MiddlewareData {
     MiddlewareData::Ping
 }
 
+/// Handle a ping carrying the version of a client, answering with the version of the dispatcher
+fn handle_hello(address: SocketAddrV4, remote: &VersionInfo, local: VersionInfo) -> MiddlewareData {
+    match local.compare(remote) {
+        VersionMatch::Compatible => log::info!("client {} connected with {}", address, remote),
+        mismatch => log::warn!(
+            "client {} connected with {}, dispatcher is {} ({:?})",
+            address,
+            remote,
+            local,
+            mismatch
+        ),
+    }
+
+    MiddlewareData::Hello(local)
+}
+
 // /// Handle remote invocations
 // async fn handle_payload<H: PayloadHandler>(handler: &mut H, payload: &[u8]) -> MiddlewareData {
 //     match handler.handle_payload(payload).await {
@@ -302,6 +1018,31 @@ async fn handle_ping() -> MiddlewareData {
 //     }
 // }
 
+/// Check the access token of an invocation, if the dispatcher requires tokens.
+///
+/// Pings and other messages that do not invoke a method are always allowed.
+fn authorize<H: PayloadHandler>(
+    secret: Option<&TokenSecret>,
+    data: &MiddlewareData,
+) -> Result<(), AuthError> {
+    let secret = match secret {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    match data {
+        MiddlewareData::Request {
+            payload,
+            token: Some(token),
+            ..
+        } => secret.verify(token, H::access(payload), SystemTime::now()),
+        MiddlewareData::Request { token: None, .. } | MiddlewareData::Payload(_) => {
+            Err(AuthError::Missing)
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Handle callbacks (not used atm)
 async fn handle_callback(call: &[u8]) -> MiddlewareData {
     todo!()
@@ -326,6 +1067,7 @@ fn hash_to_boolean<H: Hash>(item: H) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::middleware::PausedClock;
 
     #[test]
     fn test_hash_to_bool() {
@@ -336,11 +1078,35 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_block_duplicates() {
-        let mut filter = DuplicateFilter::new(Duration::from_millis(50), 2);
+    #[tokio::test]
+    async fn test_dispatch_stats() {
+        let clock = PausedClock::start();
+        let mut stats = DispatchStats::with_clock(Arc::new(clock.clone()));
+        let (first, second) = (ClientId::random(), ClientId::random());
+        let addr = |port| SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, port);
+
+        // the same client behind a NAT, from different ports
+        stats.record(first, addr(1));
+        stats.record(first, addr(2));
+        stats.record(second, addr(3));
+
+        assert_eq!(stats.total_requests, 3);
+        assert_eq!(stats.sources[&first].requests, 2);
+        assert_eq!(stats.sources[&first].addr, addr(2));
+        assert_eq!(stats.active_sources(Duration::from_secs(60)).count(), 2);
+
+        clock.advance(Duration::from_millis(20)).await;
+        assert_eq!(stats.active_sources(Duration::from_millis(10)).count(), 0);
+        assert!(stats.request_rate() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_block_duplicates() {
+        let clock = PausedClock::start();
+        let mut filter =
+            DuplicateFilter::new(Duration::from_millis(50), 2, Arc::new(clock.clone()));
 
-        let dummy_addr = SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 0);
+        let dummy_addr = ClientId::from(SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 0));
         let dummy_resp = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
         let data = vec![1, 2, 3, 4, 5];
 
@@ -352,13 +1118,647 @@ mod tests {
         let res = filter.find(dummy_addr, &data);
         assert_eq!(res, Some(dummy_resp.as_slice()));
 
-        std::thread::sleep(Duration::from_millis(300));
+        clock.advance(Duration::from_millis(300)).await;
 
         let res = filter.find(dummy_addr, &data);
         assert_eq!(res, Some(dummy_resp.as_slice()));
 
-        std::thread::sleep(Duration::from_millis(200));
+        clock.advance(Duration::from_millis(200)).await;
         let res = filter.find(dummy_addr, &data);
         assert_eq!(res, None);
     }
+
+    #[tokio::test]
+    async fn test_reap_idle_clients() {
+        let clock = PausedClock::start();
+        let stats = Arc::new(Mutex::new(DispatchStats::with_clock(Arc::new(
+            clock.clone(),
+        ))));
+        let dup_filter = Arc::new(Mutex::new(DuplicateFilter::new(
+            Duration::from_secs(60),
+            2,
+            Arc::new(clock.clone()),
+        )));
+        let reaper = ClientReaper {
+            stats: stats.clone(),
+            dup_filter: dup_filter.clone(),
+        };
+        let (idle, active) = (ClientId::random(), ClientId::random());
+        let addr = SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 0);
+
+        stats.lock().await.record(idle, addr);
+        dup_filter.lock().await.insert(idle, &[1], vec![1]);
+        dup_filter.lock().await.insert(idle, &[2], vec![2]);
+        clock.advance(Duration::from_secs(60)).await;
+        stats.lock().await.record(active, addr);
+        dup_filter.lock().await.insert(active, &[1], vec![1]);
+
+        assert_eq!(reaper.reap(Duration::from_secs(30)).await, (vec![idle], 2));
+        assert!(!stats.lock().await.sources.contains_key(&idle));
+        assert!(dup_filter.lock().await.find(active, &[1]).is_some());
+
+        assert_eq!(reaper.reap(Duration::from_secs(30)).await, (vec![], 0));
+    }
+
+    /// Counts the number of times it handles a payload
+    #[derive(Debug, Default)]
+    struct Counter(u64);
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for Counter {
+        async fn handle_payload(
+            &mut self,
+            _payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            self.0 += 1;
+            Ok(self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    /// Duplicated requests are executed again unless the duplicate filter is used.
+    #[tokio::test]
+    async fn test_injected_faults() {
+        use crate::middleware::{
+            sockaddr_to_v4, ContextManager, FailureRate, InvocationFaults, InvokeError, Invoker,
+            RequestAckProto,
+        };
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+
+        for (semantics, executions) in [(Semantics::AtLeastOnce, 3), (Semantics::AtMostOnce, 1)] {
+            let mut dispatcher = Dispatcher::new(
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+                Counter::default(),
+                Arc::new(RequestAckProto),
+                true,
+                timeout,
+                3,
+            )
+            .await
+            .with_semantics(semantics);
+            let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+            let handler = dispatcher.handler.clone();
+            let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+            let mut ctx = ContextManager::new(
+                Ipv4Addr::LOCALHOST,
+                addr,
+                timeout,
+                3,
+                Arc::new(RequestAckProto),
+            )
+            .await
+            .unwrap()
+            .with_faults(InvocationFaults {
+                drop_response: Some(FailureRate(1)),
+                duplicates: 2,
+            });
+
+            // the remote executes the request, but its response is dropped
+            assert!(matches!(
+                ctx.invoke_raw(vec![1, 2, 3]).await,
+                Err(InvokeError::RequestTimedOut)
+            ));
+            assert_eq!(handler.lock().await.0, executions);
+
+            dispatch.abort();
+        }
+    }
+
+    /// Executions of a request under each invocation semantics, when its response is lost
+    /// and the request is duplicated.
+    #[tokio::test]
+    async fn test_invocation_semantics() {
+        use crate::middleware::{
+            request_hash, sockaddr_to_v4, ContextManager, DefaultProto, FailureRate,
+            HandshakeProto, InvocationFaults, InvokeError, Invoker, LifecycleLog, RequestAckProto,
+        };
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+        let payload = vec![1, 2, 3];
+        let hash = request_hash(&payload);
+
+        // protocol, semantics, duplicates, (received, deduplicated, executed)
+        let cases: [(Arc<dyn TransmissionProtocol + Send + Sync>, _, u8, _); 3] = [
+            // maybe: sent once, executed once
+            (Arc::new(DefaultProto), Semantics::Maybe, 0, (1, 0, 1)),
+            // at-least-once: every duplicate is executed
+            (
+                Arc::new(RequestAckProto),
+                Semantics::AtLeastOnce,
+                2,
+                (3, 0, 3),
+            ),
+            // at-most-once: duplicates are answered from the filter
+            (
+                Arc::new(HandshakeProto::default()),
+                Semantics::AtMostOnce,
+                2,
+                (3, 2, 1),
+            ),
+        ];
+
+        for (proto, semantics, duplicates, (received, deduplicated, executed)) in cases {
+            let log = Arc::new(LifecycleLog::default());
+            let mut dispatcher = Dispatcher::new(
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+                Counter::default(),
+                proto.clone(),
+                true,
+                timeout,
+                3,
+            )
+            .await
+            .with_semantics(semantics)
+            .with_lifecycle_hook(log.clone());
+            let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+            let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+            let mut ctx = ContextManager::new(Ipv4Addr::LOCALHOST, addr, timeout, 3, proto)
+                .await
+                .unwrap()
+                .with_faults(InvocationFaults {
+                    drop_response: Some(FailureRate(1)),
+                    duplicates,
+                });
+
+            assert!(matches!(
+                ctx.invoke_raw(payload.clone()).await,
+                Err(InvokeError::RequestTimedOut)
+            ));
+            assert_eq!(log.received(hash), received);
+            assert_eq!(log.deduplicated(hash), deduplicated);
+            assert_eq!(log.executions(hash), executed);
+
+            dispatch.abort();
+        }
+    }
+
+    /// Counts executions, with reads (payloads starting with `r`) that can be executed again
+    #[derive(Debug, Default)]
+    struct ReadWriteCounter(u64);
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for ReadWriteCounter {
+        async fn handle_payload(
+            &mut self,
+            _payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            self.0 += 1;
+            Ok(vec![])
+        }
+
+        fn semantics(payload_bytes: &[u8]) -> Option<Semantics> {
+            match payload_bytes.first() {
+                Some(b'r') => Some(Semantics::AtLeastOnce),
+                _ => Some(Semantics::AtMostOnce),
+            }
+        }
+
+        fn access(payload_bytes: &[u8]) -> crate::middleware::MethodAccess {
+            match payload_bytes.first() {
+                Some(b'r') => crate::middleware::MethodAccess::Read,
+                _ => crate::middleware::MethodAccess::Write,
+            }
+        }
+    }
+
+    /// Dispatchers with a token secret reject invocations outside the scope of the token.
+    #[tokio::test]
+    async fn test_token_scopes() {
+        use crate::middleware::{
+            sockaddr_to_v4, ContextManager, Invoker, RequestAckProto, TokenScope,
+        };
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+        let secret = TokenSecret::random();
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            ReadWriteCounter::default(),
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await
+        .with_token_secret(secret);
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+        let unauthorized = |e| Err(InvokeError::Unauthorized(e));
+
+        let mut anonymous = ctx.clone();
+        assert_eq!(
+            anonymous.invoke_raw(b"read".to_vec()).await,
+            unauthorized(AuthError::Missing)
+        );
+
+        let expires = SystemTime::now() + Duration::from_secs(60);
+        let mut guest = ctx
+            .clone()
+            .with_token(secret.issue(TokenScope::ReadOnly, expires));
+        assert_eq!(guest.invoke_raw(b"read".to_vec()).await, Ok(vec![]));
+        assert_eq!(
+            guest.invoke_raw(b"write".to_vec()).await,
+            unauthorized(AuthError::Forbidden)
+        );
+
+        let expired = SystemTime::now() - Duration::from_secs(1);
+        let mut late = ctx.with_token(secret.issue(TokenScope::ReadWrite, expired));
+        assert_eq!(
+            late.invoke_raw(b"write".to_vec()).await,
+            unauthorized(AuthError::Expired)
+        );
+
+        dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_over_tcp() {
+        use crate::middleware::{sockaddr_to_v4, ContextManager, Invoker, TcpProto};
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            Repeater,
+            Arc::new(TcpProto::default()),
+            false,
+            timeout,
+            3,
+        )
+        .await;
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let mut ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(TcpProto::default()),
+        )
+        .await
+        .unwrap();
+
+        // responses far larger than a datagram
+        let payload = (0..20_000).map(|i| i as u8).collect::<Vec<_>>();
+        assert_eq!(
+            ctx.invoke_raw(payload.clone()).await,
+            Ok(payload.repeat(100))
+        );
+
+        dispatch.abort();
+    }
+
+    /// Repeats the payload a hundred times
+    #[derive(Debug, Default)]
+    struct Repeater;
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for Repeater {
+        async fn handle_payload(
+            &mut self,
+            payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            Ok(payload.repeat(100))
+        }
+    }
+
+    /// Requests over the memory cap reach the handler without being read into memory.
+    #[tokio::test]
+    async fn test_spilled_request() {
+        use crate::middleware::{sockaddr_to_v4, ContextManager, HandshakeProto, Invoker};
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            Streamer::default(),
+            Arc::new(HandshakeProto::default()),
+            false,
+            timeout,
+            3,
+        )
+        .await
+        .with_memory_cap(1024);
+        let handler = dispatcher.handler();
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let mut ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(HandshakeProto::default()),
+        )
+        .await
+        .unwrap();
+
+        let small = vec![1; 100];
+        let large = (0..50_000).map(|i| i as u8).collect::<Vec<_>>();
+        for payload in [&small, &large] {
+            assert_eq!(
+                ctx.invoke_raw(payload.clone()).await,
+                Ok(hash_primary(payload).to_be_bytes().to_vec())
+            );
+        }
+
+        let handler = handler.lock().await;
+        assert_eq!(handler.buffered, vec![small.len()]);
+        assert_eq!(handler.streamed, vec![large.len()]);
+
+        dispatch.abort();
+    }
+
+    /// Responds with the hash of the payload, streaming spilled payloads
+    #[derive(Debug, Default)]
+    struct Streamer {
+        /// Sizes of the payloads handled in memory
+        buffered: Vec<usize>,
+        /// Sizes of the payloads streamed from disk
+        streamed: Vec<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for Streamer {
+        async fn handle_payload(
+            &mut self,
+            payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            self.buffered.push(payload.len());
+            Ok(hash_primary(&payload).to_be_bytes().to_vec())
+        }
+
+        async fn handle_spilled(
+            &mut self,
+            payload: ReceivedPayload,
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            assert!(payload.is_spilled());
+            self.streamed.push(payload.len());
+            Ok(hash_payload(&payload).unwrap().to_be_bytes().to_vec())
+        }
+    }
+
+    /// Large responses are compressed only for clients that accept it.
+    #[tokio::test]
+    async fn test_response_compression() {
+        use crate::middleware::{sockaddr_to_v4, ContextManager, Invoker, RequestAckProto};
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            Repeater,
+            Arc::new(RequestAckProto),
+            false,
+            timeout,
+            3,
+        )
+        .await
+        .with_compression_threshold(100);
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let stats = dispatcher.stats();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+
+        let mut plain = ctx.clone();
+        let mut compressed = ctx.with_compressed_responses();
+
+        // below the threshold
+        assert_eq!(
+            compressed.invoke_raw(b"a".to_vec()).await,
+            Ok(vec![b'a'; 100])
+        );
+        assert_eq!(stats.lock().await.compressed_responses, 0);
+
+        let payload = b"compress me ".to_vec();
+        assert_eq!(
+            plain.invoke_raw(payload.clone()).await,
+            Ok(payload.repeat(100))
+        );
+        assert_eq!(stats.lock().await.compressed_responses, 0);
+        assert_eq!(stats.lock().await.compression_ratio(), None);
+
+        assert_eq!(
+            compressed.invoke_raw(payload.clone()).await,
+            Ok(payload.repeat(100))
+        );
+        let stats = stats.lock().await;
+        assert_eq!(stats.compressed_responses, 1);
+        assert_eq!(stats.compressed_bytes.0, payload.len() as u64 * 100);
+        assert!(stats.compression_ratio().unwrap() < 0.5);
+
+        dispatch.abort();
+    }
+
+    /// Semantics of a method override those of the dispatcher.
+    #[tokio::test]
+    async fn test_method_semantics() {
+        use crate::middleware::{
+            request_hash, sockaddr_to_v4, ContextManager, FailureRate, InvocationFaults, Invoker,
+            LifecycleLog, RequestAckProto,
+        };
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+
+        // dispatcher semantics, payload, executed
+        let cases = [
+            (Semantics::AtMostOnce, b"read".to_vec(), 3),
+            (Semantics::AtLeastOnce, b"write".to_vec(), 1),
+        ];
+
+        for (semantics, payload, executed) in cases {
+            let log = Arc::new(LifecycleLog::default());
+            let mut dispatcher = Dispatcher::new(
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+                ReadWriteCounter::default(),
+                Arc::new(RequestAckProto),
+                true,
+                timeout,
+                3,
+            )
+            .await
+            .with_semantics(semantics)
+            .with_lifecycle_hook(log.clone());
+            let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+            let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+            let mut ctx = ContextManager::new(
+                Ipv4Addr::LOCALHOST,
+                addr,
+                timeout,
+                3,
+                Arc::new(RequestAckProto),
+            )
+            .await
+            .unwrap()
+            .with_faults(InvocationFaults {
+                drop_response: Some(FailureRate(1)),
+                duplicates: 2,
+            });
+
+            assert!(ctx.invoke_raw(payload.clone()).await.is_err());
+            assert_eq!(log.executions(request_hash(&payload)), executed);
+
+            dispatch.abort();
+        }
+    }
+
+    /// Records the client of each request
+    #[derive(Debug, Default)]
+    struct ClientRecorder(Vec<Option<ClientId>>);
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for ClientRecorder {
+        async fn handle_payload(
+            &mut self,
+            _payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            self.0.push(crate::middleware::current_client());
+            Ok(vec![])
+        }
+    }
+
+    /// Waits before responding, unless the request is cancelled
+    #[derive(Debug, Default)]
+    struct Sleeper;
+
+    #[async_trait::async_trait]
+    impl PayloadHandler for Sleeper {
+        async fn handle_payload(
+            &mut self,
+            _payload: &[u8],
+        ) -> Result<Vec<u8>, crate::middleware::InvokeError> {
+            let token = crate::middleware::request_cancellation().unwrap();
+
+            tokio::select! {
+                _ = token.cancelled() => Ok(b"cancelled".to_vec()),
+                _ = tokio::time::sleep(Duration::from_millis(300)) => Ok(b"done".to_vec()),
+            }
+        }
+    }
+
+    /// Sending the same invocation again cancels the earlier request.
+    #[tokio::test]
+    async fn test_superseded_request() {
+        use crate::middleware::{sockaddr_to_v4, ContextManager, Invoker, RequestAckProto};
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_secs(1);
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            Sleeper,
+            Arc::new(RequestAckProto),
+            false,
+            timeout,
+            3,
+        )
+        .await
+        .with_semantics(Semantics::AtMostOnce);
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let mut ctx = ContextManager::new(
+            Ipv4Addr::LOCALHOST,
+            addr,
+            timeout,
+            3,
+            Arc::new(RequestAckProto),
+        )
+        .await
+        .unwrap();
+
+        let mut first_ctx = ctx.clone();
+        let first = tokio::spawn(async move { first_ctx.invoke_raw(vec![1, 2, 3]).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = ctx.invoke_raw(vec![1, 2, 3]).await;
+
+        assert_eq!(first.await.unwrap().unwrap(), b"cancelled");
+        assert_eq!(second.unwrap(), b"done");
+
+        dispatch.abort();
+    }
+
+    /// Identical invocations are executed once each, and attributed to the client that made them.
+    #[tokio::test]
+    async fn test_client_identification() {
+        use crate::middleware::{sockaddr_to_v4, ContextManager, Invoker, RequestAckProto};
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+
+        let mut dispatcher = Dispatcher::new(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            ClientRecorder::default(),
+            Arc::new(RequestAckProto),
+            true,
+            timeout,
+            3,
+        )
+        .await
+        .with_semantics(Semantics::AtMostOnce);
+        let addr = sockaddr_to_v4(dispatcher.socket.local_addr().unwrap()).unwrap();
+        let handler = dispatcher.handler.clone();
+        let stats = dispatcher.stats();
+        let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+        let mut clients = vec![];
+        for _ in 0..2 {
+            let ctx = ContextManager::new(
+                Ipv4Addr::LOCALHOST,
+                addr,
+                timeout,
+                3,
+                Arc::new(RequestAckProto),
+            )
+            .await
+            .unwrap();
+            clients.push(ctx);
+        }
+
+        for _ in 0..2 {
+            for ctx in clients.iter_mut() {
+                ctx.invoke_raw(vec![1, 2, 3]).await.unwrap();
+            }
+        }
+
+        let ids = clients
+            .iter()
+            .map(|c| Some(c.client_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(handler.lock().await.0, [ids.clone(), ids].concat());
+        assert_eq!(
+            stats.lock().await.sources[&clients[0].client_id()].requests,
+            2
+        );
+
+        dispatch.abort();
+    }
 }
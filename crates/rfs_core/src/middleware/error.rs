@@ -0,0 +1,134 @@
+//! Errors of method invocations, and their conversions to and from IO errors.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::AuthError;
+
+/// Method invocation errors
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum InvokeError {
+    /// The remote is unable to find a handler for the given payload.
+    ///
+    /// This should be the most common error returned from an invocation.
+    HandlerNotFound,
+
+    /// The method signature of the response does not match
+    /// the payload.
+    SignatureNotMatched,
+
+    /// The context manager is unable to get a response from the remote
+    RequestTimedOut,
+
+    /// Deserialization of the payload failed
+    DeserializationFailed,
+
+    /// Connection to the remote was unsuccessful
+    RemoteConnectionFailed,
+
+    /// Unable to send data to the remote
+    DataTransmissionFailed,
+
+    /// Remote received an error
+    RemoteReceiveError,
+
+    /// Invalid data
+    InvalidData,
+
+    /// The request is a duplicate
+    DuplicateRequest,
+
+    /// The remote method returned an error.
+    ///
+    /// Contains the serialized error, prefixed with its signature.
+    /// Generated clients decode this into the error type of the method.
+    RemoteApplication(#[serde(with = "serde_bytes")] Vec<u8>),
+
+    /// The dispatcher rejected the access token of the request
+    Unauthorized(AuthError),
+}
+
+impl std::error::Error for InvokeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        self.source()
+    }
+}
+
+// temp, display is debug
+impl std::fmt::Display for InvokeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<io::Error> for InvokeError {
+    fn from(value: io::Error) -> Self {
+        log::error!("error kind: {:?}", value.kind());
+
+        match value.kind() {
+            io::ErrorKind::NotFound => InvokeError::HandlerNotFound,
+            io::ErrorKind::PermissionDenied => InvokeError::InvalidData,
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::AddrInUse
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::BrokenPipe => InvokeError::DataTransmissionFailed,
+            // io::ErrorKind::AlreadyExists => todo!(),
+            // io::ErrorKind::WouldBlock => todo!(),
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => InvokeError::InvalidData,
+            io::ErrorKind::TimedOut => InvokeError::RequestTimedOut,
+            // io::ErrorKind::WriteZero => todo!(),
+            // io::ErrorKind::Interrupted => todo!(),
+            // io::ErrorKind::Unsupported => todo!(),
+            // io::ErrorKind::UnexpectedEof => todo!(),
+            // io::ErrorKind::OutOfMemory => todo!(),
+            // io::ErrorKind::Other => todo!(),
+            _ => InvokeError::RequestTimedOut,
+        }
+    }
+}
+
+impl From<InvokeError> for io::Error {
+    fn from(value: InvokeError) -> Self {
+        match value {
+            InvokeError::HandlerNotFound => {
+                io::Error::new(io::ErrorKind::NotFound, "handler not found")
+            }
+            InvokeError::SignatureNotMatched => {
+                io::Error::new(io::ErrorKind::InvalidData, "signature not matched")
+            }
+            InvokeError::RequestTimedOut => {
+                io::Error::new(io::ErrorKind::TimedOut, "request timed out")
+            }
+            InvokeError::DeserializationFailed => {
+                io::Error::new(io::ErrorKind::InvalidData, "deserialization failed")
+            }
+            InvokeError::RemoteConnectionFailed => {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "remote connection failed")
+            }
+            InvokeError::DataTransmissionFailed => {
+                io::Error::new(io::ErrorKind::BrokenPipe, "data transmission failed")
+            }
+            InvokeError::RemoteReceiveError => {
+                io::Error::new(io::ErrorKind::BrokenPipe, "remote receive error")
+            }
+            InvokeError::InvalidData => io::Error::new(io::ErrorKind::InvalidData, "invalid data"),
+            InvokeError::DuplicateRequest => {
+                io::Error::new(io::ErrorKind::Interrupted, "duplicate request")
+            }
+            InvokeError::RemoteApplication(_) => io::Error::other("remote application error"),
+            InvokeError::Unauthorized(e) => io::Error::new(io::ErrorKind::PermissionDenied, e),
+        }
+    }
+}
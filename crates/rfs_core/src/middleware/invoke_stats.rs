@@ -0,0 +1,117 @@
+//! Latency and throughput of the invocations made by a client.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Number of recent invocations kept
+const SAMPLE_CAPACITY: usize = 128;
+
+/// A completed invocation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvokeSample {
+    /// When the invocation completed
+    pub finished: Instant,
+
+    /// Time taken by the invocation, including its retries
+    pub latency: Duration,
+
+    /// Size of the request and response payloads
+    pub bytes: usize,
+
+    pub success: bool,
+}
+
+/// Statistics of the invocations made by a [super::ContextManager] and its clones.
+#[derive(Clone, Debug, Default)]
+pub struct InvokeStats {
+    /// Number of invocations made
+    pub invocations: u64,
+
+    /// Number of invocations that failed
+    pub failures: u64,
+
+    /// Total size of request and response payloads
+    pub bytes: u64,
+
+    /// Most recent invocations, oldest first
+    samples: VecDeque<InvokeSample>,
+}
+
+impl InvokeStats {
+    /// Record a completed invocation, discarding the oldest sample if full.
+    pub(crate) fn record(&mut self, sample: InvokeSample) {
+        self.invocations += 1;
+        self.bytes += sample.bytes as u64;
+        if !sample.success {
+            self.failures += 1;
+        }
+
+        if self.samples.len() == SAMPLE_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Most recent invocations, oldest first
+    pub fn samples(&self) -> impl Iterator<Item = &InvokeSample> {
+        self.samples.iter()
+    }
+
+    /// Latencies of the most recent invocations in milliseconds, oldest first
+    pub fn latencies_ms(&self) -> Vec<u64> {
+        self.samples
+            .iter()
+            .map(|s| s.latency.as_millis() as u64)
+            .collect()
+    }
+
+    /// Bytes transferred in each of the last `seconds` seconds before `now`, oldest first.
+    pub fn bytes_per_second(&self, now: Instant, seconds: usize) -> Vec<u64> {
+        let mut buckets = vec![0; seconds];
+
+        for sample in &self.samples {
+            let age = now.saturating_duration_since(sample.finished).as_secs() as usize;
+            if age < seconds {
+                buckets[seconds - 1 - age] += sample.bytes as u64;
+            }
+        }
+
+        buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoke_stats() {
+        let start = Instant::now();
+        let mut stats = InvokeStats::default();
+
+        for num in 0..(SAMPLE_CAPACITY + 2) {
+            stats.record(InvokeSample {
+                finished: start + Duration::from_millis(num as u64 * 10),
+                latency: Duration::from_millis(num as u64),
+                bytes: 100,
+                success: num % 2 == 0,
+            });
+        }
+
+        assert_eq!(stats.invocations, SAMPLE_CAPACITY as u64 + 2);
+        assert_eq!(stats.failures, SAMPLE_CAPACITY as u64 / 2 + 1);
+        assert_eq!(stats.bytes, 100 * (SAMPLE_CAPACITY as u64 + 2));
+
+        // the oldest samples are discarded
+        let latencies = stats.latencies_ms();
+        assert_eq!(latencies.len(), SAMPLE_CAPACITY);
+        assert_eq!(latencies[0], 2);
+
+        // samples finish between 20ms and 1.29s after the start
+        let now = start + Duration::from_millis(2_500);
+        assert_eq!(stats.bytes_per_second(now, 3), [100 * 49, 100 * 79, 0]);
+        assert_eq!(stats.bytes_per_second(now, 1), [0]);
+    }
+}
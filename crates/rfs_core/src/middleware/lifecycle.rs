@@ -0,0 +1,120 @@
+//! Hooks into the handling of requests by a [super::Dispatcher].
+//!
+//! A [LifecycleHook] is told when a request is received, answered from the duplicate filter,
+//! executed and replied to. [LifecycleLog] keeps these events, so tests of invocation semantics
+//! can count executions instead of relying on side effects of the handler.
+
+use std::fmt::Debug;
+
+use super::{hash_primary, ClientId};
+
+/// A stage in the handling of a request.
+///
+/// Requests are identified by the hash of their invocation, see [request_hash].
+#[derive(Clone, Debug, PartialEq)]
+pub enum LifecycleEvent {
+    /// The request was received and deserialized
+    Received { client: ClientId, hash: u64 },
+
+    /// The request is a duplicate, and is answered with the cached response
+    Deduplicated { client: ClientId, hash: u64 },
+
+    /// The request was passed to the handler
+    Executed { client: ClientId, hash: u64 },
+
+    /// A response was sent
+    Replied {
+        client: ClientId,
+        hash: u64,
+        response: Vec<u8>,
+    },
+}
+
+/// Observes requests handled by a dispatcher.
+pub trait LifecycleHook: Debug + Send + Sync {
+    fn on_event(&self, event: &LifecycleEvent);
+}
+
+/// Identifies a request in [LifecycleEvent]s.
+///
+/// This is the hash of the invocation payload, which is the same for every duplicate
+/// and retry of a call.
+pub fn request_hash(payload: &[u8]) -> u64 {
+    hash_primary(&payload)
+}
+
+impl LifecycleEvent {
+    pub fn hash(&self) -> u64 {
+        match self {
+            LifecycleEvent::Received { hash, .. }
+            | LifecycleEvent::Deduplicated { hash, .. }
+            | LifecycleEvent::Executed { hash, .. }
+            | LifecycleEvent::Replied { hash, .. } => *hash,
+        }
+    }
+}
+
+/// A hook that keeps every event, in the order they happened.
+#[cfg(any(test, feature = "lifecycle-hooks"))]
+#[derive(Debug, Default)]
+pub struct LifecycleLog {
+    events: std::sync::Mutex<Vec<LifecycleEvent>>,
+}
+
+#[cfg(any(test, feature = "lifecycle-hooks"))]
+impl LifecycleLog {
+    /// All recorded events
+    pub fn events(&self) -> Vec<LifecycleEvent> {
+        self.events.lock().expect("lock poisoned").clone()
+    }
+
+    /// Number of events of a request that match a predicate
+    fn count(&self, hash: u64, pred: fn(&LifecycleEvent) -> bool) -> usize {
+        self.events
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter(|e| e.hash() == hash && pred(e))
+            .count()
+    }
+
+    /// Number of times a request was received
+    pub fn received(&self, hash: u64) -> usize {
+        self.count(hash, |e| matches!(e, LifecycleEvent::Received { .. }))
+    }
+
+    /// Number of times a request was answered from the duplicate filter
+    pub fn deduplicated(&self, hash: u64) -> usize {
+        self.count(hash, |e| matches!(e, LifecycleEvent::Deduplicated { .. }))
+    }
+
+    /// Number of times a request was executed
+    pub fn executions(&self, hash: u64) -> usize {
+        self.count(hash, |e| matches!(e, LifecycleEvent::Executed { .. }))
+    }
+
+    /// Responses sent for a request
+    pub fn replies(&self, hash: u64) -> Vec<Vec<u8>> {
+        self.events
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter_map(|e| match e {
+                LifecycleEvent::Replied {
+                    hash: h, response, ..
+                } if *h == hash => Some(response.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(any(test, feature = "lifecycle-hooks"))]
+impl LifecycleHook for LifecycleLog {
+    fn on_event(&self, event: &LifecycleEvent) {
+        self.events
+            .lock()
+            .expect("lock poisoned")
+            .push(event.clone());
+    }
+}
@@ -0,0 +1,33 @@
+//! In-process transport that hands invocations straight to a payload handler.
+
+use std::{fmt::Debug, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use super::{client_id::with_client, ClientId, InvokeError, PayloadHandler};
+
+/// Handler shared between a context manager and its clones, used in place of a remote.
+#[derive(Clone)]
+pub(super) struct Loopback {
+    handler: Arc<Mutex<dyn PayloadHandler + Send>>,
+}
+
+impl Loopback {
+    pub fn new<H: PayloadHandler + Send + 'static>(handler: H) -> Self {
+        Self {
+            handler: Arc::new(Mutex::new(handler)),
+        }
+    }
+
+    /// Run an invocation on the handler, as the client.
+    pub async fn handle(&self, client: ClientId, payload: &[u8]) -> Result<Vec<u8>, InvokeError> {
+        let mut handler = self.handler.lock().await;
+        with_client(client, handler.handle_payload(payload)).await
+    }
+}
+
+impl Debug for Loopback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Loopback").finish_non_exhaustive()
+    }
+}
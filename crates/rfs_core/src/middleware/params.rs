@@ -0,0 +1,190 @@
+//! Typed parameters of the middleware.
+//!
+//! Constructors accept anything that converts into these types, so bare values still work.
+//! Bare values are not checked against each other, e.g. two `u32` arguments can still be
+//! swapped. Pass the types themselves where the order matters.
+
+use std::{fmt::Display, ops::RangeInclusive, str::FromStr, time::Duration};
+
+/// Time to wait for a response before retrying
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestTimeout(pub Duration);
+
+/// Number of times a transmission is attempted before giving up
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Retries(pub u8);
+
+/// Rate of simulated failures, as 1 in N attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FailureRate(pub u32);
+
+/// Ports that sockets for data transfers are bound to
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PortRange(pub RangeInclusive<u16>);
+
+impl From<Duration> for RequestTimeout {
+    fn from(value: Duration) -> Self {
+        Self(value)
+    }
+}
+
+impl From<humantime::Duration> for RequestTimeout {
+    fn from(value: humantime::Duration) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<RequestTimeout> for Duration {
+    fn from(value: RequestTimeout) -> Self {
+        value.0
+    }
+}
+
+/// Parses durations such as `250ms` or `1s`
+impl FromStr for RequestTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        humantime::parse_duration(s)
+            .map(Self)
+            .map_err(|e| format!("invalid timeout {:?}: {}", s, e))
+    }
+}
+
+impl Display for RequestTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.0))
+    }
+}
+
+impl From<u8> for Retries {
+    fn from(value: u8) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Retries> for u8 {
+    fn from(value: Retries) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for Retries {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Self)
+            .map_err(|e| format!("invalid number of retries {:?}: {}", s, e))
+    }
+}
+
+impl Display for Retries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// N must not be zero
+impl TryFrom<u32> for FailureRate {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Err("failure rate must be 1 in at least 1 attempt".to_string()),
+            n => Ok(Self(n)),
+        }
+    }
+}
+
+impl From<FailureRate> for u32 {
+    fn from(value: FailureRate) -> Self {
+        value.0
+    }
+}
+
+/// Parses the N in 1 in N attempts. N must not be zero.
+impl FromStr for FailureRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(n) => Self::try_from(n),
+            Err(e) => Err(format!("invalid failure rate {:?}: {}", s, e)),
+        }
+    }
+}
+
+impl Display for FailureRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<RangeInclusive<u16>> for PortRange {
+    fn from(value: RangeInclusive<u16>) -> Self {
+        Self(value)
+    }
+}
+
+/// Parses ranges such as `5000-5100`, or a single port
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .map_err(|e| format!("invalid port {:?}: {}", p, e))
+        };
+
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(s)?, parse(s)?),
+        };
+
+        match start {
+            0 => Err("port range must not contain port 0".to_string()),
+            _ if start > end => Err(format!("port range {:?} is empty", s)),
+            _ => Ok(Self(start..=end)),
+        }
+    }
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.0.start(), self.0.end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_params() {
+        assert_eq!(
+            "250ms".parse::<RequestTimeout>(),
+            Ok(RequestTimeout(Duration::from_millis(250)))
+        );
+        assert!("250".parse::<RequestTimeout>().is_err());
+        assert_eq!(
+            RequestTimeout(Duration::from_millis(1500)).to_string(),
+            "1s 500ms"
+        );
+
+        assert_eq!("3".parse::<Retries>(), Ok(Retries(3)));
+        assert!("300".parse::<Retries>().is_err());
+
+        assert_eq!("50".parse::<FailureRate>(), Ok(FailureRate(50)));
+        assert!("0".parse::<FailureRate>().is_err());
+        assert_eq!(FailureRate::try_from(50), Ok(FailureRate(50)));
+        assert!(FailureRate::try_from(0).is_err());
+
+        assert_eq!("5000-5100".parse::<PortRange>(), Ok(PortRange(5000..=5100)));
+        assert_eq!("5000".parse::<PortRange>(), Ok(PortRange(5000..=5000)));
+        assert!("5100-5000".parse::<PortRange>().is_err());
+        assert!("0-10".parse::<PortRange>().is_err());
+        assert_eq!(PortRange(5000..=5100).to_string(), "5000-5100");
+    }
+}
@@ -0,0 +1,312 @@
+//! Transmission protocols, and the registry they are constructed from by name.
+
+mod adaptive_proto;
+mod default_proto;
+mod handshake_proto;
+mod registry;
+mod request_ack;
+mod tcp_proto;
+
+use std::fmt::{Debug, Display};
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+pub use adaptive_proto::AdaptiveProto;
+pub use default_proto::{DefaultProto, FaultyDefaultProto};
+pub use handshake_proto::{FaultyHandshakeProto, HandshakeProto, TransferLimits};
+pub use registry::{ProtocolOptions, ProtocolRegistry, SharedProtocol};
+pub use request_ack::{FaultyRequestAckProto, RequestAckProto};
+pub use tcp_proto::TcpProto;
+
+use super::ReceivedPayload;
+
+/// Recommended payload to be sent between implementors of [`TransmissionProtocol`].
+///
+/// There is no requirement to use this data structure, or all it's variants/fields.
+/// Each implementor is responsible for how data is transmitted.
+///
+/// Implementors can opt to send raw bytes as well.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransmissionPacket {
+    /// Data payload
+    Data {
+        /// sequence number
+        seq: u32,
+
+        /// Hash value of bytes
+        hash: u64,
+
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+
+        /// Indicates if this is the last packet
+        last: bool,
+    },
+
+    /// For receipients of this packet, switch transmissions to this new target
+    SwitchToAddress(SocketAddrV4),
+
+    /// A request for a sequence number
+    Seq(u64),
+
+    /// An ack packet, along with a number.
+    /// The meaning of the number sent within depends on the implementor of the protocol.
+    Ack(u64),
+
+    /// Signals the completion of the transfer
+    Complete,
+
+    /// Padded packet used to probe the largest datagram that reaches the receipient
+    Probe(#[serde(with = "serde_bytes")] Vec<u8>),
+
+    /// Acknowledges a [TransmissionPacket::Probe] with the size of its padding
+    ProbeAck(u32),
+
+    /// Sent periodically during idle periods to keep NAT mappings alive
+    KeepAlive,
+}
+
+/// The local end of a transmission, which payloads are sent from and received on.
+///
+/// Datagram protocols send over the UDP socket of an endpoint. Protocols with their own
+/// transport, like [`TcpProto`], only use its address.
+pub trait Endpoint: Debug + Send + Sync {
+    /// Address that remotes reply to
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Returns the UDP socket of the endpoint.
+    ///
+    /// Endpoints without one can only be used with protocols that do not send datagrams.
+    fn datagram(&self) -> io::Result<&UdpSocket> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "endpoint has no UDP socket",
+        ))
+    }
+}
+
+impl Endpoint for UdpSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn datagram(&self) -> io::Result<&UdpSocket> {
+        Ok(self)
+    }
+}
+
+impl<T: Endpoint + ?Sized> Endpoint for Arc<T> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.as_ref().local_addr()
+    }
+
+    fn datagram(&self) -> io::Result<&UdpSocket> {
+        self.as_ref().datagram()
+    }
+}
+
+/// Types that implement this trait can be plugged into [`ContextManager`] and [`Dispatcher`].
+#[async_trait]
+pub trait TransmissionProtocol: Debug + Display {
+    /// Send bytes to the remote. Any fault-tolerant logic should be implemented here.
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<usize>;
+    // where
+    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync;
+
+    /// Wait for a payload. Returns the payload source and data.
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)>;
+
+    /// Wait for a payload, keeping at most `memory_cap` bytes in memory.
+    ///
+    /// Protocols that can receive arbitrarily large payloads should spill to disk
+    /// past the cap. By default, the payload is received in memory.
+    async fn recv_payload(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+        _memory_cap: usize,
+    ) -> io::Result<(SocketAddrV4, ReceivedPayload)> {
+        let (addr, data) = self.recv_bytes(sock, timeout, retries).await?;
+        Ok((addr, ReceivedPayload::Memory(data)))
+    }
+
+    /// Number of incoming transfers abandoned by their sender and reaped.
+    ///
+    /// Protocols that do not track transfers report none.
+    fn reaped_transfers(&self) -> u64 {
+        0
+    }
+}
+
+/// Returns the outcome of the probability of getting `1` in `frac`.
+pub(super) fn probability_frac(frac: u32) -> bool {
+    let rand_num: u64 = rand::random();
+    let threshold = u64::MAX / frac as u64;
+
+    rand_num < threshold
+}
+
+#[cfg(test)]
+#[allow(unused)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    use crate::middleware::{sockaddr_to_v4, FailureRate};
+
+    use super::*;
+
+    #[test]
+    fn test_prob() {
+        let frac = 10;
+
+        let probs = (0..64)
+            .into_iter()
+            .map(|_| match probability_frac(frac) {
+                true => 1,
+                false => 0,
+            })
+            .collect::<Vec<_>>();
+
+        let s: i32 = probs.iter().sum();
+
+        println!("1 in {} yields {}", frac, s);
+    }
+
+    /// Transmit and receive some stuff
+    async fn tx_rx(
+        proto: Arc<dyn TransmissionProtocol + Send + Sync>,
+        large: bool,
+        timeout: Duration,
+        retries: u8,
+    ) {
+        let data_size = match large {
+            true => 60_000 * 10,
+            false => 51_200,
+        };
+
+        let data_payload = (0..data_size)
+            .into_iter()
+            .map(|num| (num & 0b1) as u8)
+            .collect::<Vec<_>>();
+
+        let tx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+
+        let rx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+
+        log::debug!("tx_sock: {:?}", tx_sock);
+        log::debug!("rx_sock: {:?}", rx_sock);
+
+        let tx_target = rx_sock.local_addr().unwrap();
+        let rx_target = tx_sock.local_addr().unwrap();
+
+        log::debug!("tx_target: {:?}", tx_target);
+        log::debug!("rx_target: {:?}", rx_target);
+
+        let mut tx_proto = proto.clone();
+        let mut rx_proto = proto.clone();
+
+        let payload_clone = data_payload.clone();
+
+        // rx keeps receiving after the payload arrives, so tx retransmissions are still
+        // acknowledged if the first acknowledgement is lost
+        let (rx_tx, rx_result) = tokio::sync::oneshot::channel();
+        let rx_handle = tokio::spawn(async move {
+            let _ = rx_tx.send(rx_proto.recv_bytes(&rx_sock, timeout, retries).await);
+            while rx_proto
+                .recv_bytes(&rx_sock, timeout, retries)
+                .await
+                .is_ok()
+            {}
+        });
+
+        let tx_handle = tokio::spawn(async move {
+            tx_proto
+                .send_bytes(
+                    &tx_sock,
+                    sockaddr_to_v4(tx_target)?,
+                    &payload_clone,
+                    timeout,
+                    retries,
+                )
+                .await
+        });
+
+        let tx_result = tx_handle
+            .await
+            .expect("unable to join task")
+            .expect("transmission failed");
+
+        let rx_result = rx_result
+            .await
+            .expect("unable to join task")
+            .expect("receive failed");
+        rx_handle.abort();
+
+        assert_eq!(rx_result.1, data_payload);
+    }
+
+    #[tokio::test]
+    async fn test_transmission_protocols() {
+        std::env::set_var("RUST_LOG", "DEBUG");
+        pretty_env_logger::formatted_timed_builder()
+            .parse_filters("DEBUG")
+            .init();
+
+        let handshake_proto = HandshakeProto::default();
+        let proto_arc = Arc::new(handshake_proto);
+
+        log::info!("testing HandshakeProto large");
+        tx_rx(proto_arc.clone(), true, Duration::from_millis(750), 5).await;
+
+        log::info!("testing HandshakeProto small");
+        tx_rx(proto_arc.clone(), false, Duration::from_millis(750), 5).await;
+
+        log::info!("testing DefaultProto small");
+        tx_rx(Arc::new(DefaultProto), false, Duration::from_millis(400), 2).await;
+
+        log::info!("testing RequestAckProto small");
+        tx_rx(
+            Arc::new(RequestAckProto),
+            false,
+            Duration::from_millis(400),
+            3,
+        )
+        .await;
+
+        // each attempt is lost about 1 in 5 times, at either end
+        log::info!("testing FaultyRequestAckProto small");
+        tx_rx(
+            Arc::new(FaultyRequestAckProto::from_frac(FailureRate(10))),
+            false,
+            Duration::from_millis(100),
+            10,
+        )
+        .await;
+
+        return;
+    }
+}
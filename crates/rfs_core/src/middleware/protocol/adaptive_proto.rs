@@ -0,0 +1,238 @@
+//! Module for [AdaptiveProto]
+
+use std::fmt::Display;
+use std::sync::Arc;
+use std::{io, net::SocketAddrV4, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::middleware::{
+    clock::real_clock, Clock, Endpoint, FailureRate, FaultyHandshakeProto, FaultyRequestAckProto,
+    HandshakeProto, PortRange, ReceivedPayload, RequestAckProto, TransferLimits,
+    TransmissionProtocol, BYTE_BUF_SIZE,
+};
+
+/// Every host must be able to receive a UDP payload of this size
+const SAFE_DATAGRAM_SIZE: usize = 508;
+
+/// Prefixes payloads sent with the protocol for small payloads.
+///
+/// Packets of the protocol for large payloads are serialized enums, which never start with this byte.
+const TAG_SMALL: u8 = b'A';
+
+/// Chooses a protocol for each payload, by its size.
+///
+/// Payloads that fit in a single safe datagram are sent with [RequestAckProto],
+/// and larger ones with [HandshakeProto]. Small payloads are tagged, so the receiver
+/// can tell which protocol the sender chose from the first packet.
+#[derive(Clone, Debug)]
+pub struct AdaptiveProto {
+    small: Arc<dyn TransmissionProtocol + Send + Sync>,
+    large: Arc<dyn TransmissionProtocol + Send + Sync>,
+
+    /// Rate of simulated failures of both protocols
+    faulty: Option<FailureRate>,
+
+    /// Data ports, time source and transfer limits of the protocol for large payloads
+    ports: Option<PortRange>,
+    clock: Arc<dyn Clock>,
+    limits: TransferLimits,
+}
+
+impl Default for AdaptiveProto {
+    fn default() -> Self {
+        Self {
+            small: Arc::new(RequestAckProto),
+            large: Arc::new(HandshakeProto::default()),
+            faulty: None,
+            ports: None,
+            clock: real_clock(),
+            limits: Default::default(),
+        }
+    }
+}
+
+impl AdaptiveProto {
+    /// A faulty version, simulating a transmission failure every 1 in `frac` attempts.
+    pub fn faulty(frac: FailureRate) -> Self {
+        Self {
+            small: Arc::new(FaultyRequestAckProto::from_frac(frac)),
+            large: Arc::new(FaultyHandshakeProto::from_frac(frac)),
+            faulty: Some(frac),
+            ports: None,
+            clock: real_clock(),
+            limits: Default::default(),
+        }
+    }
+
+    /// Same as [HandshakeProto::with_data_ports], for large payloads.
+    pub fn with_data_ports(mut self, ports: Option<PortRange>) -> Self {
+        self.ports = ports;
+        self.rebuild_large()
+    }
+
+    /// Same as [HandshakeProto::with_clock], for large payloads.
+    ///
+    /// The protocol for small payloads always waits on the runtime timer.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self.rebuild_large()
+    }
+
+    /// Same as [HandshakeProto::with_transfer_limits], for large payloads.
+    pub fn with_transfer_limits(mut self, limits: TransferLimits) -> Self {
+        self.limits = limits;
+        self.rebuild_large()
+    }
+
+    fn rebuild_large(mut self) -> Self {
+        let (ports, clock) = (self.ports.clone(), self.clock.clone());
+
+        self.large = match self.faulty {
+            Some(frac) => Arc::new(
+                FaultyHandshakeProto::from_frac(frac)
+                    .with_data_ports(ports)
+                    .with_clock(clock)
+                    .with_transfer_limits(self.limits),
+            ),
+            None => Arc::new(
+                HandshakeProto::default()
+                    .with_data_ports(ports)
+                    .with_clock(clock)
+                    .with_transfer_limits(self.limits),
+            ),
+        };
+        self
+    }
+
+    /// Returns true if a payload is sent with the protocol for small payloads
+    fn is_small(payload: &[u8]) -> bool {
+        payload.len() < SAFE_DATAGRAM_SIZE
+    }
+}
+
+impl Display for AdaptiveProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AdaptiveProto")
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for AdaptiveProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<usize> {
+        match Self::is_small(payload) {
+            true => {
+                let tagged = [&[TAG_SMALL], payload].concat();
+
+                self.small
+                    .send_bytes(sock, target, &tagged, timeout, retries)
+                    .await
+                    .map(|_| payload.len())
+            }
+            false => {
+                self.large
+                    .send_bytes(sock, target, payload, timeout, retries)
+                    .await
+            }
+        }
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let (addr, payload) = self
+            .recv_payload(sock, timeout, retries, usize::MAX)
+            .await?;
+
+        Ok((addr, payload.into_bytes()?))
+    }
+
+    async fn recv_payload(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+        memory_cap: usize,
+    ) -> io::Result<(SocketAddrV4, ReceivedPayload)> {
+        // the first packet is left for the chosen protocol to receive
+        let mut peek_buf = [0_u8; BYTE_BUF_SIZE];
+        let (size, _) = sock.datagram()?.peek_from(&mut peek_buf).await?;
+
+        match peek_buf[..size].first() {
+            Some(&TAG_SMALL) => {
+                let (addr, mut data) = self.small.recv_bytes(sock, timeout, retries).await?;
+                data.remove(0);
+
+                Ok((addr, ReceivedPayload::Memory(data)))
+            }
+            _ => {
+                self.large
+                    .recv_payload(sock, timeout, retries, memory_cap)
+                    .await
+            }
+        }
+    }
+
+    fn reaped_transfers(&self) -> u64 {
+        self.large.reaped_transfers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::UdpSocket;
+
+    use crate::middleware::{serialize_primary, sockaddr_to_v4, TransmissionPacket};
+
+    use super::*;
+
+    #[test]
+    fn test_tag_is_unambiguous() {
+        let packets = [
+            TransmissionPacket::Probe(vec![0; 64]),
+            TransmissionPacket::SwitchToAddress(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1)),
+            TransmissionPacket::KeepAlive,
+        ];
+
+        for packet in packets {
+            assert_ne!(serialize_primary(&packet).unwrap()[0], TAG_SMALL);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_proto() {
+        let proto = AdaptiveProto::default();
+
+        for size in [0, SAFE_DATAGRAM_SIZE - 1, SAFE_DATAGRAM_SIZE, 200_000] {
+            let payload = (0..size).map(|n| n as u8).collect::<Vec<_>>();
+
+            let tx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let rx_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let target = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+
+            let (sent, received) = tokio::join!(
+                proto.send_bytes(&tx_sock, target, &payload, Duration::from_millis(200), 3),
+                proto.recv_bytes(&rx_sock, Duration::from_millis(200), 3)
+            );
+
+            assert_eq!(sent.unwrap(), size);
+            assert_eq!(received.unwrap().1, payload);
+        }
+    }
+}
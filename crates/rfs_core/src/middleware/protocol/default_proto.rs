@@ -0,0 +1,125 @@
+//! Module for [DefaultProto]
+
+use std::fmt::Display;
+use std::{io, net::SocketAddrV4, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::middleware::{
+    probability_frac, sockaddr_to_v4, Endpoint, FailureRate, TransmissionProtocol,
+};
+use crate::ser_de::byte_packer::{pack_bytes, unpack_bytes};
+
+/// Packets are sent to the destination without checking if they have been received.
+///
+/// This protocol is compatible only with itself.
+///
+/// As this sends all data in a single UDP packet, the max payload size is `65507` bytes.
+#[derive(Clone, Debug)]
+pub struct DefaultProto;
+
+impl Display for DefaultProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &self)
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for DefaultProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<usize> {
+        let sock = sock.datagram()?;
+
+        let packed = pack_bytes(payload);
+        sock.send_to(&packed, target).await?;
+
+        Ok(payload.len())
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let sock = sock.datagram()?;
+
+        let mut buf = [0_u8; 65535];
+
+        let (size, addr) = sock.recv_from(&mut buf).await?;
+
+        let addr = sockaddr_to_v4(addr)?;
+        let unpacked = unpack_bytes(&buf[..size]);
+
+        Ok((addr, unpacked))
+    }
+}
+
+/// A faulty version of [DefaultProto].
+#[derive(Debug)]
+pub struct FaultyDefaultProto {
+    frac: u32,
+}
+
+impl FaultyDefaultProto {
+    pub fn from_frac(frac: FailureRate) -> Self {
+        Self { frac: frac.0 }
+    }
+}
+
+impl Display for FaultyDefaultProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FaultyDefaultProto")
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for FaultyDefaultProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<usize> {
+        let sock = sock.datagram()?;
+
+        match probability_frac(self.frac) {
+            true => {
+                log::error!("simulated packet drop");
+                Ok(payload.len())
+            }
+            false => {
+                let packed = pack_bytes(payload);
+                sock.send_to(&packed, target).await?;
+
+                Ok(payload.len())
+            }
+        }
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let sock = sock.datagram()?;
+
+        let mut buf = [0_u8; 65535];
+
+        let (size, addr) = sock.recv_from(&mut buf).await?;
+
+        let addr = sockaddr_to_v4(addr)?;
+        let unpacked = unpack_bytes(&buf[..size]);
+
+        Ok((addr, unpacked))
+    }
+}
@@ -0,0 +1,2053 @@
+//! Module for [HandshakeProto]
+#![allow(unused)]
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use std::{io, net::SocketAddrV4, time::Duration};
+
+use async_trait::async_trait;
+use futures::io::ReadToEnd;
+use futures::lock::Mutex;
+use futures::{Future, FutureExt};
+use rand::seq;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::fsm::TransitableState;
+use crate::ser_de::dbg_vec_to_chars;
+use crate::{fsm, middleware::sockaddr_to_v4};
+
+use crate::middleware::received_payload::PayloadBuffer;
+use crate::middleware::{clock::real_clock, Clock};
+use crate::middleware::{
+    deserialize_primary, probability_frac, serialize_primary, Endpoint, TransmissionProtocol,
+};
+use crate::middleware::{hash_primary, TransmissionPacket};
+use crate::middleware::{report_retry, ReceivedPayload, RetryReason};
+use crate::middleware::{FailureRate, PortRange, SocketPool, SocketProvider};
+
+/// This protocol ensures that every sent packet from the source must be acknowledged by the sink.
+/// Timeouts and retries are fully implmented.
+///
+/// This protocol is not restricted by the UDP data limit.
+/// In other words, it supports the transmission of an arbitrary number of bytes.
+///
+/// Transfers switch to sockets from a pool, which is shared between clones of the protocol.
+///
+/// Incoming transfers that exceed their [TransferLimits] are abandoned by rx, and counted
+/// in [TransmissionProtocol::reaped_transfers].
+#[derive(Clone, Debug)]
+pub struct HandshakeProto {
+    sockets: TransferSockets,
+    clock: Arc<dyn Clock>,
+    limits: TransferLimits,
+
+    /// Segment sizes found by probing, shared between clones
+    segment_sizes: Arc<Mutex<HashMap<SocketAddrV4, ProbedSize>>>,
+
+    /// Number of incoming transfers reaped, shared between clones
+    reaped: Arc<AtomicU64>,
+}
+
+/// Limits on an incoming transfer, after which rx reaps it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferLimits {
+    /// Time a transfer may take, from the address change to the last segment
+    pub deadline: Duration,
+
+    /// Time rx waits without receiving anything from tx, including keep-alives
+    pub idle_timeout: Duration,
+}
+
+/// A faulty version that is compatible with [HandshakeProto].
+#[derive(Debug)]
+pub struct FaultyHandshakeProto {
+    frac: u32,
+    inner: HandshakeProto,
+}
+
+/// Pools of sockets that transfers switch to, by local address.
+#[derive(Clone, Debug, Default)]
+struct TransferSockets {
+    pools: Arc<Mutex<HashMap<Ipv4Addr, SocketPool>>>,
+
+    /// Ports the pooled sockets are bound to, advertised to the other end of a transfer
+    ports: Option<PortRange>,
+}
+
+/// Result of probing the segment size of a peer
+#[derive(Clone, Copy, Debug)]
+struct ProbedSize {
+    /// Largest segment size known to reach the peer
+    size: usize,
+
+    /// Larger segments did not reach the peer. Otherwise, larger sizes were not probed.
+    limit: bool,
+
+    /// Time of the probe
+    probed: Instant,
+}
+
+/// A socket taken from [TransferSockets] for a single transfer.
+///
+/// If the transfer is dropped before it releases the socket, such as when an invocation
+/// is abandoned, the socket is returned to its pool in the background.
+struct TransferSocket {
+    sock: Option<Arc<UdpSocket>>,
+    sockets: TransferSockets,
+    timeout: Duration,
+}
+
+/// Transmitter states
+#[derive(Clone, Copy, Debug, Default)]
+enum HandshakeTx {
+    /// tx discovering the largest datagram that reaches rx
+    #[default]
+    Probe,
+
+    SendAddressChange,
+
+    /// tx sending packets, hands over control to rx
+    Transmit,
+
+    /// tx complete
+    Complete,
+}
+
+/// State transition events for [HandshakeTx]
+#[derive(Clone, Copy, Debug)]
+enum HandshakeTxEvent {
+    // / tx has sent the new address to rx
+    // SendNewAddr,
+    /// tx has determined the segment size
+    ProbeComplete,
+
+    /// tx has received a new destination address to send to
+    ReceiveNewAddr,
+
+    /// Last packet has been acknowledged
+    AcknowledgeLast,
+}
+
+/// Receiver states
+#[derive(Clone, Copy, Debug, Default)]
+enum HandshakeRx {
+    /// rx awaiting a change in address
+    #[default]
+    AwaitAddressChange,
+
+    /// rx receiving packets, takes control over tx
+    Receive,
+
+    /// rx complete
+    Complete,
+}
+
+/// State transition events for [HandshakeRx]
+#[derive(Clone, Copy, Debug)]
+enum HandshakeRxEvent {
+    /// The new address is sent back to tx
+    SendNewAddr,
+
+    /// All packets received
+    ReceivedAll,
+}
+
+// state transitions for the transmitter
+fsm::state_transitions! {
+    type State = HandshakeTx;
+    type Event = HandshakeTxEvent;
+
+    Probe + ProbeComplete => SendAddressChange;
+    SendAddressChange + ReceiveNewAddr => Transmit;
+    Transmit + AcknowledgeLast => Complete;
+
+    // on receiving a repeat request, go back to the previous state
+    Transmit + ReceiveNewAddr => SendAddressChange;
+}
+
+// states transitions for the receiver
+fsm::state_transitions! {
+   type State = HandshakeRx;
+   type Event = HandshakeRxEvent;
+
+    AwaitAddressChange + SendNewAddr => Receive;
+    Receive + ReceivedAll => Complete;
+}
+
+impl TransferSockets {
+    /// Take a socket bound to the same address as an existing socket.
+    async fn take(&self, existing: &UdpSocket, timeout: Duration) -> io::Result<TransferSocket> {
+        let addr = *sockaddr_to_v4(existing.local_addr()?)?.ip();
+
+        let mut pools = self.pools.lock().await;
+        pools
+            .entry(addr)
+            .or_insert_with(|| match &self.ports {
+                Some(ports) => SocketPool::with_ports(addr, ports.clone()),
+                None => SocketPool::from_addr(addr),
+            })
+            .new_bind_sock()
+            .await
+            .map(|sock| TransferSocket {
+                sock: Some(sock),
+                sockets: self.clone(),
+                timeout,
+            })
+    }
+
+    /// Return a socket to its pool once its transfer is over.
+    ///
+    /// The socket is left idle for twice the transfer timeout, so late packets from the transfer
+    /// (repeated completions, re-transmissions) are not received by the next user.
+    async fn give_back(&self, sock: Arc<UdpSocket>, timeout: Duration) {
+        let addr = match sock.local_addr().map(sockaddr_to_v4) {
+            Ok(Ok(a)) => *a.ip(),
+            _ => return,
+        };
+
+        if let Some(pool) = self.pools.lock().await.get_mut(&addr) {
+            if let Err(e) = pool.free_sock_after(sock, timeout * 2).await {
+                log::error!("failed to free transfer socket: {}", e);
+            }
+        }
+    }
+
+    /// Number of sockets in all pools
+    #[cfg(test)]
+    async fn len(&self) -> usize {
+        self.pools.lock().await.values().map(|p| p.len()).sum()
+    }
+}
+
+impl TransferSocket {
+    /// Return the socket to its pool once the transfer is over.
+    async fn release(mut self) {
+        if let Some(sock) = self.sock.take() {
+            self.sockets.give_back(sock, self.timeout).await;
+        }
+    }
+}
+
+impl std::ops::Deref for TransferSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &Self::Target {
+        self.sock.as_ref().expect("socket already released")
+    }
+}
+
+impl Drop for TransferSocket {
+    fn drop(&mut self) {
+        let sock = match self.sock.take() {
+            Some(s) => s,
+            None => return,
+        };
+
+        log::debug!(
+            "transfer abandoned, returning socket {:?}",
+            sock.local_addr()
+        );
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let sockets = self.sockets.clone();
+            let timeout = self.timeout;
+            handle.spawn(async move { sockets.give_back(sock, timeout).await });
+        }
+    }
+}
+
+/// Perform an operation with a given probabililty
+async fn perform_op_with_probability<O, F: Future<Output = O>>(
+    probability: Option<u32>,
+    default: O,
+    future: F,
+) -> O {
+    match probability {
+        Some(n) => {
+            if probability_frac(n) {
+                log::error!("simulated failure");
+                return default;
+            }
+            future.await
+        }
+        None => future.await,
+    }
+}
+
+impl Default for TransferLimits {
+    fn default() -> Self {
+        Self {
+            deadline: Duration::from_secs(600),
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for HandshakeProto {
+    fn default() -> Self {
+        Self {
+            sockets: Default::default(),
+            clock: real_clock(),
+            limits: Default::default(),
+            segment_sizes: Default::default(),
+            reaped: Default::default(),
+        }
+    }
+}
+
+impl HandshakeProto {
+    /// This is a conservative limit on the max packet size
+    const MAX_PACKET_PAYLOAD_SIZE: usize = 51_200;
+
+    /// Room left in a probed datagram for the header of a data packet
+    const PROBE_HEADER_MARGIN: usize = 32;
+
+    /// Packet size used when probing fails.
+    ///
+    /// Every host must be able to receive a 508-byte UDP payload.
+    const MIN_PACKET_PAYLOAD_SIZE: usize = 508 - Self::PROBE_HEADER_MARGIN;
+
+    /// Probing stops once the largest working size is known to this precision
+    const PROBE_GRANULARITY: usize = 256;
+
+    /// Idle time after which tx sends a keep-alive to rx
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Time a probed segment size is reused for, before the peer is probed again
+    const SEGMENT_SIZE_TTL: Duration = Duration::from_secs(60);
+
+    /// Bind the sockets that transfers switch to to ports in a range, or any free port if `None`.
+    ///
+    /// The ports are advertised in [TransmissionPacket::SwitchToAddress],
+    /// so only this range needs to be reachable for data traffic.
+    pub fn with_data_ports(mut self, ports: Option<PortRange>) -> Self {
+        self.sockets = TransferSockets {
+            pools: Default::default(),
+            ports,
+        };
+        self
+    }
+
+    /// Use a different time source for timeouts and keep-alives.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Reap incoming transfers that exceed these limits.
+    pub fn with_transfer_limits(mut self, limits: TransferLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Abandon an incoming transfer from tx, returning the error rx fails with.
+    fn reap(&self, source: SocketAddrV4, reason: &str) -> io::Error {
+        self.reaped.fetch_add(1, Ordering::Relaxed);
+        log::warn!("reaped transfer from {}: {}", source, reason);
+
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("transfer reaped: {}", reason),
+        )
+    }
+
+    /// Sends something repeatedly until a response is received.
+    /// The max payload this method can accept is 65507 bytes.
+    async fn send_and_recv<A: ToSocketAddrs>(
+        &self,
+        sock: &UdpSocket,
+        target: A,
+        payload: &[u8],
+        timeout: Duration,
+        mut retries: u8,
+        faulty: Option<u32>,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        if payload.len() > 65_507 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload exceeds max UDP data size",
+            ));
+        }
+
+        let mut attempt = 0;
+        loop {
+            let _ = match faulty {
+                Some(n) => 0,
+                None => sock.send_to(&payload, &target).await?,
+            };
+
+            // send the request until the remote acknowledges the update
+            // or when the connection times out
+            tokio::select! {
+                biased;
+
+                res = async {
+                    let mut buf = [0_u8; 65535];
+                    let s = sock.recv_from(&mut buf).await;
+
+                    s.and_then(|(size, addr)| {
+
+                        let bytes = &buf[..size];
+
+                        let v4_addr = sockaddr_to_v4(addr)?;
+                        Ok((v4_addr, bytes.to_vec()))
+
+                        // if size != payload.len() {
+                        //     Err(io::Error::new(io::ErrorKind::InvalidData, format!("data not sent completely. Have {}, sent {}", payload.len(), size)))
+                        // } else  {
+
+                        // }
+                    })
+
+                }.fuse() => {
+                    match res {
+                        Ok((addr, d)) => {
+                            break Ok((addr, d))
+                        },
+                        Err(e) => {
+                            log::error!("{}", e);
+                            retries -= 1;
+                            attempt += 1;
+                            report_retry(attempt, Duration::ZERO, RetryReason::InvalidResponse);
+                        },
+                    }
+                },
+
+                _ = self.clock.sleep(timeout).fuse() => {
+
+                    log::error!("connection timed out. retries left: {}", retries);
+
+                    match retries {
+                        0 => break Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out while waiting for response")),
+                        _ => retries -= 1,
+                    }
+                    attempt += 1;
+                    report_retry(attempt, timeout, RetryReason::Timeout);
+                    continue;
+                }
+
+            }
+        }
+    }
+
+    /// The final transmission in a request-ack cycle is special.
+    ///
+    /// This method implements the following logic:
+    /// - transmit the [`TransmissionPacket::Complete`] variant
+    /// - select: timeout to elapse or an incoming packet
+    ///
+    /// Handle each case
+    async fn transmit_final_ack<A: ToSocketAddrs>(
+        &self,
+        sock: &UdpSocket,
+        target: A,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<()> {
+        const ACK_PACKET: TransmissionPacket = TransmissionPacket::Complete;
+        let ack_payload = serialize_primary(&ACK_PACKET).expect("serialization must not fail");
+
+        loop {
+            log::debug!("transmitting final packet");
+            let _ = sock.send_to(&ack_payload, &target).await?;
+
+            tokio::select! {
+
+                // if the timeout elapses and no further response is received, the packet is assumed to
+                // be received
+                _ = self.clock.sleep(timeout).fuse() => {
+                    break Ok(())
+                }
+
+                // if a `complete` packet is received first, send the packets again
+                res = async {
+                    let mut buf = [0_u8; 100];
+                    let res = sock.recv_from(&mut buf).await;
+
+                    res.and_then(|(size, _)| {
+                        Ok(buf[..size].to_vec())
+                    })
+
+                }.fuse() => {
+                    log::error!("received duplicate");
+
+                    let data = res?;
+                    let packet = deserialize_primary(&data).map_err(|_| io::Error::new (io::ErrorKind::InvalidData, "deserialization failed"))?;
+
+                    match packet {
+                        TransmissionPacket::Complete => (),
+                        other => break Err(io::Error::new(io::ErrorKind::InvalidInput, format!("expected a transmission complete packet, got {:?}", other)))
+                    }
+                }
+
+            }
+        }
+    }
+}
+
+// tx impls
+impl HandshakeProto {
+    /// Discover the largest segment size that can be sent to rx.
+    ///
+    /// The full payload size is probed first. If it does not get through,
+    /// a binary search is performed between [Self::MIN_PACKET_PAYLOAD_SIZE] and the payload size.
+    /// Lost probes are treated as too large, so the size falls back to the minimum on loss.
+    ///
+    /// The size found is reused for [Self::SEGMENT_SIZE_TTL], counted from the end of probing.
+    /// Payloads that fit in a size that reached the target, or that are larger than its limit,
+    /// are not probed again. If no probe was acknowledged, nothing is known about the path,
+    /// so the minimum is only used for the current transfer.
+    async fn probe_datagram_size(
+        &self,
+        state: &mut HandshakeTx,
+        sock: &UdpSocket,
+        target: SocketAddrV4,
+        payload_len: usize,
+        timeout: Duration,
+        faulty: Option<u32>,
+    ) -> usize {
+        let mut lower = Self::MIN_PACKET_PAYLOAD_SIZE;
+        let mut upper = payload_len.min(Self::MAX_PACKET_PAYLOAD_SIZE);
+
+        let probed = self
+            .segment_sizes
+            .lock()
+            .await
+            .get(&target)
+            .filter(|p| self.clock.elapsed(p.probed) < Self::SEGMENT_SIZE_TTL)
+            .copied();
+
+        let size = match (upper <= lower, probed) {
+            // payload fits in the smallest segment, no probing required
+            (true, _) => lower,
+            (false, Some(p)) if p.size >= upper => upper,
+            (false, Some(p)) if p.limit => p.size,
+            (false, _) => {
+                let (size, limit) = match self.probe(sock, target, upper, timeout, faulty).await {
+                    true => (upper, false),
+                    false => {
+                        upper -= 1;
+                        while upper - lower >= Self::PROBE_GRANULARITY {
+                            let mid = lower + (upper - lower) / 2;
+                            match self.probe(sock, target, mid, timeout, faulty).await {
+                                true => lower = mid,
+                                false => upper = mid - 1,
+                            }
+                        }
+                        (lower, true)
+                    }
+                };
+
+                // every size above the minimum was acknowledged by a probe
+                if size > Self::MIN_PACKET_PAYLOAD_SIZE {
+                    self.segment_sizes.lock().await.insert(
+                        target,
+                        ProbedSize {
+                            size,
+                            limit,
+                            probed: self.clock.now(),
+                        },
+                    );
+                }
+                size
+            }
+        };
+
+        log::debug!("tx segment size: {} bytes", size);
+        state.ingest(HandshakeTxEvent::ProbeComplete);
+
+        size
+    }
+
+    /// Send a single probe of a given segment size and wait for it to be acknowledged.
+    async fn probe(
+        &self,
+        sock: &UdpSocket,
+        target: SocketAddrV4,
+        size: usize,
+        timeout: Duration,
+        faulty: Option<u32>,
+    ) -> bool {
+        let padding = size + Self::PROBE_HEADER_MARGIN;
+        let packet = TransmissionPacket::Probe(vec![0; padding]);
+        let ser_packet = serialize_primary(&packet).expect("serialization must not fail");
+
+        log::debug!("tx probing segment size {}", size);
+
+        // datagrams that are too large can be rejected by the OS
+        if let Err(e) = perform_op_with_probability(
+            faulty,
+            Ok(ser_packet.len()),
+            sock.send_to(&ser_packet, target),
+        )
+        .await
+        {
+            log::debug!("probe of size {} not sent: {}", size, e);
+            return false;
+        }
+
+        let ack = async {
+            let mut buf = [0_u8; 1_000];
+            loop {
+                let (len, _) = sock.recv_from(&mut buf).await?;
+
+                // acks of earlier probes may arrive late
+                match deserialize_primary(&buf[..len]) {
+                    Ok(TransmissionPacket::ProbeAck(n)) if n as usize == padding => {
+                        break io::Result::Ok(())
+                    }
+                    _ => continue,
+                }
+            }
+        };
+
+        tokio::select! {
+            res = ack => res.is_ok(),
+            _ = self.clock.sleep(timeout) => false,
+        }
+    }
+
+    /// Sends the new address over to rx
+    async fn send_address_change<A: ToSocketAddrs>(
+        &self,
+        state: &mut HandshakeTx,
+        sock: &UdpSocket,
+        target: A,
+        new_addr: SocketAddrV4,
+        new_target: &mut Option<SocketAddrV4>,
+        timeout: Duration,
+        retries: u8,
+        // 1 in N probability of omitting the packet
+        faulty: Option<u32>,
+    ) -> io::Result<()> {
+        let payload = TransmissionPacket::SwitchToAddress(new_addr);
+        let ser_payload = serialize_primary(&payload).expect("serialization must not fail");
+
+        log::debug!("tx sending new tx address ({})", new_addr);
+
+        let (_, bytes) = self
+            .send_and_recv(sock, target, &ser_payload, timeout, retries, None)
+            .await?;
+
+        let resp: TransmissionPacket = deserialize_primary(&bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "deserialization failed"))?;
+
+        if let TransmissionPacket::SwitchToAddress(n_target) = resp {
+            log::debug!("tx received new rx address: {}", n_target);
+
+            // modify state
+            state.ingest(HandshakeTxEvent::ReceiveNewAddr);
+
+            *new_target = Some(n_target);
+        } else {
+            log::debug!("tx received incorrect packet: {:?}", resp);
+        }
+
+        Ok(())
+    }
+
+    /// Waits for rx to send over the new address. Error handling is done by rx.
+    /// Modifies the given target to this new address.
+    ///
+    /// A keep-alive is sent to rx whenever tx has been idle for `keep_alive`.
+    /// Segments are sent to the address of the latest sequence request, should rx move mid-transfer.
+    async fn transmit_data(
+        &self,
+        state: &mut HandshakeTx,
+        sock: &UdpSocket,
+        mut target: SocketAddrV4,
+        payload: &[u8],
+        segment_size: usize,
+        keep_alive: Duration,
+        faulty: Option<u32>,
+    ) -> io::Result<()> {
+        // an empty payload is sent as a single empty segment
+        let num_segments = payload.len().div_ceil(segment_size).max(1);
+
+        let mut keep_alive_timer = self.clock.sleep(keep_alive);
+
+        // wait for a sequence number and send that packet out
+        loop {
+            let mut seq_buf = [0_u8; 1_000];
+
+            let (size, addr) = tokio::select! {
+                res = sock.recv_from(&mut seq_buf) => res?,
+                _ = &mut keep_alive_timer => {
+                    keep_alive_timer = self.clock.sleep(keep_alive);
+                    log::debug!("tx sending keep-alive to {}", target);
+                    let ser_packet = serialize_primary(&TransmissionPacket::KeepAlive)
+                        .expect("serialization must not fail");
+
+                    perform_op_with_probability(
+                        faulty,
+                        Ok(ser_packet.len()),
+                        sock.send_to(&ser_packet, target),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+
+            let data = &seq_buf[..size];
+            let packet: TransmissionPacket = deserialize_primary(&data).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "tx deserialization failed of Transmission packet",
+                )
+            })?;
+
+            match packet {
+                TransmissionPacket::SwitchToAddress(_) => {
+                    // switch to the previous state and exit
+                    state.ingest(HandshakeTxEvent::ReceiveNewAddr);
+                    return Ok(());
+                }
+                TransmissionPacket::Seq(seq_num) => {
+                    let addr = sockaddr_to_v4(addr)?;
+                    if addr != target {
+                        log::debug!("tx rx address changed from {} to {}", target, addr);
+                        target = addr;
+                    }
+
+                    log::debug!("tx sending sequence {}", seq_num);
+                    let start = seq_num as usize * segment_size;
+                    let packet = match seq_num as usize + 1 == num_segments {
+                        true => {
+                            let packet_data = &payload[start..];
+
+                            TransmissionPacket::Data {
+                                seq: seq_num as u32,
+                                hash: hash_primary(&packet_data),
+                                data: packet_data.to_vec(),
+                                last: true,
+                            }
+                        }
+                        false => {
+                            let packet_data = &payload[start..(start + segment_size)];
+
+                            TransmissionPacket::Data {
+                                seq: seq_num as u32,
+                                hash: hash_primary(&packet_data),
+                                data: packet_data.to_vec(),
+                                last: false,
+                            }
+                        }
+                    };
+
+                    let ser_packet =
+                        serialize_primary(&packet).expect("serialization must not fail");
+
+                    perform_op_with_probability(
+                        faulty,
+                        Ok(ser_packet.len()),
+                        sock.send_to(&ser_packet, target),
+                    )
+                    .await?;
+                    keep_alive_timer = self.clock.sleep(keep_alive);
+
+                    // match faulty {
+                    //     Some(n) => {
+                    //         if probability_frac(n) {
+                    //         } else {
+                    //             sock.send_to(&ser_packet, target).await?;
+                    //         }
+                    //     }
+                    //     None => {
+                    //         sock.send_to(&ser_packet, target).await?;
+                    //     }
+                    // }
+                }
+
+                // update state and exit
+                TransmissionPacket::Complete => {
+                    state.ingest(HandshakeTxEvent::AcknowledgeLast);
+                    return Ok(());
+                }
+                // do nothing for the rest
+                _ => (),
+            }
+        }
+    }
+}
+
+// rx impls
+impl HandshakeProto {
+    /// await the new address and send back a new address.
+    ///
+    /// The original address of the tx is returned.
+    async fn await_address_change(
+        &self,
+        state: &mut HandshakeRx,
+        sock: &UdpSocket,
+        new_target: &mut Option<SocketAddrV4>,
+        new_address: SocketAddrV4,
+        faulty: Option<u32>,
+    ) -> io::Result<SocketAddrV4> {
+        let mut recv_buf = [0_u8; 65535];
+
+        let addr = loop {
+            let (size, addr) = sock.recv_from(&mut recv_buf).await?;
+
+            let packet: TransmissionPacket =
+                deserialize_primary(&recv_buf[..size]).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "deserialization failed of TransmissionPacket when awaiting new address",
+                    )
+                })?;
+
+            match packet {
+                TransmissionPacket::SwitchToAddress(new_addr) => {
+                    log::debug!("rx changing target addresss to {}", new_addr);
+                    *new_target = Some(new_addr);
+                    break addr;
+                }
+
+                // acknowledge the size of the probe
+                TransmissionPacket::Probe(padding) => {
+                    let ack = TransmissionPacket::ProbeAck(padding.len() as u32);
+                    let ser_ack = serialize_primary(&ack).expect("serialization must not fail");
+
+                    perform_op_with_probability(
+                        faulty,
+                        Ok(ser_ack.len()),
+                        sock.send_to(&ser_ack, addr),
+                    )
+                    .await?;
+                }
+
+                // continue listening
+                _ => (),
+            }
+        };
+
+        let packet = TransmissionPacket::SwitchToAddress(new_address);
+        let ser_packet = serialize_primary(&packet).expect("serialization must not fail");
+
+        perform_op_with_probability(
+            faulty,
+            Ok(ser_packet.len()),
+            sock.send_to(&ser_packet, addr),
+        )
+        .await?;
+
+        // match faulty {
+        //     Some(n) => {
+        //         if probability_frac(n) {
+        //         } else {
+        //             sock.send_to(&ser_packet, addr).await?;
+        //         }
+        //     }
+        //     None => {
+        //         sock.send_to(&ser_packet, addr).await?;
+        //     }
+        // }
+
+        state.ingest(HandshakeRxEvent::SendNewAddr);
+
+        Ok(sockaddr_to_v4(addr)?)
+    }
+
+    // receive loop
+    //
+    // Sequence requests are sent to the latest address tx transmits from.
+    // Only a valid segment continuing the transfer moves tx to a new address,
+    // other packets from addresses other than tx are dropped without counting
+    // as activity from tx.
+    async fn receive(
+        &self,
+        state: &mut HandshakeRx,
+        sock: &UdpSocket,
+        mut target: SocketAddrV4,
+        rx_data: &mut PayloadBuffer,
+        timeout: Duration,
+        retries: u8,
+        faulty: Option<u32>,
+    ) -> io::Result<()> {
+        let mut sequence_num = 0;
+        let mut consec_sequences = Vec::new();
+        let mut last_heard = self.clock.now();
+
+        loop {
+            let mut seq_buf = [0_u8; 65535];
+
+            // send out ack for packet number
+            let packet = TransmissionPacket::Seq(sequence_num);
+            let ser_packet = serialize_primary(&packet).expect("serialization must not fail");
+
+            log::debug!("rx requesting sequence {}", sequence_num);
+
+            match consec_sequences.len() as u8 >= retries {
+                true => {
+                    log::error!("maximum retries for sequence {} reached", sequence_num);
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "maximum retries reached for a sequence number",
+                    ));
+                }
+                false => (),
+            }
+
+            match consec_sequences.first() {
+                Some(num) => match num == &sequence_num {
+                    true => consec_sequences.push(sequence_num),
+                    false => {
+                        consec_sequences.clear();
+                        consec_sequences.push(sequence_num)
+                    }
+                },
+                None => consec_sequences.push(sequence_num),
+            }
+
+            perform_op_with_probability(
+                faulty,
+                Ok(ser_packet.len()),
+                sock.send_to(&ser_packet, target),
+            )
+            .await?;
+
+            // match faulty {
+            //     Some(n) => {
+            //         if probability_frac(n) {
+            //         } else {
+            //             sock.send_to(&ser_packet, target).await?;
+            //         }
+            //     }
+            //     None => {
+            //         sock.send_to(&ser_packet, target).await?;
+            //     }
+            // }
+
+            // receive with timeout
+            let (packet, addr) = tokio::select! {
+                biased;
+
+                res = async {
+                    loop {
+                        let (size, addr) = sock.recv_from(&mut seq_buf).await?;
+                        let addr = sockaddr_to_v4(addr)?;
+
+                        let packet: TransmissionPacket =
+                            deserialize_primary(&seq_buf[..size]).map_err(|_| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    "rx deserialization failed of TransmissionPacket. Ensure that data is serialized using `serialize` and not `serialize_packed`",
+                                )
+                            })?;
+
+                        // tx is only followed to a new address by the segment it was asked for
+                        let continues = matches!(
+                            &packet,
+                            TransmissionPacket::Data { seq, hash, data, .. }
+                                if *seq == sequence_num as u32 && *hash == hash_primary(data)
+                        );
+                        if addr != target && !continues {
+                            log::warn!("rx dropping packet from {}, expected tx at {}", addr, target);
+                            continue;
+                        }
+                        last_heard = self.clock.now();
+
+                        // keep-alives do not count towards the sequence request
+                        match packet {
+                            TransmissionPacket::KeepAlive => {
+                                log::debug!("rx received keep-alive from {}", addr);
+                            }
+                            packet => break io::Result::Ok((packet, addr)),
+                        }
+                    }
+                }.fuse() => res,
+
+                _ = self.clock.sleep(timeout).fuse() => {
+                    log::error!("timeout elapsed");
+                    if self.clock.elapsed(last_heard) >= self.limits.idle_timeout {
+                        return Err(self.reap(target, "tx is idle"));
+                    }
+                    report_retry(consec_sequences.len() as u32, timeout, RetryReason::Timeout);
+                    continue;
+                }
+            }?;
+
+            match packet {
+                TransmissionPacket::Data {
+                    seq,
+                    hash,
+                    data,
+                    last,
+                } => {
+                    match (seq == sequence_num as u32, hash == hash_primary(&data)) {
+                        (true, true) => {
+                            log::debug!("rx received sequence {}", sequence_num);
+                            if addr != target {
+                                log::debug!("rx tx address changed from {} to {}", target, addr);
+                                target = addr;
+                            }
+                            rx_data.extend(&data)?;
+                            sequence_num += 1;
+                        }
+                        // re-transmit packet
+                        _ => {
+                            log::debug!("rx requires re-transmitting sequence {}", sequence_num);
+                            report_retry(
+                                consec_sequences.len() as u32,
+                                Duration::ZERO,
+                                RetryReason::Retransmit,
+                            );
+                            continue;
+                        }
+                    }
+
+                    // correctly received last packet and exit
+                    if last {
+                        log::debug!("rx received last sequence");
+                        state.ingest(HandshakeRxEvent::ReceivedAll);
+                        break;
+                    }
+                }
+                TransmissionPacket::SwitchToAddress(_) => {
+                    state.ingest(HandshakeRxEvent::SendNewAddr);
+                    break;
+                }
+                TransmissionPacket::Complete => {
+                    state.ingest(HandshakeRxEvent::ReceivedAll);
+                    break;
+                }
+
+                // no-op
+                TransmissionPacket::Ack(_)
+                | TransmissionPacket::Seq(_)
+                | TransmissionPacket::Probe(_)
+                | TransmissionPacket::ProbeAck(_)
+                | TransmissionPacket::KeepAlive => {
+                    continue;
+                    // unimplemented!("cases are never handled by rx")
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        sock: &UdpSocket,
+        target: SocketAddrV4,
+        repeats: u8,
+        faulty: Option<u32>,
+    ) -> io::Result<()> {
+        let packet = TransmissionPacket::Complete;
+        let ser_packet = serialize_primary(&packet).expect("serialization must not fail");
+
+        // we will send multiple times to ensure that the packet is received.
+        // only one packet needs to be received for this to be successful
+        for _ in 0..repeats {
+            perform_op_with_probability(
+                faulty,
+                Ok(ser_packet.len()),
+                sock.send_to(&ser_packet, target),
+            )
+            .await?;
+
+            // match faulty {
+            //     Some(n) => {
+            //         if probability_frac(n) {
+            //         } else {
+            //             sock.send_to(&ser_packet, target).await?;
+            //         }
+            //     }
+            //     None => {
+            //         sock.send_to(&ser_packet, target).await?;
+            //     }
+            // }
+        }
+
+        Ok(())
+    }
+
+    /// Run the receiver to completion, returning the original address of tx.
+    async fn receive_payload(
+        &self,
+        sock: &UdpSocket,
+        timeout: Duration,
+        retries: u8,
+        rx_data: &mut PayloadBuffer,
+        faulty: Option<u32>,
+    ) -> io::Result<SocketAddrV4> {
+        // state control
+        let mut rx_state = HandshakeRx::default();
+        let mut rx_target: Option<SocketAddrV4> = None;
+
+        let rx_sock = self.sockets.take(sock, timeout).await?;
+
+        // this is the original address of tx
+        let mut rx_source: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0);
+
+        // the deadline runs from the address change
+        let mut started = self.clock.now();
+
+        let res: io::Result<()> = async {
+            loop {
+                log::debug!("rx state: {:?}", rx_state);
+
+                match rx_state {
+                    HandshakeRx::AwaitAddressChange => {
+                        rx_source = self
+                            .await_address_change(
+                                &mut rx_state,
+                                sock, // we need to use the existing socket when listening for these changes
+                                &mut rx_target,
+                                sockaddr_to_v4(rx_sock.local_addr()?)?,
+                                faulty,
+                            )
+                            .await?;
+                        started = self.clock.now();
+                    }
+                    HandshakeRx::Receive => {
+                        let remaining = self
+                            .limits
+                            .deadline
+                            .saturating_sub(self.clock.elapsed(started));
+
+                        tokio::select! {
+                            res = self.receive(
+                                &mut rx_state,
+                                &rx_sock,
+                                rx_target.expect("no target to receive from"),
+                                rx_data,
+                                timeout,
+                                retries,
+                                faulty,
+                            ) => res?,
+                            _ = self.clock.sleep(remaining) => {
+                                return Err(self.reap(rx_source, "deadline exceeded"));
+                            }
+                        }
+                    }
+                    HandshakeRx::Complete => {
+                        self.complete(
+                            &sock,
+                            rx_target.expect("no target to receive from"),
+                            retries,
+                            faulty,
+                        )
+                        .await?;
+                        break;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .await;
+        rx_sock.release().await;
+
+        res.map(|_| rx_source)
+    }
+}
+
+impl FaultyHandshakeProto {
+    pub fn from_frac(frac: FailureRate) -> Self {
+        Self {
+            frac: frac.0,
+            inner: Default::default(),
+        }
+    }
+
+    /// Same as [HandshakeProto::with_data_ports].
+    pub fn with_data_ports(mut self, ports: Option<PortRange>) -> Self {
+        self.inner = self.inner.with_data_ports(ports);
+        self
+    }
+
+    /// Same as [HandshakeProto::with_clock].
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.inner = self.inner.with_clock(clock);
+        self
+    }
+
+    /// Same as [HandshakeProto::with_transfer_limits].
+    pub fn with_transfer_limits(mut self, limits: TransferLimits) -> Self {
+        self.inner = self.inner.with_transfer_limits(limits);
+        self
+    }
+}
+
+impl Display for HandshakeProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HandshakeProto")
+    }
+}
+
+impl Display for FaultyHandshakeProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FaultyHandshakeProto")
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for HandshakeProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<usize> {
+        let sock = sock.datagram()?;
+
+        // first we will switch target sockets so that we don't block the main process
+        // from receiving requests
+
+        // state control variable
+        let mut tx_state = HandshakeTx::default();
+        let mut tx_target: Option<SocketAddrV4> = None;
+        let mut segment_size = Self::MAX_PACKET_PAYLOAD_SIZE;
+
+        let tx_sock = self.sockets.take(sock, timeout).await?;
+
+        let res: io::Result<()> = async {
+            loop {
+                log::debug!("tx state: {:?}", tx_state);
+
+                match tx_state {
+                    HandshakeTx::Probe => {
+                        segment_size = self
+                            .probe_datagram_size(
+                                &mut tx_state,
+                                &tx_sock,
+                                target,
+                                payload.len(),
+                                timeout,
+                                None,
+                            )
+                            .await
+                    }
+                    HandshakeTx::SendAddressChange => {
+                        self.send_address_change(
+                            &mut tx_state,
+                            &sock,
+                            &target, // address changes are sent to the existing address
+                            sockaddr_to_v4(tx_sock.local_addr()?)?,
+                            &mut tx_target,
+                            timeout,
+                            retries,
+                            None,
+                        )
+                        .await?
+                    }
+                    HandshakeTx::Transmit => {
+                        self.transmit_data(
+                            &mut tx_state,
+                            &tx_sock,
+                            tx_target.expect("tx target not set"),
+                            payload,
+                            segment_size,
+                            Self::KEEP_ALIVE_INTERVAL,
+                            None,
+                        )
+                        .await?
+                    }
+
+                    HandshakeTx::Complete => {
+                        break;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .await;
+        tx_sock.release().await;
+
+        res.map(|_| payload.len())
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let (addr, payload) = self
+            .recv_payload(sock, timeout, retries, usize::MAX)
+            .await?;
+
+        Ok((addr, payload.into_bytes()?))
+    }
+
+    async fn recv_payload(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+        memory_cap: usize,
+    ) -> io::Result<(SocketAddrV4, ReceivedPayload)> {
+        let sock = sock.datagram()?;
+
+        let mut rx_data = PayloadBuffer::new(memory_cap);
+
+        let source = self
+            .receive_payload(sock, timeout, retries, &mut rx_data, None)
+            .await?;
+
+        Ok((source, rx_data.finish()?))
+    }
+
+    fn reaped_transfers(&self) -> u64 {
+        self.reaped.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for FaultyHandshakeProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<usize> {
+        let sock = sock.datagram()?;
+
+        // first we will switch target sockets so that we don't block the main process
+        // from receiving requests
+
+        // state control variable
+        let mut tx_state = HandshakeTx::default();
+        let mut tx_target: Option<SocketAddrV4> = None;
+        let mut segment_size = HandshakeProto::MAX_PACKET_PAYLOAD_SIZE;
+
+        let tx_sock = self.inner.sockets.take(sock, timeout).await?;
+
+        let res: io::Result<()> = async {
+            loop {
+                log::debug!("tx state: {:?}", tx_state);
+
+                match tx_state {
+                    HandshakeTx::Probe => {
+                        segment_size = self
+                            .inner
+                            .probe_datagram_size(
+                                &mut tx_state,
+                                &tx_sock,
+                                target,
+                                payload.len(),
+                                timeout,
+                                Some(self.frac),
+                            )
+                            .await
+                    }
+                    HandshakeTx::SendAddressChange => {
+                        self.inner
+                            .send_address_change(
+                                &mut tx_state,
+                                &sock,
+                                &target, // address changes are sent to the existing address
+                                sockaddr_to_v4(tx_sock.local_addr()?)?,
+                                &mut tx_target,
+                                timeout,
+                                retries,
+                                Some(self.frac),
+                            )
+                            .await?
+                    }
+                    HandshakeTx::Transmit => {
+                        self.inner
+                            .transmit_data(
+                                &mut tx_state,
+                                &tx_sock,
+                                tx_target.expect("tx target not set"),
+                                payload,
+                                segment_size,
+                                HandshakeProto::KEEP_ALIVE_INTERVAL,
+                                Some(self.frac),
+                            )
+                            .await?
+                    }
+
+                    HandshakeTx::Complete => {
+                        break;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .await;
+        tx_sock.release().await;
+
+        res.map(|_| payload.len())
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let (addr, payload) = self
+            .recv_payload(sock, timeout, retries, usize::MAX)
+            .await?;
+
+        Ok((addr, payload.into_bytes()?))
+    }
+
+    async fn recv_payload(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+        memory_cap: usize,
+    ) -> io::Result<(SocketAddrV4, ReceivedPayload)> {
+        let sock = sock.datagram()?;
+
+        let mut rx_data = PayloadBuffer::new(memory_cap);
+
+        let source = self
+            .inner
+            .receive_payload(sock, timeout, retries, &mut rx_data, Some(self.frac))
+            .await?;
+
+        Ok((source, rx_data.finish()?))
+    }
+
+    fn reaped_transfers(&self) -> u64 {
+        self.inner.reaped_transfers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{middleware::PausedClock, RemoteMethodSignature, RemotelyInvocable};
+
+    use super::*;
+
+    /// Time the paused clock moves by whenever a test is waiting
+    const STEP: Duration = Duration::from_millis(5);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Packet {
+        inner: Vec<u8>,
+    }
+
+    impl RemoteMethodSignature for Packet {
+        fn remote_method_signature() -> &'static [u8] {
+            "Packet".as_bytes()
+        }
+    }
+
+    /// Check out the bug during message transmissions.
+    /// Transmissions with a '#' return an error.
+    #[tokio::test]
+    async fn test_handshake_proto() {
+        let packet = Packet {
+            inner: "hello # world".as_bytes().to_vec(),
+        };
+
+        let bytes = packet.invoke_bytes();
+
+        let proto = HandshakeProto::default();
+        let proto_clone = proto.clone();
+
+        let send_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let recv_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+
+        let send_target = sockaddr_to_v4(recv_sock.local_addr().unwrap()).unwrap();
+
+        tokio::spawn(async move {
+            proto_clone
+                .send_bytes(
+                    &send_sock,
+                    send_target,
+                    &bytes,
+                    Duration::from_millis(200),
+                    10,
+                )
+                .await;
+        });
+
+        let (_, data) = proto
+            .recv_bytes(&recv_sock, Duration::from_millis(100), 10)
+            .await
+            .unwrap();
+
+        let res = Packet::process_invocation(&data);
+
+        println!("transmission result: {:?}", res);
+
+        return;
+    }
+
+    /// Payloads spanning multiple segments are probed and received intact.
+    #[tokio::test]
+    async fn test_handshake_proto_segmented() {
+        let payload = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let payload_clone = payload.clone();
+
+        let send_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let recv_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+
+        let send_target = sockaddr_to_v4(recv_sock.local_addr().unwrap()).unwrap();
+
+        let tx_proto = HandshakeProto::default();
+        tokio::spawn(async move {
+            tx_proto
+                .send_bytes(
+                    &send_sock,
+                    send_target,
+                    &payload_clone,
+                    Duration::from_millis(200),
+                    10,
+                )
+                .await
+        });
+
+        let (_, data) = HandshakeProto::default()
+            .recv_bytes(&recv_sock, Duration::from_millis(200), 10)
+            .await
+            .unwrap();
+
+        assert_eq!(data, payload);
+    }
+
+    /// Payloads over the memory cap are spilled to disk and read back intact.
+    #[tokio::test]
+    async fn test_handshake_proto_spill() {
+        let payload = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let payload_clone = payload.clone();
+
+        let send_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let recv_sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+
+        let send_target = sockaddr_to_v4(recv_sock.local_addr().unwrap()).unwrap();
+
+        let tx_proto = HandshakeProto::default();
+        tokio::spawn(async move {
+            tx_proto
+                .send_bytes(
+                    &send_sock,
+                    send_target,
+                    &payload_clone,
+                    Duration::from_millis(200),
+                    10,
+                )
+                .await
+        });
+
+        let (_, received) = HandshakeProto::default()
+            .recv_payload(&recv_sock, Duration::from_millis(200), 10, 50_000)
+            .await
+            .unwrap();
+
+        assert!(received.is_spilled());
+        assert_eq!(received.len(), payload.len());
+        assert_eq!(received.into_bytes().unwrap(), payload);
+    }
+
+    /// Hundreds of transfers reuse a bounded number of sockets, and reused sockets
+    /// do not receive packets from earlier transfers.
+    ///
+    /// Sockets linger for twice the timeout before they are reused, so transfers are
+    /// spaced out to span several lingers.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_socket_reuse_soak() {
+        const LANES: usize = 4;
+        const TRANSFERS: usize = 50;
+        const TIMEOUT: Duration = Duration::from_millis(50);
+        const SPACING: Duration = Duration::from_millis(20);
+
+        let tx_proto = HandshakeProto::default();
+        let rx_proto = HandshakeProto::default();
+
+        let lanes = (0..LANES).map(|lane| {
+            let tx_proto = tx_proto.clone();
+            let rx_proto = rx_proto.clone();
+
+            tokio::spawn(async move {
+                let send_sock = Arc::new(localhost_socket().await);
+                let recv_sock = localhost_socket().await;
+                let target = sockaddr_to_v4(recv_sock.local_addr().unwrap()).unwrap();
+
+                for i in 0..TRANSFERS {
+                    let payload = format!("lane {} transfer {}", lane, i).into_bytes();
+
+                    let tx = {
+                        let (proto, sock, payload) =
+                            (tx_proto.clone(), send_sock.clone(), payload.clone());
+                        tokio::spawn(async move {
+                            proto.send_bytes(&sock, target, &payload, TIMEOUT, 10).await
+                        })
+                    };
+
+                    let (_, data) = rx_proto.recv_bytes(&recv_sock, TIMEOUT, 10).await.unwrap();
+                    tx.await.unwrap().unwrap();
+
+                    assert_eq!(data, payload);
+                    tokio::time::sleep(SPACING).await;
+                }
+            })
+        });
+
+        let lanes = tokio::time::timeout(Duration::from_secs(60), futures::future::join_all(lanes))
+            .await
+            .expect("transfers should not stall");
+        for lane in lanes {
+            lane.unwrap();
+        }
+
+        let pooled = tx_proto.sockets.len().await + rx_proto.sockets.len().await;
+        assert!(
+            pooled < LANES * TRANSFERS / 2,
+            "{} sockets bound for {} transfers",
+            pooled,
+            LANES * TRANSFERS
+        );
+    }
+
+    /// Probing falls back to the minimum segment size when probes are lost.
+    #[tokio::test]
+    async fn test_probe_fallback() {
+        let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        // never responds to probes
+        let sink = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let target = sockaddr_to_v4(sink.local_addr().unwrap()).unwrap();
+
+        let clock = PausedClock::start();
+        let proto = HandshakeProto::default().with_clock(Arc::new(clock.clone()));
+
+        let mut state = HandshakeTx::default();
+        let size = clock
+            .run(
+                STEP,
+                proto.probe_datagram_size(
+                    &mut state,
+                    &sock,
+                    target,
+                    100_000,
+                    Duration::from_millis(200),
+                    None,
+                ),
+            )
+            .await;
+
+        assert_eq!(size, HandshakeProto::MIN_PACKET_PAYLOAD_SIZE);
+        assert!(matches!(state, HandshakeTx::SendAddressChange));
+        // a path that lost every probe is probed again by the next transfer
+        assert!(proto.segment_sizes.lock().await.is_empty());
+
+        // small payloads are not probed
+        let mut state = HandshakeTx::default();
+        let size = proto
+            .probe_datagram_size(&mut state, &sock, target, 10, Duration::from_secs(10), None)
+            .await;
+        assert_eq!(size, HandshakeProto::MIN_PACKET_PAYLOAD_SIZE);
+    }
+
+    /// Probed segment sizes are reused until they expire.
+    #[tokio::test]
+    async fn test_probe_cache() {
+        const LIMIT: usize = 2_000;
+        const TIMEOUT: Duration = Duration::from_millis(200);
+
+        let sock = localhost_socket().await;
+        let peer = localhost_socket().await;
+        let target = sockaddr_to_v4(peer.local_addr().unwrap()).unwrap();
+
+        // acks probes up to a size, counting every probe received
+        let probes = Arc::new(AtomicU64::new(0));
+        let counter = probes.clone();
+        let responder = tokio::spawn(async move {
+            let mut buf = [0_u8; 65535];
+            loop {
+                let (len, addr) = peer.recv_from(&mut buf).await.unwrap();
+                if let Ok(TransmissionPacket::Probe(padding)) = deserialize_primary(&buf[..len]) {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    if padding.len() <= LIMIT {
+                        let ack = TransmissionPacket::ProbeAck(padding.len() as u32);
+                        let ack = serialize_primary(&ack).unwrap();
+                        peer.send_to(&ack, addr).await.unwrap();
+                    }
+                }
+            }
+        });
+
+        let clock = PausedClock::start();
+        let proto = HandshakeProto::default().with_clock(Arc::new(clock.clone()));
+        let probe = |len| {
+            let (proto, sock, clock) = (proto.clone(), &sock, clock.clone());
+            async move {
+                let mut state = HandshakeTx::default();
+                let probe = proto.probe_datagram_size(&mut state, sock, target, len, TIMEOUT, None);
+                clock.run(STEP, probe).await
+            }
+        };
+
+        // a small payload gets through, larger sizes are unknown
+        assert_eq!(probe(1_000).await, 1_000);
+        assert_eq!(probes.load(Ordering::Relaxed), 1);
+        assert_eq!(probe(800).await, 800);
+        assert_eq!(probes.load(Ordering::Relaxed), 1);
+
+        let start = clock.now();
+        let size = probe(10_000).await;
+        assert!(size <= LIMIT - HandshakeProto::PROBE_HEADER_MARGIN);
+        let probed = probes.load(Ordering::Relaxed);
+        assert!(probed > 2);
+
+        // the size is kept from when probing ended, after the lost probes timed out
+        let found = proto.segment_sizes.lock().await[&target];
+        assert!(found.probed >= start + TIMEOUT);
+
+        // larger payloads use the limit found, clones share it
+        assert_eq!(probe(20_000).await, size);
+        assert_eq!(proto.clone().segment_sizes.lock().await.len(), 1);
+        assert_eq!(probes.load(Ordering::Relaxed), probed);
+
+        // the size found expires
+        clock.advance(HandshakeProto::SEGMENT_SIZE_TTL).await;
+        probe(20_000).await;
+        assert!(probes.load(Ordering::Relaxed) > probed);
+
+        responder.abort();
+    }
+
+    async fn localhost_socket() -> UdpSocket {
+        UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap()
+    }
+
+    async fn recv_packet(sock: &UdpSocket) -> (TransmissionPacket, SocketAddrV4) {
+        let mut buf = [0_u8; 65535];
+        let (size, addr) = tokio::time::timeout(Duration::from_secs(1), sock.recv_from(&mut buf))
+            .await
+            .expect("packet not received")
+            .unwrap();
+
+        (
+            deserialize_primary(&buf[..size]).unwrap(),
+            sockaddr_to_v4(addr).unwrap(),
+        )
+    }
+
+    async fn send_packet(sock: &UdpSocket, target: SocketAddrV4, packet: TransmissionPacket) {
+        sock.send_to(&serialize_primary(&packet).unwrap(), target)
+            .await
+            .unwrap();
+    }
+
+    /// tx sends keep-alives while idle and follows rx to its latest address.
+    #[tokio::test]
+    async fn test_tx_keep_alive_address_change() {
+        let tx_sock = localhost_socket().await;
+        let tx_addr = sockaddr_to_v4(tx_sock.local_addr().unwrap()).unwrap();
+        let rx_sock = localhost_socket().await;
+        let rx_addr = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+        // rx after a NAT re-mapping
+        let moved_rx_sock = localhost_socket().await;
+
+        let clock = PausedClock::start();
+        let proto = HandshakeProto::default().with_clock(Arc::new(clock.clone()));
+
+        let tx = tokio::spawn(async move {
+            let mut state = HandshakeTx::Transmit;
+            proto
+                .transmit_data(
+                    &mut state,
+                    &tx_sock,
+                    rx_addr,
+                    b"hello world",
+                    HandshakeProto::MIN_PACKET_PAYLOAD_SIZE,
+                    Duration::from_millis(20),
+                    None,
+                )
+                .await
+                .map(|_| state)
+        });
+
+        // keep-alives are only sent once the clock moves
+        let mut buf = [0_u8; 64];
+        let early = tokio::time::timeout(Duration::from_millis(50), rx_sock.recv_from(&mut buf));
+        assert!(early.await.is_err());
+
+        let state = clock
+            .run(STEP, async {
+                let (packet, _) = recv_packet(&rx_sock).await;
+                assert!(matches!(packet, TransmissionPacket::KeepAlive));
+
+                send_packet(&moved_rx_sock, tx_addr, TransmissionPacket::Seq(0)).await;
+                let packet = loop {
+                    match recv_packet(&moved_rx_sock).await {
+                        (TransmissionPacket::KeepAlive, _) => continue,
+                        (packet, _) => break packet,
+                    }
+                };
+                assert!(matches!(
+                    packet,
+                    TransmissionPacket::Data { seq: 0, last: true, ref data, .. }
+                        if data == b"hello world"
+                ));
+
+                send_packet(&moved_rx_sock, tx_addr, TransmissionPacket::Complete).await;
+                tx.await.unwrap().unwrap()
+            })
+            .await;
+        assert!(matches!(state, HandshakeTx::Complete));
+    }
+
+    /// Transfers abandoned by tx after the address change are reaped, whether tx goes
+    /// silent or keeps the transfer alive past its deadline.
+    #[tokio::test]
+    async fn test_rx_reaps_abandoned_transfers() {
+        let clock = PausedClock::start();
+        let rx_proto = HandshakeProto::default()
+            .with_clock(Arc::new(clock.clone()))
+            .with_transfer_limits(TransferLimits {
+                deadline: Duration::from_secs(60),
+                idle_timeout: Duration::from_secs(10),
+            });
+        let rx_sock = localhost_socket().await;
+        let rx_addr = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+        let tx_sock = Arc::new(localhost_socket().await);
+        let tx_addr = sockaddr_to_v4(tx_sock.local_addr().unwrap()).unwrap();
+
+        // tx starts a transfer, and only sends keep-alives if asked to
+        let abandon = |keep_alive: bool| {
+            let tx_sock = tx_sock.clone();
+            async move {
+                send_packet(
+                    &tx_sock,
+                    rx_addr,
+                    TransmissionPacket::SwitchToAddress(tx_addr),
+                )
+                .await;
+                loop {
+                    let (packet, addr) = recv_packet(&tx_sock).await;
+                    if keep_alive && matches!(packet, TransmissionPacket::Seq(_)) {
+                        send_packet(&tx_sock, addr, TransmissionPacket::KeepAlive).await;
+                    }
+                }
+            }
+        };
+
+        for (keep_alive, reaped) in [(false, 1), (true, 2)] {
+            let tx = tokio::spawn(abandon(keep_alive));
+            let err = clock
+                .run(
+                    Duration::from_millis(100),
+                    rx_proto.recv_bytes(&rx_sock, Duration::from_secs(1), u8::MAX),
+                )
+                .await
+                .unwrap_err();
+            tx.abort();
+
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(rx_proto.reaped_transfers(), reaped);
+        }
+    }
+
+    /// Segments from other hosts that do not continue the transfer do not keep it alive.
+    #[tokio::test]
+    async fn test_rx_strangers_do_not_keep_alive() {
+        let clock = PausedClock::start();
+        let proto = HandshakeProto::default()
+            .with_clock(Arc::new(clock.clone()))
+            .with_transfer_limits(TransferLimits {
+                deadline: Duration::from_secs(60),
+                idle_timeout: Duration::from_secs(1),
+            });
+        let rx_sock = localhost_socket().await;
+        let rx_addr = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+        let tx_sock = localhost_socket().await;
+        let tx_addr = sockaddr_to_v4(tx_sock.local_addr().unwrap()).unwrap();
+        let stranger = localhost_socket().await;
+
+        // tx is silent, a stranger sends a stray segment for every sequence request
+        let stray = tokio::spawn(async move {
+            loop {
+                recv_packet(&tx_sock).await;
+                let packet = TransmissionPacket::Data {
+                    seq: 1,
+                    hash: hash_primary(b"stray"),
+                    data: b"stray".to_vec(),
+                    last: true,
+                };
+                send_packet(&stranger, rx_addr, packet).await;
+            }
+        });
+
+        let mut state = HandshakeRx::Receive;
+        let mut data = PayloadBuffer::new(usize::MAX);
+        let err = clock
+            .run(
+                Duration::from_millis(50),
+                proto.receive(
+                    &mut state,
+                    &rx_sock,
+                    tx_addr,
+                    &mut data,
+                    Duration::from_millis(200),
+                    u8::MAX,
+                    None,
+                ),
+            )
+            .await
+            .unwrap_err();
+        stray.abort();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(proto.reaped_transfers(), 1);
+    }
+
+    /// rx skips keep-alives and sends sequence requests to the latest tx address.
+    #[tokio::test]
+    async fn test_rx_address_change() {
+        let rx_sock = localhost_socket().await;
+        let rx_addr = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+        let tx_sock = localhost_socket().await;
+        let tx_addr = sockaddr_to_v4(tx_sock.local_addr().unwrap()).unwrap();
+        // tx after a NAT re-mapping
+        let moved_tx_sock = localhost_socket().await;
+
+        let rx = tokio::spawn(async move {
+            let mut state = HandshakeRx::Receive;
+            let mut data = PayloadBuffer::new(usize::MAX);
+            HandshakeProto::default()
+                .receive(
+                    &mut state,
+                    &rx_sock,
+                    tx_addr,
+                    &mut data,
+                    Duration::from_millis(200),
+                    5,
+                    None,
+                )
+                .await
+                .and_then(|_| data.finish()?.into_bytes())
+        });
+
+        let segments: [&[u8]; 2] = [b"hello ", b"world"];
+
+        let (packet, _) = recv_packet(&tx_sock).await;
+        assert!(matches!(packet, TransmissionPacket::Seq(0)));
+        send_packet(&tx_sock, rx_addr, TransmissionPacket::KeepAlive).await;
+        send_packet(
+            &moved_tx_sock,
+            rx_addr,
+            TransmissionPacket::Data {
+                seq: 0,
+                hash: hash_primary(&segments[0]),
+                data: segments[0].to_vec(),
+                last: false,
+            },
+        )
+        .await;
+
+        let (packet, _) = recv_packet(&moved_tx_sock).await;
+        assert!(matches!(packet, TransmissionPacket::Seq(1)));
+        send_packet(
+            &moved_tx_sock,
+            rx_addr,
+            TransmissionPacket::Data {
+                seq: 1,
+                hash: hash_primary(&segments[1]),
+                data: segments[1].to_vec(),
+                last: true,
+            },
+        )
+        .await;
+
+        let data = rx.await.unwrap().unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    /// rx drops packets from hosts other than tx, which cannot redirect or end the transfer.
+    #[tokio::test]
+    async fn test_rx_drops_strangers() {
+        let rx_sock = localhost_socket().await;
+        let rx_addr = sockaddr_to_v4(rx_sock.local_addr().unwrap()).unwrap();
+        let tx_sock = localhost_socket().await;
+        let tx_addr = sockaddr_to_v4(tx_sock.local_addr().unwrap()).unwrap();
+        let stranger = localhost_socket().await;
+
+        let clock = PausedClock::start();
+        let proto = HandshakeProto::default().with_clock(Arc::new(clock.clone()));
+
+        let rx = tokio::spawn(async move {
+            let mut state = HandshakeRx::Receive;
+            let mut data = PayloadBuffer::new(usize::MAX);
+            proto
+                .receive(
+                    &mut state,
+                    &rx_sock,
+                    tx_addr,
+                    &mut data,
+                    Duration::from_millis(200),
+                    5,
+                    None,
+                )
+                .await
+                .and_then(|_| data.finish()?.into_bytes())
+        });
+
+        let (packet, _) = recv_packet(&tx_sock).await;
+        assert!(matches!(packet, TransmissionPacket::Seq(0)));
+        send_packet(&stranger, rx_addr, TransmissionPacket::KeepAlive).await;
+        send_packet(&stranger, rx_addr, TransmissionPacket::Complete).await;
+        // segments that do not continue the transfer do not move tx either
+        for (seq, hash) in [(1, hash_primary(b"stray")), (0, hash_primary(b"other"))] {
+            let packet = TransmissionPacket::Data {
+                seq,
+                hash,
+                data: b"stray".to_vec(),
+                last: true,
+            };
+            send_packet(&stranger, rx_addr, packet).await;
+        }
+
+        // sequence requests still go to tx
+        let (packet, _) = clock.run(STEP, recv_packet(&tx_sock)).await;
+        assert!(matches!(packet, TransmissionPacket::Seq(0)));
+        send_packet(
+            &tx_sock,
+            rx_addr,
+            TransmissionPacket::Data {
+                seq: 0,
+                hash: hash_primary(b"hello"),
+                data: b"hello".to_vec(),
+                last: true,
+            },
+        )
+        .await;
+
+        let data = rx.await.unwrap().unwrap();
+        assert_eq!(data, b"hello");
+
+        let mut buf = [0_u8; 64];
+        let received =
+            tokio::time::timeout(Duration::from_millis(50), stranger.recv_from(&mut buf));
+        assert!(received.await.is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Construction of protocols by name.
+//!
+//! Names are used by command line arguments, and are the same on the client and remote.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io;
+use std::sync::Arc;
+
+use crate::middleware::{FailureRate, PortRange, Semantics, TransferLimits, TransmissionProtocol};
+
+use super::{
+    AdaptiveProto, DefaultProto, FaultyDefaultProto, FaultyHandshakeProto, FaultyRequestAckProto,
+    HandshakeProto, RequestAckProto, TcpProto,
+};
+
+/// A protocol shared between a context manager or dispatcher and its clones
+pub type SharedProtocol = Arc<dyn TransmissionProtocol + Send + Sync>;
+
+/// Constructs a protocol from options
+type Constructor = Arc<dyn Fn(&ProtocolOptions) -> SharedProtocol + Send + Sync>;
+
+/// Options given to protocol constructors. Protocols ignore options they do not support.
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolOptions {
+    /// Simulate a transmission failure every 1 in N attempts
+    pub failure_rate: Option<FailureRate>,
+
+    /// Ports that sockets for data transfers are bound to
+    pub data_ports: Option<PortRange>,
+
+    /// Limits after which incoming transfers are reaped
+    pub transfer_limits: TransferLimits,
+}
+
+#[derive(Clone)]
+struct Registered {
+    constructor: Constructor,
+
+    /// Semantics the dispatcher must provide along with the protocol
+    semantics: Semantics,
+}
+
+/// Maps protocol names to their constructors.
+///
+/// The default registry contains the built-in protocols, named after the invocation
+/// semantics they provide: `maybe`, `at-least-once`, `at-most-once` and `adaptive`.
+#[derive(Clone)]
+pub struct ProtocolRegistry {
+    protocols: BTreeMap<String, Registered>,
+}
+
+impl Default for ProtocolRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        registry.register("maybe", Semantics::Maybe, |opts| match opts.failure_rate {
+            Some(frac) => Arc::new(FaultyDefaultProto::from_frac(frac)),
+            None => Arc::new(DefaultProto),
+        });
+        registry.register("at-least-once", Semantics::AtLeastOnce, |opts| {
+            match opts.failure_rate {
+                Some(frac) => Arc::new(FaultyRequestAckProto::from_frac(frac)),
+                None => Arc::new(RequestAckProto),
+            }
+        });
+        registry.register("at-most-once", Semantics::AtMostOnce, |opts| {
+            let (ports, limits) = (opts.data_ports.clone(), opts.transfer_limits);
+            match opts.failure_rate {
+                Some(frac) => Arc::new(
+                    FaultyHandshakeProto::from_frac(frac)
+                        .with_data_ports(ports)
+                        .with_transfer_limits(limits),
+                ),
+                None => Arc::new(
+                    HandshakeProto::default()
+                        .with_data_ports(ports)
+                        .with_transfer_limits(limits),
+                ),
+            }
+        });
+        registry.register("adaptive", Semantics::AtMostOnce, |opts| {
+            let (ports, limits) = (opts.data_ports.clone(), opts.transfer_limits);
+            match opts.failure_rate {
+                Some(frac) => Arc::new(
+                    AdaptiveProto::faulty(frac)
+                        .with_data_ports(ports)
+                        .with_transfer_limits(limits),
+                ),
+                None => Arc::new(
+                    AdaptiveProto::default()
+                        .with_data_ports(ports)
+                        .with_transfer_limits(limits),
+                ),
+            }
+        });
+        // the OS retransmits lost packets, so there are no failures to simulate
+        registry.register("tcp", Semantics::AtMostOnce, |_| {
+            Arc::new(TcpProto::default())
+        });
+
+        registry
+    }
+}
+
+impl Debug for ProtocolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl ProtocolRegistry {
+    /// A registry without any protocols
+    pub fn empty() -> Self {
+        Self {
+            protocols: Default::default(),
+        }
+    }
+
+    /// Register a protocol under a name, replacing any protocol with the same name.
+    ///
+    /// `semantics` are the semantics the dispatcher uses with the protocol.
+    pub fn register<F>(&mut self, name: &str, semantics: Semantics, constructor: F)
+    where
+        F: Fn(&ProtocolOptions) -> SharedProtocol + Send + Sync + 'static,
+    {
+        self.protocols.insert(
+            name.to_string(),
+            Registered {
+                constructor: Arc::new(constructor),
+                semantics,
+            },
+        );
+    }
+
+    /// Construct a registered protocol.
+    pub fn build(&self, name: &str, options: &ProtocolOptions) -> io::Result<SharedProtocol> {
+        match self.protocols.get(name) {
+            Some(p) => Ok((p.constructor)(options)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown protocol {:?}", name),
+            )),
+        }
+    }
+
+    /// Semantics the dispatcher uses with a registered protocol.
+    pub fn semantics(&self, name: &str) -> Option<Semantics> {
+        self.protocols.get(name).map(|p| p.semantics)
+    }
+
+    /// Names of registered protocols, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.protocols.keys().map(|k| k.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_registry() {
+        let mut registry = ProtocolRegistry::default();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["adaptive", "at-least-once", "at-most-once", "maybe", "tcp"]
+        );
+
+        let faulty = ProtocolOptions {
+            failure_rate: Some(FailureRate(10)),
+            ..Default::default()
+        };
+        let proto = registry.build("at-least-once", &faulty).unwrap();
+        assert_eq!(
+            proto.to_string(),
+            FaultyRequestAckProto::from_frac(FailureRate(10)).to_string()
+        );
+        let proto = registry.build("maybe", &Default::default()).unwrap();
+        assert_eq!(proto.to_string(), DefaultProto.to_string());
+
+        assert_eq!(
+            registry.semantics("at-most-once"),
+            Some(Semantics::AtMostOnce)
+        );
+        assert_eq!(registry.semantics("maybe"), Some(Semantics::Maybe));
+        assert!(registry.build("unknown", &Default::default()).is_err());
+
+        registry.register("maybe", Semantics::AtLeastOnce, |_| {
+            Arc::new(RequestAckProto)
+        });
+        assert_eq!(registry.semantics("maybe"), Some(Semantics::AtLeastOnce));
+        assert_eq!(
+            registry
+                .build("maybe", &Default::default())
+                .unwrap()
+                .to_string(),
+            RequestAckProto.to_string()
+        );
+    }
+}
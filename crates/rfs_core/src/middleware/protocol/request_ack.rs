@@ -0,0 +1,250 @@
+//! Module for [RequestAckProto]
+
+use std::fmt::Display;
+use std::{io, net::SocketAddrV4, time::Duration};
+
+use async_trait::async_trait;
+use futures::FutureExt;
+
+use crate::middleware::{
+    deserialize_primary, hash_primary, probability_frac, report_retry, serialize_primary,
+    sockaddr_to_v4, Endpoint, FailureRate, RetryReason, TransmissionPacket, TransmissionProtocol,
+    BYTE_BUF_SIZE,
+};
+
+/// A simple version of [HandshakeProto]. This protocol is compatible with [FaultyRequestAckProto].
+///
+/// Every sent item needs an ack back.
+#[derive(Clone, Debug, Default)]
+pub struct RequestAckProto;
+
+impl Display for RequestAckProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &self)
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for RequestAckProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        mut retries: u8,
+    ) -> io::Result<usize>
+// where
+    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync,
+    {
+        let sock = sock.datagram()?;
+
+        let mut res: io::Result<usize> = Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection timed out",
+        ));
+        let mut attempt = 0;
+
+        while retries != 0 {
+            log::debug!("sending data to target");
+
+            // occasionally err
+            let send_size = sock.send_to(payload, &target).await?;
+
+            let mut buf = [0_u8; 100];
+
+            tokio::select! {
+                biased;
+
+                recv_res = async {
+                    sock.recv(&mut buf).await
+                }.fuse() => {
+                    log::debug!("response received from target");
+
+                    let recv_size = recv_res?;
+                    let slice = &buf[..recv_size];
+
+                    let de: TransmissionPacket = deserialize_primary(slice).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "deserialization failed"))?;
+                    let hash = if let TransmissionPacket::Ack(h) = de {
+                        h
+                    } else {
+                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "expected Ack"));
+                        break;
+                    };
+
+                    if hash == hash_primary(&payload) {
+                        res = Ok(send_size);
+                    } else {
+                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "Ack does not match"));
+                    }
+
+                    break;
+                },
+                _ = async {
+                    tokio::time::sleep(timeout).await;
+                }.fuse() => {
+                    retries -= 1;
+                    log::debug!("response timed out. retries remaining: {}", retries);
+                    attempt += 1;
+                    report_retry(attempt, timeout, RetryReason::Timeout);
+
+                    continue;
+                }
+            }
+        }
+
+        res
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let sock = sock.datagram()?;
+
+        let mut recv_buf = [0_u8; BYTE_BUF_SIZE];
+
+        let (size, addr) = sock.recv_from(&mut recv_buf).await?;
+
+        let hash = hash_primary(&&recv_buf[..size]);
+        let resp = TransmissionPacket::Ack(hash);
+
+        let ser_resp = serialize_primary(&resp).expect("serialization should not fail");
+        sock.send_to(&ser_resp, addr).await?;
+
+        Ok((sockaddr_to_v4(addr)?, recv_buf[..size].to_vec()))
+    }
+}
+
+/// A faulty version that is compatible with [RequestAckProto].
+///
+/// This protocol may drop packets on transmission.
+/// The packet drop probabilty is specified in the const generic.
+///
+/// The proto will fail to transmit every 1 in `FRAC` invocations on average.
+#[derive(Clone, Debug)]
+pub struct FaultyRequestAckProto {
+    frac: u32,
+}
+
+impl FaultyRequestAckProto {
+    pub fn from_frac(frac: FailureRate) -> Self {
+        Self { frac: frac.0 }
+    }
+}
+
+impl Display for FaultyRequestAckProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FaultyRequestAckProto")
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for FaultyRequestAckProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        mut retries: u8,
+    ) -> io::Result<usize>
+// where
+    //     A: ToSocketAddrs + std::marker::Send + std::marker::Sync,
+    {
+        let sock = sock.datagram()?;
+
+        let mut res: io::Result<usize> = Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection timed out",
+        ));
+        let mut attempt = 0;
+
+        while retries != 0 {
+            log::debug!("sending data to target");
+
+            // occasionally err
+            let send_size = match probability_frac(self.frac) {
+                true => {
+                    log::error!("simulated packet drop");
+                    payload.len()
+                }
+                false => sock.send_to(payload, &target).await?,
+            };
+
+            let mut buf = [0_u8; 100];
+
+            tokio::select! {
+                biased;
+
+                recv_res = async {
+                    sock.recv(&mut buf).await
+                }.fuse() => {
+                    log::debug!("response received from target");
+
+                    let recv_size = recv_res?;
+                    let slice = &buf[..recv_size];
+
+                    let de: TransmissionPacket = deserialize_primary(slice).unwrap();
+                    let hash = if let TransmissionPacket::Ack(h) = de {
+                        h
+                    } else {
+                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "expected Ack"));
+                        break;
+                    };
+
+                    if hash == hash_primary(&payload) {
+                        res = Ok(send_size);
+                    } else {
+                        res = Err(io::Error::new(io::ErrorKind::InvalidData, "Ack does not match"));
+                    }
+
+                    break;
+                },
+                _ = async {
+                    tokio::time::sleep(timeout).await;
+                }.fuse() => {
+                    retries -= 1;
+                    log::debug!("response timed out. retries remaining: {}", retries);
+                    attempt += 1;
+                    report_retry(attempt, timeout, RetryReason::Timeout);
+
+                    continue;
+                }
+            }
+        }
+
+        res
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        _timeout: Duration,
+        _retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let sock = sock.datagram()?;
+
+        let mut recv_buf = [0_u8; BYTE_BUF_SIZE];
+
+        let (size, addr) = sock.recv_from(&mut recv_buf).await?;
+
+        let hash = hash_primary(&&recv_buf[..size]);
+        let resp = TransmissionPacket::Ack(hash);
+
+        let ser_resp = serialize_primary(&resp).expect("serialization should not fail");
+
+        match probability_frac(self.frac) {
+            true => {
+                log::error!("simulated packet drop");
+            }
+            false => {
+                sock.send_to(&ser_resp, addr).await?;
+            }
+        };
+
+        Ok((sockaddr_to_v4(addr)?, recv_buf[..size].to_vec()))
+    }
+}
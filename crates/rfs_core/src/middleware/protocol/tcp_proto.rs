@@ -0,0 +1,541 @@
+//! Module for [TcpProto]
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{io, net::SocketAddrV4, time::Duration};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::middleware::{
+    report_retry, Endpoint, PayloadBuffer, ReceivedPayload, RetryReason, TransmissionProtocol,
+};
+
+/// Largest payload accepted from a connection
+const MAX_FRAME_SIZE: u64 = 1 << 30;
+
+/// Bytes of a payload read from a connection at a time
+const READ_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Payloads received on a listener that are not yet taken by `recv_bytes`
+const INBOX_CAPACITY: usize = 64;
+
+/// Listeners that are not received on for this long are closed
+const INBOX_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Connections that are not sent on for this long are closed
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// First wait before connecting again to a remote that refused the connection
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Payloads are sent over TCP connections, which the OS delivers reliably and in order.
+///
+/// A UDP socket still names each endpoint. Payloads sent from a socket are received on
+/// a TCP listener bound to the same address, so a remote replies to the socket a request
+/// came from, as with the UDP protocols. Payloads from a socket to the same remote share
+/// a connection, which is opened on first use and closed once idle.
+///
+/// This protocol is compatible only with itself.
+#[derive(Clone, Debug, Default)]
+pub struct TcpProto {
+    inboxes: Arc<Mutex<HashMap<SocketAddr, Arc<Inbox>>>>,
+    connections: Arc<Mutex<Connections>>,
+}
+
+/// Connections by the socket they are sent from and the remote they are sent to
+type Connections = HashMap<(SocketAddr, SocketAddrV4), Arc<Connection>>;
+
+/// Payloads received on the listener of a socket
+#[derive(Debug)]
+struct Inbox {
+    rx: tokio::sync::Mutex<mpsc::Receiver<(SocketAddrV4, ReceivedPayload)>>,
+    /// Bytes of each payload kept in memory before it is spilled to disk
+    memory_cap: Arc<AtomicUsize>,
+    last_used: Mutex<Instant>,
+    accept: JoinHandle<()>,
+}
+
+/// A connection from a socket to a remote
+#[derive(Debug)]
+struct Connection {
+    stream: tokio::sync::Mutex<Option<TcpStream>>,
+    last_used: Mutex<Instant>,
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+impl Display for TcpProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TcpProto")
+    }
+}
+
+impl TcpProto {
+    /// Returns the inbox of a socket, listening on its address if it has none.
+    async fn inbox(&self, sock: &dyn Endpoint, memory_cap: usize) -> io::Result<Arc<Inbox>> {
+        let local = sock.local_addr()?;
+
+        if let Some(inbox) = self.inboxes.lock().expect("lock poisoned").get(&local) {
+            *inbox.last_used.lock().expect("lock poisoned") = Instant::now();
+            inbox.memory_cap.store(memory_cap, Ordering::Relaxed);
+            return Ok(inbox.clone());
+        }
+
+        let listener = TcpListener::bind(local).await?;
+        let (tx, rx) = mpsc::channel(INBOX_CAPACITY);
+        let memory_cap = Arc::new(AtomicUsize::new(memory_cap));
+        let inbox = Arc::new(Inbox {
+            rx: tokio::sync::Mutex::new(rx),
+            memory_cap: memory_cap.clone(),
+            last_used: Mutex::new(Instant::now()),
+            accept: tokio::spawn(accept_connections(listener, tx, memory_cap)),
+        });
+
+        let mut inboxes = self.inboxes.lock().expect("lock poisoned");
+        // sockets of finished invocations are not received on again
+        inboxes.retain(|_, i| {
+            i.last_used.lock().expect("lock poisoned").elapsed() < INBOX_IDLE_TIMEOUT
+        });
+        let inbox = inboxes.entry(local).or_insert(inbox).clone();
+
+        Ok(inbox)
+    }
+
+    /// Returns the connection from a socket to a remote, which may not be open yet.
+    fn connection(&self, local: SocketAddr, target: SocketAddrV4) -> Arc<Connection> {
+        let mut connections = self.connections.lock().expect("lock poisoned");
+        // idle connections are closed when dropped
+        connections.retain(|_, c| {
+            c.last_used.lock().expect("lock poisoned").elapsed() < CONNECTION_IDLE_TIMEOUT
+        });
+
+        let connection = connections
+            .entry((local, target))
+            .or_insert_with(|| {
+                Arc::new(Connection {
+                    stream: tokio::sync::Mutex::new(None),
+                    last_used: Mutex::new(Instant::now()),
+                })
+            })
+            .clone();
+        *connection.last_used.lock().expect("lock poisoned") = Instant::now();
+
+        connection
+    }
+}
+
+/// Accept connections, reading payloads from each until it is closed.
+///
+/// Connections are closed when the listener is.
+async fn accept_connections(
+    listener: TcpListener,
+    tx: mpsc::Sender<(SocketAddrV4, ReceivedPayload)>,
+    memory_cap: Arc<AtomicUsize>,
+) {
+    let mut readers = JoinSet::new();
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("failed to accept connection: {}", e);
+                    tokio::time::sleep(MIN_BACKOFF).await;
+                    continue;
+                }
+            },
+            // closed connections are removed from the set
+            Some(_) = readers.join_next(), if !readers.is_empty() => continue,
+        };
+
+        readers.spawn(read_frames(stream, peer, tx.clone(), memory_cap.clone()));
+    }
+}
+
+/// Read payloads from a connection until the remote closes it.
+///
+/// A connection starts with the port of the socket its payloads are sent from.
+async fn read_frames(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    tx: mpsc::Sender<(SocketAddrV4, ReceivedPayload)>,
+    memory_cap: Arc<AtomicUsize>,
+) {
+    let ip = match peer.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => {
+            log::error!("IPv6 peer {} is not supported", peer);
+            return;
+        }
+    };
+
+    let source = match stream.read_u16().await {
+        Ok(port) => SocketAddrV4::new(ip, port),
+        Err(e) => {
+            log::error!("failed to receive source port from {}: {}", peer, e);
+            return;
+        }
+    };
+
+    loop {
+        match read_frame(&mut stream, memory_cap.load(Ordering::Relaxed)).await {
+            Ok(Some(payload)) => {
+                if tx.send((source, payload)).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("failed to receive payload from {}: {}", source, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Read a payload, keeping at most `memory_cap` bytes of it in memory.
+///
+/// Returns nothing if the remote closed the connection before sending another payload.
+async fn read_frame(
+    stream: &mut TcpStream,
+    memory_cap: usize,
+) -> io::Result<Option<ReceivedPayload>> {
+    let len = match stream.read_u64().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload of {} bytes is too large", len),
+        ));
+    }
+
+    // memory grows with the bytes received, not with the length the remote claims
+    let mut frame = stream.take(len);
+    let mut data = PayloadBuffer::new(memory_cap);
+    let mut chunk = Vec::new();
+    loop {
+        chunk.clear();
+        if (&mut frame)
+            .take(READ_CHUNK_SIZE)
+            .read_to_end(&mut chunk)
+            .await?
+            == 0
+        {
+            break;
+        }
+        data.extend(&chunk)?;
+    }
+
+    if frame.limit() > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "connection closed {} bytes before the end of a payload",
+                frame.limit()
+            ),
+        ));
+    }
+
+    data.finish().map(Some)
+}
+
+/// Checks if a connection is still open.
+///
+/// Remotes never send on connections, so a readable connection was closed by the remote.
+fn is_open(stream: &TcpStream) -> bool {
+    let mut buf = [0_u8; 1];
+    matches!(stream.try_read(&mut buf), Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Connect to a remote, trying again while it refuses connections.
+///
+/// A remote that has not started receiving on its socket refuses connections.
+async fn connect(target: SocketAddrV4, timeout: Duration, retries: u8) -> io::Result<TcpStream> {
+    let deadline = tokio::time::Instant::now() + timeout * (retries as u32 + 1);
+    let mut backoff = MIN_BACKOFF;
+    let mut attempt = 0;
+
+    loop {
+        let res = match tokio::time::timeout_at(deadline, TcpStream::connect(target)).await {
+            Ok(res) => res,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connection to {} timed out", target),
+                ))
+            }
+        };
+
+        match res {
+            Err(e)
+                if e.kind() == io::ErrorKind::ConnectionRefused
+                    && tokio::time::Instant::now() + backoff < deadline =>
+            {
+                attempt += 1;
+                report_retry(attempt, backoff, RetryReason::Timeout);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(timeout);
+            }
+            res => return res,
+        }
+    }
+}
+
+#[async_trait]
+impl TransmissionProtocol for TcpProto {
+    async fn send_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        target: SocketAddrV4,
+        payload: &[u8],
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<usize> {
+        let local = sock.local_addr()?;
+        let connection = self.connection(local, target);
+        let mut stream = connection.stream.lock().await;
+
+        if !stream.as_ref().is_some_and(is_open) {
+            let mut new_stream = connect(target, timeout, retries).await?;
+            new_stream.set_nodelay(true)?;
+            new_stream.write_u16(local.port()).await?;
+            *stream = Some(new_stream);
+        }
+
+        let s = stream.as_mut().expect("connection was opened above");
+        let sent = async {
+            s.write_u64(payload.len() as u64).await?;
+            s.write_all(payload).await
+        }
+        .await;
+        if let Err(e) = sent {
+            // the remote may have received part of the payload
+            *stream = None;
+            return Err(e);
+        }
+
+        Ok(payload.len())
+    }
+
+    async fn recv_bytes(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+    ) -> io::Result<(SocketAddrV4, Vec<u8>)> {
+        let (source, payload) = self
+            .recv_payload(sock, timeout, retries, usize::MAX)
+            .await?;
+
+        Ok((source, payload.into_bytes()?))
+    }
+
+    async fn recv_payload(
+        &self,
+        sock: &dyn Endpoint,
+        timeout: Duration,
+        retries: u8,
+        memory_cap: usize,
+    ) -> io::Result<(SocketAddrV4, ReceivedPayload)> {
+        let inbox = self.inbox(sock, memory_cap).await?;
+        let mut rx = inbox.rx.lock().await;
+
+        match tokio::time::timeout(timeout * (retries as u32 + 1), rx.recv()).await {
+            Ok(Some(received)) => Ok(received),
+            Ok(None) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "listener is no longer accepting connections",
+            )),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no payload received within the timeout",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::UdpSocket;
+
+    use crate::middleware::conformance::ConformanceSuite;
+    use crate::middleware::{sockaddr_to_v4, HandshakeProto};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tcp_conforms() {
+        // a refused connection is retried until the timeouts run out
+        ConformanceSuite::default()
+            .with_timeout(Duration::from_millis(200), 2)
+            .with_max_payload(4_000_000)
+            .run(Arc::new(TcpProto::default()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reply_to_source() {
+        let proto = TcpProto::default();
+        let timeout = Duration::from_millis(200);
+        let client = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server_addr = sockaddr_to_v4(server.local_addr().unwrap()).unwrap();
+
+        // the server starts receiving after the request is sent
+        let request = proto.send_bytes(&client, server_addr, b"ping", timeout, 3);
+        let (sent, (source, data)) = tokio::join!(request, async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            proto.recv_bytes(&server, timeout, 3).await.unwrap()
+        });
+        assert_eq!(sent.unwrap(), 4);
+        assert_eq!(data, b"ping");
+        assert_eq!(SocketAddr::from(source), client.local_addr().unwrap());
+
+        let reply = proto.send_bytes(&server, source, b"pong", timeout, 3);
+        let (sent, received) = tokio::join!(reply, proto.recv_bytes(&client, timeout, 3));
+        sent.unwrap();
+        assert_eq!(received.unwrap().1, b"pong");
+
+        assert_eq!(
+            proto
+                .recv_bytes(&client, timeout, 0)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reuse_connection() {
+        let proto = TcpProto::default();
+        let timeout = Duration::from_millis(200);
+        let client = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server_addr = sockaddr_to_v4(server.local_addr().unwrap()).unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let mut streams = Vec::new();
+        for data in [b"one", b"two"] {
+            let request = proto.send_bytes(&client, server_addr, data, timeout, 3);
+            let (sent, received) = tokio::join!(request, proto.recv_bytes(&server, timeout, 3));
+            sent.unwrap();
+            assert_eq!(received.unwrap().1, data);
+
+            let connection = proto.connection(client_addr, server_addr);
+            let stream = connection.stream.lock().await;
+            streams.push(stream.as_ref().unwrap().local_addr().unwrap());
+        }
+        assert_eq!(streams[0], streams[1]);
+
+        // connections closed by the remote are opened again
+        proto.inboxes.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let request = proto.send_bytes(&client, server_addr, b"three", timeout, 3);
+        let (sent, received) = tokio::join!(request, proto.recv_bytes(&server, timeout, 3));
+        sent.unwrap();
+        assert_eq!(received.unwrap().1, b"three");
+    }
+
+    #[tokio::test]
+    async fn test_frame_limits() {
+        let proto = TcpProto::default();
+        let timeout = Duration::from_millis(200);
+        let client = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server_addr = sockaddr_to_v4(server.local_addr().unwrap()).unwrap();
+
+        // payloads past the memory cap are spilled to disk
+        let data = vec![7_u8; 10_000];
+        let request = proto.send_bytes(&client, server_addr, &data, timeout, 3);
+        let (sent, received) = tokio::join!(request, proto.recv_payload(&server, timeout, 3, 1024));
+        sent.unwrap();
+        let (_, payload) = received.unwrap();
+        assert!(payload.is_spilled());
+        assert_eq!(payload.into_bytes().unwrap(), data);
+
+        // a remote claiming a payload past the limit is disconnected
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        stream.write_u16(1).await.unwrap();
+        stream.write_u64(MAX_FRAME_SIZE + 1).await.unwrap();
+        assert_eq!(stream.read(&mut [0_u8; 1]).await.unwrap(), 0);
+        assert_eq!(
+            proto
+                .recv_bytes(&server, timeout, 0)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    /// An endpoint without a UDP socket
+    #[derive(Debug)]
+    struct Address(SocketAddr);
+
+    impl Endpoint for Address {
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_without_datagrams() {
+        let proto = TcpProto::default();
+        let timeout = Duration::from_millis(200);
+        let free_addr = || {
+            std::net::TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .and_then(|l| l.local_addr())
+                .map(Address)
+                .unwrap()
+        };
+        let (client, server) = (free_addr(), free_addr());
+        let server_addr = sockaddr_to_v4(server.0).unwrap();
+
+        let request = proto.send_bytes(&client, server_addr, b"ping", timeout, 3);
+        let (sent, received) = tokio::join!(request, proto.recv_bytes(&server, timeout, 3));
+        sent.unwrap();
+        let (source, data) = received.unwrap();
+        assert_eq!(data, b"ping");
+        assert_eq!(SocketAddr::from(source), client.0);
+
+        assert_eq!(
+            HandshakeProto::default()
+                .send_bytes(&client, server_addr, b"ping", timeout, 0)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Client-side cache of file contents.
+//!
+//! Blocks of files are kept for a freshness interval, during which reads are served locally.
+//! Past the interval, a block is revalidated against the modification time of the remote file,
+//! and read again if the file was modified.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::Clock;
+
+/// Blocks of remote files, keyed by path and offset.
+#[derive(Debug)]
+pub struct ReadCache {
+    /// Blocks are served without revalidation for this long
+    freshness: Duration,
+
+    files: HashMap<String, CachedFile>,
+
+    clock: Arc<dyn Clock>,
+}
+
+/// Cached blocks of a file
+#[derive(Debug)]
+struct CachedFile {
+    /// Modification time of the remote file when the blocks were read
+    modified: u64,
+
+    /// Last time the modification time was checked
+    validated: Instant,
+
+    /// Blocks, by offset
+    blocks: BTreeMap<usize, CachedBlock>,
+}
+
+#[derive(Debug)]
+struct CachedBlock {
+    data: Vec<u8>,
+
+    /// The block reaches the end of the file
+    eof: bool,
+}
+
+/// Result of looking up a range of a file in a [ReadCache]
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheLookup {
+    /// The range is cached and fresh
+    Fresh(Vec<u8>),
+
+    /// The range is cached, but must be revalidated against the modification time of the file
+    Stale { data: Vec<u8>, modified: u64 },
+
+    /// The range is not cached
+    Missing,
+}
+
+impl CachedBlock {
+    /// Returns the first `len` bytes of the block, or all of them if `len` is `None`.
+    ///
+    /// Returns `None` if the block does not hold the range.
+    fn range(&self, len: Option<usize>) -> Option<&[u8]> {
+        match len {
+            Some(l) if l <= self.data.len() => Some(&self.data[..l]),
+            _ if self.eof => Some(&self.data),
+            _ => None,
+        }
+    }
+}
+
+impl ReadCache {
+    /// Create an empty cache that serves blocks for `freshness`, timed by `clock`.
+    pub fn new(freshness: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            freshness,
+            files: Default::default(),
+            clock,
+        }
+    }
+
+    /// Time that blocks are served for without revalidation
+    pub fn freshness(&self) -> Duration {
+        self.freshness
+    }
+
+    /// Look up `len` bytes of a file from `offset`, or the rest of the file if `len` is `None`.
+    pub fn lookup(&self, path: &str, offset: usize, len: Option<usize>) -> CacheLookup {
+        let file = match self.files.get(path) {
+            Some(f) => f,
+            None => return CacheLookup::Missing,
+        };
+
+        let data = match file.blocks.get(&offset).and_then(|b| b.range(len)) {
+            Some(d) => d,
+            None => return CacheLookup::Missing,
+        };
+
+        match self.clock.elapsed(file.validated) < self.freshness {
+            true => CacheLookup::Fresh(data.to_vec()),
+            false => CacheLookup::Stale {
+                data: data.to_vec(),
+                modified: file.modified,
+            },
+        }
+    }
+
+    /// Record the current modification time of a file.
+    ///
+    /// Blocks of the file stay fresh for another interval if it was not modified,
+    /// and are removed otherwise. Returns `true` if the blocks were kept.
+    pub fn revalidate(&mut self, path: &str, modified: u64) -> bool {
+        let file = match self.files.get_mut(path) {
+            Some(f) => f,
+            None => return false,
+        };
+
+        if file.modified != modified {
+            self.files.remove(path);
+            return false;
+        }
+
+        file.validated = self.clock.now();
+        true
+    }
+
+    /// Add a block read from a file with the given modification time.
+    ///
+    /// `len` is the length that was requested, which the block is shorter than
+    /// at the end of the file. Blocks from an earlier modification time are removed.
+    pub fn insert(
+        &mut self,
+        path: &str,
+        offset: usize,
+        len: Option<usize>,
+        data: Vec<u8>,
+        modified: u64,
+    ) {
+        let now = self.clock.now();
+        let file = self
+            .files
+            .entry(path.to_string())
+            .or_insert_with(|| CachedFile {
+                modified,
+                validated: now,
+                blocks: Default::default(),
+            });
+
+        if file.modified != modified {
+            file.modified = modified;
+            file.blocks.clear();
+        }
+        file.validated = now;
+
+        let eof = len.is_none_or(|l| data.len() < l);
+        file.blocks.insert(offset, CachedBlock { data, eof });
+    }
+
+    /// Remove the blocks of a file, e.g. after writing to it.
+    pub fn invalidate(&mut self, path: &str) {
+        self.files.remove(path);
+    }
+
+    /// Number of cached blocks, of all files
+    pub fn len(&self) -> usize {
+        self.files.values().map(|f| f.blocks.len()).sum()
+    }
+
+    /// Checks if no blocks are cached
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::PausedClock;
+
+    #[tokio::test]
+    async fn test_freshness() {
+        let clock = PausedClock::start();
+        let mut cache = ReadCache::new(Duration::from_secs(10), Arc::new(clock.clone()));
+
+        cache.insert("file", 0, Some(4), b"hell".to_vec(), 1);
+        cache.insert("file", 4, Some(4), b"o".to_vec(), 1);
+
+        assert_eq!(
+            cache.lookup("file", 0, Some(2)),
+            CacheLookup::Fresh(b"he".to_vec())
+        );
+        // blocks are not joined
+        assert_eq!(cache.lookup("file", 0, Some(5)), CacheLookup::Missing);
+        assert_eq!(cache.lookup("file", 0, None), CacheLookup::Missing);
+        // the end of the file is known
+        assert_eq!(
+            cache.lookup("file", 4, Some(100)),
+            CacheLookup::Fresh(b"o".to_vec())
+        );
+
+        clock.advance(Duration::from_secs(10)).await;
+        assert_eq!(
+            cache.lookup("file", 0, Some(4)),
+            CacheLookup::Stale {
+                data: b"hell".to_vec(),
+                modified: 1
+            }
+        );
+
+        assert!(cache.revalidate("file", 1));
+        assert!(matches!(
+            cache.lookup("file", 0, Some(4)),
+            CacheLookup::Fresh(_)
+        ));
+
+        // a modified file is read again
+        assert!(!cache.revalidate("file", 2));
+        assert_eq!(cache.lookup("file", 4, None), CacheLookup::Missing);
+        assert!(cache.is_empty());
+
+        cache.insert("file", 0, None, b"hello".to_vec(), 2);
+        cache.insert("file", 0, None, b"world".to_vec(), 3);
+        assert_eq!(
+            cache.lookup("file", 0, None),
+            CacheLookup::Fresh(b"world".to_vec())
+        );
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate("file");
+        assert!(cache.is_empty());
+    }
+}
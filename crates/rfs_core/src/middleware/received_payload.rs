@@ -0,0 +1,286 @@
+//! Received payloads that may be spilled to disk.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Default number of bytes a receiver keeps in memory before spilling to disk.
+pub const DEFAULT_MEMORY_CAP: usize = 64 * 1024 * 1024;
+
+/// Used to give each spill file in a process a unique name
+static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Number of names tried for a spill file before giving up
+const SPILL_ATTEMPTS: u32 = 16;
+
+/// A payload received from the remote.
+///
+/// Small payloads are kept in memory. Payloads that exceed the receiver's memory cap
+/// are written to a temporary file, which is removed when the payload is dropped.
+#[derive(Debug)]
+pub enum ReceivedPayload {
+    Memory(Vec<u8>),
+    Spilled(SpillFile),
+}
+
+/// A temporary file containing a received payload.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    /// Offset of the payload in the file
+    offset: u64,
+    len: usize,
+}
+
+/// Accumulates received bytes, spilling to a temporary file once the memory cap is exceeded.
+#[derive(Debug)]
+pub struct PayloadBuffer {
+    memory_cap: usize,
+    memory: Vec<u8>,
+    spill: Option<(SpillFile, BufWriter<File>)>,
+}
+
+impl ReceivedPayload {
+    /// Number of bytes in the payload
+    pub fn len(&self) -> usize {
+        match self {
+            ReceivedPayload::Memory(data) => data.len(),
+            ReceivedPayload::Spilled(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks if the payload was spilled to disk
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, ReceivedPayload::Spilled(_))
+    }
+
+    /// Returns a reader that streams the payload from the start.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send + '_>> {
+        self.reader_at(0)
+    }
+
+    /// Returns a reader that streams the payload from the byte at `start`.
+    pub fn reader_at(&self, start: usize) -> io::Result<Box<dyn Read + Send + '_>> {
+        let start = start.min(self.len());
+
+        Ok(match self {
+            ReceivedPayload::Memory(data) => Box::new(Cursor::new(&data[start..])),
+            ReceivedPayload::Spilled(file) => {
+                let mut reader = File::open(&file.path)?;
+                reader.seek(SeekFrom::Start(file.offset + start as u64))?;
+
+                Box::new(BufReader::new(reader).take((file.len - start) as u64))
+            }
+        })
+    }
+
+    /// Read up to `len` bytes of the payload, from the byte at `start`, into memory
+    pub fn read_range(&self, start: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.reader_at(start)?
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    /// Narrow the payload to `len` bytes from the byte at `start`.
+    ///
+    /// Spilled payloads stay on disk.
+    pub fn section(self, start: usize, len: usize) -> Self {
+        let start = start.min(self.len());
+        let len = len.min(self.len() - start);
+
+        match self {
+            ReceivedPayload::Memory(mut data) => {
+                data.truncate(start + len);
+                data.drain(..start);
+                ReceivedPayload::Memory(data)
+            }
+            ReceivedPayload::Spilled(mut file) => {
+                file.offset += start as u64;
+                file.len = len;
+                ReceivedPayload::Spilled(file)
+            }
+        }
+    }
+
+    /// Read the entire payload into memory
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            ReceivedPayload::Memory(data) => Ok(data),
+            ReceivedPayload::Spilled(_) => self.read_range(0, self.len()),
+        }
+    }
+}
+
+impl SpillFile {
+    /// Create a new empty spill file in the system's temporary directory.
+    ///
+    /// The file must not exist yet, so a file or link planted at its path is never written to.
+    /// On unix, the file is only readable and writable by its owner.
+    fn create() -> io::Result<(Self, File)> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut attempts = 0;
+        let (path, file) = loop {
+            let path = std::env::temp_dir().join(format!(
+                "rfs-spill-{}-{}-{:08x}",
+                std::process::id(),
+                SPILL_COUNTER.fetch_add(1, Ordering::Relaxed),
+                rand::random::<u32>()
+            ));
+
+            match options.open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < SPILL_ATTEMPTS => {
+                    log::warn!("spill file {:?} already exists, trying another name", path);
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        log::debug!("spilling received payload to {:?}", path);
+
+        Ok((
+            Self {
+                path,
+                offset: 0,
+                len: 0,
+            },
+            file,
+        ))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::error!("failed to remove spill file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+impl PayloadBuffer {
+    pub fn new(memory_cap: usize) -> Self {
+        Self {
+            memory_cap,
+            memory: Vec::new(),
+            spill: None,
+        }
+    }
+
+    /// Append data to the buffer
+    pub fn extend(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.spill.is_none() && self.memory.len() + data.len() > self.memory_cap {
+            let (mut file, writer) = SpillFile::create()?;
+            let mut writer = BufWriter::new(writer);
+
+            writer.write_all(&self.memory)?;
+            file.len = self.memory.len();
+            self.memory = Vec::new();
+            self.spill = Some((file, writer));
+        }
+
+        match &mut self.spill {
+            Some((file, writer)) => {
+                writer.write_all(data)?;
+                file.len += data.len();
+            }
+            None => self.memory.extend_from_slice(data),
+        }
+
+        Ok(())
+    }
+
+    /// Complete the payload, flushing any spilled data to disk
+    pub fn finish(self) -> io::Result<ReceivedPayload> {
+        match self.spill {
+            Some((file, mut writer)) => {
+                writer.flush()?;
+                Ok(ReceivedPayload::Spilled(file))
+            }
+            None => Ok(ReceivedPayload::Memory(self.memory)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_over_cap() {
+        let data = (0..=255_u8).cycle().take(1000).collect::<Vec<_>>();
+
+        let mut buffer = PayloadBuffer::new(100);
+        for chunk in data.chunks(30) {
+            buffer.extend(chunk).unwrap();
+        }
+        let payload = buffer.finish().unwrap();
+
+        assert!(payload.is_spilled());
+        assert_eq!(payload.len(), data.len());
+
+        let path = match &payload {
+            ReceivedPayload::Spilled(file) => file.path().clone(),
+            ReceivedPayload::Memory(_) => unreachable!(),
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut streamed = Vec::new();
+        payload
+            .reader()
+            .unwrap()
+            .read_to_end(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed, data);
+
+        assert_eq!(payload.into_bytes().unwrap(), data);
+        assert!(!path.exists());
+
+        // payloads under the cap stay in memory
+        let mut buffer = PayloadBuffer::new(100);
+        buffer.extend(&data[..100]).unwrap();
+        let payload = buffer.finish().unwrap();
+        assert!(!payload.is_spilled());
+        assert_eq!(payload.into_bytes().unwrap(), &data[..100]);
+    }
+
+    #[test]
+    fn test_section() {
+        let data = (0..=255_u8).cycle().take(1000).collect::<Vec<_>>();
+
+        for memory_cap in [100, usize::MAX] {
+            let mut buffer = PayloadBuffer::new(memory_cap);
+            buffer.extend(&data).unwrap();
+            let payload = buffer.finish().unwrap();
+
+            assert_eq!(payload.read_range(990, 100).unwrap(), &data[990..]);
+
+            let section = payload.section(100, 500);
+            assert_eq!(section.len(), 500);
+            assert_eq!(section.read_range(10, 20).unwrap(), &data[110..130]);
+            assert_eq!(section.into_bytes().unwrap(), &data[100..600]);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Structured reporting of retry and timeout decisions made by transmission protocols.
+//!
+//! Protocols report a [RetryEvent] every time they retransmit or re-request data.
+//! Events are delivered to the [RetryObserver] of the current task, set with [observe_retries].
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use serde::Serialize;
+
+/// Why a protocol decided to retry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum RetryReason {
+    /// No response was received within the timeout
+    Timeout,
+
+    /// A response was received, but it could not be used
+    InvalidResponse,
+
+    /// The receiver requested a packet to be sent again
+    Retransmit,
+}
+
+/// A single retry decision
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetryEvent {
+    /// Attempt number of the retry, starting from 1
+    pub attempt: u32,
+
+    /// Time spent waiting before deciding to retry
+    pub wait: Duration,
+
+    pub reason: RetryReason,
+}
+
+/// Progress of an invocation, sent to subscribers of a [super::ContextManager]
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvokeProgress {
+    /// The invocation is being retried
+    Retrying {
+        /// Attempt number of the retry, starting from 1
+        attempt: u32,
+
+        /// Number of retries before the invocation fails
+        max_attempts: u32,
+
+        /// Time left before the invocation times out
+        remaining: Duration,
+
+        reason: RetryReason,
+    },
+
+    /// An invocation that was retried has completed, successfully or not
+    Finished,
+}
+
+/// Receives retry events reported by protocols.
+pub trait RetryObserver: Send + Sync {
+    fn on_retry(&self, event: &RetryEvent);
+}
+
+impl<F> RetryObserver for F
+where
+    F: Fn(&RetryEvent) + Send + Sync,
+{
+    fn on_retry(&self, event: &RetryEvent) {
+        self(event)
+    }
+}
+
+tokio::task_local! {
+    static RETRY_OBSERVER: Arc<dyn RetryObserver>;
+}
+
+/// Run a future, reporting retries made by protocols within it to an observer.
+///
+/// Only retries made in the same task are reported.
+pub async fn observe_retries<F: Future>(observer: Arc<dyn RetryObserver>, future: F) -> F::Output {
+    RETRY_OBSERVER.scope(observer, future).await
+}
+
+/// Returns the observer of the current task, if any.
+pub(crate) fn current_observer() -> Option<Arc<dyn RetryObserver>> {
+    RETRY_OBSERVER.try_with(|observer| observer.clone()).ok()
+}
+
+/// Report a retry to the observer of the current task, if any.
+pub(crate) fn report_retry(attempt: u32, wait: Duration, reason: RetryReason) {
+    let event = RetryEvent {
+        attempt,
+        wait,
+        reason,
+    };
+    log::debug!("{:?}", event);
+
+    let _ = RETRY_OBSERVER.try_with(|observer| observer.on_retry(&event));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_observe_retries() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+
+        // not observed
+        report_retry(1, Duration::ZERO, RetryReason::Timeout);
+
+        observe_retries(
+            Arc::new(move |e: &RetryEvent| events_clone.lock().unwrap().push(e.clone())),
+            async {
+                report_retry(1, Duration::from_millis(10), RetryReason::Timeout);
+                report_retry(2, Duration::from_millis(10), RetryReason::InvalidResponse);
+            },
+        )
+        .await;
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].attempt, 2);
+        assert_eq!(events[1].reason, RetryReason::InvalidResponse);
+    }
+}
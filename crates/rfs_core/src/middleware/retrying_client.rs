@@ -0,0 +1,251 @@
+//! Automatic reconnect-and-retry for remote invocations.
+
+use std::{io, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+
+use super::{
+    clock::real_clock, report_retry, Clock, ContextManager, InvokeError, Invoker, RetryReason,
+};
+
+/// How a [RetryingClient] retries failed invocations.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+
+    /// Wait before the first retry. The wait doubles on every subsequent retry.
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the wait between retries
+    pub max_backoff: Duration,
+}
+
+/// Wraps an [Invoker], retrying invocations that fail because the remote could not be reached.
+///
+/// Before each retry, the connection to the remote is re-established.
+/// Use this in place of a [ContextManager] when calling generated clients:
+///
+/// ```ignore
+/// let mut client = RetryingClient::new(ctx, RetryPolicy::default());
+/// let contents = PrimitiveFsOpsClient::read_all(&mut client, path).await?;
+/// ```
+///
+/// Only retry invocations that are safe to repeat. A retried request is sent from
+/// a new socket, so the remote cannot recognize it as a duplicate.
+#[derive(Clone, Debug)]
+pub struct RetryingClient<T = ContextManager> {
+    inner: T,
+    policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the wait before a retry. Retries are numbered from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(retry.saturating_sub(1));
+
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Checks if an invocation error can be fixed by retrying
+    pub fn is_retryable(err: &InvokeError) -> bool {
+        matches!(
+            err,
+            InvokeError::RequestTimedOut | InvokeError::RemoteConnectionFailed
+        )
+    }
+}
+
+impl<T: Invoker> RetryingClient<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            clock: real_clock(),
+        }
+    }
+
+    /// Use a different time source for the wait between retries.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the wrapped invoker
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the wrapped invoker, consuming the client
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+#[async_trait]
+impl<T: Invoker> Invoker for RetryingClient<T> {
+    async fn invoke_raw(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+        let mut attempt = 1;
+
+        loop {
+            let err = match self.inner.invoke_raw(payload.clone()).await {
+                Ok(resp) => return Ok(resp),
+                Err(e) => e,
+            };
+
+            if attempt >= self.policy.max_attempts || !RetryPolicy::is_retryable(&err) {
+                return Err(err);
+            }
+
+            let wait = self.policy.backoff(attempt);
+            log::debug!(
+                "invocation failed with {:?}, retrying in {:?} (attempt {})",
+                err,
+                wait,
+                attempt
+            );
+            if err == InvokeError::RequestTimedOut {
+                report_retry(attempt, wait, RetryReason::Timeout);
+            }
+
+            self.clock.sleep(wait).await;
+            attempt += 1;
+
+            // a failed reconnect is retried along with the invocation
+            if let Err(e) = self.inner.reconnect().await {
+                log::debug!("failed to reconnect: {}", e);
+            }
+        }
+    }
+
+    async fn reconnect(&mut self) -> io::Result<()> {
+        self.inner.reconnect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::middleware::PausedClock;
+
+    /// Returns scripted responses, counting reconnects
+    struct ScriptedInvoker {
+        responses: VecDeque<Result<Vec<u8>, InvokeError>>,
+        invocations: u32,
+        reconnects: u32,
+    }
+
+    impl ScriptedInvoker {
+        fn new(responses: Vec<Result<Vec<u8>, InvokeError>>) -> Self {
+            Self {
+                responses: responses.into(),
+                invocations: 0,
+                reconnects: 0,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Invoker for ScriptedInvoker {
+        async fn invoke_raw(&mut self, _payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+            self.invocations += 1;
+            self.responses.pop_front().expect("no more responses")
+        }
+
+        async fn reconnect(&mut self) -> io::Result<()> {
+            self.reconnects += 1;
+            Ok(())
+        }
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy(5);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.backoff(2), Duration::from_millis(2));
+        assert_eq!(policy.backoff(3), Duration::from_millis(4));
+        assert_eq!(policy.backoff(40), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let inner = ScriptedInvoker::new(vec![
+            Err(InvokeError::RequestTimedOut),
+            Err(InvokeError::RemoteConnectionFailed),
+            Ok(vec![1, 2, 3]),
+        ]);
+        let clock = PausedClock::start();
+        let mut client =
+            RetryingClient::new(inner, RetryPolicy::default()).with_clock(Arc::new(clock.clone()));
+
+        let started = clock.now();
+        let res = clock
+            .run(Duration::from_millis(1), client.invoke_raw(vec![]))
+            .await;
+        assert_eq!(res, Ok(vec![1, 2, 3]));
+        assert_eq!(client.inner().invocations, 3);
+        assert_eq!(client.inner().reconnects, 2);
+
+        // backed off for 100ms, then 200ms
+        let waited = clock.elapsed(started);
+        assert!(
+            (300..310).contains(&waited.as_millis()),
+            "waited {:?}",
+            waited
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        let inner = ScriptedInvoker::new(vec![
+            Err(InvokeError::RequestTimedOut),
+            Err(InvokeError::RequestTimedOut),
+        ]);
+        let mut client = RetryingClient::new(inner, policy(2));
+
+        assert_eq!(
+            client.invoke_raw(vec![]).await,
+            Err(InvokeError::RequestTimedOut)
+        );
+        assert_eq!(client.inner().invocations, 2);
+
+        // errors from the remote are not retried
+        let inner = ScriptedInvoker::new(vec![Err(InvokeError::HandlerNotFound)]);
+        let mut client = RetryingClient::new(inner, policy(3));
+
+        assert_eq!(
+            client.invoke_raw(vec![]).await,
+            Err(InvokeError::HandlerNotFound)
+        );
+        assert_eq!(client.inner().invocations, 1);
+        assert_eq!(client.inner().reconnects, 0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Round-trip time estimation, for request timeouts that follow the latency of the remote.
+//!
+//! Estimates are smoothed as in the retransmission timer of TCP (RFC 6298).
+
+use std::time::Duration;
+
+/// Timeouts are never derived below this
+pub const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Weight of the variation in the timeout, `k` in RFC 6298
+const K: u32 = 4;
+
+/// Largest number of times the timeout is doubled after consecutive timeouts
+const MAX_BACKOFF: u32 = 6;
+
+/// Smoothed round-trip time and variation of the invocations to a remote.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RttEstimator {
+    /// Smoothed round-trip time, if any invocation was measured
+    srtt: Option<Duration>,
+
+    /// Smoothed variation of the round-trip time
+    rttvar: Duration,
+
+    /// Number of timeouts since the last measured invocation
+    backoff: u32,
+
+    /// Timeouts are never derived above this
+    max: Duration,
+}
+
+impl RttEstimator {
+    /// Create an estimator with no measurements, which returns `max` as the timeout.
+    pub fn new(max: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            backoff: 0,
+            max,
+        }
+    }
+
+    /// Add the round-trip time of an invocation that was not retried.
+    ///
+    /// Retried invocations are not measured, as the response could be to any attempt.
+    pub fn observe(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let diff = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + diff / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+
+        self.backoff = 0;
+    }
+
+    /// Double the timeout after an invocation timed out, until the next measurement.
+    pub fn on_timeout(&mut self) {
+        self.backoff = (self.backoff + 1).min(MAX_BACKOFF);
+    }
+
+    /// Returns the smoothed round-trip time and its variation, if any invocation was measured.
+    pub fn estimate(&self) -> Option<(Duration, Duration)> {
+        self.srtt.map(|srtt| (srtt, self.rttvar))
+    }
+
+    /// Returns the timeout of the next request, `SRTT + k·RTTVAR`.
+    pub fn timeout(&self) -> Duration {
+        let srtt = match self.srtt {
+            Some(s) => s,
+            None => return self.max,
+        };
+
+        let timeout = (srtt + self.rttvar * K).max(MIN_ADAPTIVE_TIMEOUT);
+        (timeout * 2_u32.pow(self.backoff)).min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_estimate() {
+        let max = Duration::from_secs(2);
+        let mut rtt = RttEstimator::new(max);
+        assert_eq!(rtt.timeout(), max);
+
+        rtt.observe(Duration::from_millis(100));
+        assert_eq!(
+            rtt.estimate(),
+            Some((Duration::from_millis(100), Duration::from_millis(50)))
+        );
+        assert_eq!(rtt.timeout(), Duration::from_millis(300));
+
+        // a steady latency converges, and the variation decays
+        for _ in 0..50 {
+            rtt.observe(Duration::from_millis(20));
+        }
+        let (srtt, rttvar) = rtt.estimate().unwrap();
+        assert!(srtt.abs_diff(Duration::from_millis(20)) < Duration::from_millis(1));
+        assert!(rttvar < Duration::from_millis(1));
+        assert_eq!(rtt.timeout(), MIN_ADAPTIVE_TIMEOUT);
+
+        rtt.on_timeout();
+        rtt.on_timeout();
+        assert_eq!(rtt.timeout(), MIN_ADAPTIVE_TIMEOUT * 4);
+        for _ in 0..10 {
+            rtt.on_timeout();
+        }
+        assert_eq!(rtt.timeout(), max);
+
+        rtt.observe(Duration::from_millis(20));
+        assert_eq!(rtt.timeout(), MIN_ADAPTIVE_TIMEOUT);
+
+        // the static timeout is an upper bound
+        rtt.observe(Duration::from_secs(60));
+        assert_eq!(rtt.timeout(), max);
+    }
+}
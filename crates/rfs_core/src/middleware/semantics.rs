@@ -0,0 +1,73 @@
+//! Invocation semantics of a dispatcher and its methods.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Guarantees on how many times a request is executed by the dispatcher.
+///
+/// Dispatchers are configured with [super::Dispatcher::with_semantics], and methods
+/// of a remote interface can override it with `#[wire(semantics = "..")]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Semantics {
+    /// A request may not be executed at all
+    Maybe,
+
+    /// A request is executed again for every duplicate received
+    #[default]
+    AtLeastOnce,
+
+    /// Duplicate requests are answered from the duplicate filter
+    AtMostOnce,
+}
+
+impl Semantics {
+    /// All semantics, in increasing order of guarantees
+    pub const ALL: [Semantics; 3] = [Self::Maybe, Self::AtLeastOnce, Self::AtMostOnce];
+
+    /// Returns `true` if duplicate requests must be filtered by the dispatcher.
+    pub fn filters_duplicates(&self) -> bool {
+        matches!(self, Self::AtMostOnce)
+    }
+
+    /// Name of the semantics, as used in attributes and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Maybe => "maybe",
+            Self::AtLeastOnce => "at-least-once",
+            Self::AtMostOnce => "at-most-once",
+        }
+    }
+}
+
+impl Display for Semantics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Semantics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|sem| sem.name() == s)
+            .ok_or_else(|| format!("unknown semantics {:?}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_semantics_names() {
+        for sem in Semantics::ALL {
+            assert_eq!(sem.to_string().parse::<Semantics>(), Ok(sem));
+        }
+
+        assert!("exactly-once".parse::<Semantics>().is_err());
+        assert!(Semantics::AtMostOnce.filters_duplicates());
+        assert!(!Semantics::AtLeastOnce.filters_duplicates());
+    }
+}
@@ -0,0 +1,185 @@
+//! Latency objectives of remote methods, checked against a rolling p95 latency.
+
+use std::{collections::VecDeque, time::Duration};
+
+/// Number of recent latencies kept for each class of methods
+pub const SLO_WINDOW: usize = 100;
+
+/// Latencies needed before a class is checked, so a single slow request is not a violation
+pub const SLO_MIN_SAMPLES: usize = 20;
+
+/// Target p95 latency of a class of methods.
+///
+/// A class is a method signature (`PrimitiveFsOps::read_dir`), an interface (`PrimitiveFsOps`),
+/// or a method name shared by interfaces (`read_file`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SloTarget {
+    pub class: String,
+    pub p95: Duration,
+}
+
+/// Rolling latencies of the classes with a target
+#[derive(Debug, Default)]
+pub struct SloTracker {
+    classes: Vec<ClassLatency>,
+}
+
+#[derive(Debug)]
+struct ClassLatency {
+    target: SloTarget,
+
+    /// Most recent latencies, oldest first
+    samples: VecDeque<Duration>,
+
+    /// Whether the p95 is above the target
+    violated: bool,
+}
+
+impl SloTarget {
+    pub fn new<S: Into<String>>(class: S, p95: Duration) -> Self {
+        Self {
+            class: class.into(),
+            p95,
+        }
+    }
+
+    /// Returns `true` if the method with this signature belongs to the class.
+    pub fn matches(&self, signature: &str) -> bool {
+        signature == self.class
+            || signature
+                .split_once("::")
+                .is_some_and(|(interface, method)| interface == self.class || method == self.class)
+    }
+}
+
+impl SloTracker {
+    pub fn new(targets: Vec<SloTarget>) -> Self {
+        Self {
+            classes: targets
+                .into_iter()
+                .map(|target| ClassLatency {
+                    target,
+                    samples: VecDeque::with_capacity(SLO_WINDOW),
+                    violated: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if no class has a target.
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    /// Record the latency of a method in every class it belongs to.
+    ///
+    /// Returns the number of classes whose p95 went above their target.
+    /// A class is only counted again once its p95 has recovered.
+    pub fn record(&mut self, signature: &str, latency: Duration) -> u64 {
+        let mut violations = 0;
+
+        for class in self
+            .classes
+            .iter_mut()
+            .filter(|c| c.target.matches(signature))
+        {
+            if class.samples.len() == SLO_WINDOW {
+                class.samples.pop_front();
+            }
+            class.samples.push_back(latency);
+
+            let p95 = match class.p95() {
+                Some(p) => p,
+                None => continue,
+            };
+
+            match (p95 > class.target.p95, class.violated) {
+                (true, false) => {
+                    log::warn!(
+                        "slo_violation class={} p95_ms={} target_ms={} samples={} method={}",
+                        class.target.class,
+                        p95.as_millis(),
+                        class.target.p95.as_millis(),
+                        class.samples.len(),
+                        signature
+                    );
+                    class.violated = true;
+                    violations += 1;
+                }
+                (false, true) => {
+                    log::info!(
+                        "slo_recovered class={} p95_ms={} target_ms={}",
+                        class.target.class,
+                        p95.as_millis(),
+                        class.target.p95.as_millis()
+                    );
+                    class.violated = false;
+                }
+                _ => (),
+            }
+        }
+
+        violations
+    }
+
+    /// Rolling p95 latency of a class, once it has enough samples
+    pub fn p95(&self, class: &str) -> Option<Duration> {
+        self.classes
+            .iter()
+            .find(|c| c.target.class == class)
+            .and_then(|c| c.p95())
+    }
+}
+
+impl ClassLatency {
+    fn p95(&self) -> Option<Duration> {
+        if self.samples.len() < SLO_MIN_SAMPLES {
+            return None;
+        }
+
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        let rank = (sorted.len() * 95).div_ceil(100);
+
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slo_tracker() {
+        let target = SloTarget::new("read_dir", Duration::from_millis(50));
+        assert!(target.matches("PrimitiveFsOps::read_dir"));
+        assert!(!target.matches("PrimitiveFsOps::read_all"));
+        assert!(SloTarget::new("PrimitiveFsOps", Duration::ZERO).matches("PrimitiveFsOps::stat"));
+
+        let mut tracker = SloTracker::new(vec![target]);
+        let fast = Duration::from_millis(10);
+        let slow = Duration::from_millis(80);
+
+        // nothing is checked until there are enough samples
+        for _ in 0..SLO_MIN_SAMPLES - 1 {
+            assert_eq!(tracker.record("PrimitiveFsOps::read_dir", slow), 0);
+        }
+        assert_eq!(tracker.p95("read_dir"), None);
+        assert_eq!(tracker.record("PrimitiveFsOps::read_dir", slow), 1);
+        assert_eq!(tracker.p95("read_dir"), Some(slow));
+
+        // a violation is counted once, until the p95 recovers
+        assert_eq!(tracker.record("PrimitiveFsOps::read_dir", slow), 0);
+        assert_eq!(tracker.record("PrimitiveFsOps::read_all", fast), 0);
+        for _ in 0..SLO_WINDOW {
+            tracker.record("PrimitiveFsOps::read_dir", fast);
+        }
+        assert_eq!(tracker.p95("read_dir"), Some(fast));
+
+        // 6 slow requests out of 100 put the p95 above the target
+        let counted = (0..6)
+            .map(|_| tracker.record("PrimitiveFsOps::read_dir", slow))
+            .sum::<u64>();
+        assert_eq!(counted, 1);
+    }
+}
@@ -0,0 +1,392 @@
+//! Sockets that protocols and the dispatcher bind to.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::{net::UdpSocket, sync::Mutex};
+
+use super::PortRange;
+
+/// This trait is implemented for types that provide socket addresses to bind to.
+///
+/// Socket reuse logic can be implemented for certain types.
+#[async_trait]
+pub trait SocketProvider: core::marker::Send + core::marker::Sync {
+    /// Construct an instance of `Self` from a given address
+    fn from_addr(a: Ipv4Addr) -> Self;
+
+    /// Creates a new socket address to bind to, or reuses an existing one.
+    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>>;
+
+    /// Free a socket address.
+    ///
+    /// In the default impl, this is a no-op
+    #[allow(unused_variables)]
+    async fn free_sock(&mut self, s: Arc<UdpSocket>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Converts a socket address to a V4 one.
+/// V6 addresses will return an error.
+pub fn sockaddr_to_v4(addr: SocketAddr) -> io::Result<SocketAddrV4> {
+    match addr {
+        SocketAddr::V4(a) => Ok(a),
+        SocketAddr::V6(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "IPv6 addresses are not supported",
+        )),
+    }
+}
+
+/// Returns the local address that datagrams to `target` are sent from, as chosen by the
+/// routing table of this host. No datagrams are sent.
+pub fn route_source(target: SocketAddrV4) -> io::Result<Ipv4Addr> {
+    let sock = std::net::UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+    sock.connect(target)?;
+
+    Ok(*sockaddr_to_v4(sock.local_addr()?)?.ip())
+}
+
+/// Check that datagrams to `target` can be sent from sockets bound to `source`.
+///
+/// Fails with [io::ErrorKind::AddrNotAvailable] if they cannot, e.g. when a loopback
+/// address is used to reach a remote host.
+pub fn check_route(source: Ipv4Addr, target: SocketAddrV4) -> io::Result<()> {
+    let unreachable = |reason: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!(
+                "{} cannot reach {}: {}. Bind to an address on the interface that routes to it",
+                source, target, reason
+            ),
+        )
+    };
+
+    if source.is_loopback() && !target.ip().is_loopback() {
+        return Err(unreachable(&"loopback addresses only reach this host"));
+    }
+
+    let sock =
+        std::net::UdpSocket::bind(SocketAddrV4::new(source, 0)).map_err(|e| unreachable(&e))?;
+    sock.connect(target).map_err(|e| unreachable(&e))?;
+
+    Ok(())
+}
+
+/// Basic socket provider impl, no socket reuse
+#[derive(Debug)]
+pub struct BasicSockProvider {
+    addr: Ipv4Addr,
+}
+
+#[async_trait]
+impl SocketProvider for BasicSockProvider {
+    fn from_addr(a: Ipv4Addr) -> Self {
+        Self { addr: a }
+    }
+
+    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>> {
+        Ok(Arc::new(
+            UdpSocket::bind(SocketAddrV4::new(self.addr, 0)).await?,
+        ))
+    }
+}
+
+/// Maintains an internal pool of bound sockets
+#[derive(Debug)]
+pub struct SocketPool {
+    addr: Ipv4Addr,
+
+    /// Ports new sockets are bound to. Any free port is used if unset.
+    ports: Option<PortRange>,
+
+    /// Each socket with the time it can be reused from, or `None` if it is in use
+    sockets: HashMap<SocketAddrV4, (Option<Instant>, Arc<UdpSocket>)>,
+}
+
+impl SocketPool {
+    /// Create a pool that binds its sockets to ports in a range.
+    pub fn with_ports(addr: Ipv4Addr, ports: PortRange) -> Self {
+        Self {
+            addr,
+            ports: Some(ports),
+            sockets: Default::default(),
+        }
+    }
+
+    /// Free a socket, leaving it idle for some time before it is reused.
+    ///
+    /// Late packets meant for the previous user of the socket may arrive during this time.
+    pub async fn free_sock_after(&mut self, s: Arc<UdpSocket>, linger: Duration) -> io::Result<()> {
+        let addr = match s.local_addr()? {
+            std::net::SocketAddr::V4(a) => a,
+            std::net::SocketAddr::V6(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "IPv6 addresses are not supported",
+                ))
+            }
+        };
+
+        self.release(addr, linger);
+        Ok(())
+    }
+
+    /// Mark the socket bound to an address as reusable after some time.
+    fn release(&mut self, addr: SocketAddrV4, linger: Duration) {
+        // we are ok with an entry not existing
+        if let Some((reusable_at, _)) = self.sockets.get_mut(&addr) {
+            *reusable_at = Some(Instant::now() + linger);
+        }
+    }
+
+    /// Number of sockets in the pool, in use or not
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    async fn create_new_sock(&mut self) -> io::Result<UdpSocket> {
+        let ports = match &self.ports {
+            Some(range) => range.0.clone(),
+            None => return UdpSocket::bind(SocketAddrV4::new(self.addr, 0)).await,
+        };
+
+        // ports in the range may also be taken by other processes
+        for port in ports {
+            let addr = SocketAddrV4::new(self.addr, port);
+            if self.sockets.contains_key(&addr) {
+                continue;
+            }
+
+            if let Ok(sock) = UdpSocket::bind(addr).await {
+                return Ok(sock);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no free port in the data port range",
+        ))
+    }
+
+    /// Create a new socket and inserts it into the pool as in use.
+    async fn create_insert_new_sock(&mut self) -> io::Result<Arc<UdpSocket>> {
+        let sock = Arc::new(self.create_new_sock().await?);
+
+        let a = match sock.local_addr()? {
+            std::net::SocketAddr::V4(a) => a,
+            std::net::SocketAddr::V6(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "IPv6 addresses are not supported",
+                ))
+            }
+        };
+
+        self.sockets.insert(a, (None, sock.clone()));
+
+        return Ok(sock);
+    }
+}
+
+#[async_trait]
+impl SocketProvider for SocketPool {
+    fn from_addr(a: Ipv4Addr) -> Self {
+        Self {
+            addr: a,
+            ports: None,
+            sockets: Default::default(),
+        }
+    }
+
+    async fn new_bind_sock(&mut self) -> io::Result<Arc<UdpSocket>> {
+        let now = Instant::now();
+        let unused_sock = self
+            .sockets
+            .values_mut()
+            .find(|(reusable_at, _)| reusable_at.is_some_and(|t| t <= now));
+
+        match unused_sock {
+            Some((reusable_at, sock)) => {
+                *reusable_at = None;
+
+                // discard packets received while the socket was idle
+                let mut buf = [0_u8; 1];
+                while sock.try_recv_from(&mut buf).is_ok() {}
+
+                Ok(sock.clone())
+            }
+            None => self.create_insert_new_sock().await,
+        }
+    }
+
+    async fn free_sock(&mut self, s: Arc<UdpSocket>) -> io::Result<()> {
+        self.free_sock_after(s, Duration::ZERO).await
+    }
+}
+
+/// A [SocketPool] shared between owners, which counts the sockets taken from it.
+#[derive(Clone, Debug)]
+pub struct SharedSocketPool {
+    pool: Arc<Mutex<SocketPool>>,
+
+    /// Number of sockets taken and not yet returned
+    live: Arc<AtomicUsize>,
+
+    /// Time a returned socket stays idle before it is reused
+    linger: Duration,
+}
+
+/// A socket taken from a [SharedSocketPool]. It is returned to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledSocket {
+    sock: Arc<UdpSocket>,
+    pool: SharedSocketPool,
+}
+
+impl SharedSocketPool {
+    /// Create an empty pool of sockets bound to an address.
+    pub fn new(addr: Ipv4Addr, linger: Duration) -> Self {
+        Self {
+            pool: Arc::new(Mutex::new(SocketPool::from_addr(addr))),
+            live: Default::default(),
+            linger,
+        }
+    }
+
+    /// Take an idle socket from the pool, or bind a new one.
+    pub async fn take(&self) -> io::Result<PooledSocket> {
+        let sock = self.pool.lock().await.new_bind_sock().await?;
+        self.live.fetch_add(1, Ordering::Relaxed);
+
+        Ok(PooledSocket {
+            sock,
+            pool: self.clone(),
+        })
+    }
+
+    /// Number of sockets currently taken from the pool
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Number of sockets in the pool, in use or not
+    pub async fn size(&self) -> usize {
+        self.pool.lock().await.len()
+    }
+}
+
+impl Deref for PooledSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sock
+    }
+}
+
+impl Drop for PooledSocket {
+    fn drop(&mut self) {
+        self.pool.live.fetch_sub(1, Ordering::Relaxed);
+
+        let addr = match self.sock.local_addr().map(sockaddr_to_v4) {
+            Ok(Ok(a)) => a,
+            _ => return,
+        };
+        let linger = self.pool.linger;
+
+        // the pool is only locked briefly, so waiting for it is rare
+        match self.pool.pool.try_lock() {
+            Ok(mut pool) => pool.release(addr, linger),
+            Err(_) => {
+                let pool = self.pool.pool.clone();
+                if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                    handle.spawn(async move { pool.lock().await.release(addr, linger) });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_source() {
+        let local = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9);
+        assert!(route_source(local).unwrap().is_loopback());
+
+        assert!(check_route(Ipv4Addr::LOCALHOST, local).is_ok());
+        assert!(check_route(Ipv4Addr::UNSPECIFIED, local).is_ok());
+        assert_eq!(
+            check_route(
+                Ipv4Addr::LOCALHOST,
+                SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 9)
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::AddrNotAvailable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_socket_pool_ports() {
+        // a port that was free a moment ago
+        let start = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ports = PortRange(start..=start.saturating_add(1));
+        let mut pool = SocketPool::with_ports(Ipv4Addr::LOCALHOST, ports.clone());
+
+        let first = pool.new_bind_sock().await.unwrap();
+        let second = pool.new_bind_sock().await.unwrap();
+        for sock in [&first, &second] {
+            assert!(ports.0.contains(&sock.local_addr().unwrap().port()));
+        }
+
+        // the range is exhausted until a socket is freed
+        let err = pool.new_bind_sock().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+
+        pool.free_sock_after(first.clone(), Duration::ZERO)
+            .await
+            .unwrap();
+        let reused = pool.new_bind_sock().await.unwrap();
+        assert_eq!(reused.local_addr().unwrap(), first.local_addr().unwrap());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_socket_pool() {
+        let pool = SharedSocketPool::new(Ipv4Addr::LOCALHOST, Duration::ZERO);
+
+        let first = pool.take().await.unwrap();
+        let second = pool.clone().take().await.unwrap();
+        let addr = first.local_addr().unwrap();
+        assert_eq!(pool.live(), 2);
+
+        // dropped sockets are reused instead of binding new ones
+        drop(first);
+        assert_eq!(pool.live(), 1);
+        let reused = pool.take().await.unwrap();
+        assert_eq!(reused.local_addr().unwrap(), addr);
+        assert_eq!(pool.size().await, 2);
+
+        drop((second, reused));
+        assert_eq!(pool.live(), 0);
+    }
+}
@@ -0,0 +1,163 @@
+//! Versions exchanged between the context manager and the dispatcher on connect.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the wire format of middleware messages.
+///
+/// Incremented whenever a change to the wire format breaks older remotes.
+pub const WIRE_VERSION: u32 = 7;
+
+/// Crate and wire format versions of one end of a connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Version of the middleware crate, `major.minor.patch`
+    pub crate_version: String,
+
+    /// Version of the wire format, see [WIRE_VERSION]
+    pub wire_version: u32,
+
+    /// Hash of the remote interfaces this end was built with, or 0 if unknown
+    pub interface_hash: u64,
+}
+
+/// Compatibility of a remote's version with the local version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionMatch {
+    /// Major and minor versions are the same
+    Compatible,
+
+    /// Only minor versions differ. Newer methods may not be available on one end.
+    MinorMismatch,
+
+    /// Both ends were built with different interface definitions.
+    /// Calls to methods that changed may fail to decode.
+    InterfaceMismatch,
+
+    /// Major or wire format versions differ, and the ends cannot communicate
+    MajorMismatch,
+}
+
+impl Default for VersionInfo {
+    fn default() -> Self {
+        Self::local()
+    }
+}
+
+impl Display for VersionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.interface_hash {
+            0 => write!(f, "{} (wire v{})", self.crate_version, self.wire_version),
+            hash => write!(
+                f,
+                "{} (wire v{}, interfaces {:016x})",
+                self.crate_version, self.wire_version, hash
+            ),
+        }
+    }
+}
+
+impl VersionInfo {
+    /// Version of this build
+    pub fn local() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            wire_version: WIRE_VERSION,
+            interface_hash: 0,
+        }
+    }
+
+    /// Advertise the hash of the interfaces this end was built with
+    pub fn with_interface_hash(mut self, hash: u64) -> Self {
+        self.interface_hash = hash;
+        self
+    }
+
+    /// Compare the version of a remote against this version.
+    ///
+    /// Interface hashes are only compared if both ends know theirs.
+    pub fn compare(&self, remote: &Self) -> VersionMatch {
+        let (local_major, local_minor) = self.major_minor();
+        let (remote_major, remote_minor) = remote.major_minor();
+        let same_interfaces = self.interface_hash == remote.interface_hash
+            || self.interface_hash == 0
+            || remote.interface_hash == 0;
+
+        match (
+            self.wire_version == remote.wire_version && local_major == remote_major,
+            same_interfaces,
+            local_minor == remote_minor,
+        ) {
+            (false, _, _) => VersionMatch::MajorMismatch,
+            (true, false, _) => VersionMatch::InterfaceMismatch,
+            (true, true, false) => VersionMatch::MinorMismatch,
+            (true, true, true) => VersionMatch::Compatible,
+        }
+    }
+
+    /// Major and minor parts of the crate version. Missing parts are zero.
+    fn major_minor(&self) -> (u64, u64) {
+        let mut parts = self
+            .crate_version
+            .split('.')
+            .map(|p| p.parse::<u64>().unwrap_or_default());
+
+        (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_match() {
+        let version = |crate_version: &str, wire_version| VersionInfo {
+            crate_version: crate_version.to_string(),
+            wire_version,
+            interface_hash: 0,
+        };
+        let local = version("1.2.3", 1);
+
+        assert_eq!(
+            local.compare(&version("1.2.0", 1)),
+            VersionMatch::Compatible
+        );
+        assert_eq!(
+            local.compare(&version("1.3.3", 1)),
+            VersionMatch::MinorMismatch
+        );
+        assert_eq!(
+            local.compare(&version("2.2.3", 1)),
+            VersionMatch::MajorMismatch
+        );
+        assert_eq!(
+            local.compare(&version("1.2.3", 2)),
+            VersionMatch::MajorMismatch
+        );
+        assert_eq!(
+            VersionInfo::local().compare(&VersionInfo::local()),
+            VersionMatch::Compatible
+        );
+
+        // unknown interface hashes match any other
+        let hashed = local.clone().with_interface_hash(7);
+        assert_eq!(hashed.compare(&local), VersionMatch::Compatible);
+        assert_eq!(
+            hashed.compare(&local.clone().with_interface_hash(7)),
+            VersionMatch::Compatible
+        );
+        assert_eq!(
+            hashed.compare(&version("1.3.0", 1).with_interface_hash(8)),
+            VersionMatch::InterfaceMismatch
+        );
+        assert_eq!(
+            hashed.compare(&version("2.0.0", 1).with_interface_hash(8)),
+            VersionMatch::MajorMismatch
+        );
+    }
+}
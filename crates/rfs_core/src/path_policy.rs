@@ -0,0 +1,184 @@
+//! Validation of paths on the remote.
+//!
+//! The same [PathPolicy] is enforced by the remote and checked by clients,
+//! so invalid names are rejected before they are sent.
+
+use std::fmt::Display;
+
+/// Separator between path segments
+const SEPARATOR: char = '/';
+
+/// Names that cannot be used as a path segment, with or without an extension.
+///
+/// These are reserved by some platforms, so files with these names cannot be copied off the remote.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Limits on paths and the names of files and directories.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathPolicy {
+    /// Maximum length of a path, in bytes
+    pub max_path_len: usize,
+
+    /// Maximum length of a single file or directory name, in bytes
+    pub max_segment_len: usize,
+}
+
+/// Reasons a path is rejected by a [PathPolicy]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathViolation {
+    /// The name is empty
+    Empty,
+
+    /// The path is longer than the maximum path length
+    PathTooLong { len: usize, max: usize },
+
+    /// A name is longer than the maximum segment length
+    SegmentTooLong { len: usize, max: usize },
+
+    /// A name contains a character outside of the allowed set
+    InvalidChar(char),
+
+    /// A name is reserved
+    Reserved(String),
+}
+
+impl Default for PathPolicy {
+    fn default() -> Self {
+        Self {
+            max_path_len: 1024,
+            max_segment_len: 255,
+        }
+    }
+}
+
+impl PathPolicy {
+    /// Checks if a character is allowed in a file or directory name.
+    pub fn is_allowed_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+    }
+
+    /// Check a single file or directory name.
+    pub fn check_segment(&self, name: &str) -> Result<(), PathViolation> {
+        if name.is_empty() {
+            return Err(PathViolation::Empty);
+        }
+
+        if name.len() > self.max_segment_len {
+            return Err(PathViolation::SegmentTooLong {
+                len: name.len(),
+                max: self.max_segment_len,
+            });
+        }
+
+        if let Some(c) = name.chars().find(|c| !Self::is_allowed_char(*c)) {
+            return Err(PathViolation::InvalidChar(c));
+        }
+
+        let stem = name.split('.').next().unwrap_or_default();
+        let reserved = name.chars().all(|c| c == '.')
+            || RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r));
+        match reserved {
+            true => Err(PathViolation::Reserved(name.to_string())),
+            false => Ok(()),
+        }
+    }
+
+    /// Check a `/`-separated path, relative to the base of the remote.
+    ///
+    /// Empty and `.` segments refer to the current directory and are skipped.
+    pub fn check_path(&self, path: &str) -> Result<(), PathViolation> {
+        if path.len() > self.max_path_len {
+            return Err(PathViolation::PathTooLong {
+                len: path.len(),
+                max: self.max_path_len,
+            });
+        }
+
+        path.split(SEPARATOR)
+            .filter(|s| !s.is_empty() && *s != ".")
+            .try_for_each(|s| self.check_segment(s))
+    }
+}
+
+impl Display for PathViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathViolation::Empty => write!(f, "name is empty"),
+            PathViolation::PathTooLong { len, max } => {
+                write!(f, "path is {} bytes long, max {}", len, max)
+            }
+            PathViolation::SegmentTooLong { len, max } => {
+                write!(f, "name is {} bytes long, max {}", len, max)
+            }
+            PathViolation::InvalidChar(c) => write!(f, "invalid character {:?}", c),
+            PathViolation::Reserved(name) => write!(f, "{:?} is a reserved name", name),
+        }
+    }
+}
+
+impl std::error::Error for PathViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_segment() {
+        let policy = PathPolicy::default();
+
+        assert!(policy.check_segment("valid_string.txt").is_ok());
+        assert!(policy.check_segment("0valid_string1").is_ok());
+        assert!(policy.check_segment("a").is_ok());
+        assert!(policy.check_segment("with-dash").is_ok());
+        assert!(policy.check_segment(".hidden").is_ok());
+        assert!(policy.check_segment("CONTENTS.md").is_ok());
+
+        for c in ['%', '>', '<', '|', '*', '?', ':', '"', '\\', '/', ' '] {
+            assert_eq!(
+                policy.check_segment(&format!("invalid_string{}.asd", c)),
+                Err(PathViolation::InvalidChar(c))
+            );
+        }
+
+        assert_eq!(policy.check_segment(""), Err(PathViolation::Empty));
+        for name in [".", "..", "con", "NUL.txt", "lpt1.tar.gz"] {
+            assert_eq!(
+                policy.check_segment(name),
+                Err(PathViolation::Reserved(name.to_string()))
+            );
+        }
+
+        assert_eq!(
+            policy.check_segment(&"a".repeat(256)),
+            Err(PathViolation::SegmentTooLong { len: 256, max: 255 })
+        );
+    }
+
+    #[test]
+    fn test_check_path() {
+        let policy = PathPolicy {
+            max_path_len: 16,
+            max_segment_len: 8,
+        };
+
+        assert!(policy.check_path(".").is_ok());
+        assert!(policy.check_path("").is_ok());
+        assert!(policy.check_path("./a/b//c.txt").is_ok());
+
+        assert_eq!(
+            policy.check_path("a/../b"),
+            Err(PathViolation::Reserved("..".to_string()))
+        );
+        assert_eq!(
+            policy.check_path("a/b c"),
+            Err(PathViolation::InvalidChar(' '))
+        );
+        assert_eq!(
+            policy.check_path("a/b/c/d/e/f/g/h/i"),
+            Err(PathViolation::PathTooLong { len: 17, max: 16 })
+        );
+    }
+}
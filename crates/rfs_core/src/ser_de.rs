@@ -7,6 +7,8 @@ mod consts;
 pub mod de;
 pub mod err;
 pub mod ser;
+#[cfg(test)]
+mod vectors;
 
 pub use consts::ByteSizePrefix;
 
@@ -27,6 +29,22 @@ where
     T::deserialize(&mut deserializer)
 }
 
+/// Deserialize a data structure that takes up the entire slice of bytes.
+///
+/// Unlike [deserialize], leftover bytes are an error.
+pub fn deserialize_exact<T>(bytes: &[u8]) -> SerDeResult<T>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let mut deserializer = de::RfsDeserializer::from_slice(bytes);
+    let value = T::deserialize(&mut deserializer)?;
+
+    match deserializer.remaining() {
+        0 => Ok(value),
+        n => Err(err::Error::TrailingBytes(n)),
+    }
+}
+
 /// Serialize a data structure with a header appended to the start
 pub fn serialize_with_header<T: serde::Serialize>(
     value: &T,
@@ -63,6 +81,14 @@ where
     deserialize(&byte_packer::unpack_bytes(bytes))
 }
 
+/// Unpack the bits and deserialize a data structure that takes up all of the unpacked bytes.
+pub fn deserialize_packed_exact<T>(bytes: &[u8]) -> SerDeResult<T>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    deserialize_exact(&byte_packer::unpack_bytes(bytes))
+}
+
 /// Serialize a data structure with a header appended to the start
 pub fn serialize_packed_with_header<T: serde::Serialize>(
     value: &T,
@@ -82,6 +108,49 @@ where
     }
 }
 
+/// The contents of a field of bytes, cut out of a serialized struct. See [cut_bytes_field].
+#[derive(Debug, PartialEq)]
+pub struct CutField {
+    /// The serialized struct up to and including the field, with the field left empty
+    pub head: Vec<u8>,
+
+    /// Offset of the contents of the field in the serialized struct
+    pub offset: usize,
+
+    /// Number of bytes in the field
+    pub len: usize,
+}
+
+/// Cut the contents of a field out of a serialized struct, given the first bytes of the struct.
+///
+/// The field must be serialized as bytes, e.g. with `serde_bytes`, and `prefix` must extend
+/// past the length of the field. Large fields can then be read separately: the struct deserializes
+/// with the field left empty once the bytes after the field are appended to [CutField::head].
+pub fn cut_bytes_field(prefix: &[u8], field: &str) -> Option<CutField> {
+    const LEN_BYTES: usize = std::mem::size_of::<ByteSizePrefix>();
+
+    let key = [
+        &[consts::MAP_ENTRY_OPEN][..],
+        &serialize(&field).ok()?,
+        &[consts::MAP_ENTRY_MID, consts::PREFIX_BYTES],
+    ]
+    .concat();
+
+    let value = prefix.windows(key.len()).position(|w| w == key)? + key.len() - 1;
+    let len = prefix.get(value + 1..)?.first_chunk::<LEN_BYTES>()?;
+    let len = usize::try_from(ByteSizePrefix::from_be_bytes(*len)).ok()?;
+
+    let mut head = prefix[..value].to_vec();
+    head.push(consts::PREFIX_BYTES);
+    head.extend((0 as ByteSizePrefix).to_be_bytes());
+
+    Some(CutField {
+        head,
+        offset: value + 1 + LEN_BYTES,
+        len,
+    })
+}
+
 /// A reference into an existing slice of bytes.
 ///
 /// This data structure can perform various (immutable) operations on a slice of
@@ -368,6 +437,54 @@ mod tests {
         assert_eq!(*input, des)
     }
 
+    #[test]
+    fn test_cut_bytes_field() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Upload {
+            name: String,
+            #[serde(with = "serde_bytes")]
+            contents: Vec<u8>,
+            done: bool,
+        }
+
+        let upload = Upload {
+            name: "contents".to_string(),
+            contents: (0..=255).collect(),
+            done: true,
+        };
+        let ser = serialize(&upload).unwrap();
+
+        // only the bytes before the contents are needed
+        let cut = cut_bytes_field(&ser[..ser.len() - 250], "contents").unwrap();
+        assert_eq!(&ser[cut.offset..cut.offset + cut.len], upload.contents);
+
+        let tail = &ser[cut.offset + cut.len..];
+        let des: Upload = deserialize_exact(&[cut.head.as_slice(), tail].concat()).unwrap();
+        assert_eq!(
+            des,
+            Upload {
+                contents: vec![],
+                ..upload
+            }
+        );
+
+        assert_eq!(cut_bytes_field(&ser, "name"), None);
+        assert_eq!(cut_bytes_field(&ser[..cut.offset - 1], "contents"), None);
+    }
+
+    /// Packed payloads that contain the packing delimiter deserialize to the same value.
+    #[test]
+    fn test_ser_de_pack_delimiters() {
+        let bytes: Vec<u8> = vec![26, 1, 26, 0, 0, 0, 0, 26, 26, 0, 3, 26];
+        let ser = serialize_packed(&bytes).unwrap();
+
+        let des: Vec<u8> = deserialize_packed(&ser).unwrap();
+        assert_eq!(des, bytes);
+
+        let des: Vec<u8> = deserialize_packed(&pack_bytes(&ser)).unwrap();
+        assert_eq!(des, bytes);
+    }
+
     fn ser_de_pack_header_loop<T>(input: &T)
     where
         T: Debug + PartialEq + Serialize + for<'a> Deserialize<'a> + RemoteMethodSignature,
@@ -1,5 +1,20 @@
 //! Simple byte packing, for reducing the size of a sequence of
 //! bytes that contain continuous sequences of `0`s.
+//!
+//! # Encoding
+//! Packed bytes are the input bytes with two kinds of 3-byte markers,
+//! both delimited by [BYTE_COUNT_DELIM]:
+//! - `[26, n, 26]`, `n` in `4..=255`: a run of `n` zeroes.
+//!   Longer runs are split into several markers.
+//! - `[26, 0, 26]`: a single literal delimiter.
+//!
+//! Runs of 3 or fewer zeroes are left as they are.
+//!
+//! Bytes that are already packed (every delimiter starts a marker and there are no runs of zeroes
+//! to pack) are not packed again, so packing packed bytes has no effect.
+//! Packing any other sequence of bytes, including packed bytes nested in a larger payload,
+//! is lossless. Plain bytes that happen to look packed unpack to something else,
+//! so payloads of arbitrary bytes should be serialized in full before packing.
 
 use crate::ser_de::ByteViewer;
 
@@ -9,6 +24,11 @@ const BYTE_COUNT_DELIM: u8 = 26;
 
 /// Pack a sequence of bytes
 pub fn pack_bytes(input: &[u8]) -> Vec<u8> {
+    // packed bytes are left as they are
+    if is_packed(input) {
+        return input.to_vec();
+    }
+
     let mut viewer = ByteViewer::from_slice(input);
 
     // max vec len is current slice size
@@ -22,13 +42,13 @@ pub fn pack_bytes(input: &[u8]) -> Vec<u8> {
             Some(offset) => {
                 // println!("offset to next zero byte: {}", offset);
                 // add non matching bits
-                packed.extend(viewer.next_bytes(offset, true));
+                extend_escaped(&mut packed, viewer.next_bytes(offset, true));
             }
             None => {
                 match viewer.is_end() {
                     true => (),
                     false => {
-                        packed.extend(viewer.curr_iter());
+                        extend_escaped(&mut packed, viewer.curr_iter().as_slice());
                         viewer.advance(viewer.distance_to_end()).unwrap();
                     }
                 }
@@ -64,6 +84,45 @@ pub fn pack_bytes(input: &[u8]) -> Vec<u8> {
     packed
 }
 
+/// Extend packed bytes with unpacked bytes, escaping any delimiters
+fn extend_escaped(packed: &mut Vec<u8>, bytes: &[u8]) {
+    for b in bytes {
+        match *b {
+            BYTE_COUNT_DELIM => packed.extend([BYTE_COUNT_DELIM, 0, BYTE_COUNT_DELIM]),
+            other => packed.push(other),
+        }
+    }
+}
+
+/// Checks if a sequence of bytes is already packed.
+///
+/// Every delimiter must start a marker, and no run of zeroes may be long enough to pack.
+fn is_packed(input: &[u8]) -> bool {
+    let mut viewer = ByteViewer::from_slice(input);
+
+    while let Some(&byte) = viewer.peek() {
+        match byte {
+            BYTE_COUNT_DELIM => {
+                if viewer.distance_to_end() < 3 {
+                    return false;
+                }
+
+                match viewer.next_bytes_fixed::<3>(true) {
+                    [BYTE_COUNT_DELIM, 0 | 4..=255, BYTE_COUNT_DELIM] => (),
+                    _ => return false,
+                }
+            }
+            0 => match viewer.num_duplicates() {
+                num_zeroes @ 0..=3 => viewer.advance(num_zeroes).unwrap(),
+                _ => return false,
+            },
+            _ => viewer.advance(1).unwrap(),
+        }
+    }
+
+    true
+}
+
 /// Unpack a packed sequence of bytess
 pub fn unpack_bytes(input: &[u8]) -> Vec<u8> {
     let mut viewer = ByteViewer::from_slice(input);
@@ -91,6 +150,11 @@ pub fn unpack_bytes(input: &[u8]) -> Vec<u8> {
         let window = viewer.next_bytes_fixed::<3>(false);
 
         match window {
+            // escaped delimiter
+            [BYTE_COUNT_DELIM, 0, BYTE_COUNT_DELIM] => {
+                unpacked.push(BYTE_COUNT_DELIM);
+                viewer.advance(3).unwrap();
+            }
             [BYTE_COUNT_DELIM, count, BYTE_COUNT_DELIM] => {
                 let expanded = [0_u8].repeat(count as usize);
                 unpacked.extend(expanded);
@@ -127,6 +191,41 @@ mod tests {
         assert_eq!(bytes, unpacked);
     }
 
+    /// Literal delimiters survive packing, and packing packed bytes has no effect.
+    #[test]
+    fn test_pack_delimiters() {
+        let bytes = vec![
+            BYTE_COUNT_DELIM,
+            2,
+            BYTE_COUNT_DELIM,
+            0,
+            0,
+            0,
+            0,
+            0,
+            BYTE_COUNT_DELIM,
+            0,
+            0,
+            0,
+            0,
+            BYTE_COUNT_DELIM,
+            BYTE_COUNT_DELIM,
+        ];
+
+        let packed = pack_bytes(&bytes);
+        assert_eq!(unpack_bytes(&packed), bytes);
+        assert_eq!(pack_bytes(&packed), packed);
+
+        let all_bytes = (0..=255).chain(0..=255).collect::<Vec<u8>>();
+        let packed = pack_bytes(&all_bytes);
+        assert_eq!(unpack_bytes(&packed), all_bytes);
+        assert_eq!(pack_bytes(&packed), packed);
+
+        // packed bytes nested in a larger payload are escaped
+        let nested = [[0; 8].as_slice(), &packed].concat();
+        assert_eq!(unpack_bytes(&pack_bytes(&nested)), nested);
+    }
+
     /// Test the packer on 0-sequences greater than `u8::MAX`
     #[test]
     fn test_pack_arbitrary_len_bytes() {
@@ -25,6 +25,11 @@ impl<'de> RfsDeserializer<'de> {
             input: ByteViewer::from_slice(s),
         }
     }
+
+    /// Number of bytes not yet deserialized
+    pub fn remaining(&self) -> usize {
+        self.input.distance_to_end()
+    }
 }
 
 /// Impl deserialize for primitives
@@ -111,7 +116,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut RfsDeserializer<'de> {
             Some(&consts::PREFIX_NUM) => self.deserialize_u64(visitor),
             Some(&consts::PREFIX_OPTIONAL) => self.deserialize_option(visitor),
             Some(&consts::PREFIX_SEQ) => self.deserialize_seq(visitor),
-            Some(&consts::PREFIX_SEQ_CONST) => unimplemented!("insufficient information"),
+            Some(&consts::PREFIX_SEQ_CONST) => self.deserialize_tuple(0, visitor),
+            Some(&consts::PREFIX_FLOAT) => self.deserialize_f64(visitor),
             Some(&consts::PREFIX_STR) => self.deserialize_str(visitor),
             Some(&consts::PREFIX_UNIT) => self.deserialize_unit(visitor),
 
@@ -442,11 +448,13 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut RfsDeserializer<'de> {
         self.deserialize_str(visitor)
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    /// Skips fields that are not known to the type being deserialized,
+    /// such as parameters added in a newer version of a remote interface.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_any(visitor)
     }
 }
 
@@ -26,6 +26,9 @@ pub enum Error {
     /// The deserializer does not have sufficient bytes continue the operation.
     OutOfBytes,
 
+    /// Bytes are left over after the data structure was deserialized.
+    TrailingBytes(usize),
+
     /// A custom error
     Custom(String),
 }
@@ -60,6 +63,7 @@ impl std::fmt::Display for Error {
             }
             Error::OutOfBytes => format!("Out of bytes to deserialize"),
             Error::MalformedData => format!("Malformed data"),
+            Error::TrailingBytes(n) => format!("{} bytes left after deserializing", n),
             Error::Custom(c) => format!("Error: {}", c),
         };
 
@@ -280,8 +280,8 @@ impl<'a> ser::SerializeTupleStruct for &'a mut RfsSerializer {
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        // uses [ser::SerializeTuple]
-        Ok(())
+        // fields are delimited like [ser::SerializeTuple]
+        ser::SerializeTuple::end(self)
     }
 }
 
@@ -298,8 +298,8 @@ impl<'a> ser::SerializeTupleVariant for &'a mut RfsSerializer {
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        // uses [ser::SerializeTuple]
-        Ok(())
+        // fields are delimited like [ser::SerializeTuple]
+        ser::SerializeTuple::end(self)
     }
 }
 
@@ -0,0 +1,154 @@
+//! Canonical serialized payloads, checked against the fixtures in `vectors/`.
+//!
+//! A change to the wire format fails these tests. If the change is intended, clients built
+//! before it can no longer talk to the remote. Regenerate the fixtures with:
+//!
+//! ```sh
+//! RFS_BLESS_VECTORS=1 cargo test -p rfs_core vectors
+//! ```
+
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Primitives {
+    boolean: bool,
+    i8: i8,
+    i16: i16,
+    i32: i32,
+    i64: i64,
+    u8: u8,
+    u16: u16,
+    u32: u32,
+    u64: u64,
+    f32: f32,
+    f64: f64,
+    c: char,
+    s: String,
+    #[serde(with = "serde_bytes")]
+    bytes: Vec<u8>,
+    unit: (),
+    some: Option<u16>,
+    none: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct NewType(u32);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Pair(u16, String);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+enum Variants {
+    Unit,
+    NewType(NewType),
+    Tuple(i32, bool),
+    Struct { a: bool, b: String },
+}
+
+fn primitives() -> Primitives {
+    Primitives {
+        boolean: true,
+        i8: -100,
+        i16: -1_000,
+        i32: -1_000_000_000,
+        i64: -1_000_000_000_000,
+        u8: 200,
+        u16: 60_000,
+        u32: 4_000_000_000,
+        u64: u64::MAX,
+        f32: 1.5,
+        f64: -0.1,
+        c: 'é',
+        s: "wire format".to_string(),
+        bytes: vec![0, 0, 0, 0, 1, 2, 3, 255, 255],
+        unit: (),
+        some: Some(7),
+        none: None,
+    }
+}
+
+fn variants() -> Vec<Variants> {
+    vec![
+        Variants::Unit,
+        Variants::NewType(NewType(42)),
+        Variants::Tuple(-7, false),
+        Variants::Struct {
+            a: true,
+            b: "struct variant".to_string(),
+        },
+    ]
+}
+
+fn tuples() -> (Pair, (bool, u8, char), ()) {
+    (Pair(513, "pair".to_string()), (false, 0, 'z'), ())
+}
+
+fn map() -> BTreeMap<String, Vec<u64>> {
+    BTreeMap::from([
+        ("empty".to_string(), vec![]),
+        ("repeated".to_string(), vec![0; 8]),
+        ("counting".to_string(), (1..=5).collect()),
+    ])
+}
+
+/// Location of a fixture in the crate
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("vectors")
+        .join(name)
+}
+
+/// Check the serialized value against the fixture, plain and packed.
+fn check_vector<T>(name: &str, value: &T)
+where
+    T: Debug + PartialEq + Serialize + for<'a> Deserialize<'a>,
+{
+    let cases = [
+        (format!("{}.bin", name), serialize(value).unwrap()),
+        (
+            format!("{}.packed.bin", name),
+            serialize_packed(value).unwrap(),
+        ),
+    ];
+
+    for (file, ser) in cases {
+        let path = fixture_path(&file);
+
+        if std::env::var_os("RFS_BLESS_VECTORS").is_some() {
+            std::fs::write(&path, &ser).unwrap();
+        }
+
+        let fixture = std::fs::read(&path).unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+        assert_eq!(ser, fixture, "wire format of {} changed", file);
+
+        let des: T = match file.ends_with(".packed.bin") {
+            true => deserialize_packed(&fixture).unwrap(),
+            false => deserialize(&fixture).unwrap(),
+        };
+        assert_eq!(&des, value, "{} deserialized differently", file);
+    }
+}
+
+#[test]
+fn test_vector_primitives() {
+    check_vector("primitives", &primitives());
+}
+
+#[test]
+fn test_vector_variants() {
+    check_vector("variants", &variants());
+}
+
+#[test]
+fn test_vector_tuples() {
+    check_vector("tuples", &tuples());
+}
+
+#[test]
+fn test_vector_map() {
+    check_vector("map", &map());
+}
@@ -0,0 +1,55 @@
+//! Registry of the method signatures known to this process, for readable logs.
+//!
+//! Dispatchers register the signatures their handler routes to, and clients the signatures
+//! of the methods they invoke. Payloads can then be logged as `ImmutableFileOps::read_file`
+//! instead of as bytes.
+
+use std::{collections::BTreeSet, sync::RwLock};
+
+use crate::matches_signature;
+
+static SIGNATURES: RwLock<BTreeSet<&'static [u8]>> = RwLock::new(BTreeSet::new());
+
+/// Add signatures to the registry.
+pub fn register_signatures<I: IntoIterator<Item = &'static [u8]>>(signatures: I) {
+    SIGNATURES
+        .write()
+        .expect("signature registry poisoned")
+        .extend(signatures);
+}
+
+/// Returns the name of the method a signature or payload belongs to, e.g. `ImmutableFileOps::read_file`.
+///
+/// Only registered signatures are decoded.
+pub fn decode_signature(bytes: &[u8]) -> Option<&'static str> {
+    let signatures = SIGNATURES.read().expect("signature registry poisoned");
+
+    signatures
+        .iter()
+        .find(|sig| bytes == **sig || matches_signature(bytes, sig))
+        .and_then(|sig| std::str::from_utf8(sig).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_signature() {
+        assert_eq!(decode_signature(b"TestOps::unregistered"), None);
+
+        register_signatures([b"TestOps::read".as_slice(), b"TestOps::read_all".as_slice()]);
+
+        assert_eq!(decode_signature(b"TestOps::read"), Some("TestOps::read"));
+        // payloads carry the wire format after the signature
+        assert_eq!(
+            decode_signature(b"TestOps::read\x01abc"),
+            Some("TestOps::read")
+        );
+        assert_eq!(
+            decode_signature(b"TestOps::read_all\x00abc"),
+            Some("TestOps::read_all")
+        );
+        assert_eq!(decode_signature(b"TestOps::re"), None);
+    }
+}
@@ -12,6 +12,7 @@ use syn::{
 
 use crate::{
     camel_case_to_pascal_case,
+    interface_attr::InterfaceAttrs,
     remote_message::{VARIANT_REQUEST, VARIANT_RESPONSE},
 };
 
@@ -20,28 +21,30 @@ const CTX_MGR_IDENT: &str = "ctx";
 
 /// From the trait name, derive a new client struct and implement
 /// the same methods as the trait, but with an additional parameter:
-/// the context manager.
+/// the invoker.
 ///
-/// The context manager is the middleware that handles communication with the
-/// remote.
+/// The invoker is the middleware that handles communication with the
+/// remote, usually a context manager.
 pub fn derive_client(
     trait_name: Ident,
+    interface_attrs: &InterfaceAttrs,
     trait_methods: Vec<TraitItemFn>,
 ) -> proc_macro2::TokenStream {
     // I can't seem to define this as a global without going through
     // ten thousand steps, so I'm just going to define it here.
     #[allow(non_snake_case)]
     let NEW_FUNC_ARG: FnArg =
-        syn::parse2(quote! {ctx: &mut rfs_core::middleware::ContextManager}).unwrap();
+        syn::parse2(quote! {ctx: &mut impl rfs_core::middleware::Invoker}).unwrap();
 
     // struct definition
-    let struct_name = Ident::new(&format!("{}Client", &trait_name), trait_name.span());
+    let struct_name = interface_attrs.client_ident(&trait_name);
+    let vis = interface_attrs.item_vis();
     let struct_def = quote! {
         #[doc = "Client for method invocations."]
         #[doc = ""]
         #[doc = concat!("This struct is automatically generated from [`", stringify!(#trait_name), "`]")]
         #[derive(Debug)]
-        pub struct #struct_name;
+        #vis struct #struct_name;
     };
 
     let impl_methods = trait_methods
@@ -51,10 +54,7 @@ pub fn derive_client(
 
             let request_builder = func_call_to_enum_request(
                 signature.inputs.clone(),
-                Ident::new(
-                    &camel_case_to_pascal_case(&format!("{}_{}", trait_name, signature.ident)),
-                    signature.ident.span(),
-                ),
+                interface_attrs.message_ident(&trait_name, &signature.ident),
             );
 
             signature.inputs.insert(0, NEW_FUNC_ARG.clone());
@@ -136,7 +136,14 @@ fn func_call_to_enum_request(
             #enum_params
         };
 
-        let response = ctx.invoke(request).await?;
+        // application errors are decoded into the method's error type
+        let response = match ctx.invoke(request).await {
+            Ok(r) => r,
+            Err(rfs_core::middleware::InvokeError::RemoteApplication(bytes)) => {
+                <#enum_ident as rfs_core::RemoteResponse>::from_application_error(bytes)?
+            }
+            Err(e) => return Err(e),
+        };
 
         match response {
             #enum_ident::#req_variant{..} => unimplemented!("this branch is never taken"),
@@ -12,6 +12,7 @@ use syn::{
 
 use crate::{
     camel_case_to_pascal_case,
+    interface_attr::InterfaceAttrs,
     remote_message::{VARIANT_REQUEST, VARIANT_RESPONSE},
 };
 
@@ -20,7 +21,10 @@ const PAYLOAD_IDENT: &str = "payload";
 /// Extend each method of a trait with a copy.
 /// Adds a mutable receiver to the start of each
 /// function definition as well.
-pub fn extend_trait(trait_def: proc_macro::TokenStream) -> proc_macro::TokenStream {
+pub fn extend_trait(
+    trait_def: proc_macro::TokenStream,
+    interface_attrs: &InterfaceAttrs,
+) -> proc_macro::TokenStream {
     let ItemTrait {
         attrs,
         vis,
@@ -47,7 +51,8 @@ pub fn extend_trait(trait_def: proc_macro::TokenStream) -> proc_macro::TokenStre
             }
         })
         .map(|trait_method| {
-            let extended_fn = mod_extend_method(ident.clone(), trait_method);
+            let enum_name = interface_attrs.message_path(&ident, &trait_method.sig.ident);
+            let extended_fn = mod_extend_method(ident.clone(), enum_name, trait_method);
 
             [trait_method.to_owned(), extended_fn]
         })
@@ -78,13 +83,11 @@ pub fn extend_trait(trait_def: proc_macro::TokenStream) -> proc_macro::TokenStre
 ///
 /// Modifies the given trait method and the new method so that it has a mutable self as a
 /// receiver.
-fn mod_extend_method(trait_name: Ident, method: &mut TraitItemFn) -> TraitItemFn {
-    // construct the enum name
-    let enum_name: Ident = Ident::new(
-        &camel_case_to_pascal_case(&format!("{}_{}", trait_name, method.sig.ident)),
-        method.sig.span(),
-    );
-
+fn mod_extend_method(
+    trait_name: Ident,
+    enum_name: proc_macro2::TokenStream,
+    method: &mut TraitItemFn,
+) -> TraitItemFn {
     let payload_ident = Ident::new(PAYLOAD_IDENT, Span::call_site());
     let fn_params: Punctuated<FnArg, Comma> = syn::parse_quote! {#payload_ident: #enum_name};
 
@@ -0,0 +1,183 @@
+//! Parsing of the `#[remote_interface(..)]` attribute options.
+//!
+//! ```ignore
+//! /// Generates `FsClient` and `FsRead` instead of `PrimitiveFsOpsClient` and `PrimitiveFsOpsRead`
+//! #[remote_interface(client_name = "FsClient", message_prefix = "Fs")]
+//! pub trait PrimitiveFsOps {
+//!     async fn read(path: String) -> Vec<u8>;
+//! }
+//!
+//! /// Generates the client and messages in `pub(crate) mod fs_generated`,
+//! /// and re-exports only the client next to the trait
+//! #[remote_interface(module = "fs_generated", vis = "pub(crate)", reexport = "client")]
+//! pub trait PrimitiveFsOps {
+//!     async fn read(path: String) -> Vec<u8>;
+//! }
+//! ```
+
+use proc_macro2::Ident;
+use quote::quote;
+use syn::{LitStr, Visibility};
+
+use crate::camel_case_to_pascal_case;
+
+const CLIENT_NAME: &str = "client_name";
+const MESSAGE_PREFIX: &str = "message_prefix";
+const MODULE: &str = "module";
+const VIS: &str = "vis";
+const REEXPORT: &str = "reexport";
+
+/// Generated items re-exported from the module next to the trait
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reexport {
+    #[default]
+    None,
+
+    /// Only the client struct
+    Client,
+
+    /// The client struct and the message enums
+    All,
+}
+
+/// Options of the interface attribute. Generated identifiers default to the trait name.
+#[derive(Default)]
+pub struct InterfaceAttrs {
+    /// Name of the generated client struct
+    client_name: Option<Ident>,
+    /// Prefix of the generated message enums
+    message_prefix: Option<Ident>,
+    /// Child module the generated items are placed in
+    module: Option<Ident>,
+    /// Visibility of the generated module, or of the generated items if there is no module
+    vis: Option<Visibility>,
+    reexport: Reexport,
+}
+
+impl InterfaceAttrs {
+    /// Parse the options from the attribute arguments.
+    pub fn parse(attr: proc_macro2::TokenStream) -> syn::Result<Self> {
+        let mut attrs = Self::default();
+
+        let parser = syn::meta::parser(|meta| {
+            let option = meta.path.get_ident().map(|i| i.to_string());
+            let value: LitStr = match option.as_deref() {
+                Some(CLIENT_NAME | MESSAGE_PREFIX | MODULE | VIS | REEXPORT) => {
+                    meta.value()?.parse()?
+                }
+                _ => {
+                    return Err(meta.error(format!(
+                    "unsupported interface option, expected one of `{}`, `{}`, `{}`, `{}` or `{}`",
+                    CLIENT_NAME, MESSAGE_PREFIX, MODULE, VIS, REEXPORT
+                )))
+                }
+            };
+
+            match option.as_deref() {
+                Some(CLIENT_NAME) => attrs.client_name = Some(value.parse()?),
+                Some(MESSAGE_PREFIX) => attrs.message_prefix = Some(value.parse()?),
+                Some(MODULE) => attrs.module = Some(value.parse()?),
+                Some(VIS) => attrs.vis = Some(value.parse()?),
+                _ => {
+                    attrs.reexport = match value.value().as_str() {
+                        "none" => Reexport::None,
+                        "client" => Reexport::Client,
+                        "all" => Reexport::All,
+                        _ => {
+                            return Err(syn::Error::new(
+                                value.span(),
+                                "expected `none`, `client` or `all`",
+                            ))
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        syn::parse::Parser::parse2(parser, attr.clone())?;
+
+        if attrs.module.is_none() && attrs.reexport != Reexport::None {
+            return Err(syn::Error::new_spanned(
+                attr,
+                format!("`{}` requires a `{}`", REEXPORT, MODULE),
+            ));
+        }
+
+        Ok(attrs)
+    }
+
+    /// Returns the identifier of the client struct.
+    pub fn client_ident(&self, trait_name: &Ident) -> Ident {
+        match &self.client_name {
+            Some(name) => name.clone(),
+            None => Ident::new(&format!("{}Client", trait_name), trait_name.span()),
+        }
+    }
+
+    /// Returns the identifier of the message enum of a method.
+    pub fn message_ident(&self, trait_name: &Ident, method: &Ident) -> Ident {
+        let prefix = self.message_prefix.as_ref().unwrap_or(trait_name);
+
+        Ident::new(
+            &camel_case_to_pascal_case(&format!("{}_{}", prefix, method)),
+            method.span(),
+        )
+    }
+
+    /// Returns the path to the message enum of a method, from the module of the trait.
+    pub fn message_path(&self, trait_name: &Ident, method: &Ident) -> proc_macro2::TokenStream {
+        let message = self.message_ident(trait_name, method);
+
+        match &self.module {
+            Some(module) => quote! {#module::#message},
+            None => quote! {#message},
+        }
+    }
+
+    /// Visibility of the generated client and message enums.
+    ///
+    /// Items in a generated module are public, and limited by the visibility of the module.
+    pub fn item_vis(&self) -> Visibility {
+        match (&self.module, &self.vis) {
+            (None, Some(vis)) => vis.clone(),
+            _ => syn::parse_quote! {pub},
+        }
+    }
+
+    /// Place the generated items in the generated module, if any, along with the re-exports.
+    ///
+    /// The module and re-exports default to the visibility of the trait.
+    pub fn place_generated(
+        &self,
+        trait_name: &Ident,
+        trait_vis: &Visibility,
+        items: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let module = match &self.module {
+            Some(m) => m,
+            None => return items,
+        };
+        let vis = self.vis.as_ref().unwrap_or(trait_vis);
+
+        let reexport = match self.reexport {
+            Reexport::None => quote! {},
+            Reexport::Client => {
+                let client = self.client_ident(trait_name);
+                quote! {#trait_vis use #module::#client;}
+            }
+            Reexport::All => quote! {#trait_vis use #module::*;},
+        };
+
+        quote! {
+            #[doc = concat!("Client and messages generated from [`", stringify!(#trait_name), "`]")]
+            #vis mod #module {
+                use super::*;
+
+                #items
+            }
+
+            #reexport
+        }
+    }
+}
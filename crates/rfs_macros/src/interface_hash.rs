@@ -0,0 +1,39 @@
+//! Hash of the definition of a remote interface.
+//!
+//! Clients and remotes exchange the hash when they connect, so that interfaces
+//! that drifted apart between builds are detected at runtime.
+//! Documentation does not affect the hash.
+
+use quote::ToTokens;
+use syn::{ItemTrait, TraitItem};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hash the name, method signatures and `#[wire(..)]` attributes of an interface.
+///
+/// Must be called before the wire attributes are stripped.
+pub fn derive(item: &ItemTrait) -> u64 {
+    let mut definition = item.ident.to_string();
+
+    for trait_item in &item.items {
+        let f = match trait_item {
+            TraitItem::Fn(f) => f,
+            _ => continue,
+        };
+
+        for attr in f.attrs.iter().filter(|a| a.path().is_ident("wire")) {
+            definition.push_str(&attr.to_token_stream().to_string());
+        }
+        definition.push_str(&f.sig.to_token_stream().to_string());
+    }
+
+    fnv1a(definition.as_bytes())
+}
+
+/// 64-bit FNV-1a, which is stable across builds and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(FNV_PRIME)
+    })
+}
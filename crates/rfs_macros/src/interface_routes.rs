@@ -0,0 +1,57 @@
+//! Logic for deriving the trait `InterfaceRoutes`.
+//!
+//! The client of an interface routes payloads to every method of the interface,
+//! so that a server registers the whole interface in `payload_handler!`.
+
+use quote::quote;
+
+/// Implement the trait `InterfaceRoutes` on the client, for any implementor of the interface.
+pub fn derive(
+    client: &syn::Ident,
+    trait_name: &syn::Ident,
+    messages: &[syn::Ident],
+) -> proc_macro2::TokenStream {
+    let signatures = messages
+        .iter()
+        .map(|m| quote! { <#m as rfs_core::RemoteMethodSignature>::remote_method_signature() })
+        .collect::<Vec<_>>();
+
+    quote! {
+        #[async_trait::async_trait]
+        impl<S> rfs_core::middleware::InterfaceRoutes<S> for #client
+        where
+            S: #trait_name + Send + ?Sized,
+        {
+            fn signatures() -> Vec<&'static [u8]> {
+                vec![#(#signatures),*]
+            }
+
+            async fn route(
+                server: &mut S,
+                payload_bytes: &[u8],
+            ) -> Option<Result<Vec<u8>, rfs_core::middleware::InvokeError>> {
+                #(if rfs_core::matches_signature(payload_bytes, #signatures) {
+                    return Some(rfs_core::middleware::call_route::<#messages, S>(server, payload_bytes).await);
+                })*
+
+                None
+            }
+
+            fn semantics(payload_bytes: &[u8]) -> Option<Option<rfs_core::middleware::Semantics>> {
+                #(if rfs_core::matches_signature(payload_bytes, #signatures) {
+                    return Some(<#messages as rfs_core::RemoteMethodSignature>::semantics());
+                })*
+
+                None
+            }
+
+            fn access(payload_bytes: &[u8]) -> Option<rfs_core::middleware::MethodAccess> {
+                #(if rfs_core::matches_signature(payload_bytes, #signatures) {
+                    return Some(<#messages as rfs_core::RemoteMethodSignature>::access());
+                })*
+
+                None
+            }
+        }
+    }
+}
@@ -1,14 +1,20 @@
 #![allow(unused)]
 
-use quote::quote;
+use quote::{quote, ToTokens};
 use syn::{punctuated::Punctuated, ItemTrait};
 
 mod client_builder;
 mod extend_remote_callback;
 mod extend_remote_interface;
+mod interface_attr;
+mod interface_hash;
+mod interface_routes;
+mod remote_call;
 mod remote_callback;
 mod remote_message;
 pub(crate) mod remote_method_signature;
+mod remote_response;
+mod wire_attr;
 
 /// Generates the necessary code to implement a remote interface.
 ///
@@ -31,6 +37,43 @@ pub(crate) mod remote_method_signature;
 ///     ///
 ///     /// A mutable receiver will be added after processing by the macro.
 ///     async fn do_something(left: usize, right: usize) -> usize;
+///
+///     /// Payloads are byte-packed by default.
+///     /// Packing can be disabled for payloads that do not benefit from it.
+///     #[wire(packed = false)]
+///     async fn upload(compressed: Vec<u8>) -> bool;
+///
+///     /// Methods can override the invocation semantics of the dispatcher.
+///     #[wire(semantics = "at-least-once")]
+///     async fn read(path: String) -> Vec<u8>;
+///
+///     /// Trailing parameters can be added to an existing method without breaking
+///     /// older clients. Requests without them decode with the default value.
+///     async fn list(path: String, #[wire(default)] recursive: bool) -> Vec<String>;
+/// }
+/// ```
+///
+/// The generated client and message enums are named after the trait by default
+/// (`SomeMethodsClient`, `SomeMethodsDoSomething`). Both can be renamed:
+///
+/// ```ignore
+/// // generates `FsClient`, `FsRead`
+/// #[remote_interface(client_name = "FsClient", message_prefix = "Fs")]
+/// pub trait PrimitiveFsOps {
+///     async fn read(path: String) -> Vec<u8>;
+/// }
+/// ```
+///
+/// Generated items can be kept out of the module of the trait. `module` places them in a
+/// child module, which sees the items of its parent. `vis` sets the visibility of the module,
+/// or of the generated items if there is no module. `reexport` re-exports the `client`, or
+/// `all` generated items, next to the trait with the visibility of the trait:
+///
+/// ```ignore
+/// // only `PrimitiveFsOpsClient` is public, messages are in `fs_generated`
+/// #[remote_interface(module = "fs_generated", vis = "pub(crate)", reexport = "client")]
+/// pub trait PrimitiveFsOps {
+///     async fn read(path: String) -> Vec<u8>;
 /// }
 /// ```
 #[proc_macro_attribute]
@@ -38,7 +81,26 @@ pub fn remote_interface(
     attr: proc_macro::TokenStream,
     item: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let item_cloned = proc_macro2::TokenStream::from(item.clone());
+    let mut item_trait: ItemTrait = syn::parse_macro_input!(item);
+
+    let interface_attrs = match interface_attr::InterfaceAttrs::parse(attr.into()) {
+        Ok(a) => a,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let interface_hash = interface_hash::derive(&item_trait);
+
+    let wire_options = match wire_attr::strip_wire_attrs(&mut item_trait) {
+        Ok(p) => p,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let wire_defaults = match wire_attr::strip_param_attrs(&mut item_trait) {
+        Ok(d) => d,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let item_cloned = item_trait.to_token_stream();
 
     let ItemTrait {
         attrs,
@@ -53,7 +115,7 @@ pub fn remote_interface(
         supertraits,
         brace_token,
         items,
-    } = syn::parse_macro_input!(item);
+    } = item_trait;
 
     let trait_methods = items.iter().filter_map(|item| {
         if let syn::TraitItem::Fn(f) = item {
@@ -66,18 +128,38 @@ pub fn remote_interface(
     let (derived_enum_idents_sigs, derived_enums): (Vec<_>, Vec<_>) = trait_methods
         .clone()
         .map(|m| {
-            let (enum_ident, tokens) = remote_message::derive_enum(ident.clone(), m.to_owned());
+            let (enum_ident, tokens) = remote_message::derive_enum(
+                ident.clone(),
+                &interface_attrs.item_vis(),
+                interface_attrs.message_ident(&ident, &m.sig.ident),
+                m.to_owned(),
+                wire_defaults
+                    .get(&m.sig.ident.to_string())
+                    .unwrap_or(&Default::default()),
+            );
 
+            let signature = format!("{}::{}", ident, m.sig.ident);
             let remote_sig_derive = remote_method_signature::derive(
                 enum_ident.clone(),
-                &format!("{}::{}", ident, m.sig.ident),
+                &signature,
+                wire_options
+                    .get(&m.sig.ident.to_string())
+                    .unwrap_or(&Default::default()),
             );
+            let remote_resp_derive =
+                remote_response::derive(enum_ident.clone(), &signature, &m.sig.output);
+            let remote_call_derive = remote_call::derive(enum_ident.clone(), &ident, &m.sig.ident);
 
             (
                 (enum_ident, m.sig.to_owned()),
-                [tokens, remote_sig_derive]
-                    .into_iter()
-                    .collect::<proc_macro2::TokenStream>(),
+                [
+                    tokens,
+                    remote_sig_derive,
+                    remote_resp_derive,
+                    remote_call_derive,
+                ]
+                .into_iter()
+                .collect::<proc_macro2::TokenStream>(),
             )
         })
         .unzip();
@@ -88,17 +170,41 @@ pub fn remote_interface(
 
     // pass back the new trait definition
     let new_trait_def: proc_macro2::TokenStream =
-        extend_remote_interface::extend_trait(item_cloned.into()).into();
+        extend_remote_interface::extend_trait(item_cloned.into(), &interface_attrs).into();
     let trait_def = quote! {
         #[async_trait::async_trait]
         #new_trait_def
     };
 
     // generate client struct
-    let derived_client_impl =
-        client_builder::derive_client(ident.clone(), trait_methods.map(|m| m.to_owned()).collect());
+    let derived_client_impl = client_builder::derive_client(
+        ident.clone(),
+        &interface_attrs,
+        trait_methods.map(|m| m.to_owned()).collect(),
+    );
+
+    let client_ident = interface_attrs.client_ident(&ident);
+    let message_idents = derived_enum_idents_sigs
+        .iter()
+        .map(|(enum_ident, _)| enum_ident.clone())
+        .collect::<Vec<_>>();
+    let routes_impl = interface_routes::derive(&client_ident, &ident, &message_idents);
+    let hash_impl = quote! {
+        impl #client_ident {
+            #[doc = "Hash of the interface definition. Documentation does not change the hash."]
+            pub const INTERFACE_HASH: u64 = #interface_hash;
+        }
+    };
+
+    let generated = interface_attrs.place_generated(
+        &ident,
+        &vis,
+        [derived_enums, derived_client_impl, hash_impl, routes_impl]
+            .into_iter()
+            .collect(),
+    );
 
-    [trait_def, derived_enums, derived_client_impl]
+    [trait_def, generated]
         .into_iter()
         .collect::<proc_macro2::TokenStream>()
         .into()
@@ -146,6 +252,7 @@ pub fn remote_callback(
             let remote_sig_derive = remote_method_signature::derive(
                 enum_ident.clone(),
                 &format!("{}::{}", ident, method.sig.ident),
+                &Default::default(),
             );
 
             (
@@ -0,0 +1,30 @@
+//! Logic for deriving the trait `RemoteCall`.
+//!
+//! A request payload calls the derived `*_payload` method of its interface,
+//! and wraps the return value in a response payload.
+
+use quote::quote;
+
+use crate::remote_message::VARIANT_RESPONSE;
+
+/// Implement the trait `RemoteCall` for a method payload, on any implementor of the interface.
+pub fn derive(
+    identifier: syn::Ident,
+    trait_name: &syn::Ident,
+    method: &syn::Ident,
+) -> proc_macro2::TokenStream {
+    let resp_variant = syn::Ident::new(VARIANT_RESPONSE, proc_macro2::Span::call_site());
+    let payload_method = syn::Ident::new(&format!("{}_payload", method), method.span());
+
+    quote! {
+        #[async_trait::async_trait]
+        impl<S> rfs_core::RemoteCall<S> for #identifier
+        where
+            S: #trait_name + Send + ?Sized,
+        {
+            async fn call(self, server: &mut S) -> Self {
+                Self::#resp_variant(server.#payload_method(self).await)
+            }
+        }
+    }
+}
@@ -3,6 +3,8 @@
 //!
 //! The request variant contains the function args, and the response contains the return value.
 
+use std::collections::HashSet;
+
 use proc_macro2::{extra::DelimSpan, Span};
 use quote::quote;
 use syn::{punctuated::Punctuated, token::Comma, Field};
@@ -12,23 +14,18 @@ use crate::{camel_case_to_pascal_case, pat_to_struct_field};
 pub(crate) const VARIANT_REQUEST: &str = "Request";
 pub(crate) const VARIANT_RESPONSE: &str = "Response";
 
-/// Construct the enum.
+/// Construct the enum, named `modified_method_ident`.
+///
+/// Fields of `defaults` are filled with their default value if missing from a request.
 ///
 /// Returns the enum ident and the enum as a tokenstream.
 pub fn derive_enum(
     trait_name: syn::Ident,
+    vis: &syn::Visibility,
+    modified_method_ident: syn::Ident,
     trait_method: syn::TraitItemFn,
+    defaults: &HashSet<String>,
 ) -> (syn::Ident, proc_macro2::TokenStream) {
-    let modified_method_ident = {
-        let str = trait_method.sig.ident.to_string();
-        let formatted_ident = camel_case_to_pascal_case(&str);
-
-        syn::Ident::new(
-            &format!("{}{}", trait_name, formatted_ident),
-            trait_method.sig.ident.span(),
-        )
-    };
-
     let inputs = trait_method.sig.inputs;
     let ret_val = trait_method.sig.output;
 
@@ -37,7 +34,17 @@ pub fn derive_enum(
         .map(|arg| match arg {
             syn::FnArg::Receiver(rv) => unimplemented!("trait method must not have a receiver"),
 
-            syn::FnArg::Typed(typ) => pat_to_struct_field(typ),
+            syn::FnArg::Typed(typ) => {
+                let mut field = pat_to_struct_field(typ);
+                if field
+                    .ident
+                    .as_ref()
+                    .is_some_and(|i| defaults.contains(&i.to_string()))
+                {
+                    field.attrs.push(syn::parse_quote! {#[serde(default)]});
+                }
+                field
+            }
         })
         .collect::<Punctuated<Field, Comma>>();
 
@@ -90,7 +97,7 @@ pub fn derive_enum(
             #[doc = ""]
             #[doc = concat!("This enum is automatically generated from [`", stringify!(#trait_name), "`]")]
             #[derive(Debug, serde::Serialize, serde::Deserialize)]
-            pub enum #modified_method_ident {
+            #vis enum #modified_method_ident {
                 #request_variant,
                 #response_variant
             }
@@ -5,19 +5,65 @@ use proc_macro2::Span;
 use quote::quote;
 use syn::{DeriveInput, ItemTrait};
 
+use crate::wire_attr::WireOptions;
+
 const REMOTE_METHOD_SIG_TRAIT: &str = "RemoteMethodSignature";
 const REMOTE_METHOD_SIG_TRAIT_METHOD: &str = "remote_method_signature";
 
 /// Implement the trait `RemoteMethodSignature` with the given method signature.
-pub fn derive(identifier: syn::Ident, signature: &str) -> proc_macro2::TokenStream {
+///
+/// The wire format and semantics are only overridden if they are set in the wire options.
+pub fn derive(
+    identifier: syn::Ident,
+    signature: &str,
+    options: &WireOptions,
+) -> proc_macro2::TokenStream {
     let trait_name = syn::Ident::new(REMOTE_METHOD_SIG_TRAIT, Span::call_site());
     let trait_method = syn::Ident::new(REMOTE_METHOD_SIG_TRAIT_METHOD, Span::call_site());
 
+    let wire_format = match options.packed {
+        Some(true) => quote! {
+            fn wire_format() -> rfs_core::WireFormat {
+                rfs_core::WireFormat::Packed
+            }
+        },
+        Some(false) => quote! {
+            fn wire_format() -> rfs_core::WireFormat {
+                rfs_core::WireFormat::Plain
+            }
+        },
+        None => quote! {},
+    };
+
+    let semantics = match &options.semantics {
+        Some(variant) => quote! {
+            fn semantics() -> Option<rfs_core::middleware::Semantics> {
+                Some(rfs_core::middleware::Semantics::#variant)
+            }
+        },
+        None => quote! {},
+    };
+
+    let access = match options.read_only {
+        true => quote! {
+            fn access() -> rfs_core::middleware::MethodAccess {
+                rfs_core::middleware::MethodAccess::Read
+            }
+        },
+        false => quote! {},
+    };
+
     quote! {
         impl #trait_name for #identifier {
             fn #trait_method() -> &'static [u8] {
                 #signature.as_bytes()
             }
+
+            #wire_format
+
+            #semantics
+
+            #access
         }
 
     }
@@ -0,0 +1,88 @@
+//! Logic for deriving the trait `RemoteResponse`.
+//!
+//! Methods that return a `Result` send their errors as application errors.
+//! The error signature is derived from the method signature and the error type.
+
+use quote::{quote, ToTokens};
+use syn::{GenericArgument, PathArguments, ReturnType, Type};
+
+use crate::remote_message::VARIANT_RESPONSE;
+
+/// Implement the trait `RemoteResponse` for a method payload.
+pub fn derive(
+    identifier: syn::Ident,
+    signature: &str,
+    output: &ReturnType,
+) -> proc_macro2::TokenStream {
+    let resp_variant = syn::Ident::new(VARIANT_RESPONSE, proc_macro2::Span::call_site());
+
+    let methods = match result_err_type(output) {
+        Some(err_ty) => {
+            let err_signature = format!(
+                "{}::Err<{}>",
+                signature,
+                err_ty.to_token_stream().to_string().replace(' ', "")
+            );
+
+            quote! {
+                fn application_error(&self) -> Option<Vec<u8>> {
+                    match self {
+                        Self::#resp_variant(core::result::Result::Err(e)) => {
+                            Some(rfs_core::encode_application_error(#err_signature, e))
+                        }
+                        _ => None,
+                    }
+                }
+
+                fn from_application_error(
+                    bytes: Vec<u8>,
+                ) -> Result<Self, rfs_core::middleware::InvokeError> {
+                    rfs_core::decode_application_error::<#err_ty>(#err_signature, bytes)
+                        .map(|e| Self::#resp_variant(core::result::Result::Err(e)))
+                }
+            }
+        }
+        None => quote! {
+            fn application_error(&self) -> Option<Vec<u8>> {
+                None
+            }
+
+            fn from_application_error(
+                bytes: Vec<u8>,
+            ) -> Result<Self, rfs_core::middleware::InvokeError> {
+                Err(rfs_core::middleware::InvokeError::RemoteApplication(bytes))
+            }
+        },
+    };
+
+    quote! {
+        impl rfs_core::RemoteResponse for #identifier {
+            #methods
+        }
+    }
+}
+
+/// Returns the error type of a method returning `Result<T, E>`.
+///
+/// Aliases with a single type parameter, such as `io::Result<T>`, are not detected.
+fn result_err_type(output: &ReturnType) -> Option<&Type> {
+    let ty = match output {
+        ReturnType::Default => return None,
+        ReturnType::Type(_, ty) => ty,
+    };
+
+    let segment = match ty.as_ref() {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None,
+    };
+
+    match (&segment.arguments, segment.ident == "Result") {
+        (PathArguments::AngleBracketed(args), true) if args.args.len() == 2 => {
+            match &args.args[1] {
+                GenericArgument::Type(err) => Some(err),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,200 @@
+//! Parsing of the per-method `#[wire(..)]` attribute.
+//!
+//! ```ignore
+//! #[remote_interface]
+//! pub trait SomeMethods {
+//!     /// The payload of this method is not byte-packed
+//!     #[wire(packed = false)]
+//!     async fn upload(data: Vec<u8>) -> bool;
+//! }
+//! ```
+//!
+//! Methods can override the invocation semantics of the dispatcher, e.g. so that
+//! idempotent reads skip the duplicate filter:
+//!
+//! ```ignore
+//! #[remote_interface]
+//! pub trait SomeMethods {
+//!     #[wire(semantics = "at-least-once")]
+//!     async fn read(path: String) -> Vec<u8>;
+//! }
+//! ```
+//!
+//! Methods that only read can be marked with `read_only`, so clients
+//! with read-only access tokens may invoke them:
+//!
+//! ```ignore
+//! #[remote_interface]
+//! pub trait SomeMethods {
+//!     #[wire(read_only)]
+//!     async fn read(path: String) -> Vec<u8>;
+//! }
+//! ```
+//!
+//! Trailing parameters can be marked with `#[wire(default)]`. Requests without them,
+//! sent by clients built against an older version of the interface, decode with the default value.
+//!
+//! ```ignore
+//! #[remote_interface]
+//! pub trait SomeMethods {
+//!     async fn list(path: String, #[wire(default)] recursive: bool) -> Vec<String>;
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::Span;
+use syn::{spanned::Spanned, Attribute, FnArg, Ident, ItemTrait, LitBool, LitStr, Pat, TraitItem};
+
+const WIRE_ATTR: &str = "wire";
+const WIRE_PACKED: &str = "packed";
+const WIRE_SEMANTICS: &str = "semantics";
+const WIRE_DEFAULT: &str = "default";
+const WIRE_READ_ONLY: &str = "read_only";
+
+/// Semantics names and their variants of `rfs_core::middleware::Semantics`
+const SEMANTICS_VARIANTS: [(&str, &str); 3] = [
+    ("maybe", "Maybe"),
+    ("at-least-once", "AtLeastOnce"),
+    ("at-most-once", "AtMostOnce"),
+];
+
+/// Options of a method's `#[wire(..)]` attribute
+#[derive(Clone, Debug, Default)]
+pub struct WireOptions {
+    /// Payload is byte-packed
+    pub packed: Option<bool>,
+
+    /// Variant of the invocation semantics the method uses
+    pub semantics: Option<Ident>,
+
+    /// Method only reads
+    pub read_only: bool,
+}
+
+/// Remove `#[wire(..)]` attributes from every method of a trait.
+///
+/// Returns the options of each method that has the attribute, keyed by method name.
+pub fn strip_wire_attrs(item: &mut ItemTrait) -> syn::Result<HashMap<String, WireOptions>> {
+    let mut options = HashMap::new();
+
+    for trait_item in item.items.iter_mut() {
+        if let TraitItem::Fn(f) = trait_item {
+            if let Some(o) = take_options(&mut f.attrs)? {
+                options.insert(f.sig.ident.to_string(), o);
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+/// Remove `#[wire(..)]` attributes from the parameters of every method of a trait.
+///
+/// Returns the names of the parameters marked as `default`, keyed by method name.
+/// Default parameters must come after all other parameters.
+pub fn strip_param_attrs(item: &mut ItemTrait) -> syn::Result<HashMap<String, HashSet<String>>> {
+    let mut defaults = HashMap::new();
+
+    for trait_item in item.items.iter_mut() {
+        let f = match trait_item {
+            TraitItem::Fn(f) => f,
+            _ => continue,
+        };
+
+        let mut names = HashSet::new();
+        for input in f.sig.inputs.iter_mut() {
+            let typed = match input {
+                FnArg::Typed(t) => t,
+                FnArg::Receiver(_) => continue,
+            };
+
+            let is_default = take_default(&mut typed.attrs)?;
+            match (is_default, names.is_empty()) {
+                (true, _) => {
+                    if let Pat::Ident(i) = &*typed.pat {
+                        names.insert(i.ident.to_string());
+                    }
+                }
+                (false, false) => {
+                    return Err(syn::Error::new(
+                        typed.span(),
+                        "parameters after a `#[wire(default)]` parameter must also be `#[wire(default)]`",
+                    ))
+                }
+                (false, true) => (),
+            }
+        }
+
+        if !names.is_empty() {
+            defaults.insert(f.sig.ident.to_string(), names);
+        }
+    }
+
+    Ok(defaults)
+}
+
+/// Remove the wire attribute from the attributes of a parameter, returning if it is `default`.
+fn take_default(attrs: &mut Vec<Attribute>) -> syn::Result<bool> {
+    let mut default = false;
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident(WIRE_ATTR)) {
+        attr.parse_nested_meta(|meta| match meta.path.is_ident(WIRE_DEFAULT) {
+            true => {
+                default = true;
+                Ok(())
+            }
+            false => Err(meta.error("unsupported wire option, expected `default`")),
+        })?;
+    }
+
+    attrs.retain(|a| !a.path().is_ident(WIRE_ATTR));
+
+    Ok(default)
+}
+
+/// Remove the wire attribute from a list of attributes, returning its options.
+fn take_options(attrs: &mut Vec<Attribute>) -> syn::Result<Option<WireOptions>> {
+    let mut options = None;
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident(WIRE_ATTR)) {
+        let opts = options.get_or_insert_with(WireOptions::default);
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(WIRE_PACKED) {
+                let value: LitBool = meta.value()?.parse()?;
+                opts.packed = Some(value.value);
+                return Ok(());
+            }
+
+            if meta.path.is_ident(WIRE_SEMANTICS) {
+                let value: LitStr = meta.value()?.parse()?;
+                let variant = SEMANTICS_VARIANTS
+                    .iter()
+                    .find(|(name, _)| *name == value.value())
+                    .map(|(_, variant)| Ident::new(variant, Span::call_site()))
+                    .ok_or_else(|| {
+                        syn::Error::new(
+                            value.span(),
+                            "unknown semantics, expected `maybe`, `at-least-once` or `at-most-once`",
+                        )
+                    })?;
+                opts.semantics = Some(variant);
+                return Ok(());
+            }
+
+            if meta.path.is_ident(WIRE_READ_ONLY) {
+                opts.read_only = true;
+                return Ok(());
+            }
+
+            Err(meta.error(
+                "unsupported wire option, expected `packed`, `semantics` or `read_only`",
+            ))
+        })?;
+    }
+
+    attrs.retain(|a| !a.path().is_ident(WIRE_ATTR));
+
+    Ok(options)
+}
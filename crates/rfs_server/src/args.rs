@@ -6,7 +6,8 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use rfs::middleware::{FailureRate, PortRange, RequestTimeout, SloTarget, TokenScope};
 
 /// Remote file service server arguments
 #[derive(Parser)]
@@ -29,7 +30,7 @@ pub(crate) struct ServerArgs {
     /// The timeout duration
     #[clap(short, long)]
     #[clap(default_value = rfs::defaults::DEFAULT_TIMEOUT)]
-    pub request_timeout: humantime::Duration,
+    pub request_timeout: RequestTimeout,
 
     /// Process requests sequentially instead of in parallel.
     ///
@@ -46,7 +47,114 @@ pub(crate) struct ServerArgs {
     ///
     /// The server will simulate a transmission failure every 1 in N attempts.
     #[clap(long, value_name = "N")]
-    pub simulate_ommisions: Option<u32>,
+    pub simulate_ommisions: Option<FailureRate>,
+
+    /// Requests larger than this number of bytes are spilled to a temporary file
+    /// while they are being received.
+    #[clap(long, value_name = "BYTES")]
+    #[clap(default_value_t = rfs::middleware::DEFAULT_MEMORY_CAP)]
+    pub memory_cap: usize,
+
+    /// Range of ports to send responses and transfer data from, e.g. `5000-5100`.
+    ///
+    /// Ports are assigned by the OS if not set.
+    #[clap(long, value_name = "START-END")]
+    pub data_ports: Option<PortRange>,
+
+    /// Merge updates to the same file within this window into one callback, e.g. `200ms`.
+    ///
+    /// Updates are sent to watchers as they happen if not set.
+    #[clap(long, value_name = "DURATION")]
+    pub coalesce_window: Option<humantime::Duration>,
+
+    /// File that callback registrations are saved to, and restored from on startup.
+    ///
+    /// Registrations are saved periodically and on Ctrl-C. Restored watches are told
+    /// that the server restarted, so their clients can register again.
+    #[clap(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+
+    /// Record mutating operations to a journal in the served directory before applying them.
+    ///
+    /// Operations interrupted by a crash are applied again on startup.
+    #[clap(long)]
+    pub journal: bool,
+
+    /// File with the secret that access tokens are issued with.
+    ///
+    /// Clients must present a token issued with the secret.
+    /// The file is created with a new secret if it does not exist.
+    #[clap(long, value_name = "PATH")]
+    pub token_secret: Option<PathBuf>,
+
+    /// Compress responses larger than this number of bytes, for clients that accept it.
+    #[clap(long, value_name = "BYTES")]
+    #[clap(default_value_t = rfs::middleware::DEFAULT_COMPRESSION_THRESHOLD)]
+    pub compression_threshold: usize,
+
+    /// Time an incoming transfer may take before it is abandoned, e.g. `10m`.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "10m")]
+    pub transfer_deadline: humantime::Duration,
+
+    /// Time an incoming transfer may go without any packets before it is abandoned, e.g. `30s`.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "30s")]
+    pub transfer_idle_timeout: humantime::Duration,
+
+    /// Time a client may go without requests before its callbacks, locks and open files are freed, e.g. `10m`.
+    ///
+    /// Clients that only wait for callbacks must still make requests to keep them.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "10m")]
+    pub idle_client_timeout: humantime::Duration,
+
+    /// Target p95 latency of a class of methods, e.g. `read_dir=50ms`.
+    ///
+    /// A class is a method (`PrimitiveFsOps::read_dir`), an interface (`PrimitiveFsOps`)
+    /// or a method name (`read_dir`). Violations are logged and shown in the server status.
+    #[clap(long = "slo", value_name = "CLASS=DURATION", value_parser = parse_slo_target)]
+    pub slo_targets: Vec<SloTarget>,
+
+    #[clap(subcommand)]
+    pub command: Option<ServerCommand>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ServerCommand {
+    /// Connect to a running server at the address and port, and show its status.
+    ///
+    /// The invocation semantics must match the ones used by the server.
+    Status {
+        /// Time between status refreshes
+        #[clap(long, default_value = "1s")]
+        refresh: humantime::Duration,
+    },
+
+    /// Connect to a running server at the address and port, and publish a message
+    /// to the clients subscribed to a topic.
+    Publish {
+        /// Message to publish
+        message: String,
+
+        /// Topic to publish to
+        #[clap(long, default_value = rfs::topics::SERVER_TOPIC)]
+        topic: String,
+    },
+
+    /// Check that the server can be started with these arguments, and exit.
+    Doctor,
+
+    /// Issue an access token with the secret of `--token-secret`, and print it.
+    Token {
+        /// Methods the token may invoke: `ro` (read-only) or `rw` (read-write)
+        #[clap(long, default_value = "ro")]
+        scope: TokenScope,
+
+        /// Time until the token expires
+        #[clap(long, default_value = "1h")]
+        ttl: humantime::Duration,
+    },
 }
 
 #[derive(Clone, Debug, clap::ValueEnum)]
@@ -59,6 +167,14 @@ pub enum InvocationSemantics {
 
     /// Duplicate requests will be processed at most once.
     AtMostOnce,
+
+    /// Small requests are sent until they are acknowledged, and large ones with handshakes.
+    /// Duplicate requests will be processed at most once.
+    Adaptive,
+
+    /// Requests are sent over TCP connections, which deliver them reliably.
+    /// Duplicate requests will be processed at most once.
+    Tcp,
 }
 
 impl Display for InvocationSemantics {
@@ -67,6 +183,19 @@ impl Display for InvocationSemantics {
     }
 }
 
+/// Parse a latency target, `CLASS=DURATION`
+fn parse_slo_target(s: &str) -> Result<SloTarget, String> {
+    let (class, p95) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CLASS=DURATION, got {:?}", s))?;
+    if class.is_empty() {
+        return Err("the class of methods is empty".to_string());
+    }
+    let p95 = humantime::parse_duration(p95).map_err(|e| e.to_string())?;
+
+    Ok(SloTarget::new(class, p95))
+}
+
 pub fn camel_to_snake_case(s: &str) -> String {
     let mut result = String::new();
     for (i, c) in s.chars().enumerate() {
@@ -0,0 +1,69 @@
+//! Fixtures shared by the server tests.
+
+use std::{
+    fs,
+    net::Ipv4Addr,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use rfs::middleware::DefaultProto;
+
+use crate::server::RegisteredFileUpdates;
+
+/// A directory under the system temp dir, removed when dropped.
+///
+/// The directory is unique to the test process and the name given.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory, clearing any left behind by an earlier run.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rfs_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns new file update callbacks bound to localhost, for a single test's server.
+pub fn init_callbacks() -> Arc<futures::lock::Mutex<RegisteredFileUpdates>> {
+    Arc::new_cyclic(|shared| {
+        futures::lock::Mutex::new(RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: Default::default(),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(500),
+            retries: 3,
+            coalesce: None,
+            pending: Default::default(),
+            shared: shared.clone(),
+        })
+    })
+}
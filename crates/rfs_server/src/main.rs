@@ -1,26 +1,42 @@
 #![allow(unused)]
 
 mod args;
+#[cfg(test)]
+mod fixtures;
+mod reaper;
 mod server;
+mod status;
 
 use std::{
     net::{Ipv4Addr, SocketAddrV4},
     str::FromStr,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use clap::Parser;
 use futures::{lock::Mutex, FutureExt};
-use rfs::middleware::{
-    DefaultProto, Dispatcher, FaultyDefaultProto, FaultyHandshakeProto, FaultyRequestAckProto,
-    HandshakeProto, RequestAckProto, TransmissionProtocol,
+use rfs::{
+    interfaces::AdminOpsClient,
+    middleware::{
+        ContextManager, Dispatcher, ProtocolOptions, ProtocolRegistry, TokenScope, TokenSecret,
+        TransferLimits,
+    },
 };
 
 use crate::{
     args::ServerArgs,
-    server::{RegisteredFileUpdates, RfsServer, FILE_UPDATE_CALLBACKS},
+    server::{
+        RegisteredFileUpdates, RfsServer, DISPATCH_STATS, FILE_UPDATE_CALLBACKS, TRANSFER_PROTOCOL,
+    },
 };
 
+/// How long the tokens of admin commands are valid for
+const ADMIN_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Interval between saves of the callback registrations to the state file
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() {
     match std::env::var("RUST_LOG") {
@@ -33,26 +49,104 @@ async fn main() {
         .init();
 
     let args = ServerArgs::parse();
-    let mut server = RfsServer::from_path(args.directory);
     let addr = SocketAddrV4::new(args.address, args.port);
 
-    log::info!("server listening on {}", addr);
+    let registry = ProtocolRegistry::default();
+    let protocol_name = args.invocation_semantics.to_string();
+    let protocol = registry
+        .build(
+            &protocol_name,
+            &ProtocolOptions {
+                failure_rate: args.simulate_ommisions,
+                data_ports: args.data_ports.clone(),
+                transfer_limits: TransferLimits {
+                    deadline: args.transfer_deadline.into(),
+                    idle_timeout: args.transfer_idle_timeout.into(),
+                },
+            },
+        )
+        .expect("all invocation semantics are registered");
+    let semantics = registry.semantics(&protocol_name).unwrap_or_default();
+
+    let token_secret = args
+        .token_secret
+        .as_ref()
+        .map(|path| TokenSecret::load_or_create(path).expect("failed to read the token secret"));
 
-    let (protocol, use_filter): (Arc<dyn TransmissionProtocol + Send + Sync>, bool) =
-        match (args.invocation_semantics, args.simulate_ommisions) {
-            (args::InvocationSemantics::Maybe, Some(frac)) => {
-                (Arc::new(FaultyDefaultProto::from_frac(frac)), false)
+    if let Some(args::ServerCommand::Token { scope, ttl }) = &args.command {
+        match &token_secret {
+            Some(secret) => println!("{}", secret.issue(*scope, SystemTime::now() + **ttl)),
+            None => {
+                log::error!("tokens are issued with the secret of --token-secret");
+                std::process::exit(1);
             }
-            (args::InvocationSemantics::Maybe, None) => (Arc::new(DefaultProto), false),
-            (args::InvocationSemantics::AtLeastOnce, Some(frac)) => {
-                (Arc::new(FaultyRequestAckProto::from_frac(frac)), false)
+        }
+        return;
+    }
+
+    if let Some(args::ServerCommand::Doctor) = args.command {
+        let checks = [
+            rfs::doctor::check_export_dir(&args.directory),
+            rfs::doctor::check_bind(addr).await,
+            rfs::doctor::check_max_datagram(args.address).await,
+        ];
+
+        match rfs::doctor::report(&checks) {
+            true => return,
+            false => std::process::exit(1),
+        }
+    }
+
+    if let Some(command) = args.command {
+        let mut ctx = ContextManager::new(
+            Ipv4Addr::UNSPECIFIED,
+            addr,
+            args.request_timeout,
+            rfs::defaults::DEFAULT_RETRIES,
+            protocol,
+        )
+        .await
+        .expect("failed to connect to server");
+
+        // admin commands issue themselves a token if the server requires one
+        if let Some(secret) = &token_secret {
+            let expires = SystemTime::now() + ADMIN_TOKEN_TTL;
+            ctx = ctx.with_token(secret.issue(TokenScope::ReadWrite, expires));
+        }
+
+        match command {
+            args::ServerCommand::Status { refresh } => {
+                if let Err(e) = status::run(ctx, refresh.into()).await {
+                    log::error!("status dashboard error: {}", e);
+                }
+            }
+            args::ServerCommand::Publish { message, topic } => {
+                match AdminOpsClient::publish(&mut ctx, topic.clone(), message).await {
+                    Ok(num) => log::info!("published to {} subscribers of {}", num, topic),
+                    Err(e) => log::error!("failed to publish: {}", e),
+                }
+            }
+            args::ServerCommand::Doctor | args::ServerCommand::Token { .. } => {
+                unreachable!("handled before connecting")
             }
-            (args::InvocationSemantics::AtLeastOnce, None) => (Arc::new(RequestAckProto), false),
-            (args::InvocationSemantics::AtMostOnce, Some(frac)) => {
-                (Arc::new(FaultyHandshakeProto::from_frac(frac)), true)
+        }
+
+        return;
+    }
+
+    let mut server = RfsServer::from_path(args.directory);
+
+    if args.journal {
+        match server.open_journal() {
+            Ok(num) => log::info!("applied {} interrupted operations from the journal", num),
+            Err(e) => {
+                log::error!("failed to open the journal: {}", e);
+                std::process::exit(1);
             }
-            (args::InvocationSemantics::AtMostOnce, None) => (Arc::new(HandshakeProto), true),
-        };
+        }
+    }
+
+    log::info!("server listening on {}", addr);
 
     // this line is used to send information back during testing
     server.set_protocol_name(format!("{}", &protocol));
@@ -62,26 +156,98 @@ async fn main() {
         server,
         protocol.clone(),
         args.sequential,
-        args.request_timeout.into(),
+        args.request_timeout,
         rfs::defaults::DEFAULT_RETRIES,
-        use_filter,
     )
-    .await;
+    .await
+    .with_interface_hash(rfs::interfaces::INTERFACE_HASH)
+    .with_semantics(semantics)
+    .with_memory_cap(args.memory_cap)
+    .with_data_ports(args.data_ports.clone())
+    .with_compression_threshold(args.compression_threshold)
+    .with_slo_targets(args.slo_targets.clone())
+    .with_strict_signatures()
+    .expect("server routes to colliding signatures");
+
+    if let Some(secret) = token_secret {
+        log::info!("clients must present an access token");
+        dispatcher = dispatcher.with_token_secret(secret);
+    }
+
+    DISPATCH_STATS.get_or_init(|| dispatcher.stats());
+    TRANSFER_PROTOCOL.get_or_init(|| dispatcher.protocol.clone());
 
     // initialize callback stuffs
     FILE_UPDATE_CALLBACKS.get_or_init(|| {
-        Arc::new(Mutex::new(RegisteredFileUpdates {
-            bind_addr: args.address,
-            lookup: Default::default(),
-            proto: dispatcher.protocol.clone(),
-            timeout: args.request_timeout.into(),
-            retries: rfs::defaults::DEFAULT_RETRIES,
-        }))
+        Arc::new_cyclic(|shared| {
+            Mutex::new(RegisteredFileUpdates {
+                bind_addr: args.address,
+                lookup: Default::default(),
+                dirs: Default::default(),
+                subscriptions: Default::default(),
+                topics: Default::default(),
+                proto: dispatcher.protocol.clone(),
+                timeout: args.request_timeout.into(),
+                retries: rfs::defaults::DEFAULT_RETRIES,
+                coalesce: args.coalesce_window.map(|w| w.into()),
+                pending: Default::default(),
+                shared: shared.clone(),
+            })
+        })
     });
 
-    tokio::spawn(async move { dispatcher.dispatch().await })
-        .await
-        .unwrap();
+    let callbacks = FILE_UPDATE_CALLBACKS
+        .get()
+        .expect("callbacks are initialized");
+    if let Some(path) = &args.state_file {
+        match callbacks.lock().await.restore_state(path) {
+            Ok((watches, topics)) => log::info!(
+                "restored {} watches and {} topic subscriptions from {:?}",
+                watches,
+                topics,
+                path
+            ),
+            Err(e) => log::error!("failed to restore callbacks from {:?}: {}", path, e),
+        }
+
+        tokio::spawn(server::save_state_periodically(
+            callbacks.clone(),
+            path.clone(),
+            STATE_SAVE_INTERVAL,
+        ));
+    }
+
+    let idle: Duration = args.idle_client_timeout.into();
+    tokio::spawn(reaper::run(
+        dispatcher.reaper(),
+        dispatcher.handler(),
+        idle,
+        idle.min(reaper::REAP_INTERVAL),
+    ));
+
+    let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+    // clients can register again once the dispatcher is running
+    if args.state_file.is_some() {
+        tokio::spawn(async move {
+            let notified = callbacks.lock().await.notify_restart().await;
+            if notified > 0 {
+                log::info!("notified {} restored file watches of the restart", notified);
+            }
+        });
+    }
+
+    tokio::select! {
+        res = dispatch => res.unwrap(),
+        _ = tokio::signal::ctrl_c() => {
+            if let Some(path) = &args.state_file {
+                match callbacks.lock().await.save_state(path) {
+                    Ok(_) => log::info!("saved callback registrations to {:?}", path),
+                    Err(e) => log::error!("failed to save callbacks to {:?}: {}", path, e),
+                }
+            }
+        }
+    }
 
     return;
 }
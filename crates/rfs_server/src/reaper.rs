@@ -0,0 +1,88 @@
+//! Cleanup of the state kept for clients that went idle.
+//!
+//! Clients that crash or lose their network never unregister their callbacks, release their
+//! locks or close their files. A client that has not made a request within the idle timeout
+//! is considered gone, and everything the server and its dispatcher keep for it is freed.
+
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use futures::lock::Mutex;
+use rfs::middleware::ClientReaper;
+
+use crate::server::{RegisteredFileUpdates, RfsServer};
+
+/// Longest time between two reaps
+pub const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State freed in a single reap
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReapSummary {
+    pub clients: usize,
+    pub responses: usize,
+    pub callbacks: usize,
+    pub subscriptions: usize,
+    pub open_files: usize,
+    pub range_locks: usize,
+}
+
+impl Display for ReapSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} idle clients: freed {} cached responses, {} callbacks, {} topic subscriptions, {} open files, {} range locks",
+            self.clients,
+            self.responses,
+            self.callbacks,
+            self.subscriptions,
+            self.open_files,
+            self.range_locks
+        )
+    }
+}
+
+/// Free the state of clients that have been idle for longer than `idle`.
+pub async fn reap_once(
+    reaper: &ClientReaper,
+    server: &Mutex<RfsServer>,
+    callbacks: Option<&Mutex<RegisteredFileUpdates>>,
+    idle: Duration,
+) -> ReapSummary {
+    let (clients, responses) = reaper.reap(idle).await;
+    if clients.is_empty() {
+        return ReapSummary::default();
+    }
+
+    let (open_files, range_locks) = server.lock().await.forget_clients(&clients);
+    let (callbacks, subscriptions) = match callbacks {
+        Some(c) => c.lock().await.forget_clients(&clients),
+        None => (0, 0),
+    };
+
+    ReapSummary {
+        clients: clients.len(),
+        responses,
+        callbacks,
+        subscriptions,
+        open_files,
+        range_locks,
+    }
+}
+
+/// Reap idle clients indefinitely, every `interval`.
+pub async fn run(
+    reaper: ClientReaper,
+    server: Arc<Mutex<RfsServer>>,
+    idle: Duration,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let callbacks = server.lock().await.callbacks();
+        let summary = reap_once(&reaper, &server, callbacks.as_deref(), idle).await;
+
+        if summary.clients > 0 {
+            log::info!("{}", summary);
+        }
+    }
+}
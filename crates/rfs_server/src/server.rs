@@ -1,29 +1,73 @@
 //! Server definition and implementations
 #![allow(unused)]
 
+mod callback_state;
 mod callbacks;
+mod journal;
+mod update_log;
 
 use futures::{channel::mpsc, SinkExt, StreamExt};
 // use crate::server::middleware::PayloadHandler;
 use rfs::{
-    fs::{VirtDirEntry, VirtIOErr},
-    middleware::{InvokeError, MiddlewareData, PayloadHandler},
+    fs::{DirListing, FileChunk, VirtDirEntry, VirtIOErr, VirtMetadataLite, VirtPath, VirtReadDir},
+    middleware::{
+        current_client, request_cancellation, ClientId, DispatchStats, InvokeError, MiddlewareData,
+        PayloadHandler, ReceivedPayload, SharedProtocol,
+    },
+    path_policy::PathPolicy,
     payload_handler, RemoteMethodSignature, RemotelyInvocable,
 };
 use std::{
     collections::HashMap,
     fs::{self, OpenOptions},
-    io::Write,
-    net::SocketAddrV4,
+    io::{Read, Seek, SeekFrom, Write},
+    net::{Ipv4Addr, SocketAddrV4},
     num::NonZeroU8,
     path::{Path, PathBuf},
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
+pub use callback_state::*;
 pub use callbacks::*;
+pub use journal::*;
 use rfs::interfaces::*;
+use serde_bytes::ByteBuf;
+pub use update_log::*;
+
+/// Request statistics of the dispatcher serving this server.
+pub static DISPATCH_STATS: OnceLock<Arc<futures::lock::Mutex<DispatchStats>>> = OnceLock::new();
+
+/// Protocol of the dispatcher serving this server, which counts reaped transfers.
+pub static TRANSFER_PROTOCOL: OnceLock<SharedProtocol> = OnceLock::new();
+
+/// Time taken by [SimpleOps::compute_fib], regardless of the number
+const FIB_DURATION: Duration = Duration::from_secs(5);
+
+/// Number of progress reports sent during [SimpleOps::compute_fib]
+const FIB_STEPS: u32 = 10;
+
+/// Clients idle for longer than this are not reported as sessions
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Open files that are not registered again within this time are considered closed
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Number of bytes at the start of a spilled invocation read to find the contents of an upload
+const SPILLED_HEAD_LEN: usize = 4096;
+
+/// Suffix of the temporary files uploads are written to, which are hidden from listings
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Number of names tried for the temporary file of an upload before giving up
+const PARTIAL_ATTEMPTS: u32 = 16;
+
+/// Used to give the temporary file of each upload a unique name
+static PARTIAL_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 pub struct RfsServer {
@@ -42,14 +86,74 @@ pub struct RfsServer {
     /// A path can have multiple callbacks.
     pub file_upd_callbacks: HashMap<String, Vec<FileUpdateCallback>>,
 
+    /// Change counters of directories, relative to the base path.
+    ///
+    /// A counter is incremented by any mutation inside the directory.
+    pub dir_counters: HashMap<VirtPath, u64>,
+
+    /// Versions of written files, relative to the base path.
+    ///
+    /// A version is incremented by every write to the file.
+    pub file_versions: HashMap<VirtPath, u64>,
+
+    /// Recent updates of written files, replayed to watchers that missed them
+    pub update_log: UpdateLog,
+
+    /// Clients that have a file open, and when they last registered it.
+    pub presence: HashMap<VirtPath, HashMap<ClientId, (OpenMode, Instant)>>,
+
+    /// Byte range locks held on files, relative to the base path
+    pub range_locks: HashMap<VirtPath, Vec<RangeLock>>,
+
+    /// Paths that do not satisfy this policy are not resolved.
+    pub path_policy: PathPolicy,
+
+    /// Journal that mutating operations are recorded to before they are applied.
+    pub journal: Option<Journal>,
+
+    /// Callbacks of this server, used in place of [FILE_UPDATE_CALLBACKS] if set.
+    pub callbacks: Option<Arc<futures::lock::Mutex<RegisteredFileUpdates>>>,
+
     // these are used for testing
     pub protocol_name: String,
     pub idempotent_counter: HashMap<u64, u64>,
+    pub counter: u64,
 }
 
 #[derive(Debug)]
 pub struct FileUpdateCallback {
+    id: SubscriptionId,
+
+    /// Client that registered the callback
+    client: ClientId,
+    addr: SocketAddrV4,
+    mode: WatchMode,
+}
+
+/// A watch on the entries of a directory, kept until it is unregistered
+#[derive(Debug)]
+pub struct DirUpdateCallback {
+    id: SubscriptionId,
+
+    /// Client that registered the callback
+    client: ClientId,
+    addr: SocketAddrV4,
+
+    /// Changes in subdirectories are sent as well
+    recursive: bool,
+}
+
+/// A subscription to every update of a file, kept until it is removed or expires
+#[derive(Debug)]
+pub struct FileSubscription {
+    path: String,
+
+    /// Client that subscribed
+    client: ClientId,
     addr: SocketAddrV4,
+
+    /// Last time the subscription was renewed
+    renewed: Instant,
 }
 
 impl Default for RfsServer {
@@ -65,9 +169,18 @@ impl Default for RfsServer {
             base: PathBuf::from(exe_dir),
             read_cache: Default::default(),
             file_upd_callbacks: Default::default(),
+            dir_counters: Default::default(),
+            file_versions: Default::default(),
+            update_log: Default::default(),
+            presence: Default::default(),
+            range_locks: Default::default(),
+            path_policy: Default::default(),
+            journal: None,
+            callbacks: None,
 
             protocol_name: Default::default(),
             idempotent_counter: Default::default(),
+            counter: 0,
         }
     }
 }
@@ -82,17 +195,137 @@ impl RfsServer {
                 .expect("path must be valid"),
             read_cache: Default::default(),
             file_upd_callbacks: Default::default(),
+            dir_counters: Default::default(),
+            file_versions: Default::default(),
+            update_log: Default::default(),
+            presence: Default::default(),
+            range_locks: Default::default(),
+            path_policy: Default::default(),
+            journal: None,
+            callbacks: None,
 
             protocol_name: Default::default(),
             idempotent_counter: Default::default(),
+            counter: 0,
         }
     }
 
+    /// Use these callbacks instead of the global [FILE_UPDATE_CALLBACKS].
+    pub fn with_callbacks(
+        mut self,
+        callbacks: Arc<futures::lock::Mutex<RegisteredFileUpdates>>,
+    ) -> Self {
+        self.callbacks = Some(callbacks);
+        self
+    }
+
+    /// Returns the callbacks of this server, or the global callbacks if it has none of its own.
+    pub fn callbacks(&self) -> Option<Arc<futures::lock::Mutex<RegisteredFileUpdates>>> {
+        self.callbacks
+            .clone()
+            .or_else(|| FILE_UPDATE_CALLBACKS.get().cloned())
+    }
+
     /// Set the protocol name
     pub fn set_protocol_name(&mut self, name: String) {
         self.protocol_name = name;
     }
 
+    /// Open the journal in the base directory, and apply the operations interrupted by a crash.
+    ///
+    /// Mutating operations are recorded to the journal from then on.
+    /// Returns the number of operations applied again.
+    pub fn open_journal(&mut self) -> std::io::Result<usize> {
+        let (mut journal, pending) = Journal::open(&self.base)?;
+
+        for op in &pending {
+            log::info!("applying interrupted operation {:?}", op);
+
+            // the operation may have been applied before the crash
+            if let Err(e) = op.apply(&self.base) {
+                log::warn!("discarding interrupted operation {:?}: {}", op, e);
+            }
+        }
+
+        journal.clear()?;
+        self.journal = Some(journal);
+
+        Ok(pending.len())
+    }
+
+    /// Apply a mutating operation, recording it to the journal first if there is one.
+    ///
+    /// The operation is not applied if it cannot be recorded.
+    fn journaled(&mut self, op: JournalOp) -> std::io::Result<()> {
+        let seq = match &mut self.journal {
+            Some(journal) => Some(journal.begin(&op)?),
+            None => None,
+        };
+
+        let res = op.apply(&self.base);
+
+        if let (Some(journal), Some(seq)) = (&mut self.journal, seq) {
+            if let Err(e) = journal.done(seq) {
+                log::error!("failed to record {:?} to the journal: {}", op, e);
+            }
+        }
+
+        res
+    }
+
+    /// Returns the clients that have a file open, other than `except`.
+    ///
+    /// Expired registrations are removed.
+    fn watchers(&mut self, path: &VirtPath, except: ClientId) -> Vec<Watcher> {
+        let clients = match self.presence.get_mut(path) {
+            Some(c) => c,
+            None => return vec![],
+        };
+
+        clients.retain(|_, (_, registered)| registered.elapsed() < PRESENCE_TIMEOUT);
+
+        let mut watchers = clients
+            .iter()
+            .filter(|(client, _)| **client != except)
+            .map(|(client, (mode, _))| Watcher {
+                client: *client,
+                mode: *mode,
+            })
+            .collect::<Vec<_>>();
+        watchers.sort_by_key(|w| w.client);
+
+        if clients.is_empty() {
+            self.presence.remove(path);
+        }
+
+        watchers
+    }
+
+    /// Close the files and release the range locks of clients that are gone.
+    ///
+    /// Returns the number of open files and locks removed.
+    pub fn forget_clients(&mut self, clients: &[ClientId]) -> (usize, usize) {
+        let mut open_files = 0;
+        self.presence.retain(|_, open| {
+            let before = open.len();
+            open.retain(|client, _| !clients.contains(client));
+            open_files += before - open.len();
+
+            !open.is_empty()
+        });
+
+        let mut range_locks = 0;
+        self.range_locks.retain(|_, locks| {
+            let before = locks.len();
+            locks.retain(|lock| !clients.contains(&lock.client));
+            range_locks += before - locks.len();
+
+            !locks.is_empty()
+        });
+
+        (open_files, range_locks)
+    }
+
     /// Checks if a provided path contains prev-dir path segments `..`.
     /// Paths are not resolved at the OS-level, as they might not exist yet.
     ///
@@ -106,7 +339,17 @@ impl RfsServer {
     /// Resolve the given relative path to a full path.
     ///
     /// Paths with 'backdirs' `./../` will not be resolved, and will return `None`.
+    /// Neither will paths rejected by the path policy, or the journal.
     fn resolve_path<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let relative = path.as_ref().to_string_lossy();
+        if let Err(e) = self.path_policy.check_path(&relative) {
+            log::error!("invalid path {:?}: {}", relative, e);
+            return None;
+        }
+        if Self::is_journal(&VirtPath::from(path.as_ref())) {
+            return None;
+        }
+
         let mut full_path = self.base.clone();
         full_path.push(path);
 
@@ -116,24 +359,305 @@ impl RfsServer {
         }
     }
 
-    /// Resolve the given relative path. The path must exist for method to function.
+    /// Resolve a path to an existing file or directory, following links.
+    ///
+    /// Returns `None` if the path does not exist, or if it leads outside the base directory
+    /// through a link.
+    fn resolve_existing<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let full_path = self.resolve_path(path)?.canonicalize().ok()?;
+
+        match full_path.starts_with(&self.base) {
+            true => Some(full_path),
+            false => {
+                log::error!("path {:?} leads outside the base directory", full_path);
+                None
+            }
+        }
+    }
+
+    /// Checks if a path relative to the base directory is the journal file.
+    fn is_journal(path: &VirtPath) -> bool {
+        path.as_str() == JOURNAL_FILE
+    }
+
+    /// Checks if a path is left out of directory listings:
+    /// the journal, and the temporary files of uploads in progress.
+    fn is_hidden(path: &VirtPath) -> bool {
+        let is_partial = path
+            .file_name()
+            .is_some_and(|name| name.starts_with('.') && name.ends_with(PARTIAL_SUFFIX));
+
+        Self::is_journal(path) || is_partial
+    }
+
+    /// Checks the path of a file or directory to be created against the path policy.
+    fn check_new_path(&self, path: &VirtPath) -> Result<(), VirtIOErr> {
+        self.path_policy.check_path(path.as_str()).map_err(|e| {
+            log::error!("invalid path {:?}: {}", path, e);
+            VirtIOErr::InvalidInput
+        })
+    }
+
+    /// Returns the canonical form of a path, relative to the base directory.
     ///
-    /// All links are resolved. If the path contains backdirs, this will return `None`.
-    fn resolve_relative_path<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+    /// Links are resolved if the path exists. Watches and file versions are keyed by this path,
+    /// so every spelling of a path refers to the same file.
+    /// If the path contains backdirs or resolves to outside the base directory, this will return `None`.
+    fn canonical_path(&self, path: &VirtPath) -> Option<VirtPath> {
         let resolved = self.resolve_path(path)?;
 
-        let full = resolved.canonicalize().ok()?;
+        match resolved.canonicalize() {
+            Ok(full) => Some(VirtPath::from(full.strip_prefix(&self.base).ok()?)),
+            Err(_) => Some(path.clone()),
+        }
+    }
+
+    /// Returns the change counter of a directory
+    fn dir_counter(&self, path: &VirtPath) -> u64 {
+        self.dir_counters.get(path).copied().unwrap_or_default()
+    }
+
+    /// Increment the version of a file, returning the new version.
+    fn bump_file_version(&mut self, path: &VirtPath) -> u64 {
+        let version = self.file_versions.entry(path.clone()).or_default();
+        *version += 1;
+        *version
+    }
+
+    /// Returns the notice of an update to a file, at the next version of the file.
+    ///
+    /// The notice is recorded in the update log.
+    fn update_notice(
+        &mut self,
+        path: &VirtPath,
+        author: Option<u64>,
+        update: FileUpdate,
+    ) -> FileUpdateNotice {
+        let notice = FileUpdateNotice {
+            version: self.bump_file_version(path),
+            author,
+            update,
+        };
+        self.update_log.record(path, notice.clone());
+
+        notice
+    }
+
+    /// Increment the change counters of all directories containing a mutated path.
+    fn bump_dir_counters(&mut self, path: &VirtPath) {
+        let mut dir = path.parent();
+
+        while let Some(d) = dir {
+            dir = d.parent();
+            *self.dir_counters.entry(d).or_default() += 1;
+        }
+    }
+
+    /// Returns the metadata of a file, if a callback for the file wants it.
+    fn watched_metadata(
+        &self,
+        callbacks: &RegisteredFileUpdates,
+        path: &VirtPath,
+    ) -> Option<VirtMetadataLite> {
+        if !callbacks.has_watchers(path.as_str(), WatchMode::Metadata) {
+            return None;
+        }
+
+        let full_path = self.resolve_path(path)?;
+        fs::metadata(full_path).ok().map(VirtMetadataLite::from)
+    }
+
+    /// Send an event to the watches of the directories containing the changed paths.
+    async fn notify_dir_watches(&self, event: DirEvent) {
+        if let Some(callbacks) = self.callbacks() {
+            let num_sent = callbacks.lock().await.trigger_dir_update(event).await;
+
+            if num_sent > 0 {
+                log::info!("sent directory event to {} watches", num_sent);
+            }
+        }
+    }
+
+    /// Handle an invocation that was spilled to disk while being received.
+    ///
+    /// The contents of [PrimitiveFsOps::write_all] are streamed to the file.
+    /// Other invocations are read into memory.
+    async fn handle_spilled_upload(
+        &mut self,
+        payload: ReceivedPayload,
+    ) -> Result<Vec<u8>, InvokeError> {
+        let receive_err = |e| {
+            log::error!("failed to read spilled invocation: {}", e);
+            InvokeError::RemoteReceiveError
+        };
+
+        let head = payload
+            .read_range(0, SPILLED_HEAD_LEN)
+            .map_err(receive_err)?;
+        let signature = PrimitiveFsOpsWriteAll::remote_method_signature();
+
+        let cut = match rfs::matches_signature(&head, signature) {
+            true => {
+                let (format, len) = rfs::envelope_header(&head[signature.len()..])?;
+                let body = signature.len() + rfs::ENVELOPE_LEN;
+                if body + len != payload.len() {
+                    return Err(InvokeError::DeserializationFailed);
+                }
+
+                // packed contents cannot be streamed
+                match format {
+                    rfs::WireFormat::Plain => {
+                        rfs::ser_de::cut_bytes_field(&head[body..], "contents")
+                            .map(|cut| (body, cut))
+                    }
+                    rfs::WireFormat::Packed => None,
+                }
+            }
+            false => None,
+        };
+
+        let (body, cut) = match cut {
+            Some(c) => c,
+            None => {
+                let bytes = payload.into_bytes().map_err(receive_err)?;
+                return self.handle_payload(&bytes).await;
+            }
+        };
+
+        let contents = body + cut.offset;
+        let tail = payload
+            .read_range(contents + cut.len, SPILLED_HEAD_LEN)
+            .map_err(receive_err)?;
+
+        let path = match rfs::ser_de::deserialize_exact([cut.head, tail].concat().as_slice()) {
+            Ok(PrimitiveFsOpsWriteAll::Request { path, .. }) => path,
+            _ => return Err(InvokeError::DeserializationFailed),
+        };
+
+        log::debug!("streaming {} bytes to {:?}", cut.len, path);
+        let reader = payload.reader_at(contents).map_err(receive_err)?;
+        let written = self.write_all_from(path, reader.take(cut.len as u64)).await;
+
+        Ok(PrimitiveFsOpsWriteAll::Response(written).invoke_bytes())
+    }
+
+    /// Create the temporary file an upload to `path` is written to, next to the file.
+    ///
+    /// Each upload gets a file of its own, which did not exist before, so concurrent uploads
+    /// and files left behind by an earlier run are never written over. It is hidden from listings.
+    fn create_partial(&self, path: &VirtPath) -> std::io::Result<(VirtPath, fs::File)> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+
+        let mut attempts = 0;
+        loop {
+            let partial = VirtPath::from(format!(
+                ".{}.{}{}",
+                name,
+                PARTIAL_COUNTER.fetch_add(1, Ordering::Relaxed),
+                PARTIAL_SUFFIX
+            ));
+            let partial = match path.parent() {
+                Some(dir) => dir.join(partial),
+                None => partial,
+            };
+
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.base.join(&partial))
+            {
+                Ok(file) => return Ok((partial, file)),
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::AlreadyExists
+                        && attempts < PARTIAL_ATTEMPTS =>
+                {
+                    attempts += 1
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Replace the contents of a file with the bytes of a reader, creating the file if it does not exist.
+    ///
+    /// The contents are written to a temporary file, which replaces the file,
+    /// so they are never held in memory. They are only read back to send to watchers of the file.
+    async fn write_all_from(&mut self, path: VirtPath, mut contents: impl Read) -> bool {
+        let (full_path, path) = match (self.resolve_path(&path), self.canonical_path(&path)) {
+            (Some(full), Some(canonical)) => (full, canonical),
+            _ => return false,
+        };
+        let (partial, mut file) = match self.create_partial(&path) {
+            Ok(created) => created,
+            Err(e) => {
+                log::error!("failed to create a temporary file for {:?}: {}", path, e);
+                return false;
+            }
+        };
+
+        let written = std::io::copy(&mut contents, &mut file).and_then(|_| {
+            // keep the permissions of the file being replaced
+            if let Ok(metadata) = fs::metadata(&full_path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()
+        });
+        if let Err(e) = written {
+            log::error!("failed to write {:?}: {}", partial, e);
+            let _ = fs::remove_file(self.base.join(&partial));
+            return false;
+        }
+
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
 
-        let relative = full.strip_prefix(&self.base).ok()?;
+        // previous contents are only needed to diff against
+        let prev = match lock.has_diff_watchers(path.as_str()) {
+            true => fs::read(&full_path).ok(),
+            false => None,
+        };
+
+        if self
+            .journaled(JournalOp::Rename {
+                from: partial,
+                to: path.clone(),
+            })
+            .is_err()
+        {
+            return false;
+        }
+        self.bump_dir_counters(&path);
+
+        if !lock.is_watched(path.as_str()) {
+            // the update log starts over at the next update, as it does not hold this one
+            self.bump_file_version(&path);
+            return true;
+        }
+
+        let contents = match fs::read(&full_path) {
+            Ok(c) => c,
+            Err(_) => return true,
+        };
+        let notice = self.update_notice(&path, None, FileUpdate::Overwrite(contents));
+        let metadata = self.watched_metadata(&lock, &path);
+        let num_triggered = lock
+            .trigger_file_update(path.as_str(), notice, prev.as_deref(), metadata)
+            .await;
+
+        if let Some(num) = num_triggered {
+            log::info!("triggered callbacks: {:?} ", num);
+        }
 
-        Some(relative.to_path_buf())
+        true
     }
 }
 
 #[async_trait]
 impl PrimitiveFsOps for RfsServer {
-    async fn read_all(&mut self, path: String) -> Vec<u8> {
-        let full_path = match self.resolve_path(&path) {
+    async fn read_all(&mut self, path: VirtPath) -> Vec<u8> {
+        let full_path = match self.resolve_existing(&path) {
             Some(p) => p,
             None => return vec![],
         };
@@ -152,16 +676,18 @@ impl PrimitiveFsOps for RfsServer {
         file
     }
 
-    async fn read_bytes(&mut self, path: String, offset: usize, len: usize) -> Vec<u8> {
-        let data = match self.read_cache.get(&path) {
+    async fn read_bytes(&mut self, path: VirtPath, offset: usize, len: usize) -> Vec<u8> {
+        let data = match self.read_cache.get(path.as_str()) {
             Some(contents) => {
                 let slice = &contents[offset..(offset + len)];
 
                 slice.to_vec()
             }
             None => {
-                let mut full_path = self.base.clone();
-                full_path.push(&path);
+                let full_path = match self.resolve_existing(&path) {
+                    Some(p) => p,
+                    None => return vec![],
+                };
 
                 let file_data = match fs::read(full_path) {
                     Ok(d) => d,
@@ -170,7 +696,7 @@ impl PrimitiveFsOps for RfsServer {
 
                 let slice = &file_data[offset..(offset + len)];
                 let res = slice.to_vec();
-                self.read_cache.insert(path.clone(), file_data);
+                self.read_cache.insert(path.to_string(), file_data);
 
                 res
             }
@@ -179,37 +705,53 @@ impl PrimitiveFsOps for RfsServer {
         data
     }
 
-    async fn write_all(&mut self, path: String, contents: Vec<u8>) -> bool {
-        let mut full_path = self.base.clone();
-        full_path.push(&path);
+    async fn write_all(&mut self, path: VirtPath, contents: ByteBuf) -> bool {
+        let contents = contents.into_vec();
+        let (full_path, path) = match (self.resolve_path(&path), self.canonical_path(&path)) {
+            (Some(full), Some(canonical)) => (full, canonical),
+            _ => return false,
+        };
 
-        let relative_path = full_path
-            .strip_prefix(&self.base)
-            .unwrap()
-            .to_str()
-            .unwrap();
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
 
-        let mut lock = FILE_UPDATE_CALLBACKS
-            .get()
-            .expect("must be initialized")
-            .lock()
-            .await;
+        // previous contents are only needed to diff against
+        let prev = match lock.has_diff_watchers(path.as_str()) {
+            true => fs::read(&full_path).ok(),
+            false => None,
+        };
+
+        let notice = self.update_notice(&path, None, FileUpdate::Overwrite(contents.clone()));
+
+        if self
+            .journaled(JournalOp::Write {
+                path: path.clone(),
+                contents,
+            })
+            .is_err()
+        {
+            return false;
+        }
+        self.bump_dir_counters(&path);
 
+        let metadata = self.watched_metadata(&lock, &path);
         let num_triggered = lock
-            .trigger_file_update(&relative_path, FileUpdate::Overwrite(contents.clone()))
+            .trigger_file_update(path.as_str(), notice, prev.as_deref(), metadata)
             .await;
 
         if let Some(num) = num_triggered {
             log::info!("triggered callbacks: {:?} ", num);
         }
 
-        match fs::write(full_path, contents) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        true
     }
 
-    async fn write_bytes(&mut self, path: String, data: FileUpdate) -> Result<usize, VirtIOErr> {
+    async fn write_bytes(
+        &mut self,
+        path: VirtPath,
+        data: FileUpdate,
+        author: u64,
+    ) -> Result<usize, VirtIOErr> {
         let full_path = match self.resolve_path(&path) {
             Some(p) => p,
             None => return Err(VirtIOErr::NotFound),
@@ -220,22 +762,23 @@ impl PrimitiveFsOps for RfsServer {
         let existing_contents = fs::read(&full_path).map_err(|e| VirtIOErr::from(e))?;
         let overwritten_contents = data.to_owned().update_file(&existing_contents);
 
-        fs::write(&full_path, overwritten_contents).map_err(|e| VirtIOErr::from(e))?;
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+        self.journaled(JournalOp::Write {
+            path: path.clone(),
+            contents: overwritten_contents,
+        })?;
 
-        let relative_path = full_path
-            .strip_prefix(&self.base)
-            .unwrap()
-            .to_str()
-            .unwrap();
+        self.bump_dir_counters(&path);
 
-        let mut lock = FILE_UPDATE_CALLBACKS
-            .get()
-            .expect("must be initialized")
-            .lock()
-            .await;
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
 
         let size = data.len();
-        let num_triggered = lock.trigger_file_update(&path, data).await;
+        let notice = self.update_notice(&path, Some(author), data);
+        let metadata = self.watched_metadata(&lock, &path);
+        let num_triggered = lock
+            .trigger_file_update(path.as_str(), notice, Some(&existing_contents), metadata)
+            .await;
 
         if let Some(num) = num_triggered {
             log::info!("triggered callbacks: {:?} ", num);
@@ -244,7 +787,109 @@ impl PrimitiveFsOps for RfsServer {
         Ok(size)
     }
 
-    async fn create(&mut self, path: String) -> Result<(), VirtIOErr> {
+    async fn write_batch(
+        &mut self,
+        path: VirtPath,
+        updates: Vec<FileUpdate>,
+        author: u64,
+    ) -> Result<usize, VirtIOErr> {
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
+        let existing_contents = fs::read(&full_path)?;
+
+        // every update is checked before anything is written
+        let mut contents = existing_contents.clone();
+        for update in &updates {
+            contents = match update {
+                FileUpdate::Restarted => return Err(VirtIOErr::InvalidInput),
+                FileUpdate::Diff(diff) => std::str::from_utf8(&contents)
+                    .ok()
+                    .and_then(|prev| rfs::fs::apply_diff(prev, diff))
+                    .ok_or(VirtIOErr::InvalidData)?
+                    .into_bytes(),
+                update => update.clone().update_file(&contents),
+            };
+        }
+
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+        self.journaled(JournalOp::Replace {
+            path: path.clone(),
+            contents: contents.clone(),
+        })?;
+        self.bump_dir_counters(&path);
+
+        log::debug!("applied {} updates to {}", updates.len(), path);
+
+        let notice = self.update_notice(&path, Some(author), FileUpdate::Overwrite(contents));
+        if let Some(callbacks) = self.callbacks() {
+            let mut lock = callbacks.lock().await;
+            let metadata = self.watched_metadata(&lock, &path);
+            let num_triggered = lock
+                .trigger_file_update(path.as_str(), notice, Some(&existing_contents), metadata)
+                .await;
+
+            if let Some(num) = num_triggered {
+                log::info!("triggered callbacks: {:?} ", num);
+            }
+        }
+
+        Ok(updates.iter().map(|u| u.len()).sum())
+    }
+
+    async fn append_line(
+        &mut self,
+        path: VirtPath,
+        text: String,
+        author: u64,
+    ) -> Result<usize, VirtIOErr> {
+        // the file may be created, so its path is checked like a new one
+        self.check_new_path(&path)?;
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
+
+        let (existing_contents, created) = match fs::read(&full_path) {
+            Ok(contents) => (contents, false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (vec![], true),
+            Err(e) => return Err(e.into()),
+        };
+
+        let update = FileUpdate::append_line(&existing_contents, &text);
+        let FileUpdate::Append(data) = &update else {
+            unreachable!("lines are appended")
+        };
+
+        // the server handles one request at a time, so nothing is written in between
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&full_path)
+            .and_then(|mut file| file.write_all(data))
+            .map_err(VirtIOErr::from)?;
+
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+        self.bump_dir_counters(&path);
+        if created {
+            self.notify_dir_watches(DirEvent::Created(path.clone()))
+                .await;
+        }
+
+        let size = update.len();
+        let notice = self.update_notice(&path, Some(author), update);
+        if let Some(callbacks) = self.callbacks() {
+            let mut lock = callbacks.lock().await;
+            let metadata = self.watched_metadata(&lock, &path);
+            let num_triggered = lock
+                .trigger_file_update(path.as_str(), notice, Some(&existing_contents), metadata)
+                .await;
+
+            if let Some(num) = num_triggered {
+                log::info!("triggered callbacks: {:?} ", num);
+            }
+        }
+
+        Ok(size)
+    }
+
+    async fn create(&mut self, path: VirtPath) -> Result<(), VirtIOErr> {
+        self.check_new_path(&path)?;
         let full_path = match self.resolve_path(&path) {
             Some(p) => p,
             None => return Err(VirtIOErr::NotFound),
@@ -252,8 +897,13 @@ impl PrimitiveFsOps for RfsServer {
 
         log::debug!("creating file at {:?}", full_path);
 
-        match std::fs::File::create(full_path) {
-            Ok(_) => Ok(()),
+        match self.journaled(JournalOp::Create(path.clone())) {
+            Ok(_) => {
+                self.bump_dir_counters(&path);
+                self.update_log.forget(&path);
+                self.notify_dir_watches(DirEvent::Created(path)).await;
+                Ok(())
+            }
             Err(e) => {
                 log::error!("failed to create file: {}", e);
                 Err(e.into())
@@ -261,139 +911,715 @@ impl PrimitiveFsOps for RfsServer {
         }
     }
 
-    async fn remove(&mut self, path: String) -> Result<(), VirtIOErr> {
-        let full_path = match self.resolve_path(&path) {
-            Some(p) => p,
-            None => return Err(VirtIOErr::NotFound),
-        };
+    async fn remove(&mut self, path: VirtPath) -> Result<(), VirtIOErr> {
+        if self.resolve_path(&path).is_none() {
+            return Err(VirtIOErr::NotFound);
+        }
 
-        match std::fs::remove_file(full_path) {
-            Ok(_) => Ok(()),
+        match self.journaled(JournalOp::Remove(path.clone())) {
+            Ok(_) => {
+                self.bump_dir_counters(&path);
+                self.update_log.forget(&path);
+                self.notify_dir_watches(DirEvent::Removed(path)).await;
+                Ok(())
+            }
             Err(e) => Err(e.into()),
         }
     }
 
-    async fn rename(&mut self, path: String, from: String, to: String) -> Result<(), VirtIOErr> {
-        todo!()
+    async fn rename(
+        &mut self,
+        path: VirtPath,
+        from: VirtPath,
+        to: VirtPath,
+    ) -> Result<(), VirtIOErr> {
+        let (from, to) = (path.join(from), path.join(to));
+        self.check_new_path(&to)?;
+        match (self.resolve_path(&from), self.resolve_path(&to)) {
+            (Some(_), Some(_)) => (),
+            _ => return Err(VirtIOErr::PermissionDenied),
+        }
+
+        log::debug!("renaming {} to {}", from, to);
+
+        self.journaled(JournalOp::Rename {
+            from: from.clone(),
+            to: to.clone(),
+        })?;
+        self.bump_dir_counters(&from);
+        self.bump_dir_counters(&to);
+        self.update_log.forget(&from);
+        self.update_log.forget(&to);
+        self.notify_dir_watches(DirEvent::Renamed { from, to })
+            .await;
+
+        Ok(())
     }
 
-    async fn mkdir(&mut self, path: String) -> Result<(), VirtIOErr> {
-        let full_path = match self.resolve_path(&path) {
-            Some(p) => p,
-            None => return Err(VirtIOErr::PermissionDenied),
-        };
+    async fn mkdir(&mut self, path: VirtPath) -> Result<(), VirtIOErr> {
+        self.check_new_path(&path)?;
+        if self.resolve_path(&path).is_none() {
+            return Err(VirtIOErr::PermissionDenied);
+        }
 
-        match fs::create_dir(full_path) {
-            Ok(_) => Ok(()),
+        match self.journaled(JournalOp::Mkdir(path.clone())) {
+            Ok(_) => {
+                self.bump_dir_counters(&path);
+                self.notify_dir_watches(DirEvent::Created(path)).await;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    async fn symlink(&mut self, target: VirtPath, link_path: VirtPath) -> Result<(), VirtIOErr> {
+        self.check_new_path(&link_path)?;
+        let full_link = self
+            .resolve_path(&link_path)
+            .ok_or(VirtIOErr::PermissionDenied)?;
+
+        // the link must not give access to anything outside the base directory.
+        // the target is resolved the way the OS will follow it, through any links on the way.
+        let parent = full_link
+            .parent()
+            .and_then(|p| p.canonicalize().ok())
+            .ok_or(VirtIOErr::NotFound)?;
+        let resolved = parent
+            .join(target.as_str())
+            .canonicalize()
+            .ok()
+            .filter(|t| t.starts_with(&self.base))
+            .ok_or(VirtIOErr::PermissionDenied)?;
+
+        log::debug!("linking {:?} to {} ({:?})", full_link, target, resolved);
+
+        match create_symlink(target.as_ref(), &full_link) {
+            Ok(_) => {
+                self.bump_dir_counters(&link_path);
+                self.notify_dir_watches(DirEvent::Created(link_path)).await;
+                Ok(())
+            }
             Err(e) => Err(e.into()),
         }
     }
-    async fn rmdir(&mut self, path: String) -> Result<(), VirtIOErr> {
-        let full_path = match self.resolve_path(&path) {
-            Some(p) => p,
-            None => return Err(VirtIOErr::PermissionDenied),
-        };
 
-        match std::fs::remove_dir_all(full_path) {
-            Ok(_) => Ok(()),
+    async fn hardlink(&mut self, src: VirtPath, dst: VirtPath) -> Result<(), VirtIOErr> {
+        self.check_new_path(&dst)?;
+        let full_src = self
+            .canonical_path(&src)
+            .and_then(|p| self.resolve_path(p))
+            .ok_or(VirtIOErr::PermissionDenied)?;
+        let full_dst = self.resolve_path(&dst).ok_or(VirtIOErr::PermissionDenied)?;
+
+        match fs::hard_link(full_src, full_dst) {
+            Ok(_) => {
+                self.bump_dir_counters(&dst);
+                self.notify_dir_watches(DirEvent::Created(dst)).await;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rmdir(&mut self, path: VirtPath) -> Result<(), VirtIOErr> {
+        if self.resolve_path(&path).is_none() {
+            return Err(VirtIOErr::PermissionDenied);
+        }
+
+        match self.journaled(JournalOp::Rmdir(path.clone())) {
+            Ok(_) => {
+                self.bump_dir_counters(&path);
+                self.update_log.forget(&path);
+                self.notify_dir_watches(DirEvent::Removed(path)).await;
+                Ok(())
+            }
             Err(e) => Err(e.into()),
         }
     }
 
-    async fn read_dir(&mut self, path: String) -> Vec<VirtDirEntry> {
+    async fn read_dir(&mut self, path: VirtPath) -> VirtReadDir {
+        let change_counter = self.dir_counter(&path);
+
         let full_path = match self.resolve_path(&path) {
             Some(p) => p,
-            None => return vec![],
+            None => return VirtReadDir::from([]),
         };
 
         let entries = match fs::read_dir(full_path) {
             Ok(e) => e,
-            Err(_) => return vec![],
+            Err(_) => return VirtReadDir::from([]),
         };
 
-        let virt = entries
+        let mut virt: Vec<_> = entries
             .into_iter()
             .filter_map(|entry| Some(entry.ok()?))
             .filter_map(|entry| VirtDirEntry::from_dir_entry(entry, self.base.clone()))
+            .filter(|entry| !Self::is_hidden(&entry.path.as_str().into()))
             .collect();
+        // keep listings stable between reads
+        virt.sort_by(|a, b| a.path().cmp(b.path()));
 
-        virt
+        VirtReadDir {
+            entries: virt,
+            change_counter,
+        }
     }
 
-    async fn file_size(&mut self, path: String) -> Result<usize, VirtIOErr> {
-        todo!();
-        Ok(0)
+    async fn dir_change_counter(&mut self, path: VirtPath) -> u64 {
+        self.dir_counter(&path)
     }
-}
 
-#[async_trait]
-impl SimpleOps for RfsServer {
-    async fn say_hello(&mut self, content: String) -> bool {
-        println!("Hello, {}!", content);
+    async fn read_chunk(
+        &mut self,
+        path: VirtPath,
+        offset: usize,
+        len: usize,
+    ) -> Result<FileChunk, VirtIOErr> {
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
 
-        true
+        let mut file = fs::File::open(full_path)?;
+        let size = file.metadata()?.len();
+        file.seek(SeekFrom::Start(offset as u64))?;
+
+        let mut data = Vec::new();
+        file.take(len.min(rfs::fs::MAX_CHUNK_SIZE) as u64)
+            .read_to_end(&mut data)?;
+
+        Ok(FileChunk { data, size })
     }
 
-    async fn compute_fib(&mut self, fib_num: u8) -> u64 {
-        // pretend that some expensive computation is taking place
-        tokio::time::sleep(Duration::from_secs(5)).await;
-
-        match fib_num {
-            0 => 0,
-            1 => 1,
-            other => {
-                let mut sml = 0;
-                let mut big = 1;
-
-                for _ in 2..=other {
-                    let next = sml + big;
-                    sml = big;
-                    big = next;
-                }
+    async fn file_size(&mut self, path: VirtPath) -> Result<usize, VirtIOErr> {
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
 
-                big
-            }
-        }
+        Ok(fs::metadata(full_path)?.len() as usize)
     }
-}
 
-#[async_trait]
-impl CallbackOps for RfsServer {
-    async fn register_file_update(
-        &mut self,
-        path: String,
-        return_addr: SocketAddrV4,
-    ) -> Result<(), VirtIOErr> {
-        let (send, mut recv) = mpsc::channel::<Arc<FileUpdate>>(1);
+    async fn last_modified(&mut self, path: VirtPath) -> Result<u64, VirtIOErr> {
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
 
-        let handle = FileUpdateCallback { addr: return_addr };
+        let modified = fs::metadata(full_path)?
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
 
-        // get the relative path to the server's base
+        Ok(modified.as_nanos() as u64)
+    }
+
+    async fn stat(&mut self, path: VirtPath) -> Option<VirtMetadataLite> {
+        let full_path = self.resolve_path(&path)?;
+
+        fs::metadata(full_path).ok().map(VirtMetadataLite::from)
+    }
+
+    async fn stat_many(&mut self, paths: Vec<String>) -> Vec<Option<VirtMetadataLite>> {
+        let mut stats = Vec::with_capacity(paths.len());
+        for path in paths {
+            stats.push(self.stat(path.into()).await);
+        }
+
+        stats
+    }
+}
+
+#[async_trait]
+impl ImmutableFileOps for RfsServer {
+    async fn read_file(
+        &mut self,
+        path: VirtPath,
+        offset: usize,
+        len: Option<usize>,
+    ) -> Result<Vec<u8>, VirtIOErr> {
+        let full_path = self.resolve_existing(&path).ok_or(VirtIOErr::NotFound)?;
+
+        log::debug!("reading {:?} bytes of {:?} from {}", len, full_path, offset);
+
+        let mut file = fs::File::open(full_path)?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+
+        let mut contents = Vec::new();
+        match len {
+            Some(l) => file.take(l as u64).read_to_end(&mut contents)?,
+            None => file.read_to_end(&mut contents)?,
+        };
+
+        Ok(contents)
+    }
+
+    async fn ls(
+        &mut self,
+        path: VirtPath,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<DirListing, VirtIOErr> {
+        let change_counter = self.dir_counter(&path);
+        let full_path = self.resolve_path(&path).ok_or(VirtIOErr::NotFound)?;
+
+        let mut entries: Vec<_> = fs::read_dir(full_path)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| VirtDirEntry::from_dir_entry(entry, &self.base))
+            .filter(|entry| !Self::is_hidden(&entry.path.as_str().into()))
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let total = entries.len();
+
+        // entries added or removed before the cursor do not shift the next page
+        let start = match &cursor {
+            Some(c) => entries.partition_point(|e| e.path <= *c),
+            None => 0,
+        };
+        let end = (start + limit.clamp(1, rfs::fs::MAX_LS_PAGE)).min(total);
+        let page = entries.drain(start..end).collect::<Vec<_>>();
+
+        let cursor = match end < total {
+            true => page.last().map(|e| e.path.clone()),
+            false => None,
+        };
+
+        Ok(DirListing {
+            entries: page,
+            cursor,
+            total,
+            change_counter,
+        })
+    }
+}
+
+#[async_trait]
+impl SimpleOps for RfsServer {
+    async fn say_hello(&mut self, content: String) -> bool {
+        println!("Hello, {}!", content);
+
+        true
+    }
+
+    async fn compute_fib(
+        &mut self,
+        fib_num: u8,
+        progress_addr: Option<SocketAddrV4>,
+    ) -> Result<u64, FibError> {
+        if fib_num > MAX_FIB_NUM {
+            return Err(FibError::OutOfRange(fib_num));
+        }
+
+        let cancellation = request_cancellation().unwrap_or_default();
+        let progress = match progress_addr {
+            Some(addr) => {
+                let callbacks = self.callbacks().expect("must be initialized");
+                let lock = callbacks.lock().await;
+
+                lock.sender().await.ok().map(|s| (s, addr))
+            }
+            None => None,
+        };
+
+        // pretend that some expensive computation is taking place
+        for step in 1..=FIB_STEPS {
+            tokio::select! {
+                _ = cancellation.cancelled() => {
+                    log::info!("fib({}) cancelled at step {}", fib_num, step);
+                    return Err(FibError::Cancelled);
+                }
+                _ = tokio::time::sleep(FIB_DURATION / FIB_STEPS) => (),
+            }
+
+            if let Some((sender, addr)) = &progress {
+                let report = rfs::fib::encode_progress(&FibProgress {
+                    fib_num,
+                    step,
+                    steps: FIB_STEPS,
+                });
+
+                if let Err(e) = sender.send(*addr, &report).await {
+                    log::error!("failed to report fib progress to {}: {}", addr, e);
+                }
+            }
+        }
+
+        Ok(fib(fib_num))
+    }
+}
+
+/// Returns the Nth fibonacci number. `n` must not be larger than [MAX_FIB_NUM].
+fn fib(n: u8) -> u64 {
+    match n {
+        0 => 0,
+        _ => {
+            (1..n)
+                .fold((0_u64, 1_u64), |(sml, big), _| (big, sml + big))
+                .1
+        }
+    }
+}
+
+#[async_trait]
+impl CallbackOps for RfsServer {
+    async fn register_file_update(
+        &mut self,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr> {
+        self.register_file_watch(path, return_addr, WatchMode::Raw)
+            .await
+    }
+
+    async fn register_file_watch(
+        &mut self,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+    ) -> Result<SubscriptionId, VirtIOErr> {
+        let (send, mut recv) = mpsc::channel::<Arc<FileUpdate>>(1);
+
+        let client = current_client().unwrap_or(return_addr.into());
+        let id = next_subscription_id();
+        let handle = FileUpdateCallback {
+            id,
+            client,
+            addr: return_addr,
+            mode,
+        };
+
+        // files must exist to be watched
+        if !self.resolve_path(&path).is_some_and(|p| p.is_file()) {
+            return Err(VirtIOErr::NotFound);
+        }
         let relative_path = self
-            .resolve_relative_path(&path)
+            .canonical_path(&path)
             .ok_or(VirtIOErr::NotFound)?
-            .to_str()
-            .expect("path must be valid")
             .to_string();
 
-        let mut lock = FILE_UPDATE_CALLBACKS
-            .get()
-            .expect("should be initialized")
-            .lock()
-            .await;
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
 
-        log::debug!("registering callback for {}", relative_path);
+        log::debug!("registering callback {} for {}", id, relative_path);
 
-        // create a receiver and push the channel to the callback list
+        // create a receiver and push the channel to the callback list.
+        // a client registering again replaces its callback, which may be for an address it no longer uses
         match lock.lookup.get_mut(&relative_path) {
-            Some(callbacks) => callbacks.push(handle),
+            Some(callbacks) => {
+                callbacks.retain(|cb| cb.client != client);
+                callbacks.push(handle)
+            }
             None => {
                 lock.lookup.insert(relative_path.clone(), vec![handle]);
             }
         };
 
+        Ok(id)
+    }
+
+    async fn resume_file_watch(
+        &mut self,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+        mode: WatchMode,
+        version: u64,
+    ) -> Result<(SubscriptionId, MissedUpdates), VirtIOErr> {
+        let id = self
+            .register_file_watch(path.clone(), return_addr, mode)
+            .await?;
+
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+        let current = self.file_versions.get(&path).copied().unwrap_or_default();
+        let missed = self.update_log.since(&path, version, current);
+
+        if let MissedUpdates::Replay(updates) = &missed {
+            log::debug!("replaying {} updates of {} to {}", updates.len(), path, id);
+        }
+
+        Ok((id, missed))
+    }
+
+    async fn register_dir_update(
+        &mut self,
+        path: VirtPath,
+        recursive: bool,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr> {
+        // directories must exist to be watched
+        if !self.resolve_path(&path).is_some_and(|p| p.is_dir()) {
+            return Err(VirtIOErr::NotFound);
+        }
+        let relative_path = self
+            .canonical_path(&path)
+            .ok_or(VirtIOErr::NotFound)?
+            .to_string();
+
+        let client = current_client().unwrap_or(return_addr.into());
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        let id = lock.register_dir(relative_path.clone(), client, return_addr, recursive);
+        log::debug!("registering directory watch {} for {}", id, relative_path);
+
+        Ok(id)
+    }
+
+    async fn unregister_file_watch(&mut self, path: VirtPath, return_addr: SocketAddrV4) -> bool {
+        let relative_path = match self.canonical_path(&path) {
+            Some(p) => p.to_string(),
+            None => return false,
+        };
+
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        log::debug!("unregistering callback for {}", relative_path);
+
+        let client = current_client().unwrap_or(return_addr.into());
+        lock.unregister_file_watch(&relative_path, client, return_addr)
+    }
+
+    async fn unregister(&mut self, id: SubscriptionId) -> bool {
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        log::debug!("unregistering callback {}", id);
+
+        lock.unregister(id, current_client())
+    }
+
+    async fn subscribe(
+        &mut self,
+        path: VirtPath,
+        return_addr: SocketAddrV4,
+    ) -> Result<SubscriptionId, VirtIOErr> {
+        // files must exist to be subscribed to
+        if !self.resolve_path(&path).is_some_and(|p| p.is_file()) {
+            return Err(VirtIOErr::NotFound);
+        }
+        let relative_path = self
+            .canonical_path(&path)
+            .ok_or(VirtIOErr::NotFound)?
+            .to_string();
+
+        let client = current_client().unwrap_or(return_addr.into());
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        let id = lock.subscribe_file(relative_path.clone(), client, return_addr);
+        log::debug!("subscription {} for {}", id, relative_path);
+
+        Ok(id)
+    }
+
+    async fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        log::debug!("removing subscription {}", id);
+
+        lock.unsubscribe_file(id, current_client())
+    }
+}
+
+#[async_trait]
+impl AdminOps for RfsServer {
+    async fn server_status(&mut self) -> ServerStatus {
+        let mut status = ServerStatus {
+            cache_entries: self.read_cache.len(),
+            cache_bytes: self.read_cache.values().map(|c| c.len()).sum(),
+            server_time_ms: rfs::doctor::unix_time(),
+            ..Default::default()
+        };
+
+        if let Some(stats) = DISPATCH_STATS.get() {
+            let mut stats = stats.lock().await;
+
+            status.uptime_secs = stats.uptime().as_secs();
+            status.total_requests = stats.total_requests;
+            status.duplicate_requests = stats.duplicate_requests;
+            status.request_rate = stats.request_rate();
+            status.slo_violations = stats.slo_violations;
+            status.compressed_responses = stats.compressed_responses;
+            status.compression_ratio = stats.compression_ratio();
+            status.sessions = stats
+                .active_sources(SESSION_IDLE_TIMEOUT)
+                .map(|(client, s)| SessionStatus {
+                    client: *client,
+                    addr: s.addr,
+                    requests: s.requests,
+                    idle_ms: stats.idle_time(s).as_millis() as u64,
+                })
+                .collect();
+            status.sessions.sort_by_key(|s| s.idle_ms);
+        }
+
+        if let Some(protocol) = TRANSFER_PROTOCOL.get() {
+            status.reaped_transfers = protocol.reaped_transfers();
+        }
+
+        if let Some(callbacks) = self.callbacks() {
+            let lock = callbacks.lock().await;
+
+            status.watches = lock
+                .lookup
+                .iter()
+                .map(|(path, cb)| (path.clone(), cb.len()))
+                .collect();
+            status.watches.sort();
+        }
+
+        status
+    }
+
+    async fn publish(&mut self, topic: String, message: String) -> usize {
+        log::info!("publishing to {}: {}", topic, message);
+
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        lock.publish(TopicMessage { topic, message }).await
+    }
+}
+
+#[async_trait]
+impl TopicOps for RfsServer {
+    async fn subscribe(&mut self, topic: String, return_addr: SocketAddrV4) -> bool {
+        log::debug!("subscribing {} to {}", return_addr, topic);
+
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        let client = current_client().unwrap_or(return_addr.into());
+        lock.subscribe(topic, client, return_addr)
+    }
+
+    async fn unsubscribe(&mut self, topic: String, return_addr: SocketAddrV4) -> bool {
+        let callbacks = self.callbacks().expect("must be initialized");
+        let mut lock = callbacks.lock().await;
+
+        let client = current_client().unwrap_or(return_addr.into());
+        lock.unsubscribe(&topic, client)
+    }
+}
+
+/// Create a symbolic link on the local filesystem
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symbolic link on the local filesystem
+#[cfg(not(unix))]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Clients that do not identify themselves share a single registration.
+fn presence_client() -> ClientId {
+    current_client().unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())
+}
+
+#[async_trait]
+impl PresenceOps for RfsServer {
+    async fn register_open(&mut self, path: VirtPath, mode: OpenMode) -> Result<(), VirtIOErr> {
+        match self.resolve_path(&path) {
+            Some(full_path) if full_path.is_file() => (),
+            _ => return Err(VirtIOErr::NotFound),
+        }
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+
+        self.presence
+            .entry(path)
+            .or_default()
+            .insert(presence_client(), (mode, Instant::now()));
+
         Ok(())
     }
+
+    async fn register_close(&mut self, path: VirtPath) -> bool {
+        let path = match self.canonical_path(&path) {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let closed = self
+            .presence
+            .get_mut(&path)
+            .is_some_and(|clients| clients.remove(&presence_client()).is_some());
+
+        // drops the path once it has no clients
+        self.watchers(&path, presence_client());
+
+        closed
+    }
+
+    async fn list_watchers(&mut self, path: VirtPath) -> Vec<Watcher> {
+        match self.canonical_path(&path) {
+            Some(path) => self.watchers(&path, presence_client()),
+            None => vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl LockOps for RfsServer {
+    async fn lock_range(
+        &mut self,
+        path: VirtPath,
+        offset: usize,
+        len: usize,
+        mode: LockMode,
+    ) -> Result<(), VirtIOErr> {
+        if len == 0 {
+            return Err(VirtIOErr::InvalidInput);
+        }
+        match self.resolve_path(&path) {
+            Some(full_path) if full_path.is_file() => (),
+            _ => return Err(VirtIOErr::NotFound),
+        }
+        let path = self.canonical_path(&path).ok_or(VirtIOErr::NotFound)?;
+
+        let lock = RangeLock {
+            client: presence_client(),
+            offset,
+            len,
+            mode,
+        };
+        let locks = self.range_locks.entry(path.clone()).or_default();
+
+        if let Some(conflict) = locks.iter().find(|held| held.conflicts_with(&lock)) {
+            log::debug!("lock on {} conflicts with {:?}", path, conflict);
+            return Err(VirtIOErr::WouldBlock);
+        }
+
+        // taking the same range again changes its mode
+        locks.retain(|held| {
+            !(held.client == lock.client && held.offset == offset && held.len == len)
+        });
+        locks.push(lock);
+        locks.sort_by_key(|held| (held.offset, held.len));
+
+        Ok(())
+    }
+
+    async fn unlock_range(&mut self, path: VirtPath, offset: usize, len: usize) -> bool {
+        let path = match self.canonical_path(&path) {
+            Some(p) => p,
+            None => return false,
+        };
+        let locks = match self.range_locks.get_mut(&path) {
+            Some(l) => l,
+            None => return false,
+        };
+
+        let client = presence_client();
+        let before = locks.len();
+        locks.retain(|held| !(held.client == client && held.offset == offset && held.len == len));
+        let unlocked = locks.len() < before;
+
+        if locks.is_empty() {
+            self.range_locks.remove(&path);
+        }
+
+        unlocked
+    }
+
+    async fn list_range_locks(&mut self, path: VirtPath) -> Vec<RangeLock> {
+        self.canonical_path(&path)
+            .and_then(|path| self.range_locks.get(&path).cloned())
+            .unwrap_or_default()
+    }
 }
 
 #[async_trait]
@@ -434,35 +1660,55 @@ impl TestOps for RfsServer {
     }
 }
 
+#[async_trait]
+impl CounterOps for RfsServer {
+    async fn increment(&mut self, by: u64) -> u64 {
+        self.counter += by;
+        log::debug!("counter incremented to {}", self.counter);
+
+        self.counter
+    }
+
+    async fn get(&mut self) -> u64 {
+        self.counter
+    }
+}
+
+#[async_trait]
+impl SystemOps for RfsServer {
+    async fn disk_usage(&mut self) -> Result<(u64, u64), VirtIOErr> {
+        let total = fs2::total_space(&self.base)?;
+        let free = fs2::available_space(&self.base)?;
+
+        Ok((total, free))
+    }
+}
+
 // assign dispatch paths to the server.
 payload_handler! {
     RfsServer,
-    // sanity check interface
-    SimpleOpsSayHello => SimpleOps::say_hello_payload,
-    SimpleOpsComputeFib => SimpleOps::compute_fib_payload,
-
-    // primitive ops
-    PrimitiveFsOpsReadAll => PrimitiveFsOps::read_all_payload,
-    PrimitiveFsOpsWriteAll => PrimitiveFsOps::write_all_payload,
-    PrimitiveFsOpsCreate => PrimitiveFsOps::create_payload,
-    PrimitiveFsOpsRename => PrimitiveFsOps::rename_payload,
-    PrimitiveFsOpsRemove => PrimitiveFsOps::remove_payload,
-    PrimitiveFsOpsReadBytes => PrimitiveFsOps::read_bytes_payload,
-    PrimitiveFsOpsWriteBytes => PrimitiveFsOps::write_bytes_payload,
-
-    // primitive ops (continued)
-    PrimitiveFsOpsMkdir => PrimitiveFsOps::mkdir_payload,
-    PrimitiveFsOpsRmdir => PrimitiveFsOps::rmdir_payload,
-    PrimitiveFsOpsReadDir => PrimitiveFsOps::read_dir_payload,
-
-    // callbacks
-    CallbackOpsRegisterFileUpdate => CallbackOps::register_file_update_payload,
-
-    // tests
-    TestOpsGetRemoteProtocol => TestOps::get_remote_protocol_payload,
-    TestOpsTestIdempotent => TestOps::test_idempotent_payload,
-    TestOpsTestNonIdempotent => TestOps::test_non_idempotent_payload,
-    TestOpsResetNonIdempotent => TestOps::reset_non_idempotent_payload,
+    interfaces {
+        // sanity check and non-idempotent demonstration
+        #[cfg(feature = "demo-interfaces")]
+        SimpleOpsClient,
+        #[cfg(feature = "demo-interfaces")]
+        CounterOpsClient,
+
+        ImmutableFileOpsClient,
+        PrimitiveFsOpsClient,
+        AdminOpsClient,
+        SystemOpsClient,
+        CallbackOpsClient,
+        TopicOpsClient,
+        PresenceOpsClient,
+
+        #[cfg(feature = "range-locks")]
+        LockOpsClient,
+
+        #[cfg(feature = "test-interfaces")]
+        TestOpsClient,
+    },
+    spilled = RfsServer::handle_spilled_upload,
 }
 
 // #[async_trait]
@@ -509,6 +1755,61 @@ payload_handler! {
 mod tests {
 
     use super::*;
+    use crate::fixtures::{init_callbacks, TempDir};
+    use rfs::middleware::InterfaceRoutes;
+
+    #[tokio::test]
+    async fn test_presence() {
+        let base = TempDir::new("presence");
+        fs::write(base.join("file"), b"hello").unwrap();
+
+        let mut server = RfsServer::from_path(&base);
+        let path = VirtPath::from("file");
+
+        server
+            .register_open(path.clone(), OpenMode::Editing)
+            .await
+            .unwrap();
+        assert!(server
+            .register_open(VirtPath::from("missing"), OpenMode::Viewing)
+            .await
+            .is_err());
+
+        // the caller is not one of the watchers
+        assert!(server.list_watchers(path.clone()).await.is_empty());
+
+        let other = ClientId::random();
+        let canonical = server.canonical_path(&path).unwrap();
+        let clients = server.presence.get_mut(&canonical).unwrap();
+        clients.insert(other, (OpenMode::Viewing, Instant::now()));
+        assert_eq!(
+            server.list_watchers(path.clone()).await,
+            vec![Watcher {
+                client: other,
+                mode: OpenMode::Viewing
+            }]
+        );
+
+        let expired = Instant::now().checked_sub(PRESENCE_TIMEOUT).unwrap();
+        let clients = server.presence.get_mut(&canonical).unwrap();
+        clients.insert(other, (OpenMode::Viewing, expired));
+        assert!(server.list_watchers(path.clone()).await.is_empty());
+
+        assert!(server.register_close(path.clone()).await);
+        assert!(!server.register_close(path).await);
+        assert!(server.presence.is_empty());
+    }
+
+    #[test]
+    fn test_route_signatures() {
+        let signatures = RfsServer::signatures();
+        rfs::middleware::check_signatures(&signatures).unwrap();
+
+        // every method of a registered interface is routed
+        for sig in <PrimitiveFsOpsClient as InterfaceRoutes<RfsServer>>::signatures() {
+            assert!(signatures.contains(&sig));
+        }
+    }
 
     #[test]
     fn test_contains_backdir() {
@@ -523,4 +1824,1158 @@ mod tests {
             "./this/is/valid"
         )));
     }
+
+    #[tokio::test]
+    async fn test_dir_change_counters() {
+        let base = TempDir::new("dir_counters");
+        let mut server = RfsServer::from_path(&base);
+
+        server.mkdir("./nested".into()).await.unwrap();
+        server.create("nested/file".into()).await.unwrap();
+
+        assert_eq!(server.dir_change_counter(".".into()).await, 2);
+        assert_eq!(server.dir_change_counter("./nested".into()).await, 1);
+
+        let read_dir = server.read_dir("nested".into()).await;
+        assert_eq!(read_dir.change_counter, 1);
+        assert_eq!(read_dir.len(), 1);
+
+        server.remove("nested/file".into()).await.unwrap();
+        assert_eq!(server.dir_change_counter("nested/".into()).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let base = TempDir::new("journal_ops");
+
+        // a crash after the rename was recorded, but before it was applied
+        fs::write(base.join("old"), b"hello").unwrap();
+        let (mut journal, _) = Journal::open(&base).unwrap();
+        journal
+            .begin(&JournalOp::Rename {
+                from: "old".into(),
+                to: "new".into(),
+            })
+            .unwrap();
+        drop(journal);
+
+        let mut server = RfsServer::from_path(&base);
+        assert_eq!(server.open_journal().unwrap(), 1);
+        assert_eq!(fs::read(base.join("new")).unwrap(), b"hello");
+
+        server.mkdir("dir".into()).await.unwrap();
+        server
+            .rename("dir".into(), "../new".into(), "file".into())
+            .await
+            .unwrap_err();
+        server
+            .rename(".".into(), "new".into(), "dir/file".into())
+            .await
+            .unwrap();
+        assert_eq!(fs::read(base.join("dir/file")).unwrap(), b"hello");
+        assert!(Journal::open(&base).unwrap().1.is_empty());
+
+        // the journal is hidden from clients
+        let names = server
+            .ls(".".into(), None, 10)
+            .await
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|e| e.path)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["dir"]);
+        assert!(server.read_all(JOURNAL_FILE.into()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_append_line() {
+        let base = TempDir::new("append_line");
+        fs::write(base.join("notes"), b"first").unwrap();
+        let mut server = RfsServer::from_path(&base);
+
+        // the existing line is terminated first
+        assert_eq!(
+            server
+                .append_line("notes".into(), "second".into(), 0)
+                .await
+                .unwrap(),
+            8
+        );
+        assert_eq!(
+            server
+                .append_line("notes".into(), "third\n".into(), 0)
+                .await
+                .unwrap(),
+            6
+        );
+        assert_eq!(
+            fs::read(base.join("notes")).unwrap(),
+            b"first\nsecond\nthird\n"
+        );
+
+        // missing files are created, with valid names only
+        server
+            .append_line("log".into(), "".into(), 0)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(base.join("log")).unwrap(), b"\n");
+        assert_eq!(server.dir_change_counter(".".into()).await, 3);
+        assert!(matches!(
+            server.append_line("bad name".into(), "x".into(), 0).await,
+            Err(VirtIOErr::InvalidInput)
+        ));
+    }
+
+    /// rfs::fs helpers run against the server in-process, without sockets.
+    #[tokio::test]
+    async fn test_loopback_fs() {
+        let base = TempDir::new("loopback");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+
+        assert_eq!(
+            rfs::fs::read_to_string(ctx.clone(), "file").await.unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            rfs::fs::append_line(ctx.clone(), "notes", "first")
+                .await
+                .unwrap(),
+            6
+        );
+        assert!(rfs::fs::is_file(ctx.clone(), "notes").await.unwrap());
+
+        let mut names = rfs::fs::read_dir(ctx.clone(), ".")
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["file", "notes"]);
+
+        assert!(!rfs::fs::exists(ctx.clone(), "missing").await.unwrap());
+        assert_eq!(ctx.stats().samples().count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_ls_pages() {
+        let base = TempDir::new("ls_pages");
+        fs::create_dir_all(base.join("d")).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(base.join(name), b"").unwrap();
+        }
+        let mut server = RfsServer::from_path(&base);
+
+        let first = server.ls(".".into(), None, 2).await.unwrap();
+        let names = first
+            .entries
+            .iter()
+            .map(|e| e.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(first.total, 4);
+        assert_eq!(first.cursor.as_deref(), Some("b"));
+
+        // removing a listed entry does not skip the rest
+        fs::remove_file(base.join("a")).unwrap();
+        let second = server.ls(".".into(), first.cursor, 2).await.unwrap();
+        let names = second
+            .entries
+            .iter()
+            .map(|e| e.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "d"]);
+        assert!(!second.entries[1].is_file());
+        assert_eq!(second.cursor, None);
+
+        assert!(matches!(
+            server.ls("missing".into(), None, 2).await,
+            Err(VirtIOErr::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_edit_batch() {
+        let base = TempDir::new("edit_batch");
+        fs::write(base.join("file"), b"world").unwrap();
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+        let mut file = rfs::fs::VirtFile::open(ctx.clone(), "file").await.unwrap();
+
+        file.begin_edit();
+        file.write_bytes(FileUpdate::Insert((0, b"hello ".to_vec())))
+            .await
+            .unwrap();
+        file.write_bytes(FileUpdate::Append(b"!".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(file.local_cache(), b"hello world!");
+        assert_eq!(fs::read(base.join("file")).unwrap(), b"world");
+
+        assert_eq!(file.commit().await.unwrap(), 7);
+        assert!(!file.in_edit());
+        assert_eq!(fs::read(base.join("file")).unwrap(), b"hello world!");
+
+        file.begin_edit();
+        file.write_bytes(FileUpdate::Overwrite(b"discarded".to_vec()))
+            .await
+            .unwrap();
+        assert!(file.rollback());
+        assert_eq!(file.local_cache(), b"hello world!");
+        assert_eq!(file.commit().await.unwrap(), 0);
+
+        // a batch with an invalid update is not applied at all
+        let mut server = RfsServer::from_path(&base);
+        assert!(matches!(
+            server
+                .write_batch(
+                    "file".into(),
+                    vec![FileUpdate::Append(b"?".to_vec()), FileUpdate::Restarted],
+                    0
+                )
+                .await,
+            Err(VirtIOErr::InvalidInput)
+        ));
+        assert_eq!(fs::read(base.join("file")).unwrap(), b"hello world!");
+        assert_eq!(fs::read_dir(&base).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_cache() {
+        let base = TempDir::new("read_cache");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let set_modified = |secs| {
+            fs::File::options()
+                .write(true)
+                .open(base.join("file"))
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap()
+        };
+        set_modified(1);
+
+        let fresh = rfs::middleware::ContextManager::loopback(
+            RfsServer::from_path(&base).with_callbacks(init_callbacks()),
+        )
+        .with_read_cache(Duration::from_secs(60));
+        let stale = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base))
+            .with_read_cache(Duration::ZERO);
+
+        for ctx in [&fresh, &stale] {
+            assert_eq!(
+                rfs::fs::read_range(ctx.clone(), "file", 0, None)
+                    .await
+                    .unwrap(),
+                b"hello"
+            );
+        }
+
+        fs::write(base.join("file"), b"world").unwrap();
+        set_modified(2);
+
+        // fresh blocks are served without invocations
+        let invocations = fresh.stats().samples().count();
+        assert_eq!(
+            rfs::fs::read_range(fresh.clone(), "file", 0, Some(4))
+                .await
+                .unwrap(),
+            b"hell"
+        );
+        assert_eq!(fresh.stats().samples().count(), invocations);
+
+        assert_eq!(
+            rfs::fs::read_range(stale.clone(), "file", 0, None)
+                .await
+                .unwrap(),
+            b"world"
+        );
+        assert_eq!(
+            rfs::fs::last_modified(stale.clone(), "file").await.unwrap(),
+            2_000_000_000
+        );
+
+        // writes through the same context manager are not read from the cache
+        let mut file = rfs::fs::VirtFile::open(fresh.clone(), "file")
+            .await
+            .unwrap();
+        assert_eq!(file.local_cache(), b"hello");
+        file.write_bytes(FileUpdate::Overwrite(b"again".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(
+            rfs::fs::read_range(fresh.clone(), "file", 0, None)
+                .await
+                .unwrap(),
+            b"again"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_stream() {
+        let base = TempDir::new("read_stream");
+        fs::write(base.join("file"), b"hello world").unwrap();
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+
+        let chunk = rfs::fs::read_chunk(ctx.clone(), "file", 6, 100)
+            .await
+            .unwrap();
+        assert_eq!(chunk.data, b"world");
+        assert_eq!(chunk.size, 11);
+        assert!(rfs::fs::read_chunk(ctx.clone(), "file", 20, 4)
+            .await
+            .unwrap()
+            .data
+            .is_empty());
+
+        let chunks = rfs::fs::read_stream(ctx.clone(), "file", 4)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![b"hell".to_vec(), b"o wo".to_vec(), b"rld".to_vec()]
+        );
+
+        let mut missing = rfs::fs::read_stream(ctx, "missing", 4);
+        assert!(missing.next().await.unwrap().is_err());
+        assert!(missing.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_recover_write() {
+        let base = TempDir::new("recover_write");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+        let mut file = rfs::fs::VirtFile::open(ctx, "file").await.unwrap();
+        let update = FileUpdate::Append(b" world".to_vec());
+
+        assert_eq!(
+            file.recover_write(&update).await.unwrap(),
+            rfs::fs::WriteOutcome::NotApplied
+        );
+
+        fs::write(base.join("file"), b"hello world").unwrap();
+        assert_eq!(
+            file.recover_write(&update).await.unwrap(),
+            rfs::fs::WriteOutcome::Applied
+        );
+        assert_eq!(file.local_cache(), b"hello world");
+
+        fs::write(base.join("file"), b"hello world world world").unwrap();
+        assert_eq!(
+            file.recover_write(&update).await.unwrap(),
+            rfs::fs::WriteOutcome::Duplicated
+        );
+
+        fs::write(base.join("file"), b"other").unwrap();
+        assert_eq!(
+            file.recover_write(&update).await.unwrap(),
+            rfs::fs::WriteOutcome::Diverged
+        );
+        assert_eq!(file.local_cache(), b"other");
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage() {
+        let base = TempDir::new("disk_usage");
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+
+        let (total, free) = rfs::fs::disk_usage(ctx.clone()).await.unwrap();
+        assert!(total > 0 && free <= total);
+
+        rfs::fs::check_free_space(ctx.clone(), 1).await.unwrap();
+        assert_eq!(
+            rfs::fs::check_free_space(ctx, total)
+                .await
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::StorageFull
+        );
+    }
+
+    #[tokio::test]
+    async fn test_range_locks() {
+        let base = TempDir::new("range_locks");
+        fs::write(base.join("file"), b"hello world").unwrap();
+        let mut server = RfsServer::from_path(&base);
+        let path = VirtPath::from("file");
+
+        server
+            .lock_range(path.clone(), 0, 5, LockMode::Exclusive)
+            .await
+            .unwrap();
+        server
+            .lock_range(path.clone(), 6, 5, LockMode::Shared)
+            .await
+            .unwrap();
+        assert!(matches!(
+            server
+                .lock_range("missing".into(), 0, 1, LockMode::Shared)
+                .await,
+            Err(VirtIOErr::NotFound)
+        ));
+
+        // another client can share the second range, but not the first
+        let other = RangeLock {
+            client: ClientId::random(),
+            offset: 4,
+            len: 4,
+            mode: LockMode::Shared,
+        };
+        let locks = server.list_range_locks(path.clone()).await;
+        assert_eq!(locks.len(), 2);
+        assert!(locks[0].conflicts_with(&other));
+        assert!(!locks[1].conflicts_with(&RangeLock { offset: 6, ..other }));
+        assert!(!locks[0].conflicts_with(&RangeLock { offset: 5, ..other }));
+
+        let canonical = server.canonical_path(&path).unwrap();
+        server
+            .range_locks
+            .get_mut(&canonical)
+            .unwrap()
+            .push(RangeLock { offset: 8, ..other });
+        assert!(matches!(
+            server
+                .lock_range(path.clone(), 9, 1, LockMode::Exclusive)
+                .await,
+            Err(VirtIOErr::WouldBlock)
+        ));
+
+        assert!(server.unlock_range(path.clone(), 0, 5).await);
+        assert!(!server.unlock_range(path.clone(), 0, 5).await);
+        assert!(!server.unlock_range(path.clone(), 8, 4).await);
+        assert_eq!(server.list_range_locks(path.clone()).await.len(), 2);
+
+        // locks of a client that went idle are released
+        assert_eq!(server.forget_clients(&[other.client]), (0, 1));
+        assert_eq!(server.list_range_locks(path).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let base = TempDir::new("stat");
+        fs::create_dir_all(base.join("nested")).unwrap();
+        fs::write(base.join("file"), b"hello").unwrap();
+        let mut server = RfsServer::from_path(&base);
+
+        let file = server.stat("file".into()).await.unwrap();
+        assert!(file.file);
+        assert_eq!(file.size, 5);
+
+        let dir = server.stat("nested".into()).await.unwrap();
+        assert!(!dir.file);
+        assert_eq!(dir.size, 0);
+
+        assert_eq!(server.stat("missing".into()).await, None);
+        assert_eq!(server.stat("../outside".into()).await, None);
+
+        let many = server
+            .stat_many(vec!["missing".into(), "file".into(), "nested".into()])
+            .await;
+        assert_eq!(many, vec![None, Some(file), Some(dir)]);
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let base = TempDir::new("links");
+        fs::create_dir_all(base.join("nested")).unwrap();
+        fs::write(base.join("file"), b"hello").unwrap();
+        let mut server = RfsServer::from_path(&base);
+
+        server
+            .symlink("../file".into(), "nested/link".into())
+            .await
+            .unwrap();
+        assert_eq!(server.read_all("nested/link".into()).await, b"hello");
+        assert_eq!(server.dir_change_counter("nested".into()).await, 1);
+
+        let listing = server.read_dir("nested".into()).await;
+        assert_eq!(listing[0].link_target(), Some("../file"));
+
+        // targets outside the base directory are rejected
+        assert!(matches!(
+            server
+                .symlink("../../file".into(), "nested/out".into())
+                .await,
+            Err(VirtIOErr::PermissionDenied)
+        ));
+        assert!(matches!(
+            server.hardlink("../file".into(), "out".into()).await,
+            Err(VirtIOErr::PermissionDenied)
+        ));
+
+        // targets are followed through existing links, not just by name
+        fs::create_dir_all(base.join("a/b")).unwrap();
+        server.symlink("..".into(), "a/b/sub".into()).await.unwrap();
+        assert!(matches!(
+            server
+                .symlink("a/b/sub/../../secret".into(), "esc".into())
+                .await,
+            Err(VirtIOErr::PermissionDenied)
+        ));
+        assert!(matches!(
+            server.symlink("missing".into(), "dangling".into()).await,
+            Err(VirtIOErr::PermissionDenied)
+        ));
+
+        // links that lead outside the base directory cannot be read through
+        let outside = TempDir::new("links_outside");
+        fs::write(outside.join("secret"), b"secret").unwrap();
+        create_symlink(&outside.join("secret"), &base.join("planted")).unwrap();
+        assert!(server.read_all("planted".into()).await.is_empty());
+        assert!(server.read_bytes("planted".into(), 0, 1).await.is_empty());
+        assert!(server.read_file("planted".into(), 0, None).await.is_err());
+
+        server
+            .hardlink("nested/link".into(), "hard".into())
+            .await
+            .unwrap();
+        fs::write(base.join("file"), b"updated").unwrap();
+        assert_eq!(server.read_all("hard".into()).await, b"updated");
+        assert_eq!(server.read_dir(".".into()).await[1].link_target(), None);
+    }
+
+    /// Paths outside the path policy are rejected by every handler.
+    #[tokio::test]
+    async fn test_path_policy() {
+        let base = TempDir::new("path_policy");
+        // created outside of the remote, which would not allow the name
+        fs::write(base.join("a file"), b"hello").unwrap();
+        let mut server = RfsServer::from_path(&base);
+
+        assert!(matches!(
+            server.create("bad|name".into()).await,
+            Err(VirtIOErr::InvalidInput)
+        ));
+        assert!(matches!(
+            server.mkdir("nested/AUX".into()).await,
+            Err(VirtIOErr::InvalidInput)
+        ));
+        assert!(server.create("good-name.txt".into()).await.is_ok());
+
+        assert_eq!(server.stat("a file".into()).await, None);
+        assert!(server.read_all("a file".into()).await.is_empty());
+        assert!(server.read_file("a file".into(), 0, None).await.is_err());
+
+        let long = "a/".repeat(server.path_policy.max_path_len);
+        assert_eq!(server.stat(long.into()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_read_file_range() {
+        let base = TempDir::new("read_file");
+        fs::write(base.join("file"), b"hello world").unwrap();
+        let mut server = RfsServer::from_path(&base);
+
+        assert_eq!(
+            server.read_file("file".into(), 0, None).await.unwrap(),
+            b"hello world"
+        );
+        assert_eq!(
+            server.read_file("file".into(), 6, Some(3)).await.unwrap(),
+            b"wor"
+        );
+
+        assert_eq!(
+            server.read_file("file".into(), 6, Some(100)).await.unwrap(),
+            b"world"
+        );
+        assert!(server
+            .read_file("file".into(), 100, None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            server.read_file("missing".into(), 0, None).await,
+            Err(VirtIOErr::NotFound)
+        ));
+    }
+
+    /// Applying the same update notice twice leaves the contents as they were after the first.
+    #[tokio::test]
+    async fn test_update_bytes_idempotent() {
+        let base = TempDir::new("update_idempotent");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let ctx = rfs::middleware::ContextManager::loopback(RfsServer::from_path(&base));
+
+        let mut file = rfs::fs::VirtFile::open(ctx, "file").await.unwrap();
+        let notice = FileUpdateNotice {
+            version: file.version() + 1,
+            author: None,
+            update: FileUpdate::Append(b" world".to_vec()),
+        };
+
+        assert!(file.update_bytes(&notice));
+        assert_eq!(file.local_cache(), b"hello world");
+
+        assert!(!file.update_bytes(&notice));
+        assert_eq!(file.local_cache(), b"hello world");
+        assert_eq!(file.version(), notice.version);
+    }
+
+    /// Watchers receive the file version and the author of each write.
+    #[tokio::test]
+    async fn test_file_update_notice() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("update_notice");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let watcher_addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+
+        server
+            .write_bytes("file".into(), FileUpdate::Append(b" world".to_vec()), 7)
+            .await
+            .unwrap();
+        server
+            .register_file_update("file".into(), watcher_addr)
+            .await
+            .unwrap();
+        server
+            .write_bytes("file".into(), FileUpdate::Append(b"!".to_vec()), 42)
+            .await
+            .unwrap();
+
+        let (_, bytes) = DefaultProto
+            .recv_bytes(&watcher, Duration::from_millis(500), 3)
+            .await
+            .unwrap();
+        let notice: FileUpdateNotice = rfs::ser_de::deserialize(&bytes).unwrap();
+
+        assert_eq!(notice.version, 2);
+        assert_eq!(notice.author, Some(42));
+        assert!(matches!(notice.update, FileUpdate::Append(data) if data == b"!"));
+        assert_eq!(fs::read(base.join("file")).unwrap(), b"hello world!");
+    }
+
+    /// Uploads spilled to disk are streamed to the file, instead of being read into memory.
+    #[tokio::test]
+    async fn test_spilled_write_all() {
+        use rfs::middleware::PayloadBuffer;
+
+        let base = TempDir::new("spilled_write_all");
+        fs::write(base.join("upload"), "old").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let contents = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let request = PrimitiveFsOpsWriteAll::Request {
+            path: "upload".into(),
+            contents: ByteBuf::from(contents.clone()),
+        };
+
+        let mut buffer = PayloadBuffer::new(1024);
+        buffer.extend(&request.invoke_bytes()).unwrap();
+        let payload = buffer.finish().unwrap();
+        assert!(payload.is_spilled());
+
+        let response = server.handle_spilled(payload).await.unwrap();
+        assert!(matches!(
+            PrimitiveFsOpsWriteAll::process_invocation(&response),
+            Ok(PrimitiveFsOpsWriteAll::Response(true))
+        ));
+        assert_eq!(fs::read(base.join("upload")).unwrap(), contents);
+        assert!(fs::read_dir(&*base).unwrap().all(|e| !e
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SUFFIX)));
+
+        // partial files of uploads in progress are not listed
+        fs::write(base.join(".upload.7.partial"), "in progress").unwrap();
+        let listing = server.read_dir(".".into()).await;
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].path(), "upload");
+
+        // the file is not watched, so the streamed contents are not kept for watchers to replay
+        let path = VirtPath::from("upload");
+        assert_eq!(server.file_versions[&path], 1);
+        assert!(matches!(
+            server.update_log.since(&path, 0, 1),
+            MissedUpdates::Refetch { version: 1 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_diff_watch() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("diff_watch");
+        let old = "one\ntwo\nthree\n";
+        let new = "one\n2\nthree\n";
+        fs::write(base.join("file"), old).unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let mut watchers = vec![];
+        for mode in [WatchMode::Raw, WatchMode::Diff] {
+            let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+            server
+                .register_file_watch("file".into(), addr, mode)
+                .await
+                .unwrap();
+            watchers.push(watcher);
+        }
+
+        assert!(server.write_all("file".into(), ByteBuf::from(new)).await);
+
+        let mut notices = vec![];
+        for watcher in &watchers {
+            let (_, bytes) = DefaultProto
+                .recv_bytes(watcher, Duration::from_millis(500), 3)
+                .await
+                .unwrap();
+            notices.push(rfs::ser_de::deserialize::<FileUpdateNotice>(&bytes).unwrap());
+        }
+
+        assert!(
+            matches!(&notices[0].update, FileUpdate::Overwrite(data) if data == new.as_bytes())
+        );
+        assert!(matches!(&notices[1].update, FileUpdate::Diff(_)));
+        assert_eq!(
+            notices[1].clone().update.update_file(old.as_bytes()),
+            new.as_bytes()
+        );
+    }
+
+    /// Subscriptions receive every update until they are removed.
+    #[tokio::test]
+    async fn test_subscription() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("subscription");
+        fs::write(base.join("file"), b"").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let mut watchers = vec![];
+        for _ in 0..2 {
+            let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let addr = rfs::middleware::sockaddr_to_v4(sock.local_addr().unwrap()).unwrap();
+            watchers.push((sock, addr));
+        }
+
+        let first = CallbackOps::subscribe(&mut server, "file".into(), watchers[0].1)
+            .await
+            .unwrap();
+        let second = CallbackOps::subscribe(&mut server, "file".into(), watchers[1].1)
+            .await
+            .unwrap();
+        assert_ne!(first, second);
+        // subscribing again renews the subscription
+        assert_eq!(
+            CallbackOps::subscribe(&mut server, "file".into(), watchers[0].1)
+                .await
+                .unwrap(),
+            first
+        );
+        assert!(matches!(
+            CallbackOps::subscribe(&mut server, "missing".into(), watchers[0].1).await,
+            Err(VirtIOErr::NotFound)
+        ));
+
+        // every update is delivered to both subscriptions
+        for version in 1..=2 {
+            server
+                .write_bytes("file".into(), FileUpdate::Append(b"a".to_vec()), 3)
+                .await
+                .unwrap();
+
+            for (sock, _) in &watchers {
+                let (_, bytes) = DefaultProto
+                    .recv_bytes(sock, Duration::from_millis(500), 3)
+                    .await
+                    .unwrap();
+                let notice: FileUpdateNotice = rfs::ser_de::deserialize(&bytes).unwrap();
+                assert_eq!(notice.version, version);
+            }
+        }
+
+        assert!(CallbackOps::unsubscribe(&mut server, first).await);
+        assert!(!CallbackOps::unsubscribe(&mut server, first).await);
+
+        server
+            .write_bytes("file".into(), FileUpdate::Append(b"a".to_vec()), 3)
+            .await
+            .unwrap();
+        DefaultProto
+            .recv_bytes(&watchers[1].0, Duration::from_millis(500), 3)
+            .await
+            .unwrap();
+
+        let next = DefaultProto.recv_bytes(&watchers[0].0, Duration::from_millis(200), 1);
+        assert!(tokio::time::timeout(Duration::from_millis(200), next)
+            .await
+            .is_err());
+
+        assert!(CallbackOps::unsubscribe(&mut server, second).await);
+    }
+
+    /// Watchers in metadata mode receive the size of the file instead of the update.
+    #[tokio::test]
+    async fn test_metadata_watch() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("metadata_watch");
+        fs::write(base.join("file"), b"hello").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+        server
+            .register_file_watch("file".into(), addr, WatchMode::Metadata)
+            .await
+            .unwrap();
+
+        server
+            .write_bytes("file".into(), FileUpdate::Append(b" world".to_vec()), 3)
+            .await
+            .unwrap();
+
+        let (_, bytes) = DefaultProto
+            .recv_bytes(&watcher, Duration::from_millis(500), 3)
+            .await
+            .unwrap();
+        let notice: MetadataNotice = rfs::ser_de::deserialize(&bytes).unwrap();
+
+        assert_eq!(notice.version, 1);
+        assert_eq!(notice.author, Some(3));
+        let metadata = notice.metadata.unwrap();
+        assert_eq!(metadata.size, 11);
+        assert!(metadata.file);
+    }
+
+    /// Watchers that register again receive the updates since the version they saw.
+    #[tokio::test]
+    async fn test_resume_watch() {
+        use rfs::middleware::DefaultProto;
+        use std::net::Ipv4Addr;
+
+        let base = TempDir::new("resume_watch");
+        fs::write(base.join("file"), b"").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1);
+
+        for data in [b"a", b"b", b"c"] {
+            server
+                .write_bytes("file".into(), FileUpdate::Append(data.to_vec()), 1)
+                .await
+                .unwrap();
+        }
+
+        let (_, missed) = server
+            .resume_file_watch("file".into(), addr, WatchMode::Raw, 1)
+            .await
+            .unwrap();
+        let MissedUpdates::Replay(updates) = missed else {
+            panic!("updates since version 1 are logged");
+        };
+        assert_eq!(
+            updates.iter().map(|n| n.version).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(matches!(&updates[1].update, FileUpdate::Append(d) if d == b"c"));
+
+        // the file was replaced without a write
+        server.remove("file".into()).await.unwrap();
+        server.create("file".into()).await.unwrap();
+        assert!(matches!(
+            server
+                .resume_file_watch("file".into(), addr, WatchMode::Raw, 1)
+                .await,
+            Ok((_, MissedUpdates::Refetch { version: 3 }))
+        ));
+    }
+
+    /// Directory watches receive events for their entries, and for subdirectories if recursive.
+    #[tokio::test]
+    async fn test_dir_watch() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("dir_watch");
+        fs::create_dir_all(base.join("dir/sub")).unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        let mut watchers = vec![];
+        for recursive in [false, true] {
+            let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+            server
+                .register_dir_update("dir".into(), recursive, addr)
+                .await
+                .unwrap();
+            watchers.push(watcher);
+        }
+
+        assert!(matches!(
+            server
+                .register_dir_update("missing".into(), false, "127.0.0.1:1".parse().unwrap())
+                .await,
+            Err(VirtIOErr::NotFound)
+        ));
+
+        let recv = |watcher| async move {
+            let (_, bytes) = tokio::time::timeout(
+                Duration::from_millis(200),
+                DefaultProto.recv_bytes(watcher, Duration::from_millis(200), 1),
+            )
+            .await
+            .ok()?
+            .ok()?;
+
+            rfs::ser_de::deserialize::<DirEvent>(&bytes).ok()
+        };
+
+        // only the recursive watch sees changes in subdirectories
+        server.create("dir/sub/file".into()).await.unwrap();
+        assert_eq!(
+            recv(&watchers[1]).await.unwrap(),
+            DirEvent::Created("dir/sub/file".into())
+        );
+        assert_eq!(recv(&watchers[0]).await, None);
+
+        server
+            .rename("dir".into(), "sub/file".into(), "file".into())
+            .await
+            .unwrap();
+        for watcher in &watchers {
+            assert_eq!(
+                recv(watcher).await.unwrap(),
+                DirEvent::Renamed {
+                    from: "dir/sub/file".into(),
+                    to: "dir/file".into()
+                }
+            );
+        }
+
+        server.remove("dir/file".into()).await.unwrap();
+        for watcher in &watchers {
+            assert_eq!(
+                recv(watcher).await.unwrap(),
+                DirEvent::Removed("dir/file".into())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_topic_publish() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let mut server = RfsServer::from_path(".").with_callbacks(init_callbacks());
+        let topic = "test_topic".to_string();
+
+        let subscriber = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let subscriber_addr =
+            rfs::middleware::sockaddr_to_v4(subscriber.local_addr().unwrap()).unwrap();
+
+        assert_eq!(server.publish(topic.clone(), "nobody".to_string()).await, 0);
+        assert!(TopicOps::subscribe(&mut server, topic.clone(), subscriber_addr).await);
+        // duplicate subscriptions are ignored
+        assert!(!TopicOps::subscribe(&mut server, topic.clone(), subscriber_addr).await);
+
+        let (published, received) = tokio::join!(
+            server.publish(topic.clone(), "hello".to_string()),
+            DefaultProto.recv_bytes(&subscriber, Duration::from_millis(500), 3)
+        );
+        let (_, bytes) = received.unwrap();
+
+        assert_eq!(published, 1);
+        assert_eq!(
+            rfs::topics::decode_message(&bytes),
+            Some(TopicMessage {
+                topic: topic.clone(),
+                message: "hello".to_string()
+            })
+        );
+
+        assert!(TopicOps::unsubscribe(&mut server, topic.clone(), subscriber_addr).await);
+        assert!(!TopicOps::unsubscribe(&mut server, topic.clone(), subscriber_addr).await);
+    }
+
+    /// Watches registered on a listed entry are triggered by writes to any spelling of its path.
+    #[tokio::test]
+    async fn test_nested_watch() {
+        use rfs::middleware::{DefaultProto, TransmissionProtocol};
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        let base = TempDir::new("nested_watch");
+        fs::create_dir_all(base.join("outer/inner")).unwrap();
+        fs::write(base.join("outer/inner/file"), b"hello").unwrap();
+        fs::write(base.join("outer/other"), b"").unwrap();
+        let mut server = RfsServer::from_path(&base).with_callbacks(init_callbacks());
+
+        // navigate the same way the client does, joining onto listed entry paths
+        let outer = server.read_dir("./outer/".into()).await;
+        assert_eq!(
+            outer
+                .entries
+                .iter()
+                .map(|e| e.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["outer/inner", "outer/other"]
+        );
+        let inner = server
+            .read_dir(format!("./{}/", outer.entries[0].path).into())
+            .await;
+        let file = &inner.entries[0];
+        assert_eq!(file.path, "outer/inner/file");
+
+        let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let watcher_addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+
+        server
+            .register_file_update(file.path.as_str().into(), watcher_addr)
+            .await
+            .unwrap();
+        server
+            .write_bytes(
+                "./outer//inner/./file".into(),
+                FileUpdate::Append(b"!".to_vec()),
+                1,
+            )
+            .await
+            .unwrap();
+
+        let (_, bytes) = DefaultProto
+            .recv_bytes(&watcher, Duration::from_millis(500), 3)
+            .await
+            .unwrap();
+        let notice: FileUpdateNotice = rfs::ser_de::deserialize(&bytes).unwrap();
+
+        assert_eq!(notice.version, 1);
+        assert!(matches!(notice.update, FileUpdate::Append(data) if data == b"!"));
+        assert_eq!(
+            server
+                .file_versions
+                .get(&VirtPath::from("outer/inner/file")),
+            Some(&1)
+        );
+    }
+
+    /// Invokes the server directly, without a dispatcher
+    struct Loopback(RfsServer);
+
+    #[async_trait]
+    impl rfs::middleware::Invoker for Loopback {
+        async fn invoke_raw(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, InvokeError> {
+            self.0.handle_payload(&payload).await
+        }
+
+        async fn reconnect(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Duplicated increments are executed again, unless the dispatcher filters duplicates.
+    #[tokio::test]
+    async fn test_counter_duplicates() {
+        use rfs::middleware::{
+            sockaddr_to_v4, ContextManager, Dispatcher, InvocationFaults, RequestAckProto,
+            Semantics,
+        };
+        use std::net::Ipv4Addr;
+
+        let timeout = Duration::from_millis(200);
+
+        for (semantics, expected) in [(Semantics::AtLeastOnce, 3), (Semantics::AtMostOnce, 1)] {
+            let mut dispatcher = Dispatcher::new(
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+                RfsServer::from_path("."),
+                Arc::new(RequestAckProto),
+                true,
+                timeout,
+                3,
+            )
+            .await
+            .with_semantics(semantics);
+            let addr = sockaddr_to_v4(dispatcher.local_addr().unwrap()).unwrap();
+            let dispatch = tokio::spawn(async move { dispatcher.dispatch().await });
+
+            let mut ctx = ContextManager::new(
+                Ipv4Addr::LOCALHOST,
+                addr,
+                timeout,
+                3,
+                Arc::new(RequestAckProto),
+            )
+            .await
+            .unwrap()
+            .with_faults(InvocationFaults {
+                drop_response: None,
+                duplicates: 2,
+            });
+
+            CounterOpsClient::increment(&mut ctx, 1).await.unwrap();
+            assert_eq!(CounterOpsClient::get(&mut ctx).await.unwrap(), expected);
+
+            dispatch.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compute_fib() {
+        assert_eq!(fib(0), 0);
+        assert_eq!(fib(10), 55);
+        assert_eq!(fib(MAX_FIB_NUM), 12200160415121876738);
+
+        let mut server = RfsServer::default();
+        assert_eq!(
+            server.compute_fib(MAX_FIB_NUM + 1, None).await,
+            Err(FibError::OutOfRange(MAX_FIB_NUM + 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_application_error() {
+        let base = TempDir::new("app_error");
+        let mut server = RfsServer::from_path(&base);
+
+        // errors are not sent as part of the response
+        let req = PrimitiveFsOpsRemove::Request {
+            path: "missing".into(),
+        };
+        assert!(matches!(
+            server.handle_payload(&req.invoke_bytes()).await,
+            Err(InvokeError::RemoteApplication(_))
+        ));
+
+        let mut loopback = Loopback(server);
+        let res = PrimitiveFsOpsClient::remove(&mut loopback, "missing".into()).await;
+        assert!(matches!(res, Ok(Err(VirtIOErr::NotFound))));
+
+        let res = PrimitiveFsOpsClient::mkdir(&mut loopback, "dir".into()).await;
+        assert!(matches!(res, Ok(Ok(()))));
+        let res = PrimitiveFsOpsClient::mkdir(&mut loopback, "dir".into()).await;
+        assert!(matches!(res, Ok(Err(VirtIOErr::AlreadyExists))));
+    }
 }
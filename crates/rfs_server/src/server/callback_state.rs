@@ -0,0 +1,274 @@
+//! Saved callback registrations, restored when the server restarts.
+//!
+//! Clients keep listening on their return addresses while the server is down. Restored
+//! file watches are sent a [FileUpdate::Restarted] notice, which triggers them like any
+//! other update, so clients that are still watching register again. Directory watches
+//! are kept until they are unregistered, so they are restored as they were.
+
+use std::{
+    io,
+    net::SocketAddrV4,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use futures::lock::Mutex;
+use rfs::{
+    interfaces::{FileUpdate, FileUpdateNotice, SubscriptionId, TopicMessage, WatchMode},
+    middleware::ClientId,
+    ser_de,
+    topics::SERVER_TOPIC,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    callbacks::NEXT_SUBSCRIPTION, DirUpdateCallback, FileUpdateCallback, RegisteredFileUpdates,
+};
+
+/// Callback registrations, as saved to the state file
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CallbackState {
+    watches: Vec<SavedWatch>,
+    dirs: Vec<SavedDirWatch>,
+    topics: Vec<(String, ClientId, SocketAddrV4)>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SavedWatch {
+    path: String,
+    id: SubscriptionId,
+    client: ClientId,
+    addr: SocketAddrV4,
+    mode: WatchMode,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SavedDirWatch {
+    path: String,
+    id: SubscriptionId,
+    client: ClientId,
+    addr: SocketAddrV4,
+    recursive: bool,
+}
+
+impl RegisteredFileUpdates {
+    /// Returns the registrations to save
+    pub fn state(&self) -> CallbackState {
+        let watches = self
+            .lookup
+            .iter()
+            .flat_map(|(path, callbacks)| {
+                callbacks.iter().map(|cb| SavedWatch {
+                    path: path.clone(),
+                    id: cb.id,
+                    client: cb.client,
+                    addr: cb.addr,
+                    mode: cb.mode,
+                })
+            })
+            .collect();
+
+        let dirs = self
+            .dirs
+            .iter()
+            .flat_map(|(path, callbacks)| {
+                callbacks.iter().map(|cb| SavedDirWatch {
+                    path: path.clone(),
+                    id: cb.id,
+                    client: cb.client,
+                    addr: cb.addr,
+                    recursive: cb.recursive,
+                })
+            })
+            .collect();
+
+        let topics = self
+            .topics
+            .iter()
+            .flat_map(|(topic, subscribers)| {
+                subscribers
+                    .iter()
+                    .map(|(client, addr)| (topic.clone(), *client, *addr))
+            })
+            .collect();
+
+        CallbackState {
+            watches,
+            dirs,
+            topics,
+        }
+    }
+
+    /// Add saved registrations to the registry.
+    ///
+    /// Registrations made since are kept. New subscription IDs are issued after the restored ones.
+    pub fn restore(&mut self, state: CallbackState) {
+        for watch in state.watches {
+            NEXT_SUBSCRIPTION.fetch_max(watch.id.0 + 1, Ordering::Relaxed);
+
+            self.lookup
+                .entry(watch.path)
+                .or_default()
+                .push(FileUpdateCallback {
+                    id: watch.id,
+                    client: watch.client,
+                    addr: watch.addr,
+                    mode: watch.mode,
+                });
+        }
+
+        for watch in state.dirs {
+            NEXT_SUBSCRIPTION.fetch_max(watch.id.0 + 1, Ordering::Relaxed);
+
+            self.dirs
+                .entry(watch.path)
+                .or_default()
+                .push(DirUpdateCallback {
+                    id: watch.id,
+                    client: watch.client,
+                    addr: watch.addr,
+                    recursive: watch.recursive,
+                });
+        }
+
+        for (topic, client, addr) in state.topics {
+            self.topics.entry(topic).or_default().insert(client, addr);
+        }
+    }
+
+    /// Save the registrations to a file, replacing it.
+    pub fn save_state(&self, path: &Path) -> io::Result<()> {
+        let bytes = ser_de::serialize(&self.state())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+        // a crash while writing leaves the previous state intact
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(&partial, path)
+    }
+
+    /// Restore the registrations saved to a file, if it exists.
+    ///
+    /// Returns the number of file and directory watches, and topic subscriptions restored.
+    pub fn restore_state(&mut self, path: &Path) -> io::Result<(usize, usize)> {
+        let bytes = match std::fs::read(path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+            Err(e) => return Err(e),
+        };
+
+        let state: CallbackState = ser_de::deserialize_exact(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+        let restored = (state.watches.len() + state.dirs.len(), state.topics.len());
+        self.restore(state);
+
+        Ok(restored)
+    }
+
+    /// Tell the clients of restored registrations that the server restarted.
+    ///
+    /// File watches are sent a [FileUpdate::Restarted] notice, which removes them.
+    /// Subscribers of [SERVER_TOPIC] are sent a message, and unreachable ones are unsubscribed.
+    ///
+    /// Returns the number of file watches notified.
+    pub async fn notify_restart(&mut self) -> usize {
+        let paths = self.lookup.keys().cloned().collect::<Vec<_>>();
+
+        let mut notified = 0;
+        for path in paths {
+            let notice = FileUpdateNotice {
+                version: 0,
+                author: None,
+                update: FileUpdate::Restarted,
+            };
+
+            if let Some(num) = self.send_file_update(&path, notice, None, None).await {
+                notified += num.get() as usize;
+            }
+        }
+
+        self.publish(TopicMessage {
+            topic: SERVER_TOPIC.to_string(),
+            message: "server restarted".to_string(),
+        })
+        .await;
+
+        notified
+    }
+}
+
+/// Save the callback registrations to `path` every `interval`.
+pub async fn save_state_periodically(
+    callbacks: Arc<Mutex<RegisteredFileUpdates>>,
+    path: PathBuf,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        if let Err(e) = callbacks.lock().await.save_state(&path) {
+            log::error!("failed to save callback registrations to {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::Ipv4Addr, sync::Weak};
+
+    use rfs::middleware::DefaultProto;
+
+    use super::*;
+    use crate::server::next_subscription_id;
+
+    fn registry() -> RegisteredFileUpdates {
+        RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: Default::default(),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: None,
+            pending: Default::default(),
+            shared: Weak::new(),
+        }
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let path = std::env::temp_dir().join(format!("rfs_callbacks_{}", std::process::id()));
+        let client = ClientId::random();
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1);
+        // an ID issued before the restart, ahead of this process
+        let id = SubscriptionId(next_subscription_id().0 + 1_000);
+
+        let mut saved = registry();
+        saved.lookup = HashMap::from([(
+            "notes".to_string(),
+            vec![FileUpdateCallback {
+                id,
+                client,
+                addr,
+                mode: WatchMode::Diff,
+            }],
+        )]);
+        saved.register_dir(".".to_string(), client, addr, true);
+        saved.subscribe(SERVER_TOPIC.to_string(), client, addr);
+        saved.save_state(&path).unwrap();
+
+        let mut restored = registry();
+        assert_eq!(restored.restore_state(&path).unwrap(), (2, 1));
+        assert_eq!(restored.state(), saved.state());
+        assert!(restored.has_diff_watchers("notes"));
+
+        // IDs are not issued twice
+        assert!(next_subscription_id().0 > id.0);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(registry().restore_state(&path).unwrap(), (0, 0));
+    }
+}
@@ -1,22 +1,42 @@
 use std::{
     collections::HashMap,
+    io,
     net::{Ipv4Addr, SocketAddrV4},
     num::NonZeroU8,
-    sync::{Arc, OnceLock},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::{Duration, Instant},
 };
 
 use futures::lock::Mutex;
-use rfs::{interfaces::FileUpdate, middleware::TransmissionProtocol, ser_de};
+use rfs::{
+    fs::VirtMetadataLite,
+    interfaces::{
+        DirEvent, FileUpdate, FileUpdateNotice, MetadataNotice, SubscriptionId, TopicMessage,
+        WatchMode, SUBSCRIPTION_TIMEOUT,
+    },
+    middleware::{ClientId, TransmissionProtocol},
+    ser_de,
+};
 use tokio::net::UdpSocket;
 
-use crate::server::FileUpdateCallback;
+use crate::server::{DirUpdateCallback, FileSubscription, FileUpdateCallback};
 
 // lazy_static! {
 //     pub static ref FILE_UPDATE_CALLBACKS: Arc<Mutex<HashMap<String, Vec<FileUpdateCallback>>>> =
 //         { Arc::new(Mutex::new(HashMap::new())) };
 // }
 
+/// ID of the next callback registration
+pub(super) static NEXT_SUBSCRIPTION: AtomicU64 = AtomicU64::new(1);
+
+/// Returns a new ID for a callback registration.
+pub fn next_subscription_id() -> SubscriptionId {
+    SubscriptionId(NEXT_SUBSCRIPTION.fetch_add(1, Ordering::Relaxed))
+}
+
 /// Callbacks for file updates.
 pub static FILE_UPDATE_CALLBACKS: OnceLock<Arc<Mutex<RegisteredFileUpdates>>> = OnceLock::new();
 
@@ -26,29 +46,245 @@ pub struct RegisteredFileUpdates {
     pub bind_addr: Ipv4Addr,
     /// Registered file callbacks
     pub lookup: HashMap<String, Vec<FileUpdateCallback>>,
+    /// Registered directory watches
+    pub dirs: HashMap<String, Vec<DirUpdateCallback>>,
+    /// Subscriptions to every update of a file, by ID.
+    ///
+    /// These are not saved across restarts, clients subscribe again when renewing them.
+    pub subscriptions: HashMap<SubscriptionId, FileSubscription>,
+    /// Subscribers of each topic, and the address messages are sent to
+    pub topics: HashMap<String, HashMap<ClientId, SocketAddrV4>>,
     /// Transmission protocol, same as server.
     pub proto: Arc<dyn TransmissionProtocol + Send + Sync>,
 
     pub timeout: Duration,
     pub retries: u8,
+
+    /// Updates to the same file within this window are merged into one callback.
+    /// Updates are sent as they happen if unset.
+    pub coalesce: Option<Duration>,
+    /// Merged updates waiting for their window to close
+    pub pending: HashMap<String, PendingUpdate>,
+
+    /// Handle these callbacks are shared through, locked to flush merged updates.
+    /// Merged updates are only flushed when their window closes if this is set.
+    pub shared: Weak<Mutex<RegisteredFileUpdates>>,
+}
+
+/// Updates to a file that are merged into a single notice.
+#[derive(Debug)]
+pub struct PendingUpdate {
+    notice: FileUpdateNotice,
+    /// Contents before the first update
+    prev: Option<Vec<u8>>,
+    /// Metadata after the last update
+    metadata: Option<VirtMetadataLite>,
+}
+
+impl PendingUpdate {
+    /// Merge a later update into this one. `prev` are the contents before the later update.
+    ///
+    /// Returns the later update if the two cannot be merged.
+    fn merge(
+        &mut self,
+        notice: FileUpdateNotice,
+        prev: Option<&[u8]>,
+    ) -> Result<(), FileUpdateNotice> {
+        let current = std::mem::replace(&mut self.notice.update, FileUpdate::Append(vec![]));
+
+        let merged = match (current, notice.update) {
+            (FileUpdate::Append(mut a), FileUpdate::Append(b)) => {
+                a.extend(b);
+                FileUpdate::Append(a)
+            }
+            (_, FileUpdate::Overwrite(b)) => FileUpdate::Overwrite(b),
+            (FileUpdate::Overwrite(a), upd) => FileUpdate::Overwrite(upd.update_file(&a)),
+            (current, upd) => match prev {
+                Some(contents) => FileUpdate::Overwrite(upd.update_file(contents)),
+                None => {
+                    self.notice.update = current;
+                    return Err(FileUpdateNotice {
+                        update: upd,
+                        ..notice
+                    });
+                }
+            },
+        };
+
+        self.notice = FileUpdateNotice {
+            version: notice.version,
+            author: match self.notice.author == notice.author {
+                true => notice.author,
+                false => None,
+            },
+            update: merged,
+        };
+
+        Ok(())
+    }
+}
+
+/// Sends callbacks to clients, with the same protocol as registered callbacks.
+pub struct CallbackSender {
+    sock: UdpSocket,
+    proto: Arc<dyn TransmissionProtocol + Send + Sync>,
+    timeout: Duration,
+    retries: u8,
+}
+
+impl CallbackSender {
+    /// Send a serialized callback to an address
+    pub async fn send(&self, addr: SocketAddrV4, payload: &[u8]) -> io::Result<usize> {
+        self.proto
+            .send_bytes(&self.sock, addr, payload, self.timeout, self.retries)
+            .await
+    }
 }
 
 impl RegisteredFileUpdates {
+    /// Create a sender for callbacks that are not registered, e.g. progress reports.
+    pub async fn sender(&self) -> io::Result<CallbackSender> {
+        Ok(CallbackSender {
+            sock: UdpSocket::bind(SocketAddrV4::new(self.bind_addr, 0)).await?,
+            proto: self.proto.clone(),
+            timeout: self.timeout,
+            retries: self.retries,
+        })
+    }
+
+    /// Returns true if a callback for the path wants updates as diffs
+    pub fn has_diff_watchers(&self, path: &str) -> bool {
+        self.has_watchers(path, WatchMode::Diff)
+    }
+
+    /// Returns true if any callback or subscription is sent updates to the path
+    pub fn is_watched(&self, path: &str) -> bool {
+        self.lookup.contains_key(path) || self.is_subscribed(path)
+    }
+
+    /// Returns true if a callback for the path wants updates in the given mode
+    pub fn has_watchers(&self, path: &str, mode: WatchMode) -> bool {
+        self.lookup
+            .get(path)
+            .is_some_and(|cbs| cbs.iter().any(|cb| cb.mode == mode))
+    }
+
     /// Searches for the file update callbacks and triggers them, if any.
     ///
+    /// Callbacks in [WatchMode::Diff] are sent overwrites as a diff against `prev`,
+    /// the contents before the update. If either contents are not text, the overwrite is sent.
+    /// Callbacks in [WatchMode::Metadata] are sent `metadata`, the metadata after the update.
+    ///
+    /// With a coalescing window, the update is held back and merged with the updates that
+    /// follow it, and sent when the window closes.
+    ///
     /// Returns the number of callbacks triggered.
     pub async fn trigger_file_update(
         &mut self,
         path: &str,
-        contents: FileUpdate,
+        notice: FileUpdateNotice,
+        prev: Option<&[u8]>,
+        metadata: Option<VirtMetadataLite>,
+    ) -> Option<NonZeroU8> {
+        let window = match self.coalesce {
+            Some(w) => w,
+            None => return self.send_file_update(path, notice, prev, metadata).await,
+        };
+
+        // nothing to merge for if no one is watching
+        if !self.is_watched(path) {
+            return None;
+        }
+
+        let notice = match self.pending.get_mut(path) {
+            Some(pending) => match pending.merge(notice, prev) {
+                Ok(_) => {
+                    pending.metadata = metadata;
+                    return None;
+                }
+                Err(notice) => notice,
+            },
+            None => {
+                let (flush_path, shared) = (path.to_string(), self.shared.clone());
+                tokio::spawn(async move {
+                    tokio::time::sleep(window).await;
+
+                    if let Some(callbacks) = shared.upgrade() {
+                        callbacks.lock().await.flush_file_update(&flush_path).await;
+                    }
+                });
+
+                self.pending.insert(
+                    path.to_string(),
+                    PendingUpdate {
+                        notice,
+                        prev: prev.map(|p| p.to_vec()),
+                        metadata,
+                    },
+                );
+                return None;
+            }
+        };
+
+        // unmergeable updates are sent in order, the later one is held back
+        let num_triggered = self.flush_file_update(path).await;
+        self.pending.insert(
+            path.to_string(),
+            PendingUpdate {
+                notice,
+                prev: prev.map(|p| p.to_vec()),
+                metadata,
+            },
+        );
+
+        num_triggered
+    }
+
+    /// Send the merged updates of a file to its callbacks.
+    ///
+    /// Returns the number of callbacks triggered.
+    pub async fn flush_file_update(&mut self, path: &str) -> Option<NonZeroU8> {
+        let pending = self.pending.remove(path)?;
+
+        self.send_file_update(
+            path,
+            pending.notice,
+            pending.prev.as_deref(),
+            pending.metadata,
+        )
+        .await
+    }
+
+    pub(super) async fn send_file_update(
+        &mut self,
+        path: &str,
+        notice: FileUpdateNotice,
+        prev: Option<&[u8]>,
+        metadata: Option<VirtMetadataLite>,
     ) -> Option<NonZeroU8> {
         log::debug!("checking for file update callbacks for {}", path);
 
-        let callbacks = self.lookup.remove(path)?;
+        // subscriptions are kept, watches are removed once triggered
+        self.expire_subscriptions();
+        let subscribers = self
+            .subscriptions
+            .iter()
+            .filter(|(_, sub)| sub.path == path)
+            .map(|(id, sub)| (*id, sub.addr))
+            .collect::<Vec<_>>();
+        let callbacks = match self.lookup.remove(path) {
+            Some(cbs) => cbs,
+            None if !subscribers.is_empty() => vec![],
+            None => return None,
+        };
 
-        log::debug!("callback targets: {:?}", callbacks);
+        log::debug!(
+            "callback targets: {:?}, subscribers: {:?}",
+            callbacks,
+            subscribers
+        );
 
-        let num_targets = callbacks.len();
+        let num_targets = callbacks.len() + subscribers.len();
 
         let sock = Arc::new(
             UdpSocket::bind(SocketAddrV4::new(self.bind_addr, 0))
@@ -56,12 +292,32 @@ impl RegisteredFileUpdates {
                 .ok()?,
         );
 
-        let ser_payload = Arc::new(ser_de::serialize(&contents).ok()?);
+        let diff_payload = match callbacks.iter().any(|cb| cb.mode == WatchMode::Diff) {
+            true => Self::diff_notice(&notice, prev)
+                .and_then(|n| ser_de::serialize(&n).ok())
+                .map(Arc::new),
+            false => None,
+        };
+        let metadata_payload = match callbacks.iter().any(|cb| cb.mode == WatchMode::Metadata) {
+            true => ser_de::serialize(&MetadataNotice {
+                version: notice.version,
+                author: notice.author,
+                metadata,
+            })
+            .ok()
+            .map(Arc::new),
+            false => None,
+        };
+        let ser_payload = Arc::new(ser_de::serialize(&notice).ok()?);
 
         let handles = callbacks.iter().map(|cb| {
             let proto = self.proto.clone();
             let sock_clone = sock.clone();
-            let pl = ser_payload.clone();
+            let pl = match (cb.mode, &diff_payload, &metadata_payload) {
+                (WatchMode::Diff, Some(diff), _) => diff.clone(),
+                (WatchMode::Metadata, _, Some(meta)) => meta.clone(),
+                _ => ser_payload.clone(),
+            };
             let ad = cb.addr;
             let to = self.timeout.clone();
             let rt = self.retries.clone();
@@ -72,12 +328,685 @@ impl RegisteredFileUpdates {
             )
         });
 
+        let subscriber_handles = subscribers
+            .into_iter()
+            .map(|(id, addr)| {
+                let proto = self.proto.clone();
+                let sock = sock.clone();
+                let payload = ser_payload.clone();
+                let (timeout, retries) = (self.timeout, self.retries);
+
+                (
+                    tokio::spawn(async move {
+                        proto
+                            .send_bytes(&sock, addr, &payload, timeout, retries)
+                            .await
+                    }),
+                    id,
+                )
+            })
+            .collect::<Vec<_>>();
+
         for (handle, addr) in handles {
             handle.await.inspect_err(|e| {
                 log::error!("error sending file update to {}: {:?}", addr, e);
             });
         }
 
+        // subscribers that cannot be reached are unsubscribed
+        for (handle, id) in subscriber_handles {
+            if let res @ (Err(_) | Ok(Err(_))) = handle.await {
+                log::error!(
+                    "error sending file update to subscription {}: {:?}",
+                    id,
+                    res
+                );
+                self.subscriptions.remove(&id);
+            }
+        }
+
         NonZeroU8::new(num_targets as u8)
     }
+
+    /// Returns the notice with an overwrite replaced by its diff against `prev`.
+    fn diff_notice(notice: &FileUpdateNotice, prev: Option<&[u8]>) -> Option<FileUpdateNotice> {
+        let new = match &notice.update {
+            FileUpdate::Overwrite(data) => std::str::from_utf8(data).ok()?,
+            _ => return None,
+        };
+        let prev = std::str::from_utf8(prev?).ok()?;
+
+        Some(FileUpdateNotice {
+            update: FileUpdate::Diff(rfs::fs::unified_diff(prev, new)),
+            ..notice.clone()
+        })
+    }
+
+    /// Remove a callback by its ID.
+    ///
+    /// Only the client that registered the callback can remove it, if the client is known.
+    /// Returns false if there was none.
+    pub fn unregister(&mut self, id: SubscriptionId, client: Option<ClientId>) -> bool {
+        if self.unregister_dir(id, client) || self.unsubscribe_file(id, client) {
+            return true;
+        }
+
+        let found = self.lookup.iter_mut().find_map(|(path, callbacks)| {
+            let pos = callbacks
+                .iter()
+                .position(|cb| cb.id == id && client.is_none_or(|c| cb.client == c))?;
+            callbacks.remove(pos);
+
+            Some((path.clone(), callbacks.is_empty()))
+        });
+
+        match found {
+            Some((path, true)) => {
+                self.lookup.remove(&path);
+                if !self.is_subscribed(&path) {
+                    self.pending.remove(&path);
+                }
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Remove the callbacks of a client for a path that send updates to `addr`.
+    ///
+    /// Callbacks the client registered since, to other addresses, are kept.
+    /// Returns false if there were none.
+    pub fn unregister_file_watch(
+        &mut self,
+        path: &str,
+        client: ClientId,
+        addr: SocketAddrV4,
+    ) -> bool {
+        let callbacks = match self.lookup.get_mut(path) {
+            Some(cbs) => cbs,
+            None => return false,
+        };
+
+        let before = callbacks.len();
+        callbacks.retain(|cb| cb.client != client || cb.addr != addr);
+        let removed = callbacks.len() < before;
+
+        if callbacks.is_empty() {
+            self.lookup.remove(path);
+            if !self.is_subscribed(path) {
+                self.pending.remove(path);
+            }
+        }
+
+        removed
+    }
+
+    /// Subscribe a client to every update of a file, with updates sent to `addr`.
+    ///
+    /// A subscription of the client to the file at the same address is renewed instead,
+    /// and keeps its ID.
+    pub fn subscribe_file(
+        &mut self,
+        path: String,
+        client: ClientId,
+        addr: SocketAddrV4,
+    ) -> SubscriptionId {
+        self.expire_subscriptions();
+
+        let existing = self
+            .subscriptions
+            .iter_mut()
+            .find(|(_, sub)| sub.path == path && sub.client == client && sub.addr == addr);
+
+        if let Some((id, sub)) = existing {
+            sub.renewed = Instant::now();
+            return *id;
+        }
+
+        let id = super::next_subscription_id();
+        self.subscriptions.insert(
+            id,
+            FileSubscription {
+                path,
+                client,
+                addr,
+                renewed: Instant::now(),
+            },
+        );
+
+        id
+    }
+
+    /// Remove a subscription by its ID.
+    ///
+    /// Only the client that subscribed can remove it, if the client is known.
+    /// Returns false if there was none.
+    pub fn unsubscribe_file(&mut self, id: SubscriptionId, client: Option<ClientId>) -> bool {
+        match self.subscriptions.get(&id) {
+            Some(sub) if client.is_none_or(|c| sub.client == c) => {
+                self.subscriptions.remove(&id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove the subscriptions that were not renewed within [SUBSCRIPTION_TIMEOUT].
+    ///
+    /// Returns the number of subscriptions removed.
+    pub fn expire_subscriptions(&mut self) -> usize {
+        let before = self.subscriptions.len();
+        self.subscriptions
+            .retain(|_, sub| sub.renewed.elapsed() < SUBSCRIPTION_TIMEOUT);
+
+        before - self.subscriptions.len()
+    }
+
+    /// Returns true if a client is subscribed to every update of the file
+    fn is_subscribed(&self, path: &str) -> bool {
+        self.subscriptions.values().any(|sub| sub.path == path)
+    }
+
+    /// Remove a directory watch by its ID. Returns false if there was none.
+    fn unregister_dir(&mut self, id: SubscriptionId, client: Option<ClientId>) -> bool {
+        let found = self.dirs.iter_mut().find_map(|(path, callbacks)| {
+            let pos = callbacks
+                .iter()
+                .position(|cb| cb.id == id && client.is_none_or(|c| cb.client == c))?;
+            callbacks.remove(pos);
+
+            Some((path.clone(), callbacks.is_empty()))
+        });
+
+        match found {
+            Some((path, true)) => {
+                self.dirs.remove(&path);
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Watch a directory for changes to its entries, with events sent to `addr`.
+    ///
+    /// A client watching the directory again at the same address replaces its watch.
+    pub fn register_dir(
+        &mut self,
+        path: String,
+        client: ClientId,
+        addr: SocketAddrV4,
+        recursive: bool,
+    ) -> SubscriptionId {
+        let id = super::next_subscription_id();
+        let callbacks = self.dirs.entry(path).or_default();
+
+        callbacks.retain(|cb| cb.client != client || cb.addr != addr);
+        callbacks.push(DirUpdateCallback {
+            id,
+            client,
+            addr,
+            recursive,
+        });
+
+        id
+    }
+
+    /// Send an event to the watches of the directories containing the changed paths.
+    ///
+    /// Recursive watches are sent events from all subdirectories, others only from
+    /// the directory itself. Watches that cannot be reached are removed.
+    /// Returns the number of watches the event was sent to.
+    pub async fn trigger_dir_update(&mut self, event: DirEvent) -> usize {
+        let mut targets: HashMap<SubscriptionId, (String, SocketAddrV4)> = HashMap::new();
+
+        for path in event.paths() {
+            let mut dir = path.parent();
+            let mut direct = true;
+
+            while let Some(d) = dir {
+                let callbacks = self.dirs.get(d.as_str()).into_iter().flatten();
+                for cb in callbacks.filter(|cb| direct || cb.recursive) {
+                    targets.insert(cb.id, (d.to_string(), cb.addr));
+                }
+
+                dir = d.parent();
+                direct = false;
+            }
+        }
+
+        if targets.is_empty() {
+            return 0;
+        }
+
+        let sock = match UdpSocket::bind(SocketAddrV4::new(self.bind_addr, 0)).await {
+            Ok(s) => Arc::new(s),
+            Err(e) => {
+                log::error!("failed to bind directory event socket: {}", e);
+                return 0;
+            }
+        };
+        let payload = match ser_de::serialize(&event) {
+            Ok(p) => Arc::new(p),
+            Err(e) => {
+                log::error!("failed to serialize directory event: {:?}", e);
+                return 0;
+            }
+        };
+
+        let handles = targets
+            .into_iter()
+            .map(|(id, (dir, addr))| {
+                let proto = self.proto.clone();
+                let sock = sock.clone();
+                let payload = payload.clone();
+                let (timeout, retries) = (self.timeout, self.retries);
+
+                (
+                    tokio::spawn(async move {
+                        proto
+                            .send_bytes(&sock, addr, &payload, timeout, retries)
+                            .await
+                    }),
+                    (id, dir),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut num_sent = 0;
+        for (handle, (id, dir)) in handles {
+            match handle.await {
+                Ok(Ok(_)) => num_sent += 1,
+                res => {
+                    log::error!("error sending event for {} to watch {}: {:?}", dir, id, res);
+                    self.unregister_dir(id, None);
+                }
+            }
+        }
+
+        num_sent
+    }
+
+    /// Remove the callbacks and topic subscriptions of clients that are gone.
+    ///
+    /// File subscriptions are counted with the callbacks.
+    /// Returns the number of callbacks and topic subscriptions removed.
+    pub fn forget_clients(&mut self, clients: &[ClientId]) -> (usize, usize) {
+        let mut callbacks = self.subscriptions.len();
+        self.subscriptions
+            .retain(|_, sub| !clients.contains(&sub.client));
+        callbacks -= self.subscriptions.len();
+
+        self.lookup.retain(|path, cbs| {
+            let before = cbs.len();
+            cbs.retain(|cb| !clients.contains(&cb.client));
+            callbacks += before - cbs.len();
+
+            if cbs.is_empty() && !self.subscriptions.values().any(|sub| &sub.path == path) {
+                self.pending.remove(path);
+            }
+            !cbs.is_empty()
+        });
+        self.dirs.retain(|_, cbs| {
+            let before = cbs.len();
+            cbs.retain(|cb| !clients.contains(&cb.client));
+            callbacks += before - cbs.len();
+
+            !cbs.is_empty()
+        });
+
+        let mut subscriptions = 0;
+        self.topics.retain(|_, subscribers| {
+            let before = subscribers.len();
+            subscribers.retain(|client, _| !clients.contains(client));
+            subscriptions += before - subscribers.len();
+
+            !subscribers.is_empty()
+        });
+
+        (callbacks, subscriptions)
+    }
+
+    /// Subscribe a client to a topic, with messages sent to `addr`.
+    ///
+    /// Returns false if the client is already subscribed. Its address is updated.
+    pub fn subscribe(&mut self, topic: String, client: ClientId, addr: SocketAddrV4) -> bool {
+        self.topics
+            .entry(topic)
+            .or_default()
+            .insert(client, addr)
+            .is_none()
+    }
+
+    /// Unsubscribe a client from a topic. Returns false if it was not subscribed.
+    pub fn unsubscribe(&mut self, topic: &str, client: ClientId) -> bool {
+        let subscribers = match self.topics.get_mut(topic) {
+            Some(s) => s,
+            None => return false,
+        };
+
+        let removed = subscribers.remove(&client).is_some();
+
+        if subscribers.is_empty() {
+            self.topics.remove(topic);
+        }
+
+        removed
+    }
+
+    /// Send a message to the subscribers of a topic.
+    ///
+    /// Subscribers that cannot be reached are unsubscribed.
+    /// Returns the number of subscribers the message was sent to.
+    pub async fn publish(&mut self, message: TopicMessage) -> usize {
+        let subscribers = match self.topics.get(&message.topic) {
+            Some(s) => s.clone(),
+            None => return 0,
+        };
+
+        let sock = match UdpSocket::bind(SocketAddrV4::new(self.bind_addr, 0)).await {
+            Ok(s) => Arc::new(s),
+            Err(e) => {
+                log::error!("failed to bind publish socket: {}", e);
+                return 0;
+            }
+        };
+
+        let payload = Arc::new(rfs::topics::encode_message(&message));
+
+        let handles = subscribers
+            .into_iter()
+            .map(|(client, addr)| {
+                let proto = self.proto.clone();
+                let sock = sock.clone();
+                let payload = payload.clone();
+                let (timeout, retries) = (self.timeout, self.retries);
+
+                (
+                    tokio::spawn(async move {
+                        proto
+                            .send_bytes(&sock, addr, &payload, timeout, retries)
+                            .await
+                    }),
+                    (client, addr),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut num_sent = 0;
+        for (handle, (client, addr)) in handles {
+            match handle.await {
+                Ok(Ok(_)) => num_sent += 1,
+                res => {
+                    log::error!("error publishing to {} at {}: {:?}", client, addr, res);
+                    self.unsubscribe(&message.topic, client);
+                }
+            }
+        }
+
+        num_sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rfs::middleware::DefaultProto;
+
+    use super::*;
+
+    fn notice(version: u64, update: FileUpdate) -> FileUpdateNotice {
+        FileUpdateNotice {
+            version,
+            author: Some(1),
+            update,
+        }
+    }
+
+    #[test]
+    fn test_merge_updates() {
+        let mut pending = PendingUpdate {
+            notice: notice(1, FileUpdate::Append(b"ab".to_vec())),
+            prev: Some(b"0".to_vec()),
+            metadata: None,
+        };
+
+        pending
+            .merge(notice(2, FileUpdate::Append(b"c".to_vec())), Some(b"0ab"))
+            .unwrap();
+        assert_eq!(pending.notice.update, FileUpdate::Append(b"abc".to_vec()));
+
+        pending
+            .merge(
+                notice(3, FileUpdate::Insert((0, b"_".to_vec()))),
+                Some(b"0abc"),
+            )
+            .unwrap();
+        assert_eq!(pending.notice.version, 3);
+        assert_eq!(
+            pending.notice.update,
+            FileUpdate::Overwrite(b"_0abc".to_vec())
+        );
+
+        // contents are unknown, but an overwrite replaces them
+        let mut later = notice(4, FileUpdate::Overwrite(b"new".to_vec()));
+        later.author = Some(2);
+        pending.merge(later, None).unwrap();
+        assert_eq!(pending.notice.author, None);
+        assert_eq!(
+            pending.notice.update,
+            FileUpdate::Overwrite(b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_unregister_file_watch() {
+        let client = ClientId::random();
+        let addr = |port| SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+        let callback = |port| FileUpdateCallback {
+            id: next_subscription_id(),
+            client,
+            addr: addr(port),
+            mode: WatchMode::Raw,
+        };
+
+        let mut callbacks = RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: HashMap::from([("notes".to_string(), vec![callback(1), callback(2)])]),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: None,
+            pending: Default::default(),
+            shared: Weak::new(),
+        };
+
+        // an earlier watch of the same client is removed without touching the later one
+        assert!(callbacks.unregister_file_watch("notes", client, addr(1)));
+        assert!(!callbacks.unregister_file_watch("notes", client, addr(1)));
+        assert!(!callbacks.unregister_file_watch("notes", ClientId::random(), addr(2)));
+        assert_eq!(callbacks.lookup["notes"].len(), 1);
+
+        assert!(callbacks.unregister_file_watch("notes", client, addr(2)));
+        assert!(!callbacks.lookup.contains_key("notes"));
+    }
+
+    #[test]
+    fn test_unregister_by_id() {
+        let client = ClientId::random();
+        let callback = |id| FileUpdateCallback {
+            id,
+            client,
+            addr: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1),
+            mode: WatchMode::Raw,
+        };
+        let (first, second) = (next_subscription_id(), next_subscription_id());
+
+        let mut callbacks = RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: HashMap::from([
+                ("notes".to_string(), vec![callback(first)]),
+                ("todo".to_string(), vec![callback(second)]),
+            ]),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: None,
+            pending: Default::default(),
+            shared: Weak::new(),
+        };
+
+        // other clients cannot remove the callback
+        assert!(!callbacks.unregister(first, Some(ClientId::random())));
+        assert!(callbacks.unregister(first, Some(client)));
+        assert!(!callbacks.unregister(first, Some(client)));
+        assert!(!callbacks.lookup.contains_key("notes"));
+
+        assert!(callbacks.unregister(second, None));
+        assert!(callbacks.lookup.is_empty());
+    }
+
+    #[test]
+    fn test_subscription_expiry() {
+        let client = ClientId::random();
+        let addr = |port| SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+
+        let mut callbacks = RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: Default::default(),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: None,
+            pending: Default::default(),
+            shared: Weak::new(),
+        };
+
+        let first = callbacks.subscribe_file("notes".to_string(), client, addr(1));
+        let second = callbacks.subscribe_file("notes".to_string(), client, addr(2));
+        assert_ne!(first, second);
+        assert!(callbacks.is_subscribed("notes"));
+
+        // the first subscription was not renewed in time
+        let expired = Instant::now().checked_sub(SUBSCRIPTION_TIMEOUT).unwrap();
+        callbacks.subscriptions.get_mut(&first).unwrap().renewed = expired;
+        assert_eq!(callbacks.expire_subscriptions(), 1);
+        assert_ne!(
+            callbacks.subscribe_file("notes".to_string(), client, addr(1)),
+            first
+        );
+
+        // other clients cannot remove the subscription
+        assert!(!callbacks.unsubscribe_file(second, Some(ClientId::random())));
+        assert!(callbacks.unsubscribe_file(second, Some(client)));
+        assert!(!callbacks.unsubscribe_file(second, Some(client)));
+        assert_eq!(callbacks.subscriptions.len(), 1);
+    }
+
+    #[test]
+    fn test_forget_clients() {
+        let (gone, active) = (ClientId::random(), ClientId::random());
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1);
+        let callback = |client| FileUpdateCallback {
+            id: next_subscription_id(),
+            client,
+            addr,
+            mode: WatchMode::Raw,
+        };
+
+        let mut callbacks = RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: HashMap::from([
+                ("notes".to_string(), vec![callback(gone), callback(active)]),
+                ("todo".to_string(), vec![callback(gone)]),
+            ]),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: None,
+            pending: Default::default(),
+            shared: Weak::new(),
+        };
+        callbacks.subscribe("news".to_string(), gone, addr);
+        callbacks.subscribe("news".to_string(), active, addr);
+        callbacks.subscribe("alerts".to_string(), gone, addr);
+
+        assert_eq!(callbacks.forget_clients(&[gone]), (2, 2));
+        assert_eq!(callbacks.lookup.len(), 1);
+        assert_eq!(callbacks.lookup["notes"][0].client, active);
+        assert_eq!(callbacks.topics.len(), 1);
+
+        assert_eq!(callbacks.forget_clients(&[gone]), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_burst() {
+        let watcher = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = rfs::middleware::sockaddr_to_v4(watcher.local_addr().unwrap()).unwrap();
+
+        let mut callbacks = RegisteredFileUpdates {
+            bind_addr: Ipv4Addr::LOCALHOST,
+            lookup: HashMap::from([(
+                "notes".to_string(),
+                vec![FileUpdateCallback {
+                    id: next_subscription_id(),
+                    client: ClientId::random(),
+                    addr,
+                    mode: WatchMode::Raw,
+                }],
+            )]),
+            dirs: Default::default(),
+            subscriptions: Default::default(),
+            topics: Default::default(),
+            proto: Arc::new(DefaultProto),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            coalesce: Some(Duration::from_secs(60)),
+            pending: Default::default(),
+            shared: Weak::new(),
+        };
+
+        // a burst of small writes is held back
+        let mut contents = Vec::new();
+        for (version, byte) in (1..=10).zip(b'a'..) {
+            let update = notice(version, FileUpdate::Append(vec![byte]));
+            assert!(callbacks
+                .trigger_file_update("notes", update, Some(&contents), None)
+                .await
+                .is_none());
+            contents.push(byte);
+        }
+        assert_eq!(callbacks.pending.len(), 1);
+
+        let num_triggered = callbacks.flush_file_update("notes").await;
+        assert_eq!(num_triggered, NonZeroU8::new(1));
+
+        let (_, payload) = DefaultProto
+            .recv_bytes(&watcher, Duration::from_millis(100), 1)
+            .await
+            .unwrap();
+        let received: FileUpdateNotice = ser_de::deserialize(&payload).unwrap();
+        assert_eq!(received.version, 10);
+        assert_eq!(received.update, FileUpdate::Append(contents));
+
+        // only one callback was sent for the burst
+        let next = DefaultProto.recv_bytes(&watcher, Duration::from_millis(50), 1);
+        assert!(tokio::time::timeout(Duration::from_millis(50), next)
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,264 @@
+//! Write-ahead journal of mutating file operations.
+//!
+//! An operation is appended to the journal and synced to disk before it is applied,
+//! and marked as done after. Operations that are not marked as done when the server
+//! starts were interrupted by a crash, and are applied again.
+//!
+//! Every operation leaves the same result when applied again, so an operation that
+//! was completed before the crash is not applied twice. Writes record the resulting
+//! contents of the file instead of the update.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use rfs::{fs::VirtPath, ser_de};
+use serde::{Deserialize, Serialize};
+
+/// Name of the journal file, in the base directory of the server.
+///
+/// Clients cannot access this file.
+pub const JOURNAL_FILE: &str = ".rfs_journal";
+
+/// The journal is cleared once it grows past this number of bytes
+const MAX_JOURNAL_LEN: u64 = 1024 * 1024;
+
+/// A mutating operation, with paths relative to the base directory
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JournalOp {
+    Create(VirtPath),
+    Remove(VirtPath),
+    /// Replace the contents of a file
+    Write {
+        path: VirtPath,
+        contents: Vec<u8>,
+    },
+    /// Replace a file with a new one, which is written to a temporary file first
+    Replace {
+        path: VirtPath,
+        contents: Vec<u8>,
+    },
+    Mkdir(VirtPath),
+    Rmdir(VirtPath),
+    Rename {
+        from: VirtPath,
+        to: VirtPath,
+    },
+}
+
+/// A record in the journal, prefixed by its length
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// The operation is about to be applied
+    Begin(u64, JournalOp),
+    /// The operation with this sequence number was applied
+    Done(u64),
+}
+
+/// Append-only log of mutating operations
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Length of the journal file
+    len: u64,
+    next_seq: u64,
+}
+
+impl JournalOp {
+    /// Apply the operation to the files in `base`
+    pub fn apply(&self, base: &Path) -> io::Result<()> {
+        match self {
+            JournalOp::Create(path) => File::create(base.join(path)).map(|_| ()),
+            JournalOp::Remove(path) => fs::remove_file(base.join(path)),
+            JournalOp::Write { path, contents } => fs::write(base.join(path), contents),
+            JournalOp::Replace { path, contents } => replace_file(&base.join(path), contents),
+            JournalOp::Mkdir(path) => fs::create_dir(base.join(path)),
+            JournalOp::Rmdir(path) => fs::remove_dir_all(base.join(path)),
+            JournalOp::Rename { from, to } => fs::rename(base.join(from), base.join(to)),
+        }
+    }
+}
+
+impl Journal {
+    /// Open the journal in the directory `base`, creating it if it does not exist.
+    ///
+    /// Returns the journal and the operations that were begun but not marked as done,
+    /// in the order they were begun. A record cut short by a crash is ignored.
+    pub fn open(base: &Path) -> io::Result<(Self, Vec<JournalOp>)> {
+        let path = base.join(JOURNAL_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+
+        let mut pending = BTreeMap::new();
+        let mut next_seq = 0;
+        let mut remaining = bytes.as_slice();
+
+        while !remaining.is_empty() {
+            let record = match read_record(&mut remaining) {
+                Some(r) => r,
+                None => {
+                    log::warn!(
+                        "ignoring {} bytes at the end of the journal",
+                        remaining.len()
+                    );
+                    break;
+                }
+            };
+
+            match record {
+                Record::Begin(seq, op) => {
+                    next_seq = next_seq.max(seq + 1);
+                    pending.insert(seq, op);
+                }
+                Record::Done(seq) => {
+                    pending.remove(&seq);
+                }
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let journal = Self {
+            path,
+            file,
+            len: bytes.len() as u64,
+            next_seq,
+        };
+
+        Ok((journal, pending.into_values().collect()))
+    }
+
+    /// Record that an operation is about to be applied.
+    ///
+    /// The record is on disk when this returns. Returns the sequence number of the operation.
+    pub fn begin(&mut self, op: &JournalOp) -> io::Result<u64> {
+        let seq = self.next_seq;
+        self.append(&Record::Begin(seq, op.clone()))?;
+        self.file.sync_data()?;
+        self.next_seq += 1;
+
+        Ok(seq)
+    }
+
+    /// Record that an operation was applied, whether it succeeded or not.
+    ///
+    /// The journal is cleared once it is too long, as no other operation is in progress.
+    pub fn done(&mut self, seq: u64) -> io::Result<()> {
+        self.append(&Record::Done(seq))?;
+
+        match self.len > MAX_JOURNAL_LEN {
+            true => self.clear(),
+            false => Ok(()),
+        }
+    }
+
+    /// Remove all records from the journal.
+    ///
+    /// Operations in progress must have been applied.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+
+        Ok(())
+    }
+
+    /// Returns the path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let bytes = ser_de::serialize(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+        // written at once, so a crash leaves at most one record cut short
+        let framed = [&(bytes.len() as u32).to_le_bytes()[..], &bytes].concat();
+        self.file.write_all(&framed)?;
+        self.len += framed.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Replace a file with one holding `contents` and the same permissions.
+///
+/// Readers see either the old or the new contents. Read-only files are not replaced.
+fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    if permissions.readonly() {
+        return Err(io::ErrorKind::PermissionDenied.into());
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.partial", name));
+
+    fs::write(&partial, contents)?;
+    fs::set_permissions(&partial, permissions)?;
+    fs::rename(&partial, path)
+}
+
+/// Read a length-prefixed record from the start of `bytes`, advancing past it.
+fn read_record(bytes: &mut &[u8]) -> Option<Record> {
+    let (prefix, rest) = bytes.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*prefix) as usize;
+    let body = rest.get(..len)?;
+
+    let record = ser_de::deserialize_exact(body).ok()?;
+    *bytes = &rest[len..];
+
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    #[test]
+    fn test_recover_pending() {
+        let base = TempDir::new("journal");
+
+        let write = JournalOp::Write {
+            path: "file".into(),
+            contents: b"hello".to_vec(),
+        };
+        let mkdir = JournalOp::Mkdir("dir".into());
+
+        {
+            let (mut journal, pending) = Journal::open(&base).unwrap();
+            assert!(pending.is_empty());
+
+            let seq = journal.begin(&mkdir).unwrap();
+            journal.done(seq).unwrap();
+            // the server crashes before the write is applied
+            journal.begin(&write).unwrap();
+        }
+
+        // a record cut short by the crash
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(base.join(JOURNAL_FILE))
+            .unwrap();
+        file.write_all(&[200, 0, 0, 0, 1]).unwrap();
+
+        let (mut journal, pending) = Journal::open(&base).unwrap();
+        assert_eq!(pending, vec![write.clone()]);
+
+        // applying the write again leaves the same contents
+        for _ in 0..2 {
+            pending[0].apply(&base).unwrap();
+        }
+        assert_eq!(fs::read(base.join("file")).unwrap(), b"hello");
+
+        journal.clear().unwrap();
+        assert_eq!(journal.begin(&mkdir).unwrap(), 2);
+        assert_eq!(Journal::open(&base).unwrap().1, vec![mkdir]);
+    }
+}
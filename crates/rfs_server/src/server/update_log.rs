@@ -0,0 +1,142 @@
+//! Recent updates to each file, for watchers that missed some.
+//!
+//! A watch is removed once it is triggered, and the watcher registers again. Updates made
+//! in between, or while the watcher could not reach the server, are replayed from the log
+//! when the watcher registers with the last version it saw.
+
+use std::collections::{HashMap, VecDeque};
+
+use rfs::{
+    fs::VirtPath,
+    interfaces::{FileUpdateNotice, MissedUpdates},
+};
+
+/// Number of updates kept for each file
+pub const MAX_LOGGED_UPDATES: usize = 32;
+
+/// Bytes of updates kept for each file. The latest update is kept even if it is larger.
+pub const MAX_LOGGED_BYTES: usize = 1024 * 1024;
+
+/// Updates of each file, ordered by version
+#[derive(Debug, Default)]
+pub struct UpdateLog {
+    files: HashMap<VirtPath, VecDeque<FileUpdateNotice>>,
+}
+
+impl UpdateLog {
+    /// Record an update to a file, dropping the oldest updates past the limits of the log.
+    ///
+    /// An update that does not follow the last recorded one, e.g. after the versions of the file
+    /// started over, replaces the updates of the file.
+    pub fn record(&mut self, path: &VirtPath, notice: FileUpdateNotice) {
+        let updates = self.files.entry(path.clone()).or_default();
+
+        if updates
+            .back()
+            .is_some_and(|last| last.version + 1 != notice.version)
+        {
+            updates.clear();
+        }
+        updates.push_back(notice);
+
+        let mut bytes = updates.iter().map(|n| n.update.len()).sum::<usize>();
+        while updates.len() > MAX_LOGGED_UPDATES || (bytes > MAX_LOGGED_BYTES && updates.len() > 1)
+        {
+            if let Some(dropped) = updates.pop_front() {
+                bytes -= dropped.update.len();
+            }
+        }
+    }
+
+    /// Returns the updates to a file after `version`, in order.
+    ///
+    /// `current` is the current version of the file. If the log no longer holds every update
+    /// since `version`, or the watcher is ahead of the file, the file must be read again.
+    pub fn since(&self, path: &VirtPath, version: u64, current: u64) -> MissedUpdates {
+        if version == current {
+            return MissedUpdates::Replay(vec![]);
+        }
+
+        let missed = self
+            .files
+            .get(path)
+            .into_iter()
+            .flatten()
+            .filter(|n| n.version > version)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let complete = version < current
+            && missed.len() as u64 == current - version
+            && missed.first().is_some_and(|n| n.version == version + 1);
+
+        match complete {
+            true => MissedUpdates::Replay(missed),
+            false => MissedUpdates::Refetch { version: current },
+        }
+    }
+
+    /// Remove the updates of a file, or of every file in a directory.
+    ///
+    /// Watchers of a file that was replaced other than by a write, e.g. removed and created
+    /// again, cannot catch up by replaying updates to its previous contents.
+    pub fn forget(&mut self, path: &VirtPath) {
+        self.files.retain(|p, _| !p.starts_with(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rfs::interfaces::FileUpdate;
+
+    use super::*;
+
+    fn append(version: u64, data: &[u8]) -> FileUpdateNotice {
+        FileUpdateNotice {
+            version,
+            author: None,
+            update: FileUpdate::Append(data.to_vec()),
+        }
+    }
+
+    fn versions(missed: MissedUpdates) -> Option<Vec<u64>> {
+        match missed {
+            MissedUpdates::Replay(updates) => Some(updates.iter().map(|n| n.version).collect()),
+            MissedUpdates::Refetch { .. } => None,
+        }
+    }
+
+    #[test]
+    fn test_replay_missed() {
+        let path = VirtPath::from("file");
+        let mut log = UpdateLog::default();
+
+        for version in 1..=MAX_LOGGED_UPDATES as u64 + 4 {
+            log.record(&path, append(version, b"a"));
+        }
+        let current = MAX_LOGGED_UPDATES as u64 + 4;
+
+        assert_eq!(versions(log.since(&path, current, current)), Some(vec![]));
+        assert_eq!(
+            versions(log.since(&path, current - 2, current)),
+            Some(vec![current - 1, current])
+        );
+        // the oldest updates were dropped
+        assert_eq!(versions(log.since(&path, 4, current)).unwrap().len(), 32);
+        assert_eq!(versions(log.since(&path, 3, current)), None);
+        // the watcher saw versions from before the server restarted
+        assert_eq!(versions(log.since(&path, current + 1, current)), None);
+
+        // versions started over
+        log.record(&path, append(1, b"b"));
+        assert_eq!(versions(log.since(&path, 0, 1)), Some(vec![1]));
+
+        // older updates are dropped past the byte limit
+        log.record(&path, append(2, &vec![0; MAX_LOGGED_BYTES]));
+        assert_eq!(versions(log.since(&path, 0, 2)), None);
+        assert_eq!(versions(log.since(&path, 1, 2)), Some(vec![2]));
+
+        log.forget(&path);
+        assert_eq!(versions(log.since(&path, 1, 2)), None);
+    }
+}
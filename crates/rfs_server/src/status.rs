@@ -0,0 +1,237 @@
+//! Status dashboard for a running server.
+//!
+//! The dashboard connects to a server through [AdminOps](rfs::interfaces::AdminOps) and periodically
+//! renders a snapshot of its status.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Row, Table},
+    Frame, Terminal,
+};
+use rfs::{
+    interfaces::{AdminOpsClient, ServerStatus},
+    middleware::ContextManager,
+};
+
+/// Interval between checks for key presses
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run the status dashboard until the user quits.
+pub async fn run(ctx: ContextManager, refresh: Duration) -> io::Result<()> {
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    enable_raw_mode()?;
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let res = run_dashboard(&mut terminal, ctx, refresh).await;
+
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+
+    res
+}
+
+async fn run_dashboard<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut ctx: ContextManager,
+    refresh: Duration,
+) -> io::Result<()> {
+    let mut status = None;
+
+    loop {
+        let error = match AdminOpsClient::server_status(&mut ctx).await {
+            Ok(s) => {
+                status = Some(s);
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        };
+
+        terminal.draw(|f| render_status(f, status.as_ref(), error.as_deref()))?;
+
+        let next_refresh = Instant::now() + refresh;
+        while Instant::now() < next_refresh {
+            if event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()? {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('r') => break,
+                        _ => (),
+                    }
+                }
+            }
+
+            tokio::time::sleep(KEY_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Render a status snapshot. The last error, if any, is shown in the summary.
+fn render_status(frame: &mut Frame, status: Option<&ServerStatus>, error: Option<&str>) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(8),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+
+    let default_status = ServerStatus::default();
+    let status = status.unwrap_or(&default_status);
+
+    let mut summary = vec![
+        Line::from(format!("uptime:   {}", format_secs(status.uptime_secs))),
+        Line::from(format!(
+            "requests: {} total, {} duplicate, {} transfers reaped",
+            status.total_requests, status.duplicate_requests, status.reaped_transfers
+        )),
+        Line::from(format!(
+            "rate:     {:.2} req/s, {} slo violations",
+            status.request_rate, status.slo_violations
+        )),
+        Line::from(match status.compression_ratio {
+            Some(ratio) => format!(
+                "compress: {} responses, {:.0}% of original size",
+                status.compressed_responses,
+                ratio * 100.0
+            ),
+            None => "compress: no responses".to_string(),
+        }),
+        Line::from(format!(
+            "cache:    {} files, {} bytes",
+            status.cache_entries, status.cache_bytes
+        )),
+    ];
+    if let Some(e) = error {
+        summary.push(Line::from(format!("error:    {}", e)).fg(Color::Red));
+    }
+
+    frame.render_widget(
+        Paragraph::new(summary).block(Block::default().borders(Borders::ALL).title("server")),
+        chunks[0],
+    );
+
+    let tables = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(chunks[1]);
+
+    let sessions = Table::new(
+        status.sessions.iter().map(|s| {
+            // the start of the ID is enough to tell clients apart
+            let client = s.client.to_string();
+
+            Row::new([
+                client[..8].to_string(),
+                s.addr.to_string(),
+                s.requests.to_string(),
+                format!("{}ms", s.idle_ms),
+            ])
+        }),
+        [
+            Constraint::Length(8),
+            Constraint::Min(15),
+            Constraint::Length(8),
+            Constraint::Length(6),
+        ],
+    )
+    .header(Row::new(["client", "address", "requests", "idle"]).bold())
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("sessions ({})", status.sessions.len())),
+    );
+    frame.render_widget(sessions, tables[0]);
+
+    let watches = Table::new(
+        status
+            .watches
+            .iter()
+            .map(|(path, num)| Row::new([path.clone(), num.to_string()])),
+        [Constraint::Percentage(75), Constraint::Percentage(25)],
+    )
+    .header(Row::new(["path", "callbacks"]).bold())
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("watches ({})", status.watches.len())),
+    );
+    frame.render_widget(watches, tables[1]);
+
+    frame.render_widget(
+        Paragraph::new("q: quit, r: refresh").style(Style::default().fg(Color::DarkGray)),
+        chunks[2],
+    );
+}
+
+/// Format a number of seconds as `HH:MM:SS`
+fn format_secs(secs: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use ratatui::backend::TestBackend;
+    use rfs::interfaces::SessionStatus;
+
+    use super::*;
+
+    #[test]
+    fn test_render_status() {
+        let status = ServerStatus {
+            uptime_secs: 3725,
+            total_requests: 42,
+            compressed_responses: 3,
+            compression_ratio: Some(0.25),
+            slo_violations: 2,
+            sessions: vec![SessionStatus {
+                client: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5000).into(),
+                addr: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5000),
+                requests: 42,
+                idle_ms: 10,
+            }],
+            watches: vec![("some/file.txt".to_string(), 2)],
+            ..Default::default()
+        };
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        terminal
+            .draw(|f| render_status(f, Some(&status), Some("timed out")))
+            .unwrap();
+
+        let rendered = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect::<String>();
+
+        assert!(rendered.contains("01:02:05"));
+        assert!(rendered.contains("127.0.0.1:5000"));
+        assert!(rendered.contains("some/file.txt"));
+        assert!(rendered.contains("3 responses, 25% of original size"));
+        assert!(rendered.contains("2 slo violations"));
+        assert!(rendered.contains("timed out"));
+    }
+}